@@ -0,0 +1,16 @@
+//! Stamps `--version` with the commit the binaries were built from.
+
+use std::process::Command;
+
+fn main() {
+    let hash = Command::new("git").args(["rev-parse", "--short=10", "HEAD"]).output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        // e.g. built from a source tarball
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
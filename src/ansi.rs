@@ -0,0 +1,292 @@
+//! Escape sequences in what the target prints: a parser that carries sequences cut off at
+//! the end of a read over to the next, and the [`Sanitizer`] that keeps the ones that only
+//! color text or move the cursor while showing the others, like a title change, as plain text.
+
+/// Longest CSI or ESC sequence, and longest OSC or DCS string, before the parser gives up on
+/// it, so a lost terminator doesn't swallow the rest of the session.
+pub const MAX_SEQUENCE: usize = 64;
+pub const MAX_STRING: usize = 512;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+/// SGR faint and back to normal intensity.
+const DIM: &str = "\x1b[2m";
+const NORMAL: &str = "\x1b[22m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `ESC` and one final character, maybe after intermediates, e.g. `ESC 7` or `ESC ( 0`.
+    Esc,
+    /// `ESC [`, parameters and a final character, e.g. `ESC [ 1 ; 31 m`.
+    Csi,
+    /// Operating system command, e.g. a window title: `ESC ] 0 ; title BEL`.
+    Osc,
+    /// Device control string, `ESC P … ESC \`.
+    Dcs,
+    /// Start of string, privacy message and application program command; `ESC X`, `ESC ^`
+    /// and `ESC _`.
+    Sos,
+    Pm,
+    Apc,
+    /// Broken off by an unexpected character, or longer than the limits allow.
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item<'a> {
+    Text(&'a str),
+    /// A C0 or C1 control character, or DEL.
+    Control(char),
+    /// A whole sequence, from its `ESC` to its terminator.
+    Sequence(Kind, &'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// Intermediates of an ESC sequence.
+    EscIntermediate,
+    Csi,
+    String(Kind),
+    /// An `ESC` in a string, the start of its `ESC \` terminator.
+    StringEscape(Kind),
+}
+
+fn is_control(c: char) -> bool {
+    c.is_control() && c != ESC
+}
+
+/// Splits text into [`Item`]s.
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    /// The sequence read so far.
+    pending: String,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self { state: State::Ground, pending: String::new() }
+    }
+}
+
+impl Parser {
+    /// Calls `f` for every item of `text` that is complete; a sequence still open at its end
+    /// is held back for the next call.
+    pub fn feed(&mut self, text: &str, f: &mut dyn FnMut(Item<'_>)) {
+        let mut rest = text;
+        while !rest.is_empty() {
+            if self.state == State::Ground {
+                let end = rest.find(|c: char| c == ESC || is_control(c)).unwrap_or(rest.len());
+                if end > 0 { f(Item::Text(&rest[..end])); }
+                rest = &rest[end..];
+                let c = match rest.chars().next() {
+                    Some(c) => c,
+                    None => break,
+                };
+                rest = &rest[c.len_utf8()..];
+                if c == ESC {
+                    self.pending.push(c);
+                    self.state = State::Escape;
+                } else {
+                    f(Item::Control(c));
+                }
+                continue;
+            }
+
+            let c = rest.chars().next().unwrap();
+            if !self.step(c, f) { continue; }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    /// Like ending the text: a sequence still open comes out as [`Kind::Invalid`].
+    pub fn finish(&mut self, f: &mut dyn FnMut(Item<'_>)) {
+        if !self.pending.is_empty() { f(Item::Sequence(Kind::Invalid, &self.pending)); }
+        self.reset();
+    }
+
+    /// Takes `c` into the open sequence; false when `c` ended it without belonging to it, and
+    /// is to be read again from the ground state.
+    fn step(&mut self, c: char, f: &mut dyn FnMut(Item<'_>)) -> bool {
+        let state = self.state;
+        let (next, done) = match state {
+            State::Ground => unreachable!(),
+            State::Escape => match c {
+                '[' => (Some(State::Csi), None),
+                ']' => (Some(State::String(Kind::Osc)), None),
+                'P' => (Some(State::String(Kind::Dcs)), None),
+                'X' => (Some(State::String(Kind::Sos)), None),
+                '^' => (Some(State::String(Kind::Pm)), None),
+                '_' => (Some(State::String(Kind::Apc)), None),
+                '\x20'..='\x2f' => (Some(State::EscIntermediate), None),
+                '\x30'..='\x7e' => (None, Some(Kind::Esc)),
+                _ => return self.abort(f),
+            },
+            State::EscIntermediate => match c {
+                '\x20'..='\x2f' => (Some(state), None),
+                '\x30'..='\x7e' => (None, Some(Kind::Esc)),
+                _ => return self.abort(f),
+            },
+            State::Csi => match c {
+                '\x20'..='\x3f' => (Some(state), None),
+                '\x40'..='\x7e' => (None, Some(Kind::Csi)),
+                _ => return self.abort(f),
+            },
+            State::String(kind) => match c {
+                BEL => (None, Some(kind)),
+                ESC => (Some(State::StringEscape(kind)), None),
+                _ => (Some(state), None),
+            },
+            State::StringEscape(kind) => match c {
+                '\\' => (None, Some(kind)),
+                // an ESC that starts something else ends the string
+                _ => {
+                    let escape = self.pending.pop();
+                    f(Item::Sequence(kind, &self.pending));
+                    self.reset();
+                    self.pending.extend(escape);
+                    self.state = State::Escape;
+                    return false;
+                }
+            },
+        };
+
+        self.pending.push(c);
+        if let Some(kind) = done {
+            f(Item::Sequence(kind, &self.pending));
+            self.reset();
+            return true;
+        }
+        self.state = next.unwrap();
+        let limit = if matches!(self.state, State::String(_) | State::StringEscape(_)) { MAX_STRING } else { MAX_SEQUENCE };
+        if self.pending.len() > limit {
+            f(Item::Sequence(Kind::Invalid, &self.pending));
+            self.reset();
+        }
+        true
+    }
+
+    fn abort(&mut self, f: &mut dyn FnMut(Item<'_>)) -> bool {
+        f(Item::Sequence(Kind::Invalid, &self.pending));
+        self.reset();
+        false
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+        self.state = State::Ground;
+    }
+}
+
+/// Whether a terminal can show `item` without harm: text, line endings, tab, bell and
+/// backspace; SGR colors; cursor moves and erasing; saving and restoring the cursor.
+/// Everything else could retitle the window, switch screens, remap keys or make the terminal
+/// answer as if typed.
+pub fn is_safe(item: &Item<'_>) -> bool {
+    match *item {
+        Item::Text(_) => true,
+        Item::Control(c) => matches!(c, '\r' | '\n' | '\t' | BEL | '\x08'),
+        Item::Sequence(Kind::Esc, seq) => matches!(seq, "\x1b7" | "\x1b8"),
+        Item::Sequence(Kind::Csi, seq) => {
+            let body = &seq[2..];
+            let (params, last) = body.split_at(body.len() - 1);
+            // private modes (`?`, `<`, `=`, `>`) and intermediates are the dangerous ones
+            // `:` separates the sub-parameters of a color like `38:5:208`
+            params.chars().all(|c| c.is_ascii_digit() || c == ';' || c == ':')
+                && matches!(last, "m" | "A" | "B" | "C" | "D" | "E" | "F" | "G" | "H" | "f" | "J" | "K" | "s" | "u")
+        }
+        Item::Sequence(_, _) => false,
+    }
+}
+
+/// `text` with controls in caret notation, e.g. `^[]0;title^G`; a C1 control reads like
+/// the ESC sequence it stands for.
+pub fn visible(text: &str) -> String {
+    let mut shown = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c as u32 {
+            0x00..=0x1f => { shown.push('^'); shown.push((c as u8 + 0x40) as char); }
+            0x7f => shown.push_str("^?"),
+            0x80..=0x9f => { shown.push_str("^["); shown.push(char::from_u32(c as u32 - 0x40).unwrap()); }
+            _ => shown.push(c),
+        }
+    }
+    shown
+}
+
+/// `data` with every byte a terminal wouldn't show as text written out, for `--show-control`:
+/// controls as [`visible`] writes them (except tab and line endings), bytes that aren't UTF-8 as
+/// `\xNN`, each run of them dimmed with `dim`. Characters of any script come out as they are.
+pub fn show_control(data: &[u8], dim: bool) -> String {
+    let mut shown = String::with_capacity(data.len() + 8);
+    let mut dimmed = false;
+    let mut push = |shown: &mut String, text: &str, escaped: bool| {
+        if dim && escaped != dimmed {
+            shown.push_str(if escaped { DIM } else { NORMAL });
+            dimmed = escaped;
+        }
+        shown.push_str(text);
+    };
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\t' | '\n' | '\r' => push(&mut shown, c.encode_utf8(&mut [0; 4]), false),
+                c if c.is_control() => push(&mut shown, &visible(c.encode_utf8(&mut [0; 4])), true),
+                c => push(&mut shown, c.encode_utf8(&mut [0; 4]), false),
+            }
+        }
+        for byte in chunk.invalid() { push(&mut shown, &format!("\\x{:02x}", byte), true); }
+    }
+    if dimmed { shown.push_str(NORMAL); }
+    shown
+}
+
+/// Target output fit for the user's terminal, see [`is_safe`]; what isn't is shown with
+/// [`visible`].
+#[derive(Debug, Default)]
+pub struct Sanitizer {
+    parser: Parser,
+    /// Leaves out every sequence, safe or not, for a console that would print them as text.
+    strip: bool,
+}
+
+impl Sanitizer {
+    /// One that leaves out all sequences, colors included, for a console that can't show them.
+    pub fn stripping() -> Self {
+        Self { strip: true, ..Self::default() }
+    }
+
+    /// The usual one on a terminal that shows escape sequences, else [`Sanitizer::stripping`].
+    pub fn for_terminal(escapes: bool) -> Self {
+        if escapes { Self::default() } else { Self::stripping() }
+    }
+
+    pub fn sanitize(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let strip = self.strip;
+        self.parser.feed(text, &mut |item| push_sanitized(&mut out, item, strip));
+        out
+    }
+
+    /// What is still held back, e.g. an `ESC` the target never followed up on.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        let strip = self.strip;
+        self.parser.finish(&mut |item| push_sanitized(&mut out, item, strip));
+        out
+    }
+}
+
+fn push_sanitized(out: &mut String, item: Item<'_>, strip: bool) {
+    let safe = is_safe(&item);
+    match item {
+        Item::Sequence(_, _) if strip => {}
+        Item::Text(text) => out.push_str(text),
+        Item::Control(c) if safe => out.push(c),
+        Item::Control(c) => out.push_str(&visible(c.encode_utf8(&mut [0; 4]))),
+        Item::Sequence(_, seq) if safe => out.push_str(seq),
+        Item::Sequence(_, seq) => out.push_str(&visible(seq)),
+    }
+}
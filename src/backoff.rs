@@ -0,0 +1,31 @@
+//! Growing waits between checks for a device that went away.
+
+use std::time::Duration;
+
+/// Doubles from `initial` up to `cap`; the first check is for free so a quick replug goes unnoticed.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    next: Duration,
+    cap: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, cap: Duration) -> Self {
+        Self { next: initial, cap, attempts: 0 }
+    }
+
+    /// How long to wait before the next attempt, which is counted.
+    pub fn next_delay(&mut self) -> Duration {
+        self.attempts += 1;
+        if self.attempts == 1 { return Duration::ZERO; }
+        let delay = self.next.min(self.cap);
+        self.next = (self.next * 2).min(self.cap);
+        delay
+    }
+
+    /// Attempts made so far.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
@@ -0,0 +1,113 @@
+//! What a USB serial bridge makes of a baud rate. The chips divide a fixed clock, so most
+//! rates come out a little off and some far off: within [`TOLERANCE`] a UART still samples
+//! every bit right, beyond it the target sees garbage. `--force-baud` skips the check.
+
+/// How far off the rate a bridge makes may be, as a fraction of the one asked for.
+pub const TOLERANCE: f64 = 0.02;
+
+/// The rates suggested instead of one a bridge can't make.
+pub const STANDARD_RATES: [u32; 18] = [
+    1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 250000, 460800, 500000, 576000, 921600,
+    1000000, 1500000, 2000000, 3000000,
+];
+
+/// What a CP2102 or CP2104 runs at, each rate asked for taken to the nearest of these.
+const CP210X_RATES: [u32; 29] = [
+    300, 600, 1200, 1800, 2400, 4000, 4800, 7200, 9600, 14400, 16000, 19200, 28800, 38400, 51200, 56000, 57600,
+    64000, 76800, 115200, 128000, 153600, 230400, 250000, 256000, 460800, 500000, 576000, 921600,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bridge {
+    /// FT232R, FT2232 and the like, 3 MHz divided in eighths.
+    Ftdi,
+    /// The classic CP2102 and CP2104 with their fixed rates; a CP2102N makes more than these.
+    Cp210x,
+    /// CH340 and CH341, 48 MHz through a prescaler and an 8-bit divisor.
+    Ch340,
+    /// PL2303HX, 12 MHz through a mantissa and a power of four.
+    Pl2303,
+}
+
+impl Bridge {
+    /// The bridge of a USB adapter with this vendor and product ID, for the common ones.
+    pub fn from_usb(vid: u16, pid: u16) -> Option<Bridge> {
+        match (vid, pid) {
+            (0x0403, 0x6001 | 0x6010 | 0x6011 | 0x6015) => Some(Bridge::Ftdi),
+            (0x10c4, 0xea60 | 0xea70 | 0xea71) => Some(Bridge::Cp210x),
+            (0x1a86, 0x7523 | 0x5523) => Some(Bridge::Ch340),
+            (0x067b, 0x2303) => Some(Bridge::Pl2303),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bridge::Ftdi => "FTDI",
+            Bridge::Cp210x => "CP210x",
+            Bridge::Ch340 => "CH340",
+            Bridge::Pl2303 => "PL2303",
+        }
+    }
+
+    /// The rate the bridge runs at when asked for `requested`, as its driver sets it up.
+    pub fn actual(self, requested: u32) -> u32 {
+        let requested = requested.max(1);
+        match self {
+            Bridge::Ftdi => {
+                // divisors 0 and 1 are 3 and 2 MBd, fractional ones start at 2
+                let eighths = ((24_000_000.0 / requested as f64).round() as u32).clamp(16, 0x3fff * 8 + 7);
+                [3_000_000, 2_000_000, 24_000_000 / eighths].iter().copied()
+                    .min_by(|a, b| deviation(requested, *a).total_cmp(&deviation(requested, *b)))
+                    .unwrap()
+            }
+            Bridge::Cp210x => CP210X_RATES.iter().copied().min_by_key(|rate| rate.abs_diff(requested)).unwrap(),
+            Bridge::Ch340 => ch340_rate(requested),
+            Bridge::Pl2303 => {
+                const BASELINE: u32 = 12_000_000 * 32;
+                let (mut mantissa, mut exponent) = ((BASELINE / requested).max(1), 0);
+                while mantissa >= 512 {
+                    if exponent < 7 {
+                        mantissa >>= 2;
+                        exponent += 1;
+                    } else {
+                        mantissa = 511;
+                        break;
+                    }
+                }
+                BASELINE / (mantissa << (exponent * 2))
+            }
+        }
+    }
+
+    /// `Err` with the rate to use instead when `requested` comes out more than [`TOLERANCE`]
+    /// off: the nearest of the [`STANDARD_RATES`] the bridge makes, or else what it makes.
+    pub fn check(self, requested: u32) -> Result<(), u32> {
+        let fits = |rate: u32| deviation(rate, self.actual(rate)) <= TOLERANCE;
+        if fits(requested) { return Ok(()); }
+        Err(STANDARD_RATES.iter().copied().filter(|&rate| fits(rate)).min_by_key(|rate| rate.abs_diff(requested))
+            .unwrap_or_else(|| self.actual(requested)))
+    }
+}
+
+/// How far `actual` is off `requested`, as a fraction of it.
+pub fn deviation(requested: u32, actual: u32) -> f64 {
+    (actual as f64 - requested as f64).abs() / requested.max(1) as f64
+}
+
+/// The CH341 driver's choice of prescaler and divisor, in Linux' `ch341_get_divisor`.
+fn ch340_rate(requested: u32) -> u32 {
+    const CLOCK: u64 = 48_000_000;
+    let clock_div = |prescaler: u32, fact: u32| 1u64 << (12 - 3 * prescaler - fact);
+    let speed = (requested as u64).clamp(CLOCK.div_ceil(clock_div(0, 0) * 256), CLOCK / (clock_div(3, 0) * 2));
+    let prescaler = (0..=3).rev().find(|&prescaler| speed > CLOCK / (clock_div(prescaler, 1) * 512)).unwrap_or(0);
+    let mut clock = clock_div(prescaler, 1);
+    let mut div = CLOCK / (clock * speed);
+    if !(9..=255).contains(&div) {
+        div /= 2;
+        clock *= 2;
+    }
+    // the next divisor when that comes closer
+    if 16 * CLOCK / (clock * div) - 16 * speed >= 16 * speed - 16 * CLOCK / (clock * (div + 1)) { div += 1; }
+    (CLOCK / (clock * div)) as u32
+}
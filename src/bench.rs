@@ -0,0 +1,212 @@
+//! Throughput measurements of an open port, to tell adapter, cable and loader bottlenecks apart.
+
+use std::{fmt, io::{self, Read, Write}, str::FromStr, time::{Duration, Instant}};
+
+use crate::{ErrorKind, ReadSerial, Result};
+
+/// What the generated traffic looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchData {
+    /// 0x00, 0x01, ..., 0xFF, 0x00, ...
+    Pattern,
+    /// Deterministic pseudo-random bytes, see [`Xorshift`].
+    Random,
+}
+
+impl FromStr for BenchData {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "pattern" => Ok(BenchData::Pattern),
+            "random" => Ok(BenchData::Random),
+            _ => Err("expected pattern or random".to_string()),
+        }
+    }
+}
+
+/// Small xorshift32 generator: the same seed always gives the same byte stream.
+#[derive(Debug, Clone)]
+pub struct Xorshift(u32);
+
+impl Xorshift {
+    pub fn new(seed: u32) -> Self {
+        Xorshift(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        buf.iter_mut().for_each(|b| *b = self.next_u32() as u8);
+    }
+}
+
+/// Produces `total` bytes of `data`, independent of how it is cut into chunks.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    data: BenchData,
+    rng: Xorshift,
+    counter: u8,
+}
+
+impl Generator {
+    pub fn new(data: BenchData) -> Self {
+        Self { data, rng: Xorshift::new(1), counter: 0 }
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        match self.data {
+            BenchData::Pattern => buf.iter_mut().for_each(|b| {
+                *b = self.counter;
+                self.counter = self.counter.wrapping_add(1);
+            }),
+            BenchData::Random => self.rng.fill(buf),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub bytes: u64,
+    pub chunk_sizes: Vec<usize>,
+    pub data: BenchData,
+    /// The far end echoes: also time write-then-read-back round trips.
+    pub read_back: bool,
+    /// How long to wait for an echoed chunk before counting it as lost.
+    pub read_timeout: Duration,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            bytes: 64 * 1024,
+            chunk_sizes: vec![1, 64, 512, 4096],
+            data: BenchData::Pattern,
+            read_back: false,
+            read_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Write,
+    Echo,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Phase::Write => "write",
+            Phase::Echo => "echo",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub phase: Phase,
+    pub chunk_size: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Write phase: writes that stalled past the port timeout. Echo phase: bytes lost or changed.
+    pub errors: u64,
+}
+
+impl BenchResult {
+    pub fn kib_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / 1024.0 / secs } else { 0.0 }
+    }
+}
+
+/// Writes `config.bytes` in `chunk_size` pieces and waits for the port to drain them.
+pub fn run_write<P, F>(port: &mut P, config: &BenchConfig, chunk_size: usize, mut progress: F) -> Result<BenchResult>
+    where P: Write + ?Sized, F: FnMut(u64) {
+    let mut generator = Generator::new(config.data);
+    let mut chunk = vec![0; chunk_size];
+    let (mut sent, mut errors) = (0u64, 0u64);
+
+    let started = Instant::now();
+    while sent < config.bytes {
+        let len = chunk_size.min((config.bytes - sent) as usize);
+        generator.fill(&mut chunk[..len]);
+        errors += write_chunk(port, &chunk[..len])?;
+        sent += len as u64;
+        progress(len as u64);
+    }
+    port.flush().map_err(|_| ErrorKind::ConnectionError)?;
+
+    Ok(BenchResult { phase: Phase::Write, chunk_size, bytes: sent, elapsed: started.elapsed(), errors })
+}
+
+/// Writes each chunk and reads its echo back before sending the next one.
+pub fn run_echo<P, F>(port: &mut P, config: &BenchConfig, chunk_size: usize, mut progress: F) -> Result<BenchResult>
+    where P: Read + Write + ?Sized, F: FnMut(u64) {
+    let mut generator = Generator::new(config.data);
+    let mut chunk = vec![0; chunk_size];
+    let mut echoed = vec![0; chunk_size];
+    let (mut sent, mut errors) = (0u64, 0u64);
+
+    let started = Instant::now();
+    while sent < config.bytes {
+        let len = chunk_size.min((config.bytes - sent) as usize);
+        generator.fill(&mut chunk[..len]);
+        write_chunk(port, &chunk[..len])?;
+
+        let got = port.read_serial_timeout(&mut echoed[..len], config.read_timeout)?;
+        errors += (len - got) as u64;
+        errors += chunk[..got].iter().zip(&echoed[..got]).filter(|(a, b)| a != b).count() as u64;
+        sent += len as u64;
+        progress(len as u64);
+    }
+
+    Ok(BenchResult { phase: Phase::Echo, chunk_size, bytes: sent, elapsed: started.elapsed(), errors })
+}
+
+/// Reads and drops input until the line has been quiet for `quiet`.
+pub fn drain<P: Read + ?Sized>(port: &mut P, quiet: Duration) -> Result<()> {
+    let mut buf = [0; 256];
+    while port.read_serial_timeout(&mut buf, quiet)? > 0 {}
+    Ok(())
+}
+
+/// Writes all of `data`, retrying writes that time out, and returns how many did.
+fn write_chunk<P: Write + ?Sized>(port: &mut P, mut data: &[u8]) -> Result<u64> {
+    let mut stalls = 0;
+    while !data.is_empty() {
+        match port.write(data) {
+            Ok(0) => return Err(ErrorKind::ConnectionError),
+            Ok(n) => data = &data[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => stalls += 1,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return Err(ErrorKind::ConnectionError),
+        }
+    }
+    Ok(stalls)
+}
+
+/// Plain text table of `results`.
+pub fn table(results: &[BenchResult]) -> String {
+    let mut out = format!("{:<6} {:>6} {:>9} {:>9} {:>10} {:>7}\n", "phase", "chunk", "bytes", "seconds", "KiB/s", "errors");
+    for r in results {
+        out += &format!("{:<6} {:>6} {:>9} {:>9.3} {:>10.1} {:>7}\n",
+                        r.phase, r.chunk_size, r.bytes, r.elapsed.as_secs_f64(), r.kib_per_sec(), r.errors);
+    }
+    out
+}
+
+/// One JSON object per run, tagged with the line settings, for tracking results over time.
+pub fn to_json(settings: &crate::settings::SerialSettings, results: &[BenchResult]) -> String {
+    let runs: Vec<String> = results.iter().map(|r| format!(
+        "{{\"phase\":\"{}\",\"chunk_size\":{},\"bytes\":{},\"seconds\":{:.6},\"kib_per_s\":{:.3},\"errors\":{}}}",
+        r.phase, r.chunk_size, r.bytes, r.elapsed.as_secs_f64(), r.kib_per_sec(), r.errors)).collect();
+    format!("{{\"baud\":{},\"framing\":\"{}\",\"results\":[{}]}}", settings.baud_rate, settings.framing(), runs.join(","))
+}
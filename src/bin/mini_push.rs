@@ -1,12 +1,287 @@
-use std::{fs::File, io::{Read, Write}, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+use std::{fs, io::{self, IsTerminal, Read, Seek, SeekFrom}, path::{Path, PathBuf}, process, sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use rust_serial_tool::{Colorize, create_pb, ErrorKind, ReadSerial, Result, SerialPort, SerialTool, sleep, timeout};
+use clap::Parser;
+#[cfg(feature = "http")]
+use rust_serial_tool::fetch::{self, TempImage, Url};
+use rust_serial_tool::{backoff::Backoff, block::{self, BlockSender, PushProtocol}, cli::{self, OutputArgs, ProfileArgs, SerialArgs, TerminalArgs}, command::{Command, CommandTable}, control::ControlSocket, delta::{self, DeltaCache, Manifest}, early::{EarlyBuffer, EarlyOutput}, ErrorKind, events::{Event, EventLog}, exit, fleet, highlight::Highlighter, identity::{self, TargetIdentity}, image::{self, Image, ReadAhead}, limit::RateLimiter, observer::{ObserverSlot, PushObserver, PushReport}, output::{ColorChoice, format_bytes, Icon, Output, ProgressChoice, Verbosity}, pattern::Pattern, phases::PushTimings, portwatch::{PortEvent, PortWatcher}, prompt::LocalCommands, report::{self, TransferReport}, protocol::{self, Action, Capabilities, Chainboot, Input, LoaderInfo, PushState, RequestMatcher, SizeHeader}, logfile::SessionLog, record::{Direction, Recorder}, Result, scrollback::Scrollback, SERIAL_BAUD, SerialPort, SerialTool, settings::{parse_reset_line, ResetLine, ResetPulse, SerialSettings, SyncAction}, sha256::{self, Sha256}, signal, stats::SessionStats, terminal::{self, ExitReason, TerminalOptions}, timeout, timesync::{Iso8601, TimeFormat}, transport::{self, Presence, UsbIdentity}, trigger::Triggers, txlog::TxLog, watch::{self, Build, ImageStamp, Watch}, wire::WireLog, WRITE_TIMEOUT, WriteSerial};
 
+const EXAMPLES: &str = "\
+Examples:
+  mini_push /dev/ttyUSB0 kernel8.img
+  mini_push CP210x kernel8.img --reset dtr --no-terminal --boot-secs 5
+  mini_push /dev/ttyUSB0 kernel8.img --no-terminal --expect-boot \"regex:login:|Kernel panic\"
+  mini_push /dev/ttyUSB0 kernel8.img --no-terminal --exit-on-success \"ALL TESTS PASSED\" --exit-on-failure FAILED: --max-session-time 600
+  mini_push /dev/ttyUSB0 kernel8.img --sync-time \"text:date -s '{iso8601}'\\n\" --sync-time-ready \"str:# \"
+  mini_push COM3 build/kernel.hex --protocol block --resume
+  mini_push /dev/ttyUSB0 kernel8.img --reset dtr --negotiate --delta
+  mini_push /dev/ttyUSB0 kernel8.img --negotiate --auto-baud 3000000,921600,460800
+  mini_push tcp://terminal-server:4001 kernel8.img
+  mini_push '/dev/ttyUSB*' kernel8.img --reset dtr --attach /dev/ttyUSB0
+  mini_push --profile rpi4 --baud 115200
+  MINIPUSH_SERIAL=/dev/ttyUSB0 MINIPUSH_IMAGE=kernel8.img mini_push --no-terminal
+  mini_push /dev/ttyUSB0 kernel8.img --reset dtr --watch --watch-path src --exec \"make kernel8.img\"
+  cat kernel8.img | mini_push /dev/ttyUSB0 -
+  mini_push /dev/ttyUSB0 kernel8.img --script --reset dtr --expect-boot str:Booted > boot.log";
+
+/// Push a kernel image to a chainloader over serial, then attach a terminal.
+#[derive(Parser)]
+#[command(name = "mini_push", version = rust_serial_tool::VERSION, after_help = EXAMPLES,
+          group(clap::ArgGroup::new("detached").args(["no_terminal", "script"]).multiple(true)))]
+struct Args {
+    /// Serial device of the target, e.g. /dev/ttyUSB0, COM3 or part of its USB description like "CP210x";
+    /// rfc2217://host:port or tcp://host:port for one behind a terminal server. Several boards,
+    /// as a comma-separated list or a glob like "/dev/ttyUSB*", are pushed to at once
+    serial_name: String,
+    /// Raw binary, Intel HEX or SREC image to push; - reads it from stdin, and with the http
+    /// feature an http:// or https:// URL downloads it. Wildcards in the file name, e.g. "out/kernel8-*.img",
+    /// push the newest match, picked again for each push
+    image_path: String,
+    /// SHA-256 the image must have, e.g. not yesterday's build: checked as it is pushed, and a
+    /// download before that
+    #[arg(long, alias = "expect-sha256", value_name = "HEX", value_parser = sha256::parse)]
+    sha256: Option<[u8; 32]>,
+    /// Byte used to fill gaps between HEX/SREC records (0xff or 0x00)
+    #[arg(long, default_value = "0xff", value_parser = parse_fill)]
+    fill: u8,
+    /// Refuse images larger than this, more likely a wrong path than a kernel, e.g. 512M
+    #[arg(long, value_name = "SIZE", default_value = "128M", value_parser = cli::parse_size)]
+    max_image_size: u64,
+    /// Push an image however large it is
+    #[arg(long)]
+    allow_huge: bool,
+    #[command(flatten)]
+    serial: SerialArgs,
+    #[command(flatten)]
+    terminal: TerminalArgs,
+    /// Pulse DTR or RTS to reset the target instead of asking to power it
+    #[arg(long, value_parser = parse_reset_line)]
+    reset: Option<ResetLine>,
+    /// How long the reset line is held, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    reset_hold: u64,
+    /// The reset pin is active-low: pulse by deasserting the line
+    #[arg(long)]
+    reset_active_low: bool,
+    /// Announce the size with the 64-bit extended header, for loaders that support images over 4 GiB
+    #[arg(long)]
+    extended_size: bool,
+    /// How the image is sent: stream (raw bytes) or block (CRC-checked, retransmitted on NAK)
+    #[arg(long, default_value = "stream")]
+    protocol: PushProtocol,
+    /// Pick an interrupted push up where it stopped; the loader must acknowledge every chunk
+    #[arg(long)]
+    resume: bool,
+    /// Send only the blocks that changed since the last push to this board, for loaders that
+    /// keep the image in RAM across a warm reset. A loader holding some other image gets the
+    /// whole one
+    #[arg(long, requires = "negotiate", conflicts_with_all = ["resume", "protocol"])]
+    delta: bool,
+    /// Bytes per write, block or acknowledged chunk
+    #[arg(long, value_name = "BYTES", default_value_t = block::BLOCK_SIZE as u16, value_parser = clap::value_parser!(u16).range(1..))]
+    chunk_size: u16,
+    /// What the loader sends to ask for the image: hex:BYTES, str:TEXT or regex:REGEX
+    #[arg(long, default_value = "hex:030303")]
+    trigger: RequestMatcher,
+    /// Ask the loader for its protocol version and capabilities after its request. Loaders that
+    /// don't answer get the classic exchange; the tutorial loader doesn't support this
+    #[arg(long)]
+    negotiate: bool,
+    /// Rates to switch the line to for the image, for loaders that can: the fastest the loader
+    /// takes and whose echo comes back intact is used, the next one down after a failed check.
+    /// The terminal is at --baud again afterwards
+    #[arg(long, value_name = "RATES", value_delimiter = ',', value_parser = cli::parse_baud, requires = "negotiate")]
+    auto_baud: Vec<u32>,
+    /// Take the loader for this protocol version instead of asking it; 2 takes a command line
+    #[arg(long, value_name = "N", conflicts_with = "negotiate")]
+    protocol_version: Option<u8>,
+    /// Ask the loader which board it is after its request, and say so. Loaders that don't
+    /// answer are pushed to as ever; the tutorial loader doesn't support this
+    #[arg(long)]
+    identify: bool,
+    /// Push only to the board whose loader says it is ID, e.g. serial=00000000a1b2c3d4 or its
+    /// MAC; any other, or one that doesn't say, gets none of the image. Implies --identify
+    #[arg(long, value_name = "ID")]
+    expect_target: Option<String>,
+    /// Boot arguments sent after the image, for loaders that take a command line
+    #[arg(long, value_name = "STRING")]
+    cmdline: Option<String>,
+    /// Like --cmdline, read from a file
+    #[arg(long, value_name = "PATH", conflicts_with = "cmdline")]
+    cmdline_file: Option<PathBuf>,
+    /// Seconds the loader gets to ask for the image after power-on or reset; 0 waits until it does
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    handshake_timeout: u64,
+    /// Milliseconds to wait after the loader acknowledged the size before the image follows, for
+    /// loaders that only then set up their receive buffer: sent too soon, the first bytes of the
+    /// image get lost and it boots corrupted, or not at all
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    post_ack_delay: u64,
+    #[command(flatten)]
+    output: OutputArgs,
+    #[command(flatten)]
+    profile: ProfileArgs,
+    /// Exit after the push instead of opening the terminal, e.g. in CI
+    #[arg(long)]
+    no_terminal: bool,
+    /// With several boards, open the terminal on this one once all are pushed to; without it,
+    /// there is no terminal
+    #[arg(long, value_name = "DEVICE", conflicts_with = "no_terminal")]
+    attach: Option<String>,
+    /// Push again whenever the image changes, reattaching the terminal in between
+    #[arg(long, conflicts_with = "no_terminal")]
+    watch: bool,
+    /// With --watch, a file or directory to look at for changes instead of the image; repeatable
+    #[arg(long, value_name = "PATH", requires = "watch")]
+    watch_path: Vec<PathBuf>,
+    /// With --watch, build command run through the shell on a change; the image is pushed only
+    /// when it succeeds and the image changed. Ctrl-C cancels the build
+    #[arg(long, value_name = "COMMAND", requires = "watch")]
+    exec: Option<String>,
+    /// After the push, fail unless the target prints this within --expect-boot-timeout, e.g.
+    /// "regex:login:|# $" or "str:Booted"
+    #[arg(long, value_name = "PATTERN")]
+    expect_boot: Option<Pattern>,
+    /// Seconds --expect-boot waits for its pattern
+    #[arg(long, value_name = "SECONDS", default_value_t = 30, requires = "expect_boot")]
+    expect_boot_timeout: u64,
+    /// After the push, send the host time: unix64 (8 bytes, little-endian) or text:TEMPLATE with
+    /// {unix} and {iso8601}, e.g. "text:date -s '{iso8601}'\n"
+    #[arg(long, value_name = "FORMAT")]
+    sync_time: Option<TimeFormat>,
+    /// With --sync-time, wait for the target to print this first: str:TEXT or regex:REGEX
+    #[arg(long, value_name = "PATTERN", requires = "sync_time")]
+    sync_time_ready: Option<Pattern>,
+    /// Seconds --sync-time waits for its ready marker
+    #[arg(long, value_name = "SECONDS", default_value_t = 30, requires = "sync_time_ready")]
+    sync_time_timeout: u64,
+    /// With --no-terminal, first show this many seconds of boot output; with --exit-on-success
+    /// or --exit-on-failure, as long as it takes one of them to match
+    #[arg(long, default_value_t = 0, requires = "detached")]
+    boot_secs: u64,
+    /// After a disconnect, give up once the port was looked for this many times (default: wait forever)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_reconnect_attempts: Option<u32>,
+    /// After the session, write a JSON report of the push to this file (- for stdout): device,
+    /// image, SHA-256, bytes, timing, reconnects, protocol features and the outcome
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+    /// Emit a push_progress event every this many percent
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+    progress_every: u8,
+    /// What the loader prints while the image goes out: hold (shown once the bar is done) or
+    /// live (above the bar, a line at a time)
+    #[arg(long, value_name = "MODE", default_value = "hold")]
+    early_output: EarlyOutput,
+    /// For a test harness: errors only on stderr (-v for the rest), no colors, bar or terminal, no waiting for the port or reconnecting, and exit
+    /// codes that tell failures apart: 2 handshake timeout, 3 protocol error, 4 device
+    /// missing, 5 image error, 1 anything else
+    #[arg(long, conflicts_with_all = ["watch", "attach"])]
+    script: bool,
+}
+
+fn parse_fill(s: &str) -> std::result::Result<u8, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "0xff" | "ff" => Ok(0xFF),
+        "0x00" | "00" | "0" => Ok(0x00),
+        _ => Err("expected 0xff or 0x00".to_string()),
+    }
+}
+
+/// Waits between looks for a replugged port, and how often a long wait is reported.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_BACKOFF_CAP: Duration = Duration::from_secs(4);
+const RECONNECT_STATUS_EVERY: Duration = Duration::from_secs(10);
+
+/// How long the loader gets to ask for the image unless told otherwise.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a push starts over from the size when the loader restarts during it, before
+/// that counts as a broken connection.
+const REBOOT_RESTARTS: u32 = 3;
+
+/// Frames of the spinner shown while waiting for the loader without a time limit.
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const SPINNER_EVERY: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
 pub struct MiniPush {
     name_short: String,
     binary_image_path: String,
+    /// The image when it came from stdin, kept for the attempts after a reconnect.
+    spooled: Option<Vec<u8>>,
+    /// Where the image is read from when that isn't the image path, e.g. a download.
+    image_file: Option<PathBuf>,
+    fill: u8,
+    expect_sha256: Option<[u8; 32]>,
+    max_image_size: Option<u64>,
     target_serial_name: String,
-    target_serial: Option<SerialPort>,
+    serial_settings: SerialSettings,
+    terminal_options: TerminalOptions,
+    highlighter: Highlighter,
+    triggers: Triggers,
+    recorder: Option<Arc<Recorder>>,
+    wire: Option<Arc<WireLog>>,
+    session_log: Option<Arc<SessionLog>>,
+    scrollback: Option<Arc<Scrollback>>,
+    tx_log: Option<Arc<TxLog>>,
+    stats: Arc<SessionStats>,
+    control: Option<Arc<ControlSocket>>,
+    /// Set by the `push` local command, which ends the terminal to push the image again.
+    repush: Arc<AtomicBool>,
+    reset: Option<ResetPulse>,
+    size_header: SizeHeader,
+    binary_request: RequestMatcher,
+    handshake_timeout: Option<Duration>,
+    /// `--post-ack-delay`: the pause between the size being acknowledged and the image.
+    post_ack_delay: Duration,
+    negotiate: bool,
+    /// `--auto-baud`: the rates the line is stepped up to for the image.
+    auto_baud: Vec<u32>,
+    /// The rate the last image went out at, when `--auto-baud` got a faster one.
+    line_rate: Option<u32>,
+    protocol_version: Option<u8>,
+    loader: Option<LoaderInfo>,
+    identify: bool,
+    expect_target: Option<String>,
+    /// What the loader said it is, with `--identify`.
+    target: Option<TargetIdentity>,
+    cmdline: String,
+    resume: bool,
+    /// `--delta`: what was last pushed to each target.
+    delta: Option<DeltaCache>,
+    chunk_size: usize,
+    push_protocol: PushProtocol,
+    no_terminal: Option<Duration>,
+    /// `--expect-boot`: what the target has to print after the push, and how soon.
+    boot_marker: Option<(Pattern, Duration)>,
+    /// `--sync-time`: how the time is sent, and what the target prints when it is ready for it.
+    time_sync: Option<(TimeFormat, Option<(Pattern, Duration)>)>,
+    /// `--watch`: what is looked at for changes, and the `--exec` build run on one.
+    watch_paths: Option<Vec<PathBuf>>,
+    build_command: Option<String>,
+    pushed: PushState,
+    /// The last push that went through, for `--report`.
+    last_push: Option<PushReport>,
+    /// Where the time of each attempt went, for the breakdown after the push and `--report`.
+    timings: PushTimings,
+    /// With several boards, how the push to each went.
+    board_reports: Vec<TransferReport>,
+    events: Option<Arc<EventLog>>,
+    observer: ObserverSlot,
+    output: Output,
+    progress_every: u8,
+    early_output: EarlyOutput,
+    phase: &'static str,
+    force_lock: bool,
+    force_baud: bool,
+    /// The USB adapter at the port when it was first opened, see [`SerialTool::check_device`].
+    known_device: Option<UsbIdentity>,
+    allow_device_change: bool,
+    single_attempt: bool,
+    sync_on_connect: Option<SyncAction>,
+    max_reconnect_attempts: Option<u32>,
+    /// Whether boot output is shown, which one board among several doesn't.
+    show_output: bool,
 }
 
 
@@ -15,75 +290,796 @@ impl MiniPush {
         Self {
             name_short: "MP".to_string(),
             binary_image_path,
+            spooled: None,
+            image_file: None,
+            fill: 0xFF,
+            expect_sha256: None,
+            max_image_size: Some(image::MAX_SIZE),
             target_serial_name,
-            target_serial: None,
+            serial_settings: SerialSettings::default(),
+            terminal_options: TerminalOptions::default(),
+            highlighter: Highlighter::default(),
+            triggers: Triggers::default(),
+            recorder: None,
+            wire: None,
+            session_log: None,
+            scrollback: None,
+            tx_log: None,
+            stats: Arc::new(SessionStats::default()),
+            control: None,
+            repush: Arc::new(AtomicBool::new(false)),
+            reset: None,
+            size_header: SizeHeader::Legacy,
+            binary_request: RequestMatcher::default(),
+            handshake_timeout: Some(HANDSHAKE_TIMEOUT),
+            post_ack_delay: Duration::ZERO,
+            negotiate: false,
+            auto_baud: Vec::new(),
+            line_rate: None,
+            protocol_version: None,
+            loader: None,
+            identify: false,
+            expect_target: None,
+            target: None,
+            cmdline: String::new(),
+            resume: false,
+            delta: None,
+            chunk_size: block::BLOCK_SIZE,
+            push_protocol: PushProtocol::Stream,
+            no_terminal: None,
+            boot_marker: None,
+            time_sync: None,
+            watch_paths: None,
+            build_command: None,
+            pushed: PushState::default(),
+            last_push: None,
+            timings: PushTimings::default(),
+            board_reports: Vec::new(),
+            events: None,
+            observer: ObserverSlot::default(),
+            output: Output::new("MP", Verbosity::Normal),
+            progress_every: 10,
+            early_output: EarlyOutput::Hold,
+            phase: "open",
+            force_lock: false,
+            force_baud: false,
+            known_device: None,
+            allow_device_change: false,
+            single_attempt: false,
+            sync_on_connect: None,
+            max_reconnect_attempts: None,
+            show_output: true,
         }
     }
 
-    fn wait_for_binary_request(&mut self) -> Result<()> {
-        println!("[{}] 🔌 Please power the target now", self.name_short);
-        let serial = self.target_serial().ok_or(ErrorKind::NoneError("serial"))?;
+    /// Pushes `data` rather than reading the image path, e.g. an image piped in on stdin.
+    pub fn set_spooled_image(&mut self, data: Option<Vec<u8>>) {
+        self.spooled = data;
+    }
 
-        let f = move |flag: Arc<AtomicBool>| -> Result<()> {
-            let mut received = [0; 4096];
+    /// Reads the image from `path`, still calling it by the image path.
+    pub fn set_image_file(&mut self, path: Option<PathBuf>) {
+        self.image_file = path;
+    }
 
-            let mut n = serial.read_serial(&mut received).map_err(|_| ErrorKind::ConnectionError)?;
-            let mut count = 0;
-            while flag.load(Ordering::Relaxed) {
-                for &c in received[..n].iter() {
-                    if c == 0x03 {
-                        count += 1;
-                        if count == 3 {
-                            return Ok(());
-                        }
-                    } else {
-                        count = 0;
-                        print!("{}", c as char);
-                    }
+    pub fn set_fill(&mut self, fill: u8) {
+        self.fill = fill;
+    }
+
+    /// Fail the push of an image with another SHA-256, and a download before it starts.
+    pub fn set_expect_sha256(&mut self, sha256: Option<[u8; 32]>) {
+        self.expect_sha256 = sha256;
+    }
+
+    /// Fail before pushing an image larger than this; `None` pushes any, see `--allow-huge`.
+    pub fn set_max_image_size(&mut self, max: Option<u64>) {
+        self.max_image_size = max;
+    }
+
+    pub fn set_serial_settings(&mut self, settings: SerialSettings) {
+        self.serial_settings = settings;
+    }
+
+    pub fn set_terminal_options(&mut self, options: TerminalOptions) {
+        self.terminal_options = options;
+    }
+
+    pub fn set_force_lock(&mut self, force: bool) {
+        self.force_lock = force;
+    }
+
+    pub fn set_force_baud(&mut self, force: bool) {
+        self.force_baud = force;
+    }
+
+    pub fn set_allow_device_change(&mut self, allow: bool) {
+        self.allow_device_change = allow;
+    }
+
+    /// `--script`: one attempt, nothing to wait for.
+    pub fn set_script(&mut self, script: bool) {
+        self.single_attempt = script;
+    }
+
+    pub fn set_sync_on_connect(&mut self, sync: Option<SyncAction>) {
+        self.sync_on_connect = sync;
+    }
+
+    pub fn set_highlighter(&mut self, highlighter: Highlighter) {
+        self.highlighter = highlighter;
+    }
+
+    pub fn set_triggers(&mut self, triggers: Triggers) {
+        self.triggers = triggers;
+    }
+
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        self.recorder = recorder;
+    }
+
+    pub fn set_wire(&mut self, wire: Option<Arc<WireLog>>) {
+        self.wire = wire;
+    }
+
+    pub fn set_session_log(&mut self, log: Option<Arc<SessionLog>>) {
+        self.session_log = log;
+    }
+
+    pub fn set_scrollback(&mut self, scrollback: Option<Arc<Scrollback>>) {
+        self.scrollback = scrollback;
+    }
+
+    /// Mirror every byte written to the port to `log`, see [`txlog`].
+    pub fn set_tx_log(&mut self, log: Option<Arc<TxLog>>) {
+        self.tx_log = log;
+    }
+
+    pub fn set_control(&mut self, control: Option<Arc<ControlSocket>>) {
+        self.control = control;
+    }
+
+    pub fn set_reset(&mut self, reset: Option<ResetPulse>) {
+        self.reset = reset;
+    }
+
+    pub fn set_size_header(&mut self, header: SizeHeader) {
+        self.size_header = header;
+    }
+
+    /// What the loader sends to ask for the image, three ETX bytes by default.
+    pub fn set_binary_request(&mut self, request: RequestMatcher) {
+        self.binary_request = request;
+    }
+
+    /// How long the loader gets to ask for the image before the attempt counts as failed;
+    /// `None` waits until it does or the user presses Ctrl-C.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// How long to wait after the size acknowledgement before sending the image.
+    pub fn set_post_ack_delay(&mut self, delay: Duration) {
+        self.post_ack_delay = delay;
+    }
+
+    /// Probe the loader's version after its request, see [`protocol::negotiate`].
+    pub fn set_negotiate(&mut self, negotiate: bool) {
+        self.negotiate = negotiate;
+    }
+
+    /// Step the line up to the fastest of `rates` the loader manages, see [`protocol::BaudLadder`].
+    pub fn set_auto_baud(&mut self, rates: Vec<u32>) {
+        self.auto_baud = rates;
+    }
+
+    /// Ask the loader which board it is after its request, see [`identity::identify`].
+    pub fn set_identify(&mut self, identify: bool) {
+        self.identify = identify;
+    }
+
+    /// Push only to the target that says it is `id`, see [`identity::check`]; asks it with or
+    /// without [`MiniPush::set_identify`].
+    pub fn set_expect_target(&mut self, id: Option<String>) {
+        self.expect_target = id;
+    }
+
+    /// Take the loader for `version` without asking it, see [`LoaderInfo::assumed`].
+    pub fn set_protocol_version(&mut self, version: Option<u8>) {
+        self.protocol_version = version;
+    }
+
+    /// Boot arguments sent after the image; empty skips the step.
+    pub fn set_cmdline(&mut self, cmdline: String) {
+        self.cmdline = cmdline;
+    }
+
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
+    /// Patch the image the loader kept where it can, remembering what was pushed in `cache`.
+    pub fn set_delta(&mut self, cache: Option<DeltaCache>) {
+        self.delta = cache;
+    }
+
+    /// How much of the image goes out at a time; a resuming loader acknowledges each chunk.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size;
+    }
+
+    pub fn set_push_protocol(&mut self, protocol: PushProtocol) {
+        self.push_protocol = protocol;
+    }
+
+    pub fn set_early_output(&mut self, early_output: EarlyOutput) {
+        self.early_output = early_output;
+    }
+
+    /// Stop after the push, once `boot_output` of the target's output has been shown.
+    pub fn set_no_terminal(&mut self, boot_output: Option<Duration>) {
+        self.no_terminal = boot_output;
+    }
+
+    /// Fail the push unless `pattern` shows up in the target's output within `deadline`.
+    pub fn set_boot_marker(&mut self, marker: Option<(Pattern, Duration)>) {
+        self.boot_marker = marker;
+    }
+
+    /// Send the host time in `format` after the push, once the target printed `ready` if given.
+    pub fn set_time_sync(&mut self, format: Option<TimeFormat>, ready: Option<(Pattern, Duration)>) {
+        self.time_sync = format.map(|format| (format, ready));
+    }
+
+    /// Push again from the terminal whenever a file under `paths` changes.
+    pub fn set_watch(&mut self, paths: Option<Vec<PathBuf>>) {
+        self.watch_paths = paths;
+    }
+
+    /// With `--watch`, run `command` on a change, pushing only what it built successfully.
+    pub fn set_build_command(&mut self, command: Option<String>) {
+        self.build_command = command;
+    }
+
+    /// Give up waiting for a replugged port after `attempts` looks; `None` waits forever.
+    pub fn set_max_reconnect_attempts(&mut self, attempts: Option<u32>) {
+        self.max_reconnect_attempts = attempts;
+    }
+
+    pub fn set_events(&mut self, events: Option<EventLog>, progress_every: u8) {
+        self.events = events.map(Arc::new);
+        self.progress_every = progress_every;
+    }
+
+    /// Replaces the console observer, which prints the progress bar and the status lines.
+    pub fn set_observer(&mut self, observer: Box<dyn PushObserver>) {
+        self.observer.set(observer);
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.output.set_verbosity(verbosity);
+    }
+
+    pub fn set_color(&mut self, color: ColorChoice) {
+        self.output.set_color(color);
+    }
+
+    pub fn set_progress(&mut self, progress: ProgressChoice) {
+        self.output.set_progress(progress);
+    }
+
+    pub fn set_redraw(&mut self, every: Duration) {
+        self.output.set_redraw(every);
+    }
+
+    /// Pulses the configured reset line, returns false when the user has to power the target by hand.
+    fn reset_target(&mut self, serial: &mut SerialPort) -> bool {
+        let reset = match self.reset {
+            Some(reset) => reset,
+            None => return false,
+        };
+
+        self.output.status(format!("{} Resetting the target via {}", self.output.icon(Icon::Reset), reset.line));
+        self.output.verbose(format!("holding {} for {:?}{}", reset.line, reset.hold,
+                                    if reset.active_low { ", active-low" } else { "" }));
+        match reset.pulse(serial.as_mut()) {
+            Ok(()) => true,
+            Err(e) => {
+                self.output.warn(format!("Could not toggle {}: {}", reset.line, e));
+                false
+            }
+        }
+    }
+
+    fn wait_for_binary_request(&mut self, serial: &mut SerialPort, machine: &mut Chainboot, reset: bool) -> Result<()> {
+        if !reset {
+            self.output.status(format!("{} Please power the target now", self.output.icon(Icon::Power)));
+        }
+        let out = self.output.clone();
+        // boot output is shown and recorded like the terminal's, before the terminal exists
+        let (mut display, mut taps) = self.session_taps();
+
+        let request = self.binary_request.to_string();
+        out.trace(format!("waiting for {}", request));
+        let limit = self.handshake_timeout;
+        let baud = serial.baud_rate().unwrap_or(SERIAL_BAUD);
+        let (read_size, batch_size) = (self.terminal_options.read_size(baud), self.terminal_options.batch_size(baud));
+        let spinner_every = SPINNER_EVERY.max(out.redraw());
+
+        let mut f = move |flag: Arc<AtomicBool>| -> Result<()> {
+            let started = Instant::now();
+            // without a time limit, a spinner until the target says something
+            let mut spinning = limit.is_none();
+            let mut spun = started - spinner_every;
+            let waited = protocol::wait_for_request(serial, machine, batch_size, read_size, &flag, |received, shown, requested| {
+                if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+                if !received.is_empty() { taps.iter_mut().for_each(|tap| tap.rx(received)); }
+                if spinning && (requested || !shown.is_empty()) {
+                    out.clear_transient();
+                    spinning = false;
+                }
+                display.show(shown);
+                if spinning && spun.elapsed() >= spinner_every {
+                    spun = Instant::now();
+                    let frame = SPINNER[(started.elapsed().as_millis() / spinner_every.as_millis()) as usize % SPINNER.len()];
+                    out.transient(format!("{} Waiting for the loader {} {:.0}s, Ctrl-C quits", out.icon(Icon::Wait), frame,
+                                          started.elapsed().as_secs_f64()));
+                }
+                Ok(())
+            });
+            display.finish();
+            match waited {
+                Ok(requested) => {
+                    if requested { out.trace(format!("rx {}", request)); }
+                    Ok(())
                 }
-                n = serial.read_serial(&mut received).map_err(|_| ErrorKind::ConnectionError)?;
+                Err(_) if signal::interrupted() => {
+                    out.clear_transient();
+                    Err(ErrorKind::Interrupted)
+                }
+                Err(_) => Err(ErrorKind::ConnectionError),
             }
-            Ok(())
         };
 
-        timeout(f, 10)
+        match limit {
+            Some(limit) => timeout(f, limit.as_secs())?,
+            // the board may simply be off: wait, rather than have run() retry
+            None => signal::catch_interrupts(|| f(Arc::new(AtomicBool::new(true))))?,
+        }
+        self.emit(Event::HandshakeOk);
+        self.notify(&mut |observer| observer.handshake_complete());
+        Ok(())
     }
 
-    fn load_binary(&mut self) -> Result<(File, u64)> {
-        let file = std::fs::File::open(&self.binary_image_path)?;
-        let binary_size = file.metadata()?.len();
+    fn negotiate_version(&mut self, serial: &mut SerialPort) -> Result<()> {
+        self.output.trace("tx version probe");
+        self.loader = protocol::negotiate(serial, protocol::NEGOTIATE_TIMEOUT)?;
+        let loader = match self.loader {
+            Some(loader) => loader,
+            None => {
+                self.output.verbose("The loader did not answer the version probe, using the classic exchange");
+                return Ok(());
+            }
+        };
+        self.output.verbose(format!("Loader protocol v{}, {:?}", loader.version, loader.capabilities));
 
-        Ok((file, binary_size))
+        let wanted = [
+            (self.size_header == SizeHeader::Extended, Capabilities::EXTENDED_SIZE, "--extended-size"),
+            (self.resume, Capabilities::RESUME, "--resume"),
+            (self.delta.is_some(), Capabilities::DELTA, "--delta"),
+            (self.push_protocol == PushProtocol::Block, Capabilities::BLOCK, "--protocol block"),
+        ];
+        for (wanted, capability, flag) in wanted.iter() {
+            if *wanted && !loader.capabilities.contains(*capability) {
+                self.output.warn(format!("The loader does not announce support for {}", flag));
+            }
+        }
+        Ok(())
     }
-    fn send_size(&mut self, binary_size: u64) -> Result<()> {
-        let serial = self.target_serial().ok_or(ErrorKind::NoneError("serial"))?;
-        // pi just read 4 byt, and u64 will convert to [u8;8]
-        serial.write_all(&(binary_size as u32).to_le_bytes())?;
 
-        let mut received = [0; 2];
-        serial.read_serial_exact(&mut received).map_err(|_| ErrorKind::ProtocolError)?;
-        if received != "OK".as_bytes() { Err(ErrorKind::ProtocolError) } else { Ok(()) }
+    /// `--auto-baud`: proposes each rate the adapter can make, fastest first, until one is taken
+    /// and checks out, saying every switch.
+    fn step_up_baud(&mut self, serial: &mut SerialPort) -> Result<()> {
+        self.line_rate = None;
+        if transport::Target::parse(&self.target_serial_name).fixed_settings() {
+            self.output.warn("--auto-baud: the terminal server sets the line's rate, staying at it");
+            return Ok(());
+        }
+        if !self.loader.is_some_and(|loader| loader.capabilities.contains(Capabilities::BAUD)) {
+            self.output.warn(format!("The loader does not announce support for --auto-baud, staying at {} baud", self.serial_settings.baud_rate));
+            return Ok(());
+        }
+        let rates: Vec<u32> = self.auto_baud.iter().copied().filter(|&rate| match self.check_baud(rate) {
+            Ok(()) => true,
+            Err(e) => {
+                self.output.verbose(format!("--auto-baud: skipping {}", e));
+                false
+            }
+        }).collect();
+        let mut ladder = protocol::BaudLadder::new(serial.baud_rate()?, &rates);
+        let rate = protocol::step_baud(serial.as_mut(), &mut ladder, protocol::BAUD_REVERT, |switch| self.log_baud(switch))?;
+        if rate != self.serial_settings.baud_rate {
+            self.line_rate = Some(rate);
+        } else {
+            self.output.warn(format!("No faster rate checked out, the image goes at {} baud", rate));
+        }
+        Ok(())
     }
 
+    fn log_baud(&self, switch: protocol::BaudSwitch) {
+        match switch {
+            protocol::BaudSwitch::Declined { rate } => self.output.verbose(format!("The loader can't do {} baud", rate)),
+            protocol::BaudSwitch::Switched { from, to } => {
+                self.output.verbose(format!("Switched from {} to {} baud, checking the line", from, to));
+                self.emit(Event::BaudSwitched { from, to });
+            }
+            protocol::BaudSwitch::Confirmed { rate } => self.output.status(format!("{} Line at {} baud", self.output.icon(Icon::Ok), rate)),
+            protocol::BaudSwitch::Reverted { from, to } => {
+                self.output.warn(format!("The check came back garbled at {} baud, back to {}", from, to));
+                self.emit(Event::BaudSwitched { from, to });
+            }
+        }
+    }
 
-    fn send_binary(&mut self, (mut binary_image, binary_size): (File, u64)) -> Result<()> {
-        let name_short = self.name_short();
-        let mut pb = create_pb(name_short, binary_size);
+    /// Puts the line back at `--baud` after `--auto-baud` stepped it up, for the terminal and
+    /// whatever the target prints once it boots.
+    fn restore_baud(&mut self, serial: &mut SerialPort) -> Result<()> {
+        let from = match self.line_rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        let to = self.serial_settings.baud_rate;
+        serial.flush()?;
+        serial.set_baud_rate(to)?;
+        self.output.verbose(format!("Back from {} to {} baud", from, to));
+        self.emit(Event::BaudSwitched { from, to });
+        Ok(())
+    }
+
+    /// Asks the loader who it is and says, holding back the image from any other than
+    /// `--expect-target` names.
+    fn identify_target(&mut self, serial: &mut SerialPort) -> Result<()> {
+        self.output.trace("tx identity probe");
+        self.target = identity::identify(serial, identity::IDENTIFY_TIMEOUT)?;
+        match &self.target {
+            Some(target) => self.output.status(format!("{} Target {}", self.output.icon(Icon::Ok), target)),
+            None => self.output.verbose("The loader did not answer the identity probe"),
+        }
+        match &self.expect_target {
+            Some(expected) => identity::check(self.target.as_ref(), expected),
+            None => Ok(()),
+        }
+    }
+
+    fn send_cmdline(&mut self, serial: &mut SerialPort) -> Result<()> {
+        if self.cmdline.is_empty() { return Ok(()); }
+        if !self.loader.is_some_and(|loader| loader.capabilities.contains(Capabilities::CMDLINE)) {
+            self.output.warn("The loader does not take a command line, not sending it; see --negotiate and --protocol-version");
+            return Ok(());
+        }
+        self.output.trace(format!("tx cmdline {:?}", self.cmdline));
+        protocol::send_cmdline(serial, &self.cmdline)?;
+        self.output.verbose(format!("Sent the command line, {}", format_bytes(self.cmdline.len() as u64)));
+        Ok(())
+    }
 
-        let serial = self.target_serial().ok_or(ErrorKind::NoneError("serial"))?;
+    fn load_binary(&mut self) -> Result<Image> {
+        self.resolve_image()?;
+        if self.spooled.is_none() { image::check(self.image_path())?; }
+        let image = self.open_image()?;
+        image::check_size(image.size, self.max_image_size)?;
+        Ok(image)
+    }
 
-        let mut progress = 0;
+    fn open_image(&mut self) -> Result<Image> {
+        let (image, name) = match &self.spooled {
+            Some(data) => (Image::from_data(data.clone(), self.fill)?, "stdin"),
+            None => (Image::load(self.image_path(), self.fill)?, self.binary_image_path.as_str()),
+        };
+        self.output.verbose(format!("{} is a {:?} image of {}", name, image.format, format_bytes(image.size)));
+        Ok(image)
+    }
 
-        while progress < pb.total {
-            let mut chunk = Vec::with_capacity(512);
-            let n = std::io::Read::by_ref(&mut binary_image).take(512).read_to_end(&mut chunk)?;
-            serial.write_all(&chunk[..n])?;
-            progress = pb.add(n as u64);
+    /// The file the image is read from, unless it came from stdin.
+    fn image_path(&self) -> &Path {
+        self.image_file.as_deref().unwrap_or_else(|| Path::new(&self.binary_image_path))
+    }
+
+    /// Picks the newest file a wildcard image names, afresh for each push so the latest build
+    /// is the one that goes, and says which when that is another than before.
+    fn resolve_image(&mut self) -> Result<()> {
+        if self.spooled.is_some() || !image::is_glob(&self.binary_image_path) { return Ok(()); }
+        let newest = image::newest_match(&self.binary_image_path)?;
+        if self.image_file.as_ref() == Some(&newest) {
+            self.output.verbose(format!("{} is still the newest match of {}", newest.display(), self.binary_image_path));
+        } else {
+            self.output.status(format!("{} Image {}, the newest match of {}", self.output.icon(Icon::Push), newest.display(), self.binary_image_path));
         }
-        pb.finish();
-        println!("[{}] send finish!", self.name_short());
+        self.image_file = Some(newest);
         Ok(())
     }
+
+    /// Whether the image file is there to push again, waiting a little for one a build is
+    /// still writing; if not, says so, and the running image stays.
+    fn image_ready(&mut self) -> bool {
+        if self.spooled.is_some() { return true; }
+        match self.resolve_image().and_then(|()| image::check_settled(self.image_path(), image::SETTLE_WAIT)) {
+            Ok(_) => true,
+            Err(e) => {
+                self.output.error(format!("{} {}, back to the running image", self.output.icon(Icon::Fail), e));
+                false
+            }
+        }
+    }
+
+    /// Fetches the image URL before anything else, so a failed download never waits for the target.
+    #[cfg(feature = "http")]
+    fn download(&mut self) -> Result<TempImage> {
+        let sha256 = self.expect_sha256;
+        let url: Url = self.binary_image_path.parse().map_err(ErrorKind::NetworkError)?;
+        let out = self.output.clone();
+        out.status(format!("{} Downloading {}", out.icon(Icon::Network), url));
+        let mut pb = None;
+        let fetched = fetch::fetch(&url, sha256, |received, total| match total {
+            Some(total) => {
+                if pb.is_none() { pb = out.progress_bar(Icon::Network, "Downloading", total); }
+                if let Some(pb) = pb.as_mut() { pb.set(received); }
+            }
+            // no Content-Length: a running count instead of a bar
+            None => out.transient(format!("{} Downloading {}", out.icon(Icon::Network), format_bytes(received))),
+        });
+        out.finish_progress(pb);
+        out.clear_transient();
+        let image = fetched?;
+        out.verbose(format!("Downloaded {} to {}{}", format_bytes(image.size), image.path().display(),
+                            if sha256.is_some() { ", SHA-256 matches" } else { "" }));
+        self.image_file = Some(image.path().to_path_buf());
+        Ok(image)
+    }
+
+    /// Announces the size and, with `--resume`, offers to continue where the last attempt got
+    /// to. Returns where the image starts: 0 unless the loader agreed to resume.
+    fn announce(&mut self, serial: &mut SerialPort, machine: &mut Chainboot, binary_size: u64) -> Result<(u64, Vec<Action>)> {
+        let size = protocol::encode_size(binary_size, self.size_header)?;
+        self.output.trace(format!("tx size {} as {:02x?}", binary_size, size));
+        let offer = self.resume.then(|| self.pushed.resume_offset(binary_size));
+
+        let mut actions = machine.handle(Input::Announce { size: binary_size, resume: offer }, Instant::now());
+        let mut offset = 0;
+        loop {
+            let mut next = Vec::new();
+            for action in protocol::pump(serial, machine, actions)? {
+                match action {
+                    Action::SizeAccepted => {
+                        self.output.trace("rx OK");
+                        self.emit(Event::SizeSent { bytes: binary_size });
+                        self.notify(&mut |observer| observer.size_sent(binary_size));
+                        if let Some(offer) = offer { self.output.trace(format!("tx resume at {}", offer)); }
+                    }
+                    Action::ResumeAnswered { offset: start } => {
+                        offset = start;
+                        if offset > 0 {
+                            self.emit(Event::PushResumed { offset, total: binary_size });
+                            self.notify(&mut |observer| observer.resumed(offset, binary_size));
+                        } else if offer.is_some_and(|offer| offer > 0) {
+                            self.output.warn("The loader declined to resume, sending the whole image");
+                        }
+                        self.pushed = PushState { size: binary_size, acknowledged: offset };
+                    }
+                    // the first chunk, or the end of an empty image
+                    action => next.push(action),
+                }
+            }
+            if !next.is_empty() { return Ok((offset, next)); }
+            actions = Vec::new();
+        }
+    }
+
+    fn send_binary(&mut self, serial: &mut SerialPort, machine: &mut Chainboot, mut image: Image, offset: u64, mut actions: Vec<Action>) -> Result<()> {
+        let total = image.size;
+        // hashed on the way out, what a resumed push skips included
+        let mut hasher = Sha256::default();
+        image.source.seek(SeekFrom::Start(0))?;
+        io::copy(&mut Read::by_ref(&mut image.source).take(offset), &mut hasher)?;
+        let mut limiter = self.terminal_options.limit.map(RateLimiter::new);
+        if let Some(limiter) = &limiter { self.output.verbose(format!("pacing to {}/s", format_bytes(limiter.rate()))); }
+        // what the loader prints meanwhile goes where the boot output goes, around the bar
+        let (mut display, mut taps) = self.session_taps();
+        let mut early = EarlyBuffer::new(self.early_output);
+        let observer = self.observer.get(&self.output);
+        observer.lock().unwrap().progress(offset, total);
+        let (events, step, out, resume) = (self.events.as_deref(), self.progress_every as u64, &self.output, self.resume);
+        let mut blocks = match self.push_protocol {
+            PushProtocol::Stream => None,
+            PushProtocol::Block => Some(BlockSender::default()),
+        };
+        let (name_short, pushed, stats, wire) = (self.name_short.as_str(), &mut self.pushed, &self.stats, self.wire.as_deref());
+
+        let started = Instant::now();
+        let mut progress = offset;
+        let mut reported = (offset * 100).checked_div(total).unwrap_or(0) / step * step;
+
+        // read on a thread a few chunks ahead, up to the size announced whatever the file does meanwhile
+        let mut reader = ReadAhead::spawn(image.source.take(total - offset), self.chunk_size, image::READ_AHEAD);
+        'push: loop {
+            let (mut written, mut last) = (false, false);
+            for action in protocol::pump(serial, machine, actions)? {
+                match action {
+                    Action::SendChunk { offset: at, len } => {
+                        let chunk = reader.next_chunk()?;
+                        if chunk.len() != len { return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the image got shorter").into()); }
+                        hasher.update(&chunk);
+                        if out.enabled(Verbosity::Trace) { out.trace(format!("chunk {}..{}", at, at + len as u64)); }
+                        if let Some(limiter) = limiter.as_mut() { limiter.take(len as u64); }
+                        if let Some(wire) = wire { wire.note(Direction::Tx, &chunk); }
+                        match blocks.as_mut() {
+                            Some(blocks) => blocks.send(serial, &chunk)?,
+                            None => serial.write_serial_all(&chunk, WRITE_TIMEOUT)?,
+                        }
+                        written = true;
+                        last = at + len as u64 == total;
+                    }
+                    Action::Show(shown) => {
+                        let now = early.push(&shown, Instant::now());
+                        if !now.is_empty() {
+                            out.clear_progress();
+                            display.show(&now);
+                        }
+                    }
+                    Action::Rebooted { at } => {
+                        out.clear_progress();
+                        display.show(&early.finish());
+                        display.finish();
+                        return Err(ErrorKind::TargetRebooted { at });
+                    }
+                    // written, or acknowledged with --resume, a block's retransmissions counted once
+                    Action::Progress { sent, .. } => {
+                        if blocks.is_some() || resume { pushed.acknowledged = sent; }
+                        stats.add_sent(sent - progress);
+                        progress = sent;
+                        observer.lock().unwrap().progress(progress, total);
+
+                        let percent = progress * 100 / total;
+                        if let Some(log) = events.filter(|_| percent >= reported + step || progress == total) {
+                            reported = percent - percent % step;
+                            log.emit(name_short, &Event::PushProgress { sent: progress, total, percent: percent as u8 });
+                        }
+                    }
+                    Action::Complete => break 'push,
+                    _ => {}
+                }
+            }
+            actions = Vec::new();
+            if written {
+                // a block's acks are the sender's to read, and what follows the end is the boot's
+                if blocks.is_none() && !last {
+                    let heard = protocol::heard(serial.as_mut())?;
+                    if !heard.is_empty() { taps.iter_mut().for_each(|tap| tap.rx(&heard)); }
+                    actions = machine.handle(Input::Received(&heard), Instant::now());
+                }
+                actions.extend(machine.handle(Input::Written, Instant::now()));
+            }
+        }
+        let sha256 = hasher.finish();
+        let checked = self.check_sha256(sha256);
+        if checked.is_ok() {
+            self.finish_push(PushReport {
+                bytes: total - offset,
+                total,
+                seconds: started.elapsed().as_secs_f64(),
+                retransmitted: blocks.map_or(0, |blocks| blocks.retransmitted),
+                sha256,
+                loader: self.loader,
+                target: self.target.clone(),
+            });
+        }
+        display.show(&early.finish());
+        display.finish();
+        checked
+    }
+
+    /// Holds what went out against `--sha256`.
+    fn check_sha256(&self, actual: [u8; 32]) -> Result<()> {
+        match self.expect_sha256 {
+            Some(expected) if expected != actual => Err(ErrorKind::ImageMismatch { expected, actual }),
+            Some(_) => {
+                self.output.verbose(format!("SHA-256 {} as expected", sha256::hex(&actual)));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn finish_push(&mut self, report: PushReport) {
+        self.pushed = PushState::default();
+        self.stats.add_push(report.bytes, Duration::from_secs_f64(report.seconds));
+        self.emit(Event::PushComplete { bytes: report.bytes, seconds: report.seconds, sha256: sha256::hex(&report.sha256) });
+        self.notify(&mut |observer| observer.finished(&report));
+        self.last_push = Some(report);
+    }
+
+    /// With `--delta` and a loader that takes it, the manifest of `image`, read ahead of the push.
+    fn delta_manifest(&mut self, image: &mut Image) -> Result<Option<Manifest>> {
+        let supported = self.loader.is_some_and(|loader| loader.capabilities.contains(Capabilities::DELTA));
+        if self.delta.is_none() || !supported { return Ok(None); }
+        image.source.seek(SeekFrom::Start(0))?;
+        let manifest = Manifest::read(&mut image.source, delta::DELTA_BLOCK)?;
+        image.source.seek(SeekFrom::Start(0))?;
+        Ok(Some(manifest))
+    }
+
+    /// What the last push to the target is remembered under: the serial number of its USB
+    /// adapter, which stays when it is plugged in elsewhere, or else its name.
+    fn delta_key(&self) -> String {
+        transport::usb_serial_number(&self.target_serial_name).unwrap_or_else(|| self.target_serial_name.clone())
+    }
+
+    /// Offers the loader to patch the image it kept from the last push. Returns whether it took
+    /// the offer: then only the changed blocks of `image` went out and the loader checked the
+    /// whole, otherwise the whole image is still to be sent.
+    fn send_delta(&mut self, serial: &mut SerialPort, image: &mut Image, new: &Manifest) -> Result<bool> {
+        let (cache, key) = (self.delta.clone().unwrap(), self.delta_key());
+        let old = cache.load(&key).unwrap_or_default();
+        self.output.trace(format!("tx delta offer against {} with CRC {:08x}", format_bytes(old.size), old.crc));
+        if !delta::offer(serial, &old)? {
+            self.output.verbose("The loader does not hold the image pushed last, sending the whole image");
+            return Ok(false);
+        }
+        let runs = delta::changed(&old, new);
+        let changed: u64 = runs.iter().map(|&(_, len)| len as u64).sum();
+        self.emit(Event::PushDelta { changed, total: new.size });
+        self.notify(&mut |observer| observer.delta(changed, new.size));
+
+        let started = Instant::now();
+        let mut limiter = self.terminal_options.limit.map(RateLimiter::new);
+        let observer = self.observer.get(&self.output);
+        observer.lock().unwrap().progress(0, changed);
+        let (mut sent, mut chunk) = (0, vec![0; self.chunk_size]);
+        for (offset, len) in runs {
+            if self.output.enabled(Verbosity::Trace) { self.output.trace(format!("record {}..{}", offset, offset + len as u64)); }
+            serial.write_serial_all(&delta::encode_record(offset, len), WRITE_TIMEOUT)?;
+            image.source.seek(SeekFrom::Start(offset))?;
+            let mut left = len;
+            while left > 0 {
+                let part = &mut chunk[..left.min(self.chunk_size)];
+                image.source.read_exact(part)?;
+                if let Some(limiter) = limiter.as_mut() { limiter.take(part.len() as u64); }
+                serial.write_serial_all(part, WRITE_TIMEOUT)?;
+                left -= part.len();
+                sent += part.len() as u64;
+                self.stats.add_sent(part.len() as u64);
+                observer.lock().unwrap().progress(sent, changed);
+            }
+        }
+        if let Err(e) = delta::finish(serial, new) {
+            // whatever the loader holds now, the next push sends all of it
+            cache.forget(&key);
+            if let ErrorKind::ProtocolError = e { self.output.warn("The image did not check out after the delta, sending the whole image next"); }
+            return Err(e);
+        }
+        // the manifest's digest, of the whole image the loader now holds
+        self.check_sha256(new.sha256)?;
+        self.finish_push(PushReport {
+            bytes: sent,
+            total: new.size,
+            seconds: started.elapsed().as_secs_f64(),
+            retransmitted: 0,
+            sha256: new.sha256,
+            loader: self.loader,
+            target: self.target.clone(),
+        });
+        Ok(true)
+    }
+
+    /// Keeps the manifest of what the target now holds, for the next `--delta` push.
+    fn remember(&self, manifest: &Manifest) {
+        let cache = match &self.delta {
+            Some(cache) => cache,
+            None => return,
+        };
+        if let Err(e) = cache.store(&self.delta_key(), manifest) {
+            self.output.warn(format!("Not remembering the image for --delta: {}", e));
+        }
+    }
 }
 
 
@@ -96,41 +1092,738 @@ impl SerialTool for MiniPush {
         &self.name_short
     }
 
-    fn target_serial(&mut self) -> Option<&mut SerialPort> {
-        self.target_serial.as_mut()
+    fn serial_settings(&self) -> SerialSettings {
+        self.serial_settings
+    }
+
+    fn terminal_options(&self) -> TerminalOptions {
+        self.terminal_options.clone()
+    }
+
+    fn force_lock(&self) -> bool {
+        self.force_lock
+    }
+
+    fn force_baud(&self) -> bool {
+        self.force_baud
+    }
+
+    fn known_device(&self) -> Option<UsbIdentity> {
+        self.known_device.clone()
+    }
+
+    fn set_known_device(&mut self, device: Option<UsbIdentity>) {
+        self.known_device = device;
     }
 
-    fn set_target_serial(&mut self, serialport: SerialPort) {
-        self.target_serial = Some(serialport);
+    fn allow_device_change(&self) -> bool {
+        self.allow_device_change
     }
 
-    fn handle_reconnect(&mut self) {
+    fn single_attempt(&self) -> bool {
+        self.single_attempt
+    }
+
+    fn sync_on_connect(&self) -> Option<SyncAction> {
+        self.sync_on_connect
+    }
+
+    fn highlighter(&self) -> Highlighter {
+        self.highlighter.clone()
+    }
+
+    fn triggers(&self) -> Triggers {
+        self.triggers.clone()
+    }
+
+    /// The prefix and `p` push the image again, like the `push` command.
+    fn commands(&self) -> CommandTable {
+        CommandTable::default().bind(b'p', Command::Local("push"))
+    }
+
+    /// `push` pushes the image again, read anew, e.g. the next build or from a script over
+    /// --control. With --reset, `reset` pulses the line again, e.g. to have the loader ask once more.
+    fn local_commands(&self) -> LocalCommands {
+        let repush = self.repush.clone();
+        let commands = LocalCommands::default().register("push", "push", Box::new(move |ctx, _| {
+            repush.store(true, Ordering::Relaxed);
+            ctx.say("pushing the image again".to_string());
+            ctx.quit();
+            Ok(())
+        }));
+        let reset = match self.reset {
+            Some(reset) => reset,
+            None => return commands,
+        };
+        commands.register("reset", "reset", Box::new(move |ctx, _| {
+            reset.pulse(ctx.port.as_mut()).map_err(|e| format!("could not toggle {}: {}", reset.line, e))?;
+            ctx.say(format!("{} Reset the target via {}", ctx.out.icon(Icon::Reset), reset.line));
+            Ok(())
+        }))
+    }
+
+    fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.clone()
+    }
+
+    fn wire(&self) -> Option<Arc<WireLog>> {
+        self.wire.clone()
+    }
+
+    fn session_log(&self) -> Option<Arc<SessionLog>> {
+        self.session_log.clone()
+    }
+
+    fn scrollback(&self) -> Option<Arc<Scrollback>> {
+        self.scrollback.clone()
+    }
+
+    fn tx_log(&self) -> Option<Arc<TxLog>> {
+        self.tx_log.clone()
+    }
+
+    fn control(&self) -> Option<Arc<ControlSocket>> {
+        self.control.clone()
+    }
+
+    fn stats(&self) -> Option<Arc<SessionStats>> {
+        Some(self.stats.clone())
+    }
+
+    fn events(&self) -> Option<&EventLog> {
+        self.events.as_deref()
+    }
+
+    fn observer(&self) -> Option<&Mutex<Box<dyn PushObserver>>> {
+        Some(self.observer.get(&self.output))
+    }
+
+    fn output(&self) -> &Output {
+        &self.output
+    }
+
+    fn show_output(&self) -> bool {
+        self.show_output
+    }
+
+    fn phase(&self) -> &str {
+        self.phase
+    }
+
+    fn handle_reconnect(&mut self, error: &ErrorKind) -> Result<()> {
         self.connection_reset();
-        println!("\n[{}] ⚡ {} {}",
-                 self.name_short(),
-                 "Connection or protocol Error: ".red(),
-                 "Remove power and USB serial. Reinsert serial first, then power".red());
+        self.output.blank(Verbosity::Quiet);
+        self.output.error(format!("{} Connection or protocol Error ({}): Remove power and USB serial. Reinsert serial first, then power",
+                                  self.output.icon(Icon::Error), error));
 
-        while !self.serial_connected() { sleep(1) }
+        let watcher = PortWatcher::spawn(vec![self.target_serial_name.clone()]);
+        let started = Instant::now();
+        let mut reported = started;
+        let mut backoff = Backoff::new(RECONNECT_BACKOFF, RECONNECT_BACKOFF_CAP);
+        let mut present = None;
+        signal::catch_interrupts(|| loop {
+            let deadline = Instant::now() + backoff.next_delay();
+            // in steps, so Ctrl-C doesn't sit out a long backoff; the first look counts however long it takes
+            while present.is_none() || Instant::now() < deadline {
+                if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+                let step = deadline.saturating_duration_since(Instant::now()).clamp(Duration::from_millis(1), Duration::from_millis(100));
+                match watcher.recv_timeout(step) {
+                    Some(PortEvent::Changed { presence, .. }) => present = Some(matches!(presence, Presence::Present(_))),
+                    Some(PortEvent::Failed { .. }) => present = Some(false),
+                    None => {}
+                }
+            }
+            if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+            if present == Some(true) { return Ok(()); }
+
+            if reported.elapsed() >= RECONNECT_STATUS_EVERY {
+                reported = Instant::now();
+                self.output.status(format!("{} Still waiting for {} ({:.0}s, {} attempts)", self.output.icon(Icon::Wait),
+                                           self.target_serial_name, started.elapsed().as_secs_f64(), backoff.attempts()));
+            }
+            if self.max_reconnect_attempts.is_some_and(|max| backoff.attempts() >= max) {
+                self.output.error(format!("{} {} did not come back after {} attempts ({:.0}s), giving up", self.output.icon(Icon::Fail),
+                                          self.target_serial_name, backoff.attempts(), started.elapsed().as_secs_f64()));
+                return Err(ErrorKind::ConnectionError);
+            }
+        })
     }
 
     fn exec(&mut self) -> Result<()> {
-        self.open_serial();
-        self.wait_for_binary_request()?;
+        // run again after each reconnect, which makes it the next attempt
+        self.timings.begin_attempt();
+        self.phase = "open";
+        let mut connection = self.timed("wait-serial", Self::open_serial)?;
+        let serial = &mut connection.port;
+        self.push(serial)?;
+        if let Some(boot_output) = self.no_terminal {
+            self.phase = "boot";
+            // a CI run waiting for its marker waits as long as it takes, or --max-session-time
+            let until = if self.triggers.exits() { None } else { Some(boot_output) };
+            return self.watch(serial, until);
+        }
+        if self.watch_paths.is_some() { return self.watch_image(serial); }
+        self.phase = "terminal";
+        while self.terminal_session(serial, None)? == ExitReason::QuitCommand && self.repush.swap(false, Ordering::Relaxed) {
+            self.output.blank(Verbosity::Quiet);
+            if !self.image_ready() { continue; }
+            self.timings.begin_attempt();
+            self.push(serial)?;
+            self.phase = "terminal";
+        }
+        Ok(())
+    }
+}
 
-        let result = self.load_binary()?;
-        self.send_size(result.1)?;
-        self.send_binary(result)?;
-        self.terminal()
+impl MiniPush {
+    /// Resets the target, or has it powered, and pushes the image once it asks.
+    fn push(&mut self, serial: &mut SerialPort) -> Result<()> {
+        // an image that can't be pushed is told before the target is reset or powered
+        self.phase = "load";
+        let mut loaded = Some(self.load_binary()?);
+        let reset = self.reset_target(serial);
+        self.phase = "handshake";
+        // a fresh one each time, so a partial request from before a reconnect doesn't count
+        let acks = self.resume && self.push_protocol == PushProtocol::Stream;
+        let mut machine = Chainboot::new(RequestMatcher::new(self.binary_request.pattern().clone()), self.size_header, self.chunk_size, acks);
+        self.timed("trigger", |tool| tool.wait_for_binary_request(serial, &mut machine, reset))?;
+        self.loader = self.protocol_version.map(LoaderInfo::assumed);
+        if self.identify || self.expect_target.is_some() {
+            self.phase = "identify";
+            self.timed("handshake", |tool| tool.identify_target(serial))?;
+        }
+        if self.negotiate {
+            self.phase = "negotiate";
+            self.timed("handshake", |tool| tool.negotiate_version(serial))?;
+        }
+        if !self.auto_baud.is_empty() {
+            self.phase = "baud";
+            self.timed("handshake", |tool| tool.step_up_baud(serial))?;
+        }
+
+        let mut restarts = 0;
+        let delta = loop {
+            self.phase = "load";
+            // read anew after a reboot, as the target would after a reset
+            let mut image = match loaded.take() {
+                Some(image) => image,
+                None => self.load_binary()?,
+            };
+            let delta = self.delta_manifest(&mut image)?;
+            self.phase = "size";
+            let (offset, actions) = self.timed("handshake", |tool| tool.announce(serial, &mut machine, image.size))?;
+            if !self.post_ack_delay.is_zero() { self.timed("settle", |tool| tool.settle(serial))?; }
+            self.phase = "push";
+            let pushed = self.timed("transfer", |tool| {
+                let patched = match &delta {
+                    Some(manifest) => tool.send_delta(serial, &mut image, manifest)?,
+                    None => false,
+                };
+                if !patched { tool.send_binary(serial, &mut machine, image, offset, actions)?; }
+                Ok(())
+            });
+            match pushed {
+                // the loader is back at its request: the size again, no unplugging needed
+                Err(e @ ErrorKind::TargetRebooted { .. }) if restarts < REBOOT_RESTARTS => {
+                    restarts += 1;
+                    self.pushed = PushState::default();
+                    self.output.blank(Verbosity::Quiet);
+                    self.output.warn(format!("{}, sending the size again", e));
+                    self.timings.begin_attempt();
+                }
+                pushed => break pushed.map(|_| delta)?,
+            }
+        };
+        if let Some(push) = &self.last_push { self.timings.record_bytes("transfer", push.bytes); }
+        if let Some(manifest) = &delta { self.remember(manifest); }
+        if let Some(attempt) = self.timings.last() { self.output.status(format!("{} {}", self.output.icon(Icon::Timer), attempt)); }
+        self.phase = "cmdline";
+        self.send_cmdline(serial)?;
+        self.restore_baud(serial)?;
+        self.check_boot(serial)?;
+        self.sync_time(serial)
+    }
+
+    /// Runs `step`, its time going to `phase` of the current attempt whether it gets through or not.
+    fn timed<T>(&mut self, phase: &'static str, step: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = step(self);
+        self.timings.record(phase, started.elapsed());
+        result
+    }
+
+    /// `--post-ack-delay`, counted from when the last of the size exchange has left the port
+    /// rather than from when it was handed to the driver.
+    fn settle(&mut self, serial: &mut SerialPort) -> Result<()> {
+        if self.post_ack_delay.is_zero() { return Ok(()); }
+        serial.flush()?;
+        self.output.verbose(format!("Waiting {} ms after the size was acknowledged", self.post_ack_delay.as_millis()));
+        thread::sleep(self.post_ack_delay);
+        Ok(())
+    }
+
+    /// Waits for the `--expect-boot` pattern, showing the boot output meanwhile.
+    fn check_boot(&mut self, serial: &mut SerialPort) -> Result<()> {
+        let (pattern, deadline) = match self.boot_marker.clone() {
+            Some(marker) => marker,
+            None => return Ok(()),
+        };
+        self.phase = "boot";
+        self.output.verbose(format!("Waiting up to {}s for {}", deadline.as_secs(), pattern));
+        let transcript = self.expect(serial, &pattern, deadline)?;
+        match transcript.offset() {
+            Some(offset) => {
+                self.output.status(format!("{} Booted, {} after {:.1}s and {} of output", self.output.icon(Icon::Ok), pattern,
+                                           transcript.elapsed.as_secs_f64(), format_bytes(offset as u64)));
+                Ok(())
+            }
+            None => Err(ErrorKind::ExpectTimeout { pattern: pattern.to_string(), waited: deadline }),
+        }
+    }
+
+    /// `--sync-time`, which is a convenience: a target that doesn't get ready only gets a
+    /// warning, the push went fine. A port that is gone is still an error.
+    fn sync_time(&mut self, serial: &mut SerialPort) -> Result<()> {
+        let (format, ready) = match self.time_sync.clone() {
+            Some(sync) => sync,
+            None => return Ok(()),
+        };
+        self.phase = "time";
+        if let Some((pattern, deadline)) = ready {
+            match self.expect(serial, &pattern, deadline) {
+                Ok(transcript) if transcript.found.is_some() => {}
+                Ok(_) => {
+                    self.output.warn(format!("time not sent, {} didn't show up within {}s", pattern, deadline.as_secs()));
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let now = SystemTime::now();
+        match serial.write_serial_all(&format.encode(now), WRITE_TIMEOUT) {
+            Ok(()) => {
+                let unix = now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+                self.output.verbose(format!("Sent the time, {}", Iso8601(unix)));
+                Ok(())
+            }
+            Err(e @ ErrorKind::ConnectionError) => Err(e),
+            Err(e) => {
+                self.output.warn(format!("time not sent: {}", e));
+                Ok(())
+            }
+        }
+    }
+
+    /// The terminal until a watched file changes, then the `--exec` build and, when it
+    /// succeeds with an image that differs from the one pushed, the next push. A failed build
+    /// goes back to the terminal of the image already running.
+    fn watch_image(&mut self, serial: &mut SerialPort) -> Result<()> {
+        // a wildcard image is watched for by its directory, where the next build appears
+        let watched = match Path::new(&self.binary_image_path).parent() {
+            Some(dir) if image::is_glob(&self.binary_image_path) => if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir.to_path_buf() },
+            _ => self.image_path().to_path_buf(),
+        };
+        let paths = self.watch_paths.clone().filter(|paths| !paths.is_empty()).unwrap_or_else(|| vec![watched]);
+        let watch = Watch::spawn(paths, watch::WATCH_EVERY);
+        let mut pushed = ImageStamp::of(self.image_path()).ok();
+        loop {
+            self.phase = "terminal";
+            match self.terminal_session(serial, Some(watch.changed()))? {
+                ExitReason::Stopped => {}
+                // `push` asks for the image as it is, changed or not
+                ExitReason::QuitCommand if self.repush.swap(false, Ordering::Relaxed) => {
+                    self.output.blank(Verbosity::Quiet);
+                    if !self.image_ready() { continue; }
+                    self.timings.begin_attempt();
+                    self.push(serial)?;
+                    pushed = ImageStamp::of(self.image_path()).ok();
+                    continue;
+                }
+                _ => return Ok(()),
+            }
+
+            let out = self.output.clone();
+            out.blank(Verbosity::Quiet);
+            let changes = watch.changes();
+            match changes.as_slice() {
+                [changed] => out.status(format!("{} {} changed", out.icon(Icon::Build), changed.display())),
+                changes => out.status(format!("{} {} files changed", out.icon(Icon::Build), changes.len())),
+            }
+            if let Some(command) = self.build_command.clone() {
+                self.phase = "build";
+                out.status(format!("{} Running {}", out.icon(Icon::Build), command));
+                let build = watch::build(&command);
+                // the build's own outputs are no change to act on
+                watch.rebase();
+                match build {
+                    Ok(Build::Succeeded) => {}
+                    Ok(Build::Failed(status)) => {
+                        out.error(format!("{} The build failed ({}), back to the running image", out.icon(Icon::Fail), status));
+                        continue;
+                    }
+                    Ok(Build::Cancelled) => {
+                        out.warn("build cancelled, back to the running image");
+                        continue;
+                    }
+                    Err(e) => {
+                        out.error(format!("{} Could not run {}: {}", out.icon(Icon::Fail), command, e));
+                        continue;
+                    }
+                }
+            } else {
+                watch.rebase();
+            }
+
+            if !self.image_ready() { continue; }
+            let image_path = self.image_path().to_path_buf();
+            match ImageStamp::of(&image_path) {
+                Ok(stamp) if Some(stamp) == pushed => {
+                    out.status(format!("{} {} is unchanged, not pushed", out.icon(Icon::Ok), image_path.display()));
+                    continue;
+                }
+                Ok(stamp) => pushed = Some(stamp),
+                Err(e) => {
+                    out.error(format!("{} {}: {}, back to the running image", out.icon(Icon::Fail), image_path.display(), e));
+                    continue;
+                }
+            }
+            self.push(serial)?;
+        }
+    }
+
+    /// A copy pushing to `device`, one of several boards: its messages are prefixed with the
+    /// board's name, progress goes in lines and its boot output isn't shown.
+    fn for_board(&self, device: &str) -> MiniPush {
+        let name = fleet::board_name(device);
+        let mut board = self.clone();
+        board.name_short = name.to_string();
+        board.target_serial_name = device.to_string();
+        board.output = self.output.renamed(name);
+        board.output.set_progress(ProgressChoice::Never);
+        board.show_output = false;
+        // what one session keeps or writes to is the board's own, starting out empty; the
+        // triggers and highlights of output that isn't shown are left out
+        let fresh = MiniPush::initialize(device.to_string(), self.binary_image_path.clone());
+        board.highlighter = fresh.highlighter;
+        board.triggers = fresh.triggers;
+        board.recorder = None;
+        board.wire = None;
+        board.session_log = None;
+        board.scrollback = None;
+        board.tx_log = None;
+        board.stats = fresh.stats;
+        board.control = None;
+        board.repush = fresh.repush;
+        board.line_rate = None;
+        board.loader = None;
+        board.target = None;
+        board.watch_paths = None;
+        board.build_command = None;
+        board.pushed = PushState::default();
+        board.last_push = None;
+        board.board_reports = Vec::new();
+        board.phase = fresh.phase;
+        board.known_device = None;
+        board
+    }
+
+    /// What `--report` says about the session that ended with `result`.
+    fn transfer_report(&self, result: &Result<()>) -> TransferReport {
+        // what went rather than the wildcard it was picked by
+        let image = match &self.image_file {
+            _ if self.spooled.is_some() => "-".to_string(),
+            Some(path) if image::is_glob(&self.binary_image_path) => path.display().to_string(),
+            _ => self.binary_image_path.clone(),
+        };
+        let error = result.as_ref().err().map(ToString::to_string);
+        let mut report = TransferReport::new(&self.target_serial_name, &image, self.last_push.clone(), error);
+        report.usb_serial = transport::usb_serial_number(&self.target_serial_name);
+        report.reconnects = self.stats.summary().reconnects;
+        let features = [
+            (self.push_protocol == PushProtocol::Block, "block"),
+            (self.size_header == SizeHeader::Extended, "extended_size"),
+            (self.resume, "resume"),
+            (self.delta.is_some() && self.loader.is_some_and(|loader| loader.capabilities.contains(Capabilities::DELTA)), "delta"),
+            (self.negotiate, "negotiate"),
+            (self.line_rate.is_some(), "auto_baud"),
+            (!self.cmdline.is_empty() && self.loader.is_some_and(|loader| loader.capabilities.contains(Capabilities::CMDLINE)), "cmdline"),
+        ];
+        report.features = features.iter().filter(|(used, _)| *used).map(|&(_, name)| name).collect();
+        report.timings = self.timings.report();
+        report
+    }
+
+    /// Writes `--report` to `path`: one report, or with several boards one for each.
+    fn write_report(&self, path: &Path, result: &Result<()>) -> io::Result<()> {
+        if self.board_reports.is_empty() { return report::write(&self.transfer_report(result), path); }
+        report::write(&self.board_reports, path)
+    }
+
+    /// Opens the board and pushes to it once. Nothing is retried: a board that fails is
+    /// reported along with the others.
+    fn push_board(&mut self) -> Result<()> {
+        self.phase = "open";
+        let pushed = self.timed("wait-serial", Self::open_serial).and_then(|mut connection| self.push(&mut connection.port));
+        pushed.map_err(|e| {
+            let e = e.context(self.phase, &self.target_serial_name);
+            self.notify(&mut |observer| observer.error(&e));
+            e
+        })
+    }
+
+    /// Pushes to every board at once and sums up how each went; with `attach`, then opens the
+    /// terminal on that board. Fails if any board did.
+    fn push_boards(&mut self, boards: &[String], attach: Option<&str>) -> Result<()> {
+        signal::install();
+        let out = self.output.clone();
+        out.status(format!("{} Pushing {} to {} boards", out.icon(Icon::Push), self.binary_image_path, boards.len()));
+        let (this, reports) = (&*self, Mutex::new(Vec::new()));
+        let results = fleet::push_all(boards, |device| {
+            let mut board = this.for_board(device);
+            let pushed = board.push_board();
+            reports.lock().unwrap().push(board.transfer_report(&pushed));
+            pushed
+        });
+        let mut reports = reports.into_inner().unwrap();
+        reports.sort_by_key(|report| boards.iter().position(|board| *board == report.device));
+        self.board_reports = reports;
+
+        out.blank(Verbosity::Quiet);
+        for line in fleet::summary(&results) { out.status(line); }
+        let failed = results.iter().filter(|result| !result.succeeded()).count();
+        match attach.and_then(|device| results.iter().find(|result| result.device == device)) {
+            Some(result) if result.succeeded() => {
+                self.target_serial_name = result.device.clone();
+                self.phase = "open";
+                let attached = self.open_serial().and_then(|mut connection| {
+                    self.phase = "terminal";
+                    self.terminal(&mut connection.port)
+                });
+                self.connection_reset();
+                attached?;
+            }
+            Some(result) => out.warn(format!("not attaching to {}, its push failed", result.device)),
+            None => {}
+        }
+        if failed > 0 { return Err(ErrorKind::BoardsFailed { failed, total: results.len() }); }
+        Ok(())
     }
 }
 
 fn main() {
-    let target_serial_name: String = std::env::args().nth(1).expect("expect arg [serial_name]");
-    let binary_image_path: String = std::env::args().nth(2).expect("expect arg [image_path]");
-
+    let args: Args = cli::parse();
+    let reset = args.reset.map(|line| ResetPulse {
+        line,
+        hold: Duration::from_millis(args.reset_hold),
+        active_low: args.reset_active_low,
+    });
 
-    println!("{}", "Minipush 1.0\n".cyan());
-    let mut mini_push = MiniPush::initialize(target_serial_name, binary_image_path);
-    mini_push.run();
+    let from_stdin = args.image_path == "-";
+    let from_url = image::is_url(&args.image_path);
+    let mut mini_push = MiniPush::initialize(args.serial_name, args.image_path);
+    if args.script {
+        // errors only, unless -v asks for more
+        mini_push.set_verbosity(match args.output.verbosity() { Verbosity::Normal => Verbosity::Quiet, verbosity => verbosity });
+        mini_push.set_color(ColorChoice::Never);
+        mini_push.set_progress(ProgressChoice::Never);
+    } else {
+        mini_push.set_verbosity(args.output.verbosity());
+        mini_push.set_color(args.output.color);
+        mini_push.set_progress(args.output.progress);
+    }
+    mini_push.set_redraw(args.terminal.redraw());
+    mini_push.set_script(args.script);
+    let (missing_code, image_code) = if args.script { (exit::DEVICE_MISSING, exit::IMAGE_ERROR) } else { (1, 1) };
+    mini_push.output().banner("Minipush 1.0");
+    if let Some(max) = args.terminal.max_session_time() { terminal::limit_session(max, mini_push.output().clone()); }
+    let boards = match fleet::expand(&mini_push.target_serial_name) {
+        Ok(boards) => boards,
+        Err(e) => {
+            mini_push.output().error(format!("{} {}; {}", mini_push.output().icon(Icon::Fail), e, transport::LIST_PORTS_HINT));
+            process::exit(missing_code);
+        }
+    };
+    // typos are reported now, not after the target was powered and the handshake timed out
+    for board in &boards {
+        if let Err(e) = transport::check_name(board) {
+            mini_push.output().error(format!("{} {}; {}", mini_push.output().icon(Icon::Fail), e, transport::LIST_PORTS_HINT));
+            process::exit(missing_code);
+        }
+    }
+    let several = boards.len() > 1;
+    if let Some(attach) = args.attach.as_ref().filter(|attach| !boards.contains(attach)) {
+        mini_push.output().error(format!("{} --attach {} is not one of the boards pushed to", mini_push.output().icon(Icon::Fail), attach));
+        process::exit(1);
+    }
+    if several && args.watch {
+        mini_push.output().error(format!("{} --watch pushes to one board, not {}", mini_push.output().icon(Icon::Fail), boards.len()));
+        process::exit(1);
+    }
+    if several && args.terminal.log_tx.is_some() {
+        mini_push.output().error(format!("{} --log-tx mirrors one board, not {}", mini_push.output().icon(Icon::Fail), boards.len()));
+        process::exit(1);
+    }
+    if let [board] = boards.as_slice() { mini_push.target_serial_name = board.clone(); }
+    if !from_stdin && !from_url {
+        let checked = if image::is_glob(&mini_push.binary_image_path) {
+            image::newest_match(&mini_push.binary_image_path).and_then(image::check)
+        } else {
+            image::check(&mini_push.binary_image_path)
+        };
+        if let Err(e) = checked {
+            mini_push.output().error(format!("{} {}", mini_push.output().icon(Icon::Fail), e));
+            process::exit(image_code);
+        }
+    }
+    if args.watch && (from_stdin || from_url) {
+        mini_push.output().error(format!("{} --watch needs an image file, not {}", mini_push.output().icon(Icon::Fail), mini_push.binary_image_path));
+        process::exit(1);
+    }
+    mini_push.set_fill(args.fill);
+    mini_push.set_expect_sha256(args.sha256);
+    mini_push.set_max_image_size(if args.allow_huge { None } else { Some(args.max_image_size) });
+    if from_stdin {
+        // the size goes out before the image, so all of it has to be here first
+        let spooled = if io::stdin().is_terminal() {
+            Err("stdin is a terminal, pipe the image in".to_string())
+        } else {
+            image::spool(io::stdin().lock(), image::SPOOL_MAX).map_err(|e| e.to_string()).and_then(|data| if data.is_empty() { Err("stdin is empty".to_string()) } else { Ok(data) })
+        };
+        match spooled {
+            Ok(data) => {
+                mini_push.output().verbose(format!("Read {} from stdin", format_bytes(data.len() as u64)));
+                mini_push.set_spooled_image(Some(data));
+            }
+            Err(e) => {
+                mini_push.output().error(format!("{} -: {}", mini_push.output().icon(Icon::Fail), e));
+                process::exit(image_code);
+            }
+        }
+    }
+    #[cfg(feature = "http")]
+    let downloaded = match from_url.then(|| mini_push.download()).transpose() {
+        Ok(Some(downloaded)) if downloaded.size == 0 => {
+            mini_push.output().error(format!("{} {}: the download is empty", mini_push.output().icon(Icon::Fail), mini_push.binary_image_path));
+            process::exit(image_code);
+        }
+        Ok(downloaded) => downloaded,
+        Err(e) => {
+            mini_push.output().error(format!("{} {}", mini_push.output().icon(Icon::Fail), e));
+            process::exit(image_code);
+        }
+    };
+    #[cfg(not(feature = "http"))]
+    if from_url {
+        mini_push.output().error(format!("{} {}: built without the http feature, download the image first",
+                                         mini_push.output().icon(Icon::Fail), mini_push.binary_image_path));
+        process::exit(image_code);
+    }
+    mini_push.set_serial_settings(args.serial.settings());
+    mini_push.set_force_lock(args.serial.force);
+    mini_push.set_force_baud(args.serial.force_baud);
+    mini_push.set_allow_device_change(args.serial.allow_device_change);
+    mini_push.set_sync_on_connect(args.serial.sync_on_connect);
+    mini_push.set_highlighter(args.terminal.highlighter());
+    mini_push.set_triggers(args.terminal.triggers());
+    match args.terminal.recorder(mini_push.output()) {
+        Ok(recorder) => mini_push.set_recorder(recorder),
+        Err(e) => {
+            mini_push.output().error(format!("{} {}: {}", mini_push.output().icon(Icon::Fail), args.terminal.record.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    match args.terminal.wire(mini_push.output()) {
+        Ok(wire) => mini_push.set_wire(wire),
+        Err(e) => {
+            mini_push.output().error(format!("{} {}: {}", mini_push.output().icon(Icon::Fail), args.terminal.wire_log.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_push.set_scrollback(Some(args.terminal.scrollback("mini_push")));
+    match args.terminal.tx_log() {
+        Ok(log) => mini_push.set_tx_log(log),
+        Err(e) => {
+            mini_push.output().error(format!("{} --log-tx {}: {}", mini_push.output().icon(Icon::Fail), args.terminal.log_tx.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    match args.terminal.session_log(mini_push.output()) {
+        Ok(log) => mini_push.set_session_log(log),
+        Err(e) => {
+            mini_push.output().error(format!("{} {}: {}", mini_push.output().icon(Icon::Fail), args.terminal.log.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_push.set_terminal_options(args.terminal.options());
+    match args.terminal.control_socket() {
+        Ok(control) => mini_push.set_control(control),
+        Err(e) => {
+            mini_push.output().error(format!("{} --control {}: {}", mini_push.output().icon(Icon::Fail), args.terminal.control.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_push.set_reset(reset);
+    mini_push.set_size_header(if args.extended_size { SizeHeader::Extended } else { SizeHeader::Legacy });
+    mini_push.set_binary_request(args.trigger);
+    mini_push.set_handshake_timeout(Some(Duration::from_secs(args.handshake_timeout)).filter(|limit| !limit.is_zero()));
+    mini_push.set_post_ack_delay(Duration::from_millis(args.post_ack_delay));
+    let cmdline = match &args.cmdline_file {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(cmdline) => cmdline.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => {
+                mini_push.output().error(format!("{} --cmdline-file {}: {}", mini_push.output().icon(Icon::Fail), path.display(), e));
+                process::exit(1);
+            }
+        },
+        None => args.cmdline.clone().unwrap_or_default(),
+    };
+    // a command line that can't be sent is refused before the target is even powered
+    match protocol::encode_cmdline(&cmdline).map(|_| cmdline) {
+        Ok(cmdline) => mini_push.set_cmdline(cmdline),
+        Err(e) => {
+            mini_push.output().error(format!("{} --cmdline: {}", mini_push.output().icon(Icon::Fail), e));
+            process::exit(1);
+        }
+    }
+    mini_push.set_negotiate(args.negotiate);
+    mini_push.set_auto_baud(args.auto_baud.clone());
+    mini_push.set_protocol_version(args.protocol_version);
+    mini_push.set_identify(args.identify);
+    mini_push.set_expect_target(args.expect_target.clone());
+    mini_push.set_resume(args.resume);
+    if args.delta {
+        match DeltaCache::default_dir() {
+            Some(dir) => mini_push.set_delta(Some(DeltaCache::new(dir))),
+            None => {
+                mini_push.output().error(format!("{} --delta: neither XDG_CACHE_HOME nor HOME is set to keep the last image in",
+                                                 mini_push.output().icon(Icon::Fail)));
+                process::exit(1);
+            }
+        }
+    }
+    mini_push.set_chunk_size(args.chunk_size as usize);
+    mini_push.set_push_protocol(args.protocol);
+    mini_push.set_early_output(args.early_output);
+    let boot_deadline = Duration::from_secs(args.expect_boot_timeout);
+    mini_push.set_boot_marker(args.expect_boot.map(|pattern| (pattern, boot_deadline)));
+    let ready_deadline = Duration::from_secs(args.sync_time_timeout);
+    mini_push.set_time_sync(args.sync_time, args.sync_time_ready.map(|pattern| (pattern, ready_deadline)));
+    mini_push.set_no_terminal(if args.no_terminal || args.script { Some(Duration::from_secs(args.boot_secs)) } else { None });
+    mini_push.set_watch(if args.watch { Some(args.watch_path.clone()) } else { None });
+    mini_push.set_build_command(args.exec.clone());
+    mini_push.set_max_reconnect_attempts(args.max_reconnect_attempts);
+    mini_push.set_events(args.output.event_log(), args.progress_every);
+    let result = if several { mini_push.push_boards(&boards, args.attach.as_deref()) } else { mini_push.run() };
+    if let Some(path) = &args.report {
+        if let Err(e) = mini_push.write_report(path, &result) {
+            mini_push.output().error(format!("{} --report {}: {}", mini_push.output().icon(Icon::Fail), path.display(), e));
+            if result.is_ok() { process::exit(1); }
+        }
+    }
+    #[cfg(feature = "http")]
+    drop(downloaded);
+    // closes the control socket, which exiting wouldn't
+    drop(mini_push);
+    if args.script { process::exit(exit::script_code(&result)); }
+    if let Err(e) = result { process::exit(e.exit_code()); }
 }
@@ -1,9 +1,141 @@
-use rust_serial_tool::{Colorize, Result, SerialPort, SerialTool};
+use std::{fs, net::SocketAddr, path::PathBuf, process, sync::{Arc, Mutex}, time::Duration};
+
+use clap::{CommandFactory, Parser};
+use rust_serial_tool::{bench::{self, BenchConfig}, bridge::Bridge, cli::{self, BenchArgs, OutputArgs, ProfileArgs, SelftestArgs, SerialArgs, TerminalArgs}, control::ControlSocket, doctor, ErrorKind, events::EventLog, highlight::Highlighter, observer::{ObserverSlot, PushObserver}, prompt::LocalCommands, output::{format_bytes, ColorChoice, Icon, Output, ProgressChoice, Verbosity}, logfile::SessionLog, mux::Mux, record::Recorder, wire::WireLog, Result, script::Script, scrollback::Scrollback, selftest::SelftestConfig, SerialPort, SerialTool, settings::{SerialSettings, SyncAction}, stats::SessionStats, terminal::{self, RxTap, TerminalOptions}, transport::{self, Target, UsbIdentity}, trigger::Triggers, txlog::TxLog};
+
+const EXAMPLES: &str = "\
+Examples:
+  mini_term --list-ports
+  mini_term --doctor
+  mini_term COM3 --doctor --doctor-loopback --json
+  mini_term /dev/ttyUSB0 --baud 115200
+  mini_term /dev/ttyUSB0 --prompt-key '~' --listen 0.0.0.0:4000
+  mini_term COM3 --log soak.log --log-rotate daily,50M --idle-timeout 600 --on-idle exit:2
+  mini_term /dev/ttyACM0 --script boot.expect --transcript
+  mini_term /dev/ttyACM0 --receive results/
+  mini_term /dev/ttyACM0 --pull --output crash.bin
+  mini_term /dev/ttyUSB0 --control /run/user/1000/mt.sock
+  mini_term /dev/ttyUSB0 --debug-wire --wire-log wire.jsonl
+  mini_term /dev/ttyUSB0 --second /dev/ttyUSB1 --second-baud 115200 --timestamps
+  mini_term tcp://terminal-server:4001
+  mini_term --profile rock5
+  mini_term --replay session.rec --replay-speed 0";
+
+/// Minimal serial terminal for talking to the target.
+#[derive(Parser)]
+#[command(name = "mini_term", version = rust_serial_tool::VERSION, after_help = EXAMPLES)]
+struct Args {
+    /// Serial device of the target, e.g. /dev/ttyUSB0, COM3 or part of its USB description like "CP210x";
+    /// rfc2217://host:port or tcp://host:port for one behind a terminal server
+    serial_name: Option<String>,
+    /// List the serial ports there are, with their USB descriptions, and exit
+    #[arg(long, conflicts_with = "serial_name")]
+    list_ports: bool,
+    /// Check what keeps the port from working and exit: there, openable, not held by another
+    /// program or a service like ModemManager or brltty, the baud rate taken. Without a port,
+    /// the one USB adapter there is
+    #[arg(long, conflicts_with_all = ["list_ports", "script", "benchmark", "selftest", "receive", "pull", "replay", "second"])]
+    doctor: bool,
+    /// With --doctor, also check that what is sent comes back; TX must be jumpered to RX
+    #[arg(long, requires = "doctor")]
+    doctor_loopback: bool,
+    /// Print the --doctor report as JSON
+    #[arg(long, requires = "doctor")]
+    json: bool,
+    #[command(flatten)]
+    serial: SerialArgs,
+    #[command(flatten)]
+    terminal: TerminalArgs,
+    /// Only watch the port: never write to it and leave DTR/RTS deasserted. The exit key, Ctrl-C or Ctrl-A q quits
+    #[arg(long, conflicts_with_all = ["script", "benchmark", "selftest", "receive"])]
+    read_only: bool,
+    /// Monitor a second port alongside, its lines interleaved with the first's and tagged [A]
+    /// and [B]; Ctrl-A o switches which one is typed into
+    #[arg(long, value_name = "DEVICE", conflicts_with_all = ["script", "benchmark", "selftest", "receive", "pull", "replay", "listen", "record", "hex", "control", "debug_wire"])]
+    second: Option<String>,
+    /// Baud rate of the --second port; the same as --baud by default
+    #[arg(long, value_name = "BAUD", requires = "second", value_parser = cli::parse_baud)]
+    second_baud: Option<u32>,
+    /// Stamp each line of the --second monitor with the time it came in
+    #[arg(long, requires = "second")]
+    timestamps: bool,
+    /// Also expose the port on a TCP socket, e.g. 0.0.0.0:4000
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+    /// Run an expect/send script instead of the interactive terminal
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// Print a per-step summary after the script
+    #[arg(long, requires = "script")]
+    transcript: bool,
+    /// Receive the files the target sends with YMODEM into DIR, e.g. test results, then exit
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["script", "benchmark", "selftest"])]
+    receive: Option<PathBuf>,
+    /// Wait for the target to send a memory dump, write it out and exit; in the terminal, the
+    /// pull command catches one instead
+    #[arg(long, conflicts_with_all = ["script", "benchmark", "selftest", "receive"])]
+    pull: bool,
+    /// Where --pull writes the dump, a file or a directory; the name the target gives by default
+    #[arg(long = "output", value_name = "PATH", requires = "pull")]
+    dump_output: Option<PathBuf>,
+    /// Play a session recorded with --record back instead of opening a port
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["script", "benchmark", "selftest", "listen", "record", "receive", "pull"])]
+    replay: Option<PathBuf>,
+    /// Replay this many times as fast as recorded; 0 replays without waiting
+    #[arg(long, default_value_t = 1.0, requires = "replay", value_parser = parse_speed)]
+    replay_speed: f64,
+    #[command(flatten)]
+    bench: BenchArgs,
+    #[command(flatten)]
+    selftest: SelftestArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    #[command(flatten)]
+    profile: ProfileArgs,
+}
+
+fn parse_speed(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed >= 0.0 && speed.is_finite() => Ok(speed),
+        Ok(_) => Err("expected a speed of 0 or more".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
 pub struct MiniTerm {
     name_short: String,
     target_serial_name: String,
-    target_serial: Option<SerialPort>,
+    serial_settings: SerialSettings,
+    terminal_options: TerminalOptions,
+    highlighter: Highlighter,
+    triggers: Triggers,
+    recorder: Option<Arc<Recorder>>,
+    wire: Option<Arc<WireLog>>,
+    session_log: Option<Arc<SessionLog>>,
+    scrollback: Option<Arc<Scrollback>>,
+    tx_log: Option<Arc<TxLog>>,
+    stats: Arc<SessionStats>,
+    control: Option<Arc<ControlSocket>>,
+    listen: Option<SocketAddr>,
+    bridge: Option<Bridge>,
+    script: Option<Script>,
+    transcript: bool,
+    benchmark: Option<(BenchConfig, Option<PathBuf>)>,
+    selftest: Option<SelftestConfig>,
+    receive: Option<PathBuf>,
+    pull: bool,
+    pull_output: Option<PathBuf>,
+    second: Option<(String, SerialSettings)>,
+    timestamps: bool,
+    events: Option<EventLog>,
+    observer: ObserverSlot,
+    output: Output,
+    force_lock: bool,
+    force_baud: bool,
+    /// The USB adapter at the port when it was first opened, see [`SerialTool::check_device`].
+    known_device: Option<UsbIdentity>,
+    allow_device_change: bool,
+    sync_on_connect: Option<SyncAction>,
 }
 
 impl MiniTerm {
@@ -11,8 +143,236 @@ impl MiniTerm {
         Self {
             name_short: "MT".to_string(),
             target_serial_name,
-            target_serial: None,
+            serial_settings: SerialSettings::default(),
+            terminal_options: TerminalOptions::default(),
+            highlighter: Highlighter::default(),
+            triggers: Triggers::default(),
+            recorder: None,
+            wire: None,
+            session_log: None,
+            scrollback: None,
+            tx_log: None,
+            stats: Arc::new(SessionStats::default()),
+            control: None,
+            listen: None,
+            bridge: None,
+            script: None,
+            transcript: false,
+            benchmark: None,
+            selftest: None,
+            receive: None,
+            pull: false,
+            pull_output: None,
+            second: None,
+            timestamps: false,
+            events: None,
+            observer: ObserverSlot::default(),
+            output: Output::new("MT", Verbosity::Normal),
+            force_lock: false,
+            force_baud: false,
+            known_device: None,
+            allow_device_change: false,
+            sync_on_connect: None,
+        }
+    }
+
+    pub fn set_serial_settings(&mut self, settings: SerialSettings) {
+        self.serial_settings = settings;
+    }
+
+    pub fn set_terminal_options(&mut self, options: TerminalOptions) {
+        self.terminal_options = options;
+    }
+
+    pub fn set_force_lock(&mut self, force: bool) {
+        self.force_lock = force;
+    }
+
+    pub fn set_force_baud(&mut self, force: bool) {
+        self.force_baud = force;
+    }
+
+    pub fn set_allow_device_change(&mut self, allow: bool) {
+        self.allow_device_change = allow;
+    }
+
+    pub fn set_sync_on_connect(&mut self, sync: Option<SyncAction>) {
+        self.sync_on_connect = sync;
+    }
+
+    pub fn set_highlighter(&mut self, highlighter: Highlighter) {
+        self.highlighter = highlighter;
+    }
+
+    pub fn set_triggers(&mut self, triggers: Triggers) {
+        self.triggers = triggers;
+    }
+
+    pub fn set_recorder(&mut self, recorder: Option<Arc<Recorder>>) {
+        self.recorder = recorder;
+    }
+
+    pub fn set_wire(&mut self, wire: Option<Arc<WireLog>>) {
+        self.wire = wire;
+    }
+
+    pub fn set_session_log(&mut self, log: Option<Arc<SessionLog>>) {
+        self.session_log = log;
+    }
+
+    pub fn set_scrollback(&mut self, scrollback: Option<Arc<Scrollback>>) {
+        self.scrollback = scrollback;
+    }
+
+    /// Mirror every byte written to the port to `log`, see [`txlog`].
+    pub fn set_tx_log(&mut self, log: Option<Arc<TxLog>>) {
+        self.tx_log = log;
+    }
+
+    pub fn set_control(&mut self, control: Option<Arc<ControlSocket>>) {
+        self.control = control;
+    }
+
+    pub fn set_listen(&mut self, listen: Option<SocketAddr>) {
+        self.listen = listen;
+    }
+
+    pub fn set_script(&mut self, script: Option<Script>, transcript: bool) {
+        self.script = script;
+        self.transcript = transcript;
+    }
+
+    /// Benchmark instead of opening the terminal, optionally saving the results as JSON.
+    pub fn set_benchmark(&mut self, config: Option<BenchConfig>, json: Option<PathBuf>) {
+        self.benchmark = config.map(|config| (config, json));
+    }
+
+    fn run_benchmark(&mut self, port: &mut SerialPort) -> Result<()> {
+        let (config, json) = match self.benchmark.clone() {
+            Some(benchmark) => benchmark,
+            None => return Ok(()),
+        };
+        let results = self.benchmark(port, &config)?;
+        self.output.blank(Verbosity::Quiet);
+        bench::table(&results).lines().for_each(|row| self.output.line(Verbosity::Quiet, row));
+        if let Some(path) = json {
+            fs::write(&path, bench::to_json(&self.serial_settings, &results) + "\n")?;
+            self.output.status(format!("{} Results written to {}", self.output.icon(Icon::Save), path.display()));
+        }
+        Ok(())
+    }
+
+    pub fn set_events(&mut self, events: Option<EventLog>) {
+        self.events = events;
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.output.set_verbosity(verbosity);
+    }
+
+    pub fn set_color(&mut self, color: ColorChoice) {
+        self.output.set_color(color);
+    }
+
+    pub fn set_progress(&mut self, progress: ProgressChoice) {
+        self.output.set_progress(progress);
+    }
+
+    pub fn set_redraw(&mut self, every: Duration) {
+        self.output.set_redraw(every);
+    }
+
+    /// Run the loopback self-test instead of opening the terminal.
+    pub fn set_selftest(&mut self, config: Option<SelftestConfig>) {
+        self.selftest = config;
+    }
+
+    fn run_selftest(&mut self, port: &mut SerialPort) -> Result<()> {
+        let config = match self.selftest.clone() {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        self.output.status(format!("{} Self-test at {}, TX must be jumpered to RX", self.output.icon(Icon::Loop), config.settings));
+        let report = self.selftest(port, &config)?;
+        self.output.line(Verbosity::Quiet, &report);
+        if !report.passed() { return Err(ErrorKind::SelftestError(report.to_string())); }
+
+        self.output.status(format!("{} Loopback OK", self.output.icon(Icon::Ok)));
+        Ok(())
+    }
+
+    /// Receive files with YMODEM into `dir` instead of opening the terminal.
+    pub fn set_receive(&mut self, dir: Option<PathBuf>) {
+        self.receive = dir;
+    }
+
+    fn run_receive(&mut self, port: &mut SerialPort) -> Result<()> {
+        let dir = match self.receive.clone() {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let files = self.receive_files(port, &dir)?;
+        let bytes: u64 = files.iter().map(|file| file.bytes).sum();
+        self.output.status(format!("{} {} file{} received, {} in all", self.output.icon(Icon::Save), files.len(),
+                                   if files.len() == 1 { "" } else { "s" }, format_bytes(bytes)));
+        Ok(())
+    }
+
+    /// Wait for a dump and write it to `output`, or under the name the target gives, instead of
+    /// opening the terminal.
+    pub fn set_pull(&mut self, pull: bool, output: Option<PathBuf>) {
+        self.pull = pull;
+        self.pull_output = output;
+    }
+
+    fn run_pull(&mut self, port: &mut SerialPort) -> Result<()> {
+        let output = self.pull_output.clone();
+        self.pull_dump(port, output.as_deref()).map(|_| ())
+    }
+
+    /// Monitor `name` with `settings` alongside the port, optionally with timestamps, instead
+    /// of the plain terminal.
+    pub fn set_second(&mut self, second: Option<(String, SerialSettings)>, timestamps: bool) {
+        self.second = second;
+        self.timestamps = timestamps;
+    }
+
+    fn run_mux(&mut self, port: &SerialPort) -> Result<()> {
+        let (name, settings) = match self.second.clone() {
+            Some(second) => second,
+            None => return Ok(()),
+        };
+        // one that isn't there yet is waited for alongside the first
+        let second = Target::parse(&name).open(&settings, Duration::from_millis(1)).ok();
+        let mut mux = Mux::new(self.output.clone(), self.terminal_options.clone())
+            .timestamps(self.timestamps)
+            .highlighter(self.highlighter())
+            .triggers(self.triggers())
+            .port(&self.target_serial_name, self.serial_settings, Some(port.try_clone()?))
+            .port(&name, settings, second)
+            .stats(self.stats.clone());
+        if let Some(log) = self.session_log() { mux = mux.tap(Box::new(log)); }
+        if let Some(scrollback) = self.scrollback() { mux = mux.tap(Box::new(scrollback)); }
+        mux.run().map(|_| ())
+    }
+
+    /// Starts the bridge on first use and points it at the current port.
+    fn attach_bridge(&mut self, port: &SerialPort) -> Result<()> {
+        let addr = match self.listen {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+        if self.bridge.is_none() {
+            let bridge = Bridge::listen(addr)?;
+            self.output.status(format!("{} Bridging on {}", self.output.icon(Icon::Network), bridge.local_addr()));
+            self.bridge = Some(bridge);
         }
+
+        // read-only: clients watch, but their input has nowhere to go
+        if self.terminal_options.read_only { return Ok(()); }
+        let port = port.try_clone()?;
+        if let Some(bridge) = &self.bridge { bridge.attach(port); }
+        Ok(())
     }
 }
 
@@ -25,27 +385,258 @@ impl SerialTool for MiniTerm {
         &self.name_short
     }
 
-    fn target_serial(&mut self) -> Option<&mut SerialPort> {
-        self.target_serial.as_mut()
+    fn serial_settings(&self) -> SerialSettings {
+        self.serial_settings
+    }
+
+    fn terminal_options(&self) -> TerminalOptions {
+        self.terminal_options.clone()
+    }
+
+    fn force_lock(&self) -> bool {
+        self.force_lock
+    }
+
+    fn force_baud(&self) -> bool {
+        self.force_baud
+    }
+
+    fn known_device(&self) -> Option<UsbIdentity> {
+        self.known_device.clone()
+    }
+
+    fn set_known_device(&mut self, device: Option<UsbIdentity>) {
+        self.known_device = device;
+    }
+
+    fn allow_device_change(&self) -> bool {
+        self.allow_device_change
+    }
+
+    fn sync_on_connect(&self) -> Option<SyncAction> {
+        self.sync_on_connect
     }
 
-    fn set_target_serial(&mut self, serialport: SerialPort) {
-        self.target_serial = Some(serialport);
+    fn highlighter(&self) -> Highlighter {
+        self.highlighter.clone()
     }
 
+    fn triggers(&self) -> Triggers {
+        self.triggers.clone()
+    }
+
+    fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.clone()
+    }
+
+    fn wire(&self) -> Option<Arc<WireLog>> {
+        self.wire.clone()
+    }
+
+    fn session_log(&self) -> Option<Arc<SessionLog>> {
+        self.session_log.clone()
+    }
+
+    fn scrollback(&self) -> Option<Arc<Scrollback>> {
+        self.scrollback.clone()
+    }
+
+    fn tx_log(&self) -> Option<Arc<TxLog>> {
+        self.tx_log.clone()
+    }
+
+    fn stats(&self) -> Option<Arc<SessionStats>> {
+        Some(self.stats.clone())
+    }
+
+    fn control(&self) -> Option<Arc<ControlSocket>> {
+        self.control.clone()
+    }
+
+
+    fn events(&self) -> Option<&EventLog> {
+        self.events.as_ref()
+    }
+
+    fn observer(&self) -> Option<&Mutex<Box<dyn PushObserver>>> {
+        Some(self.observer.get(&self.output))
+    }
+
+    fn output(&self) -> &Output {
+        &self.output
+    }
+
+    /// With --listen, `clients` tells who is connected.
+    fn local_commands(&self) -> LocalCommands {
+        let commands = LocalCommands::default();
+        let bridge = match &self.bridge {
+            Some(bridge) => bridge,
+            None => return commands,
+        };
+        let (tap, addr) = (bridge.tap(), bridge.local_addr());
+        commands.register("clients", "clients", Box::new(move |ctx, _| {
+            let count = tap.client_count();
+            ctx.say(format!("{} {} client{} on {}", ctx.out.icon(Icon::Network), count, if count == 1 { "" } else { "s" }, addr));
+            Ok(())
+        }))
+    }
+
+    fn rx_taps(&mut self) -> Vec<Box<dyn RxTap>> {
+        self.bridge.iter().map(|bridge| Box::new(bridge.tap()) as Box<dyn RxTap>).collect()
+    }
 
     fn exec(&mut self) -> Result<()> {
-        self.open_serial();
-        self.terminal()
+        let mut connection = self.open_serial()?;
+        let port = &mut connection.port;
+        self.attach_bridge(port)?;
+        if self.benchmark.is_some() { return self.run_benchmark(port); }
+        if self.selftest.is_some() { return self.run_selftest(port); }
+        if self.receive.is_some() { return self.run_receive(port); }
+        if self.pull { return self.run_pull(port); }
+        if self.second.is_some() { return self.run_mux(port); }
+        match self.script.take() {
+            Some(script) => {
+                let result = self.run_script(port, &script, self.transcript);
+                self.script = Some(script);
+                result
+            }
+            None => self.terminal(port),
+        }
     }
 }
 
+fn list_ports() -> ! {
+    match transport::list_ports() {
+        Ok(ports) if ports.is_empty() => eprintln!("no serial ports found"),
+        Ok(ports) => for port in ports {
+            if port.description.is_empty() { println!("{}", port.name); } else { println!("{}\t{}", port.name, port.description); }
+        },
+        Err(e) => {
+            eprintln!("listing the ports failed: {}", e);
+            process::exit(1);
+        }
+    }
+    process::exit(0);
+}
+
+/// `--doctor`: the report on stdout, exiting with 1 if a check failed.
+fn doctor(args: &Args) -> ! {
+    let name = match args.serial_name.clone().map(Ok).unwrap_or_else(|| doctor::detect(&transport::list_ports().unwrap_or_default())) {
+        Ok(name) => name,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let report = doctor::run(&name, args.serial.settings(), args.doctor_loopback);
+    if args.json { println!("{}", report.to_json()); } else { println!("{}", report); }
+    process::exit(if report.passed() { 0 } else { 1 });
+}
 
 fn main() {
-    let target_serial_name: String = std::env::args().nth(1).expect("expect arg [serial_name]");
+    let args: Args = cli::parse();
+    if args.list_ports { list_ports(); }
+    if args.doctor { doctor(&args); }
+    // not left to clap, whose required_unless_present_any a port from --profile doesn't satisfy
+    if args.serial_name.is_none() && args.replay.is_none() {
+        Args::command().error(clap::error::ErrorKind::MissingRequiredArgument, "a serial port is needed, e.g. /dev/ttyUSB0, or a --profile with one").exit();
+    }
 
-    println!("{}", "Miniterm 1.0\n".cyan());
-    let mut mini_push = MiniTerm::initialize(target_serial_name);
-    mini_push.run();
+    let mut mini_term = MiniTerm::initialize(args.serial_name.clone().unwrap_or_default());
+    mini_term.set_verbosity(args.output.verbosity());
+    mini_term.set_color(args.output.color);
+    mini_term.set_progress(args.output.progress);
+    mini_term.set_redraw(args.terminal.redraw());
+    mini_term.output().banner("Miniterm 1.0");
+    if let Some(max) = args.terminal.max_session_time() { terminal::limit_session(max, mini_term.output().clone()); }
+    mini_term.set_serial_settings(args.serial.settings());
+    mini_term.set_force_lock(args.serial.force);
+    mini_term.set_force_baud(args.serial.force_baud);
+    mini_term.set_allow_device_change(args.serial.allow_device_change);
+    mini_term.set_sync_on_connect(args.serial.sync_on_connect);
+    mini_term.set_highlighter(args.terminal.highlighter());
+    mini_term.set_triggers(args.terminal.triggers());
+    match args.terminal.recorder(mini_term.output()) {
+        Ok(recorder) => mini_term.set_recorder(recorder),
+        Err(e) => {
+            mini_term.output().error(format!("{} {}: {}", mini_term.output().icon(Icon::Fail), args.terminal.record.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    match args.terminal.wire(mini_term.output()) {
+        Ok(wire) => mini_term.set_wire(wire),
+        Err(e) => {
+            mini_term.output().error(format!("{} {}: {}", mini_term.output().icon(Icon::Fail), args.terminal.wire_log.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_term.set_scrollback(Some(args.terminal.scrollback("mini_term")));
+    match args.terminal.tx_log() {
+        Ok(log) => mini_term.set_tx_log(log),
+        Err(e) => {
+            mini_term.output().error(format!("{} --log-tx {}: {}", mini_term.output().icon(Icon::Fail), args.terminal.log_tx.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    match args.terminal.session_log(mini_term.output()) {
+        Ok(log) => mini_term.set_session_log(log),
+        Err(e) => {
+            mini_term.output().error(format!("{} {}: {}", mini_term.output().icon(Icon::Fail), args.terminal.log.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_term.set_terminal_options(TerminalOptions { read_only: args.read_only, ..args.terminal.options() });
+    if let Some(Err(e)) = args.replay.is_none().then(|| transport::check_name(&mini_term.target_serial_name)) {
+        mini_term.output().error(format!("{} {}; {}", mini_term.output().icon(Icon::Fail), e, transport::LIST_PORTS_HINT));
+        process::exit(1);
+    }
+    if let Some(path) = &args.replay {
+        mini_term.output().status(format!("{} Replaying {}", mini_term.output().icon(Icon::Loop), path.display()));
+        if let Err(e) = mini_term.replay(path, args.replay_speed) {
+            mini_term.output().error(format!("{} {}: {:?}", mini_term.output().icon(Icon::Fail), path.display(), e));
+            process::exit(1);
+        }
+        return;
+    }
+    match args.terminal.control_socket() {
+        Ok(control) => mini_term.set_control(control),
+        Err(e) => {
+            mini_term.output().error(format!("{} --control {}: {}", mini_term.output().icon(Icon::Fail), args.terminal.control.as_ref().unwrap().display(), e));
+            process::exit(1);
+        }
+    }
+    mini_term.set_listen(args.listen);
+    mini_term.set_events(args.output.event_log());
+    mini_term.set_benchmark(args.bench.config(), args.bench.bench_json.clone());
+    mini_term.set_selftest(args.selftest.config(args.serial.settings()));
+    if let Some(dir) = &args.receive {
+        if let Err(e) = fs::create_dir_all(dir) {
+            mini_term.output().error(format!("{} {}: {}", mini_term.output().icon(Icon::Fail), dir.display(), e));
+            process::exit(1);
+        }
+    }
+    mini_term.set_receive(args.receive);
+    mini_term.set_pull(args.pull, args.dump_output);
+    if let Some(second) = &args.second {
+        if let Err(e) = transport::check_name(second) {
+            mini_term.output().error(format!("{} {}; {}", mini_term.output().icon(Icon::Fail), e, transport::LIST_PORTS_HINT));
+            process::exit(1);
+        }
+        let settings = SerialSettings { baud_rate: args.second_baud.unwrap_or(args.serial.baud), ..args.serial.settings() };
+        mini_term.set_second(Some((second.clone(), settings)), args.timestamps);
+    }
+    if let Some(path) = args.script {
+        match Script::load(&path) {
+            Ok(script) => mini_term.set_script(Some(script), args.transcript),
+            Err(e) => {
+                mini_term.output().error(format!("{} {}: {:?}", mini_term.output().icon(Icon::Fail), path.display(), e));
+                process::exit(1);
+            }
+        }
+    }
+    let result = mini_term.run();
+    // closes the control socket, which exiting wouldn't
+    drop(mini_term);
+    if let Err(e) = result { process::exit(e.exit_code()); }
 }
 
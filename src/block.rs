@@ -0,0 +1,136 @@
+//! Block push protocol for noisy links: every chunk is framed with a sequence number and a
+//! CRC16, and retransmitted until the loader acknowledges it.
+//!
+//! A block is `SOH | seq: u16 LE | len: u16 LE | data[len] | crc: u16 LE`, the CRC
+//! (CRC-16/CCITT-FALSE) covering everything between `SOH` and itself. The loader answers
+//! `ACK | seq: u16 LE` once the block is stored and `NAK | seq: u16 LE` to have it sent again;
+//! it acknowledges a repeated block without storing it twice.
+
+use std::{io::{Read, Write}, str::FromStr, time::{Duration, Instant}};
+
+use crate::{bench, ErrorKind, ReadSerial, Result, WRITE_TIMEOUT, WriteSerial};
+
+pub const SOH: u8 = 0x01;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+
+/// Data bytes per block.
+pub const BLOCK_SIZE: usize = 512;
+
+/// How many times a block is sent again before the push is given up.
+pub const MAX_RETRIES: u32 = 8;
+
+/// How long to wait for the loader's verdict on a block.
+pub const BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How the image goes over the line after the size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PushProtocol {
+    /// Raw bytes, what the tutorial loaders expect.
+    #[default]
+    Stream,
+    /// CRC-checked blocks, see the module docs.
+    Block,
+}
+
+impl FromStr for PushProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "stream" => Ok(PushProtocol::Stream),
+            "block" => Ok(PushProtocol::Block),
+            _ => Err("expected stream or block".to_string()),
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &b| {
+        (0..8).fold(crc ^ (b as u16) << 8, |crc, _| if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 })
+    })
+}
+
+pub fn encode_block(seq: u16, data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + 7);
+    block.push(SOH);
+    block.extend_from_slice(&seq.to_le_bytes());
+    block.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    block.extend_from_slice(data);
+    let crc = crc16(&block[1..]);
+    block.extend_from_slice(&crc.to_le_bytes());
+    block
+}
+
+/// Sequence number and data of a complete block, as the loader checks it.
+pub fn decode_block(block: &[u8]) -> Result<(u16, &[u8])> {
+    if block.len() < 7 || block[0] != SOH { return Err(ErrorKind::ProtocolError); }
+    let len = u16::from_le_bytes([block[3], block[4]]) as usize;
+    if block.len() != len + 7 { return Err(ErrorKind::ProtocolError); }
+
+    let crc = u16::from_le_bytes([block[len + 5], block[len + 6]]);
+    if crc16(&block[1..len + 5]) != crc { return Err(ErrorKind::ProtocolError); }
+    Ok((u16::from_le_bytes([block[1], block[2]]), &block[5..len + 5]))
+}
+
+/// Sends blocks in sequence, each until it is acknowledged.
+#[derive(Debug, Clone)]
+pub struct BlockSender {
+    seq: u16,
+    timeout: Duration,
+    retries: u32,
+    /// Blocks sent again so far.
+    pub retransmitted: u64,
+}
+
+impl Default for BlockSender {
+    fn default() -> Self {
+        Self::new(BLOCK_TIMEOUT, MAX_RETRIES)
+    }
+}
+
+impl BlockSender {
+    pub fn new(timeout: Duration, retries: u32) -> Self {
+        Self { seq: 0, timeout, retries, retransmitted: 0 }
+    }
+
+    /// Sends `data` as the next block. `ProtocolError` once every retry was refused or lost.
+    pub fn send<P: Read + Write + ?Sized>(&mut self, port: &mut P, data: &[u8]) -> Result<()> {
+        let block = encode_block(self.seq, data);
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                self.retransmitted += 1;
+                // whatever is left of a garbled answer would misalign the next one
+                bench::drain(port, Duration::from_millis(20))?;
+            }
+            port.write_serial_all(&block, WRITE_TIMEOUT)?;
+            if self.acknowledged(port)? {
+                self.seq = self.seq.wrapping_add(1);
+                return Ok(());
+            }
+        }
+        Err(ErrorKind::ProtocolError)
+    }
+
+    /// Waits for the verdict on the current block; late answers about earlier blocks are skipped.
+    fn acknowledged<P: Read + ?Sized>(&self, port: &mut P) -> Result<bool> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut reply = [0; 3];
+            match port.read_serial_exact_timeout(&mut reply, deadline.saturating_duration_since(Instant::now())) {
+                Ok(()) => {}
+                Err(ErrorKind::ReadTimeout { .. }) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let seq = u16::from_le_bytes([reply[1], reply[2]]);
+            match reply[0] {
+                ACK if seq == self.seq => return Ok(true),
+                NAK if seq == self.seq => return Ok(false),
+                ACK | NAK => continue,
+                // line noise: let the retry sort it out
+                _ => return Ok(false),
+            }
+        }
+    }
+}
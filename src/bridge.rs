@@ -0,0 +1,178 @@
+use std::{io::{self, Read, Write}, net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, thread};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}, Mutex};
+use std::time::Duration;
+
+use crate::{Result, SerialPort, terminal::RxTap};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Client {
+    id: usize,
+    stream: TcpStream,
+}
+
+struct Shared {
+    clients: Mutex<Vec<Client>>,
+    /// Port the clients write to, replaced whenever the tool reconnects.
+    serial: Mutex<Option<SerialPort>>,
+    next_id: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    /// Single-writer policy: the longest connected client owns the input, the others only watch.
+    fn writer(&self) -> Option<usize> {
+        self.clients.lock().unwrap().first().map(|client| client.id)
+    }
+
+    /// Sends target output to every client, dropping the ones that went away.
+    fn broadcast(&self, data: &[u8]) {
+        let mut gone = Vec::new();
+        for client in self.clients.lock().unwrap().iter_mut() {
+            if client.stream.write_all(data).is_err() { gone.push(client.id); }
+        }
+        for id in gone { self.remove(id); }
+    }
+
+    fn remove(&self, id: usize) {
+        let mut clients = self.clients.lock().unwrap();
+        let was_writer = clients.first().map(|client| client.id) == Some(id);
+        clients.retain(|client| client.id != id);
+        if let (true, Some(writer)) = (was_writer, clients.first_mut()) {
+            let _ = writer.stream.write_all(b"\r\n[bridge] you now have write access\r\n");
+        }
+    }
+}
+
+/// Exposes the serial port on a TCP socket: everything received from the target goes to all
+/// clients, and input from the writing client goes to the target.
+pub struct Bridge {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Bridge {
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared {
+            clients: Mutex::new(Vec::new()),
+            serial: Mutex::new(None),
+            next_id: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let accept_shared = shared.clone();
+        let accept_thread = thread::spawn(move || accept_loop(listener, accept_shared));
+
+        Ok(Self { shared, local_addr, accept_thread: Some(accept_thread) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Points client input at a (re)opened port.
+    pub fn attach(&self, serial: SerialPort) {
+        *self.shared.serial.lock().unwrap() = Some(serial);
+    }
+
+    pub fn detach(&self) {
+        self.shared.serial.lock().unwrap().take();
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.shared.clients.lock().unwrap().len()
+    }
+
+    pub fn broadcast(&self, data: &[u8]) {
+        self.shared.broadcast(data);
+    }
+
+    pub fn tap(&self) -> BridgeTap {
+        BridgeTap { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        for client in self.shared.clients.lock().unwrap().drain(..) {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+        if let Some(handle) = self.accept_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Handle for the terminal's reader thread, forwards received chunks to the clients.
+pub struct BridgeTap {
+    shared: Arc<Shared>,
+}
+
+impl BridgeTap {
+    pub fn client_count(&self) -> usize {
+        self.shared.clients.lock().unwrap().len()
+    }
+}
+
+impl RxTap for BridgeTap {
+    fn rx(&mut self, data: &[u8]) {
+        self.shared.broadcast(data);
+    }
+}
+
+fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    let mut readers = Vec::new();
+    while !shared.shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Some(handle) = add_client(stream, &shared) { readers.push(handle); }
+            }
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+        readers.retain(|handle| !handle.is_finished());
+    }
+    for handle in readers { let _ = handle.join(); }
+}
+
+fn add_client(stream: TcpStream, shared: &Arc<Shared>) -> Option<thread::JoinHandle<()>> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(POLL_INTERVAL)).ok()?;
+    stream.set_write_timeout(Some(Duration::from_secs(1))).ok()?;
+    let _ = stream.set_nodelay(true);
+    let mut input = stream.try_clone().ok()?;
+
+    let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+    {
+        let mut clients = shared.clients.lock().unwrap();
+        let mut stream = stream;
+        if !clients.is_empty() {
+            let _ = stream.write_all(b"[bridge] another client has write access, watching only\r\n");
+        }
+        clients.push(Client { id, stream });
+    }
+
+    let shared = shared.clone();
+    Some(thread::spawn(move || {
+        let mut buf = [0; 256];
+        while !shared.shutdown.load(Ordering::Relaxed) {
+            match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if shared.writer() != Some(id) { continue; }
+                    if let Some(serial) = shared.serial.lock().unwrap().as_mut() {
+                        let _ = serial.write_all(&buf[..n]);
+                    }
+                }
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+                Err(_) => break,
+            }
+        }
+        shared.remove(id);
+    }))
+}
@@ -0,0 +1,533 @@
+use std::{ffi::OsString, io, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::{Args, Command, Parser};
+
+use crate::{bench::{BenchConfig, BenchData}, command, config::{Config, Profile}, control::ControlSocket, events::{EventLog, LogFormat}, filter::{FilterChain, FilterKind}, highlight::{Highlight, Highlighter}, idle::IdleAction, keys::KeyEncoding, logfile::{self, Rotation, RotatingLog, SessionLog}, output::{self, ColorChoice, Output, ProgressChoice, Verbosity}, record::Recorder, scrollback::{self, Scrollback}, SERIAL_BAUD, selftest::SelftestConfig, settings::{FlowControl, parse_flow_control, parse_framing, SerialSettings, SyncAction}, terminal::{self, COMMAND_PREFIX, Newline, TerminalOptions}, trigger::{Action, Trigger, Triggers}, txlog::{self, TxLog, TxLogFormat}, wire::WireLog};
+
+/// Line settings shared by both binaries.
+#[derive(Args, Debug, Clone)]
+pub struct SerialArgs {
+    /// Baud rate
+    #[arg(long, default_value_t = SERIAL_BAUD, value_parser = parse_baud)]
+    pub baud: u32,
+    /// Data bits, parity and stop bits, e.g. 8N1 or 7E1
+    #[arg(long, default_value = "8N1", value_parser = parse_framing)]
+    pub framing: (serialport::DataBits, serialport::Parity, serialport::StopBits),
+    /// Flow control: none, software (XON/XOFF) or hardware (RTS/CTS)
+    #[arg(long, default_value = "none", value_parser = parse_flow_control)]
+    pub flow_control: FlowControl,
+    /// Take over the port's lock file when the process that left it no longer runs
+    #[arg(long)]
+    pub force: bool,
+    /// Open the port at --baud even where its USB bridge (FTDI, CP210x, CH340, PL2303) makes
+    /// that rate more than 2 % off
+    #[arg(long)]
+    pub force_baud: bool,
+    /// Go on when the port comes back as another USB adapter after a reconnect, e.g. the
+    /// board next to it after a hub reset
+    #[arg(long)]
+    pub allow_device_change: bool,
+    /// Resynchronize the target's UART right after opening: break[:MS], autobaud[:COUNT] (0x55
+    /// bytes) or quiet[:MS]; what it answers is dropped
+    #[arg(long, value_name = "ACTION")]
+    pub sync_on_connect: Option<SyncAction>,
+}
+
+impl SerialArgs {
+    pub fn settings(&self) -> SerialSettings {
+        let (data_bits, parity, stop_bits) = self.framing;
+        SerialSettings { baud_rate: self.baud, data_bits, parity, stop_bits, flow_control: self.flow_control }
+    }
+}
+
+pub fn parse_baud(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(0) => Err("baud rate must be greater than zero".to_string()),
+        Ok(baud) => Ok(baud),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Options of the interactive terminal shared by both binaries.
+#[derive(Args, Debug, Clone)]
+pub struct TerminalArgs {
+    /// Length of the break sent with Ctrl-A b, in milliseconds
+    #[arg(long, default_value_t = 250)]
+    pub break_ms: u64,
+    /// Pace pushes and typed or pasted input to this many bytes per second, e.g. 200K
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    pub limit: Option<u64>,
+    /// Key that quits the terminal, e.g. ctrl-] or ctrl-x; Ctrl-C is sent to the target
+    #[arg(long, default_value = "ctrl-]", value_parser = parse_exit_key)]
+    pub exit_key: u8,
+    /// Key that opens the local command line when typed at the start of a line, e.g. ~ as in
+    /// ssh; typed twice it is sent. Ctrl-A : opens it anywhere
+    #[arg(long, value_name = "KEY", value_parser = parse_prompt_key)]
+    pub prompt_key: Option<u8>,
+    /// After a disconnect, keep the session and wait this many seconds for the port to come back
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    pub reconnect: u32,
+    /// What arrows, Home/End and function keys send: xterm escape sequences, or nothing with
+    /// ascii, for consoles that only take characters
+    #[arg(long, value_name = "ENCODING", default_value = "xterm")]
+    pub keys: KeyEncoding,
+    /// Leave the terminal's bracketed paste mode off; pastes are then told apart by arriving
+    /// all at once
+    #[arg(long)]
+    pub no_bracketed_paste: bool,
+    /// Wait between pasted characters, in milliseconds; typing is never delayed
+    #[arg(long, value_name = "MS", default_value_t = 1)]
+    pub paste_char_delay: u64,
+    /// Wait after each pasted line, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 10)]
+    pub paste_line_delay: u64,
+    /// Show what is typed, for targets that don't echo it back
+    #[arg(long)]
+    pub echo: bool,
+    /// What Enter sends: cr, lf or crlf
+    #[arg(long, default_value = "cr")]
+    pub newline: Newline,
+    /// Pass every escape sequence the target prints to the terminal; by default only colors
+    /// and cursor moves are, and e.g. title changes are shown as text
+    #[arg(long)]
+    pub raw_output: bool,
+    /// Show control bytes and invalid UTF-8 as dimmed escapes like ^[ or \xff, tab and line
+    /// endings aside; Ctrl-A e switches
+    #[arg(long)]
+    pub show_control: bool,
+    /// Show received bytes as a hex dump; Ctrl-A x switches views
+    #[arg(long)]
+    pub hex: bool,
+    /// Color received text matching a regex, written `regex` or `color:regex`; repeatable
+    #[arg(long, value_name = "[COLOR:]REGEX")]
+    pub highlight: Vec<Highlight>,
+    /// React to received lines: `regex:exit[:code]`, `regex:bell` or `regex:exec:cmd`; repeatable
+    #[arg(long, value_name = "REGEX:ACTION")]
+    pub on: Vec<Trigger>,
+    /// With piped stdin, end the session this many seconds after it started
+    #[arg(long, value_name = "SECONDS")]
+    pub exit_after: Option<u64>,
+    /// With piped stdin, end the session once all of it is sent instead of showing the
+    /// output until --exit-after or an exit trigger
+    #[arg(long)]
+    pub exit_on_eof: bool,
+    /// End the session successfully once a received line matches this regex, e.g. "ALL TESTS
+    /// PASSED"; repeatable
+    #[arg(long, alias = "exit-on", value_name = "REGEX", value_parser = parse_exit_on)]
+    pub exit_on_success: Vec<Trigger>,
+    /// End the session with --failure-code once a received line matches this regex, e.g.
+    /// "FAILED:"; repeatable, and ahead of --exit-on-success on a line matching both
+    #[arg(long, value_name = "REGEX", value_parser = parse_exit_on)]
+    pub exit_on_failure: Vec<Trigger>,
+    /// Exit code of --exit-on-failure
+    #[arg(long, value_name = "CODE", default_value_t = 1, value_parser = clap::value_parser!(i32).range(1..=255))]
+    pub failure_code: i32,
+    /// End the session this many seconds after the tool started, however far it got, exiting
+    /// with 124 as timeout(1) does
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_session_time: Option<u64>,
+    /// Warn once nothing has been received for this many seconds, e.g. a board that hung
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub idle_timeout: Option<u64>,
+    /// What else happens at --idle-timeout: warn (nothing else), bell, exec:CMD or exit[:CODE]
+    #[arg(long, value_name = "ACTION", default_value = "warn", requires = "idle_timeout")]
+    pub on_idle: IdleAction,
+    /// Record the session, timed, for replaying later
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+    /// Mirror every byte sent to the port to this file, a pushed image included, e.g. to put a
+    /// failed flash together again. What the file can't keep up with is dropped and said
+    #[arg(long, value_name = "PATH")]
+    pub log_tx: Option<PathBuf>,
+    /// How --log-tx writes: raw, the bytes as sent, or record, a session recording of timed frames
+    #[arg(long, value_name = "FORMAT", default_value = "raw", requires = "log_tx")]
+    pub log_tx_format: TxLogFormat,
+    /// Append what the target prints to this file, as plain bytes
+    #[arg(long, value_name = "PATH")]
+    pub log: Option<PathBuf>,
+    /// Start a fresh --log file past a size, daily (midnight UTC) or both: 50M, daily or daily,50M
+    #[arg(long, value_name = "WHEN", requires = "log")]
+    pub log_rotate: Option<Rotation>,
+    /// Rotated --log files kept, as PATH.1 (the newest) up to PATH.N
+    #[arg(long, value_name = "N", default_value_t = logfile::DEFAULT_KEEP, requires = "log")]
+    pub log_keep: usize,
+    /// Take JSON commands from scripts on this unix socket while the terminal runs, e.g.
+    /// {"cmd":"send","data":"reboot\n"} or {"cmd":"stats"}; one reply per line
+    #[arg(long, value_name = "PATH")]
+    pub control: Option<PathBuf>,
+    /// Show what is sent and received in one stream, as timed hex and ASCII rows: >> for sent,
+    /// << for received
+    #[arg(long)]
+    pub debug_wire: bool,
+    /// With --debug-wire, also log both directions to this file, one JSON object per line
+    #[arg(long, value_name = "PATH", requires = "debug_wire")]
+    pub wire_log: Option<PathBuf>,
+    /// Keep this much of what the target prints in memory, for the scrollback command and
+    /// --scrollback-dump
+    #[arg(long, value_name = "SIZE", default_value = "4M", value_parser = parse_size)]
+    pub scrollback: u64,
+    /// Where the scrollback is written when the session fails: the port lost, a panic or an
+    /// --exit-on-failure match [default: a file in the temp directory]
+    #[arg(long, value_name = "PATH")]
+    pub scrollback_dump: Option<PathBuf>,
+    /// Bytes read from the port at a time, e.g. 16K [default: about 20 ms at the baud rate]
+    #[arg(long, value_name = "SIZE", value_parser = parse_read_size)]
+    pub read_buffer: Option<usize>,
+    /// Pass what is shown through a filter first; repeatable, in order. collapse-overwrites
+    /// shows each line as it ends up once its carriage returns and backspaces are done, not
+    /// before it ends
+    #[arg(long, value_name = "FILTER")]
+    pub display_filter: Vec<FilterKind>,
+    /// Pass what goes into the --log file through a filter first, e.g. collapse-overwrites for
+    /// a counter redrawn in place, logged once as it ends up, or strip-escapes to leave out
+    /// colors and other escape sequences; repeatable, in order
+    #[arg(long, value_name = "FILTER", requires = "log")]
+    pub log_filter: Vec<FilterKind>,
+    /// For fast UARTs, e.g. 3 Mbaud: larger reads, shown in bigger batches, and progress
+    /// redrawn less often, so bursts don't overrun the OS buffer
+    #[arg(long)]
+    pub high_throughput: bool,
+    /// How long the target has to pause, in milliseconds, before a line it left unfinished is
+    /// shown anyway, e.g. a prompt
+    #[arg(long, value_name = "MS", default_value_t = terminal::LINE_IDLE.as_millis() as u64)]
+    pub line_idle: u64,
+}
+
+impl TerminalArgs {
+    pub fn options(&self) -> TerminalOptions {
+        TerminalOptions {
+            break_duration: Duration::from_millis(self.break_ms),
+            limit: self.limit,
+            hex: self.hex,
+            exit_key: self.exit_key,
+            prompt_key: self.prompt_key,
+            reconnect: self.reconnect,
+            keys: self.keys,
+            bracketed_paste: !self.no_bracketed_paste,
+            paste_char_delay: Duration::from_millis(self.paste_char_delay),
+            paste_line_delay: Duration::from_millis(self.paste_line_delay),
+            echo: self.echo,
+            newline: self.newline,
+            exit_after: self.exit_after.map(Duration::from_secs),
+            exit_on_eof: self.exit_on_eof,
+            raw_output: self.raw_output,
+            show_control: self.show_control,
+            idle_timeout: self.idle_timeout.map(Duration::from_secs),
+            on_idle: self.on_idle.clone(),
+            read_size: self.read_buffer,
+            high_throughput: self.high_throughput,
+            display_filters: self.display_filter.clone(),
+            log_filters: self.log_filter.clone(),
+            line_idle: Duration::from_millis(self.line_idle),
+            ..TerminalOptions::default()
+        }
+    }
+
+    /// How often progress is redrawn at most, see `--high-throughput`.
+    pub fn redraw(&self) -> Duration {
+        if self.high_throughput { output::HIGH_THROUGHPUT_REDRAW } else { output::BAR_REDRAW }
+    }
+
+    /// `--max-session-time`.
+    pub fn max_session_time(&self) -> Option<Duration> {
+        self.max_session_time.map(Duration::from_secs)
+    }
+
+    pub fn highlighter(&self) -> Highlighter {
+        Highlighter::new(self.highlight.clone())
+    }
+
+    pub fn triggers(&self) -> Triggers {
+        let failures = self.exit_on_failure.iter().map(|rule| Trigger { action: Action::Exit(self.failure_code), ..rule.clone() });
+        Triggers::new(self.on.iter().cloned().chain(failures).chain(self.exit_on_success.iter().cloned()).collect())
+    }
+
+    /// `--record`, warning on `out` should writing it fail.
+    pub fn recorder(&self, out: &Output) -> io::Result<Option<Arc<Recorder>>> {
+        self.record.as_ref().map(|path| Recorder::create(path).map(|recorder| Arc::new(recorder.warn_to(out, path.display())))).transpose()
+    }
+
+    /// `--log-tx`, closed however the session ends.
+    pub fn tx_log(&self) -> io::Result<Option<Arc<TxLog>>> {
+        let log = self.log_tx.as_ref().map(|path| TxLog::create(path, self.log_tx_format).map(Arc::new)).transpose()?;
+        if let Some(log) = &log { txlog::close_at_exit(log.clone()); }
+        Ok(log)
+    }
+
+    pub fn control_socket(&self) -> io::Result<Option<Arc<ControlSocket>>> {
+        self.control.as_ref().map(|path| ControlSocket::bind(path).map(Arc::new)).transpose()
+    }
+
+    pub fn wire(&self, out: &Output) -> io::Result<Option<Arc<WireLog>>> {
+        if !self.debug_wire { return Ok(None); }
+        WireLog::create(out.clone(), self.wire_log.as_deref()).map(|wire| Some(Arc::new(wire)))
+    }
+
+    /// The scrollback of `tool`, written out should the session fail.
+    pub fn scrollback(&self, tool: &str) -> Arc<Scrollback> {
+        let scrollback = Arc::new(Scrollback::new(self.scrollback as usize));
+        let path = self.scrollback_dump.clone().unwrap_or_else(|| scrollback::default_dump_path(tool));
+        scrollback::dump_on_failure(scrollback.clone(), path);
+        scrollback
+    }
+
+    /// `--log`, warning on `out` should writing it fail.
+    pub fn session_log(&self, out: &Output) -> io::Result<Option<Arc<SessionLog>>> {
+        let rotation = self.log_rotate.unwrap_or_default();
+        self.log.as_ref()
+            .map(|path| RotatingLog::open(path, rotation, self.log_keep)
+                .map(|log| Arc::new(SessionLog::with_filters(log, FilterChain::new(&self.log_filter)).warn_to(out))))
+            .transpose()
+    }
+}
+
+fn parse_exit_on(s: &str) -> Result<Trigger, String> {
+    match regex::Regex::new(s) {
+        Ok(regex) => Ok(Trigger { regex, action: Action::Exit(0) }),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_exit_key(s: &str) -> Result<u8, String> {
+    match command::parse_key(s)? {
+        COMMAND_PREFIX => Err(format!("{} is the command prefix", command::key_name(COMMAND_PREFIX))),
+        key => Ok(key),
+    }
+}
+
+/// A character like `~`, or a key named as for `--exit-key`.
+fn parse_prompt_key(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [c @ 0x21..=0x7e] => Ok(*c),
+        _ => parse_exit_key(s),
+    }
+}
+
+/// Status output and event logging shared by both binaries.
+#[derive(Args, Debug, Clone)]
+pub struct OutputArgs {
+    /// Lifecycle events: human status lines on stderr, or JSON lines there instead, only errors
+    /// and results (-v for the rest) still shown as lines
+    #[arg(long, default_value = "human")]
+    pub log_format: LogFormat,
+    /// Only print errors and results
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// More detail: -v for verbose, -vv to trace every handshake byte and chunk
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Colors and emoji: always, auto (only on a terminal, honoring NO_COLOR) or never
+    #[arg(long, default_value = "auto")]
+    pub color: ColorChoice,
+    /// Progress bars: always, auto (only on a terminal) or never, which prints a line every 10%
+    #[arg(long, default_value = "auto")]
+    pub progress: ProgressChoice,
+}
+
+impl OutputArgs {
+    /// As the flags ask, except that JSON events take the place of the status lines.
+    pub fn verbosity(&self) -> Verbosity {
+        match (Verbosity::from_flags(self.quiet, self.verbose), self.log_format) {
+            (Verbosity::Normal, LogFormat::Json) => Verbosity::Quiet,
+            (verbosity, _) => verbosity,
+        }
+    }
+
+    pub fn event_log(&self) -> Option<EventLog> {
+        match self.log_format {
+            LogFormat::Json => Some(EventLog::stderr()),
+            LogFormat::Human => None,
+        }
+    }
+}
+
+/// `--profile`, read ahead of the rest of the command line by [`parse`].
+#[derive(Args, Debug, Clone)]
+pub struct ProfileArgs {
+    /// Start from the settings of this profile in the config file; the environment variables
+    /// named in this help win over it, and the flags given here over those
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Config file with the profiles, instead of ~/.config/rust-serial-tool/config.toml
+    #[arg(long, value_name = "PATH", requires = "profile")]
+    pub config: Option<PathBuf>,
+}
+
+/// Positional arguments and the profile keys standing for them.
+const POSITIONALS: [(&str, &str); 2] = [("port", "serial_name"), ("image", "image_path")];
+
+/// Arguments that fall back on an environment variable when not given, by id, and the
+/// variable's name after the binary's, see [`env_var`].
+const ENV_FALLBACKS: [(&str, &str); 6] = [
+    ("serial_name", "SERIAL"),
+    ("image_path", "IMAGE"),
+    ("baud", "BAUD"),
+    ("framing", "FRAMING"),
+    ("flow_control", "FLOW_CONTROL"),
+    ("reset", "RESET"),
+];
+
+/// The environment variable standing in for an argument of `binary`, e.g. `MINIPUSH_BAUD`.
+pub fn env_var(binary: &str, suffix: &str) -> String {
+    format!("{}_{}", binary.replace(['_', '-'], "").to_uppercase(), suffix)
+}
+
+/// `command` with where its arguments get their values, the first there winning: the command
+/// line, the environment variable named next to the flag in `--help`, `profile`, the built-in
+/// default. `env` looks the variables up; empty ones count as unset.
+pub fn resolve_defaults(mut command: Command, profile: Option<&Profile>, env: impl Fn(&str) -> Option<String>) -> Command {
+    if let Some(profile) = profile { command = apply_profile(command, profile); }
+    let binary = command.get_name().to_string();
+    for (id, suffix) in ENV_FALLBACKS {
+        if !command.get_arguments().any(|arg| arg.get_id() == id) { continue; }
+        let var = env_var(&binary, suffix);
+        let value = env(&var).filter(|value| !value.is_empty());
+        command = command.mut_arg(id, |arg| {
+            let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+            let arg = arg.help(format!("{} [env: {}]", help, var));
+            match value {
+                Some(value) => arg.default_value(value).required(false),
+                None => arg,
+            }
+        });
+    }
+    command
+}
+
+/// Parses a binary's command line, on top of the environment variables and the profile
+/// `--profile` names, see [`resolve_defaults`].
+pub fn parse<A: Parser>() -> A {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let mut command = A::command();
+    let mut profile = None;
+    if let Some(name) = flag(&args, "--profile") {
+        let path = flag(&args, "--config").map(PathBuf::from).or_else(Config::default_path);
+        let profile_named = match path {
+            Some(path) => Config::load(&path)
+                .map_err(|e| format!("reading {}: {}", path.display(), e))
+                .and_then(|config| config.profile(&name).cloned()),
+            None => Err("no config file: neither XDG_CONFIG_HOME nor HOME is set, pass --config".to_string()),
+        };
+        match profile_named {
+            Ok(named) => profile = Some(named),
+            Err(e) => command.error(clap::error::ErrorKind::InvalidValue, e).exit(),
+        }
+    }
+    command = resolve_defaults(command, profile.as_ref(), |var| std::env::var(var).ok());
+    let matches = command.clone().get_matches_from(args);
+    A::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+}
+
+/// `command` with the settings of `profile` as its defaults. Keys it has no argument for are
+/// skipped, they belong to the other binary.
+pub fn apply_profile(mut command: Command, profile: &Profile) -> Command {
+    for (key, value) in profile.settings() {
+        let positional = POSITIONALS.iter().find(|(name, _)| *name == key).map(|(_, id)| *id);
+        let id = command.get_arguments().find(|arg| match positional {
+            Some(id) => arg.get_id() == id,
+            None => arg.get_long() == Some(key) && !matches!(key, "profile" | "config"),
+        });
+        if let Some(id) = id.map(|arg| arg.get_id().clone()) {
+            command = command.mut_arg(id, |arg| arg.default_values(value.args()).required(false));
+        }
+    }
+    command
+}
+
+/// The value of `--name VALUE` or `--name=VALUE` in `args`, before any `--`.
+fn flag(args: &[OsString], name: &str) -> Option<String> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy()).take_while(|arg| arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == name { return args.next().map(|value| value.into_owned()); }
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) { return Some(value.to_string()); }
+    }
+    None
+}
+
+/// Throughput benchmark knobs.
+#[derive(Args, Debug, Clone)]
+pub struct BenchArgs {
+    /// Measure throughput instead of opening the terminal
+    #[arg(long)]
+    pub benchmark: bool,
+    /// Bytes sent per run, e.g. 65536, 64K or 1M
+    #[arg(long, default_value = "64K", value_parser = parse_size)]
+    pub bench_bytes: u64,
+    /// Chunk sizes to measure, comma separated
+    #[arg(long, default_value = "1,64,512,4096", value_delimiter = ',', value_parser = parse_chunk_size)]
+    pub bench_chunks: Vec<usize>,
+    /// Generated data: pattern or random
+    #[arg(long, default_value = "pattern")]
+    pub bench_data: BenchData,
+    /// The far end echoes: also measure write-then-read-back round trips
+    #[arg(long, requires = "benchmark")]
+    pub bench_echo: bool,
+    /// Also write the results as JSON to this file
+    #[arg(long, requires = "benchmark")]
+    pub bench_json: Option<PathBuf>,
+}
+
+impl BenchArgs {
+    pub fn config(&self) -> Option<BenchConfig> {
+        if !self.benchmark { return None; }
+        Some(BenchConfig {
+            bytes: self.bench_bytes,
+            chunk_sizes: self.bench_chunks.clone(),
+            data: self.bench_data,
+            read_back: self.bench_echo,
+            ..BenchConfig::default()
+        })
+    }
+}
+
+/// Loopback self-test knobs.
+#[derive(Args, Debug, Clone)]
+pub struct SelftestArgs {
+    /// Check the adapter with TX jumpered to RX instead of opening the terminal
+    #[arg(long)]
+    pub selftest: bool,
+    /// How long the self-test runs, in seconds
+    #[arg(long, default_value_t = 10, requires = "selftest")]
+    pub selftest_secs: u64,
+    /// Stop the self-test after this many bytes instead, e.g. 1M
+    #[arg(long, value_parser = parse_size, requires = "selftest")]
+    pub selftest_bytes: Option<u64>,
+}
+
+impl SelftestArgs {
+    pub fn config(&self, settings: SerialSettings) -> Option<SelftestConfig> {
+        if !self.selftest { return None; }
+        Some(SelftestConfig {
+            settings,
+            duration: Duration::from_secs(self.selftest_secs),
+            bytes: self.selftest_bytes,
+            ..SelftestConfig::default()
+        })
+    }
+}
+
+/// Byte count with an optional K or M (binary) suffix.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, scale) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1024),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s, 1),
+    };
+    match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(scale)) {
+        Some(0) => Err("size must be greater than zero".to_string()),
+        Some(n) => Ok(n),
+        None => Err(format!("invalid size {:?}, expected e.g. 4096, 64K or 1M", s)),
+    }
+}
+
+fn parse_chunk_size(s: &str) -> Result<usize, String> {
+    parse_size(s).map(|n| n as usize)
+}
+
+/// `--read-buffer`, at most a second of the fastest UARTs.
+fn parse_read_size(s: &str) -> Result<usize, String> {
+    match parse_size(s)? {
+        n if n > 16 << 20 => Err("read buffer must be at most 16M".to_string()),
+        n => Ok(n as usize),
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+
+use crate::terminal::COMMAND_PREFIX;
+
+/// Local actions reachable through the command prefix in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SendBreak,
+    ToggleHex,
+    ToggleControl,
+    ClearScreen,
+    Prompt,
+    /// Type into the other port, see [`mux`](crate::mux).
+    SwitchPort,
+    /// Runs the tool's local command of this name, see [`prompt`](crate::prompt).
+    Local(&'static str),
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Command::SendBreak => "send break",
+            Command::ToggleHex => "hex/text view",
+            Command::ToggleControl => "show/hide control bytes",
+            Command::ClearScreen => "clear screen",
+            Command::Prompt => "local command line",
+            Command::SwitchPort => "switch port",
+            Command::Local(name) => name,
+            Command::Help => "help",
+            Command::Quit => "quit",
+        }
+    }
+}
+
+/// What a single stdin byte turned into after prefix handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chord {
+    /// Send the byte to the target.
+    Forward(u8),
+    /// The byte typed after the prefix.
+    Command(u8),
+}
+
+/// Splits stdin bytes into target input and prefix chords. Keeps the pending-prefix state
+/// between calls so a chord split across two reads is still recognized.
+#[derive(Debug, Clone)]
+pub struct ChordDecoder {
+    prefix: u8,
+    pending: bool,
+}
+
+impl ChordDecoder {
+    pub fn new(prefix: u8) -> Self {
+        Self { prefix, pending: false }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Chord> {
+        if self.pending {
+            self.pending = false;
+            if byte == self.prefix { Some(Chord::Forward(byte)) } else { Some(Chord::Command(byte)) }
+        } else if byte == self.prefix {
+            self.pending = true;
+            None
+        } else {
+            Some(Chord::Forward(byte))
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Drops a pending prefix, e.g. when an arrow key follows it, returning whether there was one.
+    pub fn cancel(&mut self) -> bool {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Key to command bindings of a tool's terminal.
+#[derive(Debug, Clone)]
+pub struct CommandTable {
+    prefix: u8,
+    bindings: Vec<(u8, Command)>,
+}
+
+impl Default for CommandTable {
+    fn default() -> Self {
+        CommandTable::new(COMMAND_PREFIX)
+            .bind(b'b', Command::SendBreak)
+            .bind(b'x', Command::ToggleHex)
+            .bind(b'e', Command::ToggleControl)
+            .bind(b'c', Command::ClearScreen)
+            .bind(b':', Command::Prompt)
+            .bind(b'q', Command::Quit)
+            .bind(b'h', Command::Help)
+    }
+}
+
+impl CommandTable {
+    pub fn new(prefix: u8) -> Self {
+        Self { prefix, bindings: Vec::new() }
+    }
+
+    /// Binds `key` to `command`, replacing an earlier binding of the same key.
+    pub fn bind(mut self, key: u8, command: Command) -> Self {
+        self.bindings.retain(|(k, _)| *k != key);
+        self.bindings.push((key, command));
+        self
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn decoder(&self) -> ChordDecoder {
+        ChordDecoder::new(self.prefix)
+    }
+
+    /// Looks up a key, ASCII letters match regardless of case.
+    pub fn lookup(&self, key: u8) -> Option<Command> {
+        self.bindings.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+            .map(|(_, command)| *command)
+    }
+
+    /// One-line summary of the bindings.
+    pub fn help(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for CommandTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = key_name(self.prefix);
+        write!(f, "{}", prefix)?;
+        for (key, command) in &self.bindings {
+            write!(f, "  {}: {}", key_name(*key), command.description())?;
+        }
+        write!(f, "  {}: send {}", prefix, prefix)
+    }
+}
+
+/// Byte of a key named like `ctrl-]`, `Ctrl+X`, `^X` or `esc`, as given to `--exit-key`.
+pub fn parse_key(name: &str) -> Result<u8, String> {
+    let lower = name.trim().to_ascii_lowercase();
+    if lower == "esc" { return Ok(0x1b); }
+    let key = ["ctrl-", "ctrl+", "^"].iter().find_map(|prefix| lower.strip_prefix(prefix));
+    match key.map(str::as_bytes) {
+        Some([c @ b'a'..=b'z']) => Ok(c - b'a' + 1),
+        Some([c @ b'['..=b'_']) => Ok(c - b'[' + 0x1b),
+        _ => Err(format!("unknown key {:?}, expected e.g. ctrl-] or ctrl-x", name)),
+    }
+}
+
+/// Human readable name of a key byte, `Ctrl-A` for control characters.
+pub fn key_name(key: u8) -> String {
+    match key {
+        0x01..=0x1a => format!("Ctrl-{}", (b'A' + key - 1) as char),
+        0x1b => "Esc".to_string(),
+        0x1c..=0x1f => format!("Ctrl-{}", (b'\\' + key - 0x1c) as char),
+        0x20..=0x7e => (key as char).to_string(),
+        _ => format!("{:#04x}", key),
+    }
+}
@@ -0,0 +1,250 @@
+//! `--profile`: named settings for each board on the desk, kept in a config file as
+//! `[profile.NAME]` sections of `key = value` lines, a subset of TOML:
+//!
+//! ```toml
+//! # ~/.config/rust-serial-tool/config.toml
+//! [profile.rpi4]
+//! port = "/dev/serial/by-id/usb-FTDI_FT232R-if00-port0"
+//! baud = 921600
+//! reset = "dtr"
+//! highlight = ["red:panic", "yellow:warn"]
+//!
+//! [profile.rock5]
+//! port = "CP210x"
+//! baud = 1500000
+//! ```
+//!
+//! Keys are the long flags without their dashes, `exit-key` or `exit_key`; `port` and `image`
+//! stand for the positional arguments. A binary skips keys it has no flag for, so one profile
+//! serves both.
+
+use std::{env, fmt, fs, io, path::{Path, PathBuf}};
+
+/// A setting's value. Strings, integers, floats, booleans and one-line arrays of them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The value as given on a command line, one string per element of an array.
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Value::Array(values) => values.iter().flat_map(Value::args).collect(),
+            value => vec![value.to_string()],
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => f.write_str(s),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(values) => f.write_str(&join(values.iter().map(Value::to_string))),
+        }
+    }
+}
+
+fn join(items: impl Iterator<Item = String>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+/// One `[profile.NAME]` section, its settings in file order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    settings: Vec<(String, Value)>,
+}
+
+impl Profile {
+    /// The value of `key`, written with dashes or underscores.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let key = key.replace('_', "-");
+        self.settings.iter().find(|(k, _)| *k == key).map(|(_, value)| value)
+    }
+
+    /// Keys, with dashes, and their values.
+    pub fn settings(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.settings.iter().map(|(key, value)| (key.as_str(), value))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    profiles: Vec<Profile>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rust-serial-tool/config.toml`, falling back to `~/.config` on unix and
+    /// `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("rust-serial-tool").join("config.toml"))
+    }
+
+    /// Reads the config file at `path`; a syntax error names the file and line.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Config::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}", path.display(), e)))
+    }
+
+    /// Parses the text of a config file; errors start with the line number, e.g. `3: ...`.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            config.parse_line(line).map_err(|e| format!("{}: {}", number + 1, e))?;
+        }
+        Ok(config)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut cursor = Cursor(line.trim_start());
+        if cursor.end() { return Ok(()); }
+        if cursor.eat('[') {
+            let section = cursor.key()?;
+            let name = match (section.as_str(), cursor.eat('.')) {
+                ("profile", true) => cursor.key()?,
+                _ => return Err(format!("unknown section {:?}, expected [profile.NAME]", section)),
+            };
+            if !cursor.eat(']') || !cursor.end() { return Err("expected ] after the profile name".to_string()); }
+            if self.profiles.iter().any(|profile| profile.name == name) { return Err(format!("profile {} is defined twice", name)); }
+            self.profiles.push(Profile { name, settings: Vec::new() });
+            return Ok(());
+        }
+
+        let key = cursor.key()?.replace('_', "-");
+        if !cursor.eat('=') { return Err(format!("expected = after {}", key)); }
+        let value = cursor.value()?;
+        if !cursor.end() { return Err(format!("unexpected {:?} after the value of {}", cursor.0, key)); }
+        let profile = self.profiles.last_mut().ok_or_else(|| format!("{} is outside a [profile.NAME] section", key))?;
+        if profile.get(&key).is_some() { return Err(format!("{} is set twice in profile {}", key, profile.name)); }
+        profile.settings.push((key, value));
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().map(|profile| profile.name.as_str())
+    }
+
+    /// The profile called `name`; an unknown name is told the ones there are.
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        match self.profiles.iter().find(|profile| profile.name == name) {
+            Some(profile) => Ok(profile),
+            None if self.profiles.is_empty() => Err(format!("no profile {}, the config file has none", name)),
+            None => Err(format!("no profile {}, expected {}", name, join(self.names().map(str::to_string)))),
+        }
+    }
+}
+
+/// What is left of a line, skipping spaces before each token.
+struct Cursor<'a>(&'a str);
+
+impl<'a> Cursor<'a> {
+    fn skip_space(&mut self) {
+        self.0 = self.0.trim_start();
+    }
+
+    /// Whether only spaces or a comment are left.
+    fn end(&mut self) -> bool {
+        self.skip_space();
+        self.0.is_empty() || self.0.starts_with('#')
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        match self.0.strip_prefix(c) {
+            Some(rest) => { self.0 = rest; true }
+            None => false,
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let end = self.0.find(|c| !f(c)).unwrap_or(self.0.len());
+        let (taken, rest) = self.0.split_at(end);
+        self.0 = rest;
+        taken
+    }
+
+    /// A bare key like `exit-key`, or a quoted one.
+    fn key(&mut self) -> Result<String, String> {
+        self.skip_space();
+        if self.0.starts_with(['"', '\'']) { return self.string(); }
+        match self.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            "" => Err(format!("expected a key, found {:?}", self.0)),
+            key => Ok(key.to_string()),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_space();
+        if self.0.starts_with(['"', '\'']) { return self.string().map(Value::String); }
+        if self.eat('[') {
+            let mut values = Vec::new();
+            loop {
+                if self.eat(']') { break; }
+                if self.end() { return Err("expected ] at the end of the array, on the same line".to_string()); }
+                values.push(self.value()?);
+                if self.eat(']') { break; }
+                if !self.eat(',') { return Err("expected , or ] in the array".to_string()); }
+            }
+            return Ok(Value::Array(values));
+        }
+        let word = self.take_while(|c| c.is_ascii_alphanumeric() || "+-._".contains(c));
+        let number = word.replace('_', "");
+        match word {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            "" if self.0.is_empty() || self.0.starts_with('#') => Err("expected a value".to_string()),
+            "" => Err(format!("invalid value {}, strings need quotes", self.0.trim_end())),
+            _ if number.starts_with("0x") => i64::from_str_radix(&number[2..], 16).map(Value::Integer).map_err(|_| format!("invalid number {}", word)),
+            _ => match number.parse::<i64>() {
+                Ok(n) => Ok(Value::Integer(n)),
+                Err(_) => match number.parse::<f64>() {
+                    Ok(x) if number.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') => Ok(Value::Float(x)),
+                    _ => Err(format!("invalid value {}, strings need quotes", word)),
+                },
+            },
+        }
+    }
+
+    /// A `"basic"` string with backslash escapes, or a `'literal'` one.
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.0.chars().next().unwrap();
+        let mut chars = self.0[1..].char_indices();
+        let mut s = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.0 = &self.0[1 + i + 1..];
+                    return Ok(s);
+                }
+                '\\' if quote == '"' => s.push(match chars.next().map(|(_, c)| c) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("invalid escape \\u{}", hex))?
+                    }
+                    other => return Err(format!("invalid escape \\{}", other.map_or(String::new(), String::from))),
+                }),
+                c => s.push(c),
+            }
+        }
+        Err("string without its closing quote".to_string())
+    }
+}
@@ -0,0 +1,192 @@
+//! `--control`: a unix socket through which scripts drive a running session without taking it
+//! over, e.g. to type a line, start a log or ask for the counters.
+//!
+//! Each client writes one JSON request per line and gets one JSON reply per line back.
+//! `{"cmd":"send","data":"reboot\n"}` types `data` into the target; any other `cmd` runs the
+//! local command of that name, see [`prompt`](crate::prompt), with `action`, `path` and `args`
+//! as its arguments: `{"cmd":"log","action":"start","path":"boot.log"}` is `log start boot.log`.
+//! Requests are answered by the terminal in the order they come in, clients served at once
+//! each wait their turn; outside the terminal, e.g. during a push, they wait for it.
+
+use std::{io::{self, BufRead, BufReader, Write}, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc, Mutex}, thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{lock, output::format_bytes, prompt::{Context, LocalCommands}};
+
+/// How often the listener looks up from waiting for clients, to notice the socket is closed.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// One line of a client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Request {
+    pub cmd: String,
+    /// What `send` types into the target, as it is.
+    pub data: Option<String>,
+    pub action: Option<String>,
+    pub path: Option<String>,
+    /// Anything else that goes on the command's line.
+    pub args: Option<String>,
+}
+
+impl Request {
+    /// The local command line the request stands for, e.g. `log start boot.log`.
+    pub fn line(&self) -> String {
+        let words = [Some(&self.cmd), self.action.as_ref(), self.path.as_ref(), self.args.as_ref()];
+        words.iter().flatten().map(|word| word.trim()).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The answer to a request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Reply {
+    pub ok: bool,
+    /// What the command said, as the terminal shows it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The counters, for `stats`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsReply>,
+}
+
+impl Reply {
+    pub fn failed(error: String) -> Self {
+        Reply { ok: false, error: Some(error), ..Reply::default() }
+    }
+}
+
+/// [`SessionSummary`](crate::stats::SessionSummary) for scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StatsReply {
+    pub received: u64,
+    pub sent: u64,
+    pub seconds: f64,
+    pub reconnects: u64,
+}
+
+/// Runs `request` like a line typed at the prompt, and says how it went.
+pub fn dispatch(commands: &mut LocalCommands, request: &Request, ctx: &mut Context<'_>) -> Reply {
+    let ran = match (request.cmd.as_str(), &request.data) {
+        ("send", Some(data)) => ctx.send(data.as_bytes())
+            .map(|_| ctx.say(format!("→ {} typed in over the control socket", format_bytes(data.len() as u64))))
+            .map_err(|e| format!("not sent: {:?}", e)),
+        _ => commands.run(&request.line(), ctx),
+    };
+    let output = std::mem::take(&mut ctx.said);
+    let stats = ctx.stats.filter(|_| request.cmd == "stats" && ran.is_ok()).map(|stats| {
+        let summary = stats.summary();
+        StatsReply { received: summary.received, sent: summary.sent, seconds: summary.duration.as_secs_f64(), reconnects: summary.reconnects }
+    });
+    match ran {
+        Ok(()) => Reply { ok: true, output, error: None, stats },
+        Err(e) => Reply { output, ..Reply::failed(e) },
+    }
+}
+
+/// A request waiting for the terminal to answer it.
+pub struct Pending {
+    pub request: Request,
+    reply: mpsc::Sender<Reply>,
+}
+
+impl Pending {
+    pub fn answer(self, reply: Reply) {
+        // the client may have hung up meanwhile
+        let _ = self.reply.send(reply);
+    }
+}
+
+/// The listening socket, bound for as long as it lives and removed when it is dropped or
+/// the process exits without unwinding.
+pub struct ControlSocket {
+    path: PathBuf,
+    requests: Mutex<mpsc::Receiver<Pending>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ControlSocket {
+    /// Listens on `path`. A socket left there by a process that no longer runs is replaced; one
+    /// still answering is not.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        use std::{fs::Permissions, os::unix::{fs::PermissionsExt, net::{UnixListener, UnixStream}}};
+
+        let listener = match UnixListener::bind(path) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => return Err(io::Error::new(e.kind(), "in use by another session")),
+            bound => bound?,
+        };
+        // whoever may connect may type into the target, so only the user, whatever the umask
+        std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        lock::remove_at_exit(path);
+        let (queue, requests) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let listening = closed.clone();
+        thread::spawn(move || while !listening.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((client, _)) => {
+                    let queue = queue.clone();
+                    thread::spawn(move || {
+                        // the client's lines are waited for, not polled
+                        if client.set_nonblocking(false).is_ok() { serve(&client, &client, &queue); }
+                    });
+                }
+                Err(_) => thread::sleep(ACCEPT_POLL),
+            }
+        });
+        Ok(Self { path: path.to_path_buf(), requests: Mutex::new(requests), closed })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_: &Path) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "a control socket needs unix domain sockets"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The requests that came in since last asked, oldest first.
+    pub fn pending(&self) -> Vec<Pending> {
+        self.requests.lock().unwrap().try_iter().collect()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        let _ = std::fs::remove_file(&self.path);
+        lock::keep_at_exit(&self.path);
+    }
+}
+
+/// Answers one client's lines until it hangs up or the session ends.
+fn serve<R: io::Read, W: Write>(client: R, mut replies: W, queue: &mpsc::Sender<Pending>) {
+    for line in BufReader::new(client).lines() {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, answered) = mpsc::channel();
+                if queue.send(Pending { request, reply }).is_err() { return; }
+                match answered.recv() {
+                    Ok(reply) => reply,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => Reply::failed(format!("not a request: {}", e)),
+        };
+        let mut json = serde_json::to_vec(&reply).unwrap_or_default();
+        json.push(b'\n');
+        if replies.write_all(&json).and_then(|_| replies.flush()).is_err() { return; }
+    }
+}
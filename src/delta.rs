@@ -0,0 +1,180 @@
+//! Delta pushes: a loader that keeps the last image in RAM across a warm reset gets only the
+//! blocks that changed since, e.g. a few hundred KiB of a 20 MiB kernel.
+//!
+//! After the size is taken, the host sends `DL | size: u64 LE | crc: u32 LE` describing the
+//! image it last pushed to this target, the CRC-32 covering all of it. The loader answers `OK`
+//! if that is what it holds and `NO` otherwise, and then takes the whole image as usual. After
+//! an `OK` come records `offset: u64 LE | len: u32 LE | data[len]` for every run of changed
+//! blocks, then `size: u64 LE | 0: u32 | crc: u32 LE`, the size and CRC-32 of the new image.
+//! The loader answers `OK` if what it now holds checks out and `NO` otherwise.
+
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ErrorKind, protocol::{self, REPLY_TIMEOUT, REPLY_WINDOW}, report, Result, sha256::Sha256, WRITE_TIMEOUT, WriteSerial};
+
+/// What the host sends after the size to offer a delta push.
+pub const DELTA_REQUEST: [u8; 2] = *b"DL";
+
+/// What is compared between two images, and the smallest part sent again.
+pub const DELTA_BLOCK: usize = 4096;
+
+/// What is remembered of an image: enough to tell which of its blocks the next one changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub size: u64,
+    pub block_size: usize,
+    /// CRC-32 of the whole image, what the loader checks.
+    pub crc: u32,
+    pub sha256: [u8; 32],
+    /// The start of the SHA-256 of each block, the last one possibly short.
+    pub blocks: Vec<u64>,
+}
+
+impl Manifest {
+    pub fn of(data: &[u8], block_size: usize) -> Self {
+        Manifest::read(data, block_size).unwrap()
+    }
+
+    /// Reads an image to its end for its manifest.
+    pub fn read<R: Read>(mut image: R, block_size: usize) -> io::Result<Self> {
+        let mut manifest = Manifest { block_size, crc: !0, ..Manifest::default() };
+        let mut whole = Sha256::default();
+        let mut block = vec![0; block_size];
+        loop {
+            let len = read_block(&mut image, &mut block)?;
+            if len == 0 { break; }
+            let block = &block[..len];
+            whole.update(block);
+            manifest.crc = crc32_update(manifest.crc, block);
+            let mut start = [0; 8];
+            start.copy_from_slice(&Sha256::digest(block)[..8]);
+            manifest.blocks.push(u64::from_le_bytes(start));
+            manifest.size += len as u64;
+        }
+        manifest.crc = !manifest.crc;
+        manifest.sha256 = whole.finish();
+        Ok(manifest)
+    }
+}
+
+/// Fills `block` but at the end of the image, and says how much it got.
+fn read_block<R: Read>(image: &mut R, block: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < block.len() {
+        match image.read(&mut block[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// The runs of `new` to send to a loader holding `old`, as `(offset, len)`: every block that
+/// differs or is new, adjacent ones merged. Images compared in blocks of different sizes
+/// differ throughout.
+pub fn changed(old: &Manifest, new: &Manifest) -> Vec<(u64, usize)> {
+    if old.block_size != new.block_size { return if new.size == 0 { Vec::new() } else { vec![(0, new.size as usize)] }; }
+    let mut runs: Vec<(u64, usize)> = Vec::new();
+    for (i, block) in new.blocks.iter().enumerate() {
+        if old.blocks.get(i) == Some(block) { continue; }
+        let offset = (i * new.block_size) as u64;
+        let len = (new.size - offset).min(new.block_size as u64) as usize;
+        match runs.last_mut() {
+            Some((start, run)) if *start + *run as u64 == offset && *run + len <= u32::MAX as usize => *run += len,
+            _ => runs.push((offset, len)),
+        }
+    }
+    runs
+}
+
+/// CRC-32 (ISO-HDLC, as zlib and Ethernet have it).
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _| if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+/// The offer of a delta against `old`, the image the loader is believed to hold.
+pub fn encode_offer(old: &Manifest) -> Vec<u8> {
+    DELTA_REQUEST.iter().chain(&old.size.to_le_bytes()).chain(&old.crc.to_le_bytes()).copied().collect()
+}
+
+/// What goes in front of the `len` bytes of a record at `offset`.
+pub fn encode_record(offset: u64, len: usize) -> Vec<u8> {
+    offset.to_le_bytes().iter().chain(&(len as u32).to_le_bytes()).copied().collect()
+}
+
+/// The record ending a delta push of `new`.
+pub fn encode_end(new: &Manifest) -> Vec<u8> {
+    let mut end = encode_record(new.size, 0);
+    end.extend_from_slice(&new.crc.to_le_bytes());
+    end
+}
+
+/// Offers a delta against `old` and returns whether the loader has that image to patch.
+pub fn offer<P: Read + Write + ?Sized>(port: &mut P, old: &Manifest) -> Result<bool> {
+    port.write_serial_all(&encode_offer(old), WRITE_TIMEOUT)?;
+    Ok(protocol::read_reply(port, &[b"OK", b"NO"], REPLY_WINDOW, REPLY_TIMEOUT)? == 0)
+}
+
+/// Ends a delta push of `new`: `ProtocolError` if what the loader holds now isn't it.
+pub fn finish<P: Read + Write + ?Sized>(port: &mut P, new: &Manifest) -> Result<()> {
+    port.write_serial_all(&encode_end(new), WRITE_TIMEOUT)?;
+    match protocol::read_reply(port, &[b"OK", b"NO"], REPLY_WINDOW, REPLY_TIMEOUT)? {
+        0 => Ok(()),
+        _ => Err(ErrorKind::ProtocolError),
+    }
+}
+
+/// The manifests of the images last pushed, one file per target.
+#[derive(Debug, Clone)]
+pub struct DeltaCache {
+    dir: PathBuf,
+}
+
+impl DeltaCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// `$XDG_CACHE_HOME/rust-serial-tool/delta`, falling back to `~/.cache` on unix and
+    /// `%LOCALAPPDATA%` on Windows.
+    pub fn default_dir() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None if cfg!(windows) => PathBuf::from(std::env::var_os("LOCALAPPDATA")?),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(dir.join("rust-serial-tool").join("delta"))
+    }
+
+    /// Where the manifest for `target` is kept: its name with anything but letters, digits,
+    /// `-` and `.` made `_`, e.g. `_dev_ttyUSB0.json`.
+    pub fn path(&self, target: &str) -> PathBuf {
+        let name: String = target.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' }).collect();
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// The manifest of what was last pushed to `target`; `None` also for one that can't be read.
+    pub fn load(&self, target: &str) -> Option<Manifest> {
+        serde_json::from_slice(&fs::read(self.path(target)).ok()?).ok()
+    }
+
+    pub fn store(&self, target: &str, manifest: &Manifest) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        report::write(manifest, &self.path(target))
+    }
+
+    /// Drops what is known about `target`, so the next push to it is a whole one.
+    pub fn forget(&self, target: &str) {
+        let _ = fs::remove_file(self.path(target));
+    }
+}
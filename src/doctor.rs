@@ -0,0 +1,335 @@
+//! `mini_term --doctor`: what usually keeps a port from working, checked one by one, each
+//! failure with what to do about it. Not in the dialout group, ModemManager probing a port
+//! that was just plugged in, brltty taking CP210x adapters for braille displays, the wrong COM
+//! number.
+//!
+//! Every check is a function of its own returning a [`Check`]; [`run`] goes through them in
+//! order, skipping those that need what an earlier one found missing.
+
+use std::{fmt, fs, io::{Read, Write}, path::{Path, PathBuf}, process, time::Duration};
+
+use serde::Serialize;
+
+use crate::{baud::Bridge, lock, SerialPort, selftest::{self, SelftestConfig}, settings::SerialSettings, transport::{self, PortListing, Presence, Target}};
+
+/// Bytes the `loopback` check sends.
+pub const LOOPBACK_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pass,
+    /// Works, but may not for long, e.g. a service that grabs ports now and then.
+    Warn,
+    Fail,
+    /// Not checked, e.g. the port can't be opened to check its baud rate.
+    Skip,
+}
+
+impl Status {
+    pub fn label(self) -> &'static str {
+        match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        }
+    }
+}
+
+/// The outcome of one check, e.g. `present`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found, e.g. `/dev/ttyUSB0 is held by brltty (PID 812)`.
+    pub detail: String,
+    /// What to do about a failure or warning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Pass, detail: detail.into(), hint: None }
+    }
+
+    pub fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Warn, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    pub fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Fail, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    pub fn skip(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Skip, detail: detail.into(), hint: None }
+    }
+}
+
+/// All checks of one port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub port: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether nothing failed; warnings pass.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != Status::Fail)
+    }
+
+    /// `--json`: one object with the port, whether it passed and the checks.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            port: &'a str,
+            passed: bool,
+            checks: &'a [Check],
+        }
+        serde_json::to_string(&Json { port: &self.port, passed: self.passed(), checks: &self.checks }).expect("reports always serialize")
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Checking {}", self.port)?;
+        for check in &self.checks {
+            writeln!(f, "  {}  {:<10} {}", check.status.label(), check.name, check.detail)?;
+            if let Some(hint) = &check.hint { writeln!(f, "{:19}→ {}", "", hint)?; }
+        }
+        let failed = self.checks.iter().filter(|check| check.status == Status::Fail).count();
+        match failed {
+            0 => write!(f, "All checks passed"),
+            1 => write!(f, "1 check failed"),
+            n => write!(f, "{} checks failed", n),
+        }
+    }
+}
+
+/// The port to check when none is given: the one USB adapter there is.
+pub fn detect(ports: &[PortListing]) -> Result<String, String> {
+    let usb: Vec<&PortListing> = ports.iter().filter(|port| port.usb_id.is_some()).collect();
+    match usb.as_slice() {
+        [port] => Ok(port.name.clone()),
+        [] => Err("no USB serial adapter found; is it plugged in? Name the port to check another".to_string()),
+        ports => Err(format!("several USB serial adapters, name the one to check: {}",
+                             ports.iter().map(|port| port.name.as_str()).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/// `present`: whether `name` is there and this user may open it, from how it was looked up.
+/// A missing one is told the ports there are, for a wrong COM number.
+pub fn present(name: &str, presence: &serialport::Result<Presence>, ports: &[PortListing]) -> Check {
+    let presence = match presence {
+        Ok(presence) => presence,
+        Err(e) => return Check::fail("present", format!("the ports can't be listed: {}", e), "check that the serial drivers are loaded"),
+    };
+    match presence {
+        Presence::Present(path) if path == name => Check::pass("present", format!("{} is there", path)),
+        Presence::Present(path) => Check::pass("present", format!("{} is {}", name, path)),
+        Presence::Missing if ports.is_empty() => Check::fail("present", format!("{} is not there, nor is any other port", name),
+                                                             "plug the adapter in; if it is, its driver may be missing (dmesg says)"),
+        Presence::Missing => Check::fail("present", format!("{} is not there", name),
+                                         format!("the ports there are: {}", ports.iter().map(describe).collect::<Vec<_>>().join(", "))),
+        Presence::PermissionDenied(hint) => Check::fail("present", format!("{} may not be opened by this user", name), hint.clone()),
+        Presence::Ambiguous(candidates) => Check::fail("present", format!("{} matches several ports", name),
+                                                       format!("name one of them: {}", candidates.join(", "))),
+    }
+}
+
+/// `COM3 (Silicon Labs CP210x)`, or the bare name of a port without a description.
+fn describe(port: &PortListing) -> String {
+    if port.description.is_empty() { port.name.clone() } else { format!("{} ({})", port.name, port.description) }
+}
+
+/// `exclusive`: whether a lock file in `dirs` says another program has `device`.
+pub fn exclusive(device: &Path, dirs: &[PathBuf]) -> Check {
+    let name = match lock::lock_name(device) {
+        Some(name) => name,
+        None => return Check::skip("exclusive", "no lock file name for it"),
+    };
+    for path in dirs.iter().map(|dir| dir.join(&name)) {
+        let pid = match fs::read_to_string(&path) {
+            Ok(contents) => lock::parse_pid(&contents).unwrap_or(0),
+            Err(_) => continue,
+        };
+        if pid > 0 && lock::is_alive(pid) {
+            return Check::fail("exclusive", format!("locked by PID {} ({})", pid, path.display()),
+                               format!("close the program with PID {}, e.g. another terminal", pid));
+        }
+        return Check::warn("exclusive", format!("stale lock file {} of PID {}", path.display(), pid),
+                           "the process is gone; --force takes the lock over");
+    }
+    Check::pass("exclusive", "no lock file")
+}
+
+/// A process, by its PID and name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// What to do about `name`, a program known to open serial ports on its own.
+pub fn interferer_hint(name: &str) -> Option<&'static str> {
+    match name {
+        "ModemManager" => Some("ModemManager probes new serial devices for half a minute after they show up; stop it \
+                                (sudo systemctl stop ModemManager) or have udev tell it to leave the adapter alone \
+                                with ENV{ID_MM_DEVICE_IGNORE}=\"1\""),
+        "brltty" => Some("brltty takes CP210x and CH340 adapters for braille displays; remove it (sudo apt remove \
+                          brltty) unless a braille display is used"),
+        "gpsd" => Some("gpsd opens ports it thinks are GPS receivers; stop it (sudo systemctl stop gpsd.socket gpsd)"),
+        _ => None,
+    }
+}
+
+/// The processes under `proc_root` (`/proc`) with `device` open, this one aside.
+pub fn holders(proc_root: &Path, device: &Path) -> Vec<Process> {
+    let device = fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+    processes(proc_root).into_iter()
+        .filter(|process| {
+            let fds = match fs::read_dir(proc_root.join(process.pid.to_string()).join("fd")) {
+                Ok(fds) => fds,
+                // someone else's, without root
+                Err(_) => return false,
+            };
+            fds.flatten().any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == device))
+        })
+        .collect()
+}
+
+/// The processes under `proc_root`, this one aside, by the name in their `comm`.
+pub fn processes(proc_root: &Path) -> Vec<Process> {
+    let entries = match fs::read_dir(proc_root) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut processes: Vec<Process> = entries.flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| pid != process::id())
+        .filter_map(|pid| {
+            let name = fs::read_to_string(proc_root.join(pid.to_string()).join("comm")).ok()?;
+            Some(Process { pid, name: name.trim_end().to_string() })
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+/// `holders`: whether another process has `device` open, from [`holders`]. One known to grab
+/// ports gets its own hint.
+pub fn holders_check(device: &str, holders: &[Process]) -> Check {
+    let holder = match holders.first() {
+        Some(holder) => holder,
+        None => return Check::pass("holders", "no other process has it open"),
+    };
+    let named: Vec<String> = holders.iter().map(|process| format!("{} (PID {})", process.name, process.pid)).collect();
+    let hint = holders.iter().find_map(|process| interferer_hint(&process.name)).map(str::to_string)
+        .unwrap_or_else(|| format!("close {}, or end it with kill {}", holder.name, holder.pid));
+    Check::fail("holders", format!("{} is held by {}", device, named.join(", ")), hint)
+}
+
+/// `services`: whether a program that grabs ports now and then runs, from [`processes`].
+pub fn services_check(running: &[Process]) -> Check {
+    match running.iter().find(|process| interferer_hint(&process.name).is_some()) {
+        Some(process) => Check::warn("services", format!("{} runs (PID {})", process.name, process.pid),
+                                     interferer_hint(&process.name).unwrap_or_default()),
+        None => Check::pass("services", "nothing known to grab serial ports runs"),
+    }
+}
+
+/// `open`: whether opening `name` worked, `error` being why not.
+pub fn open_check(name: &str, error: Option<&serialport::Error>) -> Check {
+    match error {
+        None => Check::pass("open", "opened"),
+        Some(e) => Check::fail("open", format!("opening failed: {}", e), transport::explain_open_error(name, e)),
+    }
+}
+
+/// `baud`: whether the driver takes `baud_rate`, and the adapter's `bridge` makes it closely
+/// enough.
+pub fn baud_check(port: &mut dyn serialport::SerialPort, baud_rate: u32, bridge: Option<Bridge>) -> Check {
+    if let Err(e) = port.set_baud_rate(baud_rate) {
+        return Check::fail("baud", format!("{} baud can't be set: {}", baud_rate, e), "try a standard rate like 115200");
+    }
+    match port.baud_rate() {
+        Ok(actual) if actual != baud_rate => return Check::fail("baud", format!("asked for {} baud, the driver set {}", baud_rate, actual),
+                                                               format!("try --baud {}", actual)),
+        _ => {}
+    }
+    match bridge.map(|bridge| (bridge, bridge.check(baud_rate))) {
+        Some((bridge, Err(nearest))) => Check::fail("baud", format!("a {} bridge makes {} baud of {}", bridge.name(), bridge.actual(baud_rate), baud_rate),
+                                                    format!("try --baud {}", nearest)),
+        Some((bridge, Ok(()))) => Check::pass("baud", format!("{} baud, which a {} bridge makes", baud_rate, bridge.name())),
+        None => Check::pass("baud", format!("{} baud", baud_rate)),
+    }
+}
+
+/// `loopback`: whether what is written comes back, TX being jumpered to RX.
+pub fn loopback_check<P: Read + Write + ?Sized>(port: &mut P, settings: SerialSettings) -> Check {
+    let config = SelftestConfig { settings, duration: Duration::from_secs(2), bytes: Some(LOOPBACK_BYTES), ..SelftestConfig::default() };
+    match selftest::run(port, &config, |_| {}) {
+        Ok(report) if report.passed() => Check::pass("loopback", format!("{} bytes came back", report.received)),
+        Ok(report) => Check::fail("loopback", report.to_string().replace('\n', "; "),
+                                  "bytes went missing or changed: check the baud rate, the cable and the ground"),
+        Err(e) => Check::fail("loopback", e.to_string(), "jumper TX to RX for this check, and turn flow control off"),
+    }
+}
+
+/// Checks `name` with `settings`; the loopback check only with `loopback`, it needs TX
+/// jumpered to RX.
+pub fn run(name: &str, settings: SerialSettings, loopback: bool) -> Report {
+    let mut checks = Vec::new();
+    let target = Target::parse(name);
+    let native = matches!(target, Target::Native(_));
+    let presence = target.presence();
+    let ports = transport::list_ports().unwrap_or_default();
+    checks.push(if native { present(name, &presence, &ports) } else {
+        match presence {
+            Ok(Presence::Present(_)) => Check::pass("present", format!("{} answers", name)),
+            _ => Check::fail("present", format!("{} does not answer", name), "check the address and that the terminal server runs"),
+        }
+    });
+
+    let path = match presence {
+        Ok(Presence::Present(path)) => path,
+        _ => return Report { port: name.to_string(), checks: skip_rest(checks, "the port isn't there", loopback) },
+    };
+    if native && cfg!(unix) {
+        checks.push(exclusive(Path::new(&path), &lock::lock_dirs()));
+    }
+    if native && cfg!(target_os = "linux") {
+        let proc_root = Path::new("/proc");
+        checks.push(holders_check(&path, &holders(proc_root, Path::new(&path))));
+        checks.push(services_check(&processes(proc_root)));
+    }
+
+    let mut port: SerialPort = match target.open(&settings, Duration::from_millis(10)) {
+        Ok(port) => {
+            checks.push(open_check(name, None));
+            port
+        }
+        Err(e) => {
+            checks.push(open_check(name, Some(&e)));
+            return Report { port: name.to_string(), checks: skip_rest(checks, "the port can't be opened", loopback) };
+        }
+    };
+    if target.fixed_settings() {
+        checks.push(Check::skip("baud", "the terminal server sets the line"));
+    } else {
+        let bridge = transport::usb_id(name).and_then(|(vid, pid)| Bridge::from_usb(vid, pid));
+        checks.push(baud_check(port.as_mut(), settings.baud_rate, bridge));
+    }
+    checks.push(if loopback { loopback_check(port.as_mut(), settings) } else { Check::skip("loopback", "not asked for, see --doctor-loopback") });
+    Report { port: name.to_string(), checks }
+}
+
+/// `checks` with the ones that need an open port skipped for `reason`.
+fn skip_rest(mut checks: Vec<Check>, reason: &str, loopback: bool) -> Vec<Check> {
+    if !checks.iter().any(|check| check.name == "open") { checks.push(Check::skip("open", reason)); }
+    checks.push(Check::skip("baud", reason));
+    checks.push(Check::skip("loopback", if loopback { reason } else { "not asked for, see --doctor-loopback" }));
+    checks
+}
@@ -0,0 +1,67 @@
+//! What the loader prints while the image is still going out, e.g. its diagnostics, shown
+//! without tearing up the progress bar: `--early-output hold` keeps it until the push is
+//! over, `live` prints it above the bar a whole line at a time.
+
+use std::{fmt, str::FromStr, time::Instant};
+
+use crate::terminal::{LineBuffer, LINE_IDLE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EarlyOutput {
+    /// All of it once the bar is done, in order.
+    #[default]
+    Hold,
+    /// Each line as it completes, the bar drawn again below it.
+    Live,
+}
+
+impl FromStr for EarlyOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "hold" => Ok(EarlyOutput::Hold),
+            "live" => Ok(EarlyOutput::Live),
+            _ => Err("expected hold or live".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for EarlyOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { EarlyOutput::Hold => "hold", EarlyOutput::Live => "live" })
+    }
+}
+
+/// Decides when what came during a push is shown; each byte comes out once.
+#[derive(Debug, Clone)]
+pub struct EarlyBuffer {
+    mode: EarlyOutput,
+    held: Vec<u8>,
+    lines: LineBuffer,
+}
+
+impl EarlyBuffer {
+    pub fn new(mode: EarlyOutput) -> Self {
+        Self { mode, held: Vec::new(), lines: LineBuffer::new(LINE_IDLE) }
+    }
+
+    /// What to show now that `data` arrived at `now`: nothing while holding, otherwise the
+    /// lines it completed. A partial line stays, the bar would draw over it.
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Vec<u8> {
+        match self.mode {
+            EarlyOutput::Hold => {
+                self.held.extend_from_slice(data);
+                Vec::new()
+            }
+            EarlyOutput::Live => self.lines.push(data, now).concat(),
+        }
+    }
+
+    /// The rest, once the bar is gone.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut rest = std::mem::take(&mut self.held);
+        if let Some(partial) = self.lines.flush() { rest.extend(partial); }
+        rest
+    }
+}
@@ -0,0 +1,85 @@
+//! Machine-readable lifecycle events, one JSON object per line, for driving the tools from scripts.
+
+use std::{io::{self, Write}, str::FromStr, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected human or json".to_string()),
+        }
+    }
+}
+
+/// Serialized with an `event` tag, e.g. `{"event":"size_sent","bytes":6144}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    WaitingForSerial { port: String },
+    Connected { port: String, settings: String },
+    /// The target asked for the image.
+    HandshakeOk,
+    /// `--auto-baud` moved the line from `from` to `to` baud, up to try a rate or back after one failed.
+    BaudSwitched { from: u32, to: u32 },
+    /// The target acknowledged the image size.
+    SizeSent { bytes: u64 },
+    /// The loader agreed to continue an interrupted push at `offset`.
+    PushResumed { offset: u64, total: u64 },
+    /// The loader took a delta push: `changed` bytes of the `total` go out.
+    PushDelta { changed: u64, total: u64 },
+    PushProgress { sent: u64, total: u64, percent: u8 },
+    /// `sha256` of the whole image, in hex.
+    PushComplete { bytes: u64, seconds: f64, sha256: String },
+    Error { kind: String, phase: String, message: String },
+    Reconnect,
+    /// Totals of the whole run, sent just before `exit`.
+    SessionSummary { received: u64, sent: u64, seconds: f64, reconnects: u64, pushed: u64, push_seconds: f64 },
+    Exit { success: bool },
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    /// Milliseconds since the Unix epoch.
+    ts_ms: u64,
+    tool: &'a str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// One JSON line for `event`, stamped with the time and the tool's short name.
+pub fn to_line(tool: &str, event: &Event) -> String {
+    let ts_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    serde_json::to_string(&Record { ts_ms, tool, event }).expect("events always serialize")
+}
+
+/// Where events go; stderr by default so stdout stays the interactive stream.
+pub struct EventLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventLog {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self { out: Mutex::new(out) }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(io::stderr()))
+    }
+
+    pub fn emit(&self, tool: &str, event: &Event) {
+        let mut out = self.out.lock().unwrap();
+        // a broken log pipe must not take the session down with it
+        let _ = writeln!(out, "{}", to_line(tool, event)).and_then(|_| out.flush());
+    }
+}
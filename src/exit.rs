@@ -0,0 +1,46 @@
+//! What `mini_push --script` exits with, so a test harness can tell failures apart without
+//! reading stderr. The codes are part of its interface and stay as they are:
+//!
+//! | code | meaning                                                          |
+//! |------|------------------------------------------------------------------|
+//! | 0    | pushed, and whatever was asked of the boot after it held         |
+//! | 1    | any other failure, e.g. `--expect-boot` not seeing its pattern   |
+//! | 2    | the loader didn't ask for the image within `--handshake-timeout` |
+//! | 3    | the loader asked, but the exchange or the push then went wrong   |
+//! | 4    | the port isn't there, went away, or someone else holds it        |
+//! | 5    | the image can't be read, is too large or isn't the expected one  |
+//! | 130  | Ctrl-C or SIGTERM, as everywhere else                            |
+
+use crate::{ErrorKind, Result, signal};
+
+pub const SUCCESS: i32 = 0;
+pub const FAILURE: i32 = 1;
+pub const HANDSHAKE_TIMEOUT: i32 = 2;
+pub const PROTOCOL_ERROR: i32 = 3;
+pub const DEVICE_MISSING: i32 = 4;
+pub const IMAGE_ERROR: i32 = 5;
+
+/// The code for how a push went, from the error and the phase it came in.
+pub fn script_code(result: &Result<()>) -> i32 {
+    let error = match result {
+        Ok(()) => return SUCCESS,
+        Err(error) => error,
+    };
+    let timeout = matches!(error.kind(), ErrorKind::TimeoutError | ErrorKind::ReadTimeout { .. } | ErrorKind::WriteTimeout { .. });
+    match (error.kind(), error.phase()) {
+        (ErrorKind::Interrupted, _) => signal::INTERRUPTED_EXIT_CODE,
+        // there, only not at that rate
+        (ErrorKind::UnsupportedBaud { .. }, _) => FAILURE,
+        // there, only another one
+        (ErrorKind::DeviceChanged { .. }, _) => FAILURE,
+        (ErrorKind::ConnectionError | ErrorKind::PortLocked { .. }, _) | (_, Some("open")) => DEVICE_MISSING,
+        (ErrorKind::ImageTooLarge(_) | ErrorKind::ImageUnusable { .. } | ErrorKind::ImageHuge { .. } | ErrorKind::ImageMismatch { .. } |
+         ErrorKind::FormatError(_) | ErrorKind::NetworkError(_), _) |
+        (_, Some("load")) => IMAGE_ERROR,
+        (_, Some("handshake")) if timeout => HANDSHAKE_TIMEOUT,
+        (_, Some("identify" | "negotiate" | "baud" | "size" | "push")) if timeout => PROTOCOL_ERROR,
+        (ErrorKind::ProtocolError | ErrorKind::UnexpectedReply { .. } | ErrorKind::TargetRebooted { .. } | ErrorKind::TransferError(_), _) =>
+            PROTOCOL_ERROR,
+        _ => FAILURE,
+    }
+}
@@ -0,0 +1,61 @@
+//! Capturing what the target prints until a pattern shows up or a deadline passes, for
+//! bring-up checks like "boots to `login:` within 30 seconds, without a `Kernel panic`".
+
+use std::{io::Read, ops::Range, time::{Duration, Instant}};
+
+use crate::{ErrorKind, pattern::{MAX_PENDING, Pattern}, ReadSerial, Result, signal};
+
+/// Everything an [`expect`] read, and where in it the pattern matched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// All the bytes received, including any that came in the same read after the match.
+    pub captured: Vec<u8>,
+    /// Byte range of the match in `captured`, `None` when the deadline passed first.
+    pub found: Option<Range<usize>>,
+    pub elapsed: Duration,
+}
+
+impl Transcript {
+    pub fn matched(&self) -> Option<&[u8]> {
+        self.found.clone().map(|range| &self.captured[range])
+    }
+
+    /// Where the match starts in `captured`.
+    pub fn offset(&self) -> Option<usize> {
+        self.found.as_ref().map(|range| range.start)
+    }
+
+    /// What was received up to the end of the match, or all of it without one.
+    pub fn until_match(&self) -> &[u8] {
+        &self.captured[..self.found.as_ref().map_or(self.captured.len(), |range| range.end)]
+    }
+}
+
+/// Reads `port` until `pattern` matches or `deadline` passes, handing each read to `echo`.
+/// A match may span reads, and may begin up to [`MAX_PENDING`] bytes before the last one.
+/// Not finding it is no error: the transcript says so, with everything received meanwhile.
+pub fn expect<P, F>(port: &mut P, pattern: &Pattern, deadline: Duration, mut echo: F) -> Result<Transcript>
+    where P: Read + ?Sized, F: FnMut(&[u8]) {
+    let started = Instant::now();
+    let mut transcript = Transcript::default();
+    let mut received = [0; 1024];
+    // an empty pattern matches before anything is read
+    if let Some((start, end)) = pattern.find(&[]) {
+        transcript.found = Some(start..end);
+        return Ok(transcript);
+    }
+    while started.elapsed() < deadline {
+        if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+        let n = port.read_serial(&mut received)?;
+        if n == 0 { continue; }
+        echo(&received[..n]);
+        let from = transcript.captured.len().saturating_sub(MAX_PENDING);
+        transcript.captured.extend_from_slice(&received[..n]);
+        if let Some((start, end)) = pattern.find_at(&transcript.captured, from) {
+            transcript.found = Some(start..end);
+            break;
+        }
+    }
+    transcript.elapsed = started.elapsed();
+    Ok(transcript)
+}
@@ -0,0 +1,158 @@
+//! Images downloaded over HTTP or HTTPS before a push, behind the `http` feature.
+
+use std::{fmt, io::{self, BufWriter, Read, Write}, path::Path, str::FromStr, time::Duration};
+
+use tempfile::NamedTempFile;
+use ureq::Agent;
+
+use crate::{ErrorKind, Result, sha256::{self, Sha256}};
+
+/// Redirects followed before giving up on a URL.
+pub const MAX_REDIRECTS: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the server may take to start answering; the body itself may take as long as it takes.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    /// With the query, without the fragment; `/` at least.
+    pub path: String,
+}
+
+impl Url {
+    /// The last path segment, e.g. `kernel8.img`, so the download keeps its extension.
+    pub fn file_name(&self) -> &str {
+        let path = self.path.split('?').next().unwrap_or("");
+        path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("image")
+    }
+
+    fn default_port(https: bool) -> u16 {
+        if https { 443 } else { 80 }
+    }
+
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == Url::default_port(self.https) { host } else { format!("{}:{}", host, self.port) }
+    }
+}
+
+impl FromStr for Url {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let (https, rest) = match s.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+            _ => return Err("expected an http:// or https:// URL".to_string()),
+        };
+        let rest = rest.split('#').next().unwrap_or("");
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let path = if path.starts_with('?') { format!("/{}", path) } else { path };
+        // credentials aren't sent, but mustn't be taken for the host either
+        let authority = authority.rsplit('@').next().unwrap_or("");
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => match v6.split_once(']') {
+                Some((host, port)) => (host, port.strip_prefix(':')),
+                None => return Err(format!("unclosed [ in {}", s)),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() { return Err(format!("no host in {}", s)); }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| format!("bad port {:?} in {}", port, s))?,
+            None => Url::default_port(https),
+        };
+        Ok(Url { https, host: host.to_string(), port, path })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", if self.https { "https" } else { "http" }, self.authority(), self.path)
+    }
+}
+
+fn network<D: fmt::Display>(url: &Url, message: D) -> ErrorKind {
+    ErrorKind::NetworkError(format!("{}: {}", url, message))
+}
+
+fn agent() -> Agent {
+    Agent::config_builder()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+        .max_redirects(MAX_REDIRECTS)
+        .user_agent("mini_push")
+        .build()
+        .into()
+}
+
+/// Writes the body at `url` into `dest`, following redirects, and returns its SHA-256.
+/// `progress` hears the bytes so far and the total, if the server tells it.
+pub fn download<W: Write>(url: &Url, dest: &mut W, mut progress: impl FnMut(u64, Option<u64>)) -> Result<[u8; 32]> {
+    let response = agent().get(&url.to_string()).call().map_err(|e| match e {
+        ureq::Error::StatusCode(status) => network(url, format!("HTTP {}", status)),
+        e => network(url, e),
+    })?;
+    // without a length the body ends with the connection, or with its last chunk
+    let length = response.body().content_length();
+    let mut body = response.into_body().into_reader();
+
+    let mut hasher = Sha256::default();
+    let mut buf = vec![0; 64 * 1024];
+    let mut received = 0;
+    progress(0, length);
+    loop {
+        let n = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(network(url, format!("{} after {} bytes", e, received))),
+        };
+        dest.write_all(&buf[..n])?;
+        hasher.update(&buf[..n]);
+        received += n as u64;
+        progress(received, length);
+    }
+    if let Some(length) = length.filter(|&length| received < length) {
+        return Err(network(url, format!("the connection closed after {} of {} bytes", received, length)));
+    }
+    dest.flush()?;
+    Ok(hasher.finish())
+}
+
+/// A downloaded image in the temp directory, removed when dropped.
+#[derive(Debug)]
+pub struct TempImage {
+    file: NamedTempFile,
+    pub size: u64,
+}
+
+impl TempImage {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Downloads `url` to a temp file, checked against `sha256` if given. The file gets a name
+/// of its own, so nothing already in the temp directory, e.g. a planted symlink, is written through.
+pub fn fetch(url: &Url, sha256: Option<[u8; 32]>, progress: impl FnMut(u64, Option<u64>)) -> Result<TempImage> {
+    let file = tempfile::Builder::new().prefix("mini_push-").suffix(&format!("-{}", url.file_name())).tempfile()?;
+    let mut writer = BufWriter::new(file.as_file());
+    let digest = download(url, &mut writer, progress)?;
+    drop(writer);
+    if let Some(expected) = sha256.filter(|&expected| expected != digest) {
+        return Err(network(url, format!("SHA-256 {} does not match the expected {}", sha256::hex(&digest), sha256::hex(&expected))));
+    }
+    let size = file.as_file().metadata()?.len();
+    Ok(TempImage { file, size })
+}
@@ -0,0 +1,264 @@
+//! Filters between the received stream and where it goes, the display or the `--log` file,
+//! each with a chain of its own: `--display-filter`, `--log-filter`.
+//!
+//! [`CollapseOverwrites`] does to a line what a terminal's carriage return and backspace do to
+//! it, so a counter redrawn in place a thousand times is logged as the line it ends up as while
+//! the display still shows it counting. [`StripEscapes`] leaves out the colors and other escape
+//! sequences an editor would show as clutter.
+
+use std::{fmt, mem, str::FromStr};
+
+use crate::{ansi::{MAX_SEQUENCE, Sanitizer}, terminal::{MAX_LINE, utf8_complete}};
+
+/// One stage of a [`FilterChain`].
+pub trait OutputFilter: Send {
+    /// Appends to `out` what becomes of `input`; what may still change, e.g. a line that
+    /// hasn't ended, is held back for the next call.
+    fn filter(&mut self, input: &[u8], out: &mut Vec<u8>);
+
+    /// Appends what is still held back, the stream having ended.
+    fn finish(&mut self, _out: &mut Vec<u8>) {}
+}
+
+/// The built-in filters, as named on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// [`CollapseOverwrites`].
+    CollapseOverwrites,
+    /// [`StripEscapes`].
+    StripEscapes,
+}
+
+impl FilterKind {
+    pub fn build(self) -> Box<dyn OutputFilter> {
+        match self {
+            FilterKind::CollapseOverwrites => Box::new(CollapseOverwrites::default()),
+            FilterKind::StripEscapes => Box::new(StripEscapes::default()),
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "collapse-overwrites" | "collapse" => Ok(FilterKind::CollapseOverwrites),
+            "strip-escapes" => Ok(FilterKind::StripEscapes),
+            _ => Err("expected collapse-overwrites or strip-escapes".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FilterKind::CollapseOverwrites => "collapse-overwrites",
+            FilterKind::StripEscapes => "strip-escapes",
+        })
+    }
+}
+
+/// Filters applied one after the other; none passes everything through as it is.
+#[derive(Default)]
+pub struct FilterChain(Vec<Box<dyn OutputFilter>>);
+
+impl FilterChain {
+    pub fn new(kinds: &[FilterKind]) -> Self {
+        Self(kinds.iter().map(|kind| kind.build()).collect())
+    }
+
+    pub fn push(&mut self, filter: Box<dyn OutputFilter>) {
+        self.0.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `input` through every filter in turn.
+    pub fn apply(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len());
+        self.filter(input, &mut out);
+        out
+    }
+
+    /// What the filters still hold back, each one's through the ones after it.
+    pub fn drain(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.finish(&mut out);
+        out
+    }
+}
+
+impl fmt::Debug for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FilterChain({} filters)", self.0.len())
+    }
+}
+
+impl OutputFilter for FilterChain {
+    fn filter(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        let mut data = input.to_vec();
+        for filter in &mut self.0 {
+            let mut next = Vec::with_capacity(data.len());
+            filter.filter(&data, &mut next);
+            data = next;
+        }
+        out.extend_from_slice(&data);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        for filter in &mut self.0 {
+            let mut next = Vec::with_capacity(data.len());
+            filter.filter(&data, &mut next);
+            filter.finish(&mut next);
+            data = next;
+        }
+        out.extend_from_slice(&data);
+    }
+}
+
+/// Each line as a terminal would leave it: `\r` goes back to its start and `\b` back a
+/// character, what follows overwriting what was there; `ESC [ K` erases to its end. A line
+/// comes out once it ends, with its `\n` or `\r\n`, or once it is [`MAX_LINE`] long.
+///
+/// A character takes a cell, whatever its UTF-8 length, and so does any other escape
+/// sequence, e.g. a color, which an overwrite thus replaces like a character.
+#[derive(Debug, Default)]
+pub struct CollapseOverwrites {
+    cells: Vec<Vec<u8>>,
+    cursor: usize,
+    /// Continuation bytes the character in the cell before the cursor still has coming.
+    continuing: usize,
+    /// A `\r` that is a line ending if `\n` follows.
+    cr: bool,
+    /// An escape sequence begun.
+    sequence: Vec<u8>,
+}
+
+impl CollapseOverwrites {
+    fn put(&mut self, cell: Vec<u8>) {
+        match self.cells.get_mut(self.cursor) {
+            Some(old) => *old = cell,
+            None => self.cells.push(cell),
+        }
+        self.cursor += 1;
+    }
+
+    fn end_line(&mut self, ending: &[u8], out: &mut Vec<u8>) {
+        self.cells.iter().for_each(|cell| out.extend_from_slice(cell));
+        out.extend_from_slice(ending);
+        self.cells.clear();
+        self.cursor = 0;
+        self.continuing = 0;
+    }
+
+    /// Takes `b` into the escape sequence begun; true once that is complete.
+    fn sequence_byte(&mut self, b: u8) -> bool {
+        self.sequence.push(b);
+        let csi = self.sequence.get(1) == Some(&b'[');
+        match self.sequence.len() {
+            2 => !csi,
+            n => (csi && (0x40..=0x7e).contains(&b)) || n > MAX_SEQUENCE,
+        }
+    }
+
+    fn end_sequence(&mut self) {
+        let sequence = mem::take(&mut self.sequence);
+        match sequence.as_slice() {
+            b"\x1b[K" | b"\x1b[0K" => self.cells.truncate(self.cursor),
+            b"\x1b[2K" => {
+                self.cells.truncate(self.cursor);
+                self.cells.iter_mut().for_each(|cell| *cell = vec![b' ']);
+            }
+            _ => self.put(sequence),
+        }
+    }
+}
+
+impl OutputFilter for CollapseOverwrites {
+    fn filter(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &b in input {
+            if !self.sequence.is_empty() {
+                // a control breaks the sequence off, and is taken as it comes
+                if b >= 0x20 {
+                    if self.sequence_byte(b) { self.end_sequence(); }
+                    continue;
+                }
+                self.end_sequence();
+            }
+            if mem::take(&mut self.cr) {
+                if b == b'\n' {
+                    self.end_line(b"\r\n", out);
+                    continue;
+                }
+                self.cursor = 0;
+            }
+            match b {
+                b'\r' => {
+                    self.cr = true;
+                    self.continuing = 0;
+                }
+                b'\n' => self.end_line(b"\n", out),
+                0x08 => {
+                    self.cursor = self.cursor.saturating_sub(1);
+                    self.continuing = 0;
+                }
+                0x1b => {
+                    self.sequence.push(b);
+                    self.continuing = 0;
+                }
+                0x80..=0xbf if self.continuing > 0 => {
+                    self.cells[self.cursor - 1].push(b);
+                    self.continuing -= 1;
+                }
+                b => {
+                    self.put(vec![b]);
+                    self.continuing = match b { 0xc0..=0xdf => 1, 0xe0..=0xef => 2, 0xf0..=0xf7 => 3, _ => 0 };
+                }
+            }
+            if self.cells.len() >= MAX_LINE { self.end_line(b"", out); }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.sequence.is_empty() { self.end_sequence(); }
+        // a `\r` at the very end moved the cursor, and changed nothing
+        self.cr = false;
+        self.end_line(b"", out);
+    }
+}
+
+/// The text without escape sequences, colors included, the way [`Sanitizer::stripping`] shows
+/// it on a console that can't: controls other than line endings, tab, bell and backspace are
+/// written out as `^X`.
+#[derive(Debug)]
+pub struct StripEscapes {
+    sanitizer: Sanitizer,
+    /// The start of a character the next read completes.
+    partial: Vec<u8>,
+}
+
+impl Default for StripEscapes {
+    fn default() -> Self {
+        Self { sanitizer: Sanitizer::stripping(), partial: Vec::new() }
+    }
+}
+
+impl OutputFilter for StripEscapes {
+    fn filter(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.partial.extend_from_slice(input);
+        let complete = utf8_complete(&self.partial);
+        let text = self.sanitizer.sanitize(&String::from_utf8_lossy(&self.partial[..complete]));
+        out.extend_from_slice(text.as_bytes());
+        self.partial.drain(..complete);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        let partial = mem::take(&mut self.partial);
+        out.extend_from_slice(self.sanitizer.sanitize(&String::from_utf8_lossy(&partial)).as_bytes());
+        out.extend_from_slice(self.sanitizer.finish().as_bytes());
+    }
+}
@@ -0,0 +1,137 @@
+//! Pushing one image to several identical boards at once, e.g. a test rack on
+//! `/dev/ttyUSB0`–`5`: each on its own thread, one failing leaves the others alone.
+
+use std::{fs, path::Path, thread, time::{Duration, Instant}};
+
+use crate::{ErrorKind, output::format_duration};
+
+/// Boards named by `spec`: a comma-separated list of devices, any of which may be a glob like
+/// `/dev/ttyUSB*` in its last component. Globs are expanded in sorted order, duplicates dropped.
+pub fn expand(spec: &str) -> Result<Vec<String>, String> {
+    let mut boards: Vec<String> = Vec::new();
+    for item in spec.split(',').map(str::trim) {
+        if item.is_empty() { return Err(format!("empty device in {:?}", spec)); }
+        let matched = if is_glob(item) { glob(item)? } else { vec![item.to_string()] };
+        for board in matched {
+            if !boards.contains(&board) { boards.push(board); }
+        }
+    }
+    Ok(boards)
+}
+
+fn is_glob(item: &str) -> bool {
+    item.contains(['*', '?'])
+}
+
+fn glob(pattern: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(pattern);
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return Err(format!("{}: not a device path", pattern)),
+    };
+    if is_glob(&dir.to_string_lossy()) { return Err(format!("{}: only the last component may have wildcards", pattern)); }
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let mut found: Vec<String> = fs::read_dir(listed).map_err(|e| format!("{}: {}", listed.display(), e))?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|entry| wildcard(name, entry))
+        .map(|entry| dir.join(entry).to_string_lossy().into_owned())
+        .collect();
+    if found.is_empty() { return Err(format!("no device matches {}", pattern)); }
+    found.sort();
+    Ok(found)
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters and `?` for one.
+pub fn wildcard(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was, and how much of the name it took so far
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((at, taken)) => {
+                    p = at + 1;
+                    n = taken + 1;
+                    star = Some((at, taken + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// What a board is called in its messages: the last component of its device, e.g. `ttyUSB0`.
+pub fn board_name(device: &str) -> &str {
+    device.rsplit(['/', '\\']).find(|part| !part.is_empty()).unwrap_or(device)
+}
+
+/// How the push to one board went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardResult {
+    pub device: String,
+    /// Why it failed, `None` when it succeeded.
+    pub error: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl BoardResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Runs `push` for every board at once, each on its own thread, and waits for all of them.
+/// The results are in the order of `devices`; a push that panics counts as failed.
+pub fn push_all<F>(devices: &[String], push: F) -> Vec<BoardResult>
+    where F: Fn(&str) -> crate::Result<()> + Sync {
+    let started = Instant::now();
+    thread::scope(|scope| {
+        let pushes: Vec<_> = devices.iter().map(|device| {
+            let push = &push;
+            scope.spawn(move || {
+                let started = Instant::now();
+                (push(device), started.elapsed())
+            })
+        }).collect();
+        devices.iter().zip(pushes).map(|(device, push)| {
+            let (error, elapsed) = match push.join() {
+                Ok((result, elapsed)) => (result.err().map(|e| describe(&e)), elapsed),
+                Err(_) => (Some("panicked".to_string()), started.elapsed()),
+            };
+            BoardResult { device: device.clone(), error, elapsed }
+        }).collect()
+    })
+}
+
+/// An error without the port, which the summary already names.
+fn describe(error: &ErrorKind) -> String {
+    match error.phase() {
+        Some(phase) => format!("{} during {}", error.kind(), phase),
+        None => error.to_string(),
+    }
+}
+
+/// The summary table of `results`, one line per board under a heading, e.g.
+/// `/dev/ttyUSB1  failed  10.0s  TimeoutError during handshake`.
+pub fn summary(results: &[BoardResult]) -> Vec<String> {
+    let width = results.iter().map(|result| result.device.len()).chain(Some("board".len())).max().unwrap_or(0);
+    let mut lines = vec![format!("{:<width$}  {:<6}  {:>7}", "board", "result", "time", width = width)];
+    for result in results {
+        let (outcome, error) = match &result.error {
+            None => ("ok", String::new()),
+            Some(error) => ("failed", format!("  {}", error)),
+        };
+        lines.push(format!("{:<width$}  {:<6}  {:>7}{}", result.device, outcome, format_duration(result.elapsed), error, width = width));
+    }
+    lines
+}
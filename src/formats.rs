@@ -0,0 +1,180 @@
+use std::{fmt, path::Path};
+
+/// Largest flat image a HEX/SREC file may expand to, guards against records at wildly
+/// distant addresses turning into a multi-GiB allocation.
+pub const MAX_FLAT_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    Srec,
+}
+
+impl Format {
+    /// Picks the format from the file extension, falling back to sniffing the first line.
+    pub fn detect(path: &Path, head: &[u8]) -> Format {
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("hex") | Some("ihex") | Some("ihx") => Format::IntelHex,
+            Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => Format::Srec,
+            _ => Format::sniff(head),
+        }
+    }
+
+    pub fn sniff(head: &[u8]) -> Format {
+        let line = head.split(|&c| c == b'\n').next().unwrap_or(&[]);
+        let line = trim(line);
+        let hex_digits = |s: &[u8]| !s.is_empty() && s.iter().all(u8::is_ascii_hexdigit);
+
+        match line {
+            [b':', rest @ ..] if rest.len() >= 10 && hex_digits(rest) => Format::IntelHex,
+            [b'S', t, rest @ ..] if t.is_ascii_digit() && rest.len() >= 8 && hex_digits(rest) => Format::Srec,
+            _ => Format::Binary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl FormatError {
+    fn new(line: usize, reason: impl Into<String>) -> Self {
+        Self { line, reason: reason.into() }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Parses an Intel HEX file into a flat image starting at the lowest record address.
+pub fn parse_ihex(text: &str, fill: u8) -> Result<Vec<u8>, FormatError> {
+    let mut segments = Vec::new();
+    let mut base = 0u64;
+
+    for (idx, raw) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = raw.trim();
+        if line.is_empty() { continue; }
+
+        let body = line.strip_prefix(':')
+            .ok_or_else(|| FormatError::new(line_no, "record does not start with ':'"))?;
+        let bytes = decode_hex(body, line_no)?;
+        if bytes.len() < 5 {
+            return Err(FormatError::new(line_no, "record too short"));
+        }
+
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(FormatError::new(line_no, format!("length field says {} data bytes, record has {}", len, bytes.len() - 5)));
+        }
+        let sum = bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        if sum != 0 {
+            let expected = bytes[..bytes.len() - 1].iter().fold(0u8, |acc, &b| acc.wrapping_add(b)).wrapping_neg();
+            return Err(FormatError::new(line_no, format!("checksum mismatch: expected {:02X}, found {:02X}", expected, bytes[bytes.len() - 1])));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+        let data = &bytes[4..4 + len];
+        match bytes[3] {
+            0x00 => segments.push((base + offset, data.to_vec())),
+            0x01 => break,
+            0x02 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 4,
+            0x04 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 16,
+            t @ (0x02 | 0x04) => return Err(FormatError::new(line_no, format!("record type {:02X} carries {} data bytes, not an address's 2", t, len))),
+            0x03 | 0x05 => {}
+            t => return Err(FormatError::new(line_no, format!("unsupported record type {:02X}", t))),
+        }
+    }
+
+    flatten(segments, fill)
+}
+
+/// Parses a Motorola S-record file into a flat image starting at the lowest record address.
+pub fn parse_srec(text: &str, fill: u8) -> Result<Vec<u8>, FormatError> {
+    let mut segments = Vec::new();
+
+    for (idx, raw) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = raw.trim();
+        if line.is_empty() { continue; }
+
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(FormatError::new(line_no, "record does not start with 'S'"));
+        }
+        let kind = chars.next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or_else(|| FormatError::new(line_no, "missing record type"))?;
+        let bytes = decode_hex(&line[2..], line_no)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(FormatError::new(line_no, "byte count does not match record length"));
+        }
+        let sum = bytes[..bytes.len() - 1].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        let checksum = bytes[bytes.len() - 1];
+        if !sum != checksum {
+            return Err(FormatError::new(line_no, format!("checksum mismatch: expected {:02X}, found {:02X}", !sum, checksum)));
+        }
+
+        let addr_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            t => return Err(FormatError::new(line_no, format!("unsupported record type S{}", t))),
+        };
+        if bytes.len() < addr_len + 2 {
+            return Err(FormatError::new(line_no, "record too short"));
+        }
+        let address = bytes[1..1 + addr_len].iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+        let data = &bytes[1 + addr_len..bytes.len() - 1];
+        match kind {
+            1..=3 => segments.push((address, data.to_vec())),
+            7..=9 => break,
+            _ => {}
+        }
+    }
+
+    flatten(segments, fill)
+}
+
+fn decode_hex(s: &str, line_no: usize) -> Result<Vec<u8>, FormatError> {
+    if !s.is_ascii() {
+        return Err(FormatError::new(line_no, "non-ASCII characters in record"));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(FormatError::new(line_no, "odd number of hex digits"));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16)
+            .map_err(|_| FormatError::new(line_no, format!("invalid hex digits {:?}", &s[i..i + 2]))))
+        .collect()
+}
+
+fn flatten(segments: Vec<(u64, Vec<u8>)>, fill: u8) -> Result<Vec<u8>, FormatError> {
+    let start = segments.iter().map(|(addr, _)| *addr).min().unwrap_or(0);
+    let end = segments.iter().map(|(addr, data)| addr + data.len() as u64).max().unwrap_or(0);
+    if end - start > MAX_FLAT_SIZE {
+        return Err(FormatError::new(0, format!("records span {:#x}..{:#x}, too large for a flat image", start, end)));
+    }
+
+    let mut image = vec![fill; (end - start) as usize];
+    for (addr, data) in segments {
+        let offset = (addr - start) as usize;
+        image[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    Ok(image)
+}
+
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(line.len());
+    let end = line.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(start, |p| p + 1);
+    &line[start..end]
+}
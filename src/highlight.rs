@@ -0,0 +1,91 @@
+//! Colors the parts of received lines that match user-given regexes, e.g. `ERROR` or `panic`.
+
+use std::str::FromStr;
+
+use crossterm::style::{Color, style, Stylize};
+use regex::Regex;
+
+/// What `--highlight` paints in when no color is given.
+pub const DEFAULT_COLOR: Color = Color::Yellow;
+
+/// One `--highlight` rule, written `regex` or `color:regex`, e.g. `red:ERROR|panic`.
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub regex: Regex,
+    pub color: Color,
+}
+
+impl FromStr for Highlight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (color, regex) = match s.split_once(':') {
+            Some((name, regex)) => match parse_color(name) {
+                Some(color) => (color, regex),
+                // a colon of the regex itself, e.g. `(?:...)` or `ERR: .*`
+                None => (DEFAULT_COLOR, s),
+            },
+            None => (DEFAULT_COLOR, s),
+        };
+        if regex.is_empty() { return Err("empty regex".to_string()); }
+        let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+        Ok(Highlight { regex, color })
+    }
+}
+
+fn parse_color(name: &str) -> Option<Color> {
+    match name.to_ascii_lowercase().as_str() {
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "yellow" => Some(Color::Yellow),
+        "blue" => Some(Color::Blue),
+        "magenta" => Some(Color::Magenta),
+        "cyan" => Some(Color::Cyan),
+        "white" => Some(Color::White),
+        "grey" | "gray" => Some(Color::Grey),
+        _ => None,
+    }
+}
+
+/// All `--highlight` rules; where two overlap, the one given first wins.
+#[derive(Debug, Clone, Default)]
+pub struct Highlighter {
+    rules: Vec<Highlight>,
+}
+
+impl Highlighter {
+    pub fn new(rules: Vec<Highlight>) -> Self {
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Byte ranges of `line` to color, in order and not overlapping.
+    pub fn spans(&self, line: &str) -> Vec<(usize, usize, Color)> {
+        let mut spans: Vec<(usize, usize, Color)> = Vec::new();
+        for rule in &self.rules {
+            for m in rule.regex.find_iter(line).filter(|m| !m.as_str().is_empty()) {
+                if spans.iter().all(|&(start, end, _)| m.end() <= start || m.start() >= end) {
+                    spans.push((m.start(), m.end(), rule.color));
+                }
+            }
+        }
+        spans.sort_unstable_by_key(|&(start, _, _)| start);
+        spans
+    }
+
+    /// `line` with every match wrapped in its color.
+    pub fn paint(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut at = 0;
+        for (start, end, color) in self.spans(line) {
+            out += &line[at..start];
+            out += &style(&line[start..end]).with(color).to_string();
+            at = end;
+        }
+        out += &line[at..];
+        out
+    }
+}
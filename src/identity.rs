@@ -0,0 +1,101 @@
+//! `--identify`: asking the loader right after its request which board it runs on, so that of
+//! several identical ones on the bench, pushing to the wrong board stops before any of the
+//! image went out:
+//!
+//! `MPI?` → `'M' 'P' 'I' | length: u8 | identity`
+//!
+//! The identity is text, by convention space-separated `key=value` fields like
+//! `serial=00000000a1b2c3d4 mac=dc:a6:32:01:02:03 loader=4f1c2e9`. Opt-in, like the
+//! [version probe](crate::protocol::VERSION_PROBE): a loader that doesn't know it takes it
+//! for the size. One that stays silent is pushed to as ever, unless `--expect-target` asks.
+
+use std::{collections::BTreeMap, fmt, io::{Read, Write}, time::Duration};
+
+use serde::Serialize;
+
+use crate::{ErrorKind, ReadSerial, Result, WRITE_TIMEOUT, WriteSerial};
+
+/// Sent after the request to ask the loader who it is.
+pub const IDENTITY_PROBE: [u8; 4] = *b"MPI?";
+
+/// Starts the answer to [`IDENTITY_PROBE`], followed by the length of the identity as a `u8`.
+pub const IDENTITY_MAGIC: [u8; 3] = *b"MPI";
+
+/// How long the loader gets to answer before it counts as one that doesn't.
+pub const IDENTIFY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// What the target said it is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetIdentity {
+    /// All of the answer, e.g. `serial=00000000a1b2c3d4 mac=dc:a6:32:01:02:03`.
+    pub text: String,
+    /// Its `key=value` fields.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl TargetIdentity {
+    pub fn parse(text: &str) -> Self {
+        let fields = text.split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self { text: text.trim().to_string(), fields }
+    }
+
+    /// The value of field `key`, e.g. `serial`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    /// Whether this is the target `expected` names: all of the identity, one of its values,
+    /// or a `key=value` field of it. Either case goes, MACs and hex serials being written both ways.
+    pub fn matches(&self, expected: &str) -> bool {
+        let expected = expected.trim();
+        if self.text.eq_ignore_ascii_case(expected) { return true; }
+        match expected.split_once('=') {
+            Some((key, value)) => self.get(key).is_some_and(|field| field.eq_ignore_ascii_case(value)),
+            None => self.fields.values().any(|value| value.eq_ignore_ascii_case(expected)),
+        }
+    }
+}
+
+impl fmt::Display for TargetIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// The answer to the probe as a loader sends it, the identity cut to 255 bytes.
+pub fn encode(identity: &str) -> Vec<u8> {
+    let mut end = identity.len().min(u8::MAX as usize);
+    while !identity.is_char_boundary(end) { end -= 1; }
+    let mut reply = IDENTITY_MAGIC.to_vec();
+    reply.push(end as u8);
+    reply.extend_from_slice(&identity.as_bytes()[..end]);
+    reply
+}
+
+/// Asks the loader who it is, right after its request. `None` from one that stays silent for
+/// `timeout`.
+pub fn identify<P: Read + Write + ?Sized>(port: &mut P, timeout: Duration) -> Result<Option<TargetIdentity>> {
+    port.write_serial_all(&IDENTITY_PROBE, WRITE_TIMEOUT)?;
+
+    let mut head = [0; 4];
+    match port.read_serial_timeout(&mut head, timeout)? {
+        0 => return Ok(None),
+        4 if head[..3] == IDENTITY_MAGIC => {}
+        n => return Err(ErrorKind::UnexpectedReply { expected: "an identity".to_string(), received: head[..n].to_vec() }),
+    }
+    let mut text = vec![0; head[3] as usize];
+    port.read_serial_exact_timeout(&mut text, timeout)?;
+    Ok(Some(TargetIdentity::parse(&String::from_utf8_lossy(&text))))
+}
+
+/// `--expect-target`: whether what the target said it is, if anything, is `expected`. A target
+/// that didn't say can't be told apart from the wrong one and isn't pushed to either.
+pub fn check(identity: Option<&TargetIdentity>, expected: &str) -> Result<()> {
+    match identity {
+        Some(identity) if identity.matches(expected) => Ok(()),
+        _ => Err(ErrorKind::WrongTarget { expected: expected.to_string(), identity: identity.map(ToString::to_string) }),
+    }
+}
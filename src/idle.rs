@@ -0,0 +1,81 @@
+//! `--idle-timeout`: noticing that the target went quiet, e.g. a board that hung during an
+//! overnight soak test. Only received bytes count; typing into a hung board doesn't wake it.
+
+use std::{fmt, str::FromStr, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+/// What happens once the target has been quiet for the idle timeout, besides the warning.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum IdleAction {
+    /// Only the warning.
+    #[default]
+    Warn,
+    Bell,
+    /// Run a command through the shell without waiting for it.
+    Exec(String),
+    /// End the session with this exit code.
+    Exit(i32),
+}
+
+impl FromStr for IdleAction {
+    type Err = String;
+
+    /// `warn`, `bell`, `exec:CMD`, `exit` (with 1) or `exit:CODE`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            None if s == "warn" => Ok(IdleAction::Warn),
+            None if s == "bell" => Ok(IdleAction::Bell),
+            None if s == "exit" => Ok(IdleAction::Exit(1)),
+            Some(("exit", code)) => code.parse().map(IdleAction::Exit).map_err(|_| format!("invalid exit code {:?}", code)),
+            Some(("exec", cmd)) if !cmd.is_empty() => Ok(IdleAction::Exec(cmd.to_string())),
+            Some(("exec", _)) => Err("empty command".to_string()),
+            _ => Err("expected warn, bell, exec:CMD or exit[:CODE]".to_string()),
+        }
+    }
+}
+
+/// Time since the last received byte, told with the times passed in so it needs no clock of
+/// its own. Fires once per silence; the next byte arms it again.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    timeout: Duration,
+    last_rx: Instant,
+    fired: bool,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self { timeout, last_rx: now, fired: false }
+    }
+
+    /// Bytes arrived at `now`. Gives back how long the silence lasted if it had fired.
+    pub fn rx(&mut self, now: Instant) -> Option<Duration> {
+        let silence = if self.fired { Some(now.saturating_duration_since(self.last_rx)) } else { None };
+        self.restart(now);
+        silence
+    }
+
+    /// How long the target has been quiet, the first time that is past the timeout.
+    pub fn check(&mut self, now: Instant) -> Option<Duration> {
+        let quiet = now.saturating_duration_since(self.last_rx);
+        if self.fired || quiet < self.timeout { return None; }
+        self.fired = true;
+        Some(quiet)
+    }
+
+    /// Counts from `now` without the time before, e.g. the port being gone and reopened,
+    /// which is a disconnect rather than a hang.
+    pub fn restart(&mut self, now: Instant) {
+        self.last_rx = now;
+        self.fired = false;
+    }
+}
+
+/// Time of day in UTC, e.g. `03:14:07 UTC`.
+pub struct UtcTime(pub SystemTime);
+
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) % (24 * 60 * 60);
+        write!(f, "{:02}:{:02}:{:02} UTC", secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
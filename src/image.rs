@@ -0,0 +1,260 @@
+use std::{fs::{self, File}, io::{self, BufReader, Cursor, Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::mpsc, thread, time::{Duration, Instant, SystemTime}};
+
+use crate::{ErrorKind, fleet::wildcard, formats::{self, Format}, Result};
+
+/// Where the bytes of an image come from: a raw binary streamed from disk, or a buffer
+/// produced by converting another format.
+pub enum ImageSource {
+    File(BufReader<File>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Read for ImageSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ImageSource::File(file) => file.read(buf),
+            ImageSource::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for ImageSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ImageSource::File(file) => file.seek(pos),
+            ImageSource::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// Read ahead of a file image, so pushing doesn't cost a read syscall per chunk.
+const FILE_BUFFER: usize = 64 * 1024;
+
+pub struct Image {
+    pub source: ImageSource,
+    pub size: u64,
+    pub format: Format,
+}
+
+impl Image {
+    pub fn from_file(file: File) -> Result<Self> {
+        let size = file.metadata()?.len();
+        Ok(Self { source: ImageSource::File(BufReader::with_capacity(FILE_BUFFER, file)), size, format: Format::Binary })
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self { size: data.len() as u64, source: ImageSource::Memory(Cursor::new(data)), format: Format::Binary }
+    }
+
+    /// Opens `path`, converting Intel HEX and SREC files into a flat image whose gaps are
+    /// filled with `fill`. Anything else is sent as-is.
+    pub fn load<P: AsRef<Path>>(path: P, fill: u8) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+
+        let mut head = Vec::with_capacity(64);
+        Read::by_ref(&mut file).take(64).read_to_end(&mut head)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let format = Format::detect(path, &head);
+        if format == Format::Binary { return Image::from_file(file); }
+
+        let mut text = String::new();
+        file.read_to_string(&mut text).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => not_text(path.display()),
+            _ => ErrorKind::IoError(e),
+        })?;
+        Self::convert(format, &text, fill)
+    }
+
+    /// An image already in memory, e.g. spooled from stdin; the format is told by its content.
+    pub fn from_data(data: Vec<u8>, fill: u8) -> Result<Self> {
+        let format = Format::sniff(&data[..data.len().min(64)]);
+        if format == Format::Binary { return Ok(Image::from_bytes(data)); }
+        let text = String::from_utf8(data).map_err(|_| not_text("the image"))?;
+        Self::convert(format, &text, fill)
+    }
+
+    fn convert(format: Format, text: &str, fill: u8) -> Result<Self> {
+        let data = match format {
+            Format::IntelHex => formats::parse_ihex(text, fill)?,
+            _ => formats::parse_srec(text, fill)?,
+        };
+        Ok(Self { format, ..Image::from_bytes(data) })
+    }
+}
+
+fn not_text<D: std::fmt::Display>(name: D) -> ErrorKind {
+    ErrorKind::FormatError(formats::FormatError { line: 0, reason: format!("{} is not a text file", name) })
+}
+
+/// Images larger than this are more likely a wrong path, e.g. to a disk image, than a kernel.
+pub const MAX_SIZE: u64 = 128 << 20;
+
+/// How long [`check_settled`] waits for a file a build is still writing.
+pub const SETTLE_WAIT: Duration = Duration::from_secs(2);
+
+/// Checks that `path` is a file worth pushing, i.e. readable and not empty, returning its size.
+/// Done upfront, so a typo shows before the target is powered rather than after the handshake.
+pub fn check<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let path = path.as_ref();
+    let unusable = |reason: String| ErrorKind::ImageUnusable { path: path.display().to_string(), reason };
+    let file = File::open(path).map_err(|e| unusable(e.to_string()))?;
+    let metadata = file.metadata().map_err(|e| unusable(e.to_string()))?;
+    if metadata.is_dir() { return Err(unusable("is a directory".to_string())); }
+    if metadata.len() == 0 { return Err(unusable("is empty".to_string())); }
+    Ok(metadata.len())
+}
+
+/// Like [`check`], but a file that is missing or empty, as while a build rewrites it, is
+/// looked at again for up to `wait` before that counts.
+pub fn check_settled<P: AsRef<Path>>(path: P, wait: Duration) -> Result<u64> {
+    let path = path.as_ref();
+    let deadline = Instant::now() + wait;
+    loop {
+        let settling = fs::metadata(path).map_or(true, |metadata| metadata.is_file() && metadata.len() == 0);
+        if !settling || Instant::now() >= deadline { return check(path); }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Checks the size of an image as it will be pushed, e.g. after converting Intel HEX: nothing
+/// at all is never right, more than `max` is suspect unless there is no limit.
+pub fn check_size(size: u64, max: Option<u64>) -> Result<()> {
+    match max {
+        _ if size == 0 => Err(ErrorKind::ImageUnusable { path: "the image".to_string(), reason: "is empty".to_string() }),
+        Some(max) if size > max => Err(ErrorKind::ImageHuge { size, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Names listed at most when a wildcard image matches nothing.
+const LISTED_MAX: usize = 20;
+
+/// Whether `path` picks its file by wildcards, e.g. `out/kernel8-*.img`, see [`newest_match`].
+pub fn is_glob(path: &str) -> bool {
+    !is_url(path) && path.contains(['*', '?'])
+}
+
+/// The most recently modified file `pattern` matches, which may have wildcards as
+/// [`wildcard`] has them in its last component only; of two as new, the last name. Nothing
+/// matching is told along with what the directory does have.
+pub fn newest_match(pattern: &str) -> Result<PathBuf> {
+    let unusable = |reason: String| ErrorKind::ImageUnusable { path: pattern.to_string(), reason };
+    let path = Path::new(pattern);
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return Err(unusable("is not a file name".to_string())),
+    };
+    if is_glob(&dir.to_string_lossy()) { return Err(unusable("only the last component may have wildcards".to_string())); }
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let entries = fs::read_dir(listed).map_err(|e| unusable(format!("{}: {}", listed.display(), e)))?;
+    let mut names = Vec::new();
+    let mut newest: Option<(SystemTime, String)> = None;
+    for entry in entries.flatten() {
+        let entry_name = match entry.file_name().into_string() {
+            Ok(entry_name) => entry_name,
+            Err(_) => continue,
+        };
+        names.push(entry_name.clone());
+        if !wildcard(name, &entry_name) { continue; }
+        // a symlink counts as what it points to, e.g. a `latest.img` the build keeps
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if newest.as_ref().is_none_or(|(time, newest)| (modified, &entry_name) > (*time, newest)) {
+            newest = Some((modified, entry_name));
+        }
+    }
+    if let Some((_, found)) = newest { return Ok(dir.join(found)); }
+    if names.is_empty() { return Err(unusable(format!("matches nothing, {} is empty", listed.display()))); }
+    names.sort();
+    let more = names.len().saturating_sub(LISTED_MAX);
+    names.truncate(LISTED_MAX);
+    let listing = if more > 0 { format!("{} and {} more", names.join(", "), more) } else { names.join(", ") };
+    Err(unusable(format!("matches nothing in {}, which has {}", listed.display(), listing)))
+}
+
+/// Whether `path` names a download rather than a file, see the `http` feature.
+pub fn is_url(path: &str) -> bool {
+    let lower = path.get(..8).unwrap_or(path).to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Largest image taken from a pipe; it is held in memory to learn its size.
+pub const SPOOL_MAX: u64 = 256 << 20;
+
+/// All of `reader`, for an image whose size isn't known before it ends. Gives up past `max`
+/// bytes.
+pub fn spool<R: Read>(reader: R, max: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(max + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("more than {} bytes", max)).into());
+    }
+    Ok(data)
+}
+
+/// Cuts an image into chunks of the same size, the last one shorter, all read into one buffer.
+#[derive(Debug)]
+pub struct Chunks {
+    buf: Vec<u8>,
+}
+
+impl Chunks {
+    pub fn new(size: usize) -> Self {
+        Self { buf: vec![0; size.max(1)] }
+    }
+
+    /// The next chunk of `source`, empty at its end.
+    pub fn next<R: Read + ?Sized>(&mut self, source: &mut R) -> io::Result<&[u8]> {
+        let mut filled = 0;
+        while filled < self.buf.len() {
+            match source.read(&mut self.buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(&self.buf[..filled])
+    }
+}
+
+/// Chunks [`ReadAhead`] keeps ready for the port at most.
+pub const READ_AHEAD: usize = 4;
+
+/// Reads an image on a thread of its own, up to `depth` chunks ahead of whoever writes them
+/// out, so a slow disk or network share waits while the UART sends instead of before it.
+/// Dropping it ends the reading after the chunk in hand.
+pub struct ReadAhead {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    done: bool,
+}
+
+impl ReadAhead {
+    pub fn spawn<R: Read + Send + 'static>(mut source: R, chunk_size: usize, depth: usize) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(depth.max(1));
+        thread::spawn(move || {
+            let mut buf = Chunks::new(chunk_size);
+            loop {
+                let chunk = buf.next(&mut source).map(<[u8]>::to_vec);
+                let last = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
+                // or the writing side is gone, done or failed
+                if sender.send(chunk).is_err() || last { return; }
+            }
+        });
+        Self { chunks, done: false }
+    }
+
+    /// The next chunk, as [`Chunks::next`] would have read it, empty at the end; the error
+    /// that ended the reading, if one did.
+    pub fn next_chunk(&mut self) -> io::Result<Vec<u8>> {
+        if self.done { return Ok(Vec::new()); }
+        let chunk = self.chunks.recv().unwrap_or_else(|_| Err(io::Error::other("the image reader stopped")));
+        self.done = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
+        chunk
+    }
+}
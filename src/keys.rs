@@ -0,0 +1,130 @@
+//! Keys read from the terminal, as the bytes a serial console expects for them.
+//!
+//! On Windows, keys come from the console's key events: characters as they were typed, in any
+//! script, and sent as UTF-8. AltGr arrives there as Ctrl and Alt held together, so a
+//! character typed with both is taken as it is, not as a control code; see [`encode_on`].
+
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// How keys without a character of their own, like arrows and function keys, are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyEncoding {
+    /// The escape sequences of an xterm, what shells and most line editors understand.
+    #[default]
+    Xterm,
+    /// Characters and control codes only; the other keys send nothing.
+    Ascii,
+}
+
+impl FromStr for KeyEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "xterm" => Ok(KeyEncoding::Xterm),
+            "ascii" => Ok(KeyEncoding::Ascii),
+            _ => Err("expected xterm or ascii".to_string()),
+        }
+    }
+}
+
+/// The single byte `key` stands for, e.g. `0x1d` for Ctrl-], which is what the exit key and
+/// the command prefix are matched against. `None` for other keys and non-ASCII characters.
+pub fn key_byte(key: &KeyEvent) -> Option<u8> {
+    key_byte_on(key, cfg!(windows))
+}
+
+/// [`key_byte`] as it is on Windows or elsewhere, where `]` typed with AltGr is just `]`.
+pub fn key_byte_on(key: &KeyEvent, windows: bool) -> Option<u8> {
+    let control = key.modifiers.contains(KeyModifiers::CONTROL) && !is_altgr(key, windows);
+    match key.code {
+        KeyCode::Enter => Some(b'\r'),
+        KeyCode::Tab => Some(b'\t'),
+        KeyCode::Backspace => Some(0x7f),
+        KeyCode::Esc => Some(0x1b),
+        KeyCode::Null => Some(0),
+        // Windows hands over control characters as they are
+        KeyCode::Char(c) if (c as u32) < 0x20 => Some(c as u8),
+        KeyCode::Char(c) if control => match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => Some(c as u8 - b'a' + 1),
+            ' ' | '@' | '2' => Some(0),
+            c @ '['..='_' => Some(c as u8 - b'[' + 0x1b),
+            // how a unix terminal reports Ctrl-\ to Ctrl-_
+            c @ '3'..='7' => Some(c as u8 - b'3' + 0x1b),
+            '8' | '?' => Some(0x7f),
+            _ => None,
+        },
+        KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+        _ => None,
+    }
+}
+
+/// What the target is sent for `key`; `None` for keys `encoding` has nothing for.
+pub fn encode(key: &KeyEvent, encoding: KeyEncoding) -> Option<Vec<u8>> {
+    encode_on(key, encoding, cfg!(windows))
+}
+
+/// [`encode`] as it is on Windows or elsewhere: on Windows, a character typed with AltGr,
+/// like `@` or `€` on a German keyboard, is sent as its UTF-8 alone.
+pub fn encode_on(key: &KeyEvent, encoding: KeyEncoding, windows: bool) -> Option<Vec<u8>> {
+    if is_altgr(key, windows) {
+        if let KeyCode::Char(c) = key.code { return Some(c.to_string().into_bytes()); }
+    }
+    let xterm = encoding == KeyEncoding::Xterm;
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let mut bytes = match key.code {
+        // the classic ASCII backspace, not DEL
+        KeyCode::Backspace if !xterm => vec![0x08],
+        KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => c.to_string().into_bytes(),
+        _ => match key_byte_on(key, windows) {
+            Some(byte) => vec![byte],
+            None if xterm => return sequence(key),
+            None => return None,
+        },
+    };
+    // Meta sends ESC first, like xterm's metaSendsEscape
+    if alt && xterm { bytes.insert(0, 0x1b); }
+    Some(bytes)
+}
+
+/// Whether `key` is a character typed with AltGr, which a Windows console reports as Ctrl and
+/// Alt held and the character the layout has there. A control character is a real Ctrl-Alt.
+fn is_altgr(key: &KeyEvent, windows: bool) -> bool {
+    windows && key.modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        && matches!(key.code, KeyCode::Char(c) if !c.is_control())
+}
+
+/// The xterm escape sequence of a cursor, editing or function key, with its modifiers.
+fn sequence(key: &KeyEvent) -> Option<Vec<u8>> {
+    let mut modifiers = 1;
+    if key.modifiers.contains(KeyModifiers::SHIFT) { modifiers += 1; }
+    if key.modifiers.contains(KeyModifiers::ALT) { modifiers += 2; }
+    if key.modifiers.contains(KeyModifiers::CONTROL) { modifiers += 4; }
+    // a final letter, after `ESC O` for F1 to F4, or a number before `~`
+    let (letter, number) = match key.code {
+        KeyCode::Up => ('A', 0),
+        KeyCode::Down => ('B', 0),
+        KeyCode::Right => ('C', 0),
+        KeyCode::Left => ('D', 0),
+        KeyCode::Home => ('H', 0),
+        KeyCode::End => ('F', 0),
+        KeyCode::BackTab => return Some(b"\x1b[Z".to_vec()),
+        KeyCode::F(n @ 1..=4) => ((b'P' + n - 1) as char, 0),
+        KeyCode::Insert => ('~', 2),
+        KeyCode::Delete => ('~', 3),
+        KeyCode::PageUp => ('~', 5),
+        KeyCode::PageDown => ('~', 6),
+        KeyCode::F(n @ 5..=12) => ('~', [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5]),
+        _ => return None,
+    };
+    let sequence = match (letter, modifiers) {
+        ('~', 1) => format!("\x1b[{}~", number),
+        ('~', modifiers) => format!("\x1b[{};{}~", number, modifiers),
+        ('P'..='S', 1) => format!("\x1bO{}", letter),
+        (letter, 1) => format!("\x1b[{}", letter),
+        (letter, modifiers) => format!("\x1b[1;{}{}", modifiers, letter),
+    };
+    Some(sequence.into_bytes())
+}
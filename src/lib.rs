@@ -1,168 +1,751 @@
-#![feature(type_alias_impl_trait)]
+use std::{fmt, io, io::{Read, Stderr, stdout, Write}, panic, thread};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
+pub use crossterm::{style::Stylize, terminal::{disable_raw_mode, enable_raw_mode}};
 
-use std::{io, io::{Read, Stdout, stdout, Write}, panic, thread};
-use std::path::Path;
-use std::process::exit;
-use std::sync::{Arc, atomic::{AtomicBool, AtomicU8, Ordering}, Condvar, Mutex};
-use std::time::Duration;
+pub mod ansi;
+pub mod backoff;
+pub mod baud;
+pub mod bench;
+pub mod block;
+pub mod bridge;
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod control;
+pub mod delta;
+pub mod doctor;
+pub mod early;
+pub mod events;
+pub mod exit;
+pub mod expect;
+#[cfg(feature = "http")]
+pub mod fetch;
+pub mod filter;
+pub mod fleet;
+pub mod formats;
+pub mod highlight;
+pub mod identity;
+pub mod idle;
+pub mod image;
+pub mod keys;
+pub mod limit;
+pub mod lock;
+pub mod logfile;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod mux;
+pub mod observer;
+pub mod output;
+pub mod paste;
+pub mod pattern;
+pub mod phases;
+pub mod portwatch;
+pub mod prompt;
+pub mod protocol;
+pub mod pull;
+pub mod record;
+pub mod report;
+pub mod script;
+pub mod scrollback;
+pub mod selftest;
+pub mod settings;
+pub mod sha256;
+pub mod signal;
+pub mod stats;
+pub mod terminal;
+pub mod timesync;
+pub mod transport;
+pub mod trigger;
+pub mod txlog;
+pub mod watch;
+pub mod wire;
+pub mod ymodem;
 
-pub use crossterm::{style::Colorize, terminal::{disable_raw_mode, enable_raw_mode}};
+use bench::{BenchConfig, BenchResult};
+use settings::{SerialSettings, SyncAction};
+use stats::SessionStats;
+use command::CommandTable;
+use control::ControlSocket;
+use events::{Event, EventLog};
+use expect::Transcript;
+use filter::FilterChain;
+use highlight::Highlighter;
+use lock::PortLock;
+use logfile::SessionLog;
+use observer::PushObserver;
+use output::{Icon, Output, Verbosity};
+use pattern::Pattern;
+use portwatch::{PortEvent, PortWatcher};
+use prompt::LocalCommands;
+use script::Script;
+use scrollback::Scrollback;
+use selftest::{SelftestConfig, SelftestReport};
+use terminal::{Display, ExitReason, RxTap, TerminalOptions, View};
+use transport::{Presence, Target, UsbIdentity};
+use trigger::Triggers;
+use txlog::TxLog;
+use wire::WireLog;
 
-#[cfg(unix)]
-pub type SerialPort = serialport::TTYPort;
-#[cfg(windows)]
-pub type SerialPort = serialport::COMPort;
+/// An open connection to the target: a native port, or any other [`transport::Transport`].
+pub type SerialPort = Box<dyn serialport::SerialPort>;
+
+/// A port [`SerialTool::open_serial`] opened, with its lock file. Dropping it closes the port,
+/// then releases the lock.
+pub struct Connection {
+    pub port: SerialPort,
+    _lock: Option<PortLock>,
+}
+
+impl Connection {
+    pub fn new(port: SerialPort, lock: Option<PortLock>) -> Self {
+        Self { port, _lock: lock }
+    }
+}
 
 pub const SERIAL_BAUD: u32 = 921_600;
 
+/// What `--version` prints: the crate version and the commit it was built from.
+pub const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
+
+/// A tool built on a serial connection. Kept object safe, so which tool runs can be decided at
+/// runtime with a `Box<dyn SerialTool>`.
+///
+/// `exec()` opens its [`Connection`] with `open_serial()` and hands the port to the steps that
+/// need it; the connection is closed whenever `exec()` returns.
 pub trait SerialTool {
     fn target_serial_name(&self) -> &str;
     fn name_short(&self) -> &str;
-    fn target_serial(&mut self) -> Option<&mut SerialPort>;
-    fn set_target_serial(&mut self, serialport: SerialPort);
+    /// Where the tool's status messages go.
+    fn output(&self) -> &Output;
 
-    fn serial_connected(&self) -> bool {
-        if cfg!(unix) {
-            Path::new(self.target_serial_name()).exists()
-        } else if cfg!(windows) {
-            serialport::available_ports()
-                .expect("find ports error")
-                .iter()
-                .any(|port| {
-                    port.port_name == self.target_serial_name()
-                })
-        } else {
-            panic!("unsupported system")
-        }
+    fn serial_settings(&self) -> SerialSettings {
+        SerialSettings::default()
+    }
+
+    /// Destination of lifecycle events, if enabled.
+    fn events(&self) -> Option<&EventLog> {
+        None
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(log) = self.events() { log.emit(self.name_short(), &event); }
     }
 
-    fn wait_for_serial(&self) {
-        if self.serial_connected() { return; }
-        println!("[{}] ⏳ Waiting for {}", self.name_short(), self.target_serial_name());
+    /// Who hears about the connection and the push; without one, nothing is said about them.
+    fn observer(&self) -> Option<&Mutex<Box<dyn PushObserver>>> {
+        None
+    }
+
+    fn notify(&self, f: &mut dyn FnMut(&mut dyn PushObserver)) {
+        if let Some(observer) = self.observer() { f(observer.lock().unwrap().as_mut()); }
+    }
 
-        while !self.serial_connected() { sleep(1); }
+    /// What the tool is currently doing, reported with errors.
+    fn phase(&self) -> &str {
+        "session"
+    }
+
+    fn serial_connected(&self) -> bool {
+        Target::parse(self.target_serial_name()).is_present()
     }
 
-    fn open_serial(&mut self) {
-        self.wait_for_serial();
-        match serialport::new(self.target_serial_name(), SERIAL_BAUD)
-            .timeout(Duration::from_millis(1))
-            .open_native() {
-            Ok(target_serial) => {
-                println!("[{}] ✅ Connected", self.name_short());
-                self.set_target_serial(target_serial);
+    /// Blocks until the target shows up, saying that it isn't there yet and how long it has been. Ctrl-C ends the wait with [`ErrorKind::Interrupted`]; a device
+    /// this user may not open fails in the `open` phase with the hint how to fix that.
+    fn wait_for_serial(&self) -> Result<()> {
+        let watcher = PortWatcher::builder(vec![self.target_serial_name().to_string()]).every(WAIT_POLL).spawn();
+        let started = Instant::now();
+        let (mut seen, mut waiting, mut told, mut denied_since) = (false, false, started, None);
+        // in steps, so Ctrl-C doesn't sit out the rest of a look
+        signal::catch_interrupts(|| loop {
+            match watcher.recv_timeout(Duration::from_millis(100)) {
+                Some(PortEvent::Failed { error, .. }) => return Err(error.into()),
+                Some(PortEvent::Changed { presence, .. }) => {
+                    seen = true;
+                    match presence {
+                        Presence::Present(_) => return Ok(()),
+                        Presence::Ambiguous(_) => return Err(presence.ambiguity().into()),
+                        Presence::PermissionDenied(_) => denied_since = Some((presence, Instant::now())),
+                        Presence::Missing if self.single_attempt() => return Err(presence.absence().into()),
+                        Presence::Missing => denied_since = None,
+                    }
+                }
+                None => {}
+            }
+            if signal::interrupted() {
+                self.output().clear_transient();
+                return Err(ErrorKind::Interrupted);
             }
-            Err(e) => {
-                print!("[{}] 🚫 {}", self.name_short(), e);
-                exit(-1);
+            if !seen { continue; }
+            // udev may not have given a device that just showed up its group yet
+            if let Some((denied, since)) = &denied_since {
+                if since.elapsed() >= PERMISSION_GRACE {
+                    self.output().clear_transient();
+                    return Err(denied.denial().into());
+                }
+            }
+
+            if !waiting {
+                waiting = true;
+                self.emit(Event::WaitingForSerial { port: self.target_serial_name().to_string() });
+                self.notify(&mut |observer| observer.waiting_for_serial(self.target_serial_name()));
+            } else if told.elapsed() >= WAIT_POLL {
+                told = Instant::now();
+                self.notify(&mut |observer| observer.still_waiting(self.target_serial_name(), started.elapsed()));
             }
+        })
+    }
+
+    /// What is done as soon as the port is open, see [`SyncAction`].
+    fn sync_on_connect(&self) -> Option<SyncAction> {
+        None
+    }
+
+    /// One go, e.g. for `--script`: a missing port isn't waited for, and `run()` ends with the
+    /// first error instead of reconnecting.
+    fn single_attempt(&self) -> bool {
+        false
+    }
+
+    /// Whether `--force` may take over a stale lock file.
+    fn force_lock(&self) -> bool {
+        false
+    }
+
+    /// Whether `--force-baud` opens the port at a rate its bridge can't make, see [`baud`].
+    fn force_baud(&self) -> bool {
+        false
+    }
+
+    /// The USB adapter found at the port when `run()` first opened it.
+    fn known_device(&self) -> Option<UsbIdentity> {
+        None
+    }
+
+    fn set_known_device(&mut self, _device: Option<UsbIdentity>) {}
+
+    /// Whether `--allow-device-change` takes another adapter at the port after a reconnect.
+    fn allow_device_change(&self) -> bool {
+        false
+    }
+
+    /// Remembers the USB adapter at the port on the first open, and refuses another one turning
+    /// up under its name on a later one, before anything is sent to it.
+    fn check_device(&mut self) -> Result<()> {
+        let now = match transport::usb_identity(self.target_serial_name()) {
+            Some(now) => now,
+            None => return Ok(()),
         };
+        match self.known_device() {
+            Some(was) if was != now && !self.allow_device_change() =>
+                return Err(ErrorKind::DeviceChanged { device: self.target_serial_name().to_string(), was: was.to_string(), now: now.to_string() }),
+            Some(was) if was != now => self.output().warn(format!("{} is another device now, {} rather than {}", self.target_serial_name(), now, was)),
+            _ => {}
+        }
+        self.set_known_device(Some(now));
+        Ok(())
     }
 
-    fn terminal(&mut self) -> Result<()> {
-        let port = self.target_serial().ok_or(ErrorKind::NoneError("serial"))?;
+    /// Refuses a baud rate the port's USB bridge makes too far off, naming one it can do.
+    fn check_baud(&self, baud_rate: u32) -> Result<()> {
+        if self.force_baud() { return Ok(()); }
+        let bridge = match transport::usb_id(self.target_serial_name()).and_then(|(vid, pid)| baud::Bridge::from_usb(vid, pid)) {
+            Some(bridge) => bridge,
+            None => return Ok(()),
+        };
+        bridge.check(baud_rate).map_err(|nearest| ErrorKind::UnsupportedBaud {
+            bridge: bridge.name(),
+            requested: baud_rate,
+            actual: bridge.actual(baud_rate),
+            nearest,
+        })
+    }
 
-        let mut serial_port = port.try_clone_native()?;
+    /// Takes the lock file of a local unix port. Windows opens ports exclusively by itself.
+    fn lock_port(&mut self) -> Result<Option<PortLock>> {
+        if !cfg!(unix) { return Ok(None); }
+        let target = Target::parse(self.target_serial_name());
+        // network targets have no lock file, and looking them up would connect once more
+        if !matches!(target, Target::Native(_)) { return Ok(None); }
+        match target.presence()? {
+            Presence::Present(path) => Ok(Some(PortLock::acquire(Path::new(&path), self.force_lock())?)),
+            _ => Ok(None),
+        }
+    }
 
+    /// Waits for the target, locks and opens it. Errors come back in the `open` phase, for
+    /// `run()` to retry or report.
+    fn open_serial(&mut self) -> Result<Connection> {
+        let settings = self.serial_settings();
+        let opened = self.wait_for_serial()
+            .and_then(|_| self.check_baud(settings.baud_rate))
+            .and_then(|_| self.check_device())
+            .and_then(|_| self.lock_port())
+            .and_then(|lock| {
+                // the lock goes again if opening fails
+                let port = Target::parse(self.target_serial_name()).open(&settings, Duration::from_millis(1))?;
+                Ok(Connection::new(txlog::mirror(port, self.tx_log().as_ref()), lock))
+            });
+        let mut connection = opened.map_err(|e| e.context("open", self.target_serial_name()))?;
+        // a driver may round a rate it can't set rather than refuse it
+        match connection.port.baud_rate() {
+            Ok(actual) if actual != settings.baud_rate => self.output().warn(format!("{} runs at {} baud, not the {} asked for; expect garbage",
+                                                                                     self.target_serial_name(), actual, settings.baud_rate)),
+            _ => {}
+        }
+        if self.terminal_options().read_only { terminal::release_control_lines(&mut connection.port); }
+        if let Some(sync) = self.sync_on_connect() { self.sync_line(&mut connection.port, sync); }
+        self.emit(Event::Connected { port: self.target_serial_name().to_string(), settings: settings.to_string() });
+        self.notify(&mut |observer| observer.connected(self.target_serial_name(), &settings));
+        Ok(connection)
+    }
 
-        enable_raw_mode().unwrap();
-        // 0: ok, no error; 1: connect error; 2: ctrl c
-        let has_error = Arc::new(AtomicU8::new(0));
-        let has_error_clone = has_error.clone();
+    /// Carries out `sync` on the port just opened. A sync that fails is only worth a warning,
+    /// the session may well work without.
+    fn sync_line(&self, port: &mut SerialPort, sync: SyncAction) {
+        if sync.writes() && self.terminal_options().read_only {
+            self.output().warn(format!("read-only, {} not sent", sync));
+            return;
+        }
+        match sync.apply(port.as_mut()) {
+            Ok(()) => self.output().verbose(format!("synced the line with {}", sync)),
+            Err(e) => self.output().warn(format!("could not sync the line with {}: {}", sync, e)),
+        }
+    }
 
-        thread::spawn(move || {
-            let mut serial_buf = [0; 256];
-            while is_ok(&*has_error_clone) {
-                match serial_port.read_serial(&mut serial_buf) {
-                    Ok(t) => {
-                        String::from_utf8_lossy(&serial_buf[..t]).chars().for_each(|c| {
-                            if c == '\n' {
-                                print!("\r");
-                            }
-                            print!("{}", c);
-                        });
-                        stdout().flush().unwrap();
-                    }
-                    Err(e) => {
-                        print!("\r\nread_serial error {:?}", e);
-                        has_error_clone.store(1, Ordering::Relaxed);
-                        break;
-                    }
-                }
+    fn terminal_options(&self) -> TerminalOptions {
+        TerminalOptions::default()
+    }
+
+    fn commands(&self) -> CommandTable {
+        CommandTable::default()
+    }
+
+    /// What the local command line offers, see [`prompt`].
+    fn local_commands(&self) -> LocalCommands {
+        LocalCommands::default()
+    }
+
+    /// `--highlight` rules applied to received lines.
+    fn highlighter(&self) -> Highlighter {
+        Highlighter::default()
+    }
+
+    /// `--on` rules reacting to received lines.
+    fn triggers(&self) -> Triggers {
+        Triggers::default()
+    }
+
+    /// Where `--record` writes the session, if anywhere.
+    fn recorder(&self) -> Option<Arc<record::Recorder>> {
+        None
+    }
+
+    /// `--debug-wire`: where both directions are shown, and logged with `--wire-log`.
+    fn wire(&self) -> Option<Arc<WireLog>> {
+        None
+    }
+
+    /// Where `--log` writes what the target prints, if anywhere.
+    fn session_log(&self) -> Option<Arc<SessionLog>> {
+        None
+    }
+
+    /// Where `--control` clients are heard, if anywhere.
+    fn control(&self) -> Option<Arc<ControlSocket>> {
+        None
+    }
+
+    /// Counters summarized when `run()` ends.
+    fn stats(&self) -> Option<Arc<SessionStats>> {
+        None
+    }
+
+    /// The last of what the target printed, see [`scrollback`].
+    fn scrollback(&self) -> Option<Arc<Scrollback>> {
+        None
+    }
+
+    /// `--log-tx`: where every byte written to the port is mirrored, if anywhere.
+    fn tx_log(&self) -> Option<Arc<TxLog>> {
+        None
+    }
+
+    /// Writes the scrollback out after the session failed, saying where.
+    fn dump_scrollback(&self) {
+        if let Some(path) = scrollback::dump_failed() {
+            self.output().status(format!("{} Scrollback saved to {}", self.output().icon(Icon::Save), path.display()));
+        }
+    }
+
+    /// Whether what the target prints is shown; one board among several keeps it to itself.
+    fn show_output(&self) -> bool {
+        true
+    }
+
+    /// Renderer of the target's output, set up from the terminal options.
+    fn display(&self) -> Display {
+        let mut display = Display::new(self.output().clone(), self.highlighter(), self.triggers());
+        display.set_view(if self.terminal_options().hex { View::Hex } else { View::Text });
+        display.set_raw_output(self.terminal_options().raw_output);
+        display.set_show_control(self.terminal_options().show_control);
+        display.set_hidden(!self.show_output());
+        display.set_filters(FilterChain::new(&self.terminal_options().display_filters));
+        display.set_line_idle(self.terminal_options().line_idle);
+        display
+    }
+
+    /// Extra consumers of the received stream, e.g. the TCP bridge.
+    fn rx_taps(&mut self) -> Vec<Box<dyn RxTap>> {
+        Vec::new()
+    }
+
+    fn terminal(&mut self, port: &mut SerialPort) -> Result<()> {
+        self.terminal_session(port, None).map(|_| ())
+    }
+
+    /// Like `terminal()`, also ending once `stop` is set, and saying why it ended.
+    fn terminal_session(&mut self, port: &mut SerialPort, stop: Option<Arc<AtomicBool>>) -> Result<ExitReason> {
+        let mut terminal = terminal::Terminal::new(self.output().clone())
+            .options(self.terminal_options())
+            .commands(self.commands())
+            .local_commands(self.local_commands())
+            .display(self.display());
+        for tap in self.rx_taps() { terminal = terminal.tap(tap); }
+        if let Some(recorder) = self.recorder() { terminal = terminal.recorder(recorder); }
+        if let Some(wire) = self.wire() { terminal = terminal.wire(wire); }
+        if let Some(log) = self.session_log() { terminal = terminal.tap(Box::new(log)); }
+        if let Some(stats) = self.stats() { terminal = terminal.stats(stats); }
+        if let Some(scrollback) = self.scrollback() { terminal = terminal.scrollback(scrollback); }
+        if let Some(stop) = stop { terminal = terminal.stop_when(stop); }
+        if let Some(control) = self.control() { terminal = terminal.control(control); }
+        if let Some(log) = self.tx_log() { terminal = terminal.tx_log(log); }
+        terminal.run(&mut terminal::Reopening::new(port, self.target_serial_name(), self.serial_settings()))
+    }
+
+    /// Where what the target prints outside the terminal is shown, and who else gets it: the
+    /// recorder, the session log, the stats, the scrollback and the wire, which hides the display.
+    fn session_taps(&mut self) -> (Display, Vec<Box<dyn RxTap>>) {
+        let mut display = self.display();
+        let mut taps = self.rx_taps();
+        taps.extend(self.recorder().map(|recorder| Box::new(recorder) as Box<dyn RxTap>));
+        taps.extend(self.session_log().map(|log| Box::new(log) as Box<dyn RxTap>));
+        taps.extend(self.stats().map(|stats| Box::new(stats) as Box<dyn RxTap>));
+        taps.extend(self.scrollback().map(|scrollback| Box::new(scrollback) as Box<dyn RxTap>));
+        if let Some(wire) = self.wire() {
+            taps.push(Box::new(wire));
+            display.set_hidden(true);
+        }
+        (display, taps)
+    }
+
+    /// Shows what the target prints for `duration`, without raw mode or reading the keyboard;
+    /// without one, until an `exit` trigger ends the process.
+    fn watch(&mut self, port: &mut SerialPort, duration: Option<Duration>) -> Result<()> {
+        let (mut display, mut taps) = self.session_taps();
+
+        let deadline = duration.map(|duration| Instant::now() + duration);
+        let (options, baud) = (self.terminal_options(), port.baud_rate().unwrap_or(SERIAL_BAUD));
+        let mut buf = vec![0; options.batch_size(baud)];
+        while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+            let n = port.read_serial_drain(&mut buf, options.read_size(baud))?;
+            if n > 0 { taps.iter_mut().for_each(|tap| tap.rx(&buf[..n])); }
+            display.show(&buf[..n]);
+        }
+        display.finish();
+        Ok(())
+    }
+
+    /// Shows what the target prints like [`SerialTool::watch`], until `pattern` shows up or
+    /// `deadline` passes, and hands back all of it.
+    fn expect(&mut self, port: &mut SerialPort, pattern: &Pattern, deadline: Duration) -> Result<Transcript> {
+        let (mut display, mut taps) = self.session_taps();
+
+        let transcript = signal::catch_interrupts(|| expect::expect(port, pattern, deadline, |data| {
+            taps.iter_mut().for_each(|tap| tap.rx(data));
+            display.show(data);
+        }));
+        display.finish();
+        transcript
+    }
+
+    /// Drives the open port through `script` instead of an interactive terminal, echoing what
+    /// the target prints. With `transcript`, a per-step summary follows.
+    fn run_script(&mut self, port: &mut SerialPort, script: &Script, transcript: bool) -> Result<()> {
+        let out = self.output().clone();
+
+        let outcomes = script.run(port, |data| {
+            print!("{}", String::from_utf8_lossy(data));
+            let _ = stdout().flush();
+        })?;
+
+        if transcript {
+            out.blank(Verbosity::Quiet);
+            out.line(Verbosity::Quiet, format!("{} Transcript", out.icon(Icon::Script)));
+            for outcome in outcomes {
+                let matched = outcome.matched
+                    .map(|m| format!(" → {:?}", String::from_utf8_lossy(&m)))
+                    .unwrap_or_default();
+                out.line(Verbosity::Quiet, format!("  line {:>3} {:>7.2}s  {}{}", outcome.line,
+                                                   outcome.elapsed.as_secs_f64(), outcome.description, matched));
+            }
+        }
+        out.blank(Verbosity::Normal);
+        out.status(format!("{} Script finished", out.icon(Icon::Ok)));
+        Ok(())
+    }
+
+    /// Measures the open port at every configured chunk size, leaving it drained afterwards so
+    /// a normal session can follow.
+    fn benchmark(&mut self, port: &mut SerialPort, config: &BenchConfig) -> Result<Vec<BenchResult>> {
+        let out = self.output().clone();
+        port.clear(serialport::ClearBuffer::All)?;
+
+        let quiet = Duration::from_millis(100);
+        let mut results = Vec::new();
+        for &chunk_size in &config.chunk_sizes {
+            let mut pb = out.progress_bar(Icon::Timer, &format!("write x{}", chunk_size), config.bytes);
+            results.push(bench::run_write(port, config, chunk_size, |n| if let Some(pb) = pb.as_mut() { pb.add(n) })?);
+            out.finish_progress(pb);
+
+            if config.read_back {
+                bench::drain(port, quiet)?;
+                let mut pb = out.progress_bar(Icon::Timer, &format!("echo x{}", chunk_size), config.bytes);
+                results.push(bench::run_echo(port, config, chunk_size, |n| if let Some(pb) = pb.as_mut() { pb.add(n) })?);
+                out.finish_progress(pb);
             }
+        }
+
+        bench::drain(port, quiet)?;
+        port.clear(serialport::ClearBuffer::All)?;
+        Ok(results)
+    }
+
+    /// Loops a test sequence through the open port, reporting progress on one status line.
+    fn selftest(&mut self, port: &mut SerialPort, config: &SelftestConfig) -> Result<SelftestReport> {
+        let name_short = self.name_short().to_string();
+        // redrawn in place, so only on a terminal
+        let interactive = self.output().enabled(Verbosity::Normal) && self.output().is_diagnostic_terminal();
+        let icon = self.output().icon(Icon::Loop);
+        port.clear(serialport::ClearBuffer::All)?;
+
+        let mut last_status = Instant::now();
+        let report = selftest::run(port, config, |report| {
+            if !interactive || last_status.elapsed() < Duration::from_millis(250) { return; }
+            last_status = Instant::now();
+            eprint!("\r[{}] {} {} bytes looped, {} errors ", name_short, icon, report.received, report.mismatched + report.dropped);
         });
+        if interactive { eprintln!(); }
 
-        let mut console_buf = [0; 256];
+        port.clear(serialport::ClearBuffer::All)?;
+        report
+    }
 
-        while is_ok(&*has_error) {
-            let len = io::stdin().read(&mut console_buf)?;
+    /// Receives the files the target sends with YMODEM into `dir`, showing what it prints
+    /// until it starts, with a progress bar for each file whose size it gives.
+    fn receive_files(&mut self, port: &mut SerialPort, dir: &Path) -> Result<Vec<ymodem::ReceivedFile>> {
+        let out = self.output().clone();
+        let mut display = self.display();
+        out.status(format!("{} Waiting for the target to send with YMODEM, Ctrl-C quits", out.icon(Icon::Wait)));
+        let mut pb = None;
+        let received = signal::catch_interrupts(|| ymodem::receive(port, dir, |transfer| match transfer {
+            ymodem::Transfer::Output(data) => display.show(data),
+            ymodem::Transfer::Started(header) => {
+                display.finish();
+                let message = format!("Receiving {}", header.name);
+                pb = header.size.and_then(|size| out.progress_bar(Icon::Save, &message, size));
+                if pb.is_none() { out.status(format!("{} {}", out.icon(Icon::Save), message)); }
+            }
+            ymodem::Transfer::Progress(bytes) => if let Some(pb) = pb.as_mut() { pb.set(bytes); },
+            ymodem::Transfer::Finished(file) => {
+                out.finish_progress(pb.take());
+                out.status(format!("{} Received {}, {}", out.icon(Icon::Ok), file.path.display(), output::format_bytes(file.bytes)));
+            }
+        }));
+        out.finish_progress(pb);
+        display.finish();
+        received
+    }
 
-            if console_buf.contains(&03) { has_error.store(2, Ordering::Relaxed); }
+    /// Waits for the target to send a [`pull`] dump, showing what it prints until then, and
+    /// writes it to `output` or under the name it gives.
+    fn pull_dump(&mut self, port: &mut SerialPort, output: Option<&Path>) -> Result<pull::Pulled> {
+        let out = self.output().clone();
+        let mut display = self.display();
+        out.status(format!("{} Waiting for the target to send a dump, Ctrl-C quits", out.icon(Icon::Wait)));
+        let mut scanner = pull::MagicScanner::default();
+        let mut buf = [0; 256];
+        let rest = signal::catch_interrupts(|| loop {
+            if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+            let n = port.read_serial(&mut buf)?;
+            let (shown, rest) = scanner.feed(&buf[..n]);
+            display.show(&shown);
+            if let Some(rest) = rest { return Ok(rest); }
+        });
+        display.finish();
+        let pulled = pull::catch(port, &rest?, output, &out)?;
+        display.show(&pulled.after);
+        display.finish();
+        Ok(pulled)
+    }
 
-            port.write_all(&mut console_buf[..len]).map_err(|_| ErrorKind::ConnectionError)?;
+    /// Plays a `--record`ed session back through the terminal's display, `speed` times as fast
+    /// (0: without waiting). Needs no port at all.
+    fn replay(&mut self, path: &Path, speed: f64) -> Result<()> {
+        let frames = record::load(path)?;
+        let mut display = self.display();
+        for frame in frames.iter().filter(|frame| frame.direction == record::Direction::Rx) {
+            let mut wait = if speed > 0.0 { frame.delta.div_f64(speed) } else { Duration::ZERO };
+            // in steps, so a held-back prompt shows up as it did live
+            while !wait.is_zero() {
+                let step = wait.min(self.terminal_options().line_idle);
+                thread::sleep(step);
+                wait -= step;
+                display.show(&[]);
+            }
+            display.show(&frame.data);
         }
-
-        if has_error.load(Ordering::Relaxed) == 1 { Err(ErrorKind::ConnectionError) } else { Ok(()) }
+        display.finish();
+        Ok(())
     }
 
+    /// Cleans up after `exec()`, whose connection is closed by then.
     fn connection_reset(&mut self) {
-        self.target_serial().take();
-        disable_raw_mode().unwrap();
+        // nothing to undo if raw mode was never entered, e.g. without a terminal on stdin
+        terminal::RAW_MODE.restore();
+        self.output().set_raw(false);
+    }
+
+    /// Gets ready for the next `exec()` after `error`; an error ends `run()` with it instead.
+    fn handle_reconnect(&mut self, error: &ErrorKind) -> Result<()> {
+        self.connection_reset();
+        self.output().blank(Verbosity::Quiet);
+        self.output().error(format!("{} Connection Error ({}): Reinsert the USB serial again", self.output().icon(Icon::Error), error));
+        Ok(())
     }
 
-    fn handle_reconnect(&mut self) {
+    fn handle_unexpected(&mut self, error: &ErrorKind) {
         self.connection_reset();
-        println!("\n[{}] ⚡ {}", self.name_short(), "Connection Error: Reinsert the USB serial again".red());
+        self.output().blank(Verbosity::Quiet);
+        match error.kind() {
+            // not unexpected at all, and the message says what to do about it
+            ErrorKind::ImageTooLarge(_) | ErrorKind::ImageHuge { .. } => self.output().error(format!("{} {}", self.output().icon(Icon::Fail), error.kind())),
+            _ => self.output().error(format!("{} Unexpected Error: #{}", self.output().icon(Icon::Error), error)),
+        }
     }
 
-    fn handle_unexpected(&mut self, error: ErrorKind) {
+    /// Says why the port couldn't be opened, e.g. who holds its lock.
+    fn handle_open_error(&mut self, error: &ErrorKind) {
         self.connection_reset();
-        println!("\n[{}] ⚡ {}", self.name_short(), format!("Unexpected Error: #{:?}", error).red());
+        let message = match error.kind() {
+            ErrorKind::PortLocked { pid, stale: false, path } =>
+                format!("{} is in use by PID {} (lock file {})", self.target_serial_name(), pid, path.display()),
+            ErrorKind::PortLocked { pid, stale: true, path } =>
+                format!("stale lock file {} of PID {}, which no longer runs; --force removes it", path.display(), pid),
+            ErrorKind::SerialError(e) => transport::explain_open_error(self.target_serial_name(), e),
+            _ => error.to_string(),
+        };
+        self.output().error(format!("{} {}", self.output().icon(Icon::Fail), message));
     }
 
     fn exec(&mut self) -> Result<()>;
-    fn run(&mut self) {
+    /// Runs until `exec` succeeds or fails with an error reconnecting can't fix, which is returned.
+    fn run(&mut self) -> Result<()> {
+        signal::install();
         panic::set_hook(Box::new(|info| {
-            disable_raw_mode().unwrap();
-            println!("{}", info);
+            lock::release_all();
+            terminal::RAW_MODE.restore();
+            eprintln!("{}", info);
+            if let Some(path) = scrollback::dump_failed() { eprintln!("Scrollback saved to {}", path.display()); }
         }));
-        loop {
-            if let Err(e) = self.exec() {
-                match e {
-                    ErrorKind::ConnectionError |
-                    ErrorKind::ProtocolError |
-                    ErrorKind::TimeoutError => {
-                        self.handle_reconnect();
-                    }
-                    _ => {
-                        self.handle_unexpected(e);
+        // a device is only held to the one of the same run
+        self.set_known_device(None);
+        let mut result = Ok(());
+        while let Err(e) = self.exec() {
+            // the phase exec() was in when it failed
+            let e = e.context(self.phase(), self.target_serial_name());
+            let opening = e.phase() == Some("open");
+            self.emit(Event::Error { kind: e.name().to_string(), phase: e.phase().unwrap_or_default().to_string(), message: format!("{:?}", e.kind()) });
+            self.notify(&mut |observer| observer.error(&e));
+            match e.kind() {
+                // unplugged between showing up and being opened: waited for again
+                ErrorKind::SerialError(serial) if opening && transport::went_away(serial) && !self.serial_connected() && !self.single_attempt() => {
+                    self.handle_open_error(&e);
+                    self.emit(Event::Reconnect);
+                    if let Some(stats) = self.stats() { stats.add_reconnect(); }
+                }
+                ErrorKind::ConnectionError |
+                ErrorKind::ProtocolError |
+                ErrorKind::UnexpectedReply { .. } |
+                ErrorKind::TimeoutError |
+                ErrorKind::ReadTimeout { .. } |
+                ErrorKind::WriteTimeout { .. } |
+                ErrorKind::TargetRebooted { .. } if !self.single_attempt() => {
+                    self.emit(Event::Reconnect);
+                    if let Some(stats) = self.stats() { stats.add_reconnect(); }
+                    self.dump_scrollback();
+                    if let Err(e) = self.handle_reconnect(&e) {
+                        self.emit(Event::Error { kind: e.name().to_string(), phase: "reconnect".to_string(), message: format!("{:?}", e) });
+                        result = Err(e);
                         break;
                     }
                 }
-            } else { break; }
+                // the user stopped waiting, there is nothing to report
+                ErrorKind::Interrupted => {
+                    self.connection_reset();
+                    result = Err(e);
+                    break;
+                }
+                _ if opening => {
+                    self.handle_open_error(&e);
+                    result = Err(e);
+                    break;
+                }
+                _ => {
+                    self.handle_unexpected(&e);
+                    self.dump_scrollback();
+                    result = Err(e);
+                    break;
+                }
+            }
         }
         self.connection_reset();
-        println!("\n[{}] Bye 👋", self.name_short());
+        self.output().blank(Verbosity::Normal);
+        if let Some(summary) = self.stats().map(|stats| stats.summary()) {
+            self.emit(Event::SessionSummary {
+                received: summary.received,
+                sent: summary.sent,
+                seconds: summary.duration.as_secs_f64(),
+                reconnects: summary.reconnects,
+                pushed: summary.pushed,
+                push_seconds: summary.push_time.as_secs_f64(),
+            });
+            self.output().status(format!("{} {}", self.output().icon(Icon::Timer), summary));
+        }
+        if let Some(log) = self.tx_log() { log.close(self.output()); }
+        if let Some(log) = self.session_log() { log.finish(); }
+        self.emit(Event::Exit { success: result.is_ok() });
+        self.output().status(format!("Bye {}", self.output().icon(Icon::Bye)));
+        result
     }
 }
 
-fn is_ok(flag: &AtomicU8) -> bool {
-    flag.load(Ordering::Relaxed) == 0
-}
-
 
 pub fn sleep(sec: u64) {
     thread::sleep(Duration::from_secs(sec));
 }
 
-pub fn create_pb(name_short: &str, total: u64) -> pbr::ProgressBar<Stdout> {
-    let mut pb = pbr::ProgressBar::new(total);
+/// A byte progress bar labelled `message`, `width` columns wide; `plain` keeps it to ASCII for
+/// logs and dumb terminals.
+pub fn create_pb(message: &str, total: u64, plain: bool, width: usize) -> pbr::ProgressBar<Stderr> {
+    let mut pb = pbr::ProgressBar::on(io::stderr(), total);
     pb.set_units(pbr::Units::Bytes);
-    pb.set_width(Some(92));
+    pb.set_width(Some(width));
     pb.show_counter = false;
-    pb.message(&format!("[{}] ⏩ Pushing 6 KiB", name_short));
-    pb.format(" =🦀- ");
+    pb.message(message);
+    pb.format(if plain { "[=>-]" } else { " =🦀- " });
     pb
 }
 
@@ -178,38 +761,51 @@ pub fn timeout<F>(f: F, sec: u64) -> Result<()>
 
     thread::spawn(move || {
         let (lock, cvar) = &*pair2;
-        let mut ok = lock.lock().unwrap();
-        loop {
-            let res = cvar.wait_timeout(ok, Duration::from_secs(sec)).unwrap();
-            ok = res.0;
+        let ok = lock.lock().unwrap();
+        let _ = cvar.wait_timeout(ok, Duration::from_secs(sec)).unwrap();
 
-            flag_clone.store(false, Ordering::Relaxed);
-            break;
-        }
+        flag_clone.store(false, Ordering::Relaxed);
     });
 
     let (_, cvar) = &*pair;
     f(flag.clone())?;
+    // before waking the timer, which clears the flag once it wakes up for whatever reason
+    let timed_out = !flag.load(Ordering::Relaxed);
     cvar.notify_one();
 
-
-    if flag.load(Ordering::Relaxed) { Ok(()) } else { Err(ErrorKind::TimeoutError) }
+    if timed_out { Err(ErrorKind::TimeoutError) } else { Ok(()) }
 }
 
+/// How often [`SerialTool::wait_for_serial`] looks for the target when nothing tells of a change.
+pub const WAIT_POLL: Duration = Duration::from_secs(1);
+
+/// How long [`SerialTool::wait_for_serial`] gives a device it may not open to get its group.
+pub const PERMISSION_GRACE: Duration = Duration::from_secs(2);
+
+/// How long a write may stall before the port is given up on.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub trait ReadSerial {
     fn read_serial(&mut self, buf: &mut [u8]) -> Result<usize>;
     fn read_serial_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+    /// Like `read_serial_exact`, but gives up with `ReadTimeout` once `timeout` passes.
+    fn read_serial_exact_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<()>;
+    /// Fills `buf` until it is full or `timeout` passes, returning how much arrived.
+    fn read_serial_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+    /// Reads `chunk` bytes at a time into `buf` until a read comes back short, the OS buffer
+    /// being drained, or `buf` is full; how much that was. An error after some data arrived
+    /// waits for the next call.
+    fn read_serial_drain(&mut self, buf: &mut [u8], chunk: usize) -> Result<usize>;
 }
 
-impl ReadSerial for SerialPort {
+impl<T: Read + ?Sized> ReadSerial for T {
     fn read_serial(&mut self, buf: &mut [u8]) -> Result<usize> {
         match self.read(buf) {
             Ok(t) => Ok(t),
             Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
-            Err(ref e) if match e.raw_os_error() {
-                Some(22) | Some(1167) => true,
-                _ => false
-            } => Err(ErrorKind::ConnectionError),
+            Err(ref e) if matches!(e.raw_os_error(), Some(22) | Some(1167)) => Err(ErrorKind::ConnectionError),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof) => Err(ErrorKind::ConnectionError),
             Err(e) => Err(ErrorKind::IoError(e))
         }
     }
@@ -227,6 +823,76 @@ impl ReadSerial for SerialPort {
         }
         if !buf.is_empty() { Err(ErrorKind::ConnectionError) } else { Ok(()) }
     }
+
+    fn read_serial_exact_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<()> {
+        match self.read_serial_timeout(buf, timeout)? {
+            n if n == buf.len() => Ok(()),
+            received => Err(ErrorKind::ReadTimeout { received, expected: buf.len() }),
+        }
+    }
+
+    fn read_serial_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut filled = 0;
+        while filled < buf.len() && Instant::now() < deadline {
+            match self.read_serial(&mut buf[filled..]) {
+                Ok(n) => filled += n,
+                Err(ErrorKind::IoError(ref e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    fn read_serial_drain(&mut self, buf: &mut [u8], chunk: usize) -> Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let end = (filled + chunk.max(1)).min(buf.len());
+            match self.read_serial(&mut buf[filled..end]) {
+                Ok(n) => {
+                    filled += n;
+                    if filled < end { break; }
+                }
+                Err(_) if filled > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+/// The write side of [`ReadSerial`]: unplugging mid-write is a `ConnectionError`, so it
+/// reconnects like a failed read does.
+pub trait WriteSerial {
+    /// Returns `Ok(0)` when the port timed out and the write can be retried.
+    fn write_serial(&mut self, buf: &[u8]) -> Result<usize>;
+    /// Writes all of `buf`, retrying short and timed out writes until `timeout` passes.
+    fn write_serial_all(&mut self, buf: &[u8], timeout: Duration) -> Result<()>;
+}
+
+impl<T: Write + ?Sized> WriteSerial for T {
+    fn write_serial(&mut self, buf: &[u8]) -> Result<usize> {
+        match self.write(buf) {
+            Ok(0) if !buf.is_empty() => Err(ErrorKind::ConnectionError),
+            Ok(t) => Ok(t),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => Ok(0),
+            // EIO and ENXIO are what a write gets once a USB adapter is unplugged
+            Err(ref e) if matches!(e.raw_os_error(), Some(5) | Some(6) | Some(22) | Some(1167)) => Err(ErrorKind::ConnectionError),
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::WriteZero) => Err(ErrorKind::ConnectionError),
+            Err(e) => Err(ErrorKind::IoError(e))
+        }
+    }
+
+    fn write_serial_all(&mut self, buf: &[u8], timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut written = 0;
+        while written < buf.len() {
+            if Instant::now() >= deadline { return Err(ErrorKind::WriteTimeout { written, expected: buf.len() }); }
+            written += self.write_serial(&buf[written..])?;
+        }
+        Ok(())
+    }
 }
 
 
@@ -238,14 +904,170 @@ pub type Result<T> = std::result::Result<T, ErrorKind>;
 pub enum ErrorKind {
     ConnectionError,
     ProtocolError,
+    /// The loader answered a handshake step with `received` rather than `expected`.
+    UnexpectedReply { expected: String, received: Vec<u8> },
     TimeoutError,
-    NoneError(&'static str),
+    /// A read deadline passed with only `received` of the `expected` bytes there.
+    ReadTimeout { received: usize, expected: usize },
+    /// A write deadline passed with only `written` of the `expected` bytes out.
+    WriteTimeout { written: usize, expected: usize },
     SerialError(serialport::Error),
     IoError(io::Error),
+    FormatError(formats::FormatError),
+    /// A script failed to parse or one of its expects timed out.
+    ScriptError(String),
+    /// `pattern` didn't show up in what the target printed for `waited`.
+    ExpectTimeout { pattern: String, waited: Duration },
+    /// The loopback self-test saw missing or corrupted bytes.
+    SelftestError(String),
+    /// A YMODEM transfer was cancelled or gave up, see [`ymodem`].
+    TransferError(String),
+    RecordingError(String),
+    /// The image is larger than the size header can express.
+    ImageTooLarge(u64),
+    /// The image at `path` can't be pushed, e.g. because it is empty or a directory.
+    ImageUnusable { path: String, reason: String },
+    /// The image is `size` bytes, more than the `max` an image is expected to be.
+    ImageHuge { size: u64, max: u64 },
+    /// The boot command line can't be sent as it is, see [`protocol::encode_cmdline`].
+    InvalidCmdline(String),
+    /// Downloading the image failed; the serial side is fine, so there is no reconnecting.
+    NetworkError(String),
+    /// The image pushed isn't the one `--sha256` names.
+    ImageMismatch { expected: [u8; 32], actual: [u8; 32] },
+    /// Ctrl-C or SIGTERM ended a wait.
+    Interrupted,
+    /// The session is read-only, so nothing is sent to the target.
+    ReadOnly,
+    /// The port's lock file at `path` names `pid`; `stale` when that process is gone.
+    PortLocked { pid: u32, stale: bool, path: PathBuf },
+    /// The loader asked for the image again `at` bytes into the push, having restarted.
+    TargetRebooted { at: u64 },
+    /// A [`pull`] dump stopped coming with `received` of its `total` bytes in.
+    DumpIncomplete { received: u64, total: u64 },
+    /// A [`pull`] dump whose data doesn't have the CRC-32 the target sent after it.
+    DumpCorrupt { expected: u32, actual: u32 },
+    /// The port's USB `bridge` makes `actual` of the `requested` baud rate, too far off;
+    /// `nearest` is a rate it can do.
+    UnsupportedBaud { bridge: &'static str, requested: u32, actual: u32, nearest: u32 },
+    /// Another USB adapter than at the first open turned up at `device`, e.g. after a hub reset.
+    DeviceChanged { device: String, was: String, now: String },
+    /// The target isn't the one `--expect-target` names; `identity` is what it said it is.
+    WrongTarget { expected: String, identity: Option<String> },
+    /// Pushing to several boards at once, `failed` of the `total` didn't make it.
+    BoardsFailed { failed: usize, total: usize },
+    /// `source` happened while the tool was in `phase` on `port`.
+    WithContext { phase: String, port: String, source: Box<ErrorKind> },
+}
+
+impl ErrorKind {
+    /// Stable short name, used in the event log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::ConnectionError => "connection",
+            ErrorKind::ProtocolError | ErrorKind::UnexpectedReply { .. } => "protocol",
+            ErrorKind::TimeoutError | ErrorKind::ReadTimeout { .. } | ErrorKind::WriteTimeout { .. } | ErrorKind::ExpectTimeout { .. } |
+            ErrorKind::DumpIncomplete { .. } => "timeout",
+            ErrorKind::SerialError(_) => "serial",
+            ErrorKind::IoError(_) => "io",
+            ErrorKind::FormatError(_) => "format",
+            ErrorKind::ScriptError(_) => "script",
+            ErrorKind::SelftestError(_) => "selftest",
+            ErrorKind::TransferError(_) => "transfer",
+            ErrorKind::RecordingError(_) => "recording",
+            ErrorKind::ImageTooLarge(_) | ErrorKind::ImageHuge { .. } => "image_too_large",
+            ErrorKind::ImageUnusable { .. } => "image",
+            ErrorKind::InvalidCmdline(_) => "cmdline",
+            ErrorKind::NetworkError(_) => "network",
+            ErrorKind::ImageMismatch { .. } | ErrorKind::DumpCorrupt { .. } => "checksum",
+            ErrorKind::Interrupted => "interrupted",
+            ErrorKind::ReadOnly => "read_only",
+            ErrorKind::PortLocked { .. } => "locked",
+            ErrorKind::TargetRebooted { .. } => "rebooted",
+            ErrorKind::UnsupportedBaud { .. } => "baud",
+            ErrorKind::WrongTarget { .. } => "wrong_target",
+            ErrorKind::DeviceChanged { .. } => "device_changed",
+            ErrorKind::BoardsFailed { .. } => "boards",
+            ErrorKind::WithContext { source, .. } => source.name(),
+        }
+    }
+
+    /// Says where the error happened; an error that already says so is left alone.
+    pub fn context(self, phase: &str, port: &str) -> Self {
+        match self {
+            ErrorKind::WithContext { .. } => self,
+            source => ErrorKind::WithContext { phase: phase.to_string(), port: port.to_string(), source: Box::new(source) },
+        }
+    }
+
+    /// What the process exits with after a tool ended with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self.kind() {
+            ErrorKind::PortLocked { .. } => lock::LOCKED_EXIT_CODE,
+            ErrorKind::Interrupted => signal::INTERRUPTED_EXIT_CODE,
+            _ => 1,
+        }
+    }
+
+    /// The phase the error happened in, if it says.
+    pub fn phase(&self) -> Option<&str> {
+        match self {
+            ErrorKind::WithContext { phase, .. } => Some(phase),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without the context around it.
+    pub fn kind(&self) -> &ErrorKind {
+        match self {
+            ErrorKind::WithContext { source, .. } => source.kind(),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::WithContext { phase, port, source } => write!(f, "{} during {} on {}", source, phase, port),
+            ErrorKind::IoError(e) => write!(f, "{}", e),
+            ErrorKind::InvalidCmdline(reason) => write!(f, "invalid command line: {}", reason),
+            ErrorKind::NetworkError(reason) => write!(f, "download failed: {}", reason),
+            ErrorKind::TransferError(reason) => write!(f, "YMODEM transfer failed: {}", reason),
+            ErrorKind::TargetRebooted { at } => write!(f, "target rebooted at byte {}", at),
+            ErrorKind::ReadOnly => write!(f, "read-only, nothing is sent"),
+            ErrorKind::ImageUnusable { path, reason } => write!(f, "{}: {}", path, reason),
+            ErrorKind::ImageTooLarge(size) =>
+                write!(f, "the image is {}, more than the 4-byte size header can express ({}); pass --extended-size if the loader supports 64-bit sizes",
+                       output::format_bytes(*size), output::format_bytes(protocol::LEGACY_MAX_SIZE)),
+            ErrorKind::ImageHuge { size, max } =>
+                write!(f, "the image is {}, more than the {} expected; pass --max-image-size, or --allow-huge if that is right",
+                       output::format_bytes(*size), output::format_bytes(*max)),
+            ErrorKind::BoardsFailed { failed, total } => write!(f, "{} of {} boards failed", failed, total),
+            ErrorKind::DeviceChanged { device, was, now } =>
+                write!(f, "device at {} changed identity: it was {}, now it is {}; --allow-device-change uses it anyway", device, was, now),
+            ErrorKind::WrongTarget { expected, identity: Some(identity) } =>
+                write!(f, "the target is {}, not {}; nothing was pushed", identity, expected),
+            ErrorKind::WrongTarget { expected, identity: None } =>
+                write!(f, "the target did not say who it is, so it can't be told to be {}; nothing was pushed", expected),
+            ErrorKind::UnsupportedBaud { bridge, requested, actual, nearest } =>
+                write!(f, "a {} bridge makes {} baud of {}, {:.1} % off; try --baud {}, or --force-baud to use it anyway",
+                       bridge, actual, requested, baud::deviation(*requested, *actual) * 100.0, nearest),
+            ErrorKind::DumpIncomplete { received, total } => write!(f, "the dump stopped after {} of {} bytes", received, total),
+            ErrorKind::DumpCorrupt { expected, actual } => write!(f, "the dump's CRC-32 is {:08x}, the target sent {:08x}", actual, expected),
+            ErrorKind::ExpectTimeout { pattern, waited } => write!(f, "{} didn't show up within {:.1}s", pattern, waited.as_secs_f64()),
+            ErrorKind::ImageMismatch { expected, actual } =>
+                write!(f, "the image's SHA-256 is {}, not the expected {}", sha256::hex(actual), sha256::hex(expected)),
+            ErrorKind::UnexpectedReply { expected, received } =>
+                write!(f, "expected {}, got {} bytes:\n{}", expected, received.len(), terminal::hex_dump(received).trim_end()),
+            e => write!(f, "{:?}", e),
+        }
+    }
 }
 
 impl_from!(io::Error, ErrorKind::IoError);
 impl_from!(serialport::Error, ErrorKind::SerialError);
+impl_from!(formats::FormatError, ErrorKind::FormatError);
 
 
 #[macro_export]
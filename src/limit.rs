@@ -0,0 +1,43 @@
+//! Pacing of what is sent, for targets that can't keep up with the line rate.
+
+use std::{thread, time::{Duration, Instant}};
+
+/// Token bucket: short bursts pass at once, longer runs average out to `rate` bytes per second.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    /// Bytes that may go out right now; negative while a wait is owed.
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Allows bursts of 50 ms worth of `rate`.
+    pub fn new(rate: u64) -> Self {
+        Self::with_burst(rate, (rate / 20).max(1), Instant::now())
+    }
+
+    pub fn with_burst(rate: u64, burst: u64, now: Instant) -> Self {
+        let rate = rate.max(1) as f64;
+        Self { rate, burst: burst as f64, tokens: burst as f64, last: now }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate as u64
+    }
+
+    /// Takes `bytes` out of the bucket at `now` and returns how long to wait before sending them.
+    pub fn reserve(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - bytes as f64;
+        if self.tokens >= 0.0 { Duration::ZERO } else { Duration::from_secs_f64(-self.tokens / self.rate) }
+    }
+
+    /// Sleeps for as long as sending `bytes` now has to wait.
+    pub fn take(&mut self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() { thread::sleep(wait); }
+    }
+}
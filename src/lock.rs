@@ -0,0 +1,122 @@
+//! UUCP style lock files, `LCK..ttyUSB0` holding the owner's PID, so two tools don't share a
+//! port without noticing. minicom and picocom take the same locks in `/var/lock`.
+//!
+//! Windows ports are opened exclusively anyway; there a second open fails with "access denied",
+//! see [`crate::transport::explain_open_error`].
+
+use std::{env, fs::{self, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, process, sync::Mutex};
+
+use crate::{ErrorKind, Result};
+
+/// Exit code when the port is locked by someone else.
+pub const LOCKED_EXIT_CODE: i32 = 3;
+
+/// Lock files of this process and the like, e.g. its control socket, for exits that skip
+/// destructors.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Where lock files may live, the shared one first.
+pub fn lock_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/var/lock")];
+    dirs.extend(env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from));
+    dirs.push(env::temp_dir());
+    dirs
+}
+
+/// `LCK..ttyUSB0` for `/dev/ttyUSB0`, also when reached through a `/dev/serial/by-id` link.
+pub fn lock_name(device: &Path) -> Option<String> {
+    let device = fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+    Some(format!("LCK..{}", device.file_name()?.to_str()?))
+}
+
+/// PID in a lock file: ASCII, padded to ten columns by convention.
+pub fn parse_pid(contents: &str) -> Option<u32> {
+    contents.trim().parse().ok().filter(|&pid| pid > 0)
+}
+
+/// Whether a process `pid` exists, ours or not.
+pub fn is_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // EPERM: it exists but belongs to someone else
+        let found = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+        found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        pid == process::id()
+    }
+}
+
+/// A held lock, removed when dropped.
+#[derive(Debug)]
+pub struct PortLock {
+    path: PathBuf,
+}
+
+impl PortLock {
+    /// Locks `device`. A lock of a live process is never taken over; a stale one only with `force`.
+    pub fn acquire(device: &Path, force: bool) -> Result<PortLock> {
+        Self::acquire_in(device, &lock_dirs(), force)
+    }
+
+    pub fn acquire_in(device: &Path, dirs: &[PathBuf], force: bool) -> Result<PortLock> {
+        let name = match lock_name(device) {
+            Some(name) => name,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no file name to lock it by", device.display())).into()),
+        };
+        for path in dirs.iter().map(|dir| dir.join(&name)) {
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(_) => continue,
+            };
+            let pid = parse_pid(&contents).unwrap_or(0);
+            if pid > 0 && is_alive(pid) { return Err(ErrorKind::PortLocked { pid, stale: false, path }); }
+            if !force { return Err(ErrorKind::PortLocked { pid, stale: true, path }); }
+            fs::remove_file(&path)?;
+        }
+
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "no lock directory");
+        for path in dirs.iter().map(|dir| dir.join(&name)) {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(format!("{:>10}\n", process::id()).as_bytes())?;
+                    HELD.lock().unwrap().push(path.clone());
+                    return Ok(PortLock { path });
+                }
+                // e.g. /var/lock writable only by the uucp or lock group
+                Err(e) => last = e,
+            }
+        }
+        Err(last.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        HELD.lock().unwrap().retain(|path| *path != self.path);
+    }
+}
+
+/// Has `path` removed by [`release_all`] like a lock file, e.g. a socket to clean up.
+pub(crate) fn remove_at_exit(path: &Path) {
+    HELD.lock().unwrap().push(path.to_path_buf());
+}
+
+/// Undoes [`remove_at_exit`] for a `path` that was removed already.
+pub(crate) fn keep_at_exit(path: &Path) {
+    HELD.lock().unwrap().retain(|held| held != path);
+}
+
+/// Removes every lock file still held, right before the process ends without unwinding.
+pub fn release_all() {
+    // try_lock: this also runs in signal handlers, where waiting could deadlock
+    if let Ok(mut held) = HELD.try_lock() {
+        held.drain(..).for_each(|path| { let _ = fs::remove_file(path); });
+    }
+}
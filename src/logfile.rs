@@ -0,0 +1,225 @@
+//! `--log`: what the target prints, as plain bytes, in a file that can be rotated so a
+//! week-long session neither fills the disk nor grows past what an editor opens.
+//!
+//! Rotated files sit next to the log as `PATH.1` (the newest) up to `PATH.N`, like logrotate
+//! keeps them.
+
+use std::{fs::{self, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, str::FromStr};
+use std::{sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{cli::parse_size, filter::FilterChain, output::{Output, WriteWarning}, terminal::RxTap};
+
+/// Rotated files kept unless `--log-keep` says otherwise.
+pub const DEFAULT_KEEP: usize = 10;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// When the active file is replaced by a fresh one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Once the file would grow past this many bytes.
+    pub max_bytes: Option<u64>,
+    /// At the first write after midnight, UTC.
+    pub daily: bool,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut rotation = Rotation::default();
+        for part in s.split(',').map(str::trim) {
+            match part.to_ascii_lowercase().as_str() {
+                "daily" if !rotation.daily => rotation.daily = true,
+                size if rotation.max_bytes.is_none() && parse_size(size).is_ok() => rotation.max_bytes = parse_size(size).ok(),
+                _ => return Err("expected a size like 50M, daily, or daily,50M".to_string()),
+            }
+        }
+        Ok(rotation)
+    }
+}
+
+/// What rotating needs from the filesystem, so the policy can be tested without one.
+pub trait LogFs: Send {
+    /// Opens `path` for appending, returning how much it already holds.
+    fn append(&mut self, path: &Path) -> io::Result<(Box<dyn Write + Send>, u64)>;
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove(&mut self, path: &Path) -> io::Result<()>;
+}
+
+pub struct RealFs;
+
+impl LogFs for RealFs {
+    fn append(&mut self, path: &Path) -> io::Result<(Box<dyn Write + Send>, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((Box::new(file), size))
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+}
+
+pub trait Clock: Send {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+fn day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() / DAY_SECS)
+}
+
+/// `path` with `.index` appended, e.g. `out.log.3`.
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Ignores a file that was never there, e.g. fewer rotated logs than are kept.
+fn existing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+pub struct RotatingLog {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    fs: Box<dyn LogFs>,
+    clock: Box<dyn Clock>,
+    out: Box<dyn Write + Send>,
+    /// A rotation failed to open the fresh file, and `out` leads nowhere.
+    closed: bool,
+    size: u64,
+    day: u64,
+}
+
+impl RotatingLog {
+    /// Appends to the file at `path`, on the real filesystem and clock.
+    pub fn open<P: AsRef<Path>>(path: P, rotation: Rotation, keep: usize) -> io::Result<Self> {
+        Self::with(path, rotation, keep, Box::new(RealFs), Box::new(SystemClock))
+    }
+
+    pub fn with<P: AsRef<Path>>(path: P, rotation: Rotation, keep: usize, mut fs: Box<dyn LogFs>, clock: Box<dyn Clock>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (out, size) = fs.append(&path)?;
+        let day = day(clock.now());
+        Ok(Self { path, rotation, keep, fs, clock, out, closed: false, size, day })
+    }
+
+    /// Writes all of `data` to one file, rotating first if it is due: a chunk is never split
+    /// across files, so nothing is lost or doubled at the boundary.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() { return Ok(()); }
+        // a file's day is that of its first line, not of when it was opened
+        if self.size == 0 { self.day = day(self.clock.now()); }
+        if self.closed {
+            self.reopen()?;
+        } else if self.due(data.len() as u64) {
+            self.rotate()?;
+        }
+        self.out.write_all(data)?;
+        // flushed right away, so a crash leaves everything up to here
+        self.out.flush()?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Whether `incoming` more bytes go to a fresh file. An empty file takes any chunk, even
+    /// one over the limit by itself.
+    fn due(&self, incoming: u64) -> bool {
+        if self.size == 0 { return false; }
+        self.rotation.max_bytes.is_some_and(|max| self.size + incoming > max)
+            || (self.rotation.daily && day(self.clock.now()) != self.day)
+    }
+
+    /// Moves the active file to `PATH.1`, dropping the oldest beyond `keep`, and starts afresh.
+    /// Should that fail, the next write tries to open the file again.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        // closed before it is renamed, which Windows insists on
+        self.out = Box::new(io::sink());
+        self.closed = true;
+        if self.keep == 0 {
+            existing(self.fs.remove(&self.path))?;
+        } else {
+            existing(self.fs.remove(&rotated_path(&self.path, self.keep)))?;
+            for index in (1..self.keep).rev() {
+                existing(self.fs.rename(&rotated_path(&self.path, index), &rotated_path(&self.path, index + 1)))?;
+            }
+            self.fs.rename(&self.path, &rotated_path(&self.path, 1))?;
+        }
+        self.reopen()
+    }
+
+    /// Appends to the file at the path, a fresh one unless rotating it away failed; if that
+    /// left it too large, the next write rotates again.
+    fn reopen(&mut self) -> io::Result<()> {
+        let (out, size) = self.fs.append(&self.path)?;
+        self.out = out;
+        self.closed = false;
+        self.size = size;
+        self.day = day(self.clock.now());
+        Ok(())
+    }
+}
+
+/// A [`RotatingLog`] shared by the reader thread and whoever else writes to it.
+pub struct SessionLog(Mutex<RotatingLog>, Mutex<FilterChain>, WriteWarning);
+
+impl SessionLog {
+    pub fn new(log: RotatingLog) -> Self {
+        Self::with_filters(log, FilterChain::default())
+    }
+
+    /// What is received goes through `filters` before it is written, see `--log-filter`.
+    pub fn with_filters(log: RotatingLog, filters: FilterChain) -> Self {
+        Self(Mutex::new(log), Mutex::new(filters), WriteWarning::default())
+    }
+
+    /// Warns on `out` when a write fails, once.
+    pub fn warn_to(mut self, out: &Output) -> Self {
+        self.2 = WriteWarning::new(out, self.0.get_mut().unwrap().path.display());
+        self
+    }
+
+    pub fn write(&self, data: &[u8]) -> io::Result<()> {
+        // rotating under the same lock, so no write lands in a file being renamed
+        self.0.lock().unwrap().write(data)
+    }
+
+    /// Writes what the filters still hold back, e.g. the last line if it never ended.
+    pub fn finish(&self) {
+        let held = self.1.lock().unwrap().drain();
+        if !held.is_empty() { self.2.check(self.write(&held)); }
+    }
+}
+
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl RxTap for Arc<SessionLog> {
+    fn rx(&mut self, data: &[u8]) {
+        let mut filters = self.1.lock().unwrap();
+        let filtered = if filters.is_empty() { None } else { Some(filters.apply(data)) };
+        self.2.check(self.write(filtered.as_deref().unwrap_or(data)));
+    }
+}
@@ -0,0 +1,458 @@
+//! A scripted stand-in for a serial port, for testing code built on the crate without hardware.
+//!
+//! The script is a list of steps worked through in order: what the target expects to be
+//! written, what it replies, when the line fails. Replies become readable as soon as every
+//! step before them is done. A write the script doesn't expect panics, showing what was
+//! expected next and where the bytes first differ.
+//!
+//! ```
+//! use rust_serial_tool::{mock::MockSerial, protocol::{self, SizeHeader}};
+//!
+//! let mock = MockSerial::new().expect(&1024u32.to_le_bytes()).reply(b"OK");
+//! protocol::send_size(&mut mock.clone(), 1024, SizeHeader::Legacy).unwrap();
+//! mock.assert_done();
+//! ```
+
+use std::{collections::VecDeque, fmt, io::{self, Read, Write}, sync::{Arc, Mutex}, thread, time::Duration};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::{block, settings::SerialSettings};
+
+/// One step of a [`MockSerial`] script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// These bytes have to be written next, in as many writes as the caller likes.
+    Expect(Vec<u8>),
+    /// Any this many bytes have to be written next.
+    ExpectAny(usize),
+    /// Readable once the steps before are done, by one read or more if it doesn't fit.
+    Reply(Vec<u8>),
+    /// The next read or write fails with this error.
+    Fail(io::ErrorKind),
+    /// Every read and write from here on fails, like an unplugged adapter.
+    Disconnect,
+    /// A break has to be sent next.
+    Break,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Expect(bytes) => write!(f, "expect \"{}\"", bytes.escape_ascii()),
+            Step::ExpectAny(n) => write!(f, "expect any {} bytes", n),
+            Step::Reply(bytes) => write!(f, "reply \"{}\"", bytes.escape_ascii()),
+            Step::Fail(kind) => write!(f, "fail with {:?}", kind),
+            Step::Disconnect => write!(f, "disconnect"),
+            Step::Break => write!(f, "break"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    script: VecDeque<Step>,
+    /// Replies that are due but not read yet. A read doesn't span two of them.
+    rx: VecDeque<Vec<u8>>,
+    written: Vec<u8>,
+    settings: SerialSettings,
+    disconnected: bool,
+}
+
+impl State {
+    /// Makes the replies at the head of the script readable.
+    fn advance(&mut self) {
+        while let Some(Step::Reply(_)) = self.script.front() {
+            if let Some(Step::Reply(bytes)) = self.script.pop_front() {
+                // an empty reply reads as the quiet line it is
+                if !bytes.is_empty() { self.rx.push_back(bytes); }
+            }
+        }
+        if self.script.front() == Some(&Step::Disconnect) {
+            self.script.pop_front();
+            self.disconnected = true;
+        }
+    }
+
+    /// A one-shot failure or the disconnect, once every step before it is done.
+    fn failure(&mut self) -> Option<io::Error> {
+        if self.disconnected { return Some(io::Error::new(io::ErrorKind::BrokenPipe, "mock disconnected")); }
+        if let Some(&Step::Fail(kind)) = self.script.front() {
+            self.script.pop_front();
+            self.advance();
+            return Some(io::Error::new(kind, "mock failure"));
+        }
+        None
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(e) = self.failure() { return Err(e); }
+        let offset = self.written.len();
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let n = match self.script.front_mut() {
+                Some(Step::Expect(expected)) => {
+                    let n = rest.len().min(expected.len());
+                    if rest[..n] != expected[..n] {
+                        let at = rest.iter().zip(expected.iter()).position(|(a, b)| a != b).unwrap_or(0);
+                        panic!("MockSerial: write at offset {} differs at byte {}\n  expected: \"{}\"\n       got: \"{}\"",
+                            offset, offset + (buf.len() - rest.len()) + at, expected.escape_ascii(), rest.escape_ascii());
+                    }
+                    expected.drain(..n);
+                    if expected.is_empty() { self.script.pop_front(); }
+                    n
+                }
+                Some(Step::ExpectAny(left)) => {
+                    let n = rest.len().min(*left);
+                    *left -= n;
+                    if *left == 0 { self.script.pop_front(); }
+                    n
+                }
+                // a failure between two expectations cuts the write short
+                Some(Step::Fail(_)) | Some(Step::Disconnect) => break,
+                next => panic!("MockSerial: unexpected write at offset {}: \"{}\"\n  next step: {}",
+                    offset + (buf.len() - rest.len()), rest.escape_ascii(), next.map_or("end of script".to_string(), |step| step.to_string())),
+            };
+            self.written.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            self.advance();
+        }
+        Ok(buf.len() - rest.len())
+    }
+}
+
+/// A scripted serial port. Clones share the script, so one can be handed to the code under
+/// test and the other kept to check on it afterwards.
+#[derive(Debug, Clone)]
+pub struct MockSerial {
+    state: Arc<Mutex<State>>,
+    timeout: Duration,
+}
+
+impl Default for MockSerial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockSerial {
+    pub fn new() -> Self {
+        Self { state: Arc::default(), timeout: Duration::from_millis(10) }
+    }
+
+    /// Adds a step to the end of the script.
+    pub fn step(self, step: Step) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.script.push_back(step);
+            state.advance();
+        }
+        self
+    }
+
+    pub fn expect(self, bytes: &[u8]) -> Self {
+        self.step(Step::Expect(bytes.to_vec()))
+    }
+
+    pub fn expect_any(self, len: usize) -> Self {
+        self.step(Step::ExpectAny(len))
+    }
+
+    pub fn reply(self, bytes: &[u8]) -> Self {
+        self.step(Step::Reply(bytes.to_vec()))
+    }
+
+    pub fn fail(self, kind: io::ErrorKind) -> Self {
+        self.step(Step::Fail(kind))
+    }
+
+    pub fn expect_break(self) -> Self {
+        self.step(Step::Break)
+    }
+
+    /// Reads and writes fail with `BrokenPipe` from here on, a `ConnectionError` to the crate.
+    pub fn disconnect(self) -> Self {
+        self.step(Step::Disconnect)
+    }
+
+    /// Everything written so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    /// Panics unless the whole script was played and every reply read.
+    pub fn assert_done(&self) {
+        let state = self.state.lock().unwrap();
+        let left: Vec<String> = state.script.iter().map(Step::to_string).collect();
+        assert!(left.is_empty(), "MockSerial: script not finished, left: {}", left.join(", "));
+        assert!(state.rx.is_empty(), "MockSerial: reply not read: \"{}\"", state.rx.iter().flatten().copied().collect::<Vec<u8>>().escape_ascii());
+    }
+
+    /// Port settings as last set through the `SerialPort` trait.
+    pub fn settings(&self) -> SerialSettings {
+        self.state.lock().unwrap().settings
+    }
+}
+
+impl Read for MockSerial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(reply) = state.rx.front_mut() {
+                let n = buf.len().min(reply.len());
+                buf[..n].copy_from_slice(&reply[..n]);
+                reply.drain(..n);
+                if reply.is_empty() { state.rx.pop_front(); }
+                return Ok(n);
+            }
+            if let Some(e) = state.failure() { return Err(e); }
+        }
+        // nothing due yet: a quiet line, as a real port would report it
+        thread::sleep(self.timeout);
+        Err(io::Error::new(io::ErrorKind::TimedOut, "mock read timed out"))
+    }
+}
+
+impl Write for MockSerial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.state.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl serialport::SerialPort for MockSerial {
+    fn name(&self) -> Option<String> {
+        Some("mock".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().unwrap().settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.state.lock().unwrap().settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.state.lock().unwrap().settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.state.lock().unwrap().settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.state.lock().unwrap().settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.state.lock().unwrap().settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.state.lock().unwrap().settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.state.lock().unwrap().settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.state.lock().unwrap().settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.state.lock().unwrap().settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().unwrap().rx.iter().map(Vec::len).sum::<usize>() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    /// Only drops replies already due; the script itself is left alone.
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if !matches!(buffer_to_clear, ClearBuffer::Output) { self.state.lock().unwrap().rx.clear(); }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.script.front() {
+            Some(Step::Break) => {
+                state.script.pop_front();
+                state.advance();
+                Ok(())
+            }
+            next => panic!("MockSerial: unexpected break\n  next step: {}", next.map_or("end of script".to_string(), |step| step.to_string())),
+        }
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+/// A port that plays back one scripted outcome per `write`: accept up to n bytes, or fail.
+/// Once the script runs out it stalls for good, every write timing out.
+#[derive(Debug, Default)]
+pub struct FlakyWriter {
+    outcomes: VecDeque<io::Result<usize>>,
+    written: Vec<u8>,
+}
+
+impl FlakyWriter {
+    pub fn new(outcomes: Vec<io::Result<usize>>) -> Self {
+        Self { outcomes: outcomes.into(), written: Vec::new() }
+    }
+
+    /// Everything the writes accepted.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+}
+
+impl Write for FlakyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.outcomes.pop_front().unwrap_or_else(|| Err(io::ErrorKind::TimedOut.into()))?.min(buf.len());
+        self.written.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What goes wrong with one block on its way to a [`BlockLoader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A bit of the block flips on the way in.
+    Corrupt,
+    /// The block is stored but its ACK is lost.
+    DropAck,
+}
+
+/// A loader speaking the block protocol that applies one scripted fault per transmission,
+/// then checks the block and answers like the real one.
+#[derive(Debug, Default)]
+pub struct BlockLoader {
+    faults: VecDeque<Option<Fault>>,
+    incoming: Vec<u8>,
+    replies: VecDeque<u8>,
+    stored: Vec<u8>,
+    next_seq: u16,
+    transmissions: usize,
+}
+
+impl BlockLoader {
+    pub fn new(faults: &[Option<Fault>]) -> Self {
+        Self { faults: faults.iter().copied().collect(), ..Self::default() }
+    }
+
+    /// The image as far as it arrived in order.
+    pub fn stored(&self) -> &[u8] {
+        &self.stored
+    }
+
+    /// How many blocks were sent, retransmissions included.
+    pub fn transmissions(&self) -> usize {
+        self.transmissions
+    }
+
+    fn reply(&mut self, verdict: u8, seq: u16) {
+        self.replies.push_back(verdict);
+        self.replies.extend(seq.to_le_bytes().iter());
+    }
+
+    fn receive(&mut self, mut block: Vec<u8>) {
+        self.transmissions += 1;
+        let fault = self.faults.pop_front().flatten();
+        if fault == Some(Fault::Corrupt) { block[6] ^= 0x10; }
+        match block::decode_block(&block) {
+            Err(_) => self.reply(block::NAK, u16::from_le_bytes([block[1], block[2]])),
+            Ok((seq, data)) => {
+                if seq == self.next_seq {
+                    self.stored.extend_from_slice(data);
+                    self.next_seq = self.next_seq.wrapping_add(1);
+                }
+                if fault != Some(Fault::DropAck) { self.reply(block::ACK, seq); }
+            }
+        }
+    }
+}
+
+impl Read for BlockLoader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.replies.is_empty() { return Err(io::ErrorKind::TimedOut.into()); }
+        let n = buf.len().min(self.replies.len());
+        buf.iter_mut().take(n).for_each(|b| *b = self.replies.pop_front().unwrap());
+        Ok(n)
+    }
+}
+
+impl Write for BlockLoader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.incoming.extend_from_slice(buf);
+        // a block is its 5-byte header, the data and a 2-byte checksum
+        while self.incoming.len() >= 5 {
+            let len = u16::from_le_bytes([self.incoming[3], self.incoming[4]]) as usize + 7;
+            if self.incoming.len() < len { break; }
+            let block = self.incoming.drain(..len).collect();
+            self.receive(block);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
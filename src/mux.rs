@@ -0,0 +1,421 @@
+//! Two ports in one session, e.g. a board's console and the debug UART of the chip next to it.
+//! Each port is read on a thread of its own that also waits for it and reopens it, so one
+//! coming and going leaves the other alone. Their lines are shown interleaved as they
+//! complete, tagged `[A]` and `[B]`; what is typed goes to the port in focus.
+
+use std::{io::{self, IsTerminal}, sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{self, RecvTimeoutError, Sender}, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use crossterm::{cursor::MoveTo, event::KeyCode, execute, style::Color, terminal::{Clear, ClearType}};
+
+use crate::{ansi::Sanitizer, command::{Chord, Command, CommandTable, key_name}, ErrorKind, filter::FilterChain, highlight::Highlighter, keys, limit::RateLimiter, output::{Icon, Output, Verbosity}, ReadSerial, Result, SerialPort, settings::SerialSettings, stats::SessionStats, transport::Target, trigger::Triggers};
+use crate::terminal::{self, BracketedPaste, ExitReason, Input, INPUT_POLL, LINE_IDLE, LineBuffer, RAW_MODE, READER_TIMEOUT, RxTap, TerminalOptions};
+
+/// Colors of the tags of the first and the second port.
+const TAG_COLORS: [Color; 2] = [Color::Cyan, Color::Magenta];
+
+/// How the tag of the `index`th port reads, `[A]` for the first.
+pub fn port_tag(index: usize) -> String {
+    format!("[{}]", (b'A' + index as u8) as char)
+}
+
+/// Time of day in UTC to the millisecond, e.g. `13:05:09.123`.
+pub fn time_of_day(at: SystemTime) -> String {
+    let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs() % (24 * 60 * 60);
+    format!("{:02}:{:02}:{:02}.{:03}", secs / 3600, secs % 3600 / 60, secs % 60, since.subsec_millis())
+}
+
+/// Marks where a line was cut because the port paused, e.g. after a prompt: at the end of the
+/// part shown then, and at the start of the rest once it comes. Tagged lines end in a `\n`
+/// either way, so this tells a cut line from one the target ended.
+pub const PARTIAL_MARK: &str = "…";
+
+/// One line of `text` behind `tag` and the time it came in, if there is one, ending in
+/// exactly one `\n`.
+pub fn tag_line(tag: &str, text: &str, stamp: Option<SystemTime>) -> String {
+    let text = text.trim_end_matches(['\r', '\n']);
+    match stamp {
+        Some(at) => format!("{} {} {}\n", tag, time_of_day(at), text),
+        None => format!("{} {}\n", tag, text),
+    }
+}
+
+/// Cuts the stream of one port into tagged lines. A partial line waits for its end like in
+/// [`LineBuffer`], and is shown on a line of its own once the port pauses, marked with
+/// [`PARTIAL_MARK`] so the log doesn't read as if the target ended it there.
+#[derive(Debug)]
+pub struct Tagger {
+    tag: String,
+    lines: LineBuffer,
+    /// `None` with `--raw-output`.
+    sanitizer: Option<Sanitizer>,
+    stamps: bool,
+    filters: FilterChain,
+}
+
+impl Tagger {
+    pub fn new(tag: String, stamps: bool) -> Self {
+        Self { tag, lines: LineBuffer::new(LINE_IDLE), sanitizer: Some(Sanitizer::default()), stamps, filters: FilterChain::default() }
+    }
+
+    /// What the port's stream goes through before it is cut into lines and stamped. The tagged
+    /// lines are what is both shown and logged, so this takes the display's and the log's filters.
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+    }
+
+    /// Whether escape sequences other than colors and cursor moves are passed on as they are;
+    /// without `escapes`, a console that can't show them, none is.
+    pub fn set_raw_output(&mut self, raw: bool, escapes: bool) {
+        self.sanitizer = if raw && escapes { None } else { Some(Sanitizer::for_terminal(escapes)) };
+    }
+
+    /// How long the port has to pause before a partial line is shown, see [`LINE_IDLE`].
+    pub fn set_line_idle(&mut self, idle: Duration) {
+        self.lines.set_idle(idle);
+    }
+
+    /// The lines complete now that `data` arrived, at `now` and `at` in wall-clock time.
+    pub fn push(&mut self, data: &[u8], now: Instant, at: SystemTime) -> Vec<String> {
+        let filtered = if self.filters.is_empty() { None } else { Some(self.filters.apply(data)) };
+        let continued = self.lines.continues();
+        let lines = self.lines.push(filtered.as_deref().unwrap_or(data), now);
+        lines.iter().enumerate().map(|(i, line)| self.tag(line, at, continued && i == 0, false)).collect()
+    }
+
+    /// The partial line, once the port has paused long enough by `now`.
+    pub fn flush_idle(&mut self, now: Instant, at: SystemTime) -> Option<String> {
+        let continued = self.lines.continues();
+        self.lines.flush_idle(now).map(|line| self.tag(&line, at, continued, true))
+    }
+
+    /// The partial line, right away, e.g. once the port is gone.
+    pub fn flush(&mut self, at: SystemTime) -> Option<String> {
+        let continued = self.lines.continues();
+        self.lines.flush().map(|line| self.tag(&line, at, continued, true))
+    }
+
+    /// `line` tagged, marked as going on with a cut line and as cut itself.
+    fn tag(&mut self, line: &[u8], at: SystemTime, continued: bool, partial: bool) -> String {
+        let text = String::from_utf8_lossy(line);
+        let text = match self.sanitizer.as_mut() {
+            Some(sanitizer) => sanitizer.sanitize(&text),
+            None => text.into_owned(),
+        };
+        let text = format!("{}{}{}", if continued { PARTIAL_MARK } else { "" }, text.trim_end_matches(['\r', '\n']),
+                           if partial { PARTIAL_MARK } else { "" });
+        tag_line(&self.tag, &text, Some(at).filter(|_| self.stamps))
+    }
+}
+
+/// The bindings of the monitor: Ctrl-A o types into the other port.
+pub fn commands(prefix: u8) -> CommandTable {
+    CommandTable::new(prefix)
+        .bind(b'o', Command::SwitchPort)
+        .bind(b'b', Command::SendBreak)
+        .bind(b'c', Command::ClearScreen)
+        .bind(b'q', Command::Quit)
+        .bind(b'h', Command::Help)
+}
+
+/// What a port's reader thread tells the renderer.
+enum PortEvent {
+    Data(Vec<u8>),
+    /// Not there at the start; it is waited for.
+    Waiting,
+    Lost,
+    Back,
+}
+
+struct MuxPort {
+    name: String,
+    settings: SerialSettings,
+    opened: Option<SerialPort>,
+}
+
+/// The monitor of two ports, set up like a [`Terminal`](crate::terminal::Terminal). It runs
+/// until the user quits; a port that goes away is waited for, however long that takes.
+pub struct Mux {
+    out: Output,
+    options: TerminalOptions,
+    ports: Vec<MuxPort>,
+    stamps: bool,
+    highlighter: Highlighter,
+    triggers: Triggers,
+    taps: Vec<Box<dyn RxTap>>,
+    stats: Option<Arc<SessionStats>>,
+}
+
+impl Mux {
+    pub fn new(out: Output, options: TerminalOptions) -> Self {
+        Self { out, options, ports: Vec::new(), stamps: false, highlighter: Highlighter::default(), triggers: Triggers::default(), taps: Vec::new(), stats: None }
+    }
+
+    /// Adds the next port, tagged `[A]` for the first; `opened` if it is open already, it is
+    /// waited for otherwise.
+    pub fn port(mut self, name: &str, settings: SerialSettings, opened: Option<SerialPort>) -> Self {
+        self.ports.push(MuxPort { name: name.to_string(), settings, opened });
+        self
+    }
+
+    /// Stamps each line with the time it came in.
+    pub fn timestamps(mut self, stamps: bool) -> Self {
+        self.stamps = stamps;
+        self
+    }
+
+    pub fn highlighter(mut self, highlighter: Highlighter) -> Self {
+        // highlighting is coloring, so it follows --color and NO_COLOR
+        self.highlighter = if self.out.target_color() { highlighter } else { Highlighter::default() };
+        self
+    }
+
+    pub fn triggers(mut self, triggers: Triggers) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    /// Gets the tagged lines as they are shown, uncolored, e.g. the `--log` file.
+    pub fn tap(mut self, tap: Box<dyn RxTap>) -> Self {
+        self.taps.push(tap);
+        self
+    }
+
+    /// Counts both directions of both ports, and how often one came back.
+    pub fn stats(mut self, stats: Arc<SessionStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    pub fn run(self) -> Result<ExitReason> {
+        let out = self.out.clone();
+        let result = self.session();
+        out.set_raw(false);
+        result
+    }
+
+    fn session(self) -> Result<ExitReason> {
+        let Mux { out, options, ports, stamps, highlighter, triggers, mut taps, stats } = self;
+        let names: Vec<String> = ports.iter().map(|port| port.name.clone()).collect();
+        let quit = Arc::new(AtomicBool::new(false));
+        let (events, received) = mpsc::channel();
+        // the writing half of each port while it is there
+        let writers: Vec<Arc<Mutex<Option<SerialPort>>>> = ports.iter().map(|_| Arc::new(Mutex::new(None))).collect();
+
+        let readers: Vec<_> = ports.into_iter().zip(&writers).enumerate().map(|(index, (port, writer))| {
+            let (events, writer, quit, options) = (events.clone(), writer.clone(), quit.clone(), options.clone());
+            thread::spawn(move || read_port(index, port, &writer, &events, &quit, &options))
+        }).collect();
+        drop(events);
+
+        let renderer_out = out.clone();
+        let mut taggers: Vec<Tagger> = (0..names.len()).map(|index| {
+            let mut tagger = Tagger::new(port_tag(index), stamps);
+            tagger.set_raw_output(options.raw_output, out.escapes());
+            tagger.set_line_idle(options.line_idle);
+            let mut filters = options.display_filters.clone();
+            filters.extend(options.log_filters.iter().filter(|kind| !options.display_filters.contains(kind)));
+            tagger.set_filters(FilterChain::new(&filters));
+            tagger
+        }).collect();
+        let renderer_names = names.clone();
+        let renderer_stats = stats.clone();
+        let renderer = thread::spawn(move || {
+            let show = |lines: Vec<String>, index: usize, taps: &mut Vec<Box<dyn RxTap>>| for line in lines {
+                taps.iter_mut().for_each(|tap| tap.rx(line.as_bytes()));
+                // the tag is ours to color, the rest is the target's
+                let (tag, text) = line.split_at(3);
+                let painted = format!("{}{}", renderer_out.paint_target(tag, TAG_COLORS[index % 2]), highlighter.paint(text));
+                terminal::print_rx(&painted, renderer_out.is_terminal());
+                triggers.matching(text).for_each(|action| terminal::fire(action, text.trim(), &renderer_out));
+            };
+            loop {
+                let event = received.recv_timeout(LINE_IDLE);
+                let (now, at) = (Instant::now(), SystemTime::now());
+                match event {
+                    Ok((index, PortEvent::Data(data))) => {
+                        if let Some(stats) = &renderer_stats { stats.add_received(data.len() as u64); }
+                        let lines = taggers[index].push(&data, now, at);
+                        show(lines, index, &mut taps);
+                    }
+                    Ok((index, PortEvent::Waiting)) => renderer_out.warn(format!("{} {} not there yet, waiting for it…", port_tag(index), renderer_names[index])),
+                    Ok((index, PortEvent::Lost)) => {
+                        let rest = taggers[index].flush(at);
+                        show(rest.into_iter().collect(), index, &mut taps);
+                        renderer_out.warn(format!("{} {} gone, waiting for it…", port_tag(index), renderer_names[index]));
+                    }
+                    Ok((index, PortEvent::Back)) => {
+                        if let Some(stats) = &renderer_stats { stats.add_reconnect(); }
+                        renderer_out.status(format!("{} {} {} is back", renderer_out.icon(Icon::Ok), port_tag(index), renderer_names[index]));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                for (index, tagger) in taggers.iter_mut().enumerate() {
+                    let rest = tagger.flush_idle(now, at);
+                    show(rest.into_iter().collect(), index, &mut taps);
+                }
+                if renderer_out.is_terminal() { let _ = io::Write::flush(&mut io::stdout()); }
+            }
+            // what the ports left unfinished
+            for (index, tagger) in taggers.iter_mut().enumerate() {
+                let rest = tagger.flush(SystemTime::now());
+                show(rest.into_iter().collect(), index, &mut taps);
+            }
+        });
+
+        let result = input(&out, &options, &names, &writers, &quit, stats.as_deref());
+        quit.store(true, Ordering::Relaxed);
+        readers.into_iter().for_each(|reader| { let _ = reader.join(); });
+        let _ = renderer.join();
+        result
+    }
+}
+
+/// Reads one port until the session ends, waiting for it whenever it isn't there.
+fn read_port(index: usize, port: MuxPort, writer: &Mutex<Option<SerialPort>>, events: &Sender<(usize, PortEvent)>, quit: &AtomicBool, options: &TerminalOptions) {
+    let MuxPort { name, settings, opened } = port;
+    let (read_only, read_size) = (options.read_only, options.read_size(settings.baud_rate));
+    let target = Target::parse(&name);
+    let mut opened = opened;
+    if opened.is_none() { let _ = events.send((index, PortEvent::Waiting)); }
+    let mut buf = vec![0; options.batch_size(settings.baud_rate)];
+    while !quit.load(Ordering::Relaxed) {
+        let (mut reader, back) = match opened.take() {
+            Some(port) => (port, false),
+            None => match wait_for(&target, &settings, quit) {
+                Some(port) => (port, true),
+                None => return,
+            },
+        };
+        match attach(&mut reader, read_only) {
+            Ok(half) => *writer.lock().unwrap() = Some(half),
+            Err(_) => continue,
+        }
+        if back { let _ = events.send((index, PortEvent::Back)); }
+        while !quit.load(Ordering::Relaxed) {
+            match reader.read_serial_drain(&mut buf, read_size) {
+                Ok(0) => {}
+                Ok(n) => { let _ = events.send((index, PortEvent::Data(buf[..n].to_vec()))); }
+                Err(_) => {
+                    *writer.lock().unwrap() = None;
+                    let _ = events.send((index, PortEvent::Lost));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Sets a port up for reading and hands back its writing half.
+fn attach(port: &mut SerialPort, read_only: bool) -> Result<SerialPort> {
+    port.set_timeout(READER_TIMEOUT)?;
+    if read_only { terminal::release_control_lines(port); }
+    Ok(port.try_clone()?)
+}
+
+/// Checks once a second for the port until it opens or the session ends.
+fn wait_for(target: &Target, settings: &SerialSettings, quit: &AtomicBool) -> Option<SerialPort> {
+    loop {
+        if target.is_present() {
+            if let Ok(port) = target.open(settings, Duration::from_millis(1)) { return Some(port); }
+        }
+        for _ in 0..10 {
+            if quit.load(Ordering::Relaxed) { return None; }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// The input loop: keys go to the port in focus, the first one to begin with. Without a
+/// terminal on stdin nothing is sent, and the session lasts until `exit_after`, if ever.
+fn input(out: &Output, options: &TerminalOptions, names: &[String], writers: &[Arc<Mutex<Option<SerialPort>>>], quit: &AtomicBool,
+         stats: Option<&SessionStats>) -> Result<ExitReason> {
+    let commands = commands(terminal::COMMAND_PREFIX);
+    let exit_key = key_name(options.exit_key);
+    if !io::stdin().is_terminal() {
+        out.status("Monitoring, piped input is not sent");
+        let deadline = options.exit_after.map(|after| Instant::now() + after);
+        while deadline.is_none_or(|deadline| Instant::now() < deadline) { thread::sleep(INPUT_POLL); }
+        return Ok(ExitReason::ExitAfter);
+    }
+    if options.exit_after.is_some() {
+        out.warn("--exit-after only ends a session with piped stdin, not one typed into");
+    }
+    if options.read_only {
+        out.status(format!("Read-only, nothing typed is sent; {} or Ctrl-C quits", exit_key));
+    } else {
+        out.status(format!("{} quits, {} o switches ports, {} h for help", exit_key, key_name(commands.prefix()), key_name(commands.prefix())));
+    }
+    let _raw = RAW_MODE.guard()?;
+    out.set_raw(true);
+    let _bracketed = (options.bracketed_paste && out.is_terminal()).then(BracketedPaste::enable);
+
+    let mut focus = 0;
+    let mut decoder = commands.decoder();
+    let mut limiter = options.limit.map(RateLimiter::new);
+    let mut send_buf = Vec::with_capacity(256);
+    let mut send = |focus: usize, buf: &mut Vec<u8>| -> Result<()> {
+        let mut writer = writers[focus].lock().unwrap();
+        let sent = match writer.as_mut() {
+            Some(port) => terminal::send(port, buf, limiter.as_mut(), None, None, stats),
+            None => Err(ErrorKind::ConnectionError),
+        };
+        // the reader thread notices too, and waits for it
+        if let Err(ErrorKind::ConnectionError) = sent {
+            buf.clear();
+            out.warn(format!("not sent, {} {} is gone", port_tag(focus), names[focus]));
+            return Ok(());
+        }
+        sent
+    };
+    loop {
+        let inputs = match terminal::read_keys(INPUT_POLL) {
+            Ok(inputs) => inputs,
+            Err(e) if terminal::console_closed(&e) => return Ok(ExitReason::StdinClosed),
+            Err(e) => return Err(ErrorKind::IoError(e)),
+        };
+        if quit.load(Ordering::Relaxed) { return Ok(ExitReason::Stopped); }
+        for input in &inputs {
+            let key = match input {
+                Input::Key(key) => key,
+                Input::Paste(text) => {
+                    if !options.read_only { send_buf.extend_from_slice(text.as_bytes()); }
+                    continue;
+                }
+            };
+            let bytes = match if key.code == KeyCode::Enter { Some(options.newline.bytes().to_vec()) } else { keys::encode(key, options.keys) } {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            match keys::key_byte(key).map(|byte| decoder.feed(byte)) {
+                Some(Some(Chord::Forward(c))) if c == options.exit_key || (options.read_only && c == 0x03) => return Ok(ExitReason::ExitKey),
+                Some(Some(Chord::Command(key))) => {
+                    send(focus, &mut send_buf)?;
+                    match commands.lookup(key) {
+                        Some(Command::SwitchPort) => {
+                            focus = (focus + 1) % names.len();
+                            out.status(format!("— typing into {} {} —", port_tag(focus), names[focus]));
+                        }
+                        Some(Command::SendBreak) if options.read_only => out.warn("read-only, break not sent"),
+                        Some(Command::SendBreak) => match writers[focus].lock().unwrap().as_ref() {
+                            Some(port) => {
+                                terminal::send_break(port, options.break_duration)?;
+                                out.status(format!("— break sent to {} —", port_tag(focus)));
+                            }
+                            None => out.warn(format!("break not sent, {} {} is gone", port_tag(focus), names[focus])),
+                        },
+                        Some(Command::ClearScreen) => { let _ = execute!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0)); }
+                        Some(Command::Quit) => return Ok(ExitReason::QuitCommand),
+                        _ => out.line(Verbosity::Quiet, commands.help()),
+                    }
+                }
+                Some(None) => {}
+                None if decoder.cancel() => out.line(Verbosity::Quiet, commands.help()),
+                _ if options.read_only => {}
+                _ => send_buf.extend_from_slice(&bytes),
+            }
+        }
+        send(focus, &mut send_buf)?;
+    }
+}
@@ -0,0 +1,154 @@
+//! Callbacks on the lifecycle of a push, for front-ends that show it their own way. What the
+//! tools print about it comes from [`ConsoleObserver`], the observer they start with.
+
+use std::{sync::{Mutex, OnceLock}, time::{Duration, Instant}};
+
+use serde::{Serialize, Serializer};
+
+use crate::{ErrorKind, identity::TargetIdentity, output::{format_bytes, format_duration, Icon, Output, Progress}, protocol::LoaderInfo, settings::SerialSettings, sha256, transport::Target};
+
+/// How a finished push went; serialized for `--report`, the digest in hex.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PushReport {
+    /// Bytes sent by this attempt, without what a resumed push skipped.
+    pub bytes: u64,
+    pub total: u64,
+    pub seconds: f64,
+    /// Blocks of the block protocol sent more than once.
+    pub retransmitted: u64,
+    /// Of the whole image, including what a resumed push skipped.
+    #[serde(serialize_with = "hex")]
+    pub sha256: [u8; 32],
+    /// What the loader said it supports, or was taken to with `--protocol-version`; `None` for
+    /// an old loader, or one never asked.
+    pub loader: Option<LoaderInfo>,
+    /// What the target said it is; `None` unless it was asked and answered.
+    pub target: Option<TargetIdentity>,
+}
+
+impl PushReport {
+    /// Bytes per second this attempt sent.
+    pub fn rate(&self) -> f64 {
+        if self.seconds > 0.0 { self.bytes as f64 / self.seconds } else { 0.0 }
+    }
+}
+
+fn hex<S: Serializer>(digest: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&sha256::hex(digest))
+}
+
+/// Every method does nothing by default. `Send`, since some phases run on their own thread.
+pub trait PushObserver: Send {
+    /// The port isn't there yet; called again whenever the reason changes.
+    fn waiting_for_serial(&mut self, _port: &str) {}
+    /// Still not there after `elapsed`, about every second.
+    fn still_waiting(&mut self, _port: &str, _elapsed: Duration) {}
+    fn connected(&mut self, _port: &str, _settings: &SerialSettings) {}
+    /// The loader asked for the image.
+    fn handshake_complete(&mut self) {}
+    /// The loader acknowledged the size.
+    fn size_sent(&mut self, _bytes: u64) {}
+    /// The loader agreed to continue an interrupted push at `offset`.
+    fn resumed(&mut self, _offset: u64, _total: u64) {}
+    /// The loader holds the image pushed last and takes only the `changed` bytes of `total`;
+    /// `progress` then counts those.
+    fn delta(&mut self, _changed: u64, _total: u64) {}
+    /// After every chunk; `sent` includes what a resumed push skipped.
+    fn progress(&mut self, _sent: u64, _total: u64) {}
+    fn finished(&mut self, _report: &PushReport) {}
+    /// The attempt failed; the tool reconnects after the errors it can recover from.
+    fn error(&mut self, _kind: &ErrorKind) {}
+}
+
+/// How often a wait for the port is reported where it can't be redrawn in place.
+pub const WAIT_STATUS_EVERY: Duration = Duration::from_secs(30);
+
+/// Status lines and the progress bar on the tool's [`Output`].
+pub struct ConsoleObserver {
+    out: Output,
+    progress: Option<Progress>,
+    /// When a wait for the port was last reported in a line of its own.
+    reported: Option<Instant>,
+}
+
+impl ConsoleObserver {
+    pub fn new(out: Output) -> Self {
+        Self { out, progress: None, reported: None }
+    }
+}
+
+impl PushObserver for ConsoleObserver {
+    /// On a terminal, the line then counts the seconds, see [`ConsoleObserver::still_waiting`].
+    fn waiting_for_serial(&mut self, port: &str) {
+        self.reported = Some(Instant::now());
+        if self.out.is_diagnostic_terminal() {
+            self.out.transient(format!("{} Waiting for {}, Ctrl-C quits", self.out.icon(Icon::Wait), port));
+        } else {
+            self.out.status(format!("{} Waiting for {}", self.out.icon(Icon::Wait), port));
+        }
+    }
+
+    fn still_waiting(&mut self, port: &str, elapsed: Duration) {
+        if self.out.is_diagnostic_terminal() {
+            self.out.transient(format!("{} Waiting for {} ({}), Ctrl-C quits", self.out.icon(Icon::Wait), port, format_duration(elapsed)));
+        } else if self.reported.is_none_or(|reported| reported.elapsed() >= WAIT_STATUS_EVERY) {
+            self.reported = Some(Instant::now());
+            self.out.status(format!("{} Still waiting for {} ({}s)", self.out.icon(Icon::Wait), port, elapsed.as_secs()));
+        }
+    }
+
+    fn connected(&mut self, port: &str, settings: &SerialSettings) {
+        if Target::parse(port).fixed_settings() {
+            self.out.status(format!("{} Connected (raw TCP, --baud and the framing are left to the terminal server)", self.out.icon(Icon::Ok)));
+            return;
+        }
+        self.out.status(format!("{} Connected ({})", self.out.icon(Icon::Ok), settings));
+    }
+
+    fn resumed(&mut self, offset: u64, total: u64) {
+        self.out.status(format!("{} Resuming at {} of {}", self.out.icon(Icon::Push), format_bytes(offset), format_bytes(total)));
+    }
+
+    fn delta(&mut self, changed: u64, total: u64) {
+        self.out.status(format!("{} Sending the {} that changed of {}", self.out.icon(Icon::Push), format_bytes(changed), format_bytes(total)));
+    }
+
+    fn progress(&mut self, sent: u64, total: u64) {
+        if self.progress.is_none() { self.progress = self.out.progress_bar(Icon::Push, "Pushing", total); }
+        if let Some(pb) = self.progress.as_mut() { pb.set(sent); }
+    }
+
+    fn finished(&mut self, report: &PushReport) {
+        self.out.finish_progress(self.progress.take());
+        if report.retransmitted > 0 { self.out.verbose(format!("{} blocks sent again", report.retransmitted)); }
+        self.out.status(format!("send finish! {} in {:.1}s, SHA-256 {}", format_bytes(report.bytes), report.seconds, sha256::hex(&report.sha256)));
+    }
+
+    fn error(&mut self, _kind: &ErrorKind) {
+        // a bar left open would swallow the error message that follows
+        self.out.finish_progress(self.progress.take());
+    }
+}
+
+/// A tool's observer: the console one unless another was set.
+/// Created on first use, so it sees the output settings the tool ends up with.
+#[derive(Default)]
+pub struct ObserverSlot(OnceLock<Mutex<Box<dyn PushObserver>>>);
+
+/// A copy starts out empty, with the console observer of whatever output it is used with; an
+/// observer that was set stays with the original.
+impl Clone for ObserverSlot {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl ObserverSlot {
+    pub fn set(&mut self, observer: Box<dyn PushObserver>) {
+        self.0 = OnceLock::from(Mutex::new(observer));
+    }
+
+    pub fn get(&self, out: &Output) -> &Mutex<Box<dyn PushObserver>> {
+        self.0.get_or_init(|| Mutex::new(Box::new(ConsoleObserver::new(out.clone()))))
+    }
+}
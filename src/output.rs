@@ -0,0 +1,571 @@
+//! Status messages of a tool, filtered by verbosity and aware of raw mode and the progress bar.
+//!
+//! There are two streams: the target's output goes to stdout and nowhere else, what the tool
+//! says (messages, progress bars, the prompt) to stderr. `mini_term … > target.log` thus
+//! still shows the tool's messages, and `2> tool.log` keeps them out of the way. Each stream
+//! is colored and redrawn in place only when it is a terminal itself.
+//!
+//! A Windows console is switched to virtual terminal processing to take escape sequences; one
+//! too old for that gets no colors, and lines are erased through the console API instead.
+
+use std::{env, fmt, io::{self, IsTerminal, stderr, Stderr, stdout, Write}, str::FromStr, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+use crossterm::{queue, style::{Color, style, Stylize}, terminal::{self, Clear, ClearType}};
+
+use crate::create_pb;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Errors and results only.
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+    /// Every handshake byte and chunk boundary.
+    Trace,
+}
+
+impl Verbosity {
+    /// `-q` wins over any number of `-v`.
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Trace,
+        }
+    }
+}
+
+/// `--color`: `auto` colors only a terminal and honors `NO_COLOR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    Always,
+    #[default]
+    Auto,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn resolve(self, is_tty: bool, no_color: Option<&str>) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_tty && no_color.is_none_or(str::is_empty),
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(ColorChoice::Always),
+            "auto" => Ok(ColorChoice::Auto),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err("expected always, auto or never".to_string()),
+        }
+    }
+}
+
+/// `--progress`: `auto` draws a bar only on a terminal; `never` reports a line every 10 %
+/// instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressChoice {
+    Always,
+    #[default]
+    Auto,
+    Never,
+}
+
+impl ProgressChoice {
+    pub fn resolve(self, is_tty: bool) -> bool {
+        match self {
+            ProgressChoice::Always => true,
+            ProgressChoice::Never => false,
+            ProgressChoice::Auto => is_tty,
+        }
+    }
+}
+
+impl FromStr for ProgressChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(ProgressChoice::Always),
+            "auto" => Ok(ProgressChoice::Auto),
+            "never" => Ok(ProgressChoice::Never),
+            _ => Err("expected always, auto or never".to_string()),
+        }
+    }
+}
+
+/// Decoration of a status line: an emoji when colored, an ASCII tag otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icon {
+    Wait,
+    Ok,
+    Fail,
+    Error,
+    Warn,
+    Reset,
+    Power,
+    Network,
+    Save,
+    Loop,
+    Script,
+    Timer,
+    Push,
+    Build,
+    Bye,
+}
+
+impl Icon {
+    pub fn emoji(self) -> &'static str {
+        match self {
+            Icon::Wait => "⏳",
+            Icon::Ok => "✅",
+            Icon::Fail => "🚫",
+            Icon::Error => "⚡",
+            Icon::Warn => "⚠",
+            Icon::Reset => "🔄",
+            Icon::Power => "🔌",
+            Icon::Network => "🌐",
+            Icon::Save => "💾",
+            Icon::Loop => "🔁",
+            Icon::Script => "📜",
+            Icon::Timer => "⏱",
+            Icon::Push => "⏩",
+            Icon::Build => "🔨",
+            Icon::Bye => "👋",
+        }
+    }
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            Icon::Wait => "[WAIT]",
+            Icon::Ok => "[OK]",
+            Icon::Fail => "[FAIL]",
+            Icon::Error => "[ERROR]",
+            Icon::Warn => "[WARN]",
+            Icon::Reset => "[RESET]",
+            Icon::Power => "[POWER]",
+            Icon::Network => "[NET]",
+            Icon::Save => "[SAVE]",
+            Icon::Loop => "[LOOP]",
+            Icon::Script => "[SCRIPT]",
+            Icon::Timer => "[TIME]",
+            Icon::Push => "[PUSH]",
+            Icon::Build => "[BUILD]",
+            Icon::Bye => "[BYE]",
+        }
+    }
+}
+
+/// Cheap to clone; clones share the raw-mode, progress-bar and transient line state.
+#[derive(Debug, Clone)]
+pub struct Output {
+    name_short: String,
+    verbosity: Verbosity,
+    /// Messages are colored and decorated with emoji.
+    color: bool,
+    /// The target's output may be colored, e.g. highlighted.
+    target_color: bool,
+    /// Stdout, the target stream, is a terminal, not a pipe or file.
+    terminal: bool,
+    /// Stderr, the diagnostic stream, is a terminal.
+    diagnostic_terminal: bool,
+    /// The terminal, if either stream is one, shows escape sequences as what they mean.
+    escapes: bool,
+    progress: bool,
+    /// How often a progress bar is redrawn at most.
+    redraw: Duration,
+    raw: Arc<AtomicBool>,
+    bar: Arc<AtomicBool>,
+    transient: Arc<AtomicBool>,
+}
+
+impl Output {
+    pub fn new(name_short: &str, verbosity: Verbosity) -> Self {
+        let escapes = !(stdout().is_terminal() || stderr().is_terminal()) || console_escapes();
+        Self {
+            name_short: name_short.to_string(),
+            verbosity,
+            color: escapes && ColorChoice::Auto.resolve(stderr().is_terminal(), env::var("NO_COLOR").ok().as_deref()),
+            target_color: escapes && ColorChoice::Auto.resolve(stdout().is_terminal(), env::var("NO_COLOR").ok().as_deref()),
+            terminal: stdout().is_terminal(),
+            diagnostic_terminal: stderr().is_terminal(),
+            escapes,
+            progress: stderr().is_terminal(),
+            redraw: BAR_REDRAW,
+            raw: Arc::new(AtomicBool::new(false)),
+            bar: Arc::new(AtomicBool::new(false)),
+            transient: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The same output, its messages prefixed with `[name_short]` instead, e.g. one per board.
+    pub fn renamed(&self, name_short: &str) -> Self {
+        Self { name_short: name_short.to_string(), ..self.clone() }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    /// `--color`, for each stream by whether it is a terminal.
+    pub fn set_color(&mut self, choice: ColorChoice) {
+        let no_color = env::var("NO_COLOR").ok();
+        self.color = self.escapes && choice.resolve(self.diagnostic_terminal, no_color.as_deref());
+        self.target_color = self.escapes && choice.resolve(self.terminal, no_color.as_deref());
+    }
+
+    /// Whether the terminal shows escape sequences as what they mean; without, nothing is
+    /// colored, whatever `--color` says, and the target's sequences are left out.
+    pub fn escapes(&self) -> bool {
+        self.escapes
+    }
+
+    /// Takes the place of what [`console_escapes`] found; call before [`Output::set_color`].
+    pub fn set_escapes(&mut self, escapes: bool) {
+        self.escapes = escapes;
+        self.color &= escapes;
+        self.target_color &= escapes;
+    }
+
+    /// `--progress`: a redrawn bar, or a line every 10 %.
+    pub fn set_progress(&mut self, choice: ProgressChoice) {
+        self.progress = choice.resolve(self.diagnostic_terminal);
+    }
+
+    /// How often progress is redrawn at most, [`BAR_REDRAW`] unless `--high-throughput` makes
+    /// it less often; also what a wait's spinner doesn't tick faster than.
+    pub fn set_redraw(&mut self, every: Duration) {
+        self.redraw = every;
+    }
+
+    pub fn redraw(&self) -> Duration {
+        self.redraw
+    }
+
+    /// Whether stdout, the target stream, is a terminal; into a pipe or file, the target's
+    /// lines end in a plain `\n` and nothing is cleared.
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// Whether stderr, the diagnostic stream, is a terminal, where status lines may be redrawn.
+    pub fn is_diagnostic_terminal(&self) -> bool {
+        self.diagnostic_terminal
+    }
+
+    /// Whether messages are colored and decorated with emoji.
+    pub fn color(&self) -> bool {
+        self.color
+    }
+
+    /// Whether the target's output may be colored, e.g. by `--highlight`.
+    pub fn target_color(&self) -> bool {
+        self.target_color
+    }
+
+    pub fn icon(&self, icon: Icon) -> &'static str {
+        if self.color { icon.emoji() } else { icon.tag() }
+    }
+
+    pub fn paint<D: fmt::Display>(&self, text: D, color: Color) -> String {
+        if self.color { style(text.to_string()).with(color).to_string() } else { text.to_string() }
+    }
+
+    /// Like [`Output::paint`], for what goes out on the target stream.
+    pub fn paint_target<D: fmt::Display>(&self, text: D, color: Color) -> String {
+        if self.target_color { style(text.to_string()).with(color).to_string() } else { text.to_string() }
+    }
+
+    pub fn enabled(&self, level: Verbosity) -> bool {
+        level <= self.verbosity
+    }
+
+    /// Lines end in `\r\n` while the terminal is in raw mode, unless stderr goes elsewhere.
+    pub fn set_raw(&self, raw: bool) {
+        self.raw.store(raw, Ordering::Relaxed);
+    }
+
+    /// Prints `[XX] message` if `level` is enabled.
+    pub fn line<D: fmt::Display>(&self, level: Verbosity, message: D) {
+        if !self.enabled(level) { return; }
+        let text = format!("[{}] {}", self.name_short, message);
+        self.write(&text);
+    }
+
+    /// Always shown, even with `-q`.
+    pub fn error<D: fmt::Display>(&self, message: D) {
+        self.line(Verbosity::Quiet, self.paint(message, Color::Red));
+    }
+
+    pub fn warn<D: fmt::Display>(&self, message: D) {
+        self.line(Verbosity::Normal, format!("{} {}", self.icon(Icon::Warn), self.paint(message, Color::Yellow)));
+    }
+
+    pub fn status<D: fmt::Display>(&self, message: D) {
+        self.line(Verbosity::Normal, message);
+    }
+
+    pub fn verbose<D: fmt::Display>(&self, message: D) {
+        self.line(Verbosity::Verbose, message);
+    }
+
+    pub fn trace<D: fmt::Display>(&self, message: D) {
+        self.line(Verbosity::Trace, message);
+    }
+
+    /// An empty line, e.g. to step off a partial line of target output.
+    pub fn blank(&self, level: Verbosity) {
+        if self.enabled(level) { self.write(""); }
+    }
+
+    /// The tool's title line, without the name prefix.
+    pub fn banner(&self, title: &str) {
+        if !self.enabled(Verbosity::Normal) { return; }
+        self.write(&format!("{}\n", self.paint(title, Color::Cyan)));
+    }
+
+    /// A progress bar labelled e.g. `[MP] ⏩ Pushing 14.0 MiB`, unless running quiet. Lines
+    /// printed while it is shown clear it first. Without `--progress`, e.g. into a CI log, or
+    /// where the terminal's width can't be told, the label is repeated with the percentage every
+    /// 10 % instead.
+    pub fn progress_bar(&self, icon: Icon, action: &str, total: u64) -> Option<Progress> {
+        if !self.enabled(Verbosity::Normal) { return None; }
+        let message = format!("[{}] {} {} {} ", self.name_short, self.icon(icon), action, format_bytes(total));
+        let width = match bar_width(terminal_columns()) {
+            Some(width) if self.progress => width,
+            _ => return Some(Progress::Lines { label: message, total, done: 0, reported: 0 }),
+        };
+        self.bar.store(true, Ordering::Relaxed);
+        Some(Progress::Bar(Box::new(Bar::new(create_pb(&message, total, !self.color, width), total, self.redraw))))
+    }
+
+    pub fn finish_progress(&self, pb: Option<Progress>) {
+        if let Some(Progress::Bar(bar)) = pb { bar.finish(); }
+        self.bar.store(false, Ordering::Relaxed);
+    }
+
+    /// Draws `[XX] message` over the previous one, e.g. a wait ticking along. Only on a terminal
+    /// and unless running quiet; the next line or [`Output::clear_transient`] erases it.
+    pub fn transient<D: fmt::Display>(&self, message: D) {
+        if !self.enabled(Verbosity::Normal) || !self.diagnostic_terminal { return; }
+        self.transient.store(true, Ordering::Relaxed);
+        let mut out = stderr();
+        erase_line(&mut out);
+        let _ = write!(out, "[{}] {}", self.name_short, message);
+        let _ = out.flush();
+    }
+
+    /// Erases what [`Output::transient`] drew, e.g. before target output goes there.
+    pub fn clear_transient(&self) {
+        if self.transient.swap(false, Ordering::Relaxed) {
+            let mut out = stderr();
+            erase_line(&mut out);
+            let _ = out.flush();
+        }
+    }
+
+    /// Erases the progress bar ahead of output that doesn't come through here, e.g. the
+    /// target's; the bar redraws itself below it on the next update.
+    pub fn clear_progress(&self) {
+        self.clear_transient();
+        if self.bar.load(Ordering::Relaxed) {
+            let mut out = stderr();
+            if self.color { erase_line(&mut out); } else { let _ = writeln!(out); }
+            let _ = out.flush();
+        }
+    }
+
+    fn write(&self, text: &str) {
+        self.clear_progress();
+        let raw = self.raw.load(Ordering::Relaxed) && self.diagnostic_terminal;
+        let mut out = stderr();
+        let text = if raw { text.replace('\n', "\r\n") } else { text.to_string() };
+        let _ = write!(out, "{}{}", text, if raw { "\r\n" } else { "\n" });
+        let _ = out.flush();
+    }
+}
+
+/// How often a progress bar is redrawn at most, however fast the chunks come.
+pub const BAR_REDRAW: Duration = Duration::from_millis(50);
+/// The same with `--high-throughput`, drawing taking time from reading.
+pub const HIGH_THROUGHPUT_REDRAW: Duration = Duration::from_millis(500);
+
+/// A progress bar is never drawn narrower than this, however narrow the terminal.
+pub const MIN_BAR_WIDTH: usize = 40;
+
+/// The first failed write to a file the session keeps on the side, e.g. a log on a full disk,
+/// as a warning. The session goes on regardless, and later failures of the same file are not
+/// repeated.
+#[derive(Debug, Default)]
+pub struct WriteWarning {
+    /// Where to warn; none stays silent.
+    out: Option<Output>,
+    what: String,
+    warned: AtomicBool,
+}
+
+impl WriteWarning {
+    /// Warns on `out` about writing `what`, e.g. the file's path.
+    pub fn new<D: fmt::Display>(out: &Output, what: D) -> Self {
+        Self { out: Some(out.clone()), what: what.to_string(), warned: AtomicBool::new(false) }
+    }
+
+    /// Warns if `result` is the first failure.
+    pub fn check(&self, result: io::Result<()>) {
+        let e = match result {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if self.warned.swap(true, Ordering::Relaxed) { return; }
+        if let Some(out) = &self.out { out.warn(format!("Writing {} failed, the session goes on: {}", self.what, e)); }
+    }
+}
+
+/// Lets a redraw through at most once per interval, timed with the instants passed in.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last: None }
+    }
+
+    /// Whether to redraw at `now`: the first time, and again once `interval` has passed.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        if self.last.is_some_and(|last| now.saturating_duration_since(last) < self.interval) { return false; }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// Width of a bar in a terminal `columns` wide, `None` when that isn't known.
+pub fn bar_width(columns: Option<u16>) -> Option<usize> {
+    columns.filter(|&columns| columns > 0).map(|columns| usize::from(columns).max(MIN_BAR_WIDTH))
+}
+
+fn terminal_columns() -> Option<u16> {
+    terminal::size().ok().map(|(columns, _)| columns)
+}
+
+/// Whether the console shows escape sequences as what they mean, on Windows once virtual
+/// terminal processing is on, which this turns on. A console before Windows 10 can't.
+pub fn console_escapes() -> bool {
+    #[cfg(windows)]
+    { crossterm::ansi_support::supports_ansi() }
+    #[cfg(not(windows))]
+    { true }
+}
+
+/// Back to the start of the line and erases it, by escape sequence or the Windows console API.
+pub(crate) fn erase_line<W: Write>(out: &mut W) {
+    let _ = write!(out, "\r");
+    let _ = queue!(out, Clear(ClearType::CurrentLine));
+}
+
+/// A drawn progress bar, redrawn at most every [`BAR_REDRAW`] or [`Output::set_redraw`], and as wide as the terminal is
+/// then, so a resized window gets a bar that fits.
+pub struct Bar {
+    pb: pbr::ProgressBar<Stderr>,
+    throttle: Throttle,
+    done: u64,
+    total: u64,
+}
+
+impl Bar {
+    fn new(pb: pbr::ProgressBar<Stderr>, total: u64, redraw: Duration) -> Self {
+        Self { pb, throttle: Throttle::new(redraw), done: 0, total }
+    }
+
+    fn set(&mut self, n: u64) {
+        self.done = n;
+        // reaching the end is always drawn
+        if n < self.total && !self.throttle.ready(Instant::now()) { return; }
+        if let Some(width) = bar_width(terminal_columns()) { self.pb.set_width(Some(width)); }
+        self.pb.set(n);
+    }
+
+    fn finish(mut self) {
+        self.pb.set(self.done);
+        self.pb.finish_println("");
+    }
+}
+
+/// Progress of a transfer, see [`Output::progress_bar`].
+pub enum Progress {
+    Bar(Box<Bar>),
+    /// Tenths of `total` reported so far in `reported`.
+    Lines { label: String, total: u64, done: u64, reported: u64 },
+}
+
+impl Progress {
+    pub fn add(&mut self, n: u64) {
+        match self {
+            Progress::Bar(bar) => bar.set(bar.done + n),
+            Progress::Lines { done, .. } => {
+                *done += n;
+                self.report();
+            }
+        }
+    }
+
+    /// Jumps to `n`, e.g. where a resumed push picks up.
+    pub fn set(&mut self, n: u64) {
+        match self {
+            Progress::Bar(bar) => bar.set(n),
+            Progress::Lines { done, .. } => {
+                *done = n;
+                self.report();
+            }
+        }
+    }
+
+    fn report(&mut self) {
+        if let Progress::Lines { label, total, done, reported } = self {
+            let tenths = ((*done).min(*total) * 10).checked_div(*total).unwrap_or(10);
+            if tenths > *reported {
+                *reported = tenths;
+                eprintln!("{}{}%", label, tenths * 10);
+            }
+        }
+    }
+}
+
+/// Human-readable size with binary units, e.g. `1023 B`, `1.5 KiB` or `14.0 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 { return format!("{} B", bytes); }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // round first so 1048575 B reads 1.0 MiB rather than 1024.0 KiB
+    while (value * 10.0).round() / 10.0 >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// A stretch of time to the second, e.g. `45s`, `5m 00s` or `26h 03m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
@@ -0,0 +1,69 @@
+//! Pasted input, sent slower than it arrived so a polled UART console doesn't drop characters.
+
+use std::{collections::VecDeque, time::Duration};
+
+/// Without bracketed paste, a batch of at least this many keys is a paste; nobody types that fast.
+pub const PASTE_THRESHOLD: usize = 16;
+
+/// A paste being drained byte by byte. A line ends at `\r` or at a `\n` not preceded by `\r`.
+#[derive(Debug, Clone)]
+pub struct Paste {
+    pending: VecDeque<u8>,
+    char_delay: Duration,
+    line_delay: Duration,
+    total: Progress,
+    sent: Progress,
+}
+
+/// Bytes and lines seen so far, the last line counting while still open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub bytes: usize,
+    pub lines: usize,
+    last: Option<u8>,
+}
+
+impl Progress {
+    /// Counts `byte` and tells whether it ends a line.
+    fn count(&mut self, byte: u8) -> bool {
+        let ends = byte == b'\r' || (byte == b'\n' && self.last != Some(b'\r'));
+        let opens = self.last.is_none_or(|last| last == b'\r' || last == b'\n');
+        if opens && !(byte == b'\n' && self.last == Some(b'\r')) { self.lines += 1; }
+        self.bytes += 1;
+        self.last = Some(byte);
+        ends
+    }
+}
+
+impl Paste {
+    pub fn new(char_delay: Duration, line_delay: Duration) -> Self {
+        Self { pending: VecDeque::new(), char_delay, line_delay, total: Progress::default(), sent: Progress::default() }
+    }
+
+    /// Queues more of the paste, e.g. a read that arrived while draining.
+    pub fn push(&mut self, data: &[u8]) {
+        for &b in data {
+            self.total.count(b);
+            self.pending.push_back(b);
+        }
+    }
+
+    /// The next byte to send and how long to wait after sending it.
+    pub fn next_byte(&mut self) -> Option<(u8, Duration)> {
+        let b = self.pending.pop_front()?;
+        let delay = if self.sent.count(b) { self.line_delay } else { self.char_delay };
+        Some((b, delay))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn sent(&self) -> Progress {
+        self.sent
+    }
+
+    pub fn total(&self) -> Progress {
+        self.total
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt, str::FromStr};
+
+use regex::bytes::Regex;
+
+use crate::script;
+
+/// Longest stretch of unmatched output kept around while waiting for a pattern.
+pub const MAX_PENDING: usize = 64 * 1024;
+
+/// Something to look for in the target's output: a plain substring or a regex.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Literal(Vec<u8>),
+    Regex(Box<Regex>),
+}
+
+impl Pattern {
+    pub fn literal<S: AsRef<[u8]>>(s: S) -> Self {
+        Pattern::Literal(s.as_ref().to_vec())
+    }
+
+    pub fn regex(s: &str) -> Result<Self, regex::Error> {
+        Ok(Pattern::Regex(Box::new(Regex::new(s)?)))
+    }
+
+    /// Byte range of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
+        self.find_at(haystack, 0)
+    }
+
+    /// Like [`Pattern::find`] for a match starting at `start` or later, with the bytes before
+    /// still there for anchors like `^` and `\b` to look at.
+    pub fn find_at(&self, haystack: &[u8], start: usize) -> Option<(usize, usize)> {
+        match self {
+            Pattern::Literal(needle) if needle.is_empty() => Some((start, start)),
+            Pattern::Literal(needle) => haystack[start..].windows(needle.len())
+                .position(|w| w == &needle[..])
+                .map(|at| (start + at, start + at + needle.len())),
+            Pattern::Regex(re) => re.find_at(haystack, start).map(|m| (m.start(), m.end())),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    /// `str:login:` with `\r`-style escapes, or `regex:Kernel panic|login:`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            Some(("str", text)) if !text.is_empty() => Ok(Pattern::Literal(script::unescape(text)?)),
+            Some(("regex", re)) if !re.is_empty() => Pattern::regex(re).map_err(|e| e.to_string()),
+            _ => Err("expected str:TEXT or regex:REGEX".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Literal(needle) => write!(f, "{:?}", String::from_utf8_lossy(needle)),
+            Pattern::Regex(re) => write!(f, "/{}/", re.as_str()),
+        }
+    }
+}
+
+/// Output received but not yet consumed by a match, so patterns spanning read chunks are found.
+#[derive(Debug, Clone, Default)]
+pub struct StreamBuffer {
+    pending: Vec<u8>,
+}
+
+impl StreamBuffer {
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        if self.pending.len() > MAX_PENDING {
+            let excess = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..excess);
+        }
+    }
+
+    /// Returns the matched bytes and drops everything up to the end of the match.
+    pub fn take_match(&mut self, pattern: &Pattern) -> Option<Vec<u8>> {
+        let (start, end) = pattern.find(&self.pending)?;
+        let matched = self.pending[start..end].to_vec();
+        self.pending.drain(..end);
+        Some(matched)
+    }
+
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,132 @@
+//! Where the time of a push went, phase by phase, e.g.
+//! `wait-serial 0.0s | trigger 3.2s | handshake 0.01s | transfer 41.7s (612.0 KiB/s)`.
+//!
+//! Time is kept per attempt: a reconnect starts the next one, so a push that got through on
+//! its third try doesn't count the first two against its transfer rate.
+
+use std::{fmt, time::Duration};
+
+use serde::Serialize;
+
+use crate::output::format_bytes;
+
+/// The time spent in one phase of an attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PhaseTime {
+    pub phase: &'static str,
+    pub seconds: f64,
+    /// What went out meanwhile, for the phases that send the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+}
+
+impl PhaseTime {
+    /// Bytes per second, for a phase that sent any.
+    pub fn rate(&self) -> Option<f64> {
+        self.bytes.filter(|_| self.seconds > 0.0).map(|bytes| bytes as f64 / self.seconds)
+    }
+}
+
+/// The phases of one attempt, in the order they were first entered.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Attempt(pub Vec<PhaseTime>);
+
+impl Attempt {
+    pub fn get(&self, phase: &str) -> Option<&PhaseTime> {
+        self.0.iter().find(|time| time.phase == phase)
+    }
+
+    /// The entry of `phase`, added last if there is none yet.
+    fn entry(&mut self, phase: &'static str) -> &mut PhaseTime {
+        let at = match self.0.iter().position(|time| time.phase == phase) {
+            Some(at) => at,
+            None => {
+                self.0.push(PhaseTime { phase, seconds: 0.0, bytes: None });
+                self.0.len() - 1
+            }
+        };
+        &mut self.0[at]
+    }
+
+    /// A phase entered twice, e.g. the handshake around a version negotiation, adds up.
+    fn add(&mut self, time: &PhaseTime) {
+        let entry = self.entry(time.phase);
+        entry.seconds += time.seconds;
+        if let Some(bytes) = time.bytes { *entry.bytes.get_or_insert(0) += bytes; }
+    }
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, time) in self.0.iter().enumerate() {
+            if i > 0 { f.write_str(" | ")?; }
+            write!(f, "{} {}", time.phase, format_seconds(time.seconds))?;
+            if let Some(rate) = time.rate() { write!(f, " ({}/s)", format_bytes(rate as u64))?; }
+        }
+        Ok(())
+    }
+}
+
+/// What `--report` says about the timing: each attempt, and all of them summed up.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingReport {
+    pub attempts: Vec<Attempt>,
+    pub total: Attempt,
+}
+
+/// The attempts of a session, the last one being timed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PushTimings {
+    attempts: Vec<Attempt>,
+}
+
+impl PushTimings {
+    /// Starts the next attempt, e.g. after a reconnect. One that has nothing timed yet is kept.
+    pub fn begin_attempt(&mut self) {
+        if self.attempts.last().is_none_or(|attempt| !attempt.0.is_empty()) { self.attempts.push(Attempt::default()); }
+    }
+
+    /// Adds `elapsed` to `phase` of the current attempt.
+    pub fn record(&mut self, phase: &'static str, elapsed: Duration) {
+        self.current().entry(phase).seconds += elapsed.as_secs_f64();
+    }
+
+    /// Adds `bytes` to what `phase` of the current attempt sent.
+    pub fn record_bytes(&mut self, phase: &'static str, bytes: u64) {
+        *self.current().entry(phase).bytes.get_or_insert(0) += bytes;
+    }
+
+    pub fn attempts(&self) -> &[Attempt] {
+        &self.attempts
+    }
+
+    /// The attempt being timed.
+    pub fn last(&self) -> Option<&Attempt> {
+        self.attempts.last().filter(|attempt| !attempt.0.is_empty())
+    }
+
+    /// Every attempt summed up, phase by phase.
+    pub fn total(&self) -> Attempt {
+        let mut total = Attempt::default();
+        for time in self.attempts.iter().flat_map(|attempt| &attempt.0) { total.add(time); }
+        total
+    }
+
+    /// `None` when nothing was timed.
+    pub fn report(&self) -> Option<TimingReport> {
+        let attempts: Vec<Attempt> = self.attempts.iter().filter(|attempt| !attempt.0.is_empty()).cloned().collect();
+        if attempts.is_empty() { return None; }
+        Some(TimingReport { attempts, total: self.total() })
+    }
+
+    fn current(&mut self) -> &mut Attempt {
+        if self.attempts.is_empty() { self.attempts.push(Attempt::default()); }
+        self.attempts.last_mut().unwrap()
+    }
+}
+
+/// `3.2s`, or `0.01s` for what a tenth of a second would round away.
+pub fn format_seconds(seconds: f64) -> String {
+    if (0.005..0.095).contains(&seconds) { format!("{:.2}s", seconds) } else { format!("{:.1}s", seconds) }
+}
@@ -0,0 +1,284 @@
+//! Watching ports come and go, e.g. for a dashboard showing which boards of a rack are there.
+//!
+//! Each port is looked up as [`Target::presence`] does, on a thread of its own. On Linux a look
+//! is also taken whenever a device node under `/dev` is added, removed or has its permissions
+//! changed; elsewhere, and for network targets, the ports are polled.
+
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc}, thread, time::{Duration, Instant}};
+
+use crate::transport::{Presence, Target};
+
+/// How long a port has to stay as it was found before that counts, so a port that
+/// re-enumerates on plugging or a reset is one change rather than a flurry of them.
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// How often the ports are looked at without anything telling of a change.
+pub const LOOK_EVERY: Duration = Duration::from_secs(1);
+
+/// How long the watching thread sleeps at most before seeing whether it should stop.
+const STOP_EVERY: Duration = Duration::from_millis(100);
+
+/// What a [`PortWatcher`] saw.
+#[derive(Debug)]
+pub enum PortEvent {
+    /// The port `selector` names is now `presence`. The first of each port is how it was found.
+    Changed { selector: String, presence: Presence },
+    /// The ports couldn't be enumerated at all; said once until a look works again.
+    Failed { selector: String, error: serialport::Error },
+}
+
+impl PortEvent {
+    pub fn selector(&self) -> &str {
+        match self {
+            PortEvent::Changed { selector, .. } | PortEvent::Failed { selector, .. } => selector,
+        }
+    }
+}
+
+/// Holds back a change of a port's presence until it has lasted [`DEBOUNCE`]. Without any
+/// I/O, see [`PortWatcher`] for what takes the looks.
+#[derive(Debug, Clone)]
+pub struct Debounce {
+    debounce: Duration,
+    settled: Option<Presence>,
+    /// A presence other than the settled one, and since when it was seen.
+    pending: Option<(Presence, Instant)>,
+}
+
+impl Debounce {
+    pub fn new(debounce: Duration) -> Self {
+        Self { debounce, settled: None, pending: None }
+    }
+
+    /// What the port settled at, `None` before the first look.
+    pub fn settled(&self) -> Option<&Presence> {
+        self.settled.as_ref()
+    }
+
+    /// A look at the port at `now`: the presence it settled at when that changed. The first
+    /// look settles at once.
+    pub fn observe(&mut self, presence: Presence, now: Instant) -> Option<Presence> {
+        match &self.settled {
+            None => {
+                self.settled = Some(presence.clone());
+                return Some(presence);
+            }
+            Some(settled) if *settled == presence => {
+                self.pending = None;
+                return None;
+            }
+            Some(_) => {}
+        }
+        let since = match &self.pending {
+            Some((pending, since)) if *pending == presence => *since,
+            _ => {
+                self.pending = Some((presence.clone(), now));
+                now
+            }
+        };
+        if now.saturating_duration_since(since) < self.debounce { return None; }
+        self.pending = None;
+        self.settled = Some(presence.clone());
+        Some(presence)
+    }
+
+    /// When a pending change settles if the next look still finds it.
+    pub fn due(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, since)| *since + self.debounce)
+    }
+}
+
+type Lookup = Box<dyn FnMut(&str) -> serialport::Result<Presence> + Send>;
+type Callback = Box<dyn FnMut(&PortEvent) + Send>;
+
+/// How a [`PortWatcher`] watches, see [`PortWatcher::builder`].
+pub struct PortWatcherBuilder {
+    selectors: Vec<String>,
+    every: Duration,
+    debounce: Duration,
+    lookup: Option<Lookup>,
+    callback: Option<Callback>,
+}
+
+impl PortWatcherBuilder {
+    /// How often the ports are looked at when nothing says they changed; [`LOOK_EVERY`] by default.
+    pub fn every(mut self, every: Duration) -> Self {
+        self.every = every;
+        self
+    }
+
+    /// How long a change has to last; [`DEBOUNCE`] by default.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Looks ports up with `lookup` instead of [`Target::presence`], which also goes without
+    /// the notifications of the OS, e.g. for tests.
+    pub fn lookup(mut self, lookup: impl FnMut(&str) -> serialport::Result<Presence> + Send + 'static) -> Self {
+        self.lookup = Some(Box::new(lookup));
+        self
+    }
+
+    /// Called on the watching thread with each event, before it is sent.
+    pub fn on_change(mut self, callback: impl FnMut(&PortEvent) + Send + 'static) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn spawn(self) -> PortWatcher {
+        let (sender, events) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let hotplug = match self.lookup {
+            Some(_) => None,
+            None => Hotplug::new(),
+        };
+        let lookup = self.lookup.unwrap_or_else(|| Box::new(|selector: &str| Target::parse(selector).presence()));
+        let debounce = self.debounce;
+        let mut watching = Watching {
+            ports: self.selectors.into_iter().map(|selector| (selector, Debounce::new(debounce), false)).collect(),
+            lookup,
+            callback: self.callback,
+            sender,
+        };
+        let (every, stop) = (self.every, running.clone());
+        thread::spawn(move || while stop.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            if !watching.look(now) { return; }
+            // sooner when a change is due to settle
+            let next = watching.ports.iter().filter_map(|(_, debounce, _)| debounce.due()).fold(now + every, Instant::min);
+            while stop.load(Ordering::Relaxed) {
+                let left = next.saturating_duration_since(Instant::now());
+                if left.is_zero() { break; }
+                let changed = match &hotplug {
+                    Some(hotplug) => hotplug.wait(left.min(STOP_EVERY)),
+                    None => {
+                        thread::sleep(left.min(STOP_EVERY));
+                        false
+                    }
+                };
+                if changed { break; }
+            }
+        });
+        PortWatcher { events, running }
+    }
+}
+
+/// The watching thread's side of a [`PortWatcher`].
+struct Watching {
+    /// Each port's selector, its debounce, and whether its last look failed.
+    ports: Vec<(String, Debounce, bool)>,
+    lookup: Lookup,
+    callback: Option<Callback>,
+    sender: mpsc::Sender<PortEvent>,
+}
+
+impl Watching {
+    /// Looks at every port, sending what changed; false once nobody listens any more.
+    fn look(&mut self, now: Instant) -> bool {
+        for (selector, debounce, failed) in self.ports.iter_mut() {
+            let event = match (self.lookup)(selector) {
+                Ok(presence) => {
+                    *failed = false;
+                    match debounce.observe(presence, now) {
+                        Some(presence) => PortEvent::Changed { selector: selector.clone(), presence },
+                        None => continue,
+                    }
+                }
+                Err(_) if *failed => continue,
+                Err(error) => {
+                    *failed = true;
+                    PortEvent::Failed { selector: selector.clone(), error }
+                }
+            };
+            if let Some(callback) = &mut self.callback { callback(&event); }
+            if self.sender.send(event).is_err() { return false; }
+        }
+        true
+    }
+}
+
+/// Watches some ports on a thread of its own until dropped, sending each change of their
+/// presence once it settled. The port selectors are those of the command line: a device,
+/// part of a USB description, or a network target.
+pub struct PortWatcher {
+    events: mpsc::Receiver<PortEvent>,
+    running: Arc<AtomicBool>,
+}
+
+impl PortWatcher {
+    pub fn builder(selectors: Vec<String>) -> PortWatcherBuilder {
+        PortWatcherBuilder { selectors, every: LOOK_EVERY, debounce: DEBOUNCE, lookup: None, callback: None }
+    }
+
+    /// Watches `selectors` as [`PortWatcher::builder`] does by default.
+    pub fn spawn(selectors: Vec<String>) -> PortWatcher {
+        Self::builder(selectors).spawn()
+    }
+
+    /// What was seen, in order; e.g. for a thread of its own or an async runtime's blocking pool.
+    pub fn events(&self) -> &mpsc::Receiver<PortEvent> {
+        &self.events
+    }
+
+    /// The next event, or `None` once `timeout` passes without one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<PortEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for PortWatcher {
+    /// Not joined: the thread ends within [`STOP_EVERY`], and whoever dropped the watcher, e.g.
+    /// to open a port that just showed up, needn't wait for it.
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Device nodes coming and going under `/dev`, and their permissions changing as udev gives a
+/// new one its group.
+#[cfg(target_os = "linux")]
+struct Hotplug(libc::c_int);
+
+#[cfg(target_os = "linux")]
+impl Hotplug {
+    fn new() -> Option<Hotplug> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 { return None; }
+        let hotplug = Hotplug(fd);
+        let events = libc::IN_CREATE | libc::IN_DELETE | libc::IN_ATTRIB;
+        if unsafe { libc::inotify_add_watch(fd, b"/dev\0".as_ptr().cast(), events) } < 0 { return None; }
+        Some(hotplug)
+    }
+
+    /// Waits up to `timeout` for a change: whether there was one.
+    fn wait(&self, timeout: Duration) -> bool {
+        let mut poll = libc::pollfd { fd: self.0, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) } <= 0 { return false; }
+        let mut buf = [0u8; 4096];
+        while unsafe { libc::read(self.0, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+        true
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Hotplug {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
+}
+
+/// Nothing tells of changes here; the ports are polled.
+#[cfg(not(target_os = "linux"))]
+struct Hotplug;
+
+#[cfg(not(target_os = "linux"))]
+impl Hotplug {
+    fn new() -> Option<Hotplug> {
+        None
+    }
+
+    fn wait(&self, _: Duration) -> bool {
+        false
+    }
+}
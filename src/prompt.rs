@@ -0,0 +1,349 @@
+//! The local command line: a one-line prompt opened from the terminal, like OpenSSH's `~`
+//! escapes, whose commands run here. Nothing typed into it reaches the target.
+
+use std::{fs, io, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{ErrorKind, logfile::{Rotation, RotatingLog}, limit::RateLimiter, output::{format_bytes, Icon, Output, WriteWarning}, record::Recorder};
+use crate::{pull::PullSwitch, scrollback::Scrollback, SerialPort, stats::SessionStats, terminal::{self, RxTap}, wire::WireLog};
+
+/// Shown in front of what is typed.
+pub const PROMPT: &str = "local> ";
+
+/// What sending a file writes at a time, so its progress bar moves.
+const SEND_CHUNK: usize = 1024;
+
+/// Edits the prompt's one line as keys come in.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    line: String,
+}
+
+/// What a key did to the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Editing,
+    /// Enter: the line to run.
+    Submit(String),
+    /// Esc or Ctrl-C: the prompt closes without running anything.
+    Cancel,
+}
+
+impl LineEditor {
+    /// Backspace and Ctrl-W delete a character and a word, Ctrl-U all of it; other keys that
+    /// aren't characters are ignored.
+    pub fn feed(&mut self, key: &KeyEvent) -> Edit {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Edit::Submit(std::mem::take(&mut self.line)),
+            KeyCode::Esc => return Edit::Cancel,
+            KeyCode::Char('c') if control => return Edit::Cancel,
+            KeyCode::Char('u') if control => self.line.clear(),
+            KeyCode::Char('w') if control => {
+                let kept = self.line.trim_end().rfind(' ').map_or(0, |space| space + 1);
+                self.line.truncate(kept);
+            }
+            KeyCode::Backspace => { self.line.pop(); }
+            KeyCode::Char(c) if !control && !c.is_control() => self.line.push(c),
+            _ => {}
+        }
+        Edit::Editing
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+}
+
+/// The file `log start` writes what the target prints to, tapped by the terminal for as long
+/// as it runs.
+#[derive(Default)]
+pub struct LogSwitch {
+    /// Where a failed write is warned about; none stays silent.
+    out: Option<Output>,
+    log: Mutex<Option<(PathBuf, RotatingLog, WriteWarning)>>,
+}
+
+impl LogSwitch {
+    /// Warns on `out` when a file it writes can't be written any more, once per file.
+    pub fn new(out: &Output) -> Self {
+        Self { out: Some(out.clone()), log: Mutex::default() }
+    }
+
+    /// Appends to `path` from now on, instead of to the file before.
+    pub fn start(&self, path: &Path) -> io::Result<()> {
+        let log = RotatingLog::open(path, Rotation::default(), 0)?;
+        let failed = self.out.as_ref().map_or_else(WriteWarning::default, |out| WriteWarning::new(out, path.display()));
+        *self.log.lock().unwrap() = Some((path.to_path_buf(), log, failed));
+        Ok(())
+    }
+
+    /// The file that was written to, if any.
+    pub fn stop(&self) -> Option<PathBuf> {
+        self.log.lock().unwrap().take().map(|(path, _, _)| path)
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.log.lock().unwrap().as_ref().map(|(path, _, _)| path.clone())
+    }
+}
+
+impl RxTap for Arc<LogSwitch> {
+    fn rx(&mut self, data: &[u8]) {
+        if let Some((_, log, failed)) = self.log.lock().unwrap().as_mut() { failed.check(log.write(data)); }
+    }
+}
+
+/// What a local command works with: the terminal's port and what the session keeps.
+pub struct Context<'a> {
+    pub port: &'a mut SerialPort,
+    pub out: &'a Output,
+    pub stats: Option<&'a SessionStats>,
+    pub log: &'a LogSwitch,
+    /// What `pull` arms, `None` where nothing reads the port meanwhile.
+    pub pull: Option<&'a PullSwitch>,
+    /// What `scrollback` writes out, `None` where nothing is kept.
+    pub scrollback: Option<&'a Scrollback>,
+    pub read_only: bool,
+    pub(crate) limiter: Option<&'a mut RateLimiter>,
+    pub(crate) recorder: Option<&'a Recorder>,
+    pub(crate) wire: Option<&'a WireLog>,
+    pub(crate) quit: bool,
+    /// What [`Context::say`] said, for the control socket to pass on.
+    pub(crate) said: Vec<String>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(port: &'a mut SerialPort, out: &'a Output, log: &'a LogSwitch) -> Self {
+        Self { port, out, stats: None, log, pull: None, scrollback: None, read_only: false, limiter: None, recorder: None, wire: None, quit: false, said: Vec::new() }
+    }
+
+    /// Writes `data` to the target like typed input: paced, recorded and counted.
+    pub fn send(&mut self, data: &[u8]) -> crate::Result<()> {
+        if self.read_only { return Err(ErrorKind::ReadOnly); }
+        terminal::send(self.port, &mut data.to_vec(), self.limiter.as_deref_mut(), self.recorder, self.wire, self.stats)
+    }
+
+    /// Tells the user how the command went, in the terminal and to a `--control` client.
+    pub fn say(&mut self, message: String) {
+        self.out.status(&message);
+        self.said.push(message);
+    }
+
+    /// Ends the session once the command is done, like Ctrl-A q.
+    pub fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    pub fn quitting(&self) -> bool {
+        self.quit
+    }
+}
+
+/// Runs a command with what follows its name, trimmed; an error is shown as a warning.
+pub type Handler = Box<dyn FnMut(&mut Context<'_>, &str) -> Result<(), String>>;
+
+struct LocalCommand {
+    name: &'static str,
+    usage: &'static str,
+    run: Handler,
+}
+
+/// The commands of the prompt, by name: `log`, `send`, `hex`, `stats`, `pull`, `scrollback`,
+/// `baud`, `quit` and whatever a tool adds with [`LocalCommands::register`].
+pub struct LocalCommands {
+    commands: Vec<LocalCommand>,
+}
+
+impl Default for LocalCommands {
+    fn default() -> Self {
+        LocalCommands::new()
+            .register("log", "log start PATH | log stop", Box::new(log))
+            .register("send", "send PATH", Box::new(send_file))
+            .register("hex", "hex BYTES", Box::new(hex_sender()))
+            .register("stats", "stats", Box::new(stats))
+            .register("pull", "pull [PATH] | pull stop", Box::new(pull))
+            .register("scrollback", "scrollback PATH [LINES]", Box::new(save_scrollback))
+            .register("baud", "baud RATE", Box::new(baud))
+            .register("quit", "quit", Box::new(|ctx, _| {
+                ctx.quit();
+                Ok(())
+            }))
+    }
+}
+
+impl LocalCommands {
+    /// Without any commands, not even the built-in ones.
+    pub fn new() -> Self {
+        Self { commands: Vec::new() }
+    }
+
+    /// Adds `name`, replacing an earlier command of the same name. `usage` is what `help`
+    /// lists for it.
+    pub fn register(mut self, name: &'static str, usage: &'static str, run: Handler) -> Self {
+        self.commands.retain(|command| command.name != name);
+        self.commands.push(LocalCommand { name, usage, run });
+        self
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.commands.iter().map(|command| command.name)
+    }
+
+    /// The usage of every command and `help`, on one line.
+    pub fn help(&self) -> String {
+        let usages: Vec<&str> = self.commands.iter().map(|command| command.usage).chain(Some("help")).collect();
+        usages.join(", ")
+    }
+
+    /// Runs one line typed at the prompt; an empty one does nothing. Unknown commands and
+    /// `help` list what there is.
+    pub fn run(&mut self, line: &str, ctx: &mut Context<'_>) -> Result<(), String> {
+        let line = line.trim();
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if name.is_empty() { return Ok(()); }
+        if name == "help" {
+            ctx.say(format!("commands: {}", self.help()));
+            return Ok(());
+        }
+        match self.commands.iter_mut().find(|command| command.name == name) {
+            Some(command) => (command.run)(ctx, args.trim()),
+            None => Err(format!("unknown command {:?}; commands: {}", name, self.help())),
+        }
+    }
+}
+
+fn log(ctx: &mut Context<'_>, args: &str) -> Result<(), String> {
+    let (what, path) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    match (what, path.trim()) {
+        ("start", "") => Err("usage: log start PATH".to_string()),
+        ("start", path) => {
+            ctx.log.start(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+            ctx.say(format!("{} Logging to {}", ctx.out.icon(Icon::Save), path));
+            Ok(())
+        }
+        ("stop", "") => {
+            let path = ctx.log.stop().ok_or("not logging")?;
+            ctx.say(format!("{} Stopped logging to {}", ctx.out.icon(Icon::Save), path.display()));
+            Ok(())
+        }
+        ("", "") => {
+            ctx.say(ctx.log.path().map_or("not logging".to_string(), |path| format!("logging to {}", path.display())));
+            Ok(())
+        }
+        _ => Err("usage: log start PATH | log stop".to_string()),
+    }
+}
+
+/// `pull` catches the next dump the target sends, into PATH or under the name it gives.
+fn pull(ctx: &mut Context<'_>, args: &str) -> Result<(), String> {
+    let switch = ctx.pull.ok_or("no dumps are caught in this session")?;
+    if args == "stop" {
+        if !switch.disarm() { return Err("not waiting for a dump".to_string()); }
+        ctx.say(format!("{} No longer waiting for a dump", ctx.out.icon(Icon::Save)));
+        return Ok(());
+    }
+    switch.arm(if args.is_empty() { None } else { Some(PathBuf::from(args)) });
+    ctx.say(format!("{} Waiting for the target to send a dump{}", ctx.out.icon(Icon::Save),
+                    if args.is_empty() { String::new() } else { format!(" into {}", args) }));
+    Ok(())
+}
+
+/// `scrollback PATH` writes all that is kept of the output to PATH, `scrollback PATH 200` the
+/// last 200 lines of it.
+fn save_scrollback(ctx: &mut Context<'_>, args: &str) -> Result<(), String> {
+    let scrollback = ctx.scrollback.ok_or("no scrollback kept in this session")?;
+    let (path, lines) = match args.rsplit_once(char::is_whitespace).and_then(|(path, lines)| Some((path.trim(), lines.parse().ok()?))) {
+        Some((path, lines)) => (path, Some(lines)),
+        None => (args, None),
+    };
+    if path.is_empty() { return Err("usage: scrollback PATH [LINES]".to_string()); }
+    let written = scrollback.dump(Path::new(path), lines).map_err(|e| format!("{}: {}", path, e))?;
+    ctx.say(format!("{} Saved {} of scrollback to {}", ctx.out.icon(Icon::Save), format_bytes(written as u64), path));
+    Ok(())
+}
+
+fn stats(ctx: &mut Context<'_>, _: &str) -> Result<(), String> {
+    let stats = ctx.stats.ok_or("no counters kept in this session")?;
+    ctx.say(format!("{} {}", ctx.out.icon(Icon::Timer), stats.summary()));
+    Ok(())
+}
+
+fn send_file(ctx: &mut Context<'_>, path: &str) -> Result<(), String> {
+    if path.is_empty() { return Err("usage: send PATH".to_string()); }
+    if ctx.read_only { return Err("read-only, not sent".to_string()); }
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut pb = ctx.out.progress_bar(Icon::Push, &format!("Sending {}", path), data.len() as u64);
+    for chunk in data.chunks(SEND_CHUNK) {
+        if let Err(e) = ctx.send(chunk) {
+            ctx.out.finish_progress(pb);
+            return Err(format!("{} not sent: {}", path, e));
+        }
+        if let Some(pb) = pb.as_mut() { pb.add(chunk.len() as u64); }
+    }
+    ctx.out.finish_progress(pb);
+    ctx.say(format!("{} Sent {}, {}", ctx.out.icon(Icon::Ok), path, format_bytes(data.len() as u64)));
+    Ok(())
+}
+
+/// `hex AA 01 00 FF` sends those bytes; `hex` by itself sends the last ones again.
+fn hex_sender() -> impl FnMut(&mut Context<'_>, &str) -> Result<(), String> {
+    let mut last: Option<Vec<u8>> = None;
+    move |ctx, args| {
+        let bytes = match (args, &last) {
+            ("", Some(last)) => last.clone(),
+            ("", None) => return Err("usage: hex BYTES, e.g. hex AA 01 00 FF".to_string()),
+            (args, _) => parse_hex(args)?,
+        };
+        ctx.send(&bytes).map_err(|e| format!("not sent: {}", e))?;
+        ctx.say(format!("→ {}", format_hex(&bytes)));
+        last = Some(bytes);
+        Ok(())
+    }
+}
+
+/// The bytes spelled by `text` in pairs of hex digits, e.g. `AA 01 00 FF`, `aa0100ff` or
+/// `0xAA 0x01`. A word with an odd number of digits, or none, or a character that isn't one is
+/// an error that marks where it is.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut at = 0;
+    for word in text.split_whitespace() {
+        let start = at + text[at..].find(word).unwrap_or(0);
+        at = start + word.len();
+        let digits = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")).unwrap_or(word);
+        let skipped = word.len() - digits.len();
+        if let Some(bad) = digits.find(|c: char| !c.is_ascii_hexdigit()) {
+            let bad = start + skipped + bad;
+            let len = text[bad..].chars().next().map_or(1, char::len_utf8);
+            return Err(format!("not a hex digit: {}", mark(text, bad, bad + len)));
+        }
+        if digits.is_empty() { return Err(format!("no hex digits after 0x: {}", mark(text, start, at))); }
+        if digits.len() % 2 == 1 { return Err(format!("odd number of hex digits: {}", mark(text, start, at))); }
+        bytes.extend((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()));
+    }
+    if bytes.is_empty() { return Err("no bytes given".to_string()); }
+    Ok(bytes)
+}
+
+/// `text` with `start..end` in brackets, the way errors point at the part that is wrong.
+fn mark(text: &str, start: usize, end: usize) -> String {
+    format!("{}[{}]{}", &text[..start], &text[start..end], &text[end..])
+}
+
+/// `bytes` the way `hex` takes them, e.g. `AA 01 00 FF`.
+pub fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn baud(ctx: &mut Context<'_>, rate: &str) -> Result<(), String> {
+    let rate: u32 = rate.parse().map_err(|_| "usage: baud RATE, e.g. baud 115200".to_string())?;
+    ctx.port.set_baud_rate(rate).map_err(|e| e.to_string())?;
+    ctx.say(format!("— {} baud —", rate));
+    Ok(())
+}
@@ -0,0 +1,859 @@
+//! Wire format of the chainloader handshake.
+
+use std::{fmt, io::{self, Read, Write}, str::FromStr, sync::atomic::{AtomicBool, Ordering}, thread, time::{Duration, Instant}};
+
+use bitflags::bitflags;
+use serde::{Serialize, Serializer};
+
+use crate::{ErrorKind, pattern::{Pattern, StreamBuffer}, ReadSerial, Result, script, WRITE_TIMEOUT, WriteSerial};
+
+/// What the tutorial chainloader sends to ask for the image.
+pub const BINARY_REQUEST: [u8; 3] = [0x03; 3];
+
+/// Largest image the classic 4-byte size header can describe; `u32::MAX` itself would read as
+/// [`EXTENDED_SIZE_MARKER`] to a loader that knows it.
+pub const LEGACY_MAX_SIZE: u64 = u32::MAX as u64 - 1;
+
+/// What the host sends after the size when resuming is enabled, followed by a `u64` little
+/// endian offset. The loader answers `OK` to continue there or `NO` to take the whole image.
+pub const RESUME_REQUEST: [u8; 2] = *b"RS";
+
+/// What a resuming loader sends after each chunk it has stored.
+pub const CHUNK_ACK: u8 = 0x06;
+
+/// How long the loader may take to answer a handshake step with `OK` or `NO`.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stray bytes, e.g. a newline the loader prints after its request, tolerated before an answer.
+pub const REPLY_WINDOW: usize = 64;
+
+/// How long the line may go quiet in the middle of an answer, e.g. after its `O`, before it
+/// counts as wrong. Stray bytes alone wait for the full timeout.
+pub const REPLY_QUIET: Duration = Duration::from_millis(100);
+
+/// How long a resuming loader may take to acknowledge a chunk.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sent in place of the 4-byte size to announce that an 8-byte size follows.
+pub const EXTENDED_SIZE_MARKER: [u8; 4] = [0xFF; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeHeader {
+    /// `u32` little endian, what the tutorial loaders read.
+    #[default]
+    Legacy,
+    /// [`EXTENDED_SIZE_MARKER`] followed by a `u64` little endian, for loaders that know it.
+    Extended,
+}
+
+/// The bytes announcing an image of `size` bytes. Fails with `ImageTooLarge` rather than
+/// truncating when the header can't express the size.
+pub fn encode_size(size: u64, header: SizeHeader) -> Result<Vec<u8>> {
+    match header {
+        SizeHeader::Legacy if size > LEGACY_MAX_SIZE => Err(ErrorKind::ImageTooLarge(size)),
+        SizeHeader::Legacy => Ok((size as u32).to_le_bytes().to_vec()),
+        SizeHeader::Extended => Ok(EXTENDED_SIZE_MARKER.iter().chain(&size.to_le_bytes()).copied().collect()),
+    }
+}
+
+/// Announces the image size and waits for the loader's `OK`.
+pub fn send_size<P: Read + Write + ?Sized>(port: &mut P, size: u64, header: SizeHeader) -> Result<()> {
+    port.write_serial_all(&encode_size(size, header)?, WRITE_TIMEOUT)?;
+
+    read_reply(port, &[b"OK"], REPLY_WINDOW, REPLY_TIMEOUT)?;
+    Ok(())
+}
+
+/// Offers to continue a push at `offset` and returns where the image has to start from:
+/// `offset` if the loader agreed, 0 if it declined.
+pub fn offer_resume<P: Read + Write + ?Sized>(port: &mut P, offset: u64) -> Result<u64> {
+    let request: Vec<u8> = RESUME_REQUEST.iter().chain(&offset.to_le_bytes()).copied().collect();
+    port.write_serial_all(&request, WRITE_TIMEOUT)?;
+
+    match read_reply(port, &[b"OK", b"NO"], REPLY_WINDOW, REPLY_TIMEOUT)? {
+        0 => Ok(offset),
+        _ => Ok(0),
+    }
+}
+
+/// Which of `answers` the loader gave to a handshake step within `timeout`, skipping up to
+/// `window` stray bytes before it. Silence is a `ReadTimeout`; bytes without an answer among
+/// them are an `UnexpectedReply` once the window fills, the time is up, or the line goes quiet
+/// for [`REPLY_QUIET`] in the middle of an answer; a failing port is a `ProtocolError`.
+pub fn read_reply<P: Read + ?Sized>(port: &mut P, answers: &[&[u8]], window: usize, timeout: Duration) -> Result<usize> {
+    let deadline = Instant::now() + timeout;
+    let mut reply = Reply::new(answers, window);
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let wait = if reply.partial() { left.min(REPLY_QUIET) } else { left };
+        let mut byte = [0];
+        match port.read_serial_exact_timeout(&mut byte, wait) {
+            Ok(()) => if let Some(answer) = reply.feed(byte[0]) { return answer; },
+            Err(ErrorKind::ReadTimeout { .. }) => return Err(reply.give_up()),
+            Err(_) => return Err(ErrorKind::ProtocolError),
+        }
+    }
+}
+
+/// One of some answers to a handshake step, looked for among stray bytes before it.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    answers: Vec<Vec<u8>>,
+    window: usize,
+    received: Vec<u8>,
+}
+
+impl Reply {
+    pub fn new(answers: &[&[u8]], window: usize) -> Self {
+        Self { answers: answers.iter().map(|answer| answer.to_vec()).collect(), window, received: Vec::new() }
+    }
+
+    /// Whether nothing arrived yet.
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// Whether what arrived last is the start of an answer, which the next byte may finish.
+    pub fn partial(&self) -> bool {
+        self.answers.iter().any(|answer| (1..answer.len()).any(|len| self.received.ends_with(&answer[..len])))
+    }
+
+    fn longest(&self) -> usize {
+        self.answers.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    /// Takes the next byte: the index of the answer it completes, an `UnexpectedReply` once
+    /// the window is full, `None` while it could still come.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<usize>> {
+        self.received.push(byte);
+        if let Some(answer) = self.answers.iter().position(|answer| self.received.ends_with(answer)) { return Some(Ok(answer)); }
+        if self.received.len() >= self.window + self.longest() { return Some(Err(self.unexpected())); }
+        None
+    }
+
+    /// The error for waiting in vain: a `ReadTimeout` after silence, an `UnexpectedReply` after
+    /// bytes that weren't an answer.
+    pub fn give_up(&self) -> ErrorKind {
+        if self.received.is_empty() { ErrorKind::ReadTimeout { received: 0, expected: self.longest() } } else { self.unexpected() }
+    }
+
+    fn unexpected(&self) -> ErrorKind {
+        let expected: Vec<String> = self.answers.iter().map(|answer| answer.escape_ascii().to_string()).collect();
+        ErrorKind::UnexpectedReply { expected: expected.join(" or "), received: self.received.clone() }
+    }
+}
+
+/// Sent after the request to ask the loader what it speaks. Opt-in: the tutorial loader would
+/// take it for the image size.
+pub const VERSION_PROBE: [u8; 4] = *b"MPV?";
+
+/// Starts a new-style loader's answer to [`VERSION_PROBE`], followed by its version as a `u8`
+/// and its [`Capabilities`] as a `u16` little endian.
+pub const VERSION_MAGIC: [u8; 3] = *b"MPV";
+
+/// How long the loader gets to answer the probe before it counts as an old one.
+pub const NEGOTIATE_TIMEOUT: Duration = Duration::from_millis(200);
+
+bitflags! {
+    /// What a new-style loader supports beyond the raw size and stream exchange.
+    #[derive(Default)]
+    pub struct Capabilities: u16 {
+        /// The 64-bit size header, see [`SizeHeader::Extended`].
+        const EXTENDED_SIZE = 1 << 0;
+        /// Continuing an interrupted push, see [`RESUME_REQUEST`].
+        const RESUME = 1 << 1;
+        /// CRC-checked blocks, see [`crate::block`].
+        const BLOCK = 1 << 2;
+        /// Taking the image compressed; reserved, nothing sends it that way yet.
+        const COMPRESSION = 1 << 3;
+        /// A boot command line after the image, see [`send_cmdline`].
+        const CMDLINE = 1 << 4;
+        /// Patching the image kept from the last push, see [`crate::delta`].
+        const DELTA = 1 << 5;
+        /// Switching the line to a faster rate before the image, see [`BAUD_REQUEST`].
+        const BAUD = 1 << 6;
+    }
+}
+
+impl Capabilities {
+    /// Each one set, e.g. `["extended_size", "resume"]`.
+    pub fn names(self) -> Vec<&'static str> {
+        let names = [
+            (Capabilities::EXTENDED_SIZE, "extended_size"),
+            (Capabilities::RESUME, "resume"),
+            (Capabilities::BLOCK, "block"),
+            (Capabilities::COMPRESSION, "compression"),
+            (Capabilities::CMDLINE, "cmdline"),
+            (Capabilities::DELTA, "delta"),
+            (Capabilities::BAUD, "baud"),
+        ];
+        names.iter().filter(|(capability, _)| self.contains(*capability)).map(|&(_, name)| name).collect()
+    }
+}
+
+impl Serialize for Capabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.names().serialize(serializer)
+    }
+}
+
+/// A new-style loader's answer to the version probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LoaderInfo {
+    pub version: u8,
+    /// Bits this host doesn't know are dropped.
+    pub capabilities: Capabilities,
+}
+
+impl LoaderInfo {
+    /// What a loader of `version` is taken to support when told rather than asked: version 2
+    /// is the first to take a command line.
+    pub fn assumed(version: u8) -> Self {
+        let capabilities = if version >= 2 { Capabilities::CMDLINE } else { Capabilities::empty() };
+        Self { version, capabilities }
+    }
+}
+
+/// Probes the loader right after its request. `None` from an old loader, which stays silent
+/// for `timeout`; the push then goes on with the raw exchange.
+pub fn negotiate<P: Read + Write + ?Sized>(port: &mut P, timeout: Duration) -> Result<Option<LoaderInfo>> {
+    port.write_serial_all(&VERSION_PROBE, WRITE_TIMEOUT)?;
+
+    let mut reply = [0; 6];
+    match port.read_serial_timeout(&mut reply, timeout)? {
+        0 => return Ok(None),
+        6 if reply[..3] == VERSION_MAGIC => {}
+        n => return Err(ErrorKind::UnexpectedReply { expected: "a version".to_string(), received: reply[..n].to_vec() }),
+    }
+    let capabilities = Capabilities::from_bits_truncate(u16::from_le_bytes([reply[4], reply[5]]));
+    Ok(Some(LoaderInfo { version: reply[3], capabilities }))
+}
+
+/// Sent after the negotiation to propose a faster line, followed by the rate as a `u32` little
+/// endian. The loader answers `OK` and switches right after it, or `NO` to a rate it can't do.
+/// Only for loaders with [`Capabilities::BAUD`].
+pub const BAUD_REQUEST: [u8; 2] = *b"BR";
+
+/// Sent at the new rate, followed by [`BAUD_CHECK_PATTERN`], which the loader echoes. A loader
+/// that hasn't seen it intact within [`BAUD_REVERT`] of its `OK` goes back to the old rate.
+pub const BAUD_CHECK: [u8; 2] = *b"BC";
+
+/// Bits that get mangled first on a marginal line: alternating, long runs, both edges.
+pub const BAUD_CHECK_PATTERN: [u8; 8] = [0x55, 0xAA, 0x00, 0xFF, 0x0F, 0xF0, 0x33, 0xCC];
+
+/// How long the loader gets to switch its UART after its `OK`.
+pub const BAUD_SETTLE: Duration = Duration::from_millis(20);
+
+/// How long the echo of the check may take.
+pub const BAUD_CHECK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long a loader waits for the check at a new rate before it goes back to the old one.
+pub const BAUD_REVERT: Duration = Duration::from_millis(500);
+
+/// What a [`BaudLadder`] did to the line, for the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaudSwitch {
+    /// The loader can't do `rate`.
+    Declined { rate: u32 },
+    /// Both ends moved from `from` to `to`, which is checked next.
+    Switched { from: u32, to: u32 },
+    /// The check came back intact at `rate`; the image goes out at it.
+    Confirmed { rate: u32 },
+    /// The check didn't come back intact at `from`; both ends went back to `to`.
+    Reverted { from: u32, to: u32 },
+}
+
+/// Where a [`BaudLadder`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaudStage {
+    /// At the rate the line was opened with, about to propose the next rate.
+    Base,
+    /// `rate` was proposed, waiting for `OK` or `NO`.
+    Proposed(u32),
+    /// Both ends are at `rate`, waiting for the check to come back.
+    Switched(u32),
+    /// `rate` passed its check; the ladder is done.
+    Confirmed(u32),
+    /// Every rate was declined or failed its check; the line stays at the base rate.
+    Exhausted,
+}
+
+/// Stepping the line up to the fastest rate both ends manage: each rate of the ladder, fastest
+/// first, is proposed; one the loader takes is switched to and checked with an echo, and one
+/// that fails its check is left for the next. Without any I/O, see [`step_baud`] for a driver.
+#[derive(Debug, Clone)]
+pub struct BaudLadder {
+    base: u32,
+    rates: Vec<u32>,
+    stage: BaudStage,
+}
+
+impl BaudLadder {
+    /// A ladder from the line's `base` rate up to `rates`; those no faster than `base` are
+    /// dropped, the rest tried fastest first.
+    pub fn new(base: u32, rates: &[u32]) -> Self {
+        let mut rates: Vec<u32> = rates.iter().copied().filter(|&rate| rate > base).collect();
+        rates.sort_unstable_by(|a, b| b.cmp(a));
+        rates.dedup();
+        Self { base, rates, stage: BaudStage::Base }
+    }
+
+    pub fn stage(&self) -> BaudStage {
+        self.stage
+    }
+
+    /// The rates still to try, fastest first.
+    pub fn rates(&self) -> &[u32] {
+        &self.rates
+    }
+
+    /// The rate the line is at.
+    pub fn rate(&self) -> u32 {
+        match self.stage {
+            BaudStage::Switched(rate) | BaudStage::Confirmed(rate) => rate,
+            BaudStage::Base | BaudStage::Proposed(_) | BaudStage::Exhausted => self.base,
+        }
+    }
+
+    /// The next rate to propose, `None` once there is none left or one was confirmed.
+    pub fn propose(&mut self) -> Option<u32> {
+        if self.stage != BaudStage::Base { return None; }
+        if self.rates.is_empty() {
+            self.stage = BaudStage::Exhausted;
+            return None;
+        }
+        let rate = self.rates.remove(0);
+        self.stage = BaudStage::Proposed(rate);
+        Some(rate)
+    }
+
+    /// The loader's answer to the proposal: the rate to switch the port to once it agreed.
+    pub fn answered(&mut self, agreed: bool) -> Option<u32> {
+        let rate = match self.stage {
+            BaudStage::Proposed(rate) => rate,
+            _ => return None,
+        };
+        self.stage = if agreed { BaudStage::Switched(rate) } else { BaudStage::Base };
+        Some(rate).filter(|_| agreed)
+    }
+
+    /// Whether the check came back intact at the new rate: what happened to the line, which is
+    /// back at the base rate after a failed check.
+    pub fn checked(&mut self, intact: bool) -> Option<BaudSwitch> {
+        let rate = match self.stage {
+            BaudStage::Switched(rate) => rate,
+            _ => return None,
+        };
+        if intact {
+            self.stage = BaudStage::Confirmed(rate);
+            return Some(BaudSwitch::Confirmed { rate });
+        }
+        self.stage = BaudStage::Base;
+        Some(BaudSwitch::Reverted { from: rate, to: self.base })
+    }
+}
+
+/// Walks `ladder` on `port` right after the negotiation, saying each switch to `log`, and
+/// returns the rate the image goes out at. After a failed check the loader is given `revert`
+/// to go back before the next rate is proposed. The rate is changed on the open port rather
+/// than by opening it again, which would pulse DTR and could reset the board.
+pub fn step_baud(port: &mut dyn serialport::SerialPort, ladder: &mut BaudLadder, revert: Duration, mut log: impl FnMut(BaudSwitch)) -> Result<u32> {
+    while let Some(rate) = ladder.propose() {
+        let request: Vec<u8> = BAUD_REQUEST.iter().chain(&rate.to_le_bytes()).copied().collect();
+        port.write_serial_all(&request, WRITE_TIMEOUT)?;
+        let agreed = read_reply(port, &[b"OK", b"NO"], REPLY_WINDOW, REPLY_TIMEOUT)? == 0;
+        let from = ladder.rate();
+        let rate = match ladder.answered(agreed) {
+            Some(rate) => rate,
+            None => {
+                log(BaudSwitch::Declined { rate });
+                continue;
+            }
+        };
+
+        // the OK has to be out of the loader before either end switches
+        port.flush()?;
+        thread::sleep(BAUD_SETTLE);
+        port.set_baud_rate(rate)?;
+        port.clear(serialport::ClearBuffer::Input)?;
+        log(BaudSwitch::Switched { from, to: rate });
+
+        let check: Vec<u8> = BAUD_CHECK.iter().chain(&BAUD_CHECK_PATTERN).copied().collect();
+        let intact = port.write_serial_all(&check, WRITE_TIMEOUT).is_ok() && {
+            let mut echo = [0; BAUD_CHECK_PATTERN.len()];
+            matches!(port.read_serial_timeout(&mut echo, BAUD_CHECK_TIMEOUT), Ok(n) if n == echo.len() && echo == BAUD_CHECK_PATTERN)
+        };
+        let switch = ladder.checked(intact).expect("switched before the check");
+        if let BaudSwitch::Reverted { to, .. } = switch {
+            port.set_baud_rate(to)?;
+            thread::sleep(revert);
+            port.clear(serialport::ClearBuffer::Input)?;
+        }
+        log(switch);
+    }
+    Ok(ladder.rate())
+}
+
+/// Longest boot command line, without its terminating NUL.
+pub const CMDLINE_MAX: usize = 4096;
+
+/// The command line as sent after the image: its length including the NUL as a `u16` little
+/// endian, the UTF-8 bytes, a NUL.
+pub fn encode_cmdline(cmdline: &str) -> Result<Vec<u8>> {
+    if cmdline.len() > CMDLINE_MAX {
+        return Err(ErrorKind::InvalidCmdline(format!("{} bytes, at most {} fit", cmdline.len(), CMDLINE_MAX)));
+    }
+    if cmdline.contains('\0') { return Err(ErrorKind::InvalidCmdline("contains a NUL".to_string())); }
+    let mut blob = Vec::with_capacity(cmdline.len() + 3);
+    blob.extend_from_slice(&(cmdline.len() as u16 + 1).to_le_bytes());
+    blob.extend_from_slice(cmdline.as_bytes());
+    blob.push(0);
+    Ok(blob)
+}
+
+/// Sends the boot command line and waits for the loader's `OK`. Only for loaders with
+/// [`Capabilities::CMDLINE`].
+pub fn send_cmdline<P: Read + Write + ?Sized>(port: &mut P, cmdline: &str) -> Result<()> {
+    port.write_serial_all(&encode_cmdline(cmdline)?, WRITE_TIMEOUT)?;
+    read_reply(port, &[b"OK"], REPLY_WINDOW, REPLY_TIMEOUT)?;
+    Ok(())
+}
+
+/// Waits for the loader to acknowledge the chunk just sent.
+pub fn read_ack<P: Read + ?Sized>(port: &mut P) -> Result<()> {
+    let mut ack = [0];
+    port.read_serial_exact_timeout(&mut ack, ACK_TIMEOUT)?;
+    if ack[0] == CHUNK_ACK { Ok(()) } else { Err(ErrorKind::ProtocolError) }
+}
+
+/// How far a push got, kept across reconnects so the next attempt can pick up there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PushState {
+    /// Size of the image being pushed.
+    pub size: u64,
+    /// Bytes the loader has acknowledged.
+    pub acknowledged: u64,
+}
+
+impl PushState {
+    /// Where to offer resuming an image of `size` bytes; 0 unless it is the same image.
+    pub fn resume_offset(&self, size: u64) -> u64 {
+        if self.size == size { self.acknowledged.min(size) } else { 0 }
+    }
+}
+
+/// Spots the loader's request for the image in its boot output, also when it arrives split
+/// over several reads.
+#[derive(Debug, Clone)]
+pub struct RequestMatcher {
+    pattern: Pattern,
+    pending: StreamBuffer,
+}
+
+impl Default for RequestMatcher {
+    fn default() -> Self {
+        Self::new(Pattern::literal(BINARY_REQUEST))
+    }
+}
+
+impl RequestMatcher {
+    pub fn new(pattern: Pattern) -> Self {
+        Self { pattern, pending: StreamBuffer::default() }
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    /// Whether the last bytes fed may be the start of the request, and are held back.
+    pub fn is_holding(&self) -> bool {
+        !self.pending.pending().is_empty()
+    }
+
+    /// Takes the next piece of output and returns what of it is boot output to show, and
+    /// whether the request is complete. A byte sequence is held back while it may be the start
+    /// of the request; regex matches are shown like any other output.
+    pub fn feed(&mut self, data: &[u8]) -> (Vec<u8>, bool) {
+        self.pending.push(data);
+        let needle = match &self.pattern {
+            Pattern::Literal(needle) => needle,
+            Pattern::Regex(_) => {
+                let found = self.pending.take_match(&self.pattern).is_some();
+                if found { self.pending.clear(); }
+                return (data.to_vec(), found);
+            }
+        };
+        if let Some((start, _)) = self.pattern.find(self.pending.pending()) {
+            let shown = self.pending.pending()[..start].to_vec();
+            self.pending.clear();
+            return (shown, true);
+        }
+        let pending = self.pending.pending();
+        let held = (1..needle.len().min(pending.len() + 1)).rev()
+            .find(|&n| pending.ends_with(&needle[..n]))
+            .unwrap_or(0);
+        let shown = pending[..pending.len() - held].to_vec();
+        self.pending.clear();
+        self.pending.push(&needle[..held]);
+        (shown, false)
+    }
+}
+
+impl FromStr for RequestMatcher {
+    type Err = String;
+
+    /// `hex:030303`, `str:READY` with `\r`-style escapes, or `regex:READY\s*$`.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let pattern = match s.split_once(':') {
+            Some(("hex", hex)) => {
+                let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+                if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err("expected pairs of hex digits".to_string());
+                }
+                Pattern::Literal((0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect())
+            }
+            Some(("str", text)) if !text.is_empty() => Pattern::Literal(script::unescape(text)?),
+            Some(("regex", re)) if !re.is_empty() => Pattern::regex(re).map_err(|e| e.to_string())?,
+            _ => return Err("expected hex:BYTES, str:TEXT or regex:REGEX".to_string()),
+        };
+        Ok(Self::new(pattern))
+    }
+}
+
+impl fmt::Display for RequestMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.pattern {
+            Pattern::Literal(bytes) if !bytes.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()) => {
+                f.write_str("hex:")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            pattern => pattern.fmt(f),
+        }
+    }
+}
+
+/// Something that happened on the line, for [`Chainboot::handle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input<'a> {
+    /// Bytes from the loader.
+    Received(&'a [u8]),
+    /// Time passed, e.g. a read that came back empty; deadlines are checked against `now`.
+    Tick,
+    /// Announce an image of `size` bytes once [`Action::Requested`] came, and a negotiation
+    /// if there was one is over. With `resume`, offer to continue at that offset after the
+    /// size was taken.
+    Announce { size: u64, resume: Option<u64> },
+    /// The chunk of the last [`Action::SendChunk`] is written.
+    Written,
+}
+
+/// What the driver is to do next.
+#[derive(Debug)]
+pub enum Action {
+    /// Boot output to show, or what the loader prints while the image goes out; the request
+    /// itself is held back.
+    Show(Vec<u8>),
+    /// The loader asked for the image.
+    Requested,
+    /// Bytes to write.
+    Send(Vec<u8>),
+    /// The loader took the size.
+    SizeAccepted,
+    /// The loader answered the resume offer: the image starts at `offset`, 0 if it declined.
+    ResumeAnswered { offset: u64 },
+    /// Write bytes `offset..offset + len` of the image, then pass [`Input::Written`].
+    SendChunk { offset: u64, len: usize },
+    /// The loader has `sent` bytes of the image: written, or acknowledged when it acks chunks.
+    Progress { sent: u64, total: u64 },
+    /// All of the image is out.
+    Complete,
+    /// The loader asked for the image again while it was being sent, having restarted with
+    /// `at` bytes of it out. The exchange is back at [`Stage::Requested`], ready for the next
+    /// [`Input::Announce`].
+    Rebooted { at: u64 },
+    /// The exchange is over, it failed; later input is ignored.
+    Failed(ErrorKind),
+}
+
+/// Where a [`Chainboot`] exchange is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Looking for the request in the boot output.
+    Request,
+    /// Requested, waiting for [`Input::Announce`].
+    Requested,
+    /// Waiting for the `OK` to the size.
+    Size,
+    /// Waiting for the `OK` or `NO` to a resume offer at `offset`.
+    Resume { offset: u64 },
+    /// A chunk ending at `end` is being written.
+    Writing { end: u64 },
+    /// The chunk ending at `end` is out, waiting for its [`CHUNK_ACK`].
+    ChunkAck { end: u64 },
+    Complete,
+    Failed,
+}
+
+/// The chainboot exchange without any I/O: the loader's request, the size and its `OK`, an
+/// optional resume offer, and the image in chunks. [`Chainboot::handle`] takes what happened
+/// on the line and says what to do about it, so a driver only moves bytes and time, and
+/// every step can be tested one byte ordering at a time. See [`pump`] for a driver.
+#[derive(Debug, Clone)]
+pub struct Chainboot {
+    stage: Stage,
+    request: RequestMatcher,
+    header: SizeHeader,
+    chunk_size: usize,
+    acks: bool,
+    size: u64,
+    resume: Option<u64>,
+    reply: Option<Reply>,
+    /// When the answer or ack is due, and when a line gone quiet in the middle of an answer
+    /// counts as a wrong one.
+    due: Option<Instant>,
+    quiet: Option<Instant>,
+    /// Bytes that came while a chunk was still being written, an early ack among them.
+    early: Vec<u8>,
+}
+
+impl Chainboot {
+    /// Looks for `request`, then announces the size with `header` and sends the image in
+    /// chunks of `chunk_size`; with `acks`, each one waits for the loader's [`CHUNK_ACK`].
+    pub fn new(request: RequestMatcher, header: SizeHeader, chunk_size: usize, acks: bool) -> Self {
+        Self { stage: Stage::Request, request, header, chunk_size: chunk_size.max(1), acks, size: 0, resume: None,
+               reply: None, due: None, quiet: None, early: Vec::new() }
+    }
+
+    pub fn stage(&self) -> &Stage {
+        &self.stage
+    }
+
+    /// The latest the driver should pass [`Input::Tick`] while nothing arrives: when an
+    /// answer is due, or when a line gone quiet in the middle of one counts as a wrong answer.
+    /// `None` when nothing is awaited with a deadline.
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.due, self.quiet) {
+            (Some(due), Some(quiet)) => Some(due.min(quiet)),
+            (due, quiet) => due.or(quiet),
+        }
+    }
+
+    pub fn handle(&mut self, input: Input<'_>, now: Instant) -> Vec<Action> {
+        let mut actions = Vec::new();
+        match input {
+            Input::Received(data) => self.receive(data, now, &mut actions),
+            Input::Tick => self.tick(now, &mut actions),
+            Input::Announce { size, resume } if self.stage == Stage::Requested => {
+                self.size = size;
+                self.resume = resume;
+                match encode_size(size, self.header) {
+                    Ok(header) => {
+                        actions.push(Action::Send(header));
+                        self.await_reply(Stage::Size, &[b"OK"], REPLY_TIMEOUT, now);
+                    }
+                    Err(e) => self.fail(e, &mut actions),
+                }
+            }
+            Input::Written => if let Stage::Writing { end } = self.stage {
+                if self.acks {
+                    self.stage = Stage::ChunkAck { end };
+                    self.due = Some(now + ACK_TIMEOUT);
+                    let early = std::mem::take(&mut self.early);
+                    self.receive(&early, now, &mut actions);
+                } else {
+                    actions.push(Action::Progress { sent: end, total: self.size });
+                    self.next_chunk(end, &mut actions);
+                }
+            },
+            // out of turn, e.g. an announce before the request
+            Input::Announce { .. } => {}
+        }
+        actions
+    }
+
+    fn receive(&mut self, data: &[u8], now: Instant, actions: &mut Vec<Action>) {
+        let mut rest = data;
+        while !rest.is_empty() {
+            match self.stage.clone() {
+                Stage::Request => {
+                    let (shown, requested) = self.request.feed(rest);
+                    if !shown.is_empty() { actions.push(Action::Show(shown)); }
+                    if requested {
+                        self.stage = Stage::Requested;
+                        actions.push(Action::Requested);
+                    }
+                    // what follows the request is no boot output and no answer yet
+                    return;
+                }
+                Stage::Size | Stage::Resume { .. } => {
+                    let answer = self.reply.as_mut().unwrap().feed(rest[0]);
+                    rest = &rest[1..];
+                    self.quiet = self.reply.as_ref().filter(|reply| reply.partial()).map(|_| now + REPLY_QUIET);
+                    match answer {
+                        Some(Ok(answer)) => self.answered(answer, now, actions),
+                        Some(Err(e)) => self.fail(e, actions),
+                        None => {}
+                    }
+                }
+                Stage::Writing { .. } if self.acks => {
+                    self.early.extend_from_slice(rest);
+                    return;
+                }
+                Stage::ChunkAck { end } if rest[0] == CHUNK_ACK && !self.request.is_holding() => {
+                    rest = &rest[1..];
+                    actions.push(Action::Progress { sent: end, total: self.size });
+                    self.next_chunk(end, actions);
+                }
+                // anything but an ack is the loader starting over, or wrong
+                Stage::ChunkAck { end } => {
+                    let (shown, requested) = self.request.feed(&rest[..1]);
+                    rest = &rest[1..];
+                    if requested { return self.rebooted(end, actions); }
+                    if !shown.is_empty() { return self.fail(ErrorKind::ProtocolError, actions); }
+                }
+                // what a loader prints while it takes the image is shown, unless it restarted and asks again
+                Stage::Writing { end } => {
+                    let (shown, requested) = self.request.feed(rest);
+                    if !shown.is_empty() { actions.push(Action::Show(shown)); }
+                    if requested { self.rebooted(end, actions); }
+                    return;
+                }
+                // after the end it boots
+                Stage::Requested | Stage::Complete | Stage::Failed => return,
+            }
+        }
+    }
+
+    fn tick(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        if self.deadline().is_none_or(|deadline| now < deadline) { return; }
+        let e = match (&self.stage, &self.reply) {
+            (Stage::Size | Stage::Resume { .. }, Some(reply)) => reply.give_up(),
+            _ => ErrorKind::ReadTimeout { received: 0, expected: 1 },
+        };
+        self.fail(e, actions);
+    }
+
+    fn await_reply(&mut self, stage: Stage, answers: &[&[u8]], timeout: Duration, now: Instant) {
+        self.stage = stage;
+        self.reply = Some(Reply::new(answers, REPLY_WINDOW));
+        self.due = Some(now + timeout);
+        self.quiet = None;
+    }
+
+    fn answered(&mut self, answer: usize, now: Instant, actions: &mut Vec<Action>) {
+        self.reply = None;
+        (self.due, self.quiet) = (None, None);
+        match self.stage {
+            Stage::Size => {
+                actions.push(Action::SizeAccepted);
+                match self.resume {
+                    Some(offset) => {
+                        actions.push(Action::Send(RESUME_REQUEST.iter().chain(&offset.to_le_bytes()).copied().collect()));
+                        self.await_reply(Stage::Resume { offset }, &[b"OK", b"NO"], REPLY_TIMEOUT, now);
+                    }
+                    None => self.next_chunk(0, actions),
+                }
+            }
+            Stage::Resume { offset } => {
+                let offset = if answer == 0 { offset.min(self.size) } else { 0 };
+                actions.push(Action::ResumeAnswered { offset });
+                self.next_chunk(offset, actions);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn next_chunk(&mut self, offset: u64, actions: &mut Vec<Action>) {
+        (self.due, self.quiet) = (None, None);
+        if offset >= self.size {
+            self.stage = Stage::Complete;
+            actions.push(Action::Complete);
+            return;
+        }
+        let len = (self.size - offset).min(self.chunk_size as u64) as usize;
+        self.stage = Stage::Writing { end: offset + len as u64 };
+        actions.push(Action::SendChunk { offset, len });
+    }
+
+    fn rebooted(&mut self, at: u64, actions: &mut Vec<Action>) {
+        self.stage = Stage::Requested;
+        (self.due, self.quiet) = (None, None);
+        self.early.clear();
+        actions.push(Action::Rebooted { at });
+    }
+
+    fn fail(&mut self, e: ErrorKind, actions: &mut Vec<Action>) {
+        self.stage = Stage::Failed;
+        self.reply = None;
+        (self.due, self.quiet) = (None, None);
+        actions.push(Action::Failed(e));
+    }
+}
+
+/// What the loader said while the image was being sent, without waiting for more: nothing,
+/// unless it restarted and asks again. A network port can't tell and says nothing.
+pub fn heard(port: &mut dyn serialport::SerialPort) -> Result<Vec<u8>> {
+    let waiting = port.bytes_to_read().unwrap_or(0) as usize;
+    let mut data = vec![0; waiting];
+    if waiting > 0 {
+        let read = port.read_serial(&mut data)?;
+        data.truncate(read);
+    }
+    Ok(data)
+}
+
+/// Reads `port` until `machine` sees the loader's request or `running` is cleared, `read_size`
+/// bytes at a time into batches of `batch_size`; whether the request came. Every batch goes to
+/// `seen` with the boot output that precedes the request and whether it came, and an error
+/// from there, e.g. an interrupt, ends the wait.
+pub fn wait_for_request<P, F>(port: &mut P, machine: &mut Chainboot, batch_size: usize, read_size: usize,
+                              running: &AtomicBool, mut seen: F) -> Result<bool>
+where
+    P: ReadSerial + ?Sized,
+    F: FnMut(&[u8], &[u8], bool) -> Result<()>,
+{
+    let mut received = vec![0; batch_size.max(1)];
+    while running.load(Ordering::Relaxed) {
+        let n = port.read_serial_drain(&mut received, read_size)?;
+        // the request is looked for in the raw bytes, only what precedes it is shown
+        let (mut shown, mut requested) = (Vec::new(), false);
+        for action in machine.handle(Input::Received(&received[..n]), Instant::now()) {
+            match action {
+                Action::Show(bytes) => shown.extend(bytes),
+                Action::Requested => requested = true,
+                _ => {}
+            }
+        }
+        seen(&received[..n], &shown, requested)?;
+        if requested { return Ok(true); }
+    }
+    Ok(false)
+}
+
+/// Carries out `actions` on `port` and feeds what the loader answers back into `machine`, until
+/// there is something for the caller or nothing is awaited with a deadline. Writes are done
+/// here and a failure comes back as the error; the other actions are returned in order.
+pub fn pump<P: Read + Write + ?Sized>(port: &mut P, machine: &mut Chainboot, mut actions: Vec<Action>) -> Result<Vec<Action>> {
+    // one byte at a time, so what follows an answer stays in the port for the next step
+    let mut byte = [0];
+    loop {
+        let mut left = Vec::new();
+        for action in actions.drain(..) {
+            match action {
+                Action::Send(bytes) => port.write_serial_all(&bytes, WRITE_TIMEOUT)?,
+                Action::Failed(e) => return Err(e),
+                action => left.push(action),
+            }
+        }
+        if !left.is_empty() || machine.deadline().is_none() { return Ok(left); }
+        let input = match port.read_serial(&mut byte) {
+            Ok(0) => Input::Tick,
+            Ok(_) => Input::Received(&byte),
+            Err(ErrorKind::IoError(ref e)) if e.kind() == io::ErrorKind::Interrupted => Input::Tick,
+            // a dropped chunk ack stays what the port said, a broken handshake is a protocol error
+            Err(e) if matches!(machine.stage(), Stage::ChunkAck { .. }) => return Err(e),
+            Err(_) => return Err(ErrorKind::ProtocolError),
+        };
+        actions = machine.handle(input, Instant::now());
+    }
+}
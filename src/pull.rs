@@ -0,0 +1,180 @@
+//! Dumps the target sends of its own accord, e.g. a RAM region for post-mortem debugging, the
+//! other way round from the push in [`protocol`](crate::protocol):
+//!
+//! `7F 'D' 'M' 'P' | length: u32 LE | name length: u8 | name | data | crc: u32 LE`
+//!
+//! The CRC is CRC-32 (IEEE, as zlib has it) of the data. The name, which may be empty, is
+//! what the file is called unless `--output` says otherwise. What precedes the magic is
+//! console output and shown as such.
+
+use std::{fs, io::{Cursor, Read}, path::{Path, PathBuf}, sync::Mutex, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{delta::crc32, ErrorKind, output::{format_bytes, Icon, Output}, ReadSerial, Result, ymodem};
+
+/// What starts a dump.
+pub const MAGIC: [u8; 4] = *b"\x7fDMP";
+
+/// How long the target may go quiet in the middle of a dump.
+pub const DUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes read at a time, the progress updated after each.
+const READ_CHUNK: usize = 4096;
+
+/// What follows the magic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpHeader {
+    pub length: u32,
+    /// As the target gave it, see [`ymodem::sanitize_name`] for where it is written.
+    pub name: String,
+}
+
+/// A whole frame for `data`, as the target sends it.
+pub fn encode(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut frame = MAGIC.to_vec();
+    frame.extend((data.len() as u32).to_le_bytes());
+    frame.push(name.len() as u8);
+    frame.extend(name.as_bytes());
+    frame.extend(data);
+    frame.extend(crc32(data).to_le_bytes());
+    frame
+}
+
+/// Looks for [`MAGIC`] in console output split over several reads.
+#[derive(Debug, Clone, Default)]
+pub struct MagicScanner {
+    held: Vec<u8>,
+}
+
+impl MagicScanner {
+    /// Takes the next read and returns what of it is console output, and once the magic came,
+    /// the bytes after it. A piece that may be the start of the magic is held back meanwhile.
+    pub fn feed(&mut self, data: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut pending = std::mem::take(&mut self.held);
+        pending.extend_from_slice(data);
+        if let Some(at) = pending.windows(MAGIC.len()).position(|window| window == MAGIC) {
+            let rest = pending.split_off(at + MAGIC.len());
+            pending.truncate(at);
+            return (pending, Some(rest));
+        }
+        let held = (1..MAGIC.len()).rev().find(|&n| pending.ends_with(&MAGIC[..n])).unwrap_or(0);
+        self.held = pending.split_off(pending.len() - held);
+        (pending, None)
+    }
+}
+
+/// Reads the header that follows the magic.
+pub fn read_header<R: Read + ?Sized>(port: &mut R, timeout: Duration) -> Result<DumpHeader> {
+    let mut fixed = [0; 5];
+    port.read_serial_exact_timeout(&mut fixed, timeout)?;
+    let mut name = vec![0; fixed[4] as usize];
+    port.read_serial_exact_timeout(&mut name, timeout)?;
+    let length = u32::from_le_bytes([fixed[0], fixed[1], fixed[2], fixed[3]]);
+    Ok(DumpHeader { length, name: String::from_utf8_lossy(&name).into_owned() })
+}
+
+/// Reads the data `header` announces and checks it against the CRC after it, telling
+/// `progress` how much is in so far. A pause longer than `timeout` ends it with what arrived.
+pub fn read_data<R: Read + ?Sized>(port: &mut R, header: &DumpHeader, timeout: Duration, mut progress: impl FnMut(u64)) -> Result<Vec<u8>> {
+    let total = header.length as u64;
+    let mut data = vec![0; header.length as usize];
+    let mut received = 0;
+    while received < data.len() {
+        let end = data.len().min(received + READ_CHUNK);
+        match port.read_serial_exact_timeout(&mut data[received..end], timeout) {
+            Ok(()) => received = end,
+            Err(ErrorKind::ReadTimeout { received: more, .. }) => {
+                return Err(ErrorKind::DumpIncomplete { received: (received + more) as u64, total });
+            }
+            Err(e) => return Err(e),
+        }
+        progress(received as u64);
+    }
+    let mut crc = [0; 4];
+    match port.read_serial_exact_timeout(&mut crc, timeout) {
+        Ok(()) => {}
+        Err(ErrorKind::ReadTimeout { .. }) => return Err(ErrorKind::DumpIncomplete { received: total, total }),
+        Err(e) => return Err(e),
+    }
+    let (expected, actual) = (u32::from_le_bytes(crc), crc32(&data));
+    if expected != actual { return Err(ErrorKind::DumpCorrupt { expected, actual }); }
+    Ok(data)
+}
+
+/// Where a dump goes: `output`, or into it when it is a directory, under the name the target
+/// gave or `dump-<unix time>.bin` without a usable one.
+pub fn dump_path(header: &DumpHeader, output: Option<&Path>) -> PathBuf {
+    let named = || ymodem::sanitize_name(&header.name).unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        format!("dump-{}.bin", now)
+    });
+    match output {
+        Some(dir) if dir.is_dir() => dir.join(named()),
+        Some(path) => path.to_path_buf(),
+        None => PathBuf::from(named()),
+    }
+}
+
+/// A dump written to disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pulled {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// What came after the frame in the same read, console output again.
+    pub after: Vec<u8>,
+}
+
+/// Receives the rest of a dump whose magic was just seen, `rest` being what came after it in
+/// the same read, with a progress bar on `out`, and writes it out.
+pub fn catch<R: Read + ?Sized>(port: &mut R, rest: &[u8], output: Option<&Path>, out: &Output) -> Result<Pulled> {
+    let mut reader = Cursor::new(rest).chain(port);
+    let header = read_header(&mut reader, DUMP_TIMEOUT)?;
+    let path = dump_path(&header, output);
+    let mut pb = out.progress_bar(Icon::Save, &format!("Pulling {}", path.display()), header.length as u64);
+    let data = read_data(&mut reader, &header, DUMP_TIMEOUT, |bytes| if let Some(pb) = pb.as_mut() { pb.set(bytes); });
+    out.finish_progress(pb);
+    let data = data?;
+    fs::write(&path, &data)?;
+    out.status(format!("{} Pulled {}, {}", out.icon(Icon::Ok), path.display(), format_bytes(data.len() as u64)));
+    let (cursor, _) = reader.into_inner();
+    let after = rest[cursor.position() as usize..].to_vec();
+    Ok(Pulled { path, bytes: data.len() as u64, after })
+}
+
+/// The terminal's `pull` command: armed, the reader looks for the next dump in what the
+/// target prints and catches it in the same session.
+#[derive(Default)]
+pub struct PullSwitch(Mutex<Option<(Option<PathBuf>, MagicScanner)>>);
+
+impl PullSwitch {
+    /// Catches the next dump, into `output` if given.
+    pub fn arm(&self, output: Option<PathBuf>) {
+        *self.0.lock().unwrap() = Some((output, MagicScanner::default()));
+    }
+
+    /// Stops looking; whether it was.
+    pub fn disarm(&self) -> bool {
+        self.0.lock().unwrap().take().is_some()
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// Like [`MagicScanner::feed`] while armed; `None` when not, all of `data` being output.
+    pub fn scan(&self, data: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        self.0.lock().unwrap().as_mut().map(|(_, scanner)| scanner.feed(data))
+    }
+
+    /// Receives the dump [`PullSwitch::scan`] found, disarming; how it went is said on `out`
+    /// rather than ending the session. Hands back what to show after it.
+    pub fn catch<R: Read + ?Sized>(&self, port: &mut R, rest: &[u8], out: &Output) -> Vec<u8> {
+        let output = self.0.lock().unwrap().take().and_then(|(output, _)| output);
+        match catch(port, rest, output.as_deref(), out) {
+            Ok(pulled) => pulled.after,
+            Err(e) => {
+                out.warn(format!("Dump not pulled: {}", e));
+                Vec::new()
+            }
+        }
+    }
+}
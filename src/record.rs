@@ -0,0 +1,133 @@
+//! Session recordings: everything that crossed the line, timed, so it can be replayed later
+//! without the hardware.
+//!
+//! A recording starts with [`MAGIC`], followed by frames of
+//! `length: u32 LE | delta_us: u64 LE | direction: u8 | data[length]`, where `delta_us` is
+//! the time since the previous frame.
+
+use std::{fmt, fs::File, io::{self, BufReader, BufWriter, ErrorKind as IoErrorKind, Read, Write}, path::Path, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::{ErrorKind, output::{Output, WriteWarning}, Result, terminal::RxTap};
+
+pub const MAGIC: [u8; 8] = *b"MTREC\x00\x00\x01";
+
+/// Longest frame accepted when reading, to fail fast on a file that isn't a recording.
+pub const MAX_FRAME: u32 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the target.
+    Rx,
+    /// Sent to the target.
+    Tx,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Time since the previous frame.
+    pub delta: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+pub fn write_frame<W: Write + ?Sized>(out: &mut W, frame: &Frame) -> io::Result<()> {
+    out.write_all(&(frame.data.len() as u32).to_le_bytes())?;
+    out.write_all(&(frame.delta.as_micros() as u64).to_le_bytes())?;
+    out.write_all(&[match frame.direction { Direction::Rx => 0, Direction::Tx => 1 }])?;
+    out.write_all(&frame.data)
+}
+
+/// The next frame, or `None` at a clean end of the recording.
+pub fn read_frame<R: Read + ?Sized>(input: &mut R) -> Result<Option<Frame>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME { return Err(ErrorKind::RecordingError(format!("frame of {} bytes", len))); }
+
+    let mut header = [0; 9];
+    let mut data = vec![0; len as usize];
+    input.read_exact(&mut header).and_then(|_| input.read_exact(&mut data)).map_err(|e| match e.kind() {
+        IoErrorKind::UnexpectedEof => ErrorKind::RecordingError("truncated frame".to_string()),
+        _ => ErrorKind::IoError(e),
+    })?;
+
+    let mut delta = [0; 8];
+    delta.copy_from_slice(&header[..8]);
+    let direction = match header[8] {
+        0 => Direction::Rx,
+        1 => Direction::Tx,
+        other => return Err(ErrorKind::RecordingError(format!("unknown direction {}", other))),
+    };
+    Ok(Some(Frame { delta: Duration::from_micros(u64::from_le_bytes(delta)), direction, data }))
+}
+
+/// Checks that `input` starts like a recording.
+pub fn read_magic<R: Read + ?Sized>(input: &mut R) -> Result<()> {
+    let mut magic = [0; 8];
+    match input.read_exact(&mut magic) {
+        Ok(()) if magic == MAGIC => Ok(()),
+        Ok(()) => Err(ErrorKind::RecordingError("not a session recording".to_string())),
+        Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => Err(ErrorKind::RecordingError("not a session recording".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// All frames of the recording at `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Frame>> {
+    let mut input = BufReader::new(File::open(path)?);
+    read_magic(&mut input)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = read_frame(&mut input)? { frames.push(frame); }
+    Ok(frames)
+}
+
+/// Writes a recording as the session goes; shared by the reader thread and the input loop.
+pub struct Recorder {
+    inner: Mutex<(Box<dyn Write + Send>, Instant)>,
+    failed: WriteWarning,
+}
+
+impl Recorder {
+    pub fn new(mut out: Box<dyn Write + Send>) -> io::Result<Self> {
+        out.write_all(&MAGIC)?;
+        out.flush()?;
+        Ok(Self { inner: Mutex::new((out, Instant::now())), failed: WriteWarning::default() })
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Warns on `out` when recording to `what`, e.g. the file's path, fails, once.
+    pub fn warn_to<D: fmt::Display>(mut self, out: &Output, what: D) -> Self {
+        self.failed = WriteWarning::new(out, what);
+        self
+    }
+
+    pub fn record(&self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() { return Ok(()); }
+        let mut inner = self.inner.lock().unwrap();
+        let (out, last) = &mut *inner;
+        let now = Instant::now();
+        let frame = Frame { delta: now - *last, direction, data: data.to_vec() };
+        *last = now;
+        // flushed right away, so a crash or a pulled cable still leaves everything up to here
+        write_frame(out, &frame).and_then(|_| out.flush())
+    }
+
+    /// Like [`Recorder::record`], a failure being warned about rather than returned: the
+    /// session goes on without the recording.
+    pub fn note(&self, direction: Direction, data: &[u8]) {
+        self.failed.check(self.record(direction, data));
+    }
+}
+
+impl RxTap for Arc<Recorder> {
+    fn rx(&mut self, data: &[u8]) {
+        self.note(Direction::Rx, data);
+    }
+}
@@ -0,0 +1,92 @@
+//! `--report`: a JSON record of how a push went, for an audit trail of e.g. every unit flashed.
+
+use std::{fs, io::{self, Write}, path::Path, time::SystemTime};
+
+use serde::Serialize;
+
+use crate::{observer::PushReport, phases::TimingReport, timesync::Iso8601};
+
+/// What `--report` writes once the session is over.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferReport {
+    /// When the session ended, e.g. `2024-02-29T13:05:09Z`.
+    pub timestamp: String,
+    pub host: String,
+    pub device: String,
+    /// Of the USB adapter, when it is one and says.
+    pub usb_serial: Option<String>,
+    /// `-` for an image piped in.
+    pub image: String,
+    /// `ok` or `failed`.
+    pub status: &'static str,
+    /// Why it failed.
+    pub error: Option<String>,
+    pub reconnects: u64,
+    /// What the push used beyond the classic exchange, e.g. `block` or `resume`.
+    pub features: Vec<&'static str>,
+    /// Bytes per second of the last push.
+    pub rate: Option<f64>,
+    /// The last push that got through, `None` when none did.
+    pub push: Option<PushReport>,
+    /// Where the time went, phase by phase and attempt by attempt.
+    pub timings: Option<TimingReport>,
+}
+
+impl TransferReport {
+    /// A report stamped with the time and host, `push` filled in from what happened.
+    pub fn new(device: &str, image: &str, push: Option<PushReport>, error: Option<String>) -> Self {
+        let unix = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        Self {
+            timestamp: Iso8601(unix).to_string(),
+            host: hostname().unwrap_or_default(),
+            device: device.to_string(),
+            usb_serial: None,
+            image: image.to_string(),
+            status: if error.is_none() { "ok" } else { "failed" },
+            error,
+            reconnects: 0,
+            features: Vec::new(),
+            rate: push.as_ref().map(PushReport::rate),
+            push,
+            timings: None,
+        }
+    }
+}
+
+/// Writes `report` as pretty-printed JSON to `path`, `-` for stdout. A file is written under a
+/// temporary name and renamed, so there never is half a report.
+pub fn write<T: Serialize>(report: &T, path: &Path) -> io::Result<()> {
+    let mut json = serde_json::to_vec_pretty(report).map_err(io::Error::from)?;
+    json.push(b'\n');
+    if path == Path::new("-") {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&json)?;
+        return stdout.flush();
+    }
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let part = path.with_file_name(format!("{}.part", name.to_string_lossy()));
+    let written = fs::File::create(&part).and_then(|mut file| {
+        file.write_all(&json)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&part, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 { return None; }
+    let len = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
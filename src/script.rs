@@ -0,0 +1,225 @@
+//! Unattended expect/send scripts, e.g.
+//!
+//! ```text
+//! expect "login:" timeout 30
+//! send "root\n"
+//! expect regex "#\s*$"
+//! sleep 0.5
+//! send "uname -a\n"
+//! expect "Linux" timeout 5
+//! ```
+
+use std::{fs, io::{Read, Write}, path::Path, thread, time::{Duration, Instant}};
+
+use crate::{ErrorKind, pattern::{Pattern, StreamBuffer}, ReadSerial, Result};
+
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    Expect { pattern: Pattern, timeout: Duration },
+    Send(Vec<u8>),
+    Sleep(Duration),
+}
+
+/// Result of one executed step, for printing a transcript.
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    pub line: usize,
+    pub description: String,
+    pub elapsed: Duration,
+    pub matched: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    steps: Vec<(usize, Step)>,
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Script::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line_no = idx + 1;
+            let err = |reason: String| ErrorKind::ScriptError(format!("line {}: {}", line_no, reason));
+            let tokens = tokenize(line).map_err(err)?;
+            if tokens.is_empty() { continue; }
+
+            let step = parse_step(&tokens).map_err(err)?;
+            steps.push((line_no, step));
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item=&Step> {
+        self.steps.iter().map(|(_, step)| step)
+    }
+
+    /// Runs the steps against `port`, handing everything received to `echo`. Fails with
+    /// `ScriptError` on the first expect that doesn't match in time.
+    pub fn run<P, F>(&self, port: &mut P, mut echo: F) -> Result<Vec<StepOutcome>>
+        where P: Read + Write + ?Sized, F: FnMut(&[u8]) {
+        let mut buffer = StreamBuffer::default();
+        let mut outcomes = Vec::with_capacity(self.steps.len());
+        let mut received = [0; 1024];
+
+        for (line, step) in &self.steps {
+            let started = Instant::now();
+            let mut matched = None;
+            match step {
+                Step::Expect { pattern, timeout } => {
+                    let deadline = started + *timeout;
+                    loop {
+                        if let Some(m) = buffer.take_match(pattern) {
+                            matched = Some(m);
+                            break;
+                        }
+                        if Instant::now() >= deadline {
+                            return Err(ErrorKind::ScriptError(format!(
+                                "line {}: expect {} timed out after {:.1}s", line, pattern, timeout.as_secs_f64())));
+                        }
+                        let n = port.read_serial(&mut received)?;
+                        if n > 0 {
+                            echo(&received[..n]);
+                            buffer.push(&received[..n]);
+                        }
+                    }
+                }
+                Step::Send(data) => port.write_all(data)?,
+                Step::Sleep(duration) => thread::sleep(*duration),
+            }
+            outcomes.push(StepOutcome { line: *line, description: describe(step), elapsed: started.elapsed(), matched });
+        }
+        Ok(outcomes)
+    }
+}
+
+fn describe(step: &Step) -> String {
+    match step {
+        Step::Expect { pattern, .. } => format!("expect {}", pattern),
+        Step::Send(data) => format!("send {:?}", String::from_utf8_lossy(data)),
+        Step::Sleep(duration) => format!("sleep {:.1}s", duration.as_secs_f64()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// Quoted text with its backslash escapes still in place.
+    Quoted(String),
+}
+
+fn parse_step(tokens: &[Token]) -> std::result::Result<Step, String> {
+    let word = |t: &Token| match t {
+        Token::Word(w) => Ok(w.clone()),
+        Token::Quoted(q) => Err(format!("unexpected string \"{}\"", q)),
+    };
+    let quoted = |t: Option<&Token>| match t {
+        Some(Token::Quoted(q)) => Ok(q.clone()),
+        _ => Err("expected a quoted string".to_string()),
+    };
+    let seconds = |t: Option<&Token>| -> std::result::Result<Duration, String> {
+        let s = t.map(word).transpose()?.ok_or("expected a number of seconds")?;
+        s.parse::<f64>().ok()
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("invalid number of seconds {:?}", s))
+    };
+
+    let mut rest = tokens[1..].iter();
+    let step = match word(&tokens[0])?.as_str() {
+        "expect" => {
+            let mut next = rest.next();
+            let pattern = if next == Some(&Token::Word("regex".to_string())) {
+                next = rest.next();
+                Pattern::regex(&quoted(next)?).map_err(|e| e.to_string())?
+            } else {
+                Pattern::literal(unescape(&quoted(next)?)?)
+            };
+            let timeout = match rest.next() {
+                Some(Token::Word(w)) if w == "timeout" => seconds(rest.next())?,
+                Some(t) => return Err(format!("expected `timeout`, got {:?}", t)),
+                None => DEFAULT_EXPECT_TIMEOUT,
+            };
+            Step::Expect { pattern, timeout }
+        }
+        "send" => Step::Send(unescape(&quoted(rest.next())?)?),
+        "sleep" => Step::Sleep(seconds(rest.next())?),
+        other => return Err(format!("unknown command {:?}, expected expect, send or sleep", other)),
+    };
+
+    match rest.next() {
+        Some(t) => Err(format!("trailing {:?}", t)),
+        None => Ok(step),
+    }
+}
+
+fn tokenize(line: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => {
+                        text.push('\\');
+                        text.push(chars.next().ok_or("unterminated string")?.1);
+                    }
+                    Some((_, c)) => text.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Quoted(text));
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '"' { break; }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Resolves `\n`, `\r`, `\t`, `\\`, `\"`, `\0` and `\xNN` escapes.
+pub fn unescape(s: &str) -> std::result::Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('\\') => out.push(b'\\'),
+            Some('"') => out.push(b'"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("invalid escape \\x{}", hex))?;
+                out.push(byte);
+            }
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err("dangling backslash".to_string()),
+        }
+    }
+    Ok(out)
+}
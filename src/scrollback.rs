@@ -0,0 +1,119 @@
+//! The last of what the target printed, kept in memory: a raw-mode terminal has no scrollback
+//! of its own, and what scrolled away is often the panic that matters. The `scrollback`
+//! command writes it to a file, and so does a session that ends badly, see [`dump_failed`].
+
+use std::{env, fs, io, path::{Path, PathBuf}, process, sync::{Arc, Mutex}};
+
+use crate::terminal::RxTap;
+
+/// The last `capacity` bytes received, in a buffer that is filled once and then written over
+/// from the oldest byte on, so keeping a read costs a copy or two.
+pub struct Scrollback(Mutex<Ring>);
+
+struct Ring {
+    buf: Vec<u8>,
+    capacity: usize,
+    /// Where the oldest byte is once `buf` is full.
+    start: usize,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self(Mutex::new(Ring { buf: Vec::new(), capacity, start: 0 }))
+    }
+
+    pub fn push(&self, data: &[u8]) {
+        let mut ring = self.0.lock().unwrap();
+        let capacity = ring.capacity;
+        let mut data = &data[data.len().saturating_sub(capacity)..];
+        let room = capacity - ring.buf.len();
+        if room > 0 {
+            let (now, rest) = data.split_at(data.len().min(room));
+            ring.buf.extend_from_slice(now);
+            data = rest;
+        }
+        while !data.is_empty() {
+            let start = ring.start;
+            let n = (capacity - start).min(data.len());
+            ring.buf[start..start + n].copy_from_slice(&data[..n]);
+            ring.start = (start + n) % capacity;
+            data = &data[n..];
+        }
+    }
+
+    /// All that is kept, oldest first.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().contents()
+    }
+
+    /// The last `n` lines of what is kept, a partial line at the end counting as one.
+    pub fn last_lines(&self, n: usize) -> Vec<u8> {
+        if n == 0 { return Vec::new(); }
+        let mut all = self.contents();
+        let body = all.strip_suffix(b"\n").unwrap_or(&all);
+        let mut start = body.len();
+        for _ in 0..n {
+            match body[..start].iter().rposition(|&b| b == b'\n') {
+                Some(at) => start = at,
+                None => return all,
+            }
+        }
+        all.drain(..=start);
+        all
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the last `lines`, or all that is kept, to `path`; how many bytes that was.
+    pub fn dump(&self, path: &Path, lines: Option<usize>) -> io::Result<usize> {
+        let data = match lines {
+            Some(lines) => self.last_lines(lines),
+            None => self.contents(),
+        };
+        fs::write(path, &data)?;
+        Ok(data.len())
+    }
+}
+
+impl Ring {
+    fn contents(&self) -> Vec<u8> {
+        [&self.buf[self.start..], &self.buf[..self.start]].concat()
+    }
+}
+
+impl RxTap for Arc<Scrollback> {
+    fn rx(&mut self, data: &[u8]) {
+        self.push(data);
+    }
+}
+
+static ON_FAILURE: Mutex<Option<(Arc<Scrollback>, PathBuf)>> = Mutex::new(None);
+
+/// Where a failed session's scrollback goes unless `--scrollback-dump` says otherwise.
+pub fn default_dump_path(tool: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}-scrollback-{}.log", tool, process::id()))
+}
+
+/// Has [`dump_failed`] write `scrollback` to `path`.
+pub fn dump_on_failure(scrollback: Arc<Scrollback>, path: PathBuf) {
+    *ON_FAILURE.lock().unwrap() = Some((scrollback, path));
+}
+
+/// Writes out the scrollback [`dump_on_failure`] named, for a session that just failed, e.g.
+/// lost the port or matched `--exit-on-failure`; where it went, if anything had been received.
+/// Each time overwrites the last, the scrollback having all of that and more.
+pub fn dump_failed() -> Option<PathBuf> {
+    // try_lock: this also runs in the panic hook, whose thread may hold either lock
+    let armed = ON_FAILURE.try_lock().ok()?;
+    let (scrollback, path) = armed.as_ref()?;
+    let data = scrollback.0.try_lock().ok()?.contents();
+    if data.is_empty() { return None; }
+    fs::write(path, &data).ok().map(|_| path.clone())
+}
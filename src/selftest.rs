@@ -0,0 +1,123 @@
+//! Loopback check of the adapter and cable: with TX jumpered to RX, everything written must
+//! come back unchanged.
+
+use std::{fmt, io::{Read, Write}, time::{Duration, Instant}};
+
+use crate::{bench::{self, Xorshift}, ErrorKind, output::format_bytes, ReadSerial, Result, settings::{DataBits, SerialSettings}};
+
+/// Chunk sizes cycled through, so both byte-at-a-time and bulk transfers are covered.
+pub const CHUNK_SIZES: [usize; 6] = [1, 7, 64, 255, 1024, 4096];
+
+/// How many mismatch offsets a report keeps.
+pub const MAX_REPORTED_ERRORS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct SelftestConfig {
+    pub settings: SerialSettings,
+    /// Stop after this long...
+    pub duration: Duration,
+    /// ...or after this many bytes, whichever comes first.
+    pub bytes: Option<u64>,
+    pub seed: u32,
+}
+
+impl Default for SelftestConfig {
+    fn default() -> Self {
+        Self { settings: SerialSettings::default(), duration: Duration::from_secs(10), bytes: None, seed: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelftestReport {
+    pub sent: u64,
+    pub received: u64,
+    pub mismatched: u64,
+    pub dropped: u64,
+    /// Stream offsets of the first mismatched bytes.
+    pub error_offsets: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+impl SelftestReport {
+    pub fn passed(&self) -> bool {
+        self.sent > 0 && self.mismatched == 0 && self.dropped == 0
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.received as f64 / secs } else { 0.0 }
+    }
+}
+
+impl fmt::Display for SelftestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sent, {} received, {} mismatched, {} dropped in {:.1}s ({}/s)",
+               format_bytes(self.sent), format_bytes(self.received), self.mismatched, self.dropped,
+               self.elapsed.as_secs_f64(), format_bytes(self.bytes_per_sec() as u64))?;
+        if !self.error_offsets.is_empty() {
+            let offsets: Vec<String> = self.error_offsets.iter().map(|o| o.to_string()).collect();
+            write!(f, "\nfirst errors at offsets {}", offsets.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a pseudo-random sequence and checks that it loops back. Fails fast with
+/// `SelftestError` when nothing comes back at all.
+pub fn run<P, F>(port: &mut P, config: &SelftestConfig, mut progress: F) -> Result<SelftestReport>
+    where P: Read + Write + ?Sized, F: FnMut(&SelftestReport) {
+    let mask = match config.settings.data_bits {
+        DataBits::Five => 0x1F,
+        DataBits::Six => 0x3F,
+        DataBits::Seven => 0x7F,
+        DataBits::Eight => 0xFF,
+    };
+    let mut rng = Xorshift::new(config.seed);
+    let mut report = SelftestReport::default();
+    let mut sent = vec![0; CHUNK_SIZES[CHUNK_SIZES.len() - 1]];
+    let mut echoed = vec![0; sent.len()];
+
+    bench::drain(port, Duration::from_millis(50))?;
+    let started = Instant::now();
+    for &chunk_size in CHUNK_SIZES.iter().cycle() {
+        let len = match config.bytes {
+            Some(limit) if report.sent >= limit => break,
+            Some(limit) => chunk_size.min((limit - report.sent) as usize),
+            None => chunk_size,
+        };
+        if started.elapsed() >= config.duration { break; }
+
+        rng.fill(&mut sent[..len]);
+        sent[..len].iter_mut().for_each(|b| *b &= mask);
+        port.write_all(&sent[..len]).map_err(|_| ErrorKind::ConnectionError)?;
+
+        let got = port.read_serial_timeout(&mut echoed[..len], chunk_deadline(&config.settings, len))?;
+        if got == 0 && report.received == 0 {
+            return Err(ErrorKind::SelftestError(
+                "nothing looped back; is TX jumpered to RX and flow control off?".to_string()));
+        }
+
+        for (i, (a, b)) in sent[..got].iter().zip(&echoed[..got]).enumerate() {
+            if a != b {
+                report.mismatched += 1;
+                if report.error_offsets.len() < MAX_REPORTED_ERRORS { report.error_offsets.push(report.sent + i as u64); }
+            }
+        }
+        if got < len {
+            report.dropped += (len - got) as u64;
+            // whatever straggles in belongs to this chunk, not the next one
+            bench::drain(port, Duration::from_millis(50))?;
+        }
+        report.sent += len as u64;
+        report.received += got as u64;
+        report.elapsed = started.elapsed();
+        progress(&report);
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Twice the wire time of `len` characters plus slack for USB latency.
+fn chunk_deadline(settings: &SerialSettings, len: usize) -> Duration {
+    Duration::from_secs_f64(2.0 * len as f64 / settings.bytes_per_sec()) + Duration::from_millis(200)
+}
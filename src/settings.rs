@@ -0,0 +1,250 @@
+use std::{fmt, str::FromStr, thread, time::Duration};
+
+pub use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+use crate::SERIAL_BAUD;
+
+/// Line settings applied when the port is opened. Defaults to 921600 8N1 without flow control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self {
+            baud_rate: SERIAL_BAUD,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+impl SerialSettings {
+    pub fn apply(&self, builder: serialport::SerialPortBuilder) -> serialport::SerialPortBuilder {
+        builder
+            .baud_rate(self.baud_rate)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control)
+    }
+
+    /// Start, data, parity and stop bits of one character on the wire.
+    pub fn bits_per_char(&self) -> u32 {
+        let data = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = if self.parity == Parity::None { 0 } else { 1 };
+        let stop = if self.stop_bits == StopBits::One { 1 } else { 2 };
+        1 + data + parity + stop
+    }
+
+    /// Highest payload rate the line can carry, in bytes per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.baud_rate as f64 / self.bits_per_char() as f64
+    }
+
+    /// Short `8N1`-style notation of the character framing.
+    pub fn framing(&self) -> String {
+        let data = match self.data_bits {
+            DataBits::Five => '5',
+            DataBits::Six => '6',
+            DataBits::Seven => '7',
+            DataBits::Eight => '8',
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => '1',
+            StopBits::Two => '2',
+        };
+        format!("{}{}{}", data, parity, stop)
+    }
+}
+
+impl fmt::Display for SerialSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flow = match self.flow_control {
+            FlowControl::None => "no flow control",
+            FlowControl::Software => "XON/XOFF flow control",
+            FlowControl::Hardware => "RTS/CTS flow control",
+        };
+        write!(f, "{} {}, {}", self.baud_rate, self.framing(), flow)
+    }
+}
+
+/// Parses framing notation such as `8N1` or `7E1` into data bits, parity and stop bits.
+pub fn parse_framing(s: &str) -> Result<(DataBits, Parity, StopBits), String> {
+    let chars: Vec<char> = s.trim().chars().collect();
+    if chars.len() != 3 {
+        return Err(format!("expected framing like 8N1 or 7E1, got {:?}", s));
+    }
+
+    let data_bits = match chars[0] {
+        '5' => DataBits::Five,
+        '6' => DataBits::Six,
+        '7' => DataBits::Seven,
+        '8' => DataBits::Eight,
+        c => return Err(format!("data bits must be 5-8, got {:?}", c)),
+    };
+    let parity = match chars[1].to_ascii_uppercase() {
+        'N' => Parity::None,
+        'E' => Parity::Even,
+        'O' => Parity::Odd,
+        c => return Err(format!("parity must be N, E or O, got {:?}", c)),
+    };
+    let stop_bits = match chars[2] {
+        '1' => StopBits::One,
+        '2' => StopBits::Two,
+        c => return Err(format!("stop bits must be 1 or 2, got {:?}", c)),
+    };
+
+    if data_bits == DataBits::Five && stop_bits == StopBits::Two {
+        return Err("5 data bits use 1.5 stop bits on most UARTs, 2 is not supported".to_string());
+    }
+    Ok((data_bits, parity, stop_bits))
+}
+
+pub fn parse_flow_control(s: &str) -> Result<FlowControl, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "none" => Ok(FlowControl::None),
+        "software" | "xonxoff" => Ok(FlowControl::Software),
+        "hardware" | "rtscts" => Ok(FlowControl::Hardware),
+        _ => Err(format!("flow control must be none, software or hardware, got {:?}", s)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetLine {
+    Dtr,
+    Rts,
+}
+
+impl fmt::Display for ResetLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ResetLine::Dtr => "DTR",
+            ResetLine::Rts => "RTS",
+        })
+    }
+}
+
+pub fn parse_reset_line(s: &str) -> Result<ResetLine, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "dtr" => Ok(ResetLine::Dtr),
+        "rts" => Ok(ResetLine::Rts),
+        _ => Err(format!("reset line must be dtr or rts, got {:?}", s)),
+    }
+}
+
+/// Hardware reset through a modem-control line wired to the target's reset pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetPulse {
+    pub line: ResetLine,
+    pub hold: Duration,
+    /// Pulse by deasserting the line instead of asserting it.
+    pub active_low: bool,
+}
+
+impl ResetPulse {
+    pub fn pulse(&self, port: &mut dyn serialport::SerialPort) -> serialport::Result<()> {
+        let mut set = |level: bool| match self.line {
+            ResetLine::Dtr => port.write_data_terminal_ready(level),
+            ResetLine::Rts => port.write_request_to_send(level),
+        };
+        set(!self.active_low)?;
+        thread::sleep(self.hold);
+        set(self.active_low)
+    }
+}
+
+/// How long the target gets to answer a sync before what it sent is dropped.
+pub const SYNC_SETTLE: Duration = Duration::from_millis(50);
+
+/// `--sync-on-connect`: what is done right after the port opens, for targets whose UART comes
+/// up mid-byte or waits to measure the baud rate. What the target receives meanwhile is
+/// dropped, so it reaches neither the handshake nor the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    /// A break this long.
+    Break(Duration),
+    /// This many `0x55` bytes, whose alternating bits an autobaud detector measures.
+    Autobaud(usize),
+    /// Nothing sent, only a wait this long.
+    Quiet(Duration),
+}
+
+impl FromStr for SyncAction {
+    type Err = String;
+
+    /// `break[:MS]`, `autobaud[:COUNT]` or `quiet[:MS]`, by default 250 ms, 16 bytes and 100 ms.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        let number = |default: u64| match value {
+            None => Ok(default),
+            Some(value) => match value.parse::<u64>() {
+                Ok(0) | Err(_) => Err(format!("expected a number greater than zero, got {:?}", value)),
+                Ok(n) => Ok(n),
+            },
+        };
+        match name {
+            "break" => Ok(SyncAction::Break(Duration::from_millis(number(250)?))),
+            "autobaud" => Ok(SyncAction::Autobaud(number(16)? as usize)),
+            "quiet" => Ok(SyncAction::Quiet(Duration::from_millis(number(100)?))),
+            _ => Err("expected break[:MS], autobaud[:COUNT] or quiet[:MS]".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncAction::Break(duration) => write!(f, "a {} ms break", duration.as_millis()),
+            SyncAction::Autobaud(count) => write!(f, "{} × 0x55", count),
+            SyncAction::Quiet(duration) => write!(f, "{} ms of quiet", duration.as_millis()),
+        }
+    }
+}
+
+impl SyncAction {
+    /// Whether it sends anything, which a read-only session mustn't.
+    pub fn writes(&self) -> bool {
+        !matches!(self, SyncAction::Quiet(_))
+    }
+
+    /// Carries it out, waits [`SYNC_SETTLE`] after a break or the bytes for the target's answer,
+    /// then drops everything received.
+    pub fn apply(&self, port: &mut dyn serialport::SerialPort) -> serialport::Result<()> {
+        match *self {
+            SyncAction::Break(duration) => {
+                port.set_break()?;
+                thread::sleep(duration);
+                port.clear_break()?;
+                thread::sleep(SYNC_SETTLE);
+            }
+            SyncAction::Autobaud(count) => {
+                port.write_all(&vec![0x55; count])?;
+                port.flush()?;
+                thread::sleep(SYNC_SETTLE);
+            }
+            SyncAction::Quiet(duration) => thread::sleep(duration),
+        }
+        port.clear(serialport::ClearBuffer::Input)
+    }
+}
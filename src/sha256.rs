@@ -0,0 +1,52 @@
+//! SHA-256, for recording which image went to the board and checking it against a published
+//! digest. The hashing is the sha2 crate's.
+
+use std::io::{self, Write};
+
+use sha2::Digest;
+
+/// `--sha256`: 64 hex digits.
+pub fn parse(s: &str) -> std::result::Result<[u8; 32], String> {
+    let s = s.trim();
+    if s.len() != 64 || !s.bytes().all(|c| c.is_ascii_hexdigit()) { return Err("expected 64 hex digits".to_string()); }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    Ok(digest)
+}
+
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// SHA-256 of a stream, fed as it arrives.
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+
+    /// SHA-256 of `data` in one go.
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        sha2::Sha256::digest(data).into()
+    }
+}
+
+/// Hashes what is written, e.g. an image copied through it with [`io::copy`].
+impl Write for Sha256 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Ending on Ctrl-C, SIGTERM or SIGHUP: lock files are released and raw mode left first, and
+//! waits can ask to be interrupted instead.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{lock, terminal};
+
+/// What a tool exits with after Ctrl-C ended it, as a shell reports a process killed by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// How many waits are catching them, e.g. one per board being pushed to.
+static CATCHING: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Where the handler writes the signal for the thread that ends the process, -1 until installed.
+#[cfg(unix)]
+static WAKE: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+/// Installs the handlers, once however often it is called. The handler itself only notes the
+/// signal, that being all that is safe there; releasing the locks and leaving raw mode is up
+/// to a thread of its own.
+#[cfg(unix)]
+pub fn install() {
+    use std::{io::Read, os::unix::{io::IntoRawFd, net::UnixStream}, sync::Once, thread};
+
+    extern "C" fn handler(signal: libc::c_int) {
+        if CATCHING.load(Ordering::SeqCst) > 0 {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            return;
+        }
+        let byte = signal as u8;
+        unsafe { libc::write(WAKE.load(Ordering::SeqCst), &byte as *const u8 as *const libc::c_void, 1); }
+    }
+
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let (mut woken, wake) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            // without it the signals keep their default, ending the process as they would
+            Err(_) => return,
+        };
+        WAKE.store(wake.into_raw_fd(), Ordering::SeqCst);
+        thread::spawn(move || {
+            let mut signal = [0];
+            if woken.read_exact(&mut signal).is_err() { return; }
+            lock::release_all();
+            terminal::RAW_MODE.restore();
+            unsafe {
+                libc::signal(signal[0] as libc::c_int, libc::SIG_DFL);
+                libc::raise(signal[0] as libc::c_int);
+            }
+        });
+        for &signal in &[libc::SIGHUP, libc::SIGINT, libc::SIGTERM] {
+            unsafe { libc::signal(signal, handler as extern "C" fn(libc::c_int) as libc::sighandler_t); }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Runs `wait` with those signals only noted, for it to poll [`interrupted`] and return early.
+/// Waits on several threads at once all see the same interrupt.
+pub fn catch_interrupts<T>(wait: impl FnOnce() -> T) -> T {
+    // only the first clears an interrupt, the others would hide it from it
+    if CATCHING.fetch_add(1, Ordering::SeqCst) == 0 { INTERRUPTED.store(false, Ordering::SeqCst); }
+    let result = wait();
+    CATCHING.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
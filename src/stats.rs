@@ -0,0 +1,98 @@
+//! Counters of a whole `run()`, reconnects included, summarized when the tool exits.
+
+use std::{fmt, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use crate::{output::format_bytes, terminal::RxTap};
+
+/// Updated from the reader thread and the input loop at once, hence atomics throughout.
+#[derive(Debug)]
+pub struct SessionStats {
+    started: Instant,
+    received: AtomicU64,
+    sent: AtomicU64,
+    reconnects: AtomicU64,
+    pushed: AtomicU64,
+    push_micros: AtomicU64,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            pushed: AtomicU64::new(0),
+            push_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn add_received(&self, bytes: u64) {
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_sent(&self, bytes: u64) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An image of `bytes` went out in `elapsed`. Its bytes are counted as sent chunk by chunk.
+    pub fn add_push(&self, bytes: u64, elapsed: Duration) {
+        self.pushed.fetch_add(bytes, Ordering::Relaxed);
+        self.push_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            duration: self.started.elapsed(),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            pushed: self.pushed.load(Ordering::Relaxed),
+            push_time: Duration::from_micros(self.push_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl RxTap for Arc<SessionStats> {
+    fn rx(&mut self, data: &[u8]) {
+        self.add_received(data.len() as u64);
+    }
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub received: u64,
+    pub sent: u64,
+    pub duration: Duration,
+    pub reconnects: u64,
+    /// Image bytes pushed, 0 for a plain terminal session.
+    pub pushed: u64,
+    pub push_time: Duration,
+}
+
+impl SessionSummary {
+    pub fn push_bytes_per_sec(&self) -> f64 {
+        let secs = self.push_time.as_secs_f64();
+        if secs > 0.0 { self.pushed as f64 / secs } else { 0.0 }
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} received, {} sent in {:.1}s, {} reconnect{}",
+               format_bytes(self.received), format_bytes(self.sent), self.duration.as_secs_f64(),
+               self.reconnects, if self.reconnects == 1 { "" } else { "s" })?;
+        if self.pushed > 0 {
+            write!(f, "; pushed {} in {:.1}s ({}/s)", format_bytes(self.pushed), self.push_time.as_secs_f64(),
+                   format_bytes(self.push_bytes_per_sec() as u64))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1470 @@
+use std::{borrow::Cow, io::{self, IsTerminal, Read, stderr, stdout, Write}, mem, process, str::FromStr, thread, time::{Duration, Instant, SystemTime}};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering}, mpsc::{self, RecvTimeoutError}, Mutex};
+
+use crossterm::{cursor::MoveTo, event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, execute, style::Color, terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode}};
+
+use crate::{ansi::{self, Sanitizer}, control::{self, ControlSocket, Reply}, command::{Chord, Command, CommandTable, key_name}, ErrorKind, highlight::Highlighter, idle::{IdleAction, UtcTime, Watchdog}, limit::RateLimiter, output::{self, format_bytes, format_duration, Icon, Output, Verbosity}, ReadSerial, Result, SERIAL_BAUD, SerialPort, WRITE_TIMEOUT, WriteSerial};
+use crate::{filter::{FilterChain, FilterKind}, keys::{self, KeyEncoding}, paste::{Paste, PASTE_THRESHOLD}, prompt::{Context, Edit, LineEditor, LocalCommands, LogSwitch, PROMPT}, pull::PullSwitch, record::{Direction, Recorder}, scrollback::{self, Scrollback}, settings::SerialSettings, stats::SessionStats, transport::Target, trigger::{self, Action, Triggers}, txlog::{self, TxLog}, wire::WireLog};
+
+/// Prefix key for local terminal commands, Ctrl-A like minicom and screen.
+pub const COMMAND_PREFIX: u8 = 0x01;
+
+/// Key that leaves the terminal, Ctrl-] like telnet, so Ctrl-C reaches the target.
+pub const EXIT_KEY: u8 = 0x1d;
+
+/// How often the input loop looks up from the keyboard, e.g. to notice the port is gone.
+pub(crate) const INPUT_POLL: Duration = Duration::from_millis(100);
+/// Keys taken in one batch at most.
+const MAX_KEYS: usize = 4096;
+/// How long the reader thread waits on a silent port before looking up, e.g. to notice a quit.
+/// Data is read as soon as it arrives either way; waking every millisecond only burns CPU.
+pub const READER_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Bounds of the read size [`read_size_for`] picks.
+pub const READ_SIZE_MIN: usize = 256;
+pub const READ_SIZE_MAX: usize = 64 << 10;
+/// How many reads' worth the reader takes in before showing any of it: as long as reads come
+/// back full, there is more waiting in the OS buffer, and overrunning it loses data.
+pub const READ_BATCH: usize = 4;
+/// With `--high-throughput`, reads are this much larger and batches this many reads long.
+pub const HIGH_THROUGHPUT_SCALE: usize = 4;
+pub const HIGH_THROUGHPUT_BATCH: usize = 16;
+
+/// Bytes read from a port at `baud` at a time: about 20 ms of it, ten bits a byte, as a power
+/// of two, e.g. 256 at 115200 and 8K at 3 Mbaud.
+pub fn read_size_for(baud: u32) -> usize {
+    (baud as usize / 500).next_power_of_two().clamp(READ_SIZE_MIN, READ_SIZE_MAX)
+}
+
+/// Knobs of the interactive terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalOptions {
+    pub break_duration: Duration,
+    /// Only watch: keystrokes other than the exit key and Ctrl-C are dropped and nothing is written.
+    pub read_only: bool,
+    /// Pace what is sent to this many bytes per second.
+    pub limit: Option<u64>,
+    /// Start in the hex dump view.
+    pub hex: bool,
+    /// Typed byte that quits instead of being sent.
+    pub exit_key: u8,
+    /// Typed byte that opens the local command line at the start of a line, see [`prompt`](crate::prompt).
+    pub prompt_key: Option<u8>,
+    /// Reopen the port in place after a disconnect, checking once a second this many times; 0 leaves
+    /// it to `run()`.
+    pub reconnect: u32,
+    /// What arrows, function keys and the like send.
+    pub keys: KeyEncoding,
+    /// Ask the terminal to mark pastes, see [`BracketedPaste`].
+    pub bracketed_paste: bool,
+    /// Waits between pasted characters and after each pasted line.
+    pub paste_char_delay: Duration,
+    pub paste_line_delay: Duration,
+    /// Show what is typed, for targets that don't echo it.
+    pub echo: bool,
+    /// What the Enter key sends, and what piped input's line endings become.
+    pub newline: Newline,
+    /// With piped stdin, end the session this long after it started.
+    pub exit_after: Option<Duration>,
+    /// With piped stdin, end the session once all of it is sent rather than showing the output
+    /// until `exit_after` or an `exit` trigger.
+    pub exit_on_eof: bool,
+    /// Pass every escape sequence the target sends through, not only the harmless ones.
+    pub raw_output: bool,
+    /// Start with control bytes written out, see [`ansi::show_control`].
+    pub show_control: bool,
+    /// Warn once nothing has been received for this long, and do `on_idle`.
+    pub idle_timeout: Option<Duration>,
+    pub on_idle: IdleAction,
+    /// `--read-buffer`: bytes read from the port at a time; by default scaled from the baud rate.
+    pub read_size: Option<usize>,
+    /// `--high-throughput`: larger reads, more of them shown at once.
+    pub high_throughput: bool,
+    /// What the received stream goes through before it is shown, and before it is logged.
+    pub display_filters: Vec<FilterKind>,
+    pub log_filters: Vec<FilterKind>,
+    /// `--line-idle`: how long the target pauses before a partial line is shown, see [`LINE_IDLE`].
+    pub line_idle: Duration,
+}
+
+impl TerminalOptions {
+    /// Bytes read from a port at `baud` at a time, see [`read_size_for`].
+    pub fn read_size(&self, baud: u32) -> usize {
+        let scale = if self.high_throughput { HIGH_THROUGHPUT_SCALE } else { 1 };
+        self.read_size.unwrap_or_else(|| read_size_for(baud) * scale).max(1)
+    }
+
+    /// Bytes taken in at most before what was received is shown, see [`READ_BATCH`].
+    pub fn batch_size(&self, baud: u32) -> usize {
+        self.read_size(baud) * if self.high_throughput { HIGH_THROUGHPUT_BATCH } else { READ_BATCH }
+    }
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        Self { break_duration: Duration::from_millis(250), read_only: false, limit: None, hex: false, exit_key: EXIT_KEY, prompt_key: None, reconnect: 0, keys: KeyEncoding::Xterm, bracketed_paste: true,
+               paste_char_delay: Duration::from_millis(1), paste_line_delay: Duration::from_millis(10),
+               echo: false, newline: Newline::Cr, exit_after: None, exit_on_eof: false, raw_output: false, show_control: false, idle_timeout: None, on_idle: IdleAction::Warn,
+               read_size: None, high_throughput: false, display_filters: Vec::new(), log_filters: Vec::new(), line_idle: LINE_IDLE }
+    }
+}
+
+/// Line ending sent for a typed Enter; pasted line endings go out as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Newline {
+    /// What the terminal sends, what most consoles expect.
+    #[default]
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl Newline {
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            Newline::Cr => b"\r",
+            Newline::Lf => b"\n",
+            Newline::CrLf => b"\r\n",
+        }
+    }
+
+    /// `data` with its `\r`, `\n` and `\r\n` line endings made this one. `after_cr` carries a
+    /// `\r` ending one piece over to the next, so a `\r\n` split between them stays one ending.
+    pub fn normalize(self, data: &[u8], after_cr: &mut bool) -> Vec<u8> {
+        let mut normalized = Vec::with_capacity(data.len());
+        for &b in data {
+            match b {
+                b'\n' if *after_cr => {}
+                b'\r' | b'\n' => normalized.extend_from_slice(self.bytes()),
+                b => normalized.push(b),
+            }
+            *after_cr = b == b'\r';
+        }
+        normalized
+    }
+}
+
+impl FromStr for Newline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "cr" => Ok(Newline::Cr),
+            "lf" => Ok(Newline::Lf),
+            "crlf" => Ok(Newline::CrLf),
+            _ => Err("expected cr, lf or crlf".to_string()),
+        }
+    }
+}
+
+/// How long the target has to pause before a partial line is shown anyway, e.g. a prompt;
+/// the default of `--line-idle`.
+pub const LINE_IDLE: Duration = Duration::from_millis(50);
+
+/// A held-back partial line longer than this is shown without waiting for its end.
+pub const MAX_LINE: usize = 4096;
+
+/// Cuts the received stream into complete lines so they can be matched as a whole. A partial
+/// line waits until it completes or the target pauses for the idle time; either way each byte
+/// comes out once.
+#[derive(Debug, Clone)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+    /// When the last bytes of the partial line came, taken once per push rather than per byte.
+    last: Option<Instant>,
+    idle: Duration,
+    /// Whether part of the pending line already came out, see [`LineBuffer::continues`].
+    continued: bool,
+}
+
+impl LineBuffer {
+    pub fn new(idle: Duration) -> Self {
+        Self { pending: Vec::new(), last: None, idle, continued: false }
+    }
+
+    pub fn set_idle(&mut self, idle: Duration) {
+        self.idle = idle;
+    }
+
+    /// Complete lines, each with its `\n`, now that `data` arrived at `now`.
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let room = (MAX_LINE - self.pending.len()).min(rest.len());
+            let end = rest[..room].iter().position(|&b| b == b'\n').map_or(room, |i| i + 1);
+            self.pending.extend_from_slice(&rest[..end]);
+            rest = &rest[end..];
+            if self.pending.ends_with(b"\n") || self.pending.len() >= MAX_LINE {
+                lines.push(mem::take(&mut self.pending));
+            }
+        }
+        if !lines.is_empty() { self.continued = false; }
+        if self.pending.is_empty() {
+            self.last = None;
+        } else if !data.is_empty() {
+            self.last = Some(now);
+        }
+        lines
+    }
+
+    /// Whether what comes out next, the first line of the next push or the next partial line,
+    /// goes on with a partial line that came out when the target paused rather than starting
+    /// one. True after [`LineBuffer::flush_idle`] gave one, until its line completes.
+    pub fn continues(&self) -> bool {
+        self.continued
+    }
+
+    /// The partial line, if the target paused for at least the idle time by `now`.
+    pub fn flush_idle(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) >= self.idle => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// The partial line, right away, e.g. once the port is gone.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        self.last = None;
+        if self.pending.is_empty() { return None; }
+        self.continued = true;
+        Some(mem::take(&mut self.pending))
+    }
+}
+
+/// Bytes per hex dump row.
+pub const HEX_ROW: usize = 16;
+
+/// Cuts the received stream into hex dump rows, holding a partial row back like [`LineBuffer`].
+#[derive(Debug, Clone)]
+pub struct HexDump {
+    row: Vec<u8>,
+    offset: u64,
+    since: Option<Instant>,
+    idle: Duration,
+}
+
+impl HexDump {
+    pub fn new(idle: Duration) -> Self {
+        Self { row: Vec::with_capacity(HEX_ROW), offset: 0, since: None, idle }
+    }
+
+    pub fn set_idle(&mut self, idle: Duration) {
+        self.idle = idle;
+    }
+
+    /// Complete rows, formatted, now that `data` arrived at `now`.
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Vec<String> {
+        let mut rows = Vec::new();
+        for &b in data {
+            if self.row.is_empty() { self.since = Some(now); }
+            self.row.push(b);
+            if self.row.len() == HEX_ROW { rows.push(self.take_row()); }
+        }
+        rows
+    }
+
+    /// The partial row, if it has waited at least the idle time by `now`.
+    pub fn flush_idle(&mut self, now: Instant) -> Option<String> {
+        match self.since {
+            Some(since) if now.duration_since(since) >= self.idle => Some(self.take_row()),
+            _ => None,
+        }
+    }
+
+    /// The partial row, right away.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.row.is_empty() { None } else { Some(self.take_row()) }
+    }
+
+    fn take_row(&mut self) -> String {
+        let hex: Vec<String> = self.row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = self.row.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }).collect();
+        let row = format!("{:08x}  {:<width$}  |{}|\n", self.offset, hex.join(" "), ascii, width = HEX_ROW * 3 - 1);
+        self.offset += self.row.len() as u64;
+        self.row.clear();
+        self.since = None;
+        row
+    }
+}
+
+/// `data` as hex dump rows, offsets counting from 0.
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut dump = HexDump::new(Duration::ZERO);
+    let mut rows = dump.push(data, Instant::now());
+    rows.extend(dump.flush());
+    rows.concat()
+}
+
+/// How received bytes are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Text,
+    Hex,
+}
+
+/// Asked of the rendering side by the input loop, so it happens between two pieces of output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayRequest {
+    ToggleView,
+    /// Switch [`TerminalOptions::show_control`].
+    ToggleControl,
+    /// Clear the local screen; nothing goes to the target.
+    Clear,
+    /// Show typed bytes locally, see [`TerminalOptions::echo`].
+    Echo(Vec<u8>),
+}
+
+/// Renders the target's output: as it comes, line by line when lines are highlighted or
+/// checked against `--on` rules, or as a hex dump. Used by the terminal and by replays alike.
+pub struct Display {
+    out: Output,
+    highlighter: Highlighter,
+    triggers: Triggers,
+    lines: LineBuffer,
+    view: View,
+    hex: HexDump,
+    /// A character cut off at the end of the last read.
+    partial: Vec<u8>,
+    /// `None` with `--raw-output`.
+    sanitizer: Option<Sanitizer>,
+    show_control: bool,
+    hidden: bool,
+    /// `--display-filter`, ahead of everything else.
+    filters: FilterChain,
+}
+
+impl Display {
+    pub fn new(out: Output, highlighter: Highlighter, triggers: Triggers) -> Self {
+        // highlighting is coloring, so it follows --color and NO_COLOR
+        let highlighter = if out.target_color() { highlighter } else { Highlighter::default() };
+        let sanitizer = Some(Sanitizer::for_terminal(out.escapes()));
+        Self { out, highlighter, triggers, lines: LineBuffer::new(LINE_IDLE), view: View::Text, hex: HexDump::new(LINE_IDLE),
+               partial: Vec::new(), sanitizer, show_control: false, hidden: false, filters: FilterChain::default() }
+    }
+
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+    }
+
+    /// How long the target has to pause before a partial line or row is shown, see [`LINE_IDLE`].
+    pub fn set_line_idle(&mut self, idle: Duration) {
+        self.lines.set_idle(idle);
+        self.hex.set_idle(idle);
+    }
+
+    /// Whether escape sequences other than colors and cursor moves reach the terminal as they
+    /// are. Not one does on a console that can't show them, see [`Output::escapes`].
+    pub fn set_raw_output(&mut self, raw: bool) {
+        self.sanitizer = if raw && self.out.escapes() { None } else { Some(Sanitizer::for_terminal(self.out.escapes())) };
+    }
+
+    /// Whether control bytes are written out instead of sanitized or passed through; takes the
+    /// place of `--raw-output` while on.
+    pub fn set_show_control(&mut self, show: bool) {
+        self.flush();
+        // a sequence held back while off comes out before switching
+        if let Some(rest) = self.sanitizer.as_mut().map(Sanitizer::finish) { self.print(&rest); }
+        self.show_control = show;
+    }
+
+    /// Whether nothing is printed at all, e.g. for one board among several being pushed to.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    pub fn set_view(&mut self, view: View) {
+        self.flush();
+        self.view = view;
+    }
+
+    pub fn handle(&mut self, request: DisplayRequest) {
+        match request {
+            DisplayRequest::ToggleView => {
+                self.set_view(if self.view == View::Text { View::Hex } else { View::Text });
+                self.out.status(if self.view == View::Hex { "— hex view —" } else { "— text view —" });
+            }
+            DisplayRequest::ToggleControl => {
+                self.set_show_control(!self.show_control);
+                self.out.status(if self.show_control { "— control bytes shown —" } else { "— control bytes hidden —" });
+            }
+            DisplayRequest::Clear => {
+                // a held-back partial line belongs to the old screen
+                self.flush();
+                if self.out.is_terminal() { let _ = execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0)); }
+                self.out.status("— screen cleared —");
+            }
+            DisplayRequest::Echo(typed) => {
+                self.flush();
+                self.print(&String::from_utf8_lossy(&typed));
+                let _ = stdout().flush();
+            }
+        }
+    }
+
+    /// Shows `data`; call it with nothing now and then so a held-back prompt still appears.
+    pub fn show(&mut self, data: &[u8]) {
+        if self.filters.is_empty() { return self.show_filtered(data); }
+        let filtered = self.filters.apply(data);
+        self.show_filtered(&filtered);
+    }
+
+    fn show_filtered(&mut self, data: &[u8]) {
+        let now = Instant::now();
+        if self.view == View::Hex {
+            let rows = self.hex.push(data, now);
+            rows.iter().chain(self.hex.flush_idle(now).iter()).for_each(|row| self.print(row));
+        } else if self.highlighter.is_empty() && self.triggers.is_empty() {
+            self.partial.extend_from_slice(data);
+            let mut partial = mem::take(&mut self.partial);
+            let complete = utf8_complete(&partial);
+            let text = self.render(&partial[..complete], self.out.target_color());
+            self.print(&text);
+            partial.drain(..complete);
+            self.partial = partial;
+        } else {
+            let lines = self.lines.push(data, now);
+            for line in lines.into_iter().chain(self.lines.flush_idle(now)) { self.show_line(&line); }
+        }
+        // into a pipe or file, whole lines are enough
+        if self.out.is_terminal() { let _ = stdout().flush(); }
+    }
+
+    /// Shows whatever partial line or row is still held back.
+    pub fn finish(&mut self) {
+        let held = self.filters.drain();
+        if !held.is_empty() { self.show_filtered(&held); }
+        self.flush();
+        if let Some(rest) = self.sanitizer.as_mut().map(Sanitizer::finish) { self.print(&rest); }
+        let _ = stdout().flush();
+    }
+
+    fn flush(&mut self) {
+        if !self.partial.is_empty() {
+            let partial = mem::take(&mut self.partial);
+            let text = self.render(&partial, self.out.target_color());
+            self.print(&text);
+        }
+        if let Some(rest) = self.lines.flush() { self.show_line(&rest); }
+        if let Some(row) = self.hex.flush() { self.print(&row); }
+    }
+
+    fn print(&self, text: &str) {
+        if self.hidden { return; }
+        print_rx(text, self.out.is_terminal());
+    }
+
+    /// Received bytes as text for the terminal: sanitized, as they are with `--raw-output`, or
+    /// with control bytes written out.
+    fn render(&mut self, data: &[u8], dim: bool) -> String {
+        if self.show_control { return ansi::show_control(data, dim); }
+        let text = String::from_utf8_lossy(data);
+        match self.sanitizer.as_mut() {
+            Some(sanitizer) => sanitizer.sanitize(&text),
+            None => text.into_owned(),
+        }
+    }
+
+    fn show_line(&mut self, line: &[u8]) {
+        // before highlighting, whose colors are ours; a regex could match inside the dimming's
+        let text = self.render(line, self.out.target_color() && self.highlighter.is_empty());
+        self.print(&self.highlighter.paint(&text));
+        let line = String::from_utf8_lossy(line);
+        self.triggers.matching(&line).for_each(|action| fire(action, line.trim_end(), &self.out));
+    }
+}
+
+/// How much of `data` can be decoded now: all of it unless it ends in the first bytes of a
+/// multi-byte character, which the next read may complete.
+pub fn utf8_complete(data: &[u8]) -> usize {
+    // a character is at most four bytes, so only the last three can start a cut-off one
+    (data.len().saturating_sub(3)..data.len())
+        .find(|&i| matches!(std::str::from_utf8(&data[i..]), Err(e) if e.valid_up_to() == 0 && e.error_len().is_none()))
+        .unwrap_or(data.len())
+}
+
+/// Target output: on the raw-mode terminal, which needs `\r\n` to start a new line, or with
+/// plain `\n` line endings into a pipe or file. One locked write for all of it.
+pub(crate) fn print_rx(text: &str, terminal: bool) {
+    let _ = stdout().lock().write_all(translate_rx(text, terminal).as_bytes());
+}
+
+/// `text` as [`print_rx`] writes it: each `\n` a `\r\n` on a terminal, without the `\r`s
+/// anywhere else. Borrowed when there is nothing to change.
+pub fn translate_rx(text: &str, terminal: bool) -> Cow<'_, str> {
+    let changes = if terminal { text.contains('\n') } else { text.contains('\r') };
+    if !changes { return Cow::Borrowed(text); }
+    let mut translated = String::with_capacity(text.len() + if terminal { text.len() / 16 } else { 0 });
+    for c in text.chars() {
+        match c {
+            '\n' if terminal => translated.push_str("\r\n"),
+            '\r' if !terminal => {}
+            c => translated.push(c),
+        }
+    }
+    Cow::Owned(translated)
+}
+
+/// Carries out an `--on` action for `line`. `exit` ends the process right away, since the
+/// input loop may be blocked on a keypress that never comes.
+pub(crate) fn fire(action: &Action, line: &str, out: &Output) {
+    match action {
+        Action::Bell => eprint!("\x07"),
+        Action::Exec(cmd) => match trigger::exec(cmd, line) {
+            // reap it without holding up the terminal
+            Ok(mut child) => { thread::spawn(move || child.wait()); }
+            Err(e) => out.warn(format!("Could not run {:?}: {}", cmd, e)),
+        },
+        Action::Exit(code) => {
+            let color = if *code == 0 { Color::Green } else { Color::Red };
+            exit_session(out, format!("Matched {}", out.paint(format!("{:?}", line), color)), *code)
+        }
+    }
+}
+
+/// Carries out `--on-idle` once nothing was received for `quiet`, after warning about it.
+fn fire_idle(action: &IdleAction, quiet: Duration, out: &Output) {
+    let message = format!("{} nothing received for {}", UtcTime(SystemTime::now()), format_duration(quiet));
+    out.warn(&message);
+    match action {
+        IdleAction::Warn => {}
+        IdleAction::Bell => eprint!("\x07"),
+        IdleAction::Exec(cmd) => match trigger::exec(cmd, &message) {
+            Ok(mut child) => { thread::spawn(move || child.wait()); }
+            Err(e) => out.warn(format!("Could not run {:?}: {}", cmd, e)),
+        },
+        IdleAction::Exit(code) => exit_session(out, format!("Idle for {}", format_duration(quiet)), *code),
+    }
+}
+
+/// Exit code once `--max-session-time` runs out, as timeout(1) has it.
+pub const SESSION_TIMEOUT_CODE: i32 = 124;
+
+/// Ends the process `max` from now, whatever it is doing by then, e.g. a CI run still
+/// waiting for a marker that never came.
+pub fn limit_session(max: Duration, out: Output) {
+    thread::spawn(move || {
+        thread::sleep(max);
+        exit_session(&out, format!("Still running after {}", format_duration(max)), SESSION_TIMEOUT_CODE);
+    });
+}
+
+/// Ends the process from the reader thread or the session limit's, leaving the terminal as it
+/// was found.
+fn exit_session(out: &Output, why: String, code: i32) -> ! {
+    let _ = stdout().flush();
+    RAW_MODE.restore();
+    out.set_raw(false);
+    out.blank(Verbosity::Normal);
+    if code != 0 {
+        if let Some(path) = scrollback::dump_failed() { out.status(format!("{} Scrollback saved to {}", out.icon(Icon::Save), path.display())); }
+    }
+    out.status(format!("{} {}, exiting with {}", out.icon(Icon::Bye), why, code));
+    txlog::close_now(out);
+    crate::lock::release_all();
+    process::exit(code);
+}
+
+/// Receives a copy of everything read from the target while the terminal runs.
+pub trait RxTap: Send {
+    fn rx(&mut self, data: &[u8]);
+}
+
+/// Holds the TX line in the break condition for `duration`.
+pub fn send_break(port: &SerialPort, duration: Duration) -> Result<()> {
+    // TTYPort::send_break (tcsendbreak) has an implementation-defined duration and COMPort
+    // has no equivalent, so time the break ourselves: TIOCSBRK/TIOCCBRK on unix,
+    // SetCommBreak/ClearCommBreak on windows.
+    port.set_break()?;
+    thread::sleep(duration);
+    port.clear_break()?;
+    Ok(())
+}
+
+/// Raw mode of the local terminal: entered by the first live guard, left by the last one or by
+/// [`RawMode::restore`], whichever comes first, and never twice.
+pub struct RawMode {
+    depth: AtomicUsize,
+    enter: fn() -> io::Result<()>,
+    leave: fn() -> io::Result<()>,
+}
+
+impl RawMode {
+    pub const fn new(enter: fn() -> io::Result<()>, leave: fn() -> io::Result<()>) -> Self {
+        Self { depth: AtomicUsize::new(0), enter, leave }
+    }
+
+    /// Enters raw mode unless a guard already did, for as long as the guard lives.
+    pub fn guard(&self) -> io::Result<RawModeGuard<'_>> {
+        if self.depth.fetch_add(1, Ordering::SeqCst) == 0 {
+            if let Err(e) = (self.enter)() {
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(RawModeGuard { mode: self })
+    }
+
+    /// Leaves raw mode now if it is on, e.g. before exiting or from the panic hook. Guards
+    /// still alive then leave it alone.
+    pub fn restore(&self) {
+        if self.depth.swap(0, Ordering::SeqCst) > 0 { let _ = (self.leave)(); }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.depth.load(Ordering::SeqCst) > 0
+    }
+
+    fn release(&self) {
+        if self.depth.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| depth.checked_sub(1)) == Ok(1) {
+            let _ = (self.leave)();
+        }
+    }
+}
+
+/// Keeps the terminal in raw mode while alive, see [`RawMode::guard`].
+pub struct RawModeGuard<'a> {
+    mode: &'a RawMode,
+}
+
+impl Drop for RawModeGuard<'_> {
+    fn drop(&mut self) {
+        self.mode.release();
+    }
+}
+
+/// The process' terminal.
+pub static RAW_MODE: RawMode = RawMode::new(enable_raw_mode, disable_raw_mode);
+
+/// Bracketed paste mode of the local terminal, for as long as this lives: a paste then comes
+/// as [`Input::Paste`] rather than as a burst of keys.
+pub struct BracketedPaste;
+
+impl BracketedPaste {
+    pub fn enable() -> Self {
+        let _ = execute!(stdout(), EnableBracketedPaste);
+        BracketedPaste
+    }
+}
+
+impl Drop for BracketedPaste {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), DisableBracketedPaste);
+    }
+}
+
+/// Deasserts DTR and RTS so a board wired for auto-reset isn't disturbed while being watched.
+/// Best effort: serialport asserts both while opening, and some transports can't drive them.
+pub fn release_control_lines(port: &mut SerialPort) {
+    let _ = port.write_data_terminal_ready(false);
+    let _ = port.write_request_to_send(false);
+}
+
+/// Why [`Terminal::run`] returned; a vanished port is `Err(ErrorKind::ConnectionError)` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The exit key, or Ctrl-C in a read-only terminal.
+    ExitKey,
+    /// The quit command, Ctrl-A q.
+    QuitCommand,
+    /// `exit_after` passed, with piped stdin.
+    ExitAfter,
+    /// Stdin closed: under an interactive terminal, or piped with `exit_on_eof`.
+    StdinClosed,
+    /// Told to by the flag given to [`Terminal::stop_when`].
+    Stopped,
+}
+
+/// The interactive terminal: raw mode, a reader thread rendering the target's output and an
+/// input loop sending what is typed. `SerialTool::terminal()` builds one from the tool's
+/// settings; other programs set it up themselves.
+///
+/// When stdin is not a terminal, e.g. a script piped in, there is no raw mode and no keys to
+/// interpret: its bytes are sent paced like a paste, and after its end the output is shown
+/// until `exit_after` or an `exit` trigger.
+pub struct Terminal {
+    out: Output,
+    options: TerminalOptions,
+    commands: CommandTable,
+    local_commands: LocalCommands,
+    display: Option<Display>,
+    taps: Vec<Box<dyn RxTap>>,
+    recorder: Option<Arc<Recorder>>,
+    wire: Option<Arc<WireLog>>,
+    stats: Option<Arc<SessionStats>>,
+    scrollback: Option<Arc<Scrollback>>,
+    stop: Option<Arc<AtomicBool>>,
+    control: Option<Arc<ControlSocket>>,
+    tx_log: Option<Arc<TxLog>>,
+    input: Option<Box<dyn Read + Send>>,
+}
+
+impl Terminal {
+    pub fn new(out: Output) -> Self {
+        Self { out, options: TerminalOptions::default(), commands: CommandTable::default(), local_commands: LocalCommands::default(), display: None,
+               taps: Vec::new(), recorder: None, wire: None, stats: None, scrollback: None, stop: None, control: None,
+               tx_log: None, input: None }
+    }
+
+    pub fn options(mut self, options: TerminalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn exit_key(mut self, key: u8) -> Self {
+        self.options.exit_key = key;
+        self
+    }
+
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.options.echo = echo;
+        self
+    }
+
+    pub fn newline(mut self, newline: Newline) -> Self {
+        self.options.newline = newline;
+        self
+    }
+
+    pub fn commands(mut self, commands: CommandTable) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// What the local command line runs.
+    pub fn local_commands(mut self, commands: LocalCommands) -> Self {
+        self.local_commands = commands;
+        self
+    }
+
+    /// Renders the target's output; a plain one following `options.hex` otherwise.
+    pub fn display(mut self, display: Display) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Gets a copy of everything received, in the order added.
+    pub fn tap(mut self, tap: Box<dyn RxTap>) -> Self {
+        self.taps.push(tap);
+        self
+    }
+
+    /// Records both directions.
+    pub fn recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.taps.push(Box::new(recorder.clone()));
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Shows both directions as timed hex rows in place of the output, see [`wire`](crate::wire).
+    pub fn wire(mut self, wire: Arc<WireLog>) -> Self {
+        self.taps.push(Box::new(wire.clone()));
+        self.wire = Some(wire);
+        self
+    }
+
+    /// Counts both directions and reconnects.
+    pub fn stats(mut self, stats: Arc<SessionStats>) -> Self {
+        self.taps.push(Box::new(stats.clone()));
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Keeps what is received for the `scrollback` command.
+    pub fn scrollback(mut self, scrollback: Arc<Scrollback>) -> Self {
+        self.taps.push(Box::new(scrollback.clone()));
+        self.scrollback = Some(scrollback);
+        self
+    }
+
+    /// Mirrors what is sent on a port reopened after a disconnect too, see [`txlog`].
+    pub fn tx_log(mut self, log: Arc<TxLog>) -> Self {
+        self.tx_log = Some(log);
+        self
+    }
+
+    /// Ends the session with [`ExitReason::Stopped`] once `stop` is set, e.g. by `--watch`.
+    pub fn stop_when(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Answers the requests of `--control` clients between keys, see [`control`](crate::control).
+    pub fn control(mut self, control: Arc<ControlSocket>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Sends what `input` reads as if it were piped into stdin, e.g. a test's script.
+    pub fn input(mut self, input: Box<dyn Read + Send>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Runs until the user quits or, unless it reconnects in place, the port goes away. With
+    /// `options.reconnect`, a port that went away is asked for again through `connection`.
+    pub fn run(self, connection: &mut impl SerialConnection) -> Result<ExitReason> {
+        let out = self.out.clone();
+        let result = self.session(connection);
+        out.set_raw(false);
+        result
+    }
+
+    fn session(self, connection: &mut impl SerialConnection) -> Result<ExitReason> {
+        let Terminal { out, options, commands, mut local_commands, display, mut taps, recorder, wire, stats, scrollback, stop, control, tx_log, input } = self;
+        let reader_out = out.clone();
+        let mut display = display.unwrap_or_else(|| {
+            let mut display = Display::new(out.clone(), Highlighter::default(), Triggers::default());
+            display.set_view(if options.hex { View::Hex } else { View::Text });
+            display
+        });
+        // the received bytes are among the wire's rows
+        if wire.is_some() { display.set_hidden(true); }
+        let (requests, display_requests) = mpsc::channel();
+        let reconnect = options.reconnect > 0;
+        // `log start` writes here, the --log file being another tap
+        let local_log = Arc::new(LogSwitch::new(&out));
+        taps.push(Box::new(local_log.clone()));
+        // armed by `pull`, the reader catches the next dump instead of showing it
+        let local_pull = Arc::new(PullSwitch::default());
+        let reader_pull = local_pull.clone();
+        let console = Arc::new(Mutex::new(Console::default()));
+        let reader_console = console.clone();
+
+        let mut serial_port = reader_half(connection.port())?;
+        let name = connection.port().name().unwrap_or_else(|| "the port".to_string());
+        // the reading half of a port the input loop reopened, for the reader thread to pick up
+        let (reader_ports, reopened) = mpsc::channel();
+        let mut reconnector = Reconnector { attempts: options.reconnect, tried: 0, next: Instant::now(), read_only: options.read_only, tx_log,
+                                            stats: stats.clone(), readers: reader_ports, name: name.clone() };
+
+        let interactive = input.is_none() && io::stdin().is_terminal();
+        let exit_key = key_name(options.exit_key);
+        if interactive && options.exit_after.is_some() {
+            out.warn("--exit-after only ends a session with piped stdin, not one typed into");
+        }
+        if !interactive {
+            out.status(if options.read_only { "Read-only, piped input is not sent" } else { "Sending piped input line by line" });
+        } else if options.read_only {
+            out.status(format!("Read-only, nothing typed is sent; {} or Ctrl-C quits", exit_key));
+        } else {
+            out.status(format!("{} quits, {} h for help", exit_key, key_name(commands.prefix())));
+        }
+        let _raw = if interactive { Some(RAW_MODE.guard()?) } else { None };
+        out.set_raw(interactive);
+        let bracketed = interactive && options.bracketed_paste && out.is_terminal();
+        let _bracketed = bracketed.then(BracketedPaste::enable);
+        // 0: ok, no error; 1: connect error; 2: quit; 3: reconnecting in place
+        let has_error = Arc::new(AtomicU8::new(0));
+        let has_error_clone = has_error.clone();
+
+        let reader_options = options.clone();
+        let baud = connection.port().baud_rate().unwrap_or(SERIAL_BAUD);
+        let (read_size, batch_size) = (options.read_size(baud), options.batch_size(baud));
+        let reader = thread::spawn(move || {
+            let mut serial_buf = vec![0; batch_size];
+            let mut watchdog = reader_options.idle_timeout.map(|timeout| Watchdog::new(timeout, Instant::now()));
+            while is_ok(&has_error_clone) {
+                display_requests.try_iter().for_each(|request| display.handle(request));
+                match serial_port.read_serial_drain(&mut serial_buf, read_size) {
+                    Ok(t) => {
+                        if t > 0 {
+                            taps.iter_mut().for_each(|tap| tap.rx(&serial_buf[..t]));
+                            if let Some(silence) = watchdog.as_mut().and_then(|watchdog| watchdog.rx(Instant::now())) {
+                                display.finish();
+                                reader_out.status(format!("— output again after {} —", format_duration(silence)));
+                            }
+                        }
+                        match reader_pull.scan(&serial_buf[..t]) {
+                            Some((shown, Some(rest))) => {
+                                reader_console.lock().unwrap().show(&mut display, &shown);
+                                display.finish();
+                                let after = reader_pull.catch(&mut serial_port, &rest, &reader_out);
+                                reader_console.lock().unwrap().show(&mut display, &after);
+                                if let Some(watchdog) = watchdog.as_mut() { watchdog.restart(Instant::now()); }
+                            }
+                            Some((shown, None)) => reader_console.lock().unwrap().show(&mut display, &shown),
+                            None => reader_console.lock().unwrap().show(&mut display, &serial_buf[..t]),
+                        }
+                        if let Some(quiet) = watchdog.as_mut().and_then(|watchdog| watchdog.check(Instant::now())) {
+                            display.finish();
+                            fire_idle(&reader_options.on_idle, quiet, &reader_out);
+                        }
+                    }
+                    Err(ErrorKind::ConnectionError) if reconnect => {
+                        display.finish();
+                        has_error_clone.store(RECONNECTING, Ordering::Relaxed);
+                        reader_out.blank(Verbosity::Quiet);
+                        reader_out.warn(format!("{} gone, reconnecting…", name));
+                        match wait_reopened(&reopened, &has_error_clone) {
+                            Some(reader) => {
+                                serial_port = reader;
+                                // the time it was gone is no silence of the target's
+                                if let Some(watchdog) = watchdog.as_mut() { watchdog.restart(Instant::now()); }
+                            }
+                            None => break,
+                        }
+                    }
+                    Err(e) => {
+                        reader_out.error(format!("\nread_serial error {:?}", e));
+                        has_error_clone.store(1, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+        });
+        let _reader = ReaderGuard { state: has_error.clone(), handle: Some(reader) };
+
+        let stopped = || stop.as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed));
+        let mut limiter = options.limit.map(RateLimiter::new);
+        let new_paste = || Paste::new(options.paste_char_delay, options.paste_line_delay);
+
+        if !interactive {
+            let chunks = read_input(input.unwrap_or_else(|| Box::new(io::stdin())));
+            let deadline = options.exit_after.map(|after| Instant::now() + after);
+            let mut piped = new_paste();
+            let mut after_cr = false;
+            let mut byte = Vec::with_capacity(1);
+            while matches!(has_error.load(Ordering::Relaxed), 0 | RECONNECTING) {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) { return Ok(ExitReason::ExitAfter); }
+                if stopped() { return Ok(ExitReason::Stopped); }
+                reconnector.poll(connection, &has_error, &out);
+                let port = connection.port();
+                if has_error.load(Ordering::Relaxed) != RECONNECTING {
+                    let mut ctx = Context { port, out: &out, stats: stats.as_deref(), log: &local_log, pull: Some(&local_pull), scrollback: scrollback.as_deref(), read_only: options.read_only,
+                                            limiter: limiter.as_mut(), recorder: recorder.as_deref(), wire: wire.as_deref(), quit: false, said: Vec::new() };
+                    if serve_control(control.as_deref(), &mut local_commands, &mut ctx) { return Ok(ExitReason::QuitCommand); }
+                }
+                let wait = if piped.is_empty() { Duration::from_millis(100) } else { Duration::ZERO };
+                match chunks.recv_timeout(wait) {
+                    Ok(data) if !options.read_only => piped.push(&options.newline.normalize(&data, &mut after_cr)),
+                    Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                    // stdin ended and all of it is sent: done, or only the output is left to show
+                    Err(RecvTimeoutError::Disconnected) if piped.is_empty() => {
+                        if options.exit_on_eof { return Ok(ExitReason::StdinClosed); }
+                        thread::sleep(wait);
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {}
+                }
+                // what is left waits for the port to come back
+                if has_error.load(Ordering::Relaxed) == RECONNECTING { continue; }
+                if let Some((b, delay)) = piped.next_byte() {
+                    byte.push(b);
+                    let sent = send(port, &mut byte, limiter.as_mut(), recorder.as_deref(), wire.as_deref(), stats.as_deref());
+                    unless_gone(sent, &mut byte, reconnect, &out)?;
+                    thread::sleep(delay);
+                }
+            }
+            return if has_error.load(Ordering::Relaxed) == 1 { Err(ErrorKind::ConnectionError) } else { Ok(ExitReason::ExitKey) };
+        }
+
+        let mut send_buf = Vec::with_capacity(256);
+        let mut echo_buf = Vec::new();
+        let mut decoder = commands.decoder();
+        let mut paste: Option<Paste> = None;
+        let mut editor: Option<LineEditor> = None;
+        // where `prompt_key` opens the local command line: nothing typed yet, or after Enter
+        let mut at_line_start = true;
+        let mut reason = ExitReason::ExitKey;
+        let encode = |key: &KeyEvent| if key.code == KeyCode::Enter {
+            Some(options.newline.bytes().to_vec())
+        } else {
+            keys::encode(key, options.keys)
+        };
+
+        while matches!(has_error.load(Ordering::Relaxed), 0 | RECONNECTING) {
+            // with a timeout, so a reader thread that gave up ends the session without a key press
+            let (pressed, pasted) = match read_keys(INPUT_POLL) {
+                Ok(inputs) => split_pasted(inputs),
+                // nothing more will be typed
+                Err(e) if console_closed(&e) => return Ok(ExitReason::StdinClosed),
+                Err(e) => return Err(ErrorKind::IoError(e)),
+            };
+            // without markers from the terminal, a burst of keys is taken for a paste
+            let burst = !bracketed && pressed.len() >= PASTE_THRESHOLD;
+            reconnector.poll(connection, &has_error, &out);
+            let port = connection.port();
+            if stopped() {
+                reason = ExitReason::Stopped;
+                break;
+            }
+            if has_error.load(Ordering::Relaxed) != RECONNECTING {
+                let mut ctx = Context { port, out: &out, stats: stats.as_deref(), log: &local_log, pull: Some(&local_pull), scrollback: scrollback.as_deref(), read_only: options.read_only,
+                                        limiter: limiter.as_mut(), recorder: recorder.as_deref(), wire: wire.as_deref(), quit: false, said: Vec::new() };
+                if serve_control(control.as_deref(), &mut local_commands, &mut ctx) {
+                    reason = ExitReason::QuitCommand;
+                    has_error.store(2, Ordering::Relaxed);
+                    break;
+                }
+            }
+            if pressed.is_empty() && pasted.is_empty() && paste.is_none() { continue; }
+            if has_error.load(Ordering::Relaxed) == RECONNECTING {
+                if pressed.iter().any(|key| keys::key_byte(key) == Some(options.exit_key)) { has_error.store(2, Ordering::Relaxed); }
+                else if !pressed.is_empty() || !pasted.is_empty() { out.warn("not sent, reconnecting"); }
+                continue;
+            }
+
+            if let Some(pending) = paste.as_mut() {
+                if pressed.iter().any(|key| key.code == KeyCode::Esc) {
+                    let (sent, total) = (pending.sent(), pending.total());
+                    out.warn(format!("paste stopped: {}/{} lines, {} of {} sent", sent.lines, total.lines,
+                                     format_bytes(sent.bytes as u64), format_bytes(total.bytes as u64)));
+                    paste = None;
+                } else {
+                    // the rest of a paste too long for one batch, or typed ahead of it
+                    pressed.iter().filter_map(encode).for_each(|bytes| pending.push(&bytes));
+                }
+            } else {
+                for key in &pressed {
+                    if let Some(line) = editor.as_mut() {
+                        let byte = keys::key_byte(key);
+                        if byte == Some(options.exit_key) {
+                            close_prompt(&console, &out);
+                            editor = None;
+                            reason = ExitReason::ExitKey;
+                            has_error.store(2, Ordering::Relaxed);
+                            break;
+                        }
+                        // typed twice, the key itself goes out
+                        let literal = line.is_empty() && byte.is_some() && byte == options.prompt_key;
+                        let edit = if literal { Edit::Cancel } else { line.feed(key) };
+                        if edit == Edit::Editing {
+                            draw_prompt(line.line(), &out);
+                            continue;
+                        }
+                        editor = None;
+                        close_prompt(&console, &out);
+                        if literal && !options.read_only {
+                            send_buf.extend(options.prompt_key);
+                            at_line_start = false;
+                        }
+                        if let Edit::Submit(line) = edit {
+                            let sent = send(port, &mut send_buf, limiter.as_mut(), recorder.as_deref(), wire.as_deref(), stats.as_deref());
+                            unless_gone(sent, &mut send_buf, reconnect, &out)?;
+                            let mut ctx = Context { port, out: &out, stats: stats.as_deref(), log: &local_log, pull: Some(&local_pull), scrollback: scrollback.as_deref(), read_only: options.read_only,
+                                                    limiter: limiter.as_mut(), recorder: recorder.as_deref(), wire: wire.as_deref(), quit: false, said: Vec::new() };
+                            if let Err(e) = local_commands.run(&line, &mut ctx) { out.warn(e); }
+                            if ctx.quitting() {
+                                reason = ExitReason::QuitCommand;
+                                has_error.store(2, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    let bytes = match encode(key) {
+                        Some(bytes) => bytes,
+                        None => continue,
+                    };
+                    // an Alt chord is never a local binding
+                    let byte = keys::key_byte(key).filter(|_| !key.modifiers.contains(KeyModifiers::ALT));
+                    match byte.map(|byte| decoder.feed(byte)) {
+                        Some(Some(Chord::Forward(c))) => {
+                            // Ctrl-C goes to the target like any other key, unless there is nothing to send it to
+                            if c == options.exit_key || (options.read_only && c == 0x03) {
+                                reason = ExitReason::ExitKey;
+                                has_error.store(2, Ordering::Relaxed);
+                            } else if Some(c) == options.prompt_key && at_line_start && !burst {
+                                open_prompt(&console, &out);
+                                editor = Some(LineEditor::default());
+                                continue;
+                            } else if !options.read_only {
+                                send_buf.extend_from_slice(&bytes);
+                                if options.echo { echo_buf.extend_from_slice(if c == b'\r' { b"\n" } else { &bytes }); }
+                            }
+                            at_line_start = matches!(c, b'\r' | b'\n');
+                        }
+                        Some(Some(Chord::Command(key))) => {
+                            // keep typed input and local actions in order
+                            let sent = send(port, &mut send_buf, limiter.as_mut(), recorder.as_deref(), wire.as_deref(), stats.as_deref());
+                            unless_gone(sent, &mut send_buf, reconnect, &out)?;
+
+                            match commands.lookup(key) {
+                                Some(Command::SendBreak) if options.read_only => out.warn("read-only, break not sent"),
+                                Some(Command::SendBreak) => {
+                                    send_break(port, options.break_duration)?;
+                                    out.status("— break sent —");
+                                }
+                                Some(Command::ToggleHex) => { let _ = requests.send(DisplayRequest::ToggleView); }
+                                Some(Command::ToggleControl) => { let _ = requests.send(DisplayRequest::ToggleControl); }
+                                // the reader thread clears between two pieces of output, never mid-line
+                                Some(Command::ClearScreen) => { let _ = requests.send(DisplayRequest::Clear); }
+                                Some(Command::Prompt) => {
+                                    open_prompt(&console, &out);
+                                    editor = Some(LineEditor::default());
+                                }
+                                Some(Command::Quit) => {
+                                    reason = ExitReason::QuitCommand;
+                                    has_error.store(2, Ordering::Relaxed);
+                                }
+                                Some(Command::Local(name)) => {
+                                    let mut ctx = Context { port, out: &out, stats: stats.as_deref(), log: &local_log, pull: Some(&local_pull), scrollback: scrollback.as_deref(), read_only: options.read_only,
+                                                            limiter: limiter.as_mut(), recorder: recorder.as_deref(), wire: wire.as_deref(), quit: false, said: Vec::new() };
+                                    if let Err(e) = local_commands.run(name, &mut ctx) { out.warn(e); }
+                                    if ctx.quitting() {
+                                        reason = ExitReason::QuitCommand;
+                                        has_error.store(2, Ordering::Relaxed);
+                                    }
+                                }
+                                // only the monitor of two ports has another to switch to
+                                Some(Command::Help) | Some(Command::SwitchPort) | None => out.line(Verbosity::Quiet, commands.help()),
+                            }
+                        }
+                        // the prefix, waiting for its command
+                        Some(None) => {}
+                        // an arrow or function key after the prefix isn't a command either
+                        None if decoder.cancel() => out.line(Verbosity::Quiet, commands.help()),
+                        None if !options.read_only => {
+                            at_line_start = false;
+                            send_buf.extend_from_slice(&bytes);
+                            // a cursor move would only garble the local echo
+                            if options.echo && matches!(key.code, KeyCode::Char(_)) { echo_buf.extend_from_slice(&bytes); }
+                        }
+                        None => {}
+                    }
+                }
+                if !echo_buf.is_empty() { let _ = requests.send(DisplayRequest::Echo(mem::take(&mut echo_buf))); }
+
+                if burst && !send_buf.is_empty() {
+                    paste.get_or_insert_with(new_paste).push(&send_buf);
+                    send_buf.clear();
+                }
+            }
+            // keys that came after a paste follow it out
+            if !pasted.is_empty() && !options.read_only {
+                let pending = paste.get_or_insert_with(new_paste);
+                for input in &pasted {
+                    match input {
+                        Input::Paste(text) => pending.push(text.as_bytes()),
+                        Input::Key(key) => encode(key).into_iter().for_each(|bytes| pending.push(&bytes)),
+                    }
+                }
+            }
+
+            let sent = send(port, &mut send_buf, limiter.as_mut(), recorder.as_deref(), wire.as_deref(), stats.as_deref());
+            unless_gone(sent, &mut send_buf, reconnect, &out)?;
+            if let Some(pending) = paste.as_mut() {
+                match send_paste(port, pending, &out, limiter.as_mut(), recorder.as_deref(), wire.as_deref(), stats.as_deref()) {
+                    Err(ErrorKind::ConnectionError) if reconnect => {
+                        out.warn("rest of the paste not sent, the port is gone");
+                        paste = None;
+                        continue;
+                    }
+                    sent => sent?,
+                }
+                if pending.is_empty() {
+                    out.status(format!("— pasted {}/{} lines —", pending.sent().lines, pending.total().lines));
+                    paste = None;
+                }
+            }
+        }
+
+        if has_error.load(Ordering::Relaxed) == 1 { Err(ErrorKind::ConnectionError) } else { Ok(reason) }
+    }
+}
+
+/// A clone of `port` for the reader thread, blocking up to [`READER_TIMEOUT`] per read. On
+/// Windows a clone shares the handle, timeout and all, so there it keeps the writer's.
+fn reader_half(port: &SerialPort) -> Result<SerialPort> {
+    #[cfg(unix)]
+    {
+        let mut reader = port.try_clone()?;
+        reader.set_timeout(READER_TIMEOUT)?;
+        Ok(reader)
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(port.try_clone()?)
+    }
+}
+
+/// What came from the keyboard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyEvent),
+    /// Marked as pasted by the terminal, see [`BracketedPaste`].
+    Paste(String),
+}
+
+/// Waits up to `timeout` for key presses, then takes all that are there already; an unmarked
+/// paste comes in as one batch. Key releases, mouse and resize events are dropped.
+pub(crate) fn read_keys(timeout: Duration) -> io::Result<Vec<Input>> {
+    let mut pressed = Vec::new();
+    let mut wait = timeout;
+    while pressed.len() < MAX_KEYS && event::poll(wait)? {
+        match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => pressed.push(Input::Key(key)),
+            Event::Paste(text) => pressed.push(Input::Paste(text)),
+            _ => {}
+        }
+        wait = Duration::ZERO;
+    }
+    Ok(pressed)
+}
+
+/// Splits `inputs` at the first paste: the keys typed ahead of it, and the paste with all
+/// that came after it.
+fn split_pasted(mut inputs: Vec<Input>) -> (Vec<KeyEvent>, Vec<Input>) {
+    let at = inputs.iter().position(|input| matches!(input, Input::Paste(_))).unwrap_or(inputs.len());
+    let pasted = inputs.split_off(at);
+    let pressed = inputs.into_iter().filter_map(|input| match input { Input::Key(key) => Some(key), Input::Paste(_) => None }).collect();
+    (pressed, pasted)
+}
+
+/// Whether reading the console failed because it is gone, e.g. its window was closed, rather
+/// than over something to report.
+pub fn console_closed(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe) || e.raw_os_error() == Some(5)
+}
+
+/// Reads `input`, stdin unless told otherwise, on a thread of its own, so waiting for it
+/// doesn't hold up the session. The channel disconnects once it ends.
+fn read_input(mut input: Box<dyn Read + Send>) -> mpsc::Receiver<Vec<u8>> {
+    let (chunks, received) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 256];
+        // an error ends it like EOF, nothing more is coming either way
+        while let Ok(n @ 1..) = input.read(&mut buf) {
+            if chunks.send(buf[..n].to_vec()).is_err() { break; }
+        }
+    });
+    received
+}
+
+/// Answers what came in on the control socket meanwhile, and says whether it was told to quit.
+fn serve_control(control: Option<&ControlSocket>, commands: &mut LocalCommands, ctx: &mut Context<'_>) -> bool {
+    for pending in control.map(ControlSocket::pending).unwrap_or_default() {
+        if ctx.quitting() {
+            pending.answer(Reply::failed("not run, the session is quitting".to_string()));
+            continue;
+        }
+        let reply = control::dispatch(commands, &pending.request, ctx);
+        pending.answer(reply);
+    }
+    ctx.quitting()
+}
+
+/// Writes what was typed at the allowed pace, noting it in the recording, `--debug-wire` and the counters.
+pub(crate) fn send(port: &mut SerialPort, buf: &mut Vec<u8>, limiter: Option<&mut RateLimiter>,
+        recorder: Option<&Recorder>, wire: Option<&WireLog>, stats: Option<&SessionStats>) -> Result<()> {
+    if buf.is_empty() { return Ok(()); }
+    if let Some(limiter) = limiter { limiter.take(buf.len() as u64); }
+    // shown ahead of the write, so the target's answer can't come out before it
+    if let Some(wire) = wire { wire.note(Direction::Tx, buf); }
+    port.write_serial_all(buf, WRITE_TIMEOUT)?;
+    if let Some(recorder) = recorder { recorder.note(Direction::Tx, buf); }
+    if let Some(stats) = stats { stats.add_sent(buf.len() as u64); }
+    buf.clear();
+    Ok(())
+}
+
+/// Sends `paste` with its delays until it runs dry or more input arrives, its first byte
+/// announcing it.
+fn send_paste(port: &mut SerialPort, paste: &mut Paste, out: &Output, mut limiter: Option<&mut RateLimiter>,
+              recorder: Option<&Recorder>, wire: Option<&WireLog>, stats: Option<&SessionStats>) -> Result<()> {
+    if paste.sent().bytes == 0 && !paste.is_empty() {
+        out.status(format!("pasting… {} lines, Esc stops", paste.total().lines));
+    }
+    let mut byte = Vec::with_capacity(1);
+    while let Some((b, delay)) = paste.next_byte() {
+        byte.push(b);
+        send(port, &mut byte, limiter.as_deref_mut(), recorder, wire, stats)?;
+        if event::poll(delay).unwrap_or(false) { break; }
+    }
+    Ok(())
+}
+
+/// With auto-reconnect, input the vanished port refused is dropped while the reader thread
+/// gets it back.
+fn unless_gone(sent: Result<()>, buf: &mut Vec<u8>, reconnect: bool, out: &Output) -> Result<()> {
+    match sent {
+        Err(ErrorKind::ConnectionError) if reconnect => {
+            buf.clear();
+            out.warn("not sent, the port is gone");
+            Ok(())
+        }
+        sent => sent,
+    }
+}
+
+/// Gets the port of a session back while its reader thread waits, trying once a second from
+/// the input loop, so the new port is in place before the session goes on.
+struct Reconnector {
+    attempts: u32,
+    tried: u32,
+    next: Instant,
+    read_only: bool,
+    tx_log: Option<Arc<TxLog>>,
+    stats: Option<Arc<SessionStats>>,
+    readers: mpsc::Sender<SerialPort>,
+    name: String,
+}
+
+impl Reconnector {
+    /// Tries again if the port is gone and it is time to; ends the session once the attempts
+    /// are used up.
+    fn poll(&mut self, connection: &mut impl SerialConnection, state: &AtomicU8, out: &Output) {
+        if state.load(Ordering::Relaxed) != RECONNECTING || Instant::now() < self.next { return; }
+        self.tried += 1;
+        self.next = Instant::now() + Duration::from_secs(1);
+        let reopened = connection.reopen()
+            .map(|port| txlog::mirror(port, self.tx_log.as_ref()))
+            .and_then(|port| Some((reader_half(&port).ok()?, port)));
+        match reopened {
+            Some((reader, mut writer)) => {
+                if self.read_only { release_control_lines(&mut writer); }
+                *connection.port() = writer;
+                self.tried = 0;
+                if self.readers.send(reader).is_err() { return; }
+                if let Some(stats) = &self.stats { stats.add_reconnect(); }
+                out.status(format!("{} Reconnected to {}", out.icon(Icon::Ok), self.name));
+                let _ = state.compare_exchange(RECONNECTING, 0, Ordering::Relaxed, Ordering::Relaxed);
+            }
+            // unless the user quit meanwhile
+            None if self.tried >= self.attempts && state.compare_exchange(RECONNECTING, 1, Ordering::Relaxed, Ordering::Relaxed).is_ok() =>
+                out.error(format!("{} did not come back", self.name)),
+            None => {}
+        }
+    }
+}
+
+/// The reading half of the port the input loop got back, for the reader thread; `None` if
+/// the session ended first.
+fn wait_reopened(reopened: &mpsc::Receiver<SerialPort>, state: &AtomicU8) -> Option<SerialPort> {
+    loop {
+        match reopened.recv_timeout(READER_TIMEOUT) {
+            Ok(reader) => return Some(reader),
+            Err(RecvTimeoutError::Timeout) if state.load(Ordering::Relaxed) == RECONNECTING => {}
+            // sent just before the state changed, or not at all
+            Err(_) => return reopened.try_recv().ok(),
+        }
+    }
+}
+
+/// The local command line as the reader thread sees it: while it is open, the target's output
+/// is held back instead of drawn over it, and shown once it closes.
+#[derive(Debug, Default)]
+struct Console {
+    open: bool,
+    held: Vec<u8>,
+    /// What didn't fit into `held`.
+    dropped: usize,
+    /// Whether the output shown last ended its line, so the prompt needn't start a new one.
+    at_line_start: bool,
+}
+
+/// Output held back while the prompt is open, at most.
+const MAX_HELD: usize = 1 << 20;
+
+impl Console {
+    fn show(&mut self, display: &mut Display, data: &[u8]) {
+        if self.open {
+            let room = MAX_HELD - self.held.len();
+            self.held.extend_from_slice(&data[..data.len().min(room)]);
+            self.dropped += data.len().saturating_sub(room);
+            return;
+        }
+        let held = mem::take(&mut self.held);
+        if let Some(&last) = data.last().or(held.last()) { self.at_line_start = last == b'\n'; }
+        if !held.is_empty() { display.show(&held); }
+        display.show(data);
+    }
+}
+
+/// Holds the output back and draws an empty prompt below it.
+fn open_prompt(console: &Mutex<Console>, out: &Output) {
+    let mut console = console.lock().unwrap();
+    console.open = true;
+    if !out.is_diagnostic_terminal() { return; }
+    let mut stderr = stderr().lock();
+    let _ = write!(stderr, "{}{}", if console.at_line_start { "\r" } else { "\r\n" }, PROMPT);
+    let _ = stderr.flush();
+}
+
+fn draw_prompt(line: &str, out: &Output) {
+    if !out.is_diagnostic_terminal() { return; }
+    let mut stderr = stderr().lock();
+    output::erase_line(&mut stderr);
+    let _ = write!(stderr, "{}{}", PROMPT, line);
+    let _ = stderr.flush();
+}
+
+/// Erases the prompt; the output held back meanwhile follows with the reader's next read.
+fn close_prompt(console: &Mutex<Console>, out: &Output) {
+    let mut console = console.lock().unwrap();
+    console.open = false;
+    if out.is_diagnostic_terminal() {
+        let mut stderr = stderr().lock();
+        output::erase_line(&mut stderr);
+        let _ = stderr.flush();
+    }
+    let dropped = mem::take(&mut console.dropped);
+    if dropped > 0 { out.warn(format!("{} of output not shown while the prompt was open", format_bytes(dropped as u64))); }
+}
+
+/// What [`Terminal::run`] talks through: the port, and where another one comes from once it
+/// went away.
+pub trait SerialConnection {
+    fn port(&mut self) -> &mut SerialPort;
+
+    /// Tries once to open the port again after it went away, `None` while it isn't back. The
+    /// default never is, so with `--reconnect` the session gives up after its attempts.
+    fn reopen(&mut self) -> Option<SerialPort> {
+        None
+    }
+}
+
+impl SerialConnection for SerialPort {
+    fn port(&mut self) -> &mut SerialPort {
+        self
+    }
+}
+
+/// A port found again by the name and settings it was opened with, e.g. a USB adapter that
+/// re-enumerated after a hub hiccup.
+pub struct Reopening<'a> {
+    port: &'a mut SerialPort,
+    name: String,
+    settings: SerialSettings,
+}
+
+impl<'a> Reopening<'a> {
+    pub fn new(port: &'a mut SerialPort, name: &str, settings: SerialSettings) -> Self {
+        Self { port, name: name.to_string(), settings }
+    }
+}
+
+impl SerialConnection for Reopening<'_> {
+    fn port(&mut self) -> &mut SerialPort {
+        self.port
+    }
+
+    fn reopen(&mut self) -> Option<SerialPort> {
+        let target = Target::parse(&self.name);
+        if !target.is_present() { return None; }
+        target.open(&self.settings, Duration::from_millis(1)).ok()
+    }
+}
+
+/// Stops the reader thread of a [`Terminal`] and waits for it, however the terminal is left.
+struct ReaderGuard {
+    state: Arc<AtomicU8>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        let _ = self.state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| match state {
+            0 | RECONNECTING => Some(2),
+            _ => None,
+        });
+        if let Some(handle) = self.handle.take() { let _ = handle.join(); }
+    }
+}
+
+/// State of a terminal waiting for its port to come back.
+const RECONNECTING: u8 = 3;
+
+fn is_ok(flag: &AtomicU8) -> bool {
+    flag.load(Ordering::Relaxed) == 0
+}
@@ -0,0 +1,73 @@
+//! `--sync-time`: telling a target without an RTC what time it is, once it is up.
+
+use std::{fmt, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::script;
+
+/// How the host time goes out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Seconds since the Unix epoch as a little-endian `u64`, like the size header.
+    Unix64,
+    /// Text with `{unix}` and `{iso8601}` filled in, e.g. `date -s '{iso8601}'\n`.
+    Text(String),
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    /// `unix64`, or `text:TEMPLATE` with `\n`-style escapes.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            None if s == "unix64" => Ok(TimeFormat::Unix64),
+            Some(("text", template)) if !template.is_empty() => {
+                // checked now, not after the push
+                script::unescape(template)?;
+                Ok(TimeFormat::Text(template.to_string()))
+            }
+            _ => Err("expected unix64 or text:TEMPLATE, e.g. \"text:date -s '{iso8601}'\\n\"".to_string()),
+        }
+    }
+}
+
+impl TimeFormat {
+    /// What is written for `time`; times before the epoch count as the epoch.
+    pub fn encode(&self, time: SystemTime) -> Vec<u8> {
+        let unix = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        match self {
+            TimeFormat::Unix64 => unix.to_le_bytes().to_vec(),
+            TimeFormat::Text(template) => {
+                let text = template.replace("{unix}", &unix.to_string()).replace("{iso8601}", &Iso8601(unix).to_string());
+                // the escapes can't fail, they were checked when parsing
+                script::unescape(&text).unwrap_or_else(|_| text.into_bytes())
+            }
+        }
+    }
+}
+
+/// Unix seconds as a UTC date and time, e.g. `2024-02-29T13:05:09Z`.
+pub struct Iso8601(pub u64);
+
+impl fmt::Display for Iso8601 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (days, secs) = (self.0 / 86_400, self.0 % 86_400);
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
+
+/// Year, month and day of the `days`th day after 1970-01-01, in the proleptic Gregorian
+/// calendar (Howard Hinnant's `civil_from_days`).
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // counted from 0000-03-01, so the leap day ends each 400-year era
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
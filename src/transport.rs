@@ -0,0 +1,401 @@
+use std::{fmt, io, net::{TcpStream, ToSocketAddrs}, path::Path, time::Duration};
+
+pub use serialport::SerialPort as Transport;
+
+use crate::{SerialPort, settings::SerialSettings};
+
+pub mod rfc2217;
+pub mod tcp;
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What a "serial name" given on the command line points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// A local device such as `/dev/ttyUSB0` or `COM3`.
+    Native(&'a str),
+    /// `rfc2217://host:port`, a remote port served by ser2net or a terminal server.
+    Rfc2217(&'a str),
+    /// `tcp://host:port`, a terminal server's raw TCP port: bytes only, its line settings fixed.
+    Tcp(&'a str),
+}
+
+impl<'a> Target<'a> {
+    pub fn parse(name: &'a str) -> Target<'a> {
+        if let Some(addr) = name.strip_prefix("rfc2217://") { return Target::Rfc2217(addr.trim_end_matches('/')); }
+        match name.strip_prefix("tcp://") {
+            Some(addr) => Target::Tcp(addr.trim_end_matches('/')),
+            None => Target::Native(name),
+        }
+    }
+
+    /// Whether the line settings are the other end's to set, and `--baud` and friends do nothing.
+    pub fn fixed_settings(&self) -> bool {
+        matches!(self, Target::Tcp(_))
+    }
+
+    /// Whether the device can be opened right now. Network targets are probed by connecting.
+    pub fn is_present(&self) -> bool {
+        matches!(self.presence(), Ok(Presence::Present(_)))
+    }
+
+    /// Looks the target up; errors only when the platform can't enumerate ports at all.
+    pub fn presence(&self) -> serialport::Result<Presence> {
+        match self {
+            Target::Native(name) => native_presence(name),
+            Target::Rfc2217(addr) | Target::Tcp(addr) if probe(addr) => Ok(Presence::Present(addr.to_string())),
+            Target::Rfc2217(_) | Target::Tcp(_) => Ok(Presence::Missing),
+        }
+    }
+
+    pub fn open(&self, settings: &SerialSettings, timeout: Duration) -> serialport::Result<SerialPort> {
+        match self {
+            Target::Native(name) => {
+                let path = match native_presence(name)? {
+                    Presence::Present(path) => path,
+                    presence @ Presence::PermissionDenied(_) => return Err(presence.denial()),
+                    presence @ Presence::Ambiguous(_) => return Err(presence.ambiguity()),
+                    // let the OS explain
+                    Presence::Missing => device_name(name).to_string(),
+                };
+                settings.apply(serialport::new(path, settings.baud_rate))
+                    .timeout(timeout)
+                    .open()
+            }
+            Target::Rfc2217(addr) => Ok(Box::new(rfc2217::Rfc2217Port::open(addr, settings, timeout)?)),
+            Target::Tcp(addr) => Ok(Box::new(tcp::TcpPort::open(addr, settings, timeout)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Presence {
+    /// Found and accessible, under this (possibly resolved) name.
+    Present(String),
+    Missing,
+    /// The device exists but this user may not open it; carries a hint on how to fix that.
+    PermissionDenied(String),
+    /// A description matched several ports, listed as `COM3 (Silicon Labs CP210x)`.
+    Ambiguous(Vec<String>),
+}
+
+impl Presence {
+    /// The error for a [`Presence::Ambiguous`] lookup.
+    pub fn ambiguity(&self) -> serialport::Error {
+        let candidates = match self {
+            Presence::Ambiguous(candidates) => candidates.join(", "),
+            _ => String::new(),
+        };
+        serialport::Error::new(serialport::ErrorKind::InvalidInput,
+                               format!("matches several ports, pick one: {}", candidates))
+    }
+
+    /// The error for a [`Presence::PermissionDenied`] device, with its hint.
+    pub fn denial(&self) -> serialport::Error {
+        let hint = match self {
+            Presence::PermissionDenied(hint) => hint.as_str(),
+            _ => "permission denied",
+        };
+        serialport::Error::new(serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied), hint)
+    }
+
+    /// The error for a [`Presence::Missing`] device, for a tool that doesn't wait for it.
+    pub fn absence(&self) -> serialport::Error {
+        serialport::Error::new(serialport::ErrorKind::Io(io::ErrorKind::NotFound), "not found; is it plugged in?")
+    }
+}
+
+/// An enumerated port and what it calls itself, e.g. `Silicon Labs CP210x USB to UART Bridge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortListing {
+    pub name: String,
+    /// Manufacturer and product of a USB adapter, empty for other ports.
+    pub description: String,
+    /// Of a USB adapter, if it has one.
+    pub serial_number: Option<String>,
+    /// Vendor and product ID of a USB adapter.
+    pub usb_id: Option<(u16, u16)>,
+}
+
+impl PortListing {
+    pub fn new(info: serialport::SerialPortInfo) -> Self {
+        let (serial_number, usb_id) = match &info.port_type {
+            serialport::SerialPortType::UsbPort(usb) => (usb.serial_number.clone(), Some((usb.vid, usb.pid))),
+            _ => (None, None),
+        };
+        let description = match info.port_type {
+            serialport::SerialPortType::UsbPort(usb) => match (usb.manufacturer, usb.product) {
+                // Windows friendly names usually lead with the manufacturer already
+                (Some(manufacturer), Some(product)) if !product.starts_with(&manufacturer) => format!("{} {}", manufacturer, product),
+                (_, Some(product)) => product,
+                (Some(manufacturer), None) => manufacturer,
+                (None, None) => String::new(),
+            },
+            _ => String::new(),
+        };
+        Self { name: info.port_name, description, serial_number, usb_id }
+    }
+}
+
+/// Who a USB adapter is, whatever name it got: its vendor and product ID, and its serial
+/// number where it has one. Two adapters of the same make without one look alike.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbIdentity {
+    pub usb_id: (u16, u16),
+    pub serial_number: Option<String>,
+}
+
+impl UsbIdentity {
+    /// Of the adapter `listing` is, `None` for other ports.
+    pub fn of(listing: &PortListing) -> Option<UsbIdentity> {
+        Some(UsbIdentity { usb_id: listing.usb_id?, serial_number: listing.serial_number.clone() })
+    }
+}
+
+impl fmt::Display for UsbIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.usb_id.0, self.usb_id.1)?;
+        match &self.serial_number {
+            Some(serial) => write!(f, " serial {}", serial),
+            None => write!(f, " without a serial number"),
+        }
+    }
+}
+
+/// The ports whose description contains `query`, ignoring case.
+pub fn match_description<'a>(query: &str, ports: &'a [PortListing]) -> Vec<&'a PortListing> {
+    let query = query.to_lowercase();
+    if query.is_empty() { return Vec::new(); }
+    ports.iter().filter(|port| port.description.to_lowercase().contains(&query)).collect()
+}
+
+/// The ports there are, for `--list-ports`.
+pub fn list_ports() -> serialport::Result<Vec<PortListing>> {
+    Ok(serialport::available_ports()?.into_iter().map(PortListing::new).collect())
+}
+
+/// The serial number of the USB adapter `name` is, also when it is a link like
+/// `/dev/serial/by-id/…`; `None` for other ports and ones that don't say.
+pub fn usb_serial_number(name: &str) -> Option<String> {
+    listing_of(name)?.serial_number
+}
+
+/// The vendor and product ID of the USB adapter `name` is, e.g. to tell its bridge chip.
+pub fn usb_id(name: &str) -> Option<(u16, u16)> {
+    listing_of(name)?.usb_id
+}
+
+/// Who the USB adapter `name` is, to tell whether the same one is there after a reconnect.
+pub fn usb_identity(name: &str) -> Option<UsbIdentity> {
+    UsbIdentity::of(&listing_of(name)?)
+}
+
+/// How the local port `name`, or the one it links to, is listed.
+fn listing_of(name: &str) -> Option<PortListing> {
+    let path = match Target::parse(name) {
+        Target::Native(name) => match native_presence(name).ok()? {
+            Presence::Present(path) => path,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let real = std::fs::canonicalize(&path).ok();
+    find_listing(&list_ports().ok()?, &path, real.as_deref()).cloned()
+}
+
+/// The port of `ports` at `path`, or at `real` where `path` is a link like
+/// `/dev/serial/by-id/…` that enumeration doesn't list.
+pub fn find_listing<'a>(ports: &'a [PortListing], path: &str, real: Option<&Path>) -> Option<&'a PortListing> {
+    ports.iter().find(|port| port.name == path || real == Some(Path::new(&port.name)))
+}
+
+/// `name` without the `\\.\` device namespace prefix, which Windows needs for `COM10` and up
+/// but serialport adds by itself.
+pub fn device_name(name: &str) -> &str {
+    name.strip_prefix(r"\\.\").or_else(|| name.strip_prefix("//./")).unwrap_or(name)
+}
+
+/// Where to look for the right name when [`check_name`] finds a typo.
+pub const LIST_PORTS_HINT: &str = "mini_term --list-ports shows the ports there are";
+
+/// Catches serial names that can't be right, before waiting for them to show up: on unix a
+/// device in a directory that doesn't exist, on Windows a COM port that isn't `COMn`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    check_name_on(name, cfg!(windows))
+}
+
+/// [`check_name`] for unix or Windows names, whichever the host is.
+pub fn check_name_on(name: &str, windows: bool) -> Result<(), String> {
+    let is_com = |name: &str| name.len() > 3 && name[..3].eq_ignore_ascii_case("com") && name[3..].bytes().all(|c| c.is_ascii_digit());
+    match Target::parse(name) {
+        Target::Rfc2217(addr) if !addr.contains(':') => Err(format!("{} has no port, e.g. rfc2217://{}:2217", name, addr)),
+        Target::Tcp(addr) if !addr.contains(':') => Err(format!("{} has no port, e.g. tcp://{}:4001", name, addr)),
+        Target::Rfc2217(_) | Target::Tcp(_) => Ok(()),
+        Target::Native(name) if name.trim().is_empty() => Err("the serial name is empty".to_string()),
+        Target::Native(name) if windows => {
+            let device = device_name(name);
+            // "COM", "COM3:" or "COM3a", but not a description like "Communications Port"
+            let looks_like_com = device.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("com"))
+                && device[3..].chars().next().is_none_or(|c| c.is_ascii_digit() || c == ':');
+            // anything else is looked up as a USB description
+            if (looks_like_com || device != name) && !is_com(device) {
+                return Err(format!("{} is not a COM port name like COM3 or \\\\.\\COM10", name));
+            }
+            Ok(())
+        }
+        Target::Native(name) => {
+            let parent = std::path::Path::new(name).parent().filter(|parent| !parent.as_os_str().is_empty());
+            match parent {
+                // udev makes /dev/serial/by-id and by-path when the first adapter is plugged in
+                Some(parent) if parent.starts_with("/dev/serial") => Ok(()),
+                Some(parent) if !parent.is_dir() => Err(format!("{} is in {}, which does not exist", name, parent.display())),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+/// Picks the enumerated port `name` refers to. `name` may contain one `*`, e.g.
+/// `/dev/cu.usbserial-*` for macOS adapters whose suffix changes. The macOS `tty.`/`cu.`
+/// twins stand in for each other and Windows names compare case-insensitively.
+pub fn resolve_native<S: AsRef<str>>(name: &str, ports: &[S]) -> Option<String> {
+    let name = device_name(name);
+    let ports = ports.iter().map(AsRef::as_ref);
+    if let Some((prefix, suffix)) = name.split_once('*') {
+        let mut matches: Vec<&str> = ports
+            .filter(|port| port.len() >= prefix.len() + suffix.len() && port.starts_with(prefix) && port.ends_with(suffix))
+            .collect();
+        matches.sort_unstable();
+        return matches.first().map(|port| port.to_string());
+    }
+
+    let twin = name.strip_prefix("/dev/tty.").map(|rest| format!("/dev/cu.{}", rest))
+        .or_else(|| name.strip_prefix("/dev/cu.").map(|rest| format!("/dev/tty.{}", rest)));
+    let mut fallback = None;
+    for port in ports {
+        if port == name || (port.starts_with("COM") && port.eq_ignore_ascii_case(name)) {
+            return Some(port.to_string());
+        }
+        if twin.as_deref() == Some(port) { fallback = Some(port.to_string()); }
+    }
+    fallback
+}
+
+/// Looks `name` up as a port description once it matched no port name.
+fn by_description(name: &str, ports: &[PortListing]) -> Presence {
+    match match_description(name, ports).as_slice() {
+        [] => Presence::Missing,
+        [port] => Presence::Present(port.name.clone()),
+        ports => Presence::Ambiguous(ports.iter().map(|port| format!("{} ({})", port.name, port.description)).collect()),
+    }
+}
+
+#[cfg(unix)]
+fn native_presence(name: &str) -> serialport::Result<Presence> {
+    // enumeration can be unavailable (no udev) and never lists ptys or /dev/serial/by-id links
+    let ports: Vec<PortListing> = serialport::available_ports()
+        .map(|ports| ports.into_iter().map(PortListing::new).collect())
+        .unwrap_or_default();
+    let names: Vec<&str> = ports.iter().map(|port| port.name.as_str()).collect();
+    let path = match resolve_native(name, &names) {
+        Some(path) => path,
+        None if !name.contains('*') && std::path::Path::new(name).exists() => name.to_string(),
+        None => match by_description(name, &ports) {
+            Presence::Present(path) => path,
+            presence => return Ok(presence),
+        },
+    };
+    Ok(access(&path))
+}
+
+#[cfg(windows)]
+fn native_presence(name: &str) -> serialport::Result<Presence> {
+    let ports: Vec<PortListing> = serialport::available_ports()?.into_iter().map(PortListing::new).collect();
+    let names: Vec<&str> = ports.iter().map(|port| port.name.as_str()).collect();
+    Ok(resolve_native(name, &names).map_or_else(|| by_description(name, &ports), Presence::Present))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn native_presence(_name: &str) -> serialport::Result<Presence> {
+    Err(serialport::Error::new(serialport::ErrorKind::Unknown, "serial ports are not supported on this platform"))
+}
+
+/// Checks read/write permission without opening the device, which would toggle DTR.
+#[cfg(unix)]
+fn access(path: &str) -> Presence {
+    use std::{ffi::CString, os::unix::fs::MetadataExt};
+
+    let c_path = match CString::new(path) {
+        Ok(c_path) => c_path,
+        Err(_) => return Presence::Missing,
+    };
+    let checked = if unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().kind())
+    };
+    let groups = std::fs::read_to_string("/etc/group").unwrap_or_default();
+    let group = std::fs::metadata(path).ok().and_then(|meta| group_name(meta.gid(), &groups));
+    classify_access(path, checked, group)
+}
+
+/// What an access(2) check of `path` says about it: a device that isn't there is waited for,
+/// one that is but may not be opened gets a hint naming `group`, the group that owns it.
+pub fn classify_access(path: &str, checked: Result<(), io::ErrorKind>, group: Option<&str>) -> Presence {
+    match checked {
+        Ok(()) => Presence::Present(path.to_string()),
+        Err(io::ErrorKind::NotFound) => Presence::Missing,
+        Err(io::ErrorKind::PermissionDenied) => Presence::PermissionDenied(match group {
+            Some(group) => format!("permission denied on {}: add yourself to the {} group \
+                                    (sudo usermod -aG {} $USER) and log in again", path, group, group),
+            None => format!("permission denied on {}: check its owner and mode", path),
+        }),
+        // can't tell; let the open report it
+        Err(_) => Presence::Present(path.to_string()),
+    }
+}
+
+/// The name of group `gid` in `groups`, the contents of `/etc/group`.
+pub fn group_name(gid: u32, groups: &str) -> Option<&str> {
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?.parse::<u32>().ok()?;
+        if id == gid { Some(name) } else { None }
+    })
+}
+
+/// Whether opening failed the way it does when the port is unplugged meanwhile.
+pub fn went_away(error: &serialport::Error) -> bool {
+    let text = error.description.to_lowercase();
+    error.kind == serialport::ErrorKind::NoDevice
+        || ["not connected", "os error 1167)", "parameter is incorrect", "os error 22)", "cannot find the file", "no such file"]
+            .iter().any(|needle| text.contains(needle))
+}
+
+/// What to do about a failed open of `name`, for the cases with a known cause; the OS text
+/// otherwise. Windows reports these as bare messages, unix with the errno.
+pub fn explain_open_error(name: &str, error: &serialport::Error) -> String {
+    explain_open_error_on(name, error, cfg!(windows))
+}
+
+/// [`explain_open_error`] for unix or Windows errors, whichever the host is.
+pub fn explain_open_error_on(name: &str, error: &serialport::Error, windows: bool) -> String {
+    let text = error.description.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| text.contains(needle));
+    // on unix that is the permission hint; Windows denies access to a port someone else has open
+    let denied = error.kind == serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) && !windows;
+    if !denied && has(&["access is denied", "os error 5)", "used by another process", "resource busy", "os error 16)"]) {
+        format!("{} is in use by another program, e.g. another terminal or a serial monitor; close it and try again", name)
+    } else if went_away(error) {
+        format!("{} went away while opening it; check the cable and that it still shows up as the same port", name)
+    } else {
+        format!("{}: {}", name, error)
+    }
+}
+
+fn probe(addr: &str) -> bool {
+    addr.to_socket_addrs()
+        .map(|mut addrs| addrs.any(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok()))
+        .unwrap_or(false)
+}
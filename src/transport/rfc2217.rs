@@ -0,0 +1,385 @@
+//! RFC 2217 (telnet COM-PORT-CONTROL) client, for ports served by ser2net and terminal servers.
+
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration};
+use std::sync::{Arc, Mutex};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort as _, StopBits};
+
+use crate::settings::SerialSettings;
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const OPT_BINARY: u8 = 0;
+pub const OPT_SGA: u8 = 3;
+pub const OPT_COM_PORT: u8 = 44;
+
+pub const SET_BAUDRATE: u8 = 1;
+pub const SET_DATASIZE: u8 = 2;
+pub const SET_PARITY: u8 = 3;
+pub const SET_STOPSIZE: u8 = 4;
+pub const SET_CONTROL: u8 = 5;
+pub const PURGE_DATA: u8 = 12;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Appends `data` to `out` with every 0xFF doubled, as telnet requires on the data channel.
+pub fn escape(data: &[u8], out: &mut Vec<u8>) {
+    for &b in data {
+        if b == IAC { out.push(IAC); }
+        out.push(b);
+    }
+}
+
+/// Frames a COM-PORT-CONTROL subnegotiation, escaping the value.
+pub fn subnegotiation(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![IAC, SB, OPT_COM_PORT, command];
+    escape(value, &mut out);
+    out.extend_from_slice(&[IAC, SE]);
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelnetEvent {
+    /// `IAC WILL/WONT/DO/DONT <option>`.
+    Negotiation(u8, u8),
+    /// Unescaped payload of `IAC SB ... IAC SE`.
+    Subnegotiation(Vec<u8>),
+    /// Any other two-byte `IAC <command>`.
+    Command(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    Iac,
+    Negotiation(u8),
+    Sub,
+    SubIac,
+}
+
+/// Separates the data channel from telnet commands. Keeps its state between calls so
+/// sequences split across TCP reads decode correctly.
+#[derive(Debug, Clone)]
+pub struct TelnetDecoder {
+    state: State,
+    sub: Vec<u8>,
+}
+
+impl Default for TelnetDecoder {
+    fn default() -> Self {
+        Self { state: State::Data, sub: Vec::new() }
+    }
+}
+
+impl TelnetDecoder {
+    pub fn decode(&mut self, input: &[u8], data: &mut Vec<u8>, events: &mut Vec<TelnetEvent>) {
+        for &b in input {
+            self.state = match (self.state, b) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(b);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL..=DONT) => State::Negotiation(b),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Sub
+                }
+                (State::Iac, _) => {
+                    events.push(TelnetEvent::Command(b));
+                    State::Data
+                }
+                (State::Negotiation(verb), _) => {
+                    events.push(TelnetEvent::Negotiation(verb, b));
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => {
+                    self.sub.push(b);
+                    State::Sub
+                }
+                (State::SubIac, SE) => {
+                    events.push(TelnetEvent::Subnegotiation(std::mem::take(&mut self.sub)));
+                    State::Data
+                }
+                (State::SubIac, _) => {
+                    self.sub.push(b);
+                    State::Sub
+                }
+            }
+        }
+    }
+}
+
+/// Reply to a negotiation from the server, refusing everything we don't speak.
+fn answer(verb: u8, option: u8) -> Option<[u8; 3]> {
+    let supported = matches!(option, OPT_BINARY | OPT_SGA | OPT_COM_PORT);
+    match verb {
+        DO if !supported => Some([IAC, WONT, option]),
+        WILL if !supported => Some([IAC, DONT, option]),
+        _ => None,
+    }
+}
+
+struct Inner {
+    decoder: TelnetDecoder,
+    settings: SerialSettings,
+}
+
+/// A remote serial port reached over telnet with the COM-PORT-CONTROL option.
+pub struct Rfc2217Port {
+    addr: String,
+    stream: TcpStream,
+    timeout: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Rfc2217Port {
+    pub fn open(addr: &str, settings: &SerialSettings, timeout: Duration) -> io::Result<Self> {
+        let socket_addr = addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", addr)))?;
+        let stream = TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+
+        let mut port = Self {
+            addr: addr.to_string(),
+            stream,
+            timeout,
+            inner: Arc::new(Mutex::new(Inner { decoder: TelnetDecoder::default(), settings: *settings })),
+        };
+        port.set_timeout(timeout)?;
+
+        let mut hello = Vec::new();
+        for &(verb, option) in &[(WILL, OPT_BINARY), (DO, OPT_BINARY), (WILL, OPT_SGA), (DO, OPT_SGA), (WILL, OPT_COM_PORT)] {
+            hello.extend_from_slice(&[IAC, verb, option]);
+        }
+        port.stream.write_all(&hello)?;
+        port.configure(settings)?;
+        Ok(port)
+    }
+
+    fn configure(&mut self, settings: &SerialSettings) -> io::Result<()> {
+        self.send_baud(settings.baud_rate)?;
+        self.send_data_bits(settings.data_bits)?;
+        self.send_parity(settings.parity)?;
+        self.send_stop_bits(settings.stop_bits)?;
+        self.send_flow_control(settings.flow_control)
+    }
+
+    fn command(&self, command: u8, value: &[u8]) -> io::Result<()> {
+        (&self.stream).write_all(&subnegotiation(command, value))
+    }
+
+    fn send_baud(&self, baud: u32) -> io::Result<()> {
+        self.command(SET_BAUDRATE, &baud.to_be_bytes())
+    }
+
+    fn send_data_bits(&self, data_bits: DataBits) -> io::Result<()> {
+        self.command(SET_DATASIZE, &[match data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        }])
+    }
+
+    fn send_parity(&self, parity: Parity) -> io::Result<()> {
+        self.command(SET_PARITY, &[match parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        }])
+    }
+
+    fn send_stop_bits(&self, stop_bits: StopBits) -> io::Result<()> {
+        self.command(SET_STOPSIZE, &[match stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        }])
+    }
+
+    fn send_flow_control(&self, flow_control: FlowControl) -> io::Result<()> {
+        self.command(SET_CONTROL, &[match flow_control {
+            FlowControl::None => 1,
+            FlowControl::Software => 2,
+            FlowControl::Hardware => 3,
+        }])
+    }
+
+    fn unsupported(what: &str) -> serialport::Error {
+        serialport::Error::new(serialport::ErrorKind::Unknown, format!("{} is not available over RFC 2217", what))
+    }
+}
+
+impl Read for Rfc2217Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = vec![0; buf.len()];
+        let n = match self.stream.read(&mut raw) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "RFC 2217 server closed the connection")),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Err(io::ErrorKind::TimedOut.into()),
+            Err(e) => return Err(e),
+        };
+
+        let mut data = Vec::with_capacity(n);
+        let mut events = Vec::new();
+        self.inner.lock().unwrap().decoder.decode(&raw[..n], &mut data, &mut events);
+        for event in events {
+            if let TelnetEvent::Negotiation(verb, option) = event {
+                if let Some(reply) = answer(verb, option) { self.stream.write_all(&reply)?; }
+            }
+        }
+
+        if data.is_empty() { return Err(io::ErrorKind::TimedOut.into()); }
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Write for Rfc2217Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(buf.len() + buf.len() / 64);
+        escape(buf, &mut escaped);
+        self.stream.write_all(&escaped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl serialport::SerialPort for Rfc2217Port {
+    fn name(&self) -> Option<String> {
+        Some(format!("rfc2217://{}", self.addr))
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.inner.lock().unwrap().settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.inner.lock().unwrap().settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.inner.lock().unwrap().settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.inner.lock().unwrap().settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.inner.lock().unwrap().settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.send_baud(baud_rate)?;
+        self.inner.lock().unwrap().settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.send_data_bits(data_bits)?;
+        self.inner.lock().unwrap().settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.send_flow_control(flow_control)?;
+        self.inner.lock().unwrap().settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.send_parity(parity)?;
+        self.inner.lock().unwrap().settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.send_stop_bits(stop_bits)?;
+        self.inner.lock().unwrap().settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        // a zero read timeout means "block forever" to the socket API
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        Ok(self.command(SET_CONTROL, &[if level { 11 } else { 12 }])?)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        Ok(self.command(SET_CONTROL, &[if level { 8 } else { 9 }])?)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("CTS"))
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("DSR"))
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("RI"))
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("CD"))
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        Ok(self.command(PURGE_DATA, &[match buffer_to_clear {
+            ClearBuffer::Input => 1,
+            ClearBuffer::Output => 2,
+            ClearBuffer::All => 3,
+        }])?)
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        Ok(Box::new(Self {
+            addr: self.addr.clone(),
+            stream: self.stream.try_clone()?,
+            timeout: self.timeout,
+            inner: self.inner.clone(),
+        }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(self.command(SET_CONTROL, &[5])?)
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(self.command(SET_CONTROL, &[6])?)
+    }
+}
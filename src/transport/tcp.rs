@@ -0,0 +1,177 @@
+//! Raw TCP ports, as terminal servers expose each UART: bytes in and out, no telnet and no
+//! way to change the line settings, which stay the server's.
+
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::settings::SerialSettings;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A UART behind a terminal server's raw TCP port.
+pub struct TcpPort {
+    addr: String,
+    stream: TcpStream,
+    timeout: Duration,
+    /// What was asked for, reported back but never applied.
+    settings: SerialSettings,
+}
+
+impl TcpPort {
+    pub fn open(addr: &str, settings: &SerialSettings, timeout: Duration) -> io::Result<Self> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", addr));
+        for socket_addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    let mut port = Self { addr: addr.to_string(), stream, timeout, settings: *settings };
+                    port.apply_timeout(timeout)?;
+                    return Ok(port);
+                }
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    fn apply_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // a zero read timeout means "block forever" to the socket API
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn unsupported(what: &str) -> serialport::Error {
+        serialport::Error::new(serialport::ErrorKind::Unknown, format!("{} is not available over raw TCP", what))
+    }
+}
+
+impl Read for TcpPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            Ok(0) if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the terminal server closed the connection")),
+            // a read timeout, which serial ports report as TimedOut
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl serialport::SerialPort for TcpPort {
+    fn name(&self) -> Option<String> {
+        Some(format!("tcp://{}", self.addr))
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        Ok(self.apply_timeout(timeout)?)
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Err(Self::unsupported("RTS"))
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Err(Self::unsupported("DTR"))
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("CTS"))
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("DSR"))
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("RI"))
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Err(Self::unsupported("CD"))
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        // nothing to purge but what the kernel buffers, which the server has sent already
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        Ok(Box::new(Self { addr: self.addr.clone(), stream: self.stream.try_clone()?, timeout: self.timeout, settings: self.settings }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Err(Self::unsupported("a break"))
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Err(Self::unsupported("a break"))
+    }
+}
@@ -0,0 +1,84 @@
+//! Reactions to the target's output for unattended runs, e.g. exiting non-zero on `Kernel panic`.
+
+use std::{io, process::{Child, Command, Stdio}, str::FromStr};
+
+use regex::Regex;
+
+/// Environment variable carrying the matched line to `exec:` commands.
+pub const LINE_ENV: &str = "SERIAL_TOOL_LINE";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// End the session with this exit code.
+    Exit(i32),
+    /// Ring the terminal bell.
+    Bell,
+    /// Run a command through the shell without waiting for it.
+    Exec(String),
+}
+
+/// One `--on` rule: `<regex>:exit[:code]`, `<regex>:bell` or `<regex>:exec:<cmd>`.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub regex: Regex,
+    pub action: Action,
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        // the action is looked for from the right, so the regex may contain colons itself
+        let (regex, action) = if let Some((regex, cmd)) = s.split_once(":exec:") {
+            (regex, Action::Exec(cmd.to_string()))
+        } else if let Some(regex) = s.strip_suffix(":bell") {
+            (regex, Action::Bell)
+        } else if let Some(regex) = s.strip_suffix(":exit") {
+            (regex, Action::Exit(1))
+        } else {
+            match s.rsplit_once(':').and_then(|(rest, code)| Some((rest.strip_suffix(":exit")?, code.parse().ok()?))) {
+                Some((regex, code)) => (regex, Action::Exit(code)),
+                None => return Err("expected <regex>:exit[:code], <regex>:bell or <regex>:exec:<cmd>".to_string()),
+            }
+        };
+        if regex.is_empty() { return Err("empty regex".to_string()); }
+        if action == Action::Exec(String::new()) { return Err("empty command".to_string()); }
+        let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+        Ok(Trigger { regex, action })
+    }
+}
+
+/// All `--on` rules, checked against every assembled line.
+#[derive(Debug, Clone, Default)]
+pub struct Triggers {
+    rules: Vec<Trigger>,
+}
+
+impl Triggers {
+    pub fn new(rules: Vec<Trigger>) -> Self {
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a rule ends the session, e.g. `--exit-on-success`.
+    pub fn exits(&self) -> bool {
+        self.rules.iter().any(|rule| matches!(rule.action, Action::Exit(_)))
+    }
+
+    /// Actions of the rules matching `line`, in the order they were given.
+    pub fn matching<'a>(&'a self, line: &'a str) -> impl Iterator<Item = &'a Action> + 'a {
+        self.rules.iter().filter(move |rule| rule.regex.is_match(line)).map(|rule| &rule.action)
+    }
+}
+
+/// Starts `cmd` with `line` in [`LINE_ENV`]. The caller decides whether to wait for it.
+pub fn exec(cmd: &str, line: &str) -> io::Result<Child> {
+    let mut command = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd)
+        .env(LINE_ENV, line)
+        .stdin(Stdio::null())
+        .spawn()
+}
@@ -0,0 +1,302 @@
+//! `--log-tx`: every byte written to the port mirrored to a file, the pushed image included,
+//! so a failed flash can be put together again bit for bit. As plain bytes, or as a
+//! [session recording](crate::record) of `Tx` frames with their timing.
+//!
+//! The port never waits for the file: what was written goes on a queue to a thread of its
+//! own, and what doesn't fit into [`QUEUE_MAX`] is dropped, counted, and said at the end.
+
+use std::{fmt, fs::File, io::{self, BufWriter, Read, Write}, path::Path, str::FromStr, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use std::sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender}, Mutex};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::{output::{format_bytes, Output}, record::{self, Direction, Frame}, SerialPort};
+
+/// Bytes waiting to be written out at most; beyond that, what the port sends isn't logged.
+pub const QUEUE_MAX: usize = 16 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TxLogFormat {
+    /// Just the bytes, as the target got them.
+    #[default]
+    Raw,
+    /// A session recording, each write a timed `Tx` frame; replays like one.
+    Record,
+}
+
+impl FromStr for TxLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "raw" => Ok(TxLogFormat::Raw),
+            "record" => Ok(TxLogFormat::Record),
+            _ => Err("expected raw or record".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TxLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { TxLogFormat::Raw => "raw", TxLogFormat::Record => "record" })
+    }
+}
+
+/// How a [`TxLog`] went, once it is closed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxSummary {
+    /// Bytes that made it into the file.
+    pub logged: u64,
+    /// Bytes sent that didn't, the queue being full or the file failing.
+    pub dropped: u64,
+    /// Why the file stopped taking them, if it did.
+    pub error: Option<String>,
+}
+
+/// A write as queued: when it went out, and what.
+type Sent = (Instant, Vec<u8>);
+
+/// Where what is sent is mirrored to; shared by every [`TxMirror`] of the session.
+pub struct TxLog {
+    name: String,
+    queue: Mutex<Option<Sender<Sent>>>,
+    queued: Arc<AtomicUsize>,
+    logged: Arc<AtomicU64>,
+    dropped: AtomicU64,
+    writer: Mutex<Option<JoinHandle<io::Result<()>>>>,
+}
+
+impl TxLog {
+    /// Logs to `out`, `name` being what it is called in what is said about it.
+    pub fn new(out: Box<dyn Write + Send>, format: TxLogFormat, name: &str) -> io::Result<Self> {
+        let (queue, pending) = mpsc::channel();
+        let (queued, logged) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicU64::new(0)));
+        let (writer_queued, writer_logged) = (queued.clone(), logged.clone());
+        let writer = thread::Builder::new().name("tx-log".to_string())
+            .spawn(move || write_out(out, format, pending, &writer_queued, &writer_logged))?;
+        Ok(Self { name: name.to_string(), queue: Mutex::new(Some(queue)), queued, logged, dropped: AtomicU64::new(0), writer: Mutex::new(Some(writer)) })
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, format: TxLogFormat) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::new(Box::new(BufWriter::new(File::create(path)?)), format, &path.display().to_string())
+    }
+
+    /// Queues `data`, just written to the port. Never waits: with the queue full, or the log
+    /// closed or failed, it is only counted.
+    pub fn note(&self, data: &[u8]) {
+        if data.is_empty() { return; }
+        let fits = self.queued.fetch_add(data.len(), Ordering::Relaxed) + data.len() <= QUEUE_MAX;
+        let queued = fits && self.queue.lock().unwrap().as_ref().is_some_and(|queue| queue.send((Instant::now(), data.to_vec())).is_ok());
+        if !queued {
+            self.queued.fetch_sub(data.len(), Ordering::Relaxed);
+            self.dropped.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Bytes sent that didn't make it into the log so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writes out what is queued and closes the file; later writes are only counted.
+    pub fn finish(&self) -> TxSummary {
+        self.queue.lock().unwrap().take();
+        let written = self.writer.lock().unwrap().take().map(|writer| writer.join());
+        let error = match written {
+            Some(Ok(Err(e))) => Some(e.to_string()),
+            Some(Err(_)) => Some("the writer panicked".to_string()),
+            _ => None,
+        };
+        // what a failed writer left queued never made it either
+        self.dropped.fetch_add(self.queued.swap(0, Ordering::Relaxed) as u64, Ordering::Relaxed);
+        TxSummary { logged: self.logged.load(Ordering::Relaxed), dropped: self.dropped(), error }
+    }
+
+    /// [`TxLog::finish`], saying on `out` how it went: quietly when all of it is there. Once
+    /// closed, it is not again.
+    pub fn close(&self, out: &Output) {
+        if self.queue.lock().unwrap().is_none() { return; }
+        let summary = self.finish();
+        if let Some(e) = &summary.error { out.error(format!("--log-tx: {}: {}", self.name, e)); }
+        if summary.dropped > 0 {
+            out.warn(format!("--log-tx: {} of the {} sent isn't in {}, the log couldn't keep up",
+                             format_bytes(summary.dropped), format_bytes(summary.logged + summary.dropped), self.name));
+        } else {
+            out.verbose(format!("Logged the {} sent to {}", format_bytes(summary.logged), self.name));
+        }
+    }
+}
+
+static ON_EXIT: Mutex<Option<Arc<TxLog>>> = Mutex::new(None);
+
+/// Has [`close_now`] close `log`.
+pub fn close_at_exit(log: Arc<TxLog>) {
+    *ON_EXIT.lock().unwrap() = Some(log);
+}
+
+/// Closes the log [`close_at_exit`] named, for a process that ends in the middle of the
+/// session, e.g. at `--max-session-time`.
+pub fn close_now(out: &Output) {
+    let log = ON_EXIT.try_lock().ok().and_then(|mut log| log.take());
+    if let Some(log) = log { log.close(out); }
+}
+
+/// The writing thread: a batch at a time, flushed once the queue runs dry.
+fn write_out(mut out: Box<dyn Write + Send>, format: TxLogFormat, pending: Receiver<Sent>,
+             queued: &AtomicUsize, logged: &AtomicU64) -> io::Result<()> {
+    let mut last = Instant::now();
+    if format == TxLogFormat::Record { out.write_all(&record::MAGIC)?; }
+    while let Ok(first) = pending.recv() {
+        for (at, data) in std::iter::once(first).chain(pending.try_iter()) {
+            match format {
+                TxLogFormat::Raw => out.write_all(&data)?,
+                TxLogFormat::Record => {
+                    for (i, piece) in data.chunks(record::MAX_FRAME as usize).enumerate() {
+                        let delta = if i == 0 { at.saturating_duration_since(last) } else { Duration::ZERO };
+                        record::write_frame(&mut out, &Frame { delta, direction: Direction::Tx, data: piece.to_vec() })?;
+                    }
+                    last = last.max(at);
+                }
+            }
+            queued.fetch_sub(data.len(), Ordering::Relaxed);
+            logged.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        out.flush()?;
+    }
+    out.flush()
+}
+
+/// A port whose writes are also noted on a [`TxLog`], whichever part of the tool makes them.
+pub struct TxMirror {
+    port: SerialPort,
+    log: Arc<TxLog>,
+}
+
+/// `port`, mirrored to `log` if there is one.
+pub fn mirror(port: SerialPort, log: Option<&Arc<TxLog>>) -> SerialPort {
+    match log {
+        Some(log) => Box::new(TxMirror { port, log: log.clone() }),
+        None => port,
+    }
+}
+
+impl Read for TxMirror {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for TxMirror {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.port.write(buf)?;
+        self.log.note(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl serialport::SerialPort for TxMirror {
+    fn name(&self) -> Option<String> {
+        self.port.name()
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.port.baud_rate()
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        self.port.data_bits()
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        self.port.flow_control()
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        self.port.parity()
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        self.port.stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.port.set_baud_rate(baud_rate)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.port.set_data_bits(data_bits)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.port.set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.port.set_parity(parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.port.set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.port.set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.port.read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.port.read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.port.read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.port.read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_read()
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        self.port.clear(buffer_to_clear)
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        Ok(Box::new(TxMirror { port: self.port.try_clone()?, log: self.log.clone() }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        self.port.set_break()
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        self.port.clear_break()
+    }
+}
@@ -0,0 +1,189 @@
+//! `--watch`: pushing again when the image changes, rebuilding it first with `--exec`.
+//!
+//! Watched files are polled for their size and modification time, which needs nothing from
+//! the OS and works the same on network shares and in containers.
+
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}, process::{Command, ExitStatus}, thread, time::{Duration, SystemTime}};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex};
+
+use crate::{sha256::Sha256, signal};
+
+/// How often the watched paths are looked at. A change counts once a look finds nothing new,
+/// so a checkout or a save of many files is one change.
+pub const WATCH_EVERY: Duration = Duration::from_millis(500);
+
+/// Directory levels walked at most, which also ends a symlink loop.
+const MAX_DEPTH: usize = 32;
+
+/// Size and modification time of every file under some paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<PathBuf, (u64, Option<SystemTime>)>);
+
+impl Snapshot {
+    /// The files of `paths`, walking directories but skipping hidden ones and `target`, where
+    /// version control and cargo keep theirs. A path that doesn't exist yet has no files.
+    pub fn take(paths: &[PathBuf]) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for path in paths { snapshot.add(path, 0); }
+        snapshot
+    }
+
+    fn add(&mut self, path: &Path, depth: usize) {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        };
+        if !metadata.is_dir() {
+            self.0.insert(path.to_path_buf(), (metadata.len(), metadata.modified().ok()));
+            return;
+        }
+        if depth >= MAX_DEPTH { return; }
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || name == "target" { continue; }
+            self.add(&entry.path(), depth + 1);
+        }
+    }
+
+    /// Files added, changed or removed since `earlier`, sorted.
+    pub fn changes(&self, earlier: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self.0.iter()
+            .filter(|(path, stamp)| earlier.0.get(*path) != Some(stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(earlier.0.keys().filter(|path| !self.0.contains_key(*path)).cloned());
+        changed.sort();
+        changed
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    snapshot: Snapshot,
+    /// Changed since the last rebase.
+    changes: Vec<PathBuf>,
+    /// Something changed at the last look, which may not be the end of it.
+    settling: bool,
+}
+
+/// Polls the watched paths on a thread of its own, until dropped.
+pub struct Watch {
+    paths: Vec<PathBuf>,
+    state: Arc<Mutex<State>>,
+    changed: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Watch {
+    pub fn spawn(paths: Vec<PathBuf>, every: Duration) -> Watch {
+        let state = Arc::new(Mutex::new(State { snapshot: Snapshot::take(&paths), ..State::default() }));
+        let changed = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let (paths, state, changed, running) = (paths.clone(), state.clone(), changed.clone(), running.clone());
+            thread::spawn(move || while running.load(Ordering::Relaxed) {
+                thread::sleep(every);
+                let now = Snapshot::take(&paths);
+                let mut state = state.lock().unwrap();
+                let changes = now.changes(&state.snapshot);
+                if !changes.is_empty() {
+                    state.changes.extend(changes);
+                    state.snapshot = now;
+                    state.settling = true;
+                } else if state.settling {
+                    state.settling = false;
+                    changed.store(true, Ordering::Relaxed);
+                }
+            })
+        };
+        Watch { paths, state, changed, running, handle: Some(handle) }
+    }
+
+    /// Set once the watched files changed and settled; cleared by [`Watch::rebase`].
+    pub fn changed(&self) -> Arc<AtomicBool> {
+        self.changed.clone()
+    }
+
+    /// The files that changed since the last rebase, each once.
+    pub fn changes(&self) -> Vec<PathBuf> {
+        let mut changes = self.state.lock().unwrap().changes.clone();
+        changes.sort();
+        changes.dedup();
+        changes
+    }
+
+    /// Takes the files as they are now as seen, e.g. what a build just wrote.
+    pub fn rebase(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = State { snapshot: Snapshot::take(&self.paths), ..State::default() };
+        self.changed.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() { let _ = handle.join(); }
+    }
+}
+
+/// What tells one build of the image from the next: its modification time, and its contents
+/// for filesystems whose times are too coarse to tell two quick builds apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageStamp {
+    pub modified: Option<SystemTime>,
+    pub sha256: [u8; 32],
+}
+
+impl ImageStamp {
+    pub fn of<P: AsRef<Path>>(path: P) -> io::Result<ImageStamp> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path)?.modified().ok();
+        Ok(ImageStamp { modified, sha256: Sha256::digest(&fs::read(path)?) })
+    }
+}
+
+/// How a `--exec` build ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Build {
+    Succeeded,
+    Failed(ExitStatus),
+    /// Ctrl-C while it ran.
+    Cancelled,
+}
+
+/// Runs `command` through the shell, its output going straight to the screen. Ctrl-C ends
+/// the build rather than the tool.
+pub fn build(command: &str) -> io::Result<Build> {
+    let mut child = shell(command).spawn()?;
+    signal::catch_interrupts(|| loop {
+        if let Some(status) = child.try_wait()? {
+            // a build ended by the Ctrl-C too fails, but was cancelled
+            if signal::interrupted() { return Ok(Build::Cancelled); }
+            return Ok(if status.success() { Build::Succeeded } else { Build::Failed(status) });
+        }
+        if signal::interrupted() {
+            // unless it ignores the Ctrl-C it got as well
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(Build::Cancelled);
+        }
+        thread::sleep(Duration::from_millis(50));
+    })
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
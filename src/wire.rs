@@ -0,0 +1,94 @@
+//! `--debug-wire`: both directions in one stream, in the order they crossed the line, as
+//! timed hex and ASCII rows in place of the target's output:
+//!
+//! ```text
+//!    0.512 >> 72 65 62 6f 6f 74 0d                             |reboot.|
+//!    0.515 << 72 65 62 6f 6f 74 0d 0a                          |reboot..|
+//! ```
+//!
+//! With `--wire-log`, the same goes to a file as one JSON object per line, e.g.
+//! `{"t":0.512034,"dir":"tx","hex":"7265626f6f740d"}`, for offline analysis.
+
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crossterm::style::Color;
+use serde::Serialize;
+
+use crate::{output::{Output, WriteWarning}, record::Direction, sha256, terminal::{self, HEX_ROW, RxTap}};
+
+/// `data` as rows of [`HEX_ROW`] bytes, the first stamped with `elapsed` and the direction.
+pub fn rows(elapsed: Duration, direction: Direction, data: &[u8]) -> Vec<String> {
+    let arrow = match direction { Direction::Tx => ">>", Direction::Rx => "<<" };
+    data.chunks(HEX_ROW).enumerate().map(|(i, row)| {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = row.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }).collect();
+        let stamp = if i == 0 { format!("{:>8.3} {}", elapsed.as_secs_f64(), arrow) } else { " ".repeat(11) };
+        format!("{} {:<width$}  |{}|\n", stamp, hex.join(" "), ascii, width = HEX_ROW * 3 - 1)
+    }).collect()
+}
+
+/// One line of `--wire-log`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    /// Seconds since the log started.
+    pub t: f64,
+    /// `tx` or `rx`.
+    pub dir: &'static str,
+    pub hex: String,
+}
+
+impl Entry {
+    pub fn new(elapsed: Duration, direction: Direction, data: &[u8]) -> Self {
+        let dir = match direction { Direction::Tx => "tx", Direction::Rx => "rx" };
+        Self { t: (elapsed.as_secs_f64() * 1e6).round() / 1e6, dir, hex: sha256::hex(data) }
+    }
+}
+
+/// Where both directions are shown and logged; shared by the reader thread and the input
+/// loop, one lock keeping each piece whole and in order.
+pub struct WireLog {
+    out: Output,
+    started: Instant,
+    file: Mutex<Option<Box<dyn Write + Send>>>,
+    failed: WriteWarning,
+}
+
+impl WireLog {
+    /// Shows on `out`, and with `file` also logs there.
+    pub fn new(out: Output, file: Option<Box<dyn Write + Send>>) -> Self {
+        let failed = WriteWarning::new(&out, "the wire log");
+        Self { out, started: Instant::now(), file: Mutex::new(file), failed }
+    }
+
+    pub fn create(out: Output, path: Option<&Path>) -> io::Result<Self> {
+        let file = path.map(|path| File::create(path).map(|file| Box::new(BufWriter::new(file)) as Box<dyn Write + Send>)).transpose()?;
+        let mut wire = Self::new(out, file);
+        if let Some(path) = path { wire.failed = WriteWarning::new(&wire.out, path.display()); }
+        Ok(wire)
+    }
+
+    /// Shows and logs `data`, which just went `direction`.
+    pub fn note(&self, direction: Direction, data: &[u8]) {
+        if data.is_empty() { return; }
+        let mut file = self.file.lock().unwrap();
+        let elapsed = self.started.elapsed();
+        let text: String = rows(elapsed, direction, data).concat();
+        let text = match direction {
+            Direction::Tx => text.lines().map(|row| format!("{}\n", self.out.paint_target(row, Color::Green))).collect(),
+            Direction::Rx => text,
+        };
+        terminal::print_rx(&text, self.out.is_terminal());
+        let _ = io::stdout().flush();
+        if let Some(log) = file.as_mut() {
+            let mut line = serde_json::to_vec(&Entry::new(elapsed, direction, data)).unwrap_or_default();
+            line.push(b'\n');
+            self.failed.check(log.write_all(&line).and_then(|_| log.flush()));
+        }
+    }
+}
+
+impl RxTap for Arc<WireLog> {
+    fn rx(&mut self, data: &[u8]) {
+        self.note(Direction::Rx, data);
+    }
+}
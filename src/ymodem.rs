@@ -0,0 +1,337 @@
+//! YMODEM receive, for pulling files the target sends over its console, e.g. test results.
+//!
+//! The receiver asks with `C` for CRC mode. Each file starts with block 0, `name NUL size ...`,
+//! then data blocks of 128 (`SOH`) or 1024 (`STX`) bytes: `SOH|STX seq !seq data crc: u16 BE`,
+//! the CRC (CRC-16/XMODEM) covering the data. Every block is answered with `ACK`, or `NAK` to
+//! have it sent again, and the file ends with `EOT`. A block 0 without a name ends the batch.
+
+use std::{fs::{self, OpenOptions}, io::{self, Read, Write}, path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use crate::{ErrorKind, ReadSerial, Result, signal, WRITE_TIMEOUT, WriteSerial};
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+/// What the receiver sends to ask for blocks with a CRC rather than a checksum.
+pub const CRC_REQUEST: u8 = b'C';
+/// Padding of the last block of a file.
+pub const SUB: u8 = 0x1A;
+
+/// How often the receiver asks again while the sender hasn't started.
+pub const START_EVERY: Duration = Duration::from_secs(3);
+
+/// How long the next block may take before it is asked for again.
+pub const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors in a row, corrupted blocks or timeouts, before the transfer is given up.
+pub const MAX_ERRORS: u32 = 10;
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ (b as u16) << 8, |crc, _| if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 })
+    })
+}
+
+/// A block as the sender frames it: 128 bytes when `data` fits, 1024 otherwise, padded with [`SUB`].
+pub fn encode_block(seq: u8, data: &[u8]) -> Vec<u8> {
+    let (start, len) = if data.len() <= 128 { (SOH, 128) } else { (STX, 1024) };
+    let mut block = Vec::with_capacity(len + 5);
+    block.extend_from_slice(&[start, seq, !seq]);
+    block.extend_from_slice(&data[..data.len().min(len)]);
+    block.resize(len + 3, SUB);
+    let crc = crc16(&block[3..]);
+    block.extend_from_slice(&crc.to_be_bytes());
+    block
+}
+
+/// Sequence number and data of a complete block, start byte included, checked as a receiver
+/// does. A `ProtocolError` is a corrupted block, to be sent again.
+pub fn decode_block(block: &[u8]) -> Result<(u8, &[u8])> {
+    let len = match block.first() {
+        Some(&SOH) => 128,
+        Some(&STX) => 1024,
+        _ => return Err(ErrorKind::ProtocolError),
+    };
+    if block.len() != len + 5 || block[1] != !block[2] { return Err(ErrorKind::ProtocolError); }
+    let crc = u16::from_be_bytes([block[len + 3], block[len + 4]]);
+    if crc16(&block[3..len + 3]) != crc { return Err(ErrorKind::ProtocolError); }
+    Ok((block[1], &block[3..len + 3]))
+}
+
+/// What block 0 says about the file that follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
+    /// As the sender gave it, see [`sanitize_name`] for where it is written.
+    pub name: String,
+    /// Senders that know it give it, the others leave the padding on the last block.
+    pub size: Option<u64>,
+}
+
+/// The file block 0 announces, `None` for the empty block 0 that ends the batch.
+pub fn parse_header(data: &[u8]) -> Result<Option<FileHeader>> {
+    let mut fields = data.splitn(3, |&b| b == 0);
+    let name = fields.next().unwrap_or_default();
+    if name.is_empty() { return Ok(None); }
+    let name = String::from_utf8(name.to_vec()).map_err(|_| ErrorKind::TransferError("the file name is not UTF-8".to_string()))?;
+    // `size mtime mode ...`, all but the size optional
+    let size = fields.next()
+        .and_then(|info| std::str::from_utf8(info).ok())
+        .and_then(|info| info.split(' ').next())
+        .and_then(|size| size.parse::<u64>().ok());
+    Ok(Some(FileHeader { name, size }))
+}
+
+/// Where a file the sender calls `name` may be written: its last path component, so a name
+/// like `../../.bashrc` can't leave the directory, without control characters. `None` for a
+/// name that is nothing but a path.
+pub fn sanitize_name(name: &str) -> Option<String> {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    // and no drive letter or stream name on Windows
+    let clean: String = last.chars().filter(|c| !c.is_control() && *c != ':').collect();
+    match clean.trim() {
+        "" | "." | ".." => None,
+        _ => Some(clean),
+    }
+}
+
+/// `name` in `dir`, or with `-1`, `-2` and so on before its extension while that or its
+/// partial file is taken, e.g. `results-1.bin`.
+pub fn unused_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let taken = |path: &Path| fs::symlink_metadata(path).is_ok() || fs::symlink_metadata(part_path(path)).is_ok();
+    (0..).map(|n| if n == 0 { dir.join(name) } else { dir.join(format!("{}-{}{}", stem, n, extension)) })
+        .find(|path| !taken(path))
+        .unwrap()
+}
+
+/// Where `path` is written until it is complete.
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// A file written to disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// How a transfer goes, for [`receive`] to report.
+#[derive(Debug)]
+pub enum Transfer<'a> {
+    /// What the target printed before its first block, console output.
+    Output(&'a [u8]),
+    Started(&'a FileHeader),
+    /// Bytes of the current file so far.
+    Progress(u64),
+    Finished(&'a ReceivedFile),
+}
+
+/// What came over the line where a block was expected.
+enum Frame {
+    Block(Vec<u8>),
+    Eot,
+    Cancel,
+    Other(u8),
+    Timeout,
+}
+
+/// Receives a batch of files into `dir`, asking the sender to start until it does or Ctrl-C
+/// is pressed. Each file is written under a temporary name and renamed once complete; a file
+/// already in `dir` is never replaced, see [`unused_path`].
+pub fn receive<P: Read + Write + ?Sized>(port: &mut P, dir: &Path, mut report: impl FnMut(Transfer<'_>)) -> Result<Vec<ReceivedFile>> {
+    let mut files = Vec::new();
+    loop {
+        let header = match next_header(port, files.is_empty(), &mut report)? {
+            Some(header) => header,
+            None => return Ok(files),
+        };
+        report(Transfer::Started(&header));
+        let file = receive_file(port, dir, &header, &mut report)?;
+        report(Transfer::Finished(&file));
+        files.push(file);
+    }
+}
+
+fn read_frame<P: Read + ?Sized>(port: &mut P, timeout: Duration) -> Result<Frame> {
+    let mut first = [0];
+    match port.read_serial_exact_timeout(&mut first, timeout) {
+        Ok(()) => {}
+        Err(ErrorKind::ReadTimeout { .. }) => return Ok(Frame::Timeout),
+        Err(e) => return Err(e),
+    }
+    let len = match first[0] {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Ok(Frame::Eot),
+        // two in a row, so line noise doesn't end the transfer
+        CAN => return match port.read_serial_exact_timeout(&mut first, Duration::from_secs(1)) {
+            Ok(()) if first[0] == CAN => Ok(Frame::Cancel),
+            Ok(()) => Ok(Frame::Other(first[0])),
+            Err(ErrorKind::ReadTimeout { .. }) => Ok(Frame::Other(CAN)),
+            Err(e) => Err(e),
+        },
+        other => return Ok(Frame::Other(other)),
+    };
+    let mut block = vec![0; len + 5];
+    block[0] = first[0];
+    let received = port.read_serial_timeout(&mut block[1..], Duration::from_secs(1))?;
+    // a short block is a corrupted one
+    block.truncate(received + 1);
+    Ok(Frame::Block(block))
+}
+
+fn send<P: Write + ?Sized>(port: &mut P, byte: u8) -> Result<()> {
+    port.write_serial_all(&[byte], WRITE_TIMEOUT)
+}
+
+/// Drops whatever is left of a bad block, then asks for it again.
+fn reject<P: Read + Write + ?Sized>(port: &mut P) -> Result<()> {
+    let mut rest = [0; 256];
+    while port.read_serial_timeout(&mut rest, Duration::from_millis(100))? > 0 {}
+    send(port, NAK)
+}
+
+/// Tells the sender to stop, and why the transfer ended.
+fn cancel<P: Write + ?Sized>(port: &mut P, reason: String) -> ErrorKind {
+    let _ = port.write_serial_all(&[CAN; 5], WRITE_TIMEOUT);
+    ErrorKind::TransferError(reason)
+}
+
+/// Asks for block 0 and acknowledges it. The first file may take as long as the target
+/// does to send it, its console output shown meanwhile.
+fn next_header<P: Read + Write + ?Sized>(port: &mut P, first: bool, report: &mut impl FnMut(Transfer<'_>)) -> Result<Option<FileHeader>> {
+    let mut errors = 0;
+    send(port, CRC_REQUEST)?;
+    let mut asked = Instant::now();
+    loop {
+        if signal::interrupted() { return Err(ErrorKind::Interrupted); }
+        let wait = if first { START_EVERY.saturating_sub(asked.elapsed()).min(Duration::from_millis(100)) } else { START_EVERY };
+        match read_frame(port, wait)? {
+            Frame::Block(block) => match decode_block(&block) {
+                Ok((0, data)) => {
+                    let header = parse_header(data)?;
+                    send(port, ACK)?;
+                    // the data blocks are asked for like block 0 was
+                    if header.is_some() { send(port, CRC_REQUEST)?; }
+                    return Ok(header);
+                }
+                Ok((seq, _)) => return Err(cancel(port, format!("block {} before the file's block 0", seq))),
+                Err(_) => {
+                    errors += 1;
+                    reject(port)?;
+                }
+            },
+            // the sender missed the ACK to the last EOT
+            Frame::Eot => send(port, ACK)?,
+            Frame::Cancel => return Err(ErrorKind::TransferError("the sender cancelled".to_string())),
+            Frame::Other(byte) if first => report(Transfer::Output(&[byte])),
+            Frame::Other(_) => {}
+            Frame::Timeout if first => if asked.elapsed() >= START_EVERY {
+                send(port, CRC_REQUEST)?;
+                asked = Instant::now();
+            },
+            Frame::Timeout => {
+                errors += 1;
+                send(port, CRC_REQUEST)?;
+            }
+        }
+        if errors >= MAX_ERRORS { return Err(cancel(port, "too many errors waiting for block 0".to_string())); }
+    }
+}
+
+fn receive_file<P: Read + Write + ?Sized>(port: &mut P, dir: &Path, header: &FileHeader, report: &mut impl FnMut(Transfer<'_>)) -> Result<ReceivedFile> {
+    let name = match sanitize_name(&header.name) {
+        Some(name) => name,
+        None => return Err(cancel(port, format!("refusing the file name {:?}", header.name))),
+    };
+    let path = unused_path(dir, &name);
+    let part = part_path(&path);
+    // never through a link or over a file someone else left there
+    let file = match OpenOptions::new().write(true).create_new(true).open(&part) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(cancel(port, format!("{} is in the way", part.display()))),
+        Err(e) => return Err(e.into()),
+    };
+    let result = receive_data(port, file, header, report);
+    match result {
+        Ok(bytes) => {
+            fs::rename(&part, &path)?;
+            Ok(ReceivedFile { path, bytes })
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+/// The data blocks of one file, written to `file`. The last block is held back until the
+/// `EOT`, so its padding can be cut off.
+fn receive_data<P: Read + Write + ?Sized, W: Write>(port: &mut P, mut file: W, header: &FileHeader, report: &mut impl FnMut(Transfer<'_>)) -> Result<u64> {
+    let (mut expected, mut errors, mut eots) = (1u8, 0, 0);
+    let mut held: Vec<u8> = Vec::new();
+    let mut written = 0u64;
+    loop {
+        if signal::interrupted() {
+            let _ = cancel(port, String::new());
+            return Err(ErrorKind::Interrupted);
+        }
+        match read_frame(port, BLOCK_TIMEOUT)? {
+            Frame::Block(block) => match decode_block(&block) {
+                Ok((seq, data)) if seq == expected => {
+                    written += held.len() as u64;
+                    file.write_all(&held)?;
+                    held = data.to_vec();
+                    if let Some(size) = header.size {
+                        held.truncate(size.saturating_sub(written) as usize);
+                    }
+                    send(port, ACK)?;
+                    report(Transfer::Progress(written + held.len() as u64));
+                    (expected, errors, eots) = (expected.wrapping_add(1), 0, 0);
+                }
+                // sent again because the ACK got lost: acknowledged, not stored twice
+                Ok((seq, _)) if seq == expected.wrapping_sub(1) => {
+                    send(port, ACK)?;
+                    // block 0 again, before any data: the sender waits to be asked once more
+                    if written == 0 && held.is_empty() { send(port, CRC_REQUEST)?; }
+                }
+                Ok((seq, _)) => return Err(cancel(port, format!("block {} out of order, expected {}", seq, expected))),
+                Err(_) => {
+                    errors += 1;
+                    reject(port)?;
+                }
+            },
+            // the first EOT is asked for again, so a stray one can't end the file early
+            Frame::Eot if eots == 0 => {
+                eots += 1;
+                send(port, NAK)?;
+            }
+            Frame::Eot => {
+                if header.size.is_none() {
+                    while held.last() == Some(&SUB) { held.pop(); }
+                }
+                file.write_all(&held)?;
+                file.flush()?;
+                send(port, ACK)?;
+                return Ok(written + held.len() as u64);
+            }
+            Frame::Cancel => return Err(ErrorKind::TransferError("the sender cancelled".to_string())),
+            Frame::Other(_) => {}
+            Frame::Timeout => {
+                errors += 1;
+                send(port, NAK)?;
+            }
+        }
+        if errors >= MAX_ERRORS { return Err(cancel(port, format!("too many errors at block {}", expected))); }
+    }
+}
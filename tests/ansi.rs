@@ -0,0 +1,176 @@
+use rust_serial_tool::ansi::{self, Item, Kind, Parser, Sanitizer, MAX_STRING};
+
+fn sanitize(text: &str) -> String {
+    let mut sanitizer = Sanitizer::default();
+    sanitizer.sanitize(text) + &sanitizer.finish()
+}
+
+#[test]
+fn passes_colors_and_cursor_moves() {
+    let allowed = [
+        "plain text, ünïcode\r\n",
+        "\t\x07\x08",
+        "\x1b[m\x1b[0m\x1b[1;31mred\x1b[39;49m",
+        "\x1b[38;5;208m\x1b[38;2;1;2;3m",
+        "\x1b[38:5:208m\x1b[48:2::1:2:3m\x1b[4:3m",
+        "\x1b[2A\x1b[B\x1b[10C\x1b[D\x1b[E\x1b[F\x1b[5G\x1b[1;1H\x1b[3;4f",
+        "\x1b[K\x1b[2K\x1b[J\x1b[2J",
+        "\x1b[s\x1b[u\x1b7\x1b8",
+    ];
+    for text in allowed {
+        assert_eq!(sanitize(text), text, "{:?}", text);
+    }
+}
+
+#[test]
+fn shows_dangerous_sequences_as_text() {
+    let blocked = [
+        // window title, by BEL and by ST
+        ("\x1b]0;pwned\x07", "^[]0;pwned^G"),
+        ("\x1b]2;pwned\x1b\\", "^[]2;pwned^[\\"),
+        // clipboard
+        ("\x1b]52;c;aGk=\x07", "^[]52;c;aGk=^G"),
+        // alternate screen, hidden cursor, mouse reporting, bracketed paste
+        ("\x1b[?1049h", "^[[?1049h"),
+        ("\x1b[?25l", "^[[?25l"),
+        ("\x1b[?1000h", "^[[?1000h"),
+        ("\x1b[?2004l", "^[[?2004l"),
+        // the terminal answering as if typed: device status and attributes
+        ("\x1b[6n", "^[[6n"),
+        ("\x1b[c", "^[[c"),
+        // window operations, scroll regions, modes
+        ("\x1b[8;100;100t", "^[[8;100;100t"),
+        ("\x1b[1;5r", "^[[1;5r"),
+        ("\x1b[4h", "^[[4h"),
+        // device control, other strings, reset and charsets
+        ("\x1bP1$r\x1b\\", "^[P1$r^[\\"),
+        ("\x1b_apc\x1b\\", "^[_apc^[\\"),
+        ("\x1bX\x1b\\\x1b^\x1b\\", "^[X^[\\^[^^[\\"),
+        ("\x1bc", "^[c"),
+        ("\x1b(0", "^[(0"),
+        // raw C0 and C1 controls, DEL
+        ("a\x00b\x0cc\x0e", "a^@b^Lc^N"),
+        ("\x7f", "^?"),
+        ("\u{9b}31m\u{9d}", "^[[31m^[]"),
+    ];
+    for (text, shown) in blocked {
+        assert_eq!(sanitize(text), shown, "{:?}", text);
+    }
+}
+
+#[test]
+fn strips_all_sequences_for_a_console_without_them() {
+    let cases = [
+        ("\x1b[1;31mred\x1b[0m\r\n", "red\r\n"),
+        ("\x1b[2J\x1b[1;1Hhome", "home"),
+        ("\x1b]0;title\x07prompt> ", "prompt> "),
+        ("\x1b[?1049h\x1b7ünïcode\x1b8", "ünïcode"),
+        // controls are shown as the usual sanitizer shows them
+        ("bell\x07 nul\x00", "bell\x07 nul^@"),
+    ];
+    for (text, shown) in cases {
+        let mut sanitizer = Sanitizer::stripping();
+        assert_eq!(sanitizer.sanitize(text) + &sanitizer.finish(), shown, "{:?}", text);
+    }
+    // cut off between reads, and never followed up on
+    let mut sanitizer = Sanitizer::for_terminal(false);
+    assert_eq!(sanitizer.sanitize("a\x1b[3") + &sanitizer.sanitize("2mb\x1b"), "ab");
+    assert_eq!(sanitizer.finish(), "");
+    let mut sanitizer = Sanitizer::for_terminal(true);
+    assert_eq!(sanitizer.sanitize("\x1b[32mok"), "\x1b[32mok");
+}
+
+#[test]
+fn sequences_split_across_reads() {
+    let text = "ok \x1b[1;32mgreen\x1b[0m \x1b]0;title\x1b\\ \x1b[?1049h done\r\n";
+    let whole = sanitize(text);
+    assert_eq!(whole, "ok \x1b[1;32mgreen\x1b[0m ^[]0;title^[\\ ^[[?1049h done\r\n");
+    for split in 1..text.len() {
+        if !text.is_char_boundary(split) { continue; }
+        let mut sanitizer = Sanitizer::default();
+        let shown = sanitizer.sanitize(&text[..split]) + &sanitizer.sanitize(&text[split..]) + &sanitizer.finish();
+        assert_eq!(shown, whole, "split at {}", split);
+    }
+    // one character at a time
+    let mut sanitizer = Sanitizer::default();
+    let shown: String = text.chars().map(|c| sanitizer.sanitize(c.encode_utf8(&mut [0; 4]))).collect();
+    assert_eq!(shown, whole);
+}
+
+#[test]
+fn broken_and_endless_sequences() {
+    let cases = [
+        // an ESC interrupted by a line ending, the line ending still comes through
+        ("\x1b\r\n", "^[\r\n"),
+        // a CSI broken off by another ESC, which starts a sequence of its own
+        ("\x1b[1\x1b[31m", "^[[1\x1b[31m"),
+        // an OSC cut short by another sequence
+        ("\x1b]0;t\x1b[0m", "^[]0;t\x1b[0m"),
+        // never finished
+        ("tail \x1b[12", "tail ^[[12"),
+    ];
+    for (text, shown) in cases {
+        assert_eq!(sanitize(text), shown, "{:?}", text);
+    }
+
+    // an OSC without its terminator gives up after MAX_STRING, and what follows shows again
+    let endless = format!("\x1b]0;{}", "x".repeat(MAX_STRING * 2));
+    let mut sanitizer = Sanitizer::default();
+    let shown = sanitizer.sanitize(&endless);
+    assert!(shown.starts_with("^[]0;") && shown.len() > MAX_STRING, "{}", shown.len());
+    assert_eq!(sanitizer.sanitize("\x1b[1mback"), "\x1b[1mback");
+}
+
+#[test]
+fn parser_items() {
+    let mut items = Vec::new();
+    let mut parser = Parser::default();
+    parser.feed("a\x1b[1mb\r\n\x1b]0;t\x07", &mut |item| items.push(match item {
+        Item::Text(text) => format!("text {}", text),
+        Item::Control(c) => format!("control {:?}", c),
+        Item::Sequence(kind, seq) => format!("{:?} {}", kind, ansi::visible(seq)),
+    }));
+    assert_eq!(items, ["text a", "Csi ^[[1m", "text b", "control '\\r'", "control '\\n'", "Osc ^[]0;t^G"]);
+    assert!(!ansi::is_safe(&Item::Sequence(Kind::Invalid, "\x1b")));
+}
+
+#[test]
+fn shows_control_bytes_as_escapes() {
+    let cases: [(&[u8], &str); 7] = [
+        (b"login: root\r\n\tok", "login: root\r\n\tok"),
+        (b"\x00\x07\x08\x1b[31mred\x7f", "^@^G^H^[[31mred^?"),
+        ("ünïcode ✓ 🦀\r\n".as_bytes(), "ünïcode ✓ 🦀\r\n"),
+        (b"\xc2\x9b2J", "^[[2J"),
+        (b"bad \xff\xfe utf-8 \xe2\x9c", "bad \\xff\\xfe utf-8 \\xe2\\x9c"),
+        (b"\xc3(\x80", "\\xc3(\\x80"),
+        (b"", ""),
+    ];
+    for (data, expected) in cases {
+        assert_eq!(ansi::show_control(data, false), expected, "{}", data.escape_ascii());
+    }
+    assert_eq!(ansi::show_control(b"a\x00\x01b\xffc\n", true), "a\x1b[2m^@^A\x1b[22mb\x1b[2m\\xff\x1b[22mc\n");
+    assert_eq!(ansi::show_control(b"end\x1b", true), "end\x1b[2m^[\x1b[22m");
+}
+
+#[test]
+fn control_bytes_split_across_reads() {
+    // as the terminal shows them: what a read cut off in a character waits for the next one
+    let data = "┌─ \x1b[1mBoot\x1b[0m ─┐\r\n\x00ok ✓\x7f".as_bytes().iter().chain(b"\xff\r\n").copied().collect::<Vec<u8>>();
+    let whole = ansi::show_control(&data, true);
+    for reads in [vec![1, 2], vec![4, 1, 11], vec![2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2], vec![30]] {
+        let (mut shown, mut partial, mut rest) = (String::new(), Vec::new(), &data[..]);
+        for n in reads.into_iter().chain(std::iter::repeat(1)) {
+            if rest.is_empty() { break; }
+            let (read, after) = rest.split_at(n.min(rest.len()));
+            rest = after;
+            partial.extend_from_slice(read);
+            let complete = rust_serial_tool::terminal::utf8_complete(&partial);
+            shown += &ansi::show_control(&partial[..complete], false);
+            partial.drain(..complete);
+        }
+        shown += &ansi::show_control(&partial, false);
+        assert_eq!(shown, ansi::show_control(&data, false));
+        assert!(!shown.contains('\u{fffd}') && shown.ends_with("ok ✓^?\\xff\r\n"), "{:?}", shown);
+    }
+    assert!(whole.starts_with("┌─ \x1b[2m^[\x1b[22m[1mBoot"));
+}
@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use rust_serial_tool::backoff::Backoff;
+
+#[test]
+fn doubles_up_to_the_cap() {
+    let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+    let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_millis() as u64).collect();
+    // the first look is immediate, so a quick replug costs nothing
+    assert_eq!(delays, [0, 100, 200, 400, 500, 500]);
+    assert_eq!(backoff.attempts(), 6);
+}
+
+#[test]
+fn initial_above_cap() {
+    let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(4));
+    backoff.next_delay();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+}
@@ -0,0 +1,47 @@
+use rust_serial_tool::baud::{self, Bridge};
+
+#[test]
+fn knows_what_each_bridge_makes_of_a_rate() {
+    // (bridge, asked for, what it runs at)
+    let cases = [
+        (Bridge::Ftdi, 115200, 115384),
+        (Bridge::Ftdi, 921600, 923076),
+        (Bridge::Ftdi, 2000000, 2000000),
+        (Bridge::Ftdi, 3000000, 3000000),
+        (Bridge::Ftdi, 2500000, 3000000),
+        (Bridge::Cp210x, 115200, 115200),
+        (Bridge::Cp210x, 912600, 921600),
+        (Bridge::Cp210x, 1000000, 921600),
+        (Bridge::Ch340, 9600, 9615),
+        (Bridge::Ch340, 115200, 115384),
+        (Bridge::Ch340, 921600, 923076),
+        (Bridge::Ch340, 3000000, 3000000),
+        (Bridge::Pl2303, 115200, 115384),
+        (Bridge::Pl2303, 300, 300),
+    ];
+    for (bridge, requested, actual) in cases {
+        assert_eq!(bridge.actual(requested), actual, "{:?} at {}", bridge, requested);
+    }
+    assert_eq!(Bridge::from_usb(0x10c4, 0xea60), Some(Bridge::Cp210x));
+    assert_eq!(Bridge::from_usb(0x1a86, 0x7523), Some(Bridge::Ch340));
+    assert_eq!(Bridge::from_usb(0x1234, 0x5678), None);
+}
+
+#[test]
+fn suggests_a_rate_the_bridge_can_do() {
+    assert!((baud::deviation(115200, 115384) - 0.0016).abs() < 0.0001);
+    // (bridge, asked for, the rate suggested instead)
+    let cases = [
+        (Bridge::Ftdi, 115200, None),
+        (Bridge::Ftdi, 2400000, Some(2000000)),
+        (Bridge::Ftdi, 1700000, Some(1500000)),
+        (Bridge::Cp210x, 912600, None),
+        (Bridge::Cp210x, 1000000, Some(921600)),
+        (Bridge::Cp210x, 3000000, Some(921600)),
+        (Bridge::Ch340, 921600, None),
+        (Bridge::Pl2303, 1200, None),
+    ];
+    for (bridge, requested, nearest) in cases {
+        assert_eq!(bridge.check(requested).err(), nearest, "{:?} at {}", bridge, requested);
+    }
+}
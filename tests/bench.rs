@@ -0,0 +1,104 @@
+use std::{collections::VecDeque, io::{self, Read, Write}, time::Duration};
+
+use rust_serial_tool::{bench::*, cli::parse_size, settings::SerialSettings};
+
+/// Echoes everything written, optionally corrupting or dropping bytes at given offsets.
+#[derive(Default)]
+struct Loopback {
+    queue: VecDeque<u8>,
+    offset: usize,
+    corrupt: Vec<usize>,
+    drop: Vec<usize>,
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.queue.is_empty() { return Err(io::ErrorKind::TimedOut.into()); }
+        let n = buf.len().min(self.queue.len());
+        buf.iter_mut().take(n).for_each(|b| *b = self.queue.pop_front().unwrap());
+        Ok(n)
+    }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if self.corrupt.contains(&self.offset) {
+                self.queue.push_back(!b);
+            } else if !self.drop.contains(&self.offset) {
+                self.queue.push_back(b);
+            }
+            self.offset += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn config(bytes: u64) -> BenchConfig {
+    BenchConfig { bytes, read_timeout: Duration::from_millis(20), ..BenchConfig::default() }
+}
+
+#[test]
+fn generator_ignores_chunking() {
+    for &data in &[BenchData::Pattern, BenchData::Random] {
+        let mut whole = vec![0; 1000];
+        Generator::new(data).fill(&mut whole);
+
+        let mut generator = Generator::new(data);
+        let mut pieces = Vec::new();
+        for size in [1, 7, 300, 692].iter() {
+            let mut chunk = vec![0; *size];
+            generator.fill(&mut chunk);
+            pieces.extend(chunk);
+        }
+        assert_eq!(pieces, whole);
+    }
+
+    let mut pattern = [0; 3];
+    Generator::new(BenchData::Pattern).fill(&mut pattern);
+    assert_eq!(pattern, [0, 1, 2]);
+}
+
+#[test]
+fn write_counts_every_byte() {
+    let mut port = Loopback::default();
+    let mut progress = 0;
+    let result = run_write(&mut port, &config(1000), 64, |n| progress += n).unwrap();
+    assert_eq!((result.phase, result.bytes, result.errors, progress), (Phase::Write, 1000, 0, 1000));
+    assert_eq!(port.queue.len(), 1000);
+}
+
+#[test]
+fn echo_counts_lost_and_changed_bytes() {
+    let mut clean = Loopback::default();
+    assert_eq!(run_echo(&mut clean, &config(500), 100, |_| {}).unwrap().errors, 0);
+
+    let mut flaky = Loopback { corrupt: vec![3, 250], drop: vec![420], ..Loopback::default() };
+    let result = run_echo(&mut flaky, &config(500), 100, |_| {}).unwrap();
+    assert_eq!((result.phase, result.bytes), (Phase::Echo, 500));
+    // the dropped byte leaves its chunk one short; the rest of that chunk shifts by one
+    assert!(result.errors >= 3, "{}", result.errors);
+}
+
+#[test]
+fn sizes() {
+    let cases = [("4096", Ok(4096)), ("64K", Ok(65536)), ("1m", Ok(1 << 20)), ("0", Err(())), ("K", Err(())), ("12x", Err(()))];
+    for (input, expected) in cases.iter() {
+        assert_eq!(parse_size(input).map_err(|_| ()), *expected, "{}", input);
+    }
+}
+
+#[test]
+fn reports() {
+    let results = [BenchResult { phase: Phase::Write, chunk_size: 64, bytes: 2048, elapsed: Duration::from_secs(2), errors: 1 }];
+    assert_eq!(results[0].kib_per_sec(), 1.0);
+    let row: Vec<String> = table(&results).lines().nth(1).unwrap().split_whitespace().map(String::from).collect();
+    assert_eq!(row, ["write", "64", "2048", "2.000", "1.0", "1"]);
+    assert_eq!(to_json(&SerialSettings::default(), &results),
+               "{\"baud\":921600,\"framing\":\"8N1\",\"results\":[{\"phase\":\"write\",\"chunk_size\":64,\"bytes\":2048,\
+                \"seconds\":2.000000,\"kib_per_s\":1.000,\"errors\":1}]}");
+}
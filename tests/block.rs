@@ -0,0 +1,52 @@
+use rust_serial_tool::block::*;
+#[cfg(feature = "mock")]
+use {std::time::Duration, rust_serial_tool::{ErrorKind, mock::{BlockLoader, Fault}}};
+
+#[test]
+fn crc_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+    assert_eq!(crc16(b""), 0xFFFF);
+}
+
+#[test]
+fn block_layout() {
+    let block = encode_block(0x0102, b"hi");
+    assert_eq!(&block[..7], [SOH, 0x02, 0x01, 2, 0, b'h', b'i']);
+    assert_eq!(decode_block(&block).unwrap(), (0x0102, &b"hi"[..]));
+
+    for i in 0..block.len() {
+        let mut damaged = block.clone();
+        damaged[i] ^= 0x01;
+        assert!(decode_block(&damaged).is_err(), "flipped byte {}", i);
+    }
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn retransmits_corrupted_and_unacknowledged_blocks() {
+    let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i * 7) as u8).collect();
+    let faults = [None, Some(Fault::Corrupt), Some(Fault::Corrupt), None, Some(Fault::DropAck), None];
+    let mut peer = BlockLoader::new(&faults);
+    let mut sender = BlockSender::new(Duration::from_millis(20), MAX_RETRIES);
+
+    for chunk in data.chunks(BLOCK_SIZE) { sender.send(&mut peer, chunk).unwrap(); }
+    assert_eq!(peer.stored(), data);
+    assert_eq!((peer.transmissions(), sender.retransmitted), (6, 3));
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn gives_up_after_the_retries() {
+    let mut peer = BlockLoader::new(&[Some(Fault::Corrupt); 4]);
+    let mut sender = BlockSender::new(Duration::from_millis(20), 3);
+    assert!(matches!(sender.send(&mut peer, b"kernel"), Err(ErrorKind::ProtocolError)));
+    assert_eq!((peer.transmissions(), sender.retransmitted), (4, 3));
+    assert!(peer.stored().is_empty());
+}
+
+#[test]
+fn protocols() {
+    assert_eq!("block".parse(), Ok(PushProtocol::Block));
+    assert_eq!("Stream".parse(), Ok(PushProtocol::Stream));
+    assert!("xmodem".parse::<PushProtocol>().is_err());
+}
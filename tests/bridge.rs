@@ -0,0 +1,75 @@
+#![cfg(unix)]
+
+use std::{io::{Read, Write}, net::TcpStream, thread, time::{Duration, Instant}};
+
+use rust_serial_tool::{bridge::Bridge, ReadSerial, SerialPort, terminal::RxTap};
+
+fn read_until(stream: &mut TcpStream, expected: &[u8]) -> Vec<u8> {
+    stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut received = Vec::new();
+    let mut buf = [0; 256];
+    while !received.windows(expected.len()).any(|w| w == expected) && Instant::now() < deadline {
+        if let Ok(n) = stream.read(&mut buf) { received.extend_from_slice(&buf[..n]); }
+    }
+    received
+}
+
+fn read_serial_for(port: &mut SerialPort, duration: Duration) -> Vec<u8> {
+    let deadline = Instant::now() + duration;
+    let mut received = Vec::new();
+    let mut buf = [0; 256];
+    while Instant::now() < deadline {
+        let n = port.read_serial(&mut buf).unwrap();
+        received.extend_from_slice(&buf[..n]);
+    }
+    received
+}
+
+fn wait_for_clients(bridge: &Bridge, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while bridge.client_count() != count && Instant::now() < deadline { thread::sleep(Duration::from_millis(10)); }
+    assert_eq!(bridge.client_count(), count);
+}
+
+#[test]
+fn bridges_both_directions_with_single_writer() {
+    let (target, host) = serialport::TTYPort::pair().unwrap();
+    let mut target: SerialPort = Box::new(target);
+    let bridge = Bridge::listen("127.0.0.1:0").unwrap();
+    bridge.attach(Box::new(host));
+
+    let mut writer = TcpStream::connect(bridge.local_addr()).unwrap();
+    wait_for_clients(&bridge, 1);
+    let mut watcher = TcpStream::connect(bridge.local_addr()).unwrap();
+    wait_for_clients(&bridge, 2);
+
+    // serial to all clients
+    bridge.tap().rx(b"boot log\r\n");
+    assert!(read_until(&mut writer, b"boot log").ends_with(b"boot log\r\n"));
+    assert!(read_until(&mut watcher, b"boot log").ends_with(b"boot log\r\n"));
+
+    // only the first client may type
+    watcher.write_all(b"ignored").unwrap();
+    writer.write_all(b"help\r").unwrap();
+    assert_eq!(read_serial_for(&mut target, Duration::from_millis(500)), b"help\r");
+
+    // the watcher takes over once the writer leaves
+    drop(writer);
+    wait_for_clients(&bridge, 1);
+    assert!(!read_until(&mut watcher, b"write access").is_empty());
+    watcher.write_all(b"ls\r").unwrap();
+    assert_eq!(read_serial_for(&mut target, Duration::from_millis(500)), b"ls\r");
+}
+
+#[test]
+fn shutdown_closes_clients() {
+    let bridge = Bridge::listen("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(bridge.local_addr()).unwrap();
+    wait_for_clients(&bridge, 1);
+
+    drop(bridge);
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(client.read(&mut buf).unwrap(), 0);
+}
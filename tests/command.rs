@@ -0,0 +1,102 @@
+use rust_serial_tool::command::{Chord, ChordDecoder, Command, CommandTable, key_name, parse_key};
+
+fn feed_reads(decoder: &mut ChordDecoder, reads: &[&[u8]]) -> Vec<Chord> {
+    reads.iter().flat_map(|read| read.iter().filter_map(|&b| decoder.feed(b)).collect::<Vec<_>>()).collect()
+}
+
+type Case<'a> = (&'a str, &'a [&'a [u8]], &'a [Chord]);
+
+#[test]
+fn chords() {
+    let cases: &[Case] = &[
+        ("plain input", &[b"ls\r"], &[Chord::Forward(b'l'), Chord::Forward(b's'), Chord::Forward(b'\r')]),
+        ("command", &[b"\x01b"], &[Chord::Command(b'b')]),
+        ("command split across reads", &[b"x\x01", b"q"], &[Chord::Forward(b'x'), Chord::Command(b'q')]),
+        ("prefix twice sends prefix", &[b"\x01\x01"], &[Chord::Forward(0x01)]),
+        ("prefix twice split", &[b"\x01", b"\x01a"], &[Chord::Forward(0x01), Chord::Forward(b'a')]),
+        ("input after command", &[b"\x01hz"], &[Chord::Command(b'h'), Chord::Forward(b'z')]),
+        ("prefix then prefix then key", &[b"\x01\x01\x01b"], &[Chord::Forward(0x01), Chord::Command(b'b')]),
+    ];
+    for (name, reads, expected) in cases {
+        let mut decoder = ChordDecoder::new(0x01);
+        assert_eq!(feed_reads(&mut decoder, reads), *expected, "{}", name);
+        assert!(!decoder.is_pending(), "{}", name);
+    }
+}
+
+#[test]
+fn pending_prefix_survives_empty_read() {
+    let mut decoder = ChordDecoder::new(0x01);
+    assert_eq!(feed_reads(&mut decoder, &[b"\x01", b""]), &[]);
+    assert!(decoder.is_pending());
+    assert_eq!(decoder.feed(b'b'), Some(Chord::Command(b'b')));
+}
+
+#[test]
+fn cancel_drops_a_pending_prefix() {
+    let mut decoder = ChordDecoder::new(0x01);
+    assert!(!decoder.cancel());
+    assert_eq!(decoder.feed(0x01), None);
+    assert!(decoder.cancel());
+    assert_eq!(decoder.feed(b'b'), Some(Chord::Forward(b'b')));
+}
+
+#[test]
+fn table_lookup() {
+    let table = CommandTable::default();
+    assert_eq!(table.lookup(b'b'), Some(Command::SendBreak));
+    assert_eq!(table.lookup(b'B'), Some(Command::SendBreak));
+    assert_eq!(table.lookup(b'q'), Some(Command::Quit));
+    assert_eq!(table.lookup(b'c'), Some(Command::ClearScreen));
+    assert_eq!(table.lookup(b'x'), Some(Command::ToggleHex));
+    assert_eq!(table.lookup(b'e'), Some(Command::ToggleControl));
+    assert_eq!(table.lookup(b':'), Some(Command::Prompt));
+    assert_eq!(table.lookup(b'z'), None);
+
+    let table = table.bind(b'b', Command::Help);
+    assert_eq!(table.lookup(b'b'), Some(Command::Help));
+    assert_eq!(table.help().matches("b:").count(), 1);
+}
+
+#[test]
+fn help_lists_bindings() {
+    let help = CommandTable::default().help();
+    assert!(help.starts_with("Ctrl-A"));
+    assert!(help.contains("b: send break"));
+    assert!(help.contains("q: quit"));
+    assert!(help.contains("c: clear screen"));
+    assert!(help.ends_with("Ctrl-A: send Ctrl-A"));
+    // a tool's own local command, named as on the command line
+    let help = CommandTable::default().bind(b'p', Command::Local("push")).help();
+    assert!(help.contains("p: push"), "{}", help);
+}
+
+#[test]
+fn key_names() {
+    assert_eq!(key_name(0x01), "Ctrl-A");
+    assert_eq!(key_name(0x18), "Ctrl-X");
+    assert_eq!(key_name(0x1d), "Ctrl-]");
+    assert_eq!(key_name(b'q'), "q");
+}
+
+#[test]
+fn parse_keys() {
+    let cases: &[(&str, Result<u8, ()>)] = &[
+        ("ctrl-]", Ok(0x1d)),
+        ("ctrl-x", Ok(0x18)),
+        ("Ctrl-X", Ok(0x18)),
+        ("ctrl+a", Ok(0x01)),
+        ("^]", Ok(0x1d)),
+        ("ctrl-\\", Ok(0x1c)),
+        ("ctrl-_", Ok(0x1f)),
+        ("esc", Ok(0x1b)),
+        ("ctrl-", Err(())),
+        ("ctrl-xy", Err(())),
+        ("x", Err(())),
+        ("ctrl-1", Err(())),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(parse_key(name).map_err(|_| ()), *expected, "{}", name);
+        if let Ok(key) = expected { assert_eq!(parse_key(&key_name(*key)), Ok(*key), "{} round trip", name); }
+    }
+}
@@ -0,0 +1,106 @@
+//! Fixtures shared by the integration tests; each test crate uses some of them.
+#![allow(dead_code)]
+
+use std::{collections::VecDeque, env, fs, io::{self, Read, Write}, path::PathBuf, process, sync::{Arc, Mutex, mpsc::Receiver}, thread, time::Duration};
+
+/// A file kept in memory, shared with the test; `gated` takes nothing until the test lets it.
+#[derive(Clone, Default)]
+pub struct Shared {
+    data: Arc<Mutex<Vec<u8>>>,
+    gate: Option<Arc<Mutex<Receiver<()>>>>,
+}
+
+impl Shared {
+    /// Takes one write for every `()` sent to `gate`.
+    pub fn gated(gate: Receiver<()>) -> Self {
+        Self { gate: Some(Arc::new(Mutex::new(gate))), ..Self::default() }
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(gate) = &self.gate { let _ = gate.lock().unwrap().recv(); }
+        self.data.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// TX wired to RX, or left `open`, through a line that may flip or lose bytes at given
+/// stream offsets.
+#[derive(Default)]
+pub struct Jumper {
+    queue: VecDeque<u8>,
+    offset: u64,
+    corrupt: Vec<u64>,
+    drop: Vec<u64>,
+    open: bool,
+    /// Data bits the line carries, like a UART set to 7-bit characters.
+    data_mask: Option<u8>,
+}
+
+impl Jumper {
+    /// No jumper at all: nothing comes back.
+    pub fn open() -> Self {
+        Self { open: true, ..Self::default() }
+    }
+
+    /// Flips a bit of the bytes at the `corrupt` offsets and loses those at `drop`.
+    pub fn faulty(corrupt: &[u64], drop: &[u64]) -> Self {
+        Self { corrupt: corrupt.to_vec(), drop: drop.to_vec(), ..Self::default() }
+    }
+
+    /// Carries only the bits of `mask`.
+    pub fn masked(mask: u8) -> Self {
+        Self { data_mask: Some(mask), ..Self::default() }
+    }
+}
+
+impl Read for Jumper {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.queue.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n = buf.len().min(self.queue.len());
+        buf.iter_mut().take(n).for_each(|b| *b = self.queue.pop_front().unwrap());
+        Ok(n)
+    }
+}
+
+impl Write for Jumper {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if self.open || self.drop.contains(&self.offset) {
+                // lost on the wire
+            } else if self.corrupt.contains(&self.offset) {
+                self.queue.push_back(b ^ 0x10);
+            } else {
+                self.queue.push_back(b & self.data_mask.unwrap_or(0xFF));
+            }
+            self.offset += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An empty directory for `name`, of its own to the test crate and the run, so tests can go
+/// in parallel.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("serial-tool-{}-{}-{}", env!("CARGO_CRATE_NAME"), process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use clap::{CommandFactory, FromArgMatches, Parser};
+use rust_serial_tool::{cli::{self, SerialArgs}, config::{Config, Value}};
+
+const CONFIG: &str = r#"
+# the boards on the desk
+[profile.rpi4]
+port = "/dev/ttyUSB0"   # the FTDI one
+baud = 921_600
+echo = true
+exit_key = "ctrl-x"
+highlight = ["red:panic", 'yellow:warn',]
+image = "C:\\images\\kernel8.img"
+
+[profile."rock 5"]
+port = 'CP210x'
+framing = "7E1"
+speed = 1.5
+"#;
+
+#[test]
+fn parses_profiles() {
+    let config = Config::parse(CONFIG).unwrap();
+    assert_eq!(config.names().collect::<Vec<_>>(), ["rpi4", "rock 5"]);
+
+    let rpi4 = config.profile("rpi4").unwrap();
+    let cases = [
+        ("port", Value::String("/dev/ttyUSB0".to_string())),
+        ("baud", Value::Integer(921_600)),
+        ("echo", Value::Boolean(true)),
+        ("exit-key", Value::String("ctrl-x".to_string())),
+        ("exit_key", Value::String("ctrl-x".to_string())),
+        ("highlight", Value::Array(vec![Value::String("red:panic".to_string()), Value::String("yellow:warn".to_string())])),
+        ("image", Value::String("C:\\images\\kernel8.img".to_string())),
+    ];
+    for (key, value) in cases {
+        assert_eq!(rpi4.get(key), Some(&value), "{}", key);
+    }
+    assert_eq!(rpi4.settings().map(|(key, _)| key).collect::<Vec<_>>(), ["port", "baud", "echo", "exit-key", "highlight", "image"]);
+    assert_eq!(config.profile("rock 5").unwrap().get("speed"), Some(&Value::Float(1.5)));
+    assert_eq!(rpi4.get("highlight").unwrap().args(), ["red:panic", "yellow:warn"]);
+}
+
+#[test]
+fn unknown_profiles_list_the_others() {
+    let config = Config::parse(CONFIG).unwrap();
+    assert_eq!(config.profile("rpi5").unwrap_err(), "no profile rpi5, expected rpi4, rock 5");
+    assert_eq!(Config::parse("# nothing yet\n").unwrap().profile("rpi4").unwrap_err(), "no profile rpi4, the config file has none");
+}
+
+#[test]
+fn rejects_broken_files() {
+    let cases = [
+        ("baud = 115200", "1: baud is outside a [profile.NAME] section"),
+        ("[board.rpi4]", "1: unknown section \"board\", expected [profile.NAME]"),
+        ("[profile.a]\n[profile.a]", "2: profile a is defined twice"),
+        ("[profile.a]\nbaud = 1\nbaud = 2", "3: baud is set twice in profile a"),
+        ("[profile.a]\nport = /dev/ttyUSB0", "2: invalid value /dev/ttyUSB0, strings need quotes"),
+        ("[profile.a]\nport = \"/dev/tty", "2: string without its closing quote"),
+        ("[profile.a]\non = [\"a:bell\",", "2: expected ] at the end of the array, on the same line"),
+        ("[profile.a]\nbaud 9600", "2: expected = after baud"),
+        ("[profile.a]\nbaud = 9600 bps", "2: unexpected \"bps\" after the value of baud"),
+    ];
+    for (text, error) in cases {
+        assert_eq!(Config::parse(text).unwrap_err(), error, "{:?}", text);
+    }
+}
+
+/// A binary's command line in small.
+#[derive(Parser, Debug)]
+struct Args {
+    serial_name: String,
+    #[command(flatten)]
+    serial: SerialArgs,
+    #[arg(long)]
+    highlight: Vec<String>,
+}
+
+fn parse(config: &str, args: &[&str]) -> Args {
+    let config = Config::parse(config).unwrap();
+    let command = cli::apply_profile(Args::command(), config.profile("rpi4").unwrap());
+    let matches = command.try_get_matches_from(["args"].iter().chain(args)).unwrap();
+    Args::from_arg_matches(&matches).unwrap()
+}
+
+#[test]
+fn flags_override_the_profile() {
+    let profile = CONFIG.replace("echo = true", "force = true");
+    let args = parse(&profile, &[]);
+    assert_eq!((args.serial_name.as_str(), args.serial.baud, args.serial.force), ("/dev/ttyUSB0", 921_600, true));
+    assert_eq!(args.highlight, ["red:panic", "yellow:warn"]);
+    // keys without an argument, like echo and image here, are the other binary's
+    let args = parse(CONFIG, &["/dev/ttyACM0", "--baud", "115200", "--highlight", "green:ok"]);
+    assert_eq!((args.serial_name.as_str(), args.serial.baud), ("/dev/ttyACM0", 115_200));
+    assert_eq!(args.highlight, ["green:ok"]);
+}
+
+#[test]
+fn flags_then_environment_then_profile_then_defaults() {
+    let config = Config::parse(CONFIG).unwrap();
+    let parse = |profile: bool, env: &[(&str, &str)], args: &[&str]| {
+        let env: HashMap<String, String> = env.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect();
+        let profile = if profile { Some(config.profile("rpi4").unwrap()) } else { None };
+        let command = cli::resolve_defaults(Args::command().name("mini_push"), profile, |var| env.get(var).cloned());
+        let args = Args::from_arg_matches(&command.try_get_matches_from(["mini_push"].iter().chain(args)).unwrap()).unwrap();
+        (args.serial_name, args.serial.baud)
+    };
+    let port = |name: &str| name.to_string();
+    let env = [("MINIPUSH_SERIAL", "/dev/ttyACM0"), ("MINIPUSH_BAUD", "115200")];
+    let cases = [
+        ((false, &[][..], &["/dev/ttyS0"][..]), (port("/dev/ttyS0"), 921_600)),
+        ((true, &[], &[]), (port("/dev/ttyUSB0"), 921_600)),
+        ((false, &env, &[]), (port("/dev/ttyACM0"), 115_200)),
+        ((true, &env, &[]), (port("/dev/ttyACM0"), 115_200)),
+        ((true, &env[1..], &[]), (port("/dev/ttyUSB0"), 115_200)),
+        ((true, &env, &["/dev/ttyS0", "--baud", "9600"]), (port("/dev/ttyS0"), 9600)),
+        ((false, &[("MINIPUSH_SERIAL", "")], &["/dev/ttyS0"]), (port("/dev/ttyS0"), 921_600)),
+    ];
+    for ((profile, env, args), expected) in cases {
+        assert_eq!(parse(profile, env, args), expected, "profile {}, {:?}, {:?}", profile, env, args);
+    }
+
+    // only the variables of this binary count, and --help names them
+    let env = HashMap::from([("MINITERM_SERIAL".to_string(), "/dev/ttyS1".to_string())]);
+    let mut command = cli::resolve_defaults(Args::command().name("mini_push"), None, |var| env.get(var).cloned());
+    command.clone().try_get_matches_from(["mini_push"]).unwrap_err();
+    let help = command.render_help().to_string();
+    assert!(help.contains("[env: MINIPUSH_SERIAL]") && help.contains("Baud rate [env: MINIPUSH_BAUD]"), "{}", help);
+    assert_eq!(cli::env_var("mini_term", "BAUD"), "MINITERM_BAUD");
+}
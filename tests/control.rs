@@ -0,0 +1,118 @@
+mod common;
+
+use std::fs;
+
+use common::scratch;
+use rust_serial_tool::control::Request;
+
+fn request(json: &str) -> Request {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn requests_are_command_lines() {
+    let cases = [
+        (r#"{"cmd":"stats"}"#, "stats"),
+        (r#"{"cmd":"log","action":"start","path":"/tmp/boot log.txt"}"#, "log start /tmp/boot log.txt"),
+        (r#"{"cmd":"log","action":"stop"}"#, "log stop"),
+        (r#"{"cmd":"hex","args":"AA 01"}"#, "hex AA 01"),
+        (r#"{"cmd":"baud","args":" 115200 ","path":""}"#, "baud 115200"),
+    ];
+    for (json, line) in cases {
+        assert_eq!(request(json).line(), line, "{}", json);
+    }
+    assert!(serde_json::from_str::<Request>(r#"{"data":"x"}"#).is_err());
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn dispatches_to_the_local_commands() {
+    use rust_serial_tool::{control, mock::MockSerial, output::{Output, Verbosity}, prompt::{Context, LocalCommands, LogSwitch}, SerialPort, stats::SessionStats};
+
+    let mock = MockSerial::new().expect(b"reboot\n").expect(b"\xAA\x01");
+    let mut port: SerialPort = Box::new(mock.clone());
+    let (out, log, stats) = (Output::new("T", Verbosity::Quiet), LogSwitch::default(), SessionStats::default());
+    let mut commands = LocalCommands::default();
+    let mut ctx = Context::new(&mut port, &out, &log);
+    ctx.stats = Some(&stats);
+    let path = scratch("log").join("session.log");
+    let start = format!(r#"{{"cmd":"log","action":"start","path":"{}"}}"#, path.display());
+
+    let cases = [
+        (r#"{"cmd":"send","data":"reboot\n"}"#, r#"{"ok":true,"output":["→ 7 B typed in over the control socket"]}"#),
+        (r#"{"cmd":"hex","args":"AA 01"}"#, r#"{"ok":true,"output":["→ AA 01"]}"#),
+        (&start, &*format!(r#"{{"ok":true,"output":["[SAVE] Logging to {}"]}}"#, path.display())),
+        (r#"{"cmd":"log","action":"stop"}"#, &*format!(r#"{{"ok":true,"output":["[SAVE] Stopped logging to {}"]}}"#, path.display())),
+        (r#"{"cmd":"log","action":"stop"}"#, r#"{"ok":false,"error":"not logging"}"#),
+        (r#"{"cmd":"reboot"}"#, r#"{"ok":false,"error":"unknown command \"reboot\"; commands: log start PATH | log stop, send PATH, hex BYTES, stats, pull [PATH] | pull stop, scrollback PATH [LINES], baud RATE, quit, help"}"#),
+    ];
+    for (json, expected) in cases {
+        let reply = control::dispatch(&mut commands, &request(json), &mut ctx);
+        assert_eq!(serde_json::to_string(&reply).unwrap(), expected, "{}", json);
+    }
+    mock.assert_done();
+
+    let reply = control::dispatch(&mut commands, &request(r#"{"cmd":"stats"}"#), &mut ctx);
+    let stats = reply.stats.unwrap();
+    assert_eq!((stats.received, stats.sent, stats.reconnects), (0, 9, 0));
+    assert!(!ctx.quitting());
+    assert!(control::dispatch(&mut commands, &request(r#"{"cmd":"quit"}"#), &mut ctx).ok);
+    assert!(ctx.quitting());
+    let _ = fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_clients_at_once() {
+    use std::{io::{BufRead, BufReader, Write}, os::unix::net::{UnixListener, UnixStream}, thread, time::Duration};
+
+    use rust_serial_tool::control::{ControlSocket, Reply};
+
+    let path = scratch("sock").join("control");
+    // left behind by a session that is gone
+    drop(UnixListener::bind(&path).unwrap());
+    let socket = ControlSocket::bind(&path).unwrap();
+    assert!(ControlSocket::bind(&path).is_err(), "taken over while in use");
+
+    let clients: Vec<_> = (0..3).map(|i| {
+        let path = path.clone();
+        thread::spawn(move || {
+            let mut client = UnixStream::connect(&path).unwrap();
+            writeln!(client, "{{\"cmd\":\"hex\",\"args\":\"{:02X}\"}}\nnot json", i).unwrap();
+            let mut lines = BufReader::new(client).lines();
+            (lines.next().unwrap().unwrap(), lines.next().unwrap().unwrap())
+        })
+    }).collect();
+
+    let mut answered = 0;
+    while answered < 3 {
+        for pending in socket.pending() {
+            let args = pending.request.args.clone().unwrap();
+            pending.answer(Reply { ok: true, output: vec![args], ..Reply::default() });
+            answered += 1;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut replies: Vec<(String, String)> = clients.into_iter().map(|client| client.join().unwrap()).collect();
+    replies.sort();
+    for (i, (reply, bad)) in replies.iter().enumerate() {
+        assert_eq!(*reply, format!("{{\"ok\":true,\"output\":[\"{:02X}\"]}}", i));
+        assert!(bad.starts_with("{\"ok\":false,\"error\":\"not a request: "), "{}", bad);
+    }
+
+    drop(socket);
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_user_may_connect() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use rust_serial_tool::control::ControlSocket;
+
+    let path = scratch("mode").join("control");
+    let socket = ControlSocket::bind(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    drop(socket);
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "mock")]
+
+use rust_serial_tool::{delta::{self, DeltaCache, Manifest}, ErrorKind, mock::MockSerial};
+
+fn image(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32).map(|i| (i.wrapping_mul(2_654_435_761) ^ seed) as u8).collect()
+}
+
+#[test]
+fn finds_the_changed_blocks() {
+    let block = 16;
+    let old = image(100, 1);
+    let patched = |at: &[usize]| {
+        let mut new = old.clone();
+        for &i in at { new[i] ^= 0xFF; }
+        new
+    };
+    let cases = [
+        ("same", old.clone(), vec![]),
+        ("one byte", patched(&[40]), vec![(32, 16)]),
+        ("adjacent blocks merge", patched(&[17, 40]), vec![(16, 32)]),
+        ("apart", patched(&[0, 99]), vec![(0, 16), (96, 4)]),
+        ("grown", [&old[..], &[7; 30]].concat(), vec![(96, 34)]),
+        ("shrunk", old[..50].to_vec(), vec![(48, 2)]),
+        ("shrunk to a block edge", old[..48].to_vec(), vec![]),
+        ("empty", Vec::new(), vec![]),
+    ];
+    let before = Manifest::of(&old, block);
+    for (name, new, expected) in cases {
+        assert_eq!(delta::changed(&before, &Manifest::of(&new, block)), expected, "{}", name);
+    }
+
+    // nothing to patch from: all of it
+    assert_eq!(delta::changed(&Manifest::default(), &Manifest::of(&old, block)), [(0, 100)]);
+    assert_eq!(delta::changed(&Manifest::of(&old, 32), &Manifest::of(&old, block)), [(0, 100)]);
+}
+
+#[test]
+fn sums_up_an_image() {
+    assert_eq!(delta::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(delta::crc32(b""), 0);
+
+    let data = image(10_000, 3);
+    let manifest = Manifest::of(&data, delta::DELTA_BLOCK);
+    assert_eq!((manifest.size, manifest.blocks.len()), (10_000, 3));
+    assert_eq!(manifest.crc, delta::crc32(&data));
+    assert_eq!(manifest.sha256, rust_serial_tool::sha256::Sha256::digest(&data));
+}
+
+#[test]
+fn patches_what_the_loader_holds() {
+    let old = Manifest::of(&image(100, 1), 16);
+    let new = Manifest::of(&image(120, 1), 16);
+    let offer = [&b"DL"[..], &100u64.to_le_bytes(), &old.crc.to_le_bytes()].concat();
+    let end = [&120u64.to_le_bytes()[..], &0u32.to_le_bytes(), &new.crc.to_le_bytes()].concat();
+    assert_eq!(delta::encode_offer(&old), offer);
+    assert_eq!(delta::encode_end(&new), end);
+    assert_eq!(delta::encode_record(96, 24), [&96u64.to_le_bytes()[..], &24u32.to_le_bytes()].concat());
+
+    let mock = MockSerial::new().expect(&offer).reply(b"\r\nOK");
+    assert!(delta::offer(&mut mock.clone(), &old).unwrap());
+    mock.assert_done();
+    // a cold target, or one that booted something else since
+    let mock = MockSerial::new().expect(&offer).reply(b"NO");
+    assert!(!delta::offer(&mut mock.clone(), &old).unwrap());
+
+    let mock = MockSerial::new().expect(&end).reply(b"OK");
+    delta::finish(&mut mock.clone(), &new).unwrap();
+    let mock = MockSerial::new().expect(&end).reply(b"NO");
+    assert!(matches!(delta::finish(&mut mock.clone(), &new), Err(ErrorKind::ProtocolError)));
+}
+
+#[test]
+fn remembers_each_target() {
+    let dir = std::env::temp_dir().join(format!("delta-{}", std::process::id()));
+    let cache = DeltaCache::new(&dir);
+    let manifest = Manifest::of(&image(5000, 2), delta::DELTA_BLOCK);
+
+    assert_eq!(cache.load("/dev/ttyUSB0"), None);
+    cache.store("/dev/ttyUSB0", &manifest).unwrap();
+    assert_eq!(cache.load("/dev/ttyUSB0"), Some(manifest));
+    assert_eq!(cache.load("A9XK2L"), None);
+    assert_eq!(cache.path("/dev/ttyUSB0"), dir.join("_dev_ttyUSB0.json"));
+    cache.forget("/dev/ttyUSB0");
+    assert_eq!(cache.load("/dev/ttyUSB0"), None);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::{fs, process};
+
+use common::{Jumper, scratch};
+use rust_serial_tool::{doctor::*, settings::SerialSettings, transport::{PortListing, Presence}};
+#[cfg(feature = "mock")]
+use rust_serial_tool::{baud::Bridge, mock::MockSerial};
+
+fn listing(name: &str, description: &str, usb_id: Option<(u16, u16)>) -> PortListing {
+    PortListing { name: name.to_string(), description: description.to_string(), serial_number: None, usb_id }
+}
+
+fn process(pid: u32, name: &str) -> Process {
+    Process { pid, name: name.to_string() }
+}
+
+#[test]
+fn detects_the_one_usb_adapter() {
+    let ports = [listing("/dev/ttyS0", "", None), listing("/dev/ttyUSB0", "Silicon Labs CP2102", Some((0x10c4, 0xea60)))];
+    assert_eq!(detect(&ports), Ok("/dev/ttyUSB0".to_string()));
+    assert!(detect(&ports[..1]).unwrap_err().contains("no USB serial adapter"));
+
+    let several = [ports[1].clone(), listing("/dev/ttyUSB1", "FT232R", Some((0x0403, 0x6001)))];
+    assert!(detect(&several).unwrap_err().ends_with("/dev/ttyUSB0, /dev/ttyUSB1"));
+}
+
+#[test]
+fn a_missing_port_is_told_the_ports_there_are() {
+    let ports = [listing("COM4", "Silicon Labs CP210x", Some((0x10c4, 0xea60))), listing("COM1", "", None)];
+    let check = present("COM3", &Ok(Presence::Missing), &ports);
+    assert_eq!(check.status, Status::Fail);
+    assert_eq!(check.hint.as_deref(), Some("the ports there are: COM4 (Silicon Labs CP210x), COM1"));
+
+    assert!(present("COM3", &Ok(Presence::Missing), &[]).hint.unwrap().contains("plug the adapter in"));
+    assert_eq!(present("COM4", &Ok(Presence::Present("COM4".to_string())), &ports).status, Status::Pass);
+}
+
+#[test]
+fn a_denied_port_gets_the_group_hint() {
+    let hint = "permission denied on /dev/ttyUSB0: add yourself to the dialout group";
+    let check = present("/dev/ttyUSB0", &Ok(Presence::PermissionDenied(hint.to_string())), &[]);
+    assert_eq!((check.status, check.hint.as_deref()), (Status::Fail, Some(hint)));
+}
+
+#[test]
+fn lock_files_of_others_fail_and_stale_ones_warn() {
+    let dirs = [scratch("lock")];
+    assert_eq!(exclusive("/dev/ttyUSB5".as_ref(), &dirs).status, Status::Pass);
+
+    fs::write(dirs[0].join("LCK..ttyUSB5"), format!("{:>10}\n", process::id())).unwrap();
+    let check = exclusive("/dev/ttyUSB5".as_ref(), &dirs);
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.detail.contains(&format!("PID {}", process::id())), "{}", check.detail);
+
+    // far above any pid_max
+    fs::write(dirs[0].join("LCK..ttyUSB5"), "999999999\n").unwrap();
+    assert_eq!(exclusive("/dev/ttyUSB5".as_ref(), &dirs).status, Status::Warn);
+}
+
+#[cfg(unix)]
+#[test]
+fn finds_who_holds_the_device_in_proc() {
+    let root = scratch("proc");
+    let device = root.join("ttyUSB0");
+    fs::write(&device, "").unwrap();
+    for (pid, name, fd) in [(812, "brltty", Some(&device)), (900, "bash", None), (1234, "picocom", Some(&device))] {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(dir.join("fd")).unwrap();
+        fs::write(dir.join("comm"), format!("{}\n", name)).unwrap();
+        std::os::unix::fs::symlink(fd.unwrap_or(&root.join("null")), dir.join("fd").join("3")).unwrap();
+    }
+    fs::create_dir_all(root.join("self")).unwrap();
+
+    assert_eq!(processes(&root), [process(812, "brltty"), process(900, "bash"), process(1234, "picocom")]);
+    assert_eq!(holders(&root, &device), [process(812, "brltty"), process(1234, "picocom")]);
+}
+
+#[test]
+fn known_interferers_get_their_own_hint() {
+    assert_eq!(holders_check("/dev/ttyUSB0", &[]).status, Status::Pass);
+
+    let check = holders_check("/dev/ttyUSB0", &[process(1234, "picocom"), process(812, "brltty")]);
+    assert_eq!(check.status, Status::Fail);
+    assert_eq!(check.detail, "/dev/ttyUSB0 is held by picocom (PID 1234), brltty (PID 812)");
+    assert!(check.hint.unwrap().contains("braille"));
+
+    let check = holders_check("/dev/ttyUSB0", &[process(1234, "picocom")]);
+    assert_eq!(check.hint.as_deref(), Some("close picocom, or end it with kill 1234"));
+}
+
+#[test]
+fn port_grabbing_services_warn() {
+    assert_eq!(services_check(&[process(1, "systemd"), process(900, "bash")]).status, Status::Pass);
+    let check = services_check(&[process(1, "systemd"), process(640, "ModemManager")]);
+    assert_eq!(check.status, Status::Warn);
+    assert!(check.hint.unwrap().contains("ID_MM_DEVICE_IGNORE"));
+}
+
+#[test]
+fn a_port_in_use_says_so() {
+    let busy = serialport::Error::new(serialport::ErrorKind::Unknown, "Device or resource busy (os error 16)");
+    let check = open_check("/dev/ttyUSB0", Some(&busy));
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.hint.unwrap().contains("in use by another program"));
+    assert_eq!(open_check("/dev/ttyUSB0", None).status, Status::Pass);
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn checks_the_baud_rate_against_the_bridge() {
+    let mut port = MockSerial::new();
+    assert_eq!(baud_check(&mut port, 115_200, Some(Bridge::Ftdi)).status, Status::Pass);
+    assert_eq!(port.settings().baud_rate, 115_200);
+
+    let check = baud_check(&mut port, 1_000_000, Some(Bridge::Cp210x));
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.hint.unwrap().starts_with("try --baud"));
+}
+
+#[test]
+fn loopback_needs_the_jumper() {
+    let check = loopback_check(&mut Jumper::default(), SerialSettings::default());
+    assert_eq!((check.status, check.detail.as_str()), (Status::Pass, "4096 bytes came back"));
+
+    let check = loopback_check(&mut Jumper::open(), SerialSettings::default());
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.hint.unwrap().contains("jumper TX to RX"));
+}
+
+#[test]
+fn reports_as_text_and_json() {
+    let report = Report {
+        port: "/dev/ttyUSB0".to_string(),
+        checks: vec![
+            Check::pass("present", "/dev/ttyUSB0 is there"),
+            Check::fail("holders", "/dev/ttyUSB0 is held by cat (PID 7)", "close cat, or end it with kill 7"),
+            Check::skip("loopback", "not asked for"),
+        ],
+    };
+    assert!(!report.passed());
+    let text = report.to_string();
+    assert!(text.contains("  FAIL  holders    /dev/ttyUSB0 is held by cat (PID 7)\n"), "{}", text);
+    assert!(text.contains("→ close cat"), "{}", text);
+    assert!(text.ends_with("1 check failed"), "{}", text);
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["passed"], false);
+    assert_eq!(json["checks"][1]["status"], "fail");
+    assert_eq!(json["checks"][1]["hint"], "close cat, or end it with kill 7");
+    assert!(json["checks"][0].get("hint").is_none());
+}
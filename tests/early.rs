@@ -0,0 +1,29 @@
+use std::time::Instant;
+
+use rust_serial_tool::early::{EarlyBuffer, EarlyOutput};
+
+#[test]
+fn parses_the_mode() {
+    let cases = [("hold", Ok(EarlyOutput::Hold)), ("Live", Ok(EarlyOutput::Live)), ("now", Err("expected hold or live".to_string()))];
+    for (text, expected) in cases {
+        assert_eq!(text.parse::<EarlyOutput>(), expected, "{}", text);
+    }
+    assert_eq!(EarlyOutput::default().to_string(), "hold");
+}
+
+#[test]
+fn shows_each_byte_once() {
+    let pieces: [&[u8]; 3] = [b"crc ok\r\nloa", b"ding", b" 4 KiB\r\nbo"];
+    let cases = [
+        (EarlyOutput::Hold, vec!["", "", ""], "crc ok\r\nloading 4 KiB\r\nbo"),
+        (EarlyOutput::Live, vec!["crc ok\r\n", "", "loading 4 KiB\r\n"], "bo"),
+    ];
+    for (mode, shown, rest) in cases {
+        let mut early = EarlyBuffer::new(mode);
+        let now = Instant::now();
+        let got: Vec<String> = pieces.iter().map(|piece| String::from_utf8(early.push(piece, now)).unwrap()).collect();
+        assert_eq!(got, shown, "{}", mode);
+        assert_eq!(early.finish(), rest.as_bytes(), "{}", mode);
+        assert!(early.finish().is_empty(), "{}", mode);
+    }
+}
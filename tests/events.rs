@@ -0,0 +1,52 @@
+mod common;
+
+use common::Shared;
+use rust_serial_tool::events::*;
+
+fn without_timestamp(line: &str) -> String {
+    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert!(value["ts_ms"].as_u64().unwrap() > 0);
+    let mut object = value.as_object().unwrap().clone();
+    object.remove("ts_ms");
+    serde_json::Value::Object(object).to_string()
+}
+
+#[test]
+fn schema() {
+    let cases = [
+        (Event::WaitingForSerial { port: "/dev/ttyUSB0".to_string() }, r#"{"event":"waiting_for_serial","port":"/dev/ttyUSB0","tool":"MP"}"#),
+        (Event::HandshakeOk, r#"{"event":"handshake_ok","tool":"MP"}"#),
+        (Event::BaudSwitched { from: 115_200, to: 921_600 }, r#"{"event":"baud_switched","from":115200,"to":921600,"tool":"MP"}"#),
+        (Event::SizeSent { bytes: 6144 }, r#"{"bytes":6144,"event":"size_sent","tool":"MP"}"#),
+        (Event::PushProgress { sent: 512, total: 1024, percent: 50 }, r#"{"event":"push_progress","percent":50,"sent":512,"tool":"MP","total":1024}"#),
+        (
+            Event::Error { kind: "protocol".to_string(), phase: "size".to_string(), message: "ProtocolError".to_string() },
+            r#"{"event":"error","kind":"protocol","message":"ProtocolError","phase":"size","tool":"MP"}"#,
+        ),
+        (Event::Exit { success: true }, r#"{"event":"exit","success":true,"tool":"MP"}"#),
+    ];
+    for (event, expected) in cases.iter() {
+        assert_eq!(without_timestamp(&to_line("MP", event)), *expected);
+    }
+}
+
+#[test]
+fn log_writes_one_line_per_event() {
+    let out = Shared::default();
+    let log = EventLog::new(Box::new(out.clone()));
+    log.emit("MT", &Event::Reconnect);
+    log.emit("MT", &Event::Connected { port: "COM3".to_string(), settings: "921600 8N1, no flow control".to_string() });
+
+    let text = String::from_utf8(out.contents()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(without_timestamp(lines[0]), r#"{"event":"reconnect","tool":"MT"}"#);
+    assert!(lines[1].contains(r#""event":"connected""#));
+}
+
+#[test]
+fn formats() {
+    assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+    assert_eq!("human".parse(), Ok(LogFormat::Human));
+    assert!("xml".parse::<LogFormat>().is_err());
+}
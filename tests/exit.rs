@@ -0,0 +1,36 @@
+use std::{io, path::PathBuf, time::Duration};
+
+use rust_serial_tool::{ErrorKind, exit};
+
+#[test]
+fn tells_failures_apart_by_code() {
+    let during = |error: ErrorKind, phase: &str| Err(error.context(phase, "/dev/ttyUSB0"));
+    let missing = serialport::Error::new(serialport::ErrorKind::Io(io::ErrorKind::NotFound), "not found; is it plugged in?");
+    let cases: Vec<(Result<(), ErrorKind>, i32)> = vec![
+        (Ok(()), 0),
+        (during(ErrorKind::ExpectTimeout { pattern: "str:Booted".to_string(), waited: Duration::from_secs(30) }, "boot"), 1),
+        (during(ErrorKind::BoardsFailed { failed: 1, total: 2 }, "push"), 1),
+        (during(ErrorKind::WrongTarget { expected: "serial=a1b2".to_string(), identity: Some("serial=c3d4".to_string()) }, "identify"), 1),
+        (during(ErrorKind::UnsupportedBaud { bridge: "CP210x", requested: 1000000, actual: 921600, nearest: 921600 }, "open"), 1),
+        (during(ErrorKind::DeviceChanged { device: "/dev/ttyUSB0".to_string(), was: "10c4:ea60 serial 0001".to_string(),
+                                           now: "0403:6001 serial A50285BI".to_string() }, "open"), 1),
+        (during(ErrorKind::TimeoutError, "handshake"), 2),
+        (during(ErrorKind::ProtocolError, "handshake"), 3),
+        (during(ErrorKind::UnexpectedReply { expected: "OK".to_string(), received: b"NO".to_vec() }, "size"), 3),
+        (during(ErrorKind::ReadTimeout { received: 1, expected: 2 }, "size"), 3),
+        (during(ErrorKind::ReadTimeout { received: 3, expected: 30 }, "identify"), 3),
+        (during(ErrorKind::WriteTimeout { written: 0, expected: 512 }, "push"), 3),
+        (during(ErrorKind::TargetRebooted { at: 4096 }, "push"), 3),
+        (during(ErrorKind::SerialError(missing), "open"), 4),
+        (during(ErrorKind::PortLocked { pid: 1, stale: false, path: PathBuf::from("/var/lock/LCK..ttyUSB0") }, "open"), 4),
+        (during(ErrorKind::ConnectionError, "push"), 4),
+        (during(ErrorKind::IoError(io::Error::from(io::ErrorKind::NotFound)), "load"), 5),
+        (during(ErrorKind::ImageTooLarge(1 << 40), "size"), 5),
+        (during(ErrorKind::ImageHuge { size: 1 << 30, max: 128 << 20 }, "load"), 5),
+        (Err(ErrorKind::NetworkError("404 Not Found".to_string())), 5),
+        (during(ErrorKind::Interrupted, "handshake"), 130),
+    ];
+    for (result, code) in &cases {
+        assert_eq!(exit::script_code(result), *code, "{:?}", result);
+    }
+}
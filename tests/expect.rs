@@ -0,0 +1,82 @@
+#![cfg(feature = "mock")]
+
+use std::time::Duration;
+
+use rust_serial_tool::{expect::{self, Transcript}, mock::MockSerial, pattern::Pattern};
+
+fn run(replies: &[&[u8]], pattern: &Pattern, deadline: Duration) -> (Transcript, Vec<u8>) {
+    let mock = replies.iter().fold(MockSerial::new(), |mock, reply| mock.reply(reply));
+    let mut echoed = Vec::new();
+    let transcript = expect::expect(&mut mock.clone(), pattern, deadline, |data| echoed.extend_from_slice(data)).unwrap();
+    (transcript, echoed)
+}
+
+#[test]
+fn finds_patterns_across_reads() {
+    let boot: [&[u8]; 4] = [b"U-Boot 2024.01\r\n", b"Starting kernel ...\r\n[    1.2] Kernel pa", b"nic - not syncing\r\n", b"after"];
+    let cases = [
+        (Pattern::literal("Kernel panic"), Some((47, b"Kernel panic".as_ref()))),
+        (Pattern::regex(r"Kernel panic|login:").unwrap(), Some((47, b"Kernel panic".as_ref()))),
+        (Pattern::regex(r"\[\s*[\d.]+\]").unwrap(), Some((37, b"[    1.2]".as_ref()))),
+        (Pattern::literal("U-Boot"), Some((0, b"U-Boot".as_ref()))),
+        (Pattern::literal("login:"), None),
+    ];
+    for (pattern, expected) in cases {
+        let (transcript, echoed) = run(&boot, &pattern, Duration::from_millis(200));
+        assert_eq!(transcript.offset().zip(transcript.matched()), expected, "{}", pattern);
+        assert_eq!(transcript.captured, echoed, "{}", pattern);
+        // reading stops with the match
+        match expected {
+            Some((offset, matched)) => {
+                assert_eq!(transcript.until_match().len(), offset + matched.len());
+                assert!(!transcript.captured.ends_with(b"after"), "{}", pattern);
+            }
+            None => assert_eq!(transcript.captured, boot.concat()),
+        }
+    }
+}
+
+#[test]
+fn gives_up_at_the_deadline() {
+    let (transcript, _) = run(&[b"still booting"], &Pattern::literal("login:"), Duration::from_millis(100));
+    assert_eq!(transcript.found, None);
+    assert_eq!(transcript.until_match(), b"still booting");
+    assert!(transcript.elapsed >= Duration::from_millis(100));
+
+    let (transcript, _) = run(&[b"anything"], &Pattern::literal(""), Duration::from_secs(10));
+    assert_eq!((transcript.offset(), transcript.captured.len()), (Some(0), 0));
+}
+
+#[test]
+fn anchors_see_the_bytes_before_the_search() {
+    let pattern = Pattern::regex(r"\blogin:").unwrap();
+    let cases = [
+        (b"xlogin: login:".as_ref(), 0, Some((8, 14))),
+        (b"xlogin: login:", 1, Some((8, 14))),
+        (b"login:", 0, Some((0, 6))),
+        (b"login:", 6, None),
+    ];
+    for (haystack, start, expected) in cases {
+        assert_eq!(pattern.find_at(haystack, start), expected, "{} at {}", haystack.escape_ascii(), start);
+    }
+    assert_eq!(Pattern::literal("in").find_at(b"login: in", 4), Some((7, 9)));
+}
+
+#[test]
+fn parses_patterns() {
+    let cases = [
+        ("str:login:", Ok("\"login:\"")),
+        ("str:# \\r\\n", Ok("\"# \\r\\n\"")),
+        ("regex:Kernel panic|login:", Ok("/Kernel panic|login:/")),
+        ("login:", Err("expected str:TEXT or regex:REGEX")),
+        ("str:", Err("expected str:TEXT or regex:REGEX")),
+        ("regex:(", Err("regex parse error")),
+    ];
+    for (s, expected) in cases {
+        match (s.parse::<Pattern>(), expected) {
+            (Ok(pattern), Ok(shown)) => assert_eq!(pattern.to_string(), shown, "{}", s),
+            (Err(e), Err(start)) => assert!(e.starts_with(start), "{}: {}", s, e),
+            (result, _) => panic!("{}: {:?}", s, result),
+        }
+    }
+}
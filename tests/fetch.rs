@@ -0,0 +1,126 @@
+#![cfg(feature = "http")]
+
+use std::{fs, io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+
+use rust_serial_tool::{ErrorKind, fetch::{self, Url}, sha256::{self, Sha256}};
+
+/// Answers one request per response, in order, and hands back the request lines it saw.
+fn serve(responses: Vec<Vec<u8>>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            requests.push(line.trim_end().to_string());
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            let _ = stream.write_all(&response);
+        }
+        requests
+    });
+    (base, handle)
+}
+
+fn response(head: &str, body: &[u8]) -> Vec<u8> {
+    [format!("HTTP/1.1 {}\r\n\r\n", head).as_bytes(), body].concat()
+}
+
+fn get(url: &str) -> Result<(Vec<u8>, [u8; 32]), ErrorKind> {
+    let mut body = Vec::new();
+    let digest = fetch::download(&url.parse().unwrap(), &mut body, |_, _| {})?;
+    Ok((body, digest))
+}
+
+#[test]
+fn parses_urls() {
+    let cases = [
+        ("http://lab:8080/ci/kernel8.img", Url { https: false, host: "lab".into(), port: 8080, path: "/ci/kernel8.img".into() }),
+        ("HTTPS://artifacts.example", Url { https: true, host: "artifacts.example".into(), port: 443, path: "/".into() }),
+        ("http://ci@[::1]/a?b=1#top", Url { https: false, host: "::1".into(), port: 80, path: "/a?b=1".into() }),
+        ("http://host?x", Url { https: false, host: "host".into(), port: 80, path: "/?x".into() }),
+    ];
+    for (s, url) in cases {
+        assert_eq!(s.parse::<Url>().unwrap(), url, "{}", s);
+    }
+    assert_eq!("http://[::1]:81/x".parse::<Url>().unwrap().to_string(), "http://[::1]:81/x");
+    assert_eq!("http://lab:8080/ci/kernel8.img".parse::<Url>().unwrap().file_name(), "kernel8.img");
+    for bad in ["ftp://host/x", "kernel8.img", "http://:80/", "http://host:port/"] {
+        assert!(bad.parse::<Url>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn downloads_with_or_without_a_length() {
+    let body = b"kernel image".to_vec();
+    let (base, server) = serve(vec![
+        response(&format!("200 OK\r\nContent-Length: {}", body.len()), &body),
+        // no length: the body ends with the connection
+        response("200 OK", &body),
+        response("200 OK\r\nTransfer-Encoding: chunked", b"6;ext=1\r\nkernel\r\n6\r\n image\r\n0\r\nX-Trailer: 1\r\n\r\n"),
+    ]);
+    for _ in 0..3 {
+        let (got, digest) = get(&format!("{}/k.img", base)).unwrap();
+        assert_eq!(got, body);
+        assert_eq!(digest, Sha256::digest(&body));
+    }
+    assert_eq!(server.join().unwrap(), ["GET /k.img HTTP/1.1"; 3]);
+}
+
+#[test]
+fn follows_redirects() {
+    let (base, server) = serve(vec![
+        response("302 Found\r\nLocation: /ci/latest", b""),
+        response("301 Moved Permanently\r\nLocation: kernel8.img", b""),
+        response("200 OK\r\nContent-Length: 2", b"ok"),
+    ]);
+    assert_eq!(get(&format!("{}/k.img", base)).unwrap().0, b"ok");
+    assert_eq!(server.join().unwrap(), ["GET /k.img HTTP/1.1", "GET /ci/latest HTTP/1.1", "GET /ci/kernel8.img HTTP/1.1"]);
+
+    let loop_forever = vec![response("307 Temporary Redirect\r\nLocation: /again", b""); fetch::MAX_REDIRECTS as usize + 1];
+    let (base, _) = serve(loop_forever);
+    let error = get(&format!("{}/again", base)).unwrap_err();
+    assert!(error.to_string().contains("redirects"), "{}", error);
+}
+
+#[test]
+fn download_errors_are_network_errors() {
+    let (base, _) = serve(vec![
+        response("404 Not Found", b""),
+        response("200 OK\r\nContent-Length: 100", b"short"),
+        response("500 Internal Server Error", b""),
+    ]);
+    let expected = ["HTTP 404", "after 5 bytes", "HTTP 500"];
+    for expected in expected {
+        let error = get(&format!("{}/k.img", base)).unwrap_err();
+        assert!(matches!(error, ErrorKind::NetworkError(_)), "{:?}", error);
+        assert_eq!(error.name(), "network");
+        assert!(error.to_string().contains(expected), "{}", error);
+    }
+}
+
+#[test]
+fn fetches_into_a_temp_file() {
+    let (base, _) = serve(vec![response("200 OK", b"abc"), response("200 OK", b"abd")]);
+    let abc = sha256::parse("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad").unwrap();
+    let url: Url = format!("{}/ci/kernel8.img", base).parse().unwrap();
+
+    let image = fetch::fetch(&url, Some(abc), |_, _| {}).unwrap();
+    assert_eq!(image.size, 3);
+    assert!(image.path().to_string_lossy().ends_with("kernel8.img"));
+    #[cfg(unix)]
+    assert_eq!(std::os::unix::fs::MetadataExt::mode(&fs::metadata(image.path()).unwrap()) & 0o777, 0o600);
+    assert_eq!(fs::read(image.path()).unwrap(), b"abc");
+    let path = image.path().to_path_buf();
+    drop(image);
+    assert!(!path.exists());
+
+    let error = fetch::fetch(&url, Some(abc), |_, _| {}).unwrap_err();
+    assert!(error.to_string().contains("does not match"), "{}", error);
+    assert!(!path.exists(), "a rejected download is removed");
+}
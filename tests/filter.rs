@@ -0,0 +1,148 @@
+use rust_serial_tool::filter::{CollapseOverwrites, FilterChain, FilterKind, OutputFilter, StripEscapes};
+use rust_serial_tool::terminal::MAX_LINE;
+
+fn collapse(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut filter = CollapseOverwrites::default();
+    let mut out = Vec::new();
+    pieces.iter().for_each(|piece| filter.filter(piece, &mut out));
+    filter.finish(&mut out);
+    out
+}
+
+#[test]
+fn collapses_carriage_returns_and_backspaces() {
+    let cases: &[(&[u8], &[u8])] = &[
+        (b"plain line\r\n", b"plain line\r\n"),
+        (b"unix line\n", b"unix line\n"),
+        // a counter redrawn in place
+        (b"Erasing  10%\rErasing  55%\rErasing 100%\r\n", b"Erasing 100%\r\n"),
+        // a shorter overwrite leaves the rest of the longer line
+        (b"loading......\rdone\n", b"doneing......\n"),
+        (b"12345\x08\x08ab\n", b"123ab\n"),
+        // backspaces past the start stop there
+        (b"ab\x08\x08\x08\x08x\n", b"xb\n"),
+        // the classic erase, a space over the character
+        (b"rebooot\x08 \x08\x08t\n", b"reboot \n"),
+        // a spinner between two lines
+        (b"boot\r\n|\x08/\x08-\x08\\\x08ok\r\n", b"boot\r\nok\r\n"),
+        (b"\r\r\nCR CR LF\r\n", b"\r\nCR CR LF\r\n"),
+        // erased to the end of the line, or all of it
+        (b"line 1000 of 1000\r\x1b[Kdone\n", b"done\n"),
+        (b"abcdef\x08\x08\x08\x1b[0Kxy\n", b"abcxy\n"),
+        (b"abc\x1b[2Kd\n", b"   d\n"),
+        // a character takes one cell however long its UTF-8
+        (b"\xc3\xa9t\xc3\xa9\r\xe2\x82\xac\n", b"\xe2\x82\xact\xc3\xa9\n"),
+        (b"\xf0\x9f\x98\x80x\x08y\n", b"\xf0\x9f\x98\x80y\n"),
+        // and so does a color code, which an overwrite replaces
+        (b"\x1b[32mok\x1b[0m\rno\n", b"nok\x1b[0m\n"),
+        // a sequence broken off by a control is kept like one
+        (b"a\x1b[3\rb\n", b"b\x1b[3\n"),
+        // the last line, never ended, comes out at the end
+        (b"count 1\rcount 2", b"count 2"),
+        (b"prompt> \r", b"prompt> "),
+    ];
+    for (input, collapsed) in cases {
+        assert_eq!(collapse(&[input]), *collapsed, "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn the_same_at_every_split_between_reads() {
+    let inputs: &[&[u8]] = &[
+        b"Erasing  10%\rErasing  55%\rErasing 100%\r\nnext\n",
+        b"ab\r\ncd\x08e\r\n\xc3\xa9x\x08\x08\xe2\x82\xac\r\n",
+        b"x\r\x1b[K\x1b[1;31merror\x1b[0m\r\n\r",
+    ];
+    for input in inputs {
+        let whole = collapse(&[input]);
+        for split in 0..=input.len() {
+            assert_eq!(collapse(&[&input[..split], &input[split..]]), whole, "{:?} split at {}", String::from_utf8_lossy(input), split);
+        }
+        // a byte at a time
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(collapse(&bytes), whole, "{:?} a byte at a time", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn holds_a_line_back_until_it_ends() {
+    let mut filter = CollapseOverwrites::default();
+    let mut out = Vec::new();
+    filter.filter(b"10%\r20%\r", &mut out);
+    assert!(out.is_empty());
+    // a CR at the end of a read may be half of a CRLF
+    filter.filter(b"30%\r", &mut out);
+    filter.filter(b"\nnext", &mut out);
+    assert_eq!(out, b"30%\r\n");
+    // a line too long to wait for comes out as it is
+    let long = vec![b'x'; MAX_LINE + 10];
+    out.clear();
+    filter.filter(&long, &mut out);
+    assert_eq!(out.len(), MAX_LINE);
+    assert_eq!(&out[..4], b"next");
+}
+
+/// Upper-cases what goes through, to tell the order of a chain.
+struct Upper;
+
+impl OutputFilter for Upper {
+    fn filter(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        out.extend(input.to_ascii_uppercase());
+    }
+}
+
+/// Holds everything back until the end.
+#[derive(Default)]
+struct Hold(Vec<u8>);
+
+impl OutputFilter for Hold {
+    fn filter(&mut self, input: &[u8], _out: &mut Vec<u8>) {
+        self.0.extend_from_slice(input);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.0);
+    }
+}
+
+#[test]
+fn chains_filters_in_order() {
+    assert!(FilterChain::new(&[]).is_empty());
+    assert_eq!(FilterChain::default().apply(b"a\rb\n"), b"a\rb\n");
+
+    let mut chain = FilterChain::new(&[FilterKind::CollapseOverwrites]);
+    chain.push(Box::new(Upper));
+    assert_eq!(chain.apply(b"one\rtwo\r\nthr"), b"TWO\r\n");
+    assert_eq!(chain.apply(b"ee\x08E"), b"");
+    assert_eq!(chain.drain(), b"THREE");
+
+    // what one holds back until the end still goes through the ones after it
+    let mut chain = FilterChain::default();
+    chain.push(Box::<Hold>::default());
+    chain.push(Box::new(CollapseOverwrites::default()));
+    assert_eq!(chain.apply(b"1\r2\r3\r\n4\r5"), b"");
+    assert_eq!(chain.drain(), b"3\r\n5");
+    assert_eq!(chain.drain(), b"");
+}
+
+#[test]
+fn strips_escapes_cut_off_between_reads() {
+    let mut strip = StripEscapes::default();
+    let mut out = Vec::new();
+    for piece in [&b"\x1b[1;31merr\x1b[0m: \x1b]0;t\x07x\x00\r\n\x1b["[..], b"2Kd\xc3", b"\xb6ne\x1b"] {
+        strip.filter(piece, &mut out);
+    }
+    assert_eq!(out, "err: x^@\r\ndöne".as_bytes());
+    strip.finish(&mut out);
+    assert_eq!(out, "err: x^@\r\ndöne".as_bytes());
+}
+
+#[test]
+fn filters_by_name() {
+    for name in ["collapse-overwrites", "Collapse"] {
+        assert_eq!(name.parse(), Ok(FilterKind::CollapseOverwrites));
+    }
+    assert_eq!("strip-escapes".parse(), Ok(FilterKind::StripEscapes));
+    assert!("strip".parse::<FilterKind>().is_err());
+    assert_eq!(FilterKind::CollapseOverwrites.to_string(), "collapse-overwrites");
+}
@@ -0,0 +1,83 @@
+use std::{fs, thread, time::Duration};
+
+use rust_serial_tool::{ErrorKind, fleet::{self, BoardResult}};
+
+#[test]
+fn matches_wildcards() {
+    let cases = [
+        ("ttyUSB*", "ttyUSB0", true),
+        ("ttyUSB*", "ttyUSB12", true),
+        ("ttyUSB*", "ttyACM0", false),
+        ("ttyUSB?", "ttyUSB1", true),
+        ("ttyUSB?", "ttyUSB10", false),
+        ("tty*0", "ttyUSB10", true),
+        ("*", "", true),
+        ("a*b*c", "aXXbYbZc", true),
+        ("a*b*c", "aXXbYbZ", false),
+    ];
+    for (pattern, name, expected) in cases {
+        assert_eq!(fleet::wildcard(pattern, name), expected, "{} {}", pattern, name);
+    }
+}
+
+#[test]
+fn expands_lists_and_globs() {
+    let dir = std::env::temp_dir().join(format!("fleet-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["ttyUSB1", "ttyUSB0", "ttyACM0"] { fs::write(dir.join(name), b"").unwrap(); }
+    let dir = dir.display();
+
+    let cases = [
+        (format!("{}/ttyUSB*", dir), Ok(vec![format!("{}/ttyUSB0", dir), format!("{}/ttyUSB1", dir)])),
+        (format!("{0}/ttyACM0, {0}/ttyUSB?,{0}/ttyUSB0", dir),
+         Ok(vec![format!("{}/ttyACM0", dir), format!("{}/ttyUSB0", dir), format!("{}/ttyUSB1", dir)])),
+        ("tcp://rack:4001,CP210x".to_string(), Ok(vec!["tcp://rack:4001".to_string(), "CP210x".to_string()])),
+        (format!("{}/ttyS*", dir), Err("no device matches")),
+        ("/dev/*/ttyUSB0".to_string(), Err("/dev/*/ttyUSB0: only the last component")),
+        ("/dev/ttyUSB0,".to_string(), Err("empty device")),
+    ];
+    for (spec, expected) in cases {
+        match (fleet::expand(&spec), expected) {
+            (Ok(boards), Ok(expected)) => assert_eq!(boards, expected, "{}", spec),
+            (Err(e), Err(start)) => assert!(e.starts_with(start), "{}: {}", spec, e),
+            (result, _) => panic!("{}: {:?}", spec, result),
+        }
+    }
+    assert_eq!(fleet::board_name("/dev/ttyUSB3"), "ttyUSB3");
+    assert_eq!(fleet::board_name("tcp://rack:4001"), "rack:4001");
+    assert_eq!(fleet::board_name("COM3"), "COM3");
+}
+
+#[test]
+fn one_failure_leaves_the_others() {
+    let boards: Vec<String> = (0..4).map(|i| format!("/dev/ttyUSB{}", i)).collect();
+    let results = fleet::push_all(&boards, |device| {
+        thread::sleep(Duration::from_millis(20));
+        match device {
+            "/dev/ttyUSB1" => Err(ErrorKind::TimeoutError.context("handshake", device)),
+            "/dev/ttyUSB2" => panic!("boom"),
+            _ => Ok(()),
+        }
+    });
+    let outcomes: Vec<(&str, Option<&str>)> = results.iter().map(|result| (result.device.as_str(), result.error.as_deref())).collect();
+    assert_eq!(outcomes, [
+        ("/dev/ttyUSB0", None),
+        ("/dev/ttyUSB1", Some("TimeoutError during handshake")),
+        ("/dev/ttyUSB2", Some("panicked")),
+        ("/dev/ttyUSB3", None),
+    ]);
+    assert!(results.iter().all(|result| result.elapsed >= Duration::from_millis(20)));
+}
+
+#[test]
+fn sums_up_the_boards() {
+    let results = [
+        BoardResult { device: "/dev/ttyUSB0".to_string(), error: None, elapsed: Duration::from_secs(41) },
+        BoardResult { device: "/dev/ttyUSB1".to_string(), error: Some("TimeoutError during handshake".to_string()), elapsed: Duration::from_secs(10) },
+    ];
+    assert_eq!(fleet::summary(&results), [
+        "board         result     time",
+        "/dev/ttyUSB0  ok          41s",
+        "/dev/ttyUSB1  failed      10s  TimeoutError during handshake",
+    ]);
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use rust_serial_tool::formats::{Format, parse_ihex, parse_srec};
+
+#[test]
+fn ihex_images() {
+    let cases: &[(&str, &str, u8, &[u8])] = &[
+        ("single record", ":0400000001020304F2\n:00000001FF", 0xFF, &[1, 2, 3, 4]),
+        ("gap filled 0xff", ":0400000001020304F2\n:020006000708E9\n:00000001FF", 0xFF, &[1, 2, 3, 4, 0xFF, 0xFF, 7, 8]),
+        ("gap filled 0x00", ":0400000001020304F2\n:020006000708E9\n:00000001FF", 0x00, &[1, 2, 3, 4, 0, 0, 7, 8]),
+        ("starts at lowest address", ":020006000708E9\n:00000001FF", 0xFF, &[7, 8]),
+        ("extended linear address", ":020000040001F9\n:02001000AABB89\n:00000001FF", 0xFF, &[0xAA, 0xBB]),
+        ("extended segment address", ":020000021000EC\n:02001000AABB89\n:00000001FF", 0xFF, &[0xAA, 0xBB]),
+        ("crlf and blank lines", ":0400000001020304F2\r\n\r\n:00000001FF\r\n", 0xFF, &[1, 2, 3, 4]),
+        ("records after eof ignored", ":00000001FF\n:0400000001020304F2", 0xFF, &[]),
+    ];
+    for (name, text, fill, expected) in cases {
+        assert_eq!(parse_ihex(text, *fill).as_deref(), Ok(*expected), "{}", name);
+    }
+}
+
+#[test]
+fn ihex_errors_report_line() {
+    let cases: &[(&str, &str, usize, &str)] = &[
+        ("bad checksum", ":0400000001020304F2\n:020006000708E8", 2, "checksum mismatch"),
+        ("missing colon", "0400000001020304F2", 1, "does not start"),
+        ("length mismatch", ":0500000001020304F1", 1, "length field"),
+        ("not hex", ":04000000010203GGF2", 1, "invalid hex"),
+        ("odd digits", "\n\n:0400000001020304F", 3, "odd number"),
+        ("address record too long", ":03000004000100F8", 1, "record type 04 carries 3 data bytes"),
+    ];
+    for (name, text, line, reason) in cases {
+        let err = parse_ihex(text, 0xFF).unwrap_err();
+        assert_eq!(err.line, *line, "{}", name);
+        assert!(err.reason.contains(reason), "{}: {}", name, err);
+    }
+}
+
+#[test]
+fn srec_images() {
+    let cases: &[(&str, &str, u8, &[u8])] = &[
+        ("s1 with header", "S00600004844521B\nS10510000102E7\nS9031000EC", 0xFF, &[1, 2]),
+        ("gap filled 0xff", "S10510000102E7\nS104100405E2\nS9031000EC", 0xFF, &[1, 2, 0xFF, 0xFF, 5]),
+        ("gap filled 0x00", "S10510000102E7\nS104100405E2\nS9031000EC", 0x00, &[1, 2, 0, 0, 5]),
+        ("s3 32-bit address", "S30780000000DEADED\nS70500000000FA", 0xFF, &[0xDE, 0xAD]),
+    ];
+    for (name, text, fill, expected) in cases {
+        assert_eq!(parse_srec(text, *fill).as_deref(), Ok(*expected), "{}", name);
+    }
+}
+
+#[test]
+fn srec_errors_report_line() {
+    let cases: &[(&str, &str, usize, &str)] = &[
+        ("bad checksum", "S00600004844521B\nS10510000102E8", 2, "checksum mismatch"),
+        ("count mismatch", "S10610000102E7", 1, "byte count"),
+        ("unknown type", "S4031000EC", 1, "unsupported record type"),
+        ("missing S", "10510000102E7", 1, "does not start"),
+    ];
+    for (name, text, line, reason) in cases {
+        let err = parse_srec(text, 0xFF).unwrap_err();
+        assert_eq!(err.line, *line, "{}", name);
+        assert!(err.reason.contains(reason), "{}: {}", name, err);
+    }
+}
+
+#[test]
+fn format_detection() {
+    let cases: &[(&str, &[u8], Format)] = &[
+        ("kernel8.img", b"\x00\x00\xa0\xe3", Format::Binary),
+        ("kernel.hex", b"", Format::IntelHex),
+        ("kernel.HEX", b"", Format::IntelHex),
+        ("kernel.s19", b"", Format::Srec),
+        ("kernel.srec", b"", Format::Srec),
+        ("kernel", b":0400000001020304F2\n", Format::IntelHex),
+        ("kernel", b"S00600004844521B\r\n", Format::Srec),
+        ("kernel", b":not a hex record", Format::Binary),
+        ("kernel", b"Some text", Format::Binary),
+    ];
+    for (path, head, expected) in cases {
+        assert_eq!(Format::detect(Path::new(path), head), *expected, "{}", path);
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use crossterm::style::Color;
+use rust_serial_tool::{highlight::*, terminal::LineBuffer};
+
+fn highlighter(rules: &[&str]) -> Highlighter {
+    Highlighter::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+}
+
+#[test]
+fn parses_rules() {
+    let cases = [
+        ("ERROR", "ERROR", DEFAULT_COLOR),
+        ("red:panic", "panic", Color::Red),
+        ("Cyan:\\[drv\\]", "\\[drv\\]", Color::Cyan),
+        ("(?:WARN|ERR): .*", "(?:WARN|ERR): .*", DEFAULT_COLOR),
+        ("fault:addr", "fault:addr", DEFAULT_COLOR),
+    ];
+    for (arg, regex, color) in cases.iter() {
+        let rule: Highlight = arg.parse().unwrap();
+        assert_eq!((rule.regex.as_str(), rule.color), (*regex, *color), "{}", arg);
+    }
+    for arg in ["(unclosed", "red:", "green:[a-"].iter() {
+        assert!(arg.parse::<Highlight>().is_err(), "{}", arg);
+    }
+}
+
+#[test]
+fn first_rule_wins_overlaps() {
+    let h = highlighter(&["red:ERROR", "green:OR: disk", "blue:disk"]);
+    assert_eq!(h.spans("ERROR: disk full"), [(0, 5, Color::Red), (7, 11, Color::Blue)]);
+    assert!(h.spans("all good").is_empty());
+    assert_eq!(h.paint("all good"), "all good");
+    assert!(h.paint("ERROR").contains("ERROR") && h.paint("ERROR") != "ERROR");
+}
+
+#[test]
+fn lines_across_reads_come_out_once() {
+    let start = Instant::now();
+    let mut lines = LineBuffer::new(Duration::from_millis(100));
+    assert!(lines.push(b"boot: ER", start).is_empty());
+    assert_eq!(lines.push(b"ROR\nok\nlogin", start), [b"boot: ERROR\n".to_vec(), b"ok\n".to_vec()]);
+
+    // the prompt shows up once the target goes quiet, and only once
+    assert_eq!(lines.flush_idle(start + Duration::from_millis(50)), None);
+    assert_eq!(lines.flush_idle(start + Duration::from_millis(100)), Some(b"login".to_vec()));
+    assert_eq!(lines.flush_idle(start + Duration::from_secs(1)), None);
+    assert_eq!(lines.push(b": \n", start + Duration::from_secs(1)), [b": \n".to_vec()]);
+}
+
+#[test]
+fn a_prompt_shows_once_the_target_pauses() {
+    let start = Instant::now();
+    let ms = |ms| start + Duration::from_millis(ms);
+    let mut lines = LineBuffer::new(Duration::from_millis(50));
+    assert!(lines.push(b"Password", ms(0)).is_empty());
+    // the pause counts from the last byte, not from the start of the line
+    assert!(lines.push(b": ", ms(40)).is_empty());
+    assert_eq!(lines.flush_idle(ms(60)), None);
+    assert_eq!(lines.flush_idle(ms(90)), Some(b"Password: ".to_vec()));
+    assert_eq!(lines.flush_idle(ms(200)), None);
+}
+
+#[test]
+fn what_comes_after_a_pause_continues_the_line() {
+    let start = Instant::now();
+    let ms = |ms| start + Duration::from_millis(ms);
+    let mut lines = LineBuffer::new(Duration::from_millis(50));
+    assert!(lines.push(b"Erasing", ms(0)).is_empty());
+    assert_eq!(lines.flush_idle(ms(50)), Some(b"Erasing".to_vec()));
+    assert!(lines.continues());
+    assert!(lines.push(b"...", ms(300)).is_empty());
+    assert_eq!(lines.flush_idle(ms(350)), Some(b"...".to_vec()));
+    assert!(lines.continues());
+    assert_eq!(lines.push(b" done\nnext", ms(600)), [b" done\n".to_vec()]);
+    assert!(!lines.continues());
+}
+
+#[test]
+fn a_steady_stream_is_never_cut() {
+    let start = Instant::now();
+    let mut lines = LineBuffer::new(Duration::from_millis(50));
+    // a byte every 10 ms for a second: slow, but never a pause
+    for i in 0..100 {
+        let now = start + Duration::from_millis(i * 10);
+        assert!(lines.push(b".", now).is_empty());
+        assert_eq!(lines.flush_idle(now + Duration::from_millis(9)), None);
+    }
+    let line = lines.push(b"\n", start + Duration::from_secs(1));
+    assert_eq!(line, [[vec![b'.'; 100], b"\n".to_vec()].concat()]);
+    assert!(!lines.continues());
+}
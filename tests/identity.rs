@@ -0,0 +1,82 @@
+#![cfg(feature = "mock")]
+
+use rust_serial_tool::{ErrorKind, identity::{self, IDENTIFY_TIMEOUT, IDENTITY_PROBE, TargetIdentity}, mock::MockSerial, protocol::{send_size, SizeHeader}};
+
+const BOARD: &str = "serial=00000000a1b2c3d4 mac=dc:a6:32:01:02:03 loader=4f1c2e9";
+
+#[test]
+fn asks_loaders_of_each_vintage() {
+    let cases: [(MockSerial, Option<&str>); 3] = [
+        // a loader that doesn't know the probe stays silent and gets the size right after
+        (MockSerial::new().expect(&IDENTITY_PROBE), None),
+        (MockSerial::new().expect(&IDENTITY_PROBE).reply(&identity::encode(BOARD)), Some(BOARD)),
+        (MockSerial::new().expect(&IDENTITY_PROBE).reply(b"MPI\x00"), Some("")),
+    ];
+    for (mock, expected) in cases {
+        let mock = mock.expect(&16u32.to_le_bytes()).reply(b"OK");
+        let mut port = mock.clone();
+        let identity = identity::identify(&mut port, IDENTIFY_TIMEOUT).unwrap();
+        assert_eq!(identity.as_ref().map(|identity| identity.text.as_str()), expected);
+        send_size(&mut port, 16, SizeHeader::Legacy).unwrap();
+        mock.assert_done();
+    }
+
+    // something other than an answer, and an answer cut short
+    let mut port = MockSerial::new().expect(&IDENTITY_PROBE).reply(b"OK");
+    match identity::identify(&mut port, IDENTIFY_TIMEOUT) {
+        Err(ErrorKind::UnexpectedReply { received, .. }) => assert_eq!(received, b"OK"),
+        other => panic!("expected UnexpectedReply, got {:?}", other),
+    }
+    let mut port = MockSerial::new().expect(&IDENTITY_PROBE).reply(b"MPI\x10serial=");
+    match identity::identify(&mut port, IDENTIFY_TIMEOUT) {
+        Err(ErrorKind::ReadTimeout { received, expected }) => assert_eq!((received, expected), (7, 16)),
+        other => panic!("expected ReadTimeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn parses_the_identity() {
+    let identity = TargetIdentity::parse(&format!(" {}\r\n", BOARD));
+    assert_eq!(identity.text, BOARD);
+    assert_eq!(identity.get("mac"), Some("dc:a6:32:01:02:03"));
+    assert_eq!(identity.get("loader"), Some("4f1c2e9"));
+    assert_eq!(identity.get("board"), None);
+    assert!(TargetIdentity::parse("bench-3").fields.is_empty());
+
+    // longer than the length byte holds: cut, and not in the middle of a character
+    let long = format!("name={}", "é".repeat(200));
+    let reply = identity::encode(&long);
+    assert_eq!(reply[3] as usize, reply.len() - 4);
+    assert_eq!(reply[3], 255);
+    assert!(std::str::from_utf8(&reply[4..]).is_ok());
+}
+
+#[test]
+fn pushes_only_to_the_expected_target() {
+    let board = TargetIdentity::parse(BOARD);
+    let cases = [
+        ("serial=00000000a1b2c3d4", true),
+        ("00000000A1B2C3D4", true),
+        ("DC:A6:32:01:02:03", true),
+        ("mac=dc:a6:32:01:02:03", true),
+        (BOARD, true),
+        ("serial=00000000a1b2c3d5", false),
+        // the value, but of another field
+        ("mac=00000000a1b2c3d4", false),
+        ("00000000a1b2", false),
+        ("serial", false),
+    ];
+    for (expected, matches) in cases {
+        assert_eq!(board.matches(expected), matches, "{}", expected);
+        assert_eq!(identity::check(Some(&board), expected).is_ok(), matches, "{}", expected);
+    }
+    assert!(TargetIdentity::parse("bench-3").matches("bench-3"));
+
+    match identity::check(Some(&board), "serial=0000000011111111") {
+        Err(ErrorKind::WrongTarget { identity, .. }) => assert_eq!(identity.as_deref(), Some(BOARD)),
+        other => panic!("expected WrongTarget, got {:?}", other),
+    }
+    // one that didn't say can't be told to be the right one
+    let silent = identity::check(None, "serial=00000000a1b2c3d4").unwrap_err();
+    assert!(silent.to_string().contains("did not say"), "{}", silent);
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use rust_serial_tool::idle::{IdleAction, UtcTime, Watchdog};
+
+#[test]
+fn parses_actions() {
+    let cases = [
+        ("warn", Ok(IdleAction::Warn)),
+        ("bell", Ok(IdleAction::Bell)),
+        ("exit", Ok(IdleAction::Exit(1))),
+        ("exit:2", Ok(IdleAction::Exit(2))),
+        ("exec:notify-send \"$SERIAL_TOOL_LINE\"", Ok(IdleAction::Exec("notify-send \"$SERIAL_TOOL_LINE\"".to_string()))),
+        ("exec:a:b", Ok(IdleAction::Exec("a:b".to_string()))),
+        ("exit:two", Err("invalid exit code \"two\"")),
+        ("exec:", Err("empty command")),
+        ("beep", Err("expected warn, bell, exec:CMD or exit[:CODE]")),
+    ];
+    for (s, expected) in cases {
+        assert_eq!(s.parse::<IdleAction>(), expected.map_err(str::to_string), "{}", s);
+    }
+}
+
+#[test]
+fn fires_once_per_silence() {
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let mut watchdog = Watchdog::new(Duration::from_secs(60), start);
+
+    // (when, what happened: a byte or a look, what the watchdog says)
+    let steps = [
+        (30, false, None),
+        (59, false, None),
+        (60, false, Some(60)),
+        // not again for the same silence
+        (61, false, None),
+        (600, false, None),
+        (700, true, Some(700)),
+        (701, false, None),
+        // bytes keep it quiet
+        (740, true, None),
+        (790, true, None),
+        (849, false, None),
+        (850, false, Some(60)),
+        (851, true, Some(61)),
+    ];
+    for (secs, rx, expected) in steps {
+        let said = if rx { watchdog.rx(at(secs)) } else { watchdog.check(at(secs)) };
+        assert_eq!(said, expected.map(Duration::from_secs), "at {}s", secs);
+    }
+}
+
+#[test]
+fn a_reconnect_starts_over() {
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let mut watchdog = Watchdog::new(Duration::from_secs(10), start);
+    // gone from 5s, back at 30s: the time away doesn't count
+    watchdog.restart(at(30));
+    assert_eq!(watchdog.check(at(39)), None);
+    assert_eq!(watchdog.check(at(40)), Some(Duration::from_secs(10)));
+
+    // and a silence that fired before the disconnect isn't reported as ending
+    watchdog.restart(at(50));
+    assert_eq!(watchdog.rx(at(51)), None);
+}
+
+#[test]
+fn times_of_day_are_utc() {
+    let cases = [(0, "00:00:00 UTC"), (3 * 3600 + 14 * 60 + 7, "03:14:07 UTC"), (86_400 * 20_000 + 86_399, "23:59:59 UTC")];
+    for (secs, expected) in cases {
+        assert_eq!(UtcTime(UNIX_EPOCH + Duration::from_secs(secs)).to_string(), expected);
+    }
+}
@@ -0,0 +1,245 @@
+use std::{fs::{self, File}, io::{self, Read, Seek, SeekFrom}, thread, time::{Duration, Instant, SystemTime}};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use rust_serial_tool::{formats::Format, image::{self, Chunks, Image, ReadAhead}};
+
+fn synthetic(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 4096) as u8).collect()
+}
+
+/// Every chunk of `image` from `offset`, checked to be full but for the last.
+fn push(image: &mut Image, chunk_size: usize, offset: u64) -> Vec<u8> {
+    image.source.seek(SeekFrom::Start(offset)).unwrap();
+    let mut chunks = Chunks::new(chunk_size);
+    let mut pushed = Vec::new();
+    let mut short = false;
+    loop {
+        let chunk = chunks.next(&mut image.source).unwrap();
+        if chunk.is_empty() { break; }
+        assert!(!short, "short chunk before the end");
+        short = chunk.len() < chunk_size;
+        pushed.extend_from_slice(chunk);
+    }
+    pushed
+}
+
+#[test]
+fn chunks_a_multi_megabyte_file() {
+    let path = std::env::temp_dir().join(format!("chunks-{}.img", std::process::id()));
+    // not a multiple of any chunk size below, so the last chunk is partial
+    let data = synthetic((24 << 20) + 123);
+    fs::write(&path, &data).unwrap();
+
+    for chunk_size in [512, 4096, 65535] {
+        let mut image = Image::from_file(File::open(&path).unwrap()).unwrap();
+        let pushed = push(&mut image, chunk_size, 0);
+        assert!(pushed == data, "chunks of {}", chunk_size);
+    }
+
+    // resuming seeks past the buffered read-ahead
+    let mut image = Image::from_file(File::open(&path).unwrap()).unwrap();
+    push(&mut image, 512, 0);
+    assert!(push(&mut image, 512, 3 << 20) == data[3 << 20..]);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn chunks_small_and_empty_images() {
+    let cases: [(usize, usize); 4] = [(0, 512), (1, 512), (512, 512), (1000, 512)];
+    for (len, chunk_size) in cases {
+        let data = synthetic(len);
+        let mut image = Image::from_bytes(data.clone());
+        assert_eq!(push(&mut image, chunk_size, 0), data, "{} bytes", len);
+    }
+}
+
+#[test]
+fn spools_a_piped_image() {
+    let data = synthetic(5000);
+    assert_eq!(image::spool(&data[..], 5000).unwrap(), data);
+    assert!(image::spool(&data[..], 4999).is_err());
+    assert!(image::spool(&[][..], 0).unwrap().is_empty());
+
+    // the format comes from the content, there being no file name
+    let image = Image::from_data(data.clone(), 0xff).unwrap();
+    assert_eq!((image.size, image.format), (5000, Format::Binary));
+    let image = Image::from_data(b":03000000616263D7\n:00000001FF\n".to_vec(), 0xff).unwrap();
+    assert_eq!((image.size, image.format), (3, Format::IntelHex));
+}
+
+#[test]
+fn checks_the_image_upfront() {
+    let dir = std::env::temp_dir().join(format!("image-check-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (full, empty) = (dir.join("kernel8.img"), dir.join("empty.img"));
+    fs::write(&full, b"\x00\x01\x02").unwrap();
+    fs::write(&empty, b"").unwrap();
+
+    assert_eq!(image::check(&full).unwrap(), 3);
+    let cases = [(empty.clone(), "is empty"), (dir.join("missing.img"), "No such file"), (dir.clone(), "is a directory")];
+    for (bad, reason) in cases {
+        let said = image::check(&bad).unwrap_err().to_string();
+        assert!(said.starts_with(&format!("{}: {}", bad.display(), reason)), "{}", said);
+    }
+
+    // (size, limit, what is wrong)
+    let cases = [(0, Some(image::MAX_SIZE), Some("the image: is empty")), (3, Some(image::MAX_SIZE), None),
+                 (200 << 20, Some(image::MAX_SIZE), Some("the image is 200.0 MiB, more than the 128.0 MiB expected")),
+                 (200 << 20, None, None), (0, None, Some("the image: is empty"))];
+    for (size, max, wrong) in cases {
+        let said = image::check_size(size, max).err().map(|e| e.to_string());
+        assert_eq!(said.is_some(), wrong.is_some(), "{} of {:?}: {:?}", size, max, said);
+        if let (Some(said), Some(wrong)) = (said, wrong) { assert!(said.starts_with(wrong), "{}", said); }
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn waits_for_an_image_being_rebuilt() {
+    let path = std::env::temp_dir().join(format!("image-settle-{}.img", std::process::id()));
+    fs::write(&path, b"").unwrap();
+    assert!(image::check_settled(&path, Duration::ZERO).is_err());
+
+    // truncated by the build, written again a moment later
+    let rebuilt = path.clone();
+    let build = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        fs::write(&rebuilt, b"kernel").unwrap();
+    });
+    let started = Instant::now();
+    assert_eq!(image::check_settled(&path, image::SETTLE_WAIT).unwrap(), 6);
+    assert!(started.elapsed() < image::SETTLE_WAIT);
+    build.join().unwrap();
+
+    // a directory is no file being written, nothing to wait for
+    let started = Instant::now();
+    assert!(image::check_settled(std::env::temp_dir(), image::SETTLE_WAIT).is_err());
+    assert!(started.elapsed() < Duration::from_millis(100));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn picks_the_newest_artifact() {
+    let dir = std::env::temp_dir().join(format!("image-newest-{}", std::process::id()));
+    fs::create_dir_all(dir.join("kernel8-dir.img")).unwrap();
+    let pattern = format!("{}/kernel8-*.img", dir.display());
+    let built = |name: &str, age: u64| {
+        let path = dir.join(name);
+        fs::write(&path, name).unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+    };
+    built("kernel8-20240517-143012.img", 60);
+    built("kernel8-20240517 150001.img", 10);
+    built("kernel8-20240516-090000.img", 3600);
+    built("kernel8-latest.elf", 0);
+    assert_eq!(image::newest_match(&pattern).unwrap(), dir.join("kernel8-20240517 150001.img"));
+
+    // the next build is the one picked
+    built("kernel8-20240517-160000.img", 0);
+    assert_eq!(image::newest_match(&pattern).unwrap(), dir.join("kernel8-20240517-160000.img"));
+
+    let missing = image::newest_match(&format!("{}/zImage-*", dir.display())).unwrap_err().to_string();
+    assert!(missing.contains("matches nothing in"), "{}", missing);
+    assert!(missing.contains("kernel8-20240516-090000.img, kernel8-20240517 150001.img"), "{}", missing);
+    assert!(missing.contains("kernel8-latest.elf"), "{}", missing);
+
+    let empty = dir.join("empty");
+    fs::create_dir_all(&empty).unwrap();
+    assert!(image::newest_match(&format!("{}/*.img", empty.display())).unwrap_err().to_string().ends_with("is empty"));
+    assert!(image::newest_match(&format!("{}/*/kernel8.img", dir.display())).is_err());
+    for (path, glob) in [("out/kernel8-*.img", true), ("kernel8-?.img", true), ("out/kernel8.img", false), ("http://host/kernel8.img?v=2", false)] {
+        assert_eq!(image::is_glob(path), glob, "{}", path);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// A share that is slow to answer and hands out odd-sized reads, failing after `fail_at`
+/// bytes if set. Says when it is dropped, i.e. when its reading thread ended.
+struct SlowReader {
+    data: Vec<u8>,
+    at: usize,
+    fail_at: Option<usize>,
+    reads: Arc<AtomicUsize>,
+    dropped: Arc<AtomicBool>,
+}
+
+impl SlowReader {
+    fn new(data: Vec<u8>, fail_at: Option<usize>) -> Self {
+        Self { data, at: 0, fail_at, reads: Arc::default(), dropped: Arc::default() }
+    }
+}
+
+impl Read for SlowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(1));
+        if self.fail_at.is_some_and(|fail_at| self.at >= fail_at) { return Err(io::Error::other("stale NFS file handle")); }
+        let n = buf.len().min(self.data.len() - self.at).min(1 + self.at % 37);
+        buf[..n].copy_from_slice(&self.data[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+impl Drop for SlowReader {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+fn wait_for(flag: &AtomicBool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !flag.load(Ordering::Relaxed) {
+        assert!(Instant::now() < deadline, "the reading thread did not stop");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn reads_ahead_in_the_same_chunks_and_order() {
+    let data = synthetic(3000);
+    for (chunk_size, depth) in [(64, 1), (500, image::READ_AHEAD), (4096, 2)] {
+        let mut expected = Vec::new();
+        let mut image = Image::from_bytes(data.clone());
+        let mut chunks = Chunks::new(chunk_size);
+        loop {
+            let chunk = chunks.next(&mut image.source).unwrap().to_vec();
+            expected.push(chunk.clone());
+            if chunk.is_empty() { break; }
+        }
+
+        let reader = SlowReader::new(data.clone(), None);
+        let dropped = reader.dropped.clone();
+        let mut ahead = ReadAhead::spawn(reader, chunk_size, depth);
+        let mut got = Vec::new();
+        loop {
+            let chunk = ahead.next_chunk().unwrap();
+            got.push(chunk.clone());
+            // slower than the reader, like a UART
+            thread::sleep(Duration::from_millis(2));
+            if chunk.is_empty() { break; }
+        }
+        assert_eq!(got, expected, "chunks of {}", chunk_size);
+        assert!(ahead.next_chunk().unwrap().is_empty());
+        wait_for(&dropped);
+    }
+}
+
+#[test]
+fn read_ahead_stops_with_either_side() {
+    // a read error comes after the chunks read before it, and ends the reading
+    let mut ahead = ReadAhead::spawn(SlowReader::new(synthetic(1000), Some(300)), 100, 2);
+    for _ in 0..3 { assert_eq!(ahead.next_chunk().unwrap().len(), 100); }
+    assert_eq!(ahead.next_chunk().unwrap_err().to_string(), "stale NFS file handle");
+    assert!(ahead.next_chunk().unwrap().is_empty());
+
+    // a failed write drops the reader, which stops reading a few chunks on at most
+    let reader = SlowReader::new(synthetic(1 << 20), None);
+    let (reads, dropped) = (reader.reads.clone(), reader.dropped.clone());
+    let mut ahead = ReadAhead::spawn(reader, 100, 2);
+    ahead.next_chunk().unwrap();
+    drop(ahead);
+    wait_for(&dropped);
+    // each chunk of 100 takes a few reads of up to 37 bytes
+    assert!(reads.load(Ordering::Relaxed) < 5 * 100, "{} reads", reads.load(Ordering::Relaxed));
+}
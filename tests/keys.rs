@@ -0,0 +1,91 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use rust_serial_tool::keys::{encode, encode_on, key_byte, key_byte_on, KeyEncoding};
+
+fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+    KeyEvent::new(code, modifiers)
+}
+
+#[test]
+fn encodes_keys_like_xterm() {
+    let (none, shift, ctrl, alt) = (KeyModifiers::NONE, KeyModifiers::SHIFT, KeyModifiers::CONTROL, KeyModifiers::ALT);
+    let cases: &[(KeyEvent, &[u8])] = &[
+        (key(KeyCode::Char('a'), none), b"a"),
+        (key(KeyCode::Char('A'), shift), b"A"),
+        (key(KeyCode::Char('é'), none), "é".as_bytes()),
+        (key(KeyCode::Char('c'), ctrl), b"\x03"),
+        (key(KeyCode::Char('x'), alt), b"\x1bx"),
+        (key(KeyCode::Enter, none), b"\r"),
+        (key(KeyCode::Backspace, none), b"\x7f"),
+        (key(KeyCode::Up, none), b"\x1b[A"),
+        (key(KeyCode::Left, none), b"\x1b[D"),
+        (key(KeyCode::Right, ctrl), b"\x1b[1;5C"),
+        (key(KeyCode::Home, none), b"\x1b[H"),
+        (key(KeyCode::End, shift), b"\x1b[1;2F"),
+        (key(KeyCode::Delete, none), b"\x1b[3~"),
+        (key(KeyCode::PageDown, alt), b"\x1b[6;3~"),
+        (key(KeyCode::BackTab, shift), b"\x1b[Z"),
+        (key(KeyCode::F(1), none), b"\x1bOP"),
+        (key(KeyCode::F(4), ctrl), b"\x1b[1;5S"),
+        (key(KeyCode::F(5), none), b"\x1b[15~"),
+        (key(KeyCode::F(12), none), b"\x1b[24~"),
+    ];
+    for (key, bytes) in cases {
+        assert_eq!(encode(key, KeyEncoding::Xterm).as_deref(), Some(*bytes), "{:?}", key);
+    }
+    assert_eq!(encode(&key(KeyCode::F(13), none), KeyEncoding::Xterm), None);
+}
+
+#[test]
+fn ascii_sends_characters_only() {
+    let none = KeyModifiers::NONE;
+    assert_eq!(encode(&key(KeyCode::Char('q'), KeyModifiers::ALT), KeyEncoding::Ascii).as_deref(), Some(&b"q"[..]));
+    assert_eq!(encode(&key(KeyCode::Backspace, none), KeyEncoding::Ascii).as_deref(), Some(&b"\x08"[..]));
+    assert_eq!(encode(&key(KeyCode::Tab, none), KeyEncoding::Ascii).as_deref(), Some(&b"\t"[..]));
+    for code in [KeyCode::Up, KeyCode::Home, KeyCode::Delete, KeyCode::F(2)] {
+        assert_eq!(encode(&key(code, none), KeyEncoding::Ascii), None, "{:?}", code);
+    }
+    assert_eq!("ASCII".parse(), Ok(KeyEncoding::Ascii));
+    assert!("vt52".parse::<KeyEncoding>().is_err());
+}
+
+#[test]
+fn control_keys_as_bytes() {
+    let ctrl = KeyModifiers::CONTROL;
+    let cases = [
+        (key(KeyCode::Char('a'), ctrl), Some(0x01)),
+        (key(KeyCode::Char('A'), ctrl | KeyModifiers::SHIFT), Some(0x01)),
+        // Ctrl-] as Windows and as a unix terminal report it
+        (key(KeyCode::Char(']'), ctrl), Some(0x1d)),
+        (key(KeyCode::Char('5'), ctrl), Some(0x1d)),
+        (key(KeyCode::Char('\x1d'), ctrl), Some(0x1d)),
+        (key(KeyCode::Char(' '), ctrl), Some(0x00)),
+        (key(KeyCode::Esc, KeyModifiers::NONE), Some(0x1b)),
+        (key(KeyCode::Char('1'), ctrl), None),
+        (key(KeyCode::Left, KeyModifiers::NONE), None),
+        (key(KeyCode::Char('ü'), KeyModifiers::NONE), None),
+    ];
+    for (key, byte) in cases {
+        assert_eq!(key_byte(&key), byte, "{:?}", key);
+    }
+}
+
+#[test]
+fn altgr_characters_on_windows() {
+    let altgr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+    // what a German layout has on AltGr, and a character beyond Latin-1
+    let cases: &[(char, &[u8])] = &[('@', b"@"), (']', b"]"), ('\\', b"\\"), ('€', "€".as_bytes()), ('µ', "µ".as_bytes())];
+    for &(c, bytes) in cases {
+        let key = key(KeyCode::Char(c), altgr);
+        for encoding in [KeyEncoding::Xterm, KeyEncoding::Ascii] {
+            assert_eq!(encode_on(&key, encoding, true).as_deref(), Some(bytes), "{:?}", key);
+        }
+        assert_eq!(key_byte_on(&key, true), c.is_ascii().then_some(c as u8), "{:?}", key);
+    }
+    // ] typed with AltGr is not Ctrl-], the exit key
+    assert_eq!(key_byte_on(&key(KeyCode::Char(']'), altgr), true), Some(b']'));
+    assert_eq!(key_byte_on(&key(KeyCode::Char(']'), altgr), false), Some(0x1d));
+    // a Ctrl-Alt the console did turn into a control character, and elsewhere Meta and Ctrl
+    assert_eq!(encode_on(&key(KeyCode::Char('\x1d'), altgr), KeyEncoding::Xterm, true).as_deref(), Some(&b"\x1b\x1d"[..]));
+    assert_eq!(encode_on(&key(KeyCode::Char('a'), altgr), KeyEncoding::Xterm, false).as_deref(), Some(&b"\x1b\x01"[..]));
+    assert_eq!(encode_on(&key(KeyCode::Up, altgr), KeyEncoding::Xterm, true).as_deref(), Some(&b"\x1b[1;7A"[..]));
+}
@@ -0,0 +1,39 @@
+use std::time::{Duration, Instant};
+
+use rust_serial_tool::limit::RateLimiter;
+
+#[test]
+fn bursts_pass_and_the_rest_waits() {
+    let start = Instant::now();
+    let mut limiter = RateLimiter::with_burst(1000, 100, start);
+
+    assert_eq!(limiter.reserve(100, start), Duration::ZERO);
+    // the bucket is empty: 50 more bytes cost 50 ms
+    assert_eq!(limiter.reserve(50, start), Duration::from_millis(50));
+    // after waiting those out, another 500 bytes owe half a second
+    assert_eq!(limiter.reserve(500, start + Duration::from_millis(50)), Duration::from_millis(500));
+}
+
+#[test]
+fn idle_time_refills_up_to_the_burst() {
+    let start = Instant::now();
+    let mut limiter = RateLimiter::with_burst(1000, 100, start);
+    limiter.reserve(100, start);
+
+    let later = start + Duration::from_secs(10);
+    assert_eq!(limiter.reserve(100, later), Duration::ZERO);
+    assert_eq!(limiter.reserve(10, later), Duration::from_millis(10));
+}
+
+#[test]
+fn holds_the_average_rate() {
+    let start = Instant::now();
+    let mut limiter = RateLimiter::with_burst(200 * 1024, 10 * 1024, start);
+
+    // send 512-byte chunks as fast as the limiter lets us, for 1 MiB
+    let mut now = start;
+    for _ in 0..2048 { now += limiter.reserve(512, now); }
+    let secs = (now - start).as_secs_f64();
+    let expected = (1024.0 * 1024.0 - 10.0 * 1024.0) / (200.0 * 1024.0);
+    assert!((secs - expected).abs() < 0.01, "{} vs {}", secs, expected);
+}
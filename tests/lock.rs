@@ -0,0 +1,96 @@
+mod common;
+
+use std::{fs, process};
+
+use common::scratch;
+use rust_serial_tool::{ErrorKind, lock::{lock_name, parse_pid, PortLock}};
+
+// far above any pid_max
+const GONE: u32 = 999_999_999;
+
+#[test]
+fn parses_pids() {
+    assert_eq!(parse_pid("      1234\n"), Some(1234));
+    assert_eq!(parse_pid("1234"), Some(1234));
+    assert_eq!(parse_pid("0\n"), None);
+    assert_eq!(parse_pid("\x01\x02\x00\x00"), None);
+}
+
+#[test]
+fn lock_file_holds_pid_until_dropped() {
+    let dirs = [scratch("drop")];
+    let dir = &dirs[0];
+    let lock = PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &dirs, false).unwrap();
+    assert_eq!(lock.path(), dir.join("LCK..ttyUSB7"));
+    let contents = fs::read_to_string(lock.path()).unwrap();
+    assert_eq!(contents.len(), 11);
+    assert_eq!(parse_pid(&contents), Some(process::id()));
+
+    drop(lock);
+    assert!(!dir.join("LCK..ttyUSB7").exists());
+}
+
+#[test]
+fn live_lock_is_refused_even_with_force() {
+    let dirs = [scratch("live")];
+    let dir = &dirs[0];
+    fs::write(dir.join("LCK..ttyUSB7"), format!("{:>10}\n", process::id())).unwrap();
+    for &force in &[false, true] {
+        match PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &dirs, force) {
+            Err(ErrorKind::PortLocked { pid, stale: false, .. }) => assert_eq!(pid, process::id()),
+            other => panic!("force {}: {:?}", force, other),
+        }
+    }
+}
+
+#[test]
+fn stale_lock_needs_force() {
+    let dirs = [scratch("stale")];
+    let dir = &dirs[0];
+    let path = dir.join("LCK..ttyUSB7");
+    fs::write(&path, format!("{:>10}\n", GONE)).unwrap();
+    match PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &dirs, false) {
+        Err(ErrorKind::PortLocked { pid: GONE, stale: true, path: locked }) => assert_eq!(locked, path),
+        other => panic!("{:?}", other),
+    }
+
+    let lock = PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &dirs, true).unwrap();
+    assert_eq!(parse_pid(&fs::read_to_string(lock.path()).unwrap()), Some(process::id()));
+}
+
+#[test]
+fn falls_back_and_checks_every_dir() {
+    let missing = scratch("fallback").join("missing");
+    let dir = scratch("fallback-2");
+    let lock = PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &[missing.clone(), dir.clone()], false).unwrap();
+    assert_eq!(lock.path(), dir.join("LCK..ttyUSB7"));
+
+    // whichever dir the other instance got, the lock is found
+    assert!(matches!(PortLock::acquire_in("/dev/ttyUSB7".as_ref(), &[missing, dir], false),
+                     Err(ErrorKind::PortLocked { stale: false, .. })));
+}
+
+#[cfg(unix)]
+#[test]
+fn links_share_the_device_lock() {
+    let dir = scratch("links");
+    let device = dir.join("ttyUSB3");
+    fs::write(&device, "").unwrap();
+    let link = dir.join("usb-FTDI_FT232R_A50285BI-if00-port0");
+    std::os::unix::fs::symlink(&device, &link).unwrap();
+    assert_eq!(lock_name(&link).as_deref(), Some("LCK..ttyUSB3"));
+    assert_eq!(lock_name(&device).as_deref(), Some("LCK..ttyUSB3"));
+}
+
+#[test]
+fn a_device_without_a_name_is_refused_by_name() {
+    let dirs = [scratch("nameless")];
+    match PortLock::acquire_in("/".as_ref(), &dirs, false) {
+        Err(ErrorKind::IoError(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(e.to_string(), "/ has no file name to lock it by");
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(fs::read_dir(&dirs[0]).unwrap().count(), 0);
+}
@@ -0,0 +1,174 @@
+use std::{collections::BTreeMap, io::{self, Write}, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}, Mutex}, thread};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rust_serial_tool::{filter::{FilterChain, FilterKind}, logfile::{self, Clock, LogFs, Rotation, RotatingLog, SessionLog}, terminal::RxTap};
+
+type Files = Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>;
+
+/// Files in memory, shared with the test, which can make opening them fail like a full disk.
+#[derive(Clone, Default)]
+struct MemFs(Files, Arc<AtomicBool>);
+
+struct MemFile(Files, PathBuf);
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().entry(self.1.clone()).or_default().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LogFs for MemFs {
+    fn append(&mut self, path: &Path) -> io::Result<(Box<dyn Write + Send>, u64)> {
+        if self.1.load(Ordering::Relaxed) { return Err(io::Error::other("no space left")); }
+        let size = self.0.lock().unwrap().entry(path.to_path_buf()).or_default().len() as u64;
+        Ok((Box::new(MemFile(self.0.clone(), path.to_path_buf())), size))
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.0.lock().unwrap();
+        let data = files.remove(from).ok_or(io::ErrorKind::NotFound)?;
+        files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        self.0.lock().unwrap().remove(path).map(|_| ()).ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+/// Seconds since the epoch, moved by the test.
+#[derive(Clone, Default)]
+struct FakeClock(Arc<AtomicU64>);
+
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.0.load(Ordering::Relaxed))
+    }
+}
+
+fn open_log(rotation: &str, keep: usize) -> (RotatingLog, MemFs, FakeClock) {
+    let (fs, clock) = (MemFs::default(), FakeClock::default());
+    let log = RotatingLog::with("out.log", rotation.parse().unwrap(), keep, Box::new(fs.clone()), Box::new(clock.clone())).unwrap();
+    (log, fs, clock)
+}
+
+fn contents(fs: &MemFs) -> Vec<(String, String)> {
+    fs.0.lock().unwrap().iter().map(|(path, data)| (path.display().to_string(), String::from_utf8_lossy(data).into_owned())).collect()
+}
+
+#[test]
+fn parses_rotations() {
+    let cases = [
+        ("50M", Rotation { max_bytes: Some(50 << 20), daily: false }),
+        ("daily", Rotation { max_bytes: None, daily: true }),
+        ("Daily, 64K", Rotation { max_bytes: Some(64 << 10), daily: true }),
+    ];
+    for (s, rotation) in cases {
+        assert_eq!(s.parse::<Rotation>().unwrap(), rotation, "{}", s);
+    }
+    for bad in ["", "weekly", "daily,daily", "1M,2M", "0"] {
+        assert!(bad.parse::<Rotation>().is_err(), "{}", bad);
+    }
+    assert_eq!(logfile::rotated_path(Path::new("logs/out.log"), 3), Path::new("logs/out.log.3"));
+}
+
+#[test]
+fn rotates_by_size_keeping_the_newest() {
+    let (mut log, fs, _) = open_log("10", 2);
+    for chunk in ["aaaa", "bbbb", "cc", "dddd", "eeeeeeeeeeee", "f"] {
+        log.write(chunk.as_bytes()).unwrap();
+    }
+    // a chunk goes whole into one file: "cc" still fits, "dddd" doesn't, the oversized one
+    // gets a file of its own, and the first file was dropped as the third-newest
+    let expected = [("out.log", "f"), ("out.log.1", "eeeeeeeeeeee"), ("out.log.2", "dddd")];
+    assert_eq!(contents(&fs), expected.map(|(path, data)| (path.to_string(), data.to_string())));
+}
+
+#[test]
+fn opens_a_fresh_file_again_after_a_failed_rotation() {
+    let (mut log, fs, _) = open_log("4", 1);
+    log.write(b"abcd").unwrap();
+    fs.1.store(true, Ordering::Relaxed);
+    assert!(log.write(b"efgh").is_err());
+    fs.1.store(false, Ordering::Relaxed);
+    log.write(b"ijkl").unwrap();
+    // what came while no file was open is gone, what came after isn't
+    let expected = [("out.log", "ijkl"), ("out.log.1", "abcd")];
+    assert_eq!(contents(&fs), expected.map(|(path, data)| (path.to_string(), data.to_string())));
+}
+
+#[test]
+fn rotates_daily() {
+    let (mut log, fs, clock) = open_log("daily", 5);
+    clock.0.store(86_400 * 100 + 3600 * 23, Ordering::Relaxed);
+    log.write(b"before midnight\n").unwrap();
+    clock.0.fetch_add(1800, Ordering::Relaxed);
+    log.write(b"still the same day\n").unwrap();
+    assert_eq!(contents(&fs).len(), 1);
+
+    clock.0.fetch_add(3600, Ordering::Relaxed);
+    log.write(b"the next day\n").unwrap();
+    let files = contents(&fs);
+    assert_eq!(files[0], ("out.log".to_string(), "the next day\n".to_string()));
+    assert_eq!(files[1], ("out.log.1".to_string(), "before midnight\nstill the same day\n".to_string()));
+
+    // keep 0: the old file just goes
+    let (mut log, fs, _) = open_log("4", 0);
+    log.write(b"12345").unwrap();
+    log.write(b"678").unwrap();
+    assert_eq!(contents(&fs), [("out.log".to_string(), "678".to_string())]);
+}
+
+#[test]
+fn logs_what_the_filters_make_of_it() {
+    let (log, fs, _) = open_log("1M", 1);
+    let mut log = Arc::new(SessionLog::with_filters(log, FilterChain::new(&[FilterKind::CollapseOverwrites])));
+    for step in (0..=100).step_by(10) { log.rx(format!("\rErasing {:>3}%", step).as_bytes()); }
+    log.rx(b"\r\nStarting kernel\r\nlogin: ");
+    assert_eq!(contents(&fs), [("out.log".to_string(), "Erasing 100%\r\nStarting kernel\r\n".to_string())]);
+    // the prompt that never ended its line, once the log is done
+    drop(log);
+    assert_eq!(contents(&fs)[0].1, "Erasing 100%\r\nStarting kernel\r\nlogin: ");
+}
+
+#[test]
+fn concurrent_writers_lose_nothing() {
+    let (log, fs, _) = open_log("100", 1000);
+    let log = Arc::new(SessionLog::new(log));
+    thread::scope(|scope| {
+        for writer in 0..4u8 {
+            let log = log.clone();
+            scope.spawn(move || for i in 0..250u8 { log.write(&[b'a' + writer, i]).unwrap(); });
+        }
+    });
+
+    let files = fs.0.lock().unwrap();
+    assert!(files.len() > 10);
+    assert!(files.values().all(|data| data.len() <= 100 && data.len() % 2 == 0));
+    let mut pairs: Vec<[u8; 2]> = files.values().flat_map(|data| data.chunks(2).map(|pair| [pair[0], pair[1]])).collect();
+    pairs.sort();
+    let mut expected: Vec<[u8; 2]> = (0..4u8).flat_map(|writer| (0..250u8).map(move |i| [b'a' + writer, i])).collect();
+    expected.sort();
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn appends_to_a_real_file() {
+    let dir = std::env::temp_dir().join(format!("logfile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.log");
+    std::fs::write(&path, b"earlier session\n").unwrap();
+
+    let rotation = Rotation { max_bytes: Some(24), daily: false };
+    let mut log = RotatingLog::open(&path, rotation, 3).unwrap();
+    log.write(b"more\n").unwrap();
+    log.write(b"past the limit\n").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"past the limit\n");
+    assert_eq!(std::fs::read(logfile::rotated_path(&path, 1)).unwrap(), b"earlier session\nmore\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,155 @@
+#![cfg(feature = "mock")]
+
+use std::{io, panic, sync::atomic::AtomicBool, time::{Duration, Instant}};
+
+use rust_serial_tool::{ErrorKind, mock::MockSerial, protocol::{self, Chainboot, RequestMatcher, SizeHeader}};
+use rust_serial_tool::{ReadSerial, SerialPort, WRITE_TIMEOUT, WriteSerial};
+
+#[test]
+fn send_size_against_a_script() {
+    let cases = [
+        (MockSerial::new().expect(&0x1234u32.to_le_bytes()).reply(b"OK"), true),
+        (MockSerial::new().expect(&0x1234u32.to_le_bytes()).reply(b"NO"), false),
+        // the target answering before the size is sent changes nothing
+        (MockSerial::new().reply(b"OK").expect_any(4), true),
+        (MockSerial::new().expect_any(4).disconnect(), false),
+    ];
+    for (mock, ok) in cases {
+        match protocol::send_size(&mut mock.clone(), 0x1234, SizeHeader::Legacy) {
+            Ok(()) => assert!(ok),
+            Err(e) => assert!(!ok && e.name() == "protocol", "{:?}", e),
+        }
+        assert_eq!(mock.written(), 0x1234u32.to_le_bytes());
+        mock.assert_done();
+    }
+}
+
+#[test]
+fn send_size_skips_stray_bytes() {
+    let size = 0x1234u32.to_le_bytes();
+    // the newline some loaders print after their request, still arriving after the size went out
+    let mock = MockSerial::new().reply(b"\r\n").expect(&size).reply(b"\nO").reply(b"K");
+    protocol::send_size(&mut mock.clone(), 0x1234, SizeHeader::Legacy).unwrap();
+    mock.assert_done();
+
+    // no OK among the noise, or noise and then half an answer: an error showing the noise, well
+    // before REPLY_TIMEOUT
+    let cases = [
+        MockSerial::new().expect(&size).reply(&[b'.'; protocol::REPLY_WINDOW * 2]),
+        MockSerial::new().expect(&size).reply(b"booting\r\nO"),
+    ];
+    for mock in cases {
+        let started = Instant::now();
+        let result = protocol::send_size(&mut mock.clone(), 0x1234, SizeHeader::Legacy);
+        assert!(matches!(result, Err(ErrorKind::UnexpectedReply { .. })), "{:?}", result);
+        assert!(started.elapsed() < protocol::REPLY_TIMEOUT / 5, "{:?}", started.elapsed());
+    }
+}
+
+#[test]
+fn read_serial_exact_across_replies() {
+    let mut mock = MockSerial::new().reply(b"he").reply(b"llo").disconnect();
+    let mut buf = [0; 5];
+    mock.read_serial_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(matches!(mock.read_serial_exact(&mut buf), Err(ErrorKind::ConnectionError)));
+    mock.assert_done();
+
+    // a one-shot failure is reported once, then the script goes on
+    let mut mock = MockSerial::new().fail(io::ErrorKind::Interrupted).reply(b"ok");
+    let mut buf = [0; 2];
+    mock.read_serial_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ok");
+
+    let mut mock = MockSerial::new().reply(b"x");
+    assert!(matches!(mock.read_serial_exact_timeout(&mut buf, Duration::from_millis(30)),
+        Err(ErrorKind::ReadTimeout { received: 1, expected: 2 })));
+}
+
+#[test]
+fn read_serial_drain_until_a_short_read() {
+    // full reads go on, the short one ends it
+    let mut mock = MockSerial::new().reply(b"abcd").reply(b"efgh").reply(b"ij").reply(b"kl");
+    let mut buf = [0; 16];
+    assert_eq!(mock.read_serial_drain(&mut buf, 4).unwrap(), 10);
+    assert_eq!(&buf[..10], b"abcdefghij");
+    // no further than the buffer goes
+    let mut mock = MockSerial::new().reply(b"abcdefgh");
+    assert_eq!(mock.read_serial_drain(&mut buf[..6], 4).unwrap(), 6);
+    // what arrived before the port went away comes first, the error with the next call
+    let mut mock = MockSerial::new().reply(b"abcd").disconnect();
+    assert_eq!(mock.read_serial_drain(&mut buf, 4).unwrap(), 4);
+    assert!(matches!(mock.read_serial_drain(&mut buf, 4), Err(ErrorKind::ConnectionError)));
+    assert_eq!(MockSerial::new().read_serial_drain(&mut buf, 4).unwrap(), 0);
+}
+
+fn wait_for_binary_request(port: &mut SerialPort) -> Vec<u8> {
+    let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 1, false);
+    let mut shown = Vec::new();
+    let requested = protocol::wait_for_request(port, &mut machine, 16, 4, &AtomicBool::new(true), |_, boot, _| {
+        shown.extend_from_slice(boot);
+        Ok(())
+    }).unwrap();
+    assert!(requested);
+    shown
+}
+
+#[test]
+fn binary_request_then_size() {
+    let mock = MockSerial::new()
+        .reply(b"U-Boot\r\n\x03")
+        .reply(b"\x03\x03")
+        .expect(&3u32.to_le_bytes())
+        .reply(b"OK")
+        .expect(b"abc");
+    let mut port: SerialPort = Box::new(mock.clone());
+    assert_eq!(wait_for_binary_request(&mut port), b"U-Boot\r\n");
+    protocol::send_size(&mut port, 3, SizeHeader::Legacy).unwrap();
+    port.write_serial_all(b"abc", WRITE_TIMEOUT).unwrap();
+    mock.assert_done();
+
+    let mut port: SerialPort = Box::new(MockSerial::new().reply(b"REA").reply(b"DY"));
+    let mut request: RequestMatcher = "regex:READY".parse().unwrap();
+    let mut buf = [0; 8];
+    let n = port.read_serial(&mut buf).unwrap();
+    assert!(!request.feed(&buf[..n]).1);
+    let n = port.read_serial(&mut buf).unwrap();
+    assert!(request.feed(&buf[..n]).1);
+}
+
+#[test]
+fn waiting_for_the_request_stops_when_told() {
+    let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 1, false);
+    let mut port: SerialPort = Box::new(MockSerial::new().reply(b"booting"));
+    let waited = protocol::wait_for_request(&mut port, &mut machine, 16, 4, &AtomicBool::new(true),
+                                            |_, _, _| Err(ErrorKind::Interrupted));
+    assert!(matches!(waited, Err(ErrorKind::Interrupted)));
+    assert!(!protocol::wait_for_request(&mut port, &mut machine, 16, 4, &AtomicBool::new(false), |_, _, _| Ok(())).unwrap());
+    // a port that goes away ends it with the port's error
+    let mut port: SerialPort = Box::new(MockSerial::new().disconnect());
+    assert!(protocol::wait_for_request(&mut port, &mut machine, 16, 4, &AtomicBool::new(true), |_, _, _| Ok(())).is_err());
+}
+
+#[test]
+fn unexpected_writes_panic_with_a_diff() {
+    let message = |result: std::thread::Result<()>| match result.unwrap_err().downcast::<String>() {
+        Ok(message) => *message,
+        Err(_) => String::new(),
+    };
+
+    let mut mock = MockSerial::new().expect(b"\x10\x00\x00\x00");
+    let result = panic::catch_unwind(move || { let _ = mock.write_serial(b"\x10\x00\x01\x00"); });
+    let message = message(result);
+    assert!(message.contains("differs at byte 2"), "{}", message);
+    assert!(message.contains(r#"expected: "\x10\x00\x00\x00""#), "{}", message);
+
+    let mut mock = MockSerial::new().reply(b"OK");
+    let result = panic::catch_unwind(move || { let _ = mock.write_serial(b"hi"); });
+    assert!(result.is_err());
+
+    let mock = MockSerial::new().expect(b"size");
+    assert!(panic::catch_unwind(|| mock.assert_done()).is_err());
+
+    let mock = MockSerial::new().expect(b"size");
+    assert!(panic::catch_unwind(|| { let _ = serialport::SerialPort::set_break(&mock); }).is_err());
+}
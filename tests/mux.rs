@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rust_serial_tool::{command::Command, filter::{FilterChain, FilterKind}, mux::{self, Tagger}, terminal::LINE_IDLE};
+
+#[test]
+fn tags_lines() {
+    let at = UNIX_EPOCH + Duration::from_millis(1_709_211_909_123);
+    let cases = [
+        ("[A]", "login: root\r\n", None, "[A] login: root\n"),
+        ("[B]", "I (312) wifi: connected\n", None, "[B] I (312) wifi: connected\n"),
+        ("[A]", "login: ", None, "[A] login: \n"),
+        ("[B]", "\r\n", None, "[B] \n"),
+        ("[A]", "# \r\n", Some(at), "[A] 13:05:09.123 # \n"),
+    ];
+    for (tag, text, stamp, expected) in cases {
+        assert_eq!(mux::tag_line(tag, text, stamp), expected, "{:?}", text);
+    }
+    assert_eq!((mux::port_tag(0), mux::port_tag(1)), ("[A]".to_string(), "[B]".to_string()));
+    assert_eq!(mux::time_of_day(UNIX_EPOCH + Duration::from_millis(86_399_999)), "23:59:59.999");
+}
+
+#[test]
+fn holds_partial_lines_per_port() {
+    let (start, at) = (Instant::now(), SystemTime::now());
+    let mut a = Tagger::new(mux::port_tag(0), false);
+    let mut b = Tagger::new(mux::port_tag(1), false);
+
+    // interleaved reads don't mix, each port completes its own lines
+    assert_eq!(a.push(b"U-Boot 2024", start, at), Vec::<String>::new());
+    assert_eq!(b.push(b"rst:0x1 (POWERON)\r\nboot:", start, at), ["[B] rst:0x1 (POWERON)\n"]);
+    assert_eq!(a.push(b".01\r\nDRAM: 2 GiB\r\n", start, at), ["[A] U-Boot 2024.01\n", "[A] DRAM: 2 GiB\n"]);
+
+    // a prompt that never ends its line is shown once the port pauses, marked as cut
+    assert_eq!(b.flush_idle(start + LINE_IDLE / 2, at), None);
+    assert_eq!(b.flush_idle(start + LINE_IDLE, at).as_deref(), Some("[B] boot:…\n"));
+    assert_eq!(b.flush_idle(start + LINE_IDLE * 2, at), None);
+    // and the rest of its line is marked as going on with it
+    assert_eq!(b.push(b"0x13 (SPI_FAST_FLASH_BOOT)\r\n", start, at), ["[B] …0x13 (SPI_FAST_FLASH_BOOT)\n"]);
+    assert_eq!(b.push(b"ready\n", start, at), ["[B] ready\n"]);
+
+    // what the target sends is no escape of its own
+    assert_eq!(a.push(b"\x1b]0;title\x07ok\n", start, at), ["[A] ^[]0;title^Gok\n"]);
+}
+
+#[test]
+fn a_stamped_prompt_is_marked_not_ended() {
+    let start = Instant::now();
+    let (at, later) = (UNIX_EPOCH + Duration::from_millis(43_200_000), UNIX_EPOCH + Duration::from_millis(43_203_100));
+    let mut tagger = Tagger::new(mux::port_tag(0), true);
+    tagger.set_line_idle(Duration::from_millis(20));
+    assert!(tagger.push(b"login: ", start, at).is_empty());
+    assert_eq!(tagger.flush_idle(start + Duration::from_millis(20), at).as_deref(), Some("[A] 12:00:00.000 login: …\n"));
+    assert_eq!(tagger.push(b"root\r\n", start, later), ["[A] 12:00:03.100 …root\n"]);
+    // gone in the middle of a line
+    assert!(tagger.push(b"Passw", start, later).is_empty());
+    assert_eq!(tagger.flush(later).as_deref(), Some("[A] 12:00:03.100 Passw…\n"));
+}
+
+#[test]
+fn stamps_a_redrawn_line_once() {
+    let (start, at) = (Instant::now(), SystemTime::now());
+    let mut tagger = Tagger::new(mux::port_tag(0), false);
+    tagger.set_filters(FilterChain::new(&[FilterKind::CollapseOverwrites]));
+    assert!(tagger.push(b"count 1\rcount 2\r", start, at).is_empty());
+    assert_eq!(tagger.flush_idle(start + LINE_IDLE * 2, at), None);
+    assert_eq!(tagger.push(b"count 3\r\n", start, at), ["[A] count 3\n"]);
+}
+
+#[test]
+fn switches_ports_with_a_chord() {
+    let table = mux::commands(0x01);
+    assert_eq!(table.lookup(b'o'), Some(Command::SwitchPort));
+    assert_eq!(table.lookup(b'q'), Some(Command::Quit));
+    assert_eq!(table.lookup(b'x'), None);
+    assert!(table.help().contains("o: switch port"));
+}
@@ -0,0 +1,54 @@
+use std::{sync::{Arc, Mutex}, thread};
+
+use rust_serial_tool::{ErrorKind, observer::{ObserverSlot, PushObserver, PushReport}, output::{Output, Verbosity}};
+
+/// Writes down every callback, shared with the test.
+#[derive(Clone, Default)]
+struct Recording(Arc<Mutex<Vec<String>>>);
+
+impl PushObserver for Recording {
+    fn handshake_complete(&mut self) {
+        self.0.lock().unwrap().push("handshake".to_string());
+    }
+
+    fn progress(&mut self, sent: u64, total: u64) {
+        self.0.lock().unwrap().push(format!("{}/{}", sent, total));
+    }
+
+    fn finished(&mut self, report: &PushReport) {
+        self.0.lock().unwrap().push(format!("finished {}", report.bytes));
+    }
+
+    fn error(&mut self, kind: &ErrorKind) {
+        self.0.lock().unwrap().push(kind.name().to_string());
+    }
+}
+
+#[test]
+fn set_observer_replaces_the_console() {
+    let out = Output::new("T", Verbosity::Quiet);
+    let recording = Recording::default();
+    let mut slot = ObserverSlot::default();
+    slot.set(Box::new(recording.clone()));
+
+    // callbacks may come from another thread, e.g. during the handshake
+    thread::scope(|scope| {
+        scope.spawn(|| slot.get(&out).lock().unwrap().handshake_complete());
+    });
+    let observer = slot.get(&out);
+    observer.lock().unwrap().progress(512, 1024);
+    observer.lock().unwrap().finished(&PushReport { bytes: 1024, total: 1024, seconds: 0.1, retransmitted: 0, sha256: [0; 32], loader: None, target: None });
+    observer.lock().unwrap().error(&ErrorKind::ProtocolError);
+    assert_eq!(*recording.0.lock().unwrap(), ["handshake", "512/1024", "finished 1024", "protocol"]);
+}
+
+#[test]
+fn console_observer_by_default() {
+    let out = Output::new("T", Verbosity::Quiet);
+    let slot = ObserverSlot::default();
+    // quiet: the console observer has nothing to print, and must not need a terminal for it
+    let mut observer = slot.get(&out).lock().unwrap();
+    observer.progress(0, 10);
+    observer.progress(10, 10);
+    observer.finished(&PushReport { bytes: 10, total: 10, seconds: 0.0, retransmitted: 2, sha256: [0; 32], loader: None, target: None });
+}
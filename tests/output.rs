@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use crossterm::style::Color;
+use rust_serial_tool::output::{bar_width, ColorChoice, format_bytes, format_duration, Icon, Output, Progress, ProgressChoice};
+use rust_serial_tool::output::{Throttle, Verbosity, BAR_REDRAW, MIN_BAR_WIDTH};
+
+#[test]
+fn flags_to_verbosity() {
+    let cases = [
+        ((false, 0), Verbosity::Normal),
+        ((false, 1), Verbosity::Verbose),
+        ((false, 2), Verbosity::Trace),
+        ((false, 5), Verbosity::Trace),
+        ((true, 0), Verbosity::Quiet),
+        ((true, 2), Verbosity::Quiet),
+    ];
+    for ((quiet, verbose), expected) in cases.iter() {
+        assert_eq!(Verbosity::from_flags(*quiet, *verbose), *expected);
+    }
+}
+
+#[test]
+fn levels_filter() {
+    let mut out = Output::new("MP", Verbosity::default());
+    assert!(out.enabled(Verbosity::Quiet) && out.enabled(Verbosity::Normal));
+    assert!(!out.enabled(Verbosity::Verbose));
+
+    out.set_verbosity(Verbosity::Quiet);
+    assert!(!out.enabled(Verbosity::Normal));
+    assert!(out.progress_bar(Icon::Push, "Pushing", 100).is_none());
+
+    out.set_verbosity(Verbosity::Trace);
+    assert!(out.enabled(Verbosity::Trace));
+}
+
+#[test]
+fn color_choice() {
+    let cases = [
+        (ColorChoice::Always, false, Some("1"), true),
+        (ColorChoice::Never, true, None, false),
+        (ColorChoice::Auto, true, None, true),
+        (ColorChoice::Auto, true, Some(""), true),
+        (ColorChoice::Auto, true, Some("1"), false),
+        (ColorChoice::Auto, false, None, false),
+    ];
+    for (choice, tty, no_color, expected) in cases.iter() {
+        assert_eq!(choice.resolve(*tty, *no_color), *expected, "{:?} tty={} NO_COLOR={:?}", choice, tty, no_color);
+    }
+    assert_eq!("NEVER".parse(), Ok(ColorChoice::Never));
+    assert!("sometimes".parse::<ColorChoice>().is_err());
+}
+
+#[test]
+fn progress_choice() {
+    let cases = [
+        (ProgressChoice::Always, false, true),
+        (ProgressChoice::Never, true, false),
+        (ProgressChoice::Auto, true, true),
+        (ProgressChoice::Auto, false, false),
+    ];
+    for (choice, tty, expected) in cases.iter() {
+        assert_eq!(choice.resolve(*tty), *expected, "{:?} tty={}", choice, tty);
+    }
+    assert_eq!("Always".parse(), Ok(ProgressChoice::Always));
+    assert!("bar".parse::<ProgressChoice>().is_err());
+}
+
+#[test]
+fn plain_output_has_no_escapes_or_emoji() {
+    let mut out = Output::new("MP", Verbosity::Normal);
+    out.set_color(ColorChoice::Never);
+    assert_eq!(out.icon(Icon::Ok), "[OK]");
+    assert_eq!(out.paint("boom", Color::Red), "boom");
+
+    out.set_color(ColorChoice::Always);
+    assert_eq!(out.icon(Icon::Ok), "✅");
+    assert_ne!(out.paint("boom", Color::Red), "boom");
+
+    // a console that would print the colors as text gets none, even when asked
+    out.set_escapes(false);
+    assert!(!out.escapes());
+    assert_eq!(out.paint("boom", Color::Red), "boom");
+    out.set_color(ColorChoice::Always);
+    assert_eq!(out.paint_target("boom", Color::Red), "boom");
+}
+
+#[test]
+fn progress_lines_every_tenth() {
+    let mut out = Output::new("MP", Verbosity::Normal);
+    out.set_progress(ProgressChoice::Never);
+    let mut progress = out.progress_bar(Icon::Push, "Pushing", 1000).unwrap();
+    let reported = |progress: &Progress| match progress {
+        Progress::Lines { reported, .. } => *reported,
+        Progress::Bar(_) => panic!("a bar with --progress never"),
+    };
+    for (add, tenths) in [(50, 0), (50, 1), (249, 3), (51, 4), (10_000, 10)] {
+        progress.add(add);
+        assert_eq!(reported(&progress), tenths);
+    }
+    progress.set(400);
+    assert_eq!(reported(&progress), 10);
+
+    out.set_verbosity(Verbosity::Quiet);
+    assert!(out.progress_bar(Icon::Push, "Pushing", 1000).is_none());
+}
+
+#[test]
+fn throttles_redraws() {
+    // 512-byte chunks at 3 Mbaud come about every 1.7 ms
+    let (start, chunk) = (Instant::now(), Duration::from_micros(1707));
+    let mut throttle = Throttle::new(BAR_REDRAW);
+    // two seconds of them
+    let drawn = (0..1172).filter(|&i| throttle.ready(start + chunk * i)).count();
+    assert_eq!(drawn, 40);
+
+    let mut throttle = Throttle::new(BAR_REDRAW);
+    let cases = [(0, true), (10, false), (49, false), (50, true), (60, false), (500, true), (501, false)];
+    for (ms, expected) in cases {
+        assert_eq!(throttle.ready(start + Duration::from_millis(ms)), expected, "{} ms", ms);
+    }
+}
+
+#[test]
+fn bar_fits_the_terminal() {
+    let cases = [(None, None), (Some(0), None), (Some(20), Some(MIN_BAR_WIDTH)), (Some(80), Some(80)), (Some(200), Some(200))];
+    for (columns, expected) in cases {
+        assert_eq!(bar_width(columns), expected, "{:?}", columns);
+    }
+}
+
+#[test]
+fn byte_sizes() {
+    let cases = [
+        (0, "0 B"),
+        (1023, "1023 B"),
+        (1024, "1.0 KiB"),
+        (1536, "1.5 KiB"),
+        (6 * 1024, "6.0 KiB"),
+        (1024 * 1024 - 1, "1.0 MiB"),
+        (1024 * 1024 * 3 / 2, "1.5 MiB"),
+        (14 * 1024 * 1024, "14.0 MiB"),
+        (5 * 1024 * 1024 * 1024 + 1024 * 1024 * 1024 / 2, "5.5 GiB"),
+        (u64::MAX, "16777216.0 TiB"),
+    ];
+    for (bytes, expected) in cases.iter() {
+        assert_eq!(format_bytes(*bytes), *expected, "{}", bytes);
+    }
+}
+
+#[test]
+fn durations_read_to_the_second() {
+    let cases = [
+        (0, "0s"),
+        (59, "59s"),
+        (60, "1m 00s"),
+        (5 * 60 + 7, "5m 07s"),
+        (3599, "59m 59s"),
+        (3600, "1h 00m"),
+        (26 * 3600 + 3 * 60 + 59, "26h 03m"),
+    ];
+    for (secs, expected) in cases.iter() {
+        assert_eq!(format_duration(Duration::from_secs(*secs)), *expected, "{}", secs);
+    }
+}
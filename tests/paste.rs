@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rust_serial_tool::paste::Paste;
+
+const CHAR: Duration = Duration::from_millis(1);
+const LINE: Duration = Duration::from_millis(10);
+
+fn drain(paste: &mut Paste) -> Vec<(u8, Duration)> {
+    std::iter::from_fn(|| paste.next_byte()).collect()
+}
+
+#[test]
+fn line_counts() {
+    let cases: &[(&str, &[u8], usize)] = &[
+        ("empty", b"", 0),
+        ("one open line", b"ls -l", 1),
+        ("cr", b"ls\rpwd\r", 2),
+        ("lf", b"ls\npwd", 2),
+        ("crlf counts once", b"ls\r\npwd\r\n", 2),
+        ("empty lines", b"a\n\n\nb", 4),
+        ("crlf empty lines", b"\r\n\r\n", 2),
+    ];
+    for (name, data, lines) in cases {
+        let mut paste = Paste::new(CHAR, LINE);
+        paste.push(data);
+        assert_eq!(paste.total().lines, *lines, "{}", name);
+        assert_eq!(paste.total().bytes, data.len(), "{}", name);
+        drain(&mut paste);
+        assert_eq!(paste.sent(), paste.total(), "{}", name);
+    }
+}
+
+#[test]
+fn line_ends_wait_longer() {
+    let mut paste = Paste::new(CHAR, LINE);
+    paste.push(b"ab\r\nc");
+    assert_eq!(drain(&mut paste), &[(b'a', CHAR), (b'b', CHAR), (b'\r', LINE), (b'\n', CHAR), (b'c', CHAR)]);
+    assert!(paste.is_empty());
+}
+
+#[test]
+fn push_while_draining() {
+    let mut paste = Paste::new(CHAR, LINE);
+    paste.push(b"echo 1\r");
+    assert_eq!(paste.next_byte(), Some((b'e', CHAR)));
+    assert_eq!(paste.sent().lines, 1);
+    drain(&mut paste);
+
+    // the rest of a paste too long for one read
+    paste.push(b"\necho 2\r");
+    assert_eq!(paste.total().lines, 2);
+    assert_eq!(paste.next_byte(), Some((b'\n', CHAR)));
+    assert_eq!(paste.sent().lines, 1);
+    drain(&mut paste);
+    assert_eq!(paste.sent().lines, 2);
+    assert_eq!(paste.sent().bytes, 15);
+}
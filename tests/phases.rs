@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use rust_serial_tool::phases::{self, PushTimings};
+use serde_json::json;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn breaks_an_attempt_down() {
+    let mut timings = PushTimings::default();
+    timings.begin_attempt();
+    timings.record("wait-serial", ms(2));
+    timings.record("trigger", ms(3200));
+    timings.record("handshake", ms(4));
+    timings.record("transfer", ms(2000));
+    timings.record_bytes("transfer", 2048 * 1024);
+    // the size exchange after a negotiation is the same phase
+    timings.record("handshake", ms(6));
+    assert_eq!(timings.last().unwrap().to_string(), "wait-serial 0.0s | trigger 3.2s | handshake 0.01s | transfer 2.0s (1.0 MiB/s)");
+
+    let cases = [(0.0, "0.0s"), (0.004, "0.0s"), (0.01, "0.01s"), (0.094, "0.09s"), (0.095, "0.1s"), (41.66, "41.7s")];
+    for (seconds, expected) in cases {
+        assert_eq!(phases::format_seconds(seconds), expected, "{}", seconds);
+    }
+}
+
+#[test]
+fn keeps_each_attempt_apart() {
+    let mut timings = PushTimings::default();
+    assert_eq!(timings.report(), None);
+    timings.begin_attempt();
+    timings.record("wait-serial", ms(100));
+    timings.record("trigger", ms(1500));
+    timings.record("transfer", ms(500));
+    timings.record_bytes("transfer", 1000);
+    // reconnected: the next attempt starts over
+    timings.begin_attempt();
+    timings.record("wait-serial", ms(4000));
+    // an attempt that got nowhere before the next began
+    timings.begin_attempt();
+    timings.begin_attempt();
+    timings.record("wait-serial", ms(100));
+    timings.record("trigger", ms(500));
+    timings.record("transfer", ms(1000));
+    timings.record_bytes("transfer", 3000);
+
+    let attempts = timings.attempts();
+    assert_eq!(attempts.len(), 3);
+    assert_eq!(attempts[1].to_string(), "wait-serial 4.0s");
+    assert_eq!(attempts[2].get("transfer").unwrap().rate(), Some(3000.0));
+    let total = timings.total();
+    assert_eq!(total.to_string(), "wait-serial 4.2s | trigger 2.0s | transfer 1.5s (2.6 KiB/s)");
+    assert_eq!(total.get("transfer").unwrap().bytes, Some(4000));
+    assert_eq!(total.get("handshake"), None);
+
+    let report = serde_json::to_value(timings.report().unwrap()).unwrap();
+    assert_eq!(report["attempts"][1], json!([{ "phase": "wait-serial", "seconds": 4.0 }]));
+    assert_eq!(report["total"][2], json!({ "phase": "transfer", "seconds": 1.5, "bytes": 4000 }));
+}
@@ -0,0 +1,97 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use rust_serial_tool::{portwatch::*, transport::Presence};
+
+fn present(name: &str) -> Presence {
+    Presence::Present(name.to_string())
+}
+
+/// What each look at `at` milliseconds settles at, looks given as (at, presence).
+fn debounce(looks: &[(u64, Presence)]) -> Vec<(u64, Presence)> {
+    let start = Instant::now();
+    let mut debounce = Debounce::new(Duration::from_millis(250));
+    looks.iter()
+        .filter_map(|(at, presence)| debounce.observe(presence.clone(), start + Duration::from_millis(*at)).map(|settled| (*at, settled)))
+        .collect()
+}
+
+#[test]
+fn the_first_look_settles_at_once() {
+    assert_eq!(debounce(&[(0, Presence::Missing), (100, Presence::Missing)]), [(0, Presence::Missing)]);
+}
+
+#[test]
+fn a_change_counts_once_it_lasted() {
+    let looks = [(0, Presence::Missing), (1000, present("/dev/ttyUSB0")), (1200, present("/dev/ttyUSB0")), (1250, present("/dev/ttyUSB0")),
+                 (1300, present("/dev/ttyUSB0"))];
+    assert_eq!(debounce(&looks), [(0, Presence::Missing), (1250, present("/dev/ttyUSB0"))]);
+}
+
+#[test]
+fn a_blip_is_no_change() {
+    let looks = [(0, present("/dev/ttyUSB0")), (100, Presence::Missing), (200, Presence::Missing), (300, present("/dev/ttyUSB0")),
+                 (1000, present("/dev/ttyUSB0"))];
+    assert_eq!(debounce(&looks), [(0, present("/dev/ttyUSB0"))]);
+}
+
+#[test]
+fn a_re_enumeration_is_one_change() {
+    // udev makes the node, gives it its group, and the adapter resets and comes back as another
+    let looks = [
+        (0, Presence::Missing),
+        (1000, Presence::PermissionDenied("add yourself to the dialout group".to_string())),
+        (1050, present("/dev/ttyUSB0")),
+        (1100, Presence::Missing),
+        (1200, present("/dev/ttyUSB1")),
+        (1400, present("/dev/ttyUSB1")),
+        (1450, present("/dev/ttyUSB1")),
+    ];
+    assert_eq!(debounce(&looks), [(0, Presence::Missing), (1450, present("/dev/ttyUSB1"))]);
+}
+
+#[test]
+fn says_when_a_pending_change_settles() {
+    let start = Instant::now();
+    let mut debounce = Debounce::new(Duration::from_millis(250));
+    debounce.observe(Presence::Missing, start);
+    assert_eq!(debounce.due(), None);
+    debounce.observe(present("COM3"), start + Duration::from_millis(100));
+    assert_eq!(debounce.due(), Some(start + Duration::from_millis(350)));
+    assert_eq!(debounce.settled(), Some(&Presence::Missing));
+}
+
+#[test]
+fn watches_ports_through_a_channel_and_a_callback() {
+    // each port is looked at in turn; the board blips once before it stays
+    let script = [Presence::Missing, Presence::Missing, present("/dev/ttyUSB0"), Presence::Missing, present("/dev/ttyUSB0")];
+    let looks = Arc::new(Mutex::new(0));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let watcher = {
+        let (looks, seen) = (looks.clone(), seen.clone());
+        PortWatcher::builder(vec!["CP210x".to_string(), "tcp://rack:4001".to_string()])
+            .every(Duration::from_millis(5))
+            .debounce(Duration::from_millis(20))
+            .lookup(move |selector| {
+                if selector.starts_with("tcp://") {
+                    return Err(serialport::Error::new(serialport::ErrorKind::Unknown, "no route to host"));
+                }
+                let mut looks = looks.lock().unwrap();
+                *looks += 1;
+                Ok(script[(*looks - 1).min(script.len() - 1)].clone())
+            })
+            .on_change(move |event| seen.lock().unwrap().push(format!("{:?}", event)))
+            .spawn()
+    };
+
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        events.push(watcher.recv_timeout(Duration::from_secs(2)).expect("an event"));
+    }
+    assert!(matches!(&events[0], PortEvent::Changed { selector, presence: Presence::Missing } if selector == "CP210x"), "{:?}", events);
+    // a failing lookup is said once
+    assert!(matches!(&events[1], PortEvent::Failed { selector, .. } if selector == "tcp://rack:4001"), "{:?}", events);
+    assert!(matches!(&events[2], PortEvent::Changed { presence: Presence::Present(name), .. } if name == "/dev/ttyUSB0"), "{:?}", events);
+    assert!(watcher.recv_timeout(Duration::from_millis(100)).is_none());
+    assert!(*looks.lock().unwrap() > 5);
+    assert_eq!(*seen.lock().unwrap(), events.iter().map(|event| format!("{:?}", event)).collect::<Vec<_>>());
+}
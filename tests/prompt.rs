@@ -0,0 +1,186 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use std::{fs, sync::Arc};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use common::scratch;
+use rust_serial_tool::{ErrorKind, mock::MockSerial, output::{Output, Verbosity}, prompt::*, SerialPort};
+use rust_serial_tool::{scrollback::Scrollback, stats::SessionStats, terminal::RxTap};
+
+fn typed(text: &str) -> Vec<KeyEvent> {
+    text.chars().map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)).collect()
+}
+
+#[test]
+fn edits_the_line() {
+    let (none, ctrl) = (KeyModifiers::NONE, KeyModifiers::CONTROL);
+    let key = |code| KeyEvent::new(code, none);
+    let cases: [(&str, Vec<KeyEvent>, Edit); 7] = [
+        ("typed", [typed("stats"), vec![key(KeyCode::Enter)]].concat(), Edit::Submit("stats".to_string())),
+        ("backspace", [typed("baud 1152000"), vec![key(KeyCode::Backspace)], typed(" "), vec![key(KeyCode::Enter)]].concat(),
+         Edit::Submit("baud 115200 ".to_string())),
+        ("backspace past the start", [vec![key(KeyCode::Backspace); 2], typed("quit"), vec![key(KeyCode::Enter)]].concat(),
+         Edit::Submit("quit".to_string())),
+        ("ctrl-w", [typed("log start foo.txt"), vec![KeyEvent::new(KeyCode::Char('w'), ctrl)], typed("bar.txt"), vec![key(KeyCode::Enter)]].concat(),
+         Edit::Submit("log start bar.txt".to_string())),
+        ("ctrl-u", [typed("send x"), vec![KeyEvent::new(KeyCode::Char('u'), ctrl), key(KeyCode::Left)], vec![key(KeyCode::Enter)]].concat(),
+         Edit::Submit(String::new())),
+        ("esc", [typed("quit"), vec![key(KeyCode::Esc)]].concat(), Edit::Cancel),
+        ("ctrl-c", [typed("quit"), vec![KeyEvent::new(KeyCode::Char('c'), ctrl)]].concat(), Edit::Cancel),
+    ];
+    for (name, keys, expected) in cases {
+        let mut editor = LineEditor::default();
+        let (last, editing) = keys.split_last().unwrap();
+        assert!(editing.iter().all(|key| editor.feed(key) == Edit::Editing), "{}", name);
+        assert_eq!(editor.feed(last), expected, "{}", name);
+    }
+
+    let mut editor = LineEditor::default();
+    typed("log ü").iter().for_each(|key| { editor.feed(key); });
+    assert_eq!(editor.line(), "log ü");
+}
+
+#[test]
+fn runs_commands_by_name() {
+    let mock = MockSerial::new().expect(b"\x7fELF").expect(b"\x7fELF").expect(b"\x7fELF");
+    let mut port: SerialPort = Box::new(mock.clone());
+    let (out, log, stats) = (Output::new("T", Verbosity::Quiet), LogSwitch::default(), SessionStats::default());
+    let image = scratch("image").join("kernel.img");
+    fs::write(&image, b"\x7fELF").unwrap();
+
+    let mut commands = LocalCommands::default();
+    let mut ctx = Context::new(&mut port, &out, &log);
+    ctx.stats = Some(&stats);
+    let cases = [
+        ("", Ok(())),
+        ("  help  ", Ok(())),
+        ("stats", Ok(())),
+        ("baud 115200", Ok(())),
+        ("baud fast", Err("usage: baud RATE")),
+        ("send", Err("usage: send PATH")),
+        ("send /nonexistent/image", Err("/nonexistent/image: ")),
+        ("log", Ok(())),
+        ("log stop", Err("not logging")),
+        ("log restart", Err("usage: log start PATH | log stop")),
+        ("hex", Err("usage: hex BYTES")),
+        ("hex 7f 45 4c 46", Ok(())),
+        ("hex", Ok(())),
+        ("hex 7f 4", Err("odd number of hex digits: 7f [4]")),
+        ("scrollback", Err("no scrollback kept in this session")),
+        ("reboot now", Err("unknown command \"reboot\"; commands: log start PATH | log stop, send PATH, hex BYTES, stats, pull [PATH] | pull stop, \
+                            scrollback PATH [LINES], baud RATE, quit, help")),
+    ];
+    for (line, expected) in cases {
+        match (commands.run(line, &mut ctx), expected) {
+            (Ok(()), Ok(())) => {}
+            (Err(e), Err(start)) => assert!(e.starts_with(start), "{:?}: {}", line, e),
+            (result, _) => panic!("{:?}: {:?}", line, result),
+        }
+    }
+    commands.run(&format!("send {}", image.display()), &mut ctx).unwrap();
+    assert!(!ctx.quitting());
+    commands.run("quit", &mut ctx).unwrap();
+    assert!(ctx.quitting());
+
+    mock.assert_done();
+    assert_eq!(mock.settings().baud_rate, 115_200);
+    assert_eq!(stats.summary().sent, 12);
+    let _ = fs::remove_file(&image);
+}
+
+#[test]
+fn parses_hex_bytes() {
+    let cases = [
+        ("AA 01 00 FF", Ok(vec![0xaa, 0x01, 0x00, 0xff])),
+        ("  aa0100ff\t", Ok(vec![0xaa, 0x01, 0x00, 0xff])),
+        ("0xAA 0X01 00ff", Ok(vec![0xaa, 0x01, 0x00, 0xff])),
+        ("AA 1 FF", Err("odd number of hex digits: AA [1] FF")),
+        ("AA 0x FF", Err("no hex digits after 0x: AA [0x] FF")),
+        ("AA 0G FF", Err("not a hex digit: AA 0[G] FF")),
+        ("AA ü", Err("not a hex digit: AA [ü]")),
+        ("0xx1", Err("not a hex digit: 0x[x]1")),
+        ("   ", Err("no bytes given")),
+    ];
+    for (text, expected) in cases {
+        match (parse_hex(text), expected) {
+            (Ok(bytes), Ok(expected)) => assert_eq!(bytes, expected, "{:?}", text),
+            (Err(e), Err(expected)) => assert_eq!(e, expected, "{:?}", text),
+            (result, _) => panic!("{:?}: {:?}", text, result),
+        }
+    }
+    assert_eq!(format_hex(&[0xaa, 0x01, 0x00, 0xff]), "AA 01 00 FF");
+}
+
+#[test]
+fn read_only_sends_nothing() {
+    let mock = MockSerial::new();
+    let mut port: SerialPort = Box::new(mock.clone());
+    let (out, log) = (Output::new("T", Verbosity::Quiet), LogSwitch::default());
+    let mut ctx = Context::new(&mut port, &out, &log);
+    ctx.read_only = true;
+    assert_eq!(LocalCommands::default().run("send Cargo.toml", &mut ctx), Err("read-only, not sent".to_string()));
+    assert_eq!(LocalCommands::default().run("hex AA", &mut ctx), Err("not sent: read-only, nothing is sent".to_string()));
+    assert!(matches!(ctx.send(b"x"), Err(ErrorKind::ReadOnly)));
+    assert!(mock.written().is_empty());
+}
+
+#[test]
+fn logs_from_start_to_stop() {
+    let path = scratch("log").join("session.log");
+    let mut port: SerialPort = Box::new(MockSerial::new());
+    let (out, log) = (Output::new("T", Verbosity::Quiet), Arc::new(LogSwitch::default()));
+    let mut tap = log.clone();
+    let mut commands = LocalCommands::default();
+
+    tap.rx(b"before\n");
+    commands.run(&format!("log start {}", path.display()), &mut Context::new(&mut port, &out, &log)).unwrap();
+    assert_eq!(log.path(), Some(path.clone()));
+    tap.rx(b"during\n");
+    commands.run("log stop", &mut Context::new(&mut port, &out, &log)).unwrap();
+    tap.rx(b"after\n");
+
+    assert_eq!(fs::read(&path).unwrap(), b"during\n");
+    assert_eq!(log.path(), None);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn saves_the_scrollback() {
+    let path = scratch("scrollback").join("scrollback.txt");
+    let mut port: SerialPort = Box::new(MockSerial::new());
+    let (out, log) = (Output::new("T", Verbosity::Quiet), LogSwitch::default());
+    let scrollback = Arc::new(Scrollback::new(1024));
+    let mut tap = scrollback.clone();
+    let mut commands = LocalCommands::default();
+    let mut ctx = Context::new(&mut port, &out, &log);
+    ctx.scrollback = Some(&scrollback);
+
+    // from before any log was started
+    tap.rx(b"U-Boot\nStarting kernel\nKernel panic\n");
+    commands.run(&format!("scrollback {}", path.display()), &mut ctx).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"U-Boot\nStarting kernel\nKernel panic\n");
+    commands.run(&format!("scrollback {} 2", path.display()), &mut ctx).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"Starting kernel\nKernel panic\n");
+    assert_eq!(commands.run("scrollback", &mut ctx).unwrap_err(), "usage: scrollback PATH [LINES]");
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn tools_add_and_replace_commands() {
+    let mut port: SerialPort = Box::new(MockSerial::new());
+    let (out, log) = (Output::new("T", Verbosity::Quiet), LogSwitch::default());
+    let mut commands = LocalCommands::default()
+        .register("reset", "reset", Box::new(|ctx, args| {
+            ctx.port.write_data_terminal_ready(args != "hold").map_err(|e| e.to_string())
+        }))
+        .register("quit", "quit", Box::new(|_, _| Err("not now".to_string())));
+
+    let mut ctx = Context::new(&mut port, &out, &log);
+    assert_eq!(commands.run("reset", &mut ctx), Ok(()));
+    assert_eq!(commands.run("quit", &mut ctx), Err("not now".to_string()));
+    assert!(!ctx.quitting());
+    assert_eq!(commands.names().collect::<Vec<_>>(), ["log", "send", "hex", "stats", "pull", "scrollback", "baud", "reset", "quit"]);
+    assert!(commands.help().ends_with("baud RATE, reset, quit, help"));
+}
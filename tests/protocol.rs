@@ -0,0 +1,526 @@
+#![cfg(feature = "mock")]
+
+use std::{io::{Read, Seek, SeekFrom}, time::{Duration, Instant}};
+
+use rust_serial_tool::{ErrorKind, image::Image, mock::MockSerial, protocol::*, ReadSerial};
+
+#[test]
+fn encodes_sizes() {
+    let cases: [(u64, SizeHeader, &[u8]); 5] = [
+        (6144, SizeHeader::Legacy, &[0x00, 0x18, 0x00, 0x00]),
+        (LEGACY_MAX_SIZE, SizeHeader::Legacy, &[0xFE, 0xFF, 0xFF, 0xFF]),
+        (6144, SizeHeader::Extended, &[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x18, 0, 0, 0, 0, 0, 0]),
+        (5 << 30, SizeHeader::Extended, &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0x40, 0x01, 0, 0, 0]),
+        (0, SizeHeader::Legacy, &[0, 0, 0, 0]),
+    ];
+    for (size, header, expected) in cases.iter() {
+        assert_eq!(encode_size(*size, *header).unwrap(), *expected, "{} {:?}", size, header);
+    }
+    // all ones is the extended marker, not a size
+    assert!(matches!(encode_size(u32::MAX as u64, SizeHeader::Legacy), Err(ErrorKind::ImageTooLarge(_))));
+}
+
+#[test]
+fn legacy_handshake_is_unchanged() {
+    let mock = MockSerial::new().expect(&[0x04, 0x03, 0x02, 0x01]).reply(b"OK");
+    send_size(&mut mock.clone(), 0x0102_0304, SizeHeader::Legacy).unwrap();
+    mock.assert_done();
+
+    let mut port = MockSerial::new().expect_any(4).reply(b"NO");
+    match send_size(&mut port, 16, SizeHeader::Legacy) {
+        Err(ErrorKind::UnexpectedReply { expected, received }) => assert_eq!((expected.as_str(), received.as_slice()), ("OK", &b"NO"[..])),
+        other => panic!("expected UnexpectedReply, got {:?}", other),
+    }
+}
+
+#[test]
+fn scans_for_the_reply() {
+    const SHORT: Duration = Duration::from_millis(300);
+    // reply, window, which answer or the error's name
+    let cases: [(&[u8], usize, std::result::Result<usize, &str>); 8] = [
+        (b"OK", 0, Ok(0)),
+        (b"NO", 0, Ok(1)),
+        (b"\r\nOK", 2, Ok(0)),
+        (b"\r\nNO", 4, Ok(1)),
+        (b"\r\nOK", 1, Err("protocol")),
+        (b"ok", 8, Err("protocol")),
+        (b"\r\nO", 8, Err("protocol")),
+        (b"", 8, Err("timeout")),
+    ];
+    for (reply, window, expected) in cases.iter() {
+        let started = Instant::now();
+        let result = read_reply(&mut MockSerial::new().reply(reply), &[b"OK", b"NO"], *window, SHORT);
+        assert_eq!(result.map_err(|e| e.name()), *expected, "{:?}", reply);
+        assert!(started.elapsed() < SHORT * 2);
+    }
+}
+
+#[test]
+fn unexpected_replies_come_with_a_hex_dump() {
+    let error = read_reply(&mut MockSerial::new().reply(b"\x1b[0mU-Boot 2024.01\r\n"), &[b"OK"], 64, REPLY_TIMEOUT).unwrap_err();
+    assert_eq!(error.to_string(), "expected OK, got 20 bytes:\n\
+        00000000  1b 5b 30 6d 55 2d 42 6f 6f 74 20 32 30 32 34 2e  |.[0mU-Boot 2024.|\n\
+        00000010  30 31 0d 0a                                      |01..|");
+    let error = ErrorKind::UnexpectedReply { expected: "OK or NO".to_string(), received: vec![0] };
+    assert_eq!(error.to_string(), "expected OK or NO, got 1 bytes:\n00000000  00                                               |.|");
+}
+
+#[test]
+fn rejects_images_over_4_gib_without_writing() {
+    let mut image = Image::from_bytes(vec![0; 16]);
+    image.size = 5 << 30;
+
+    let mock = MockSerial::new().expect_any(12).reply(b"OK");
+    match send_size(&mut mock.clone(), image.size, SizeHeader::Legacy) {
+        Err(ErrorKind::ImageTooLarge(size)) => assert_eq!(size, 5 << 30),
+        other => panic!("expected ImageTooLarge, got {:?}", other),
+    }
+    assert!(mock.written().is_empty());
+
+    send_size(&mut mock.clone(), image.size, SizeHeader::Extended).unwrap();
+    mock.assert_done();
+}
+
+#[test]
+fn negotiates_resume() {
+    let cases: [(&[u8], Option<u64>); 3] = [(b"OK", Some(4096)), (b"NO", Some(0)), (b"??", None)];
+    for (reply, expected) in cases.iter() {
+        let mock = MockSerial::new().expect(&[b'R', b'S', 0x00, 0x10, 0, 0, 0, 0, 0, 0]).reply(reply);
+        assert_eq!(offer_resume(&mut mock.clone(), 4096).ok(), *expected, "{:?}", reply);
+        mock.assert_done();
+    }
+}
+
+#[test]
+fn reads_chunk_acks() {
+    assert!(read_ack(&mut MockSerial::new().reply(&[CHUNK_ACK])).is_ok());
+    assert!(matches!(read_ack(&mut MockSerial::new().reply(b"x")), Err(ErrorKind::ProtocolError)));
+    assert!(matches!(read_ack(&mut MockSerial::new()), Err(ErrorKind::ReadTimeout { received: 0, expected: 1 })));
+}
+
+#[test]
+fn resumes_only_the_same_image() {
+    let pushed = PushState { size: 25 << 20, acknowledged: 22 << 20 };
+    assert_eq!(pushed.resume_offset(25 << 20), 22 << 20);
+    assert_eq!(pushed.resume_offset(24 << 20), 0);
+    assert_eq!(PushState::default().resume_offset(1024), 0);
+}
+
+#[test]
+fn image_seeks_to_the_resume_offset() {
+    let mut image = Image::from_bytes((0..=255).collect());
+    image.source.seek(SeekFrom::Start(250)).unwrap();
+    let mut rest = Vec::new();
+    image.source.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, [250, 251, 252, 253, 254, 255]);
+}
+
+#[test]
+fn exact_reads_give_up_after_the_deadline() {
+    let cases: [(&[u8], usize, Option<usize>); 3] = [(b"OK", 2, None), (b"O", 2, Some(1)), (b"", 4, Some(0))];
+    for (reply, len, short) in cases.iter() {
+        let mut port = MockSerial::new().reply(reply);
+        let mut buf = vec![0; *len];
+        let started = Instant::now();
+        match (port.read_serial_exact_timeout(&mut buf, Duration::from_millis(50)), short) {
+            (Ok(()), None) => assert_eq!(&buf[..], *reply),
+            (Err(ErrorKind::ReadTimeout { received, expected }), Some(short)) => {
+                assert_eq!((received, expected), (*short, *len));
+                assert!(started.elapsed() >= Duration::from_millis(50));
+            }
+            (other, _) => panic!("{:?}: unexpected {:?}", reply, other),
+        }
+    }
+}
+
+/// Boot output shown and whether the request was seen, one entry per read.
+fn feed_all(request: &mut RequestMatcher, reads: &[&[u8]]) -> (Vec<u8>, bool) {
+    let mut shown = Vec::new();
+    for read in reads {
+        let (boot_output, requested) = request.feed(read);
+        shown.extend(boot_output);
+        if requested { return (shown, true); }
+    }
+    (shown, false)
+}
+
+/// Spec, the reads, the boot output shown and whether the request was seen.
+type RequestCase<'a> = (&'a str, &'a [&'a [u8]], &'a [u8], bool);
+
+#[test]
+fn spots_binary_requests() {
+    let cases: [RequestCase; 7] = [
+        ("hex:030303", &[b"boot\r\n\x03\x03\x03"], b"boot\r\n", true),
+        ("hex:030303", &[b"boot\x03", b"\x03", b"\x03"], b"boot", true),
+        ("hex:030303", &[b"\x03\x03x\x03\x03"], b"\x03\x03x", false),
+        ("hex:03 03 03", &[b"\x03\x03\x03\x03"], b"", true),
+        ("str:\\r\\nREADY\\r\\n", &[b"banner\r\nREA", b"DY\r\n"], b"banner", true),
+        ("str:READY", &[b"REA", b"L"], b"REAL", false),
+        ("regex:READY\\s", &[b"loader READ", b"Y\r\n"], b"loader READY\r\n", true),
+    ];
+    for (spec, reads, shown, requested) in cases.iter() {
+        let mut request: RequestMatcher = spec.parse().unwrap();
+        assert_eq!(feed_all(&mut request, reads), (shown.to_vec(), *requested), "{}", spec);
+    }
+}
+
+#[test]
+fn parses_binary_requests() {
+    assert_eq!(RequestMatcher::default().to_string(), "hex:030303");
+    assert_eq!("str:READY".parse::<RequestMatcher>().unwrap().to_string(), "\"READY\"");
+    assert_eq!("regex:^OK$".parse::<RequestMatcher>().unwrap().to_string(), "/^OK$/");
+    for bad in ["030303", "hex:", "hex:0", "hex:zz", "str:", "regex:(", "bytes:03"] {
+        assert!(bad.parse::<RequestMatcher>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn negotiates_with_loaders_of_each_vintage() {
+    let cases: [(MockSerial, Option<LoaderInfo>); 3] = [
+        // the classic loader says nothing and gets the size right after
+        (MockSerial::new().expect(&VERSION_PROBE), None),
+        (MockSerial::new().expect(&VERSION_PROBE).reply(b"MPV\x01\x03\x00"),
+         Some(LoaderInfo { version: 1, capabilities: Capabilities::EXTENDED_SIZE | Capabilities::RESUME })),
+        // bits of a later version are dropped
+        (MockSerial::new().expect(&VERSION_PROBE).reply(b"MPV\x07\x04\x80"), Some(LoaderInfo { version: 7, capabilities: Capabilities::BLOCK })),
+    ];
+    for (mock, expected) in cases {
+        let mock = mock.expect(&16u32.to_le_bytes()).reply(b"OK");
+        let mut port = mock.clone();
+        assert_eq!(negotiate(&mut port, NEGOTIATE_TIMEOUT).unwrap(), expected);
+        send_size(&mut port, 16, SizeHeader::Legacy).unwrap();
+        mock.assert_done();
+    }
+
+    // the start of a version and then nothing is neither vintage
+    let mut port = MockSerial::new().expect(&VERSION_PROBE).reply(b"MPV\x01");
+    match negotiate(&mut port, NEGOTIATE_TIMEOUT) {
+        Err(ErrorKind::UnexpectedReply { received, .. }) => assert_eq!(received, b"MPV\x01"),
+        other => panic!("expected UnexpectedReply, got {:?}", other),
+    }
+}
+
+#[test]
+fn sends_the_cmdline_after_the_image() {
+    let mock = MockSerial::new().expect(b"\x11\x00console=ttyS0 -v\x00").reply(b"OK");
+    send_cmdline(&mut mock.clone(), "console=ttyS0 -v").unwrap();
+    mock.assert_done();
+
+    assert_eq!(encode_cmdline(&"x".repeat(CMDLINE_MAX)).unwrap().len(), CMDLINE_MAX + 3);
+    for cmdline in ["x".repeat(CMDLINE_MAX + 1), "a\0b".to_string()] {
+        // nothing is written for a command line that can't be sent
+        let mock = MockSerial::new();
+        let error = send_cmdline(&mut mock.clone(), &cmdline).unwrap_err();
+        assert!(matches!(error, ErrorKind::InvalidCmdline(_)), "{:?}", error);
+        assert!(mock.written().is_empty());
+    }
+
+    assert_eq!(LoaderInfo::assumed(1).capabilities, Capabilities::empty());
+    assert!(LoaderInfo::assumed(2).capabilities.contains(Capabilities::CMDLINE));
+}
+
+/// The actions for each input in turn, in their Debug form, inputs `at` milliseconds after `start`.
+fn drive(machine: &mut Chainboot, start: Instant, inputs: &[(u64, Input)]) -> Vec<String> {
+    inputs.iter()
+        .flat_map(|(at, input)| machine.handle(*input, start + Duration::from_millis(*at)))
+        .map(|action| format!("{:?}", action))
+        .collect()
+}
+
+/// A machine that has seen the default request, sending chunks of 4.
+fn requested(acks: bool) -> Chainboot {
+    let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 4, acks);
+    machine.handle(Input::Received(&BINARY_REQUEST), Instant::now());
+    assert_eq!(*machine.stage(), Stage::Requested);
+    machine
+}
+
+#[test]
+fn chainboot_finds_the_request_however_it_is_split() {
+    let boot = b"boot\r\n\x03\x03\x03";
+    for split in 0..=boot.len() {
+        for second in split..=boot.len() {
+            let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 4, false);
+            let (mut shown, mut requests) = (Vec::new(), 0);
+            for read in [&boot[..split], &boot[split..second], &boot[second..], b"\x03OK"] {
+                for action in machine.handle(Input::Received(read), Instant::now()) {
+                    match action {
+                        Action::Show(bytes) => shown.extend(bytes),
+                        Action::Requested => requests += 1,
+                        action => panic!("{:?}", action),
+                    }
+                }
+            }
+            assert_eq!((shown.as_slice(), requests), (&b"boot\r\n"[..], 1), "split at {} and {}", split, second);
+            assert_eq!(*machine.stage(), Stage::Requested);
+            assert_eq!(machine.deadline(), None);
+        }
+    }
+}
+
+#[test]
+fn chainboot_streams_the_image_in_chunks() {
+    let start = Instant::now();
+    let accepted = "Send([10, 0, 0, 0]), SizeAccepted, SendChunk { offset: 0, len: 4 }";
+    // the OK whole, a byte at a time, after stray bytes and after a false start
+    let replies: [&[&[u8]]; 4] = [&[b"OK"], &[b"O", b"K"], &[b"\x00~", b"O", b"", b"K"], &[b"OOK"]];
+    for reads in replies {
+        let mut machine = requested(false);
+        let mut inputs = vec![(0, Input::Announce { size: 10, resume: None })];
+        inputs.extend(reads.iter().map(|read| (50, Input::Received(read))));
+        assert_eq!(drive(&mut machine, start, &inputs).join(", "), accepted, "{:?}", reads);
+
+        // what the loader prints meanwhile is shown
+        let written = drive(&mut machine, start, &[(60, Input::Written), (70, Input::Received(b"x")), (80, Input::Written), (90, Input::Written)]);
+        assert_eq!(written, [
+            "Progress { sent: 4, total: 10 }", "SendChunk { offset: 4, len: 4 }", "Show([120])",
+            "Progress { sent: 8, total: 10 }", "SendChunk { offset: 8, len: 2 }",
+            "Progress { sent: 10, total: 10 }", "Complete",
+        ]);
+        assert_eq!(*machine.stage(), Stage::Complete);
+        assert!(drive(&mut machine, start, &[(100, Input::Written), (100_000, Input::Tick)]).is_empty());
+    }
+}
+
+#[test]
+fn chainboot_gives_up_on_a_wrong_or_missing_answer() {
+    let start = Instant::now();
+    let junk = [b'x'; REPLY_WINDOW + 2];
+    let cases: [(&[(u64, Input)], &str); 6] = [
+        (&[(4_999, Input::Tick), (5_000, Input::Tick)], "ReadTimeout { received: 0, expected: 2 }"),
+        // stray bytes wait as long as silence does
+        (&[(0, Input::Received(b"ERR")), (4_999, Input::Tick), (5_000, Input::Tick)], "UnexpectedReply { expected: \"OK\", received: [69, 82, 82] }"),
+        // a line gone quiet in the middle of an answer gives up sooner, however long it stayed
+        // quiet before
+        (&[(0, Input::Received(b"O")), (99, Input::Tick), (100, Input::Tick)], "UnexpectedReply { expected: \"OK\", received: [79] }"),
+        (&[(0, Input::Received(b"\r\n")), (3_000, Input::Received(b"O")), (3_099, Input::Tick), (3_100, Input::Tick)], "UnexpectedReply { expected: \"OK\", received: [13, 10, 79] }"),
+        // the answer still counts after a quiet line, if it comes before the tick
+        (&[(0, Input::Received(b"x")), (4_000, Input::Received(b"OK"))], "SizeAccepted"),
+        (&[(0, Input::Received(&junk))], "UnexpectedReply"),
+    ];
+    for (inputs, expected) in cases {
+        let mut machine = requested(false);
+        machine.handle(Input::Announce { size: 10, resume: None }, start);
+        let actions = drive(&mut machine, start, inputs);
+        assert!(actions.first().is_some_and(|action| action.contains(expected)), "{:?} gave {:?}", inputs, actions);
+        if expected != "SizeAccepted" {
+            assert_eq!(actions.len(), 1);
+            assert_eq!((machine.stage(), machine.deadline()), (&Stage::Failed, None));
+            assert!(drive(&mut machine, start, &[(0, Input::Received(b"OK")), (0, Input::Written)]).is_empty());
+        }
+    }
+}
+
+#[test]
+fn chainboot_resumes_where_the_loader_agrees() {
+    let start = Instant::now();
+    let cases: [(u64, &[u8], &str); 4] = [
+        (4, b"OK", "ResumeAnswered { offset: 4 }, SendChunk { offset: 4, len: 4 }"),
+        (4, b"NO", "ResumeAnswered { offset: 0 }, SendChunk { offset: 0, len: 4 }"),
+        (4, b"..N..NO", "ResumeAnswered { offset: 0 }, SendChunk { offset: 0, len: 4 }"),
+        // an offer past the end, for an image that shrank, ends the push
+        (20, b"OK", "ResumeAnswered { offset: 10 }, Complete"),
+    ];
+    for (at, answer, expected) in cases {
+        let mut machine = requested(false);
+        // the size's OK and the resume answer in one read
+        let reply = [&b"OK"[..], answer].concat();
+        let actions = drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: Some(at) }), (10, Input::Received(&reply))]);
+        let offer = [&RESUME_REQUEST[..], &at.to_le_bytes()].concat();
+        assert_eq!(actions.join(", "), format!("Send([10, 0, 0, 0]), SizeAccepted, Send({:?}), {}", offer, expected));
+    }
+}
+
+#[test]
+fn chainboot_waits_for_each_chunk_ack() {
+    let start = Instant::now();
+    let mut machine = requested(true);
+    drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: None }), (0, Input::Received(b"OK"))]);
+
+    // an ack while nothing is written yet counts once the chunk is
+    assert!(drive(&mut machine, start, &[(10, Input::Received(&[CHUNK_ACK]))]).is_empty());
+    assert_eq!(drive(&mut machine, start, &[(20, Input::Written)]), ["Progress { sent: 4, total: 10 }", "SendChunk { offset: 4, len: 4 }"]);
+    // two acks in one read, the second for a chunk still being written
+    assert!(drive(&mut machine, start, &[(30, Input::Written)]).is_empty());
+    assert_eq!(machine.deadline(), Some(start + Duration::from_millis(30) + ACK_TIMEOUT));
+    assert_eq!(drive(&mut machine, start, &[(40, Input::Received(&[CHUNK_ACK, CHUNK_ACK]))]), ["Progress { sent: 8, total: 10 }", "SendChunk { offset: 8, len: 2 }"]);
+    assert_eq!(drive(&mut machine, start, &[(50, Input::Written)]), ["Progress { sent: 10, total: 10 }", "Complete"]);
+
+    let cases: [(Input, &str); 2] = [(Input::Received(b"K"), "ProtocolError"), (Input::Tick, "ReadTimeout { received: 0, expected: 1 }")];
+    for (input, expected) in cases {
+        let mut machine = requested(true);
+        drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: None }), (0, Input::Received(b"OK")), (0, Input::Written)]);
+        let at = ACK_TIMEOUT.as_millis() as u64;
+        assert_eq!(drive(&mut machine, start, &[(at - 1, Input::Tick), (at, input)]), [format!("Failed({})", expected)]);
+    }
+}
+
+#[test]
+fn chainboot_ignores_steps_out_of_turn() {
+    let start = Instant::now();
+    let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 4, false);
+    assert!(drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: None }), (0, Input::Written), (100_000, Input::Tick)]).is_empty());
+    assert_eq!(*machine.stage(), Stage::Request);
+
+    // an empty image is complete once the loader took its size
+    let mut machine = requested(false);
+    let actions = drive(&mut machine, start, &[(0, Input::Announce { size: 0, resume: None }), (0, Input::Announce { size: 10, resume: None }), (0, Input::Received(b"OK"))]);
+    assert_eq!(actions, ["Send([0, 0, 0, 0])", "SizeAccepted", "Complete"]);
+
+    let mut machine = requested(false);
+    let actions = drive(&mut machine, start, &[(0, Input::Announce { size: LEGACY_MAX_SIZE + 1, resume: None })]);
+    assert_eq!(actions, ["Failed(ImageTooLarge(4294967295))"]);
+}
+
+#[test]
+fn pump_carries_out_the_exchange_on_a_port() {
+    let mut port = MockSerial::new().expect(&8u32.to_le_bytes()).reply(b"~OK\x06\x06boot");
+    let mut machine = requested(true);
+    let actions = machine.handle(Input::Announce { size: 8, resume: None }, Instant::now());
+    let actions: Vec<String> = pump(&mut port, &mut machine, actions).unwrap().iter().map(|action| format!("{:?}", action)).collect();
+    assert_eq!(actions, ["SizeAccepted", "SendChunk { offset: 0, len: 4 }"]);
+    let actions = machine.handle(Input::Written, Instant::now());
+    let actions: Vec<String> = pump(&mut port, &mut machine, actions).unwrap().iter().map(|action| format!("{:?}", action)).collect();
+    assert_eq!(actions, ["Progress { sent: 4, total: 8 }", "SendChunk { offset: 4, len: 4 }"]);
+    let actions = machine.handle(Input::Written, Instant::now());
+    let actions: Vec<String> = pump(&mut port, &mut machine, actions).unwrap().iter().map(|action| format!("{:?}", action)).collect();
+    assert_eq!(actions, ["Progress { sent: 8, total: 8 }", "Complete"]);
+    // what the kernel says after the last ack is left for the terminal
+    let mut rest = [0; 4];
+    port.read_serial_exact(&mut rest).unwrap();
+    assert_eq!(&rest, b"boot");
+    port.assert_done();
+
+    let mut port = MockSerial::new().expect_any(4).reply(&[b'x'; REPLY_WINDOW + 2]);
+    let mut machine = requested(false);
+    let actions = machine.handle(Input::Announce { size: 8, resume: None }, Instant::now());
+    let error = pump(&mut port, &mut machine, actions).unwrap_err();
+    assert!(matches!(error, ErrorKind::UnexpectedReply { .. }), "{:?}", error);
+}
+
+#[test]
+fn chainboot_starts_over_when_the_loader_asks_again() {
+    let start = Instant::now();
+    for acks in [false, true] {
+        let mut machine = requested(acks);
+        drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: None }), (0, Input::Received(b"OK"))]);
+        let progressed = drive(&mut machine, start, &[(10, Input::Written), (20, Input::Received(&[CHUNK_ACK]))]);
+        assert_eq!(progressed.first().map(String::as_str), Some("Progress { sent: 4, total: 10 }"), "acks {}", acks);
+        // the request split over reads, while a chunk is out or being written
+        let inputs = if acks { [(30, Input::Written), (40, Input::Received(b"\x03\x03")), (50, Input::Received(b"\x03"))] }
+                     else { [(30, Input::Received(b"boot\r\n\x03")), (40, Input::Received(b"\x03")), (50, Input::Received(b"\x03\x03"))] };
+        let expected: &[&str] = if acks { &["Rebooted { at: 8 }"] } else { &["Show([98, 111, 111, 116, 13, 10])", "Rebooted { at: 8 }"] };
+        assert_eq!(drive(&mut machine, start, &inputs), expected, "acks {}", acks);
+        assert_eq!((machine.stage(), machine.deadline()), (&Stage::Requested, None));
+        let again = drive(&mut machine, start, &[(60, Input::Announce { size: 10, resume: None }), (70, Input::Received(b"OK"))]);
+        assert_eq!(again, ["Send([10, 0, 0, 0])", "SizeAccepted", "SendChunk { offset: 0, len: 4 }"]);
+    }
+
+    // what looked like the start of the request and then wasn't is no ack
+    let mut machine = requested(true);
+    drive(&mut machine, start, &[(0, Input::Announce { size: 10, resume: None }), (0, Input::Received(b"OK")), (0, Input::Written)]);
+    assert_eq!(drive(&mut machine, start, &[(10, Input::Received(b"\x03")), (20, Input::Received(&[CHUNK_ACK]))]), ["Failed(ProtocolError)"]);
+}
+
+#[test]
+fn hears_the_loader_restart_during_the_push() {
+    // the board resets after the first chunk and asks again; the next push goes through
+    let mock = MockSerial::new()
+        .expect(&4u32.to_le_bytes()).reply(b"OK").expect(b"ab").reply(b"\x03\x03\x03")
+        .expect(&4u32.to_le_bytes()).reply(b"OK").expect(b"ab").expect(b"cd");
+    let mut port: rust_serial_tool::SerialPort = Box::new(mock.clone());
+    let mut machine = Chainboot::new(RequestMatcher::default(), SizeHeader::Legacy, 2, false);
+    machine.handle(Input::Received(&BINARY_REQUEST), Instant::now());
+    let mut events = Vec::new();
+    for _ in 0..2 {
+        let mut actions = machine.handle(Input::Announce { size: 4, resume: None }, Instant::now());
+        'push: loop {
+            for action in pump(&mut port, &mut machine, actions).unwrap() {
+                match action {
+                    Action::SendChunk { offset, len } => port.write_all(&b"abcd"[offset as usize..offset as usize + len]).unwrap(),
+                    Action::Rebooted { at } => {
+                        events.push(format!("rebooted at {}", at));
+                        break 'push;
+                    }
+                    Action::Complete => {
+                        events.push("complete".to_string());
+                        break 'push;
+                    }
+                    _ => {}
+                }
+            }
+            actions = machine.handle(Input::Received(&heard(port.as_mut()).unwrap()), Instant::now());
+            actions.extend(machine.handle(Input::Written, Instant::now()));
+        }
+    }
+    assert_eq!(events, ["rebooted at 2", "complete"]);
+    mock.assert_done();
+}
+
+fn baud_request(rate: u32) -> Vec<u8> {
+    BAUD_REQUEST.iter().chain(&rate.to_le_bytes()).copied().collect()
+}
+
+fn baud_check() -> Vec<u8> {
+    BAUD_CHECK.iter().chain(&BAUD_CHECK_PATTERN).copied().collect()
+}
+
+#[test]
+fn baud_ladder_tries_the_faster_rates_fastest_first() {
+    let mut ladder = BaudLadder::new(115_200, &[460_800, 57_600, 3_000_000, 115_200, 921_600, 460_800]);
+    assert_eq!(ladder.rates(), [3_000_000, 921_600, 460_800]);
+    assert_eq!(ladder.propose(), Some(3_000_000));
+    assert_eq!(ladder.propose(), None, "not before the answer");
+    assert_eq!(ladder.answered(false), None);
+    assert_eq!(ladder.propose(), Some(921_600));
+    assert_eq!(ladder.answered(true), Some(921_600));
+    assert_eq!(ladder.rate(), 921_600);
+    assert_eq!(ladder.checked(false), Some(BaudSwitch::Reverted { from: 921_600, to: 115_200 }));
+    assert_eq!(ladder.propose(), Some(460_800));
+    ladder.answered(true);
+    assert_eq!(ladder.checked(true), Some(BaudSwitch::Confirmed { rate: 460_800 }));
+    assert_eq!((ladder.stage(), ladder.propose()), (BaudStage::Confirmed(460_800), None));
+
+    let mut ladder = BaudLadder::new(921_600, &[115_200, 460_800]);
+    assert_eq!((ladder.propose(), ladder.stage(), ladder.rate()), (None, BaudStage::Exhausted, 921_600));
+}
+
+#[test]
+fn steps_the_line_up_past_declined_and_garbled_rates() {
+    let mock = MockSerial::new()
+        .expect(&baud_request(3_000_000)).reply(b"NO")
+        .expect(&baud_request(921_600)).reply(b"OK").expect(&baud_check()).reply(b"\x55\xAA\x00\xFF\x0F\xF0\x13\xCC")
+        .expect(&baud_request(460_800)).reply(b"OK").expect(&baud_check()).reply(&BAUD_CHECK_PATTERN);
+    let mut port: rust_serial_tool::SerialPort = Box::new(mock.clone());
+    port.set_baud_rate(115_200).unwrap();
+    let mut ladder = BaudLadder::new(115_200, &[460_800, 921_600, 3_000_000]);
+    let mut switches = Vec::new();
+    assert_eq!(step_baud(port.as_mut(), &mut ladder, Duration::ZERO, |switch| switches.push(switch)).unwrap(), 460_800);
+    assert_eq!(switches, [
+        BaudSwitch::Declined { rate: 3_000_000 },
+        BaudSwitch::Switched { from: 115_200, to: 921_600 },
+        BaudSwitch::Reverted { from: 921_600, to: 115_200 },
+        BaudSwitch::Switched { from: 115_200, to: 460_800 },
+        BaudSwitch::Confirmed { rate: 460_800 },
+    ]);
+    assert_eq!(mock.settings().baud_rate, 460_800);
+    mock.assert_done();
+}
+
+#[test]
+fn stays_at_the_base_rate_when_nothing_checks_out() {
+    // no echo at all: the loader went back on its own
+    let mock = MockSerial::new().expect(&baud_request(921_600)).reply(b"OK").expect(&baud_check());
+    let mut port: rust_serial_tool::SerialPort = Box::new(mock.clone());
+    port.set_baud_rate(115_200).unwrap();
+    let mut ladder = BaudLadder::new(115_200, &[921_600]);
+    assert_eq!(step_baud(port.as_mut(), &mut ladder, Duration::ZERO, |_| {}).unwrap(), 115_200);
+    assert_eq!((ladder.stage(), mock.settings().baud_rate), (BaudStage::Exhausted, 115_200));
+    mock.assert_done();
+
+    // nothing faster than the line, nothing asked
+    let mock = MockSerial::new();
+    let mut port: rust_serial_tool::SerialPort = Box::new(mock.clone());
+    assert_eq!(step_baud(port.as_mut(), &mut BaudLadder::new(115_200, &[9_600]), Duration::ZERO, |_| {}).unwrap(), 115_200);
+    assert!(mock.written().is_empty());
+}
@@ -0,0 +1,1042 @@
+//! End to end against a pseudo-terminal standing in for the target: the tools open the slave
+//! side like any serial port, the test plays the loader on the master side.
+#![cfg(unix)]
+
+use std::{ffi::CStr, fs::{self, File}, io::{Read, Write}, os::unix::io::{AsRawFd, FromRawFd}, path::{Path, PathBuf}};
+use std::{process::{Child, Command, ExitStatus, Stdio}, ptr, sync::{Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use rust_serial_tool::{delta::{self, Manifest}, identity, lock, protocol::{self, SizeHeader}, ReadSerial, settings::SerialSettings, sha256::{self, Sha256}, transport::Target, WRITE_TIMEOUT, WriteSerial};
+
+/// Both ends of a pseudo-terminal. The slave stays open so the master reads don't fail while
+/// no tool has it open.
+struct Pty {
+    master: File,
+    slave: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    fn open() -> Pty {
+        let (mut master, mut slave) = (0, 0);
+        let mut name = [0 as libc::c_char; 64];
+        let opened = unsafe { libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), ptr::null(), ptr::null()) };
+        assert_eq!(opened, 0, "openpty failed");
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) }.to_str().unwrap().into();
+        unsafe {
+            // the tools the tests start must not hold on to either end
+            libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+            // raw, so the test sees the bytes the tool wrote and nothing else
+            let mut termios = std::mem::zeroed();
+            libc::tcgetattr(slave, &mut termios);
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(slave, libc::TCSANOW, &termios);
+            Pty { master: File::from_raw_fd(master), slave: File::from_raw_fd(slave), path }
+        }
+    }
+
+    fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.master.write_all(data).unwrap();
+    }
+
+    /// Exactly `n` bytes from the tool, or a panic once `timeout` passes.
+    fn expect(&mut self, n: usize, timeout: Duration) -> Vec<u8> {
+        let deadline = Instant::now() + timeout;
+        let mut received = Vec::with_capacity(n);
+        let mut buf = [0; 4096];
+        while received.len() < n {
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(!left.is_zero(), "got {} of {} bytes", received.len(), n);
+            let mut fd = libc::pollfd { fd: self.master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            if unsafe { libc::poll(&mut fd, 1, left.as_millis() as i32) } <= 0 { continue; }
+            let len = self.master.read(&mut buf[..(n - received.len()).min(4096)]).unwrap();
+            received.extend_from_slice(&buf[..len]);
+        }
+        received
+    }
+}
+
+/// A tool under test, killed if the test fails before it exits.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts `command` with stdout and stderr into one pipe, so what the tool printed comes in
+/// the order it did, and gives back the reading end.
+fn spawn_merged(command: &mut Command) -> (Running, File) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0, "pipe failed");
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    let running = Running(command.stdout(write.try_clone().unwrap()).stderr(write).spawn().unwrap());
+    // the writing end is the tool's alone, or the reads never end
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+    (running, read)
+}
+
+fn open_port(pty: &Pty) -> rust_serial_tool::SerialPort {
+    Target::parse(pty.path()).open(&SerialSettings::default(), Duration::from_millis(10)).unwrap()
+}
+
+#[test]
+fn opens_a_pty_like_a_port() {
+    let mut pty = Pty::open();
+    let mut port = open_port(&pty);
+
+    port.write_serial_all(b"ping", WRITE_TIMEOUT).unwrap();
+    assert_eq!(pty.expect(4, Duration::from_secs(2)), b"ping");
+
+    pty.send(b"pong");
+    let mut buf = [0; 4];
+    port.read_serial_exact_timeout(&mut buf, Duration::from_secs(2)).unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+#[test]
+fn size_handshake_over_a_pty() {
+    let mut pty = Pty::open();
+    let mut port = open_port(&pty);
+
+    pty.send(b"OK");
+    protocol::send_size(&mut port, 0x1234, SizeHeader::Legacy).unwrap();
+    assert_eq!(pty.expect(4, Duration::from_secs(2)), 0x1234u32.to_le_bytes());
+}
+
+/// A mini_push under test and what it has printed so far.
+struct Push {
+    running: Running,
+    stdout: Arc<Mutex<String>>,
+    reader: JoinHandle<()>,
+    image_path: PathBuf,
+}
+
+/// Starts mini_push with `args` and the environment `envs` against `pty`, and waits until it
+/// asks for the target to be powered.
+fn start_push(pty: &Pty, name: &str, image: &[u8], args: &[&str], envs: &[(&str, &str)]) -> Push {
+    let push = spawn_push(pty, name, image, &[&["--no-terminal"], args].concat(), envs, Stdio::null());
+    push.wait_for("power the target", 0);
+    push
+}
+
+/// Starts mini_push like [`start_push`] without waiting, and with its terminal, whose keys
+/// come from `stdin`.
+fn spawn_push(pty: &Pty, name: &str, image: &[u8], args: &[&str], envs: &[(&str, &str)], stdin: Stdio) -> Push {
+    let image_path = std::env::temp_dir().join(format!("pty-{}-{}.img", name, std::process::id()));
+    fs::write(&image_path, image).unwrap();
+    spawn_push_file(pty.path(), image_path, args, envs, stdin)
+}
+
+/// Starts mini_push like [`spawn_push`] on an image file already there, pushing to `device`.
+fn spawn_push_file(device: &str, image_path: PathBuf, args: &[&str], envs: &[(&str, &str)], stdin: Stdio) -> Push {
+    // --force: a run killed earlier may have left its lock file behind
+    let (push, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_push"))
+        .args([device, image_path.to_str().unwrap(), "--force", "--color", "never", "--progress", "never"])
+        .args(args)
+        .envs(envs.iter().copied())
+        .stdin(stdin));
+
+    // collected on a thread, so the test can tell when the tool starts listening
+    let stdout = Arc::new(Mutex::new(String::new()));
+    let collected = stdout.clone();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+            collected.lock().unwrap().push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    });
+    Push { running: push, stdout, reader, image_path }
+}
+
+impl Push {
+    /// Waits until `text` shows up in what the tool printed, past the first `from` bytes.
+    fn wait_for(&self, text: &str, from: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !self.stdout.lock().unwrap().get(from..).is_some_and(|printed| printed.contains(text)) {
+            assert!(Instant::now() < deadline, "{}", self.stdout.lock().unwrap());
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn printed(&self) -> usize {
+        self.stdout.lock().unwrap().len()
+    }
+
+    /// How the tool exited and what it printed.
+    fn finish(mut self) -> (ExitStatus, String) {
+        let status = self.running.0.wait().unwrap();
+        self.reader.join().unwrap();
+        let _ = fs::remove_file(&self.image_path);
+        let stdout = self.stdout.lock().unwrap().clone();
+        (status, stdout)
+    }
+}
+
+/// Runs mini_push with `args` against `pty` playing the loader, which prints `boot` once it
+/// has the image. Gives back how the tool exited and what it printed.
+fn push_over(pty: &mut Pty, name: &str, image: &[u8], args: &[&str], boot: &[u8]) -> (ExitStatus, String) {
+    let push = start_push(pty, name, image, args, &[]);
+    pty.send(b"booting \xe2\x9c\x93\r\n");
+    pty.send(&[0x03; 3]);
+    let size = pty.expect(4, Duration::from_secs(5));
+    assert_eq!(size, (image.len() as u32).to_le_bytes());
+    pty.send(b"OK");
+    assert_eq!(pty.expect(image.len(), Duration::from_secs(5)), image);
+    pty.send(boot);
+    push.finish()
+}
+
+#[test]
+fn mini_push_pushes_an_image() {
+    let mut pty = Pty::open();
+    let image: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+    let (status, stdout) = push_over(&mut pty, "push", &image, &[], b"");
+    assert!(status.success(), "{}", stdout);
+    // boot output comes through in plain lines, the request bytes don't
+    assert!(stdout.contains("booting \u{2713}\n"), "{}", stdout);
+    assert!(!stdout.contains('\x03'), "{}", stdout);
+}
+
+#[test]
+fn mini_push_traces_the_handshake() {
+    let mut pty = Pty::open();
+    let (status, stdout) = push_over(&mut pty, "trace", b"kernel", &["-vv"], b"");
+    assert!(status.success(), "{}", stdout);
+    // each step of the exchange in the order it happened
+    let steps = ["rx hex:030303", "tx size 6 as [06, 00, 00, 00]", "rx OK", "chunk 0..6"];
+    let at: Vec<usize> = steps.iter().map(|step| stdout.find(step).unwrap_or_else(|| panic!("{:?} not in {}", step, stdout))).collect();
+    assert!(at.windows(2).all(|pair| pair[0] < pair[1]), "{}", stdout);
+}
+
+#[test]
+fn mini_push_starts_over_when_the_target_reboots() {
+    let mut pty = Pty::open();
+    let image = vec![0xAA; 32 * 1024];
+    let size = (image.len() as u32).to_le_bytes();
+    let push = start_push(&pty, "reboot", &image, &["--chunk-size", "256"], &[]);
+    pty.send(&[0x03; 3]);
+    assert_eq!(pty.expect(4, Duration::from_secs(5)), size);
+    pty.send(b"OK");
+    pty.expect(1024, Duration::from_secs(5));
+    // reset a quarter in: the tool sends what it had under way, then the size again
+    pty.send(&[0x03; 3]);
+    let mut tail = Vec::new();
+    while !tail.ends_with(&size) {
+        tail.extend(pty.expect(1, Duration::from_secs(5)));
+        assert!(tail.len() < image.len(), "the push went on after the reset");
+    }
+    pty.send(b"OK");
+    assert_eq!(pty.expect(image.len(), Duration::from_secs(5)), image);
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("target rebooted at byte "), "{}", stdout);
+}
+
+#[test]
+fn mini_push_holds_what_the_loader_prints_during_the_push() {
+    let mut pty = Pty::open();
+    let image = vec![0x55; 8 * 1024];
+    // --boot-secs: a loaded machine may be done pushing before the line comes in, and then
+    // it still has to be read
+    let push = start_push(&pty, "early", &image, &["--chunk-size", "256", "--boot-secs", "1"], &[]);
+    pty.send(&[0x03; 3]);
+    pty.expect(4, Duration::from_secs(5));
+    pty.send(b"OK");
+    pty.expect(1024, Duration::from_secs(5));
+    pty.send(b"crc ok\r\n");
+    pty.expect(image.len() - 1024, Duration::from_secs(5));
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    // held until the push is over, then shown like the rest of the boot output
+    let finished = stdout.find("send finish!").unwrap();
+    assert!(stdout[finished..].contains("crc ok\n"), "{}", stdout);
+}
+
+#[test]
+fn mini_push_watches_the_boot_for_the_ci_markers() {
+    let mut pty = Pty::open();
+    // no --boot-secs: the markers decide when it is over
+    let args = ["--exit-on-success", "login:", "--exit-on-failure", "Kernel panic", "--failure-code", "7"];
+    let (status, stdout) = push_over(&mut pty, "markers", b"kernel", &args, b"[ 0.1] Kernel panic - not syncing\r\n");
+    assert_eq!(status.code(), Some(7), "{}", stdout);
+    assert!(stdout.contains("Matched \"[ 0.1] Kernel panic - not syncing\", exiting with 7"), "{}", stdout);
+}
+
+#[test]
+fn mini_push_scripted_keeps_stdout_to_the_target() {
+    let mut pty = Pty::open();
+    let image_path = std::env::temp_dir().join(format!("pty-script-{}.img", std::process::id()));
+    fs::write(&image_path, b"kernel").unwrap();
+    // -v, so there is a status line on stderr to tell when it listens
+    let mut push = Running(Command::new(env!("CARGO_BIN_EXE_mini_push"))
+        .args([pty.path(), image_path.to_str().unwrap(), "--script", "--force", "-v", "--boot-secs", "1"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap());
+    let stderr = Arc::new(Mutex::new(String::new()));
+    let mut pipe = push.0.stderr.take().unwrap();
+    let collected = stderr.clone();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+            collected.lock().unwrap().push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    });
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !stderr.lock().unwrap().contains("power the target") {
+        assert!(Instant::now() < deadline, "{}", stderr.lock().unwrap());
+        thread::sleep(Duration::from_millis(10));
+    }
+    pty.send(b"booting\r\n");
+    pty.send(&[0x03; 3]);
+    assert_eq!(pty.expect(4, Duration::from_secs(5)), 6u32.to_le_bytes());
+    pty.send(b"OK");
+    assert_eq!(pty.expect(6, Duration::from_secs(5)), b"kernel");
+    pty.send(b"Booted\r\n");
+    let mut stdout = String::new();
+    push.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+    let status = push.0.wait().unwrap();
+    reader.join().unwrap();
+    let _ = fs::remove_file(&image_path);
+    let stderr = stderr.lock().unwrap().clone();
+    assert_eq!(status.code(), Some(0), "{}", stderr);
+    assert_eq!(stdout, "booting\nBooted\n");
+    assert!(stderr.contains("[MP] send finish! 6 B"), "{}", stderr);
+    assert!(!stderr.contains('\u{1b}'), "{}", stderr);
+
+    // nothing to wait for: a port that isn't there is exit code 4 right away
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_mini_push"))
+        .args(["/dev/ttyNOPE0", "Cargo.toml", "--script"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(status.status.code(), Some(4), "{}", String::from_utf8_lossy(&status.stderr));
+    assert!(status.stdout.is_empty(), "{}", String::from_utf8_lossy(&status.stdout));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+/// Plays the loader's side of a push of `image` over `pty`.
+fn handshake(pty: &mut Pty, image: &[u8]) {
+    pty.send(&[0x03; 3]);
+    assert_eq!(pty.expect(4, Duration::from_secs(5)), (image.len() as u32).to_le_bytes());
+    pty.send(b"OK");
+    assert_eq!(pty.expect(image.len(), Duration::from_secs(5)), image);
+}
+
+#[test]
+fn mini_push_pushes_again_from_the_terminal() {
+    let mut pty = Pty::open();
+    // keys come from another pseudo-terminal, piped input has no commands
+    let mut keys = Pty::open();
+    let push = spawn_push(&pty, "again", b"first build", &[], &[], Stdio::from(keys.slave.try_clone().unwrap()));
+    push.wait_for("power the target", 0);
+    handshake(&mut pty, b"first build");
+    push.wait_for("quits", 0);
+
+    // the next build, picked up by the prefix and p
+    fs::write(&push.image_path, b"second, larger build").unwrap();
+    let printed = push.printed();
+    keys.send(b"\x01p");
+    push.wait_for("power the target", printed);
+    handshake(&mut pty, b"second, larger build");
+    let printed = push.printed();
+    push.wait_for("quits", printed);
+    keys.send(b"\x01q");
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("pushing the image again"), "{}", stdout);
+    assert!(stdout.contains("Pushing 20 B"), "{}", stdout);
+}
+
+#[test]
+fn mini_push_without_a_terminal_stops_after_the_boot_output() {
+    let mut pty = Pty::open();
+    // keys a terminal would read, left alone
+    let keys = Pty::open();
+    let push = spawn_push(&pty, "no-terminal", b"kernel", &["--no-terminal", "--boot-secs", "1"], &[], Stdio::from(keys.slave.try_clone().unwrap()));
+    push.wait_for("power the target", 0);
+    handshake(&mut pty, b"kernel");
+    let pushed = Instant::now();
+    pty.send(b"Starting kernel\r\n");
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert!(pushed.elapsed() >= Duration::from_secs(1), "{}", stdout);
+    assert!(stdout.contains("Starting kernel\n"), "{}", stdout);
+    assert!(!stdout.contains("quits"), "{}", stdout);
+}
+
+#[test]
+fn mini_push_watches_the_image() {
+    let mut pty = Pty::open();
+    let mut keys = Pty::open();
+    let push = spawn_push(&pty, "watch", b"first build", &["--watch"], &[], Stdio::from(keys.slave.try_clone().unwrap()));
+    push.wait_for("power the target", 0);
+    handshake(&mut pty, b"first build");
+    push.wait_for("quits", 0);
+
+    // the next build is pushed on its own, no key pressed
+    fs::write(&push.image_path, b"second, larger build").unwrap();
+    let printed = push.printed();
+    push.wait_for("changed", printed);
+    push.wait_for("power the target", printed);
+    handshake(&mut pty, b"second, larger build");
+    let printed = push.printed();
+    push.wait_for("quits", printed);
+    keys.send(b"\x01q");
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("Pushing 20 B"), "{}", stdout);
+}
+
+#[test]
+fn mini_push_settles_after_the_size() {
+    let mut pty = Pty::open();
+    let started = Instant::now();
+    let (status, stdout) = push_over(&mut pty, "settle", b"kernel", &["--post-ack-delay", "300", "-v"], b"");
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("Waiting 300 ms after the size was acknowledged"), "{}", stdout);
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn mini_push_keeps_the_options_for_each_of_several_boards() {
+    let (mut a, mut b) = (Pty::open(), Pty::open());
+    let image_path = std::env::temp_dir().join(format!("pty-boards-{}.img", std::process::id()));
+    fs::write(&image_path, b"kernel").unwrap();
+    let boards = format!("{},{}", a.path(), b.path());
+    let push = spawn_push_file(&boards, image_path, &["--no-terminal", "--post-ack-delay", "300", "-v"], &[], Stdio::null());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while push.stdout.lock().unwrap().matches("power the target").count() < 2 {
+        assert!(Instant::now() < deadline, "{}", push.stdout.lock().unwrap());
+        thread::sleep(Duration::from_millis(10));
+    }
+    handshake(&mut a, b"kernel");
+    handshake(&mut b, b"kernel");
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert_eq!(stdout.matches("Waiting 300 ms after the size was acknowledged").count(), 2, "{}", stdout);
+}
+
+#[test]
+fn mini_push_leaves_the_wrong_board_alone() {
+    let mut pty = Pty::open();
+    let push = start_push(&pty, "identify", b"kernel", &["--expect-target", "serial=00000000a1b2c3d4"], &[]);
+    pty.send(&[0x03; 3]);
+    assert_eq!(pty.expect(4, Duration::from_secs(5)), identity::IDENTITY_PROBE);
+    pty.send(&identity::encode("serial=00000000e5f6a7b8 mac=dc:a6:32:0a:0b:0c"));
+    let (status, stdout) = push.finish();
+    assert!(!status.success(), "{}", stdout);
+    assert!(stdout.contains("Target serial=00000000e5f6a7b8"), "{}", stdout);
+    assert!(stdout.contains("not serial=00000000a1b2c3d4; nothing was pushed"), "{}", stdout);
+    // not even the size went out
+    let mut fd = libc::pollfd { fd: pty.master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    assert_eq!(unsafe { libc::poll(&mut fd, 1, 0) }, 0, "wrote after the identity");
+}
+
+#[test]
+fn mini_push_logs_what_it_sent() {
+    let mut pty = Pty::open();
+    let path = std::env::temp_dir().join(format!("pty-log-tx-{}.bin", std::process::id()));
+    let image: Vec<u8> = (0..20_000u32).map(|i| (i * 13) as u8).collect();
+    let (status, stdout) = push_over(&mut pty, "log-tx", &image, &["--log-tx", path.to_str().unwrap()], b"");
+    assert!(status.success(), "{}", stdout);
+    // the size and the image, byte for byte as the loader got them
+    assert_eq!(fs::read(&path).unwrap(), [&(image.len() as u32).to_le_bytes()[..], &image].concat());
+    assert!(!stdout.contains("--log-tx"), "{}", stdout);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn mini_push_reports_the_push() {
+    let mut pty = Pty::open();
+    let path = std::env::temp_dir().join(format!("pty-report-{}.json", std::process::id()));
+    let (status, stdout) = push_over(&mut pty, "report", b"kernel", &["--report", path.to_str().unwrap(), "--cmdline", "quiet"], b"");
+    assert!(status.success(), "{}", stdout);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(report["status"], "ok", "{}", report);
+    assert_eq!(report["device"], pty.path());
+    // the loader takes no command line, so none was sent
+    assert_eq!(report["features"], serde_json::json!([]));
+    assert_eq!(report["push"]["total"], 6);
+    assert_eq!(report["push"]["sha256"].as_str().unwrap().len(), 64);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn mini_push_wants_an_image_piped_into_stdin() {
+    // a terminal for stdin, or a pipe with nothing in it, both refused before the port is opened
+    let terminal = Pty::open();
+    let cases = [
+        (Stdio::from(File::options().read(true).write(true).open(terminal.path()).unwrap()), "-: stdin is a terminal, pipe the image in"),
+        (Stdio::null(), "-: stdin is empty"),
+    ];
+    for (stdin, said) in cases {
+        let output = Command::new(env!("CARGO_BIN_EXE_mini_push"))
+            .args(["/dev/ttyNOPE0", "-", "--color", "never"])
+            .stdin(stdin)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{}", stderr);
+        assert!(stderr.contains(said), "{}", stderr);
+    }
+}
+
+#[test]
+fn mini_push_names_a_cmdline_file_it_cannot_read() {
+    let missing = std::env::temp_dir().join(format!("pty-no-cmdline-{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_mini_push"))
+        .args(["/dev/ttyNOPE0", "Cargo.toml", "--color", "never", "--cmdline-file", missing.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains(&format!("--cmdline-file {}: No such file or directory", missing.display())), "{}", stderr);
+}
+
+#[test]
+fn mini_push_refuses_a_size_the_header_cannot_carry() {
+    let mut pty = Pty::open();
+    let image_path = std::env::temp_dir().join(format!("pty-too-large-{}.img", std::process::id()));
+    // sparse, so it costs no disk; nothing of it is read before the size goes out
+    File::create(&image_path).unwrap().set_len(protocol::LEGACY_MAX_SIZE + 1).unwrap();
+    let push = spawn_push_file(pty.path(), image_path, &["--no-terminal", "--allow-huge"], &[], Stdio::null());
+    push.wait_for("power the target", 0);
+    pty.send(&[0x03; 3]);
+    let (status, stdout) = push.finish();
+    assert!(!status.success());
+    assert!(stdout.contains("more than the 4-byte size header can express"), "{}", stdout);
+    assert!(stdout.contains("--extended-size"), "{}", stdout);
+    assert!(!stdout.contains("Unexpected Error"), "{}", stdout);
+    // not a byte of the size went out
+    let mut fd = libc::pollfd { fd: pty.master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    assert_eq!(unsafe { libc::poll(&mut fd, 1, 0) }, 0);
+}
+
+#[test]
+fn mini_push_checks_the_sha256_of_what_it_wrote() {
+    let mut pty = Pty::open();
+    let image: Vec<u8> = (0..5000u32).map(|i| (i * 13) as u8).collect();
+    let digest = sha256::hex(&Sha256::digest(&image));
+    let path = std::env::temp_dir().join(format!("pty-sha256-{}.json", std::process::id()));
+    // push_over checks the loader got the image byte for byte
+    let (status, stdout) = push_over(&mut pty, "sha256", &image, &["--sha256", &digest, "--report", path.to_str().unwrap()], b"");
+    assert!(status.success(), "{}", stdout);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(report["push"]["sha256"], digest.as_str());
+    let _ = fs::remove_file(&path);
+
+    let other = sha256::hex(&Sha256::digest(b"yesterday's build"));
+    let (status, stdout) = push_over(&mut pty, "sha256-mismatch", &image, &["--sha256", &other], b"");
+    assert!(!status.success());
+    assert!(stdout.contains(&format!("SHA-256 is {}, not the expected {}", digest, other)), "{}", stdout);
+}
+
+#[test]
+fn mini_push_sends_only_what_changed() {
+    let mut pty = Pty::open();
+    let cache = std::env::temp_dir().join(format!("pty-delta-{}", std::process::id()));
+    let envs = [("XDG_CACHE_HOME", cache.to_str().unwrap())];
+    let args = ["--negotiate", "--delta", "-v"];
+    let old: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+    let mut new = old.clone();
+    new[5000] ^= 0xFF;
+
+    // the loader takes the probe and the size, then says what it makes of the offer
+    let handshake = |pty: &mut Pty, image: &[u8]| {
+        pty.send(&[0x03; 3]);
+        assert_eq!(pty.expect(4, Duration::from_secs(5)), protocol::VERSION_PROBE);
+        pty.send(b"MPV\x03\x20\x00");
+        assert_eq!(pty.expect(4, Duration::from_secs(5)), (image.len() as u32).to_le_bytes());
+        pty.send(b"OK");
+        pty.expect(14, Duration::from_secs(5))
+    };
+
+    // a cold target: nothing to patch, the whole image
+    let push = start_push(&pty, "delta", &old, &args, &envs);
+    assert_eq!(handshake(&mut pty, &old), delta::encode_offer(&Manifest::default()));
+    pty.send(b"NO");
+    assert_eq!(pty.expect(old.len(), Duration::from_secs(5)), old);
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+
+    let push = start_push(&pty, "delta", &new, &args, &envs);
+    let (before, after) = (Manifest::of(&old, delta::DELTA_BLOCK), Manifest::of(&new, delta::DELTA_BLOCK));
+    assert_eq!(handshake(&mut pty, &new), delta::encode_offer(&before));
+    pty.send(b"OK");
+    assert_eq!(pty.expect(12, Duration::from_secs(5)), delta::encode_record(4096, 4096));
+    assert_eq!(pty.expect(4096, Duration::from_secs(5)), new[4096..8192]);
+    assert_eq!(pty.expect(16, Duration::from_secs(5)), delta::encode_end(&after));
+    pty.send(b"OK");
+    let (status, stdout) = push.finish();
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("Sending the 4.0 KiB that changed of 9.8 KiB"), "{}", stdout);
+    let _ = fs::remove_dir_all(&cache);
+}
+
+#[test]
+fn mini_push_waits_for_the_boot_marker() {
+    let cases: [(&[u8], bool); 3] = [
+        (b"Welcome\r\nraspberrypi login: ", true),
+        (b"Starting init\r\n/ # ", true),
+        (b"Kernel panic - not syncing\r\n", false),
+    ];
+    for (i, &(boot, booted)) in cases.iter().enumerate() {
+        let mut pty = Pty::open();
+        let args = ["--expect-boot", "regex:login:|# ", "--expect-boot-timeout", "1"];
+        let (status, stdout) = push_over(&mut pty, &format!("boot-{}", i), b"kernel", &args, boot);
+        assert_eq!(status.success(), booted, "{}", stdout);
+        assert_eq!(stdout.contains("Booted, /login:|# /"), booted, "{}", stdout);
+        assert!(stdout.contains(&*String::from_utf8_lossy(boot).trim_end().replace('\r', "")), "{}", stdout);
+    }
+}
+
+#[test]
+fn ctrl_c_ends_the_wait_for_the_port() {
+    let missing = std::env::temp_dir().join(format!("pty-missing-{}", std::process::id()));
+    let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([missing.to_str().unwrap(), "--color", "never"])
+        .stdin(Stdio::null()));
+    thread::sleep(Duration::from_millis(1500));
+    unsafe { libc::kill(term.0.id() as libc::pid_t, libc::SIGINT); }
+    let interrupted = Instant::now();
+    let status = loop {
+        if let Some(status) = term.0.try_wait().unwrap() { break status; }
+        assert!(interrupted.elapsed() < Duration::from_secs(1), "still waiting after Ctrl-C");
+        thread::sleep(Duration::from_millis(20));
+    };
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    assert_eq!(status.code(), Some(130), "{}", stdout);
+    // into a pipe, the wait is one line rather than one redrawn every second
+    assert_eq!(stdout.matches("Waiting for").count(), 1, "{}", stdout);
+}
+
+#[test]
+fn sigterm_releases_the_lock_on_the_way_out() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pty = Pty::open();
+    let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never"])
+        .stdin(Stdio::null()));
+    let name = lock::lock_name(Path::new(pty.path())).unwrap();
+    let held = || lock::lock_dirs().iter().any(|dir| dir.join(&name).exists());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !held() {
+        assert!(Instant::now() < deadline, "no lock file taken");
+        thread::sleep(Duration::from_millis(10));
+    }
+    unsafe { libc::kill(term.0.id() as libc::pid_t, libc::SIGTERM); }
+    let status = term.0.wait().unwrap();
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    // ended by the signal itself, as the shell expects, once the lock was gone
+    assert_eq!(status.signal(), Some(libc::SIGTERM), "{}", stdout);
+    assert!(!held(), "{}", stdout);
+}
+
+#[test]
+fn mini_term_doctor_finds_who_holds_the_port() {
+    let pty = Pty::open();
+    let output = Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--doctor", "--json"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let check = |name: &str| report["checks"].as_array().unwrap().iter().find(|check| check["name"] == name).cloned().unwrap();
+
+    assert_eq!(check("present")["status"], "pass", "{}", stdout);
+    assert_eq!(check("open")["status"], "pass", "{}", stdout);
+    assert_eq!(check("loopback")["status"], "skip", "{}", stdout);
+    // the test keeps the slave open
+    if cfg!(target_os = "linux") {
+        assert_eq!(check("holders")["status"], "fail", "{}", stdout);
+        assert!(check("holders")["detail"].as_str().unwrap().contains(&format!("(PID {})", std::process::id())), "{}", stdout);
+        assert_eq!(report["passed"], false);
+        assert_eq!(output.status.code(), Some(1));
+    }
+}
+
+#[test]
+fn mini_term_says_exit_after_is_for_piped_stdin() {
+    let pty = Pty::open();
+    let mut keys = Pty::open();
+    let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--exit-after", "1"])
+        .stdin(Stdio::from(keys.slave.try_clone().unwrap())));
+    // still there past --exit-after, until the exit key
+    thread::sleep(Duration::from_millis(1500));
+    assert!(term.0.try_wait().unwrap().is_none(), "--exit-after ended a typed session");
+    keys.send(b"\x1d");
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    assert!(term.0.wait().unwrap().success(), "{}", stdout);
+    assert!(stdout.contains("--exit-after only ends a session with piped stdin"), "{}", stdout);
+}
+
+#[test]
+fn mini_term_keeps_stdout_to_the_target() {
+    for log_format in ["human", "json"] {
+        let mut pty = Pty::open();
+        let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+            .args([pty.path(), "--force", "--color", "never", "--exit-after", "1", "--log-format", log_format])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap());
+        thread::sleep(Duration::from_millis(300));
+        pty.send(b"login: \r\n");
+
+        let (mut stdout, mut stderr) = (String::new(), String::new());
+        term.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+        term.0.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+        assert!(term.0.wait().unwrap().success(), "{}", stderr);
+        assert_eq!(stdout, "login: \n", "{}", log_format);
+        if log_format == "human" {
+            assert!(stderr.contains("Miniterm 1.0"), "{}", stderr);
+        } else {
+            // the events and nothing else, for a parser to take line by line
+            assert!(stderr.lines().all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()), "{}", stderr);
+            assert!(stderr.contains(r#""event":"connected""#), "{}", stderr);
+        }
+    }
+}
+
+#[test]
+fn mini_term_exits_once_the_target_goes_quiet() {
+    let mut pty = Pty::open();
+    let started = Instant::now();
+    let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--idle-timeout", "1", "--on-idle", "exit:3"])
+        .stdin(Stdio::null()));
+    // output keeps it going past the timeout
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(500));
+        pty.send(b"still alive\r\n");
+    }
+
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    let status = term.0.wait().unwrap();
+    assert_eq!(status.code(), Some(3), "{}", stdout);
+    assert!(started.elapsed() >= Duration::from_millis(2500), "{}", stdout);
+    assert!(stdout.contains("still alive"), "{}", stdout);
+    assert!(stdout.contains(" UTC nothing received for 1s"), "{}", stdout);
+    assert!(stdout.contains("Idle for 1s, exiting with 3"), "{}", stdout);
+}
+
+#[test]
+fn mini_term_exits_on_the_ci_markers() {
+    // (what the target prints, each piece a read of its own, exit code, last words)
+    let cases: [(&[&[u8]], i32, &str); 3] = [
+        (&[b"test 1 ok\r\nALL TESTS ", b"PASSED\r\n"], 0, "Matched \"ALL TESTS PASSED\", exiting with 0"),
+        (&[b"FAIL", b"ED: net\r\n"], 5, "Matched \"FAILED: net\", exiting with 5"),
+        (&[b"test 1 ok\r\n"], 124, "Still running after 1s, exiting with 124"),
+    ];
+    for &(printed, code, said) in cases.iter() {
+        let mut pty = Pty::open();
+        let dump = std::env::temp_dir().join(format!("pty-scrollback-{}-{}.log", code, std::process::id()));
+        let _ = fs::remove_file(&dump);
+        let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+            .args([pty.path(), "--force", "--color", "never", "--exit-on-success", "ALL TESTS PASSED", "--exit-on-failure", "FAILED:"])
+            .args(["--failure-code", "5", "--max-session-time", "1", "--scrollback-dump", dump.to_str().unwrap()])
+            .stdin(Stdio::null()));
+        thread::sleep(Duration::from_millis(200));
+        for piece in printed {
+            pty.send(piece);
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let mut stdout = String::new();
+        pipe.read_to_string(&mut stdout).unwrap();
+        let status = term.0.wait().unwrap();
+        assert_eq!(status.code(), Some(code), "{}", stdout);
+        assert!(stdout.contains(said), "{}", stdout);
+        // a failed session leaves what the target printed behind, as it came
+        if code == 0 {
+            assert!(!dump.exists(), "{}", stdout);
+        } else {
+            assert_eq!(fs::read(&dump).unwrap(), printed.concat(), "{}", stdout);
+            assert!(stdout.contains(&format!("Scrollback saved to {}", dump.display())), "{}", stdout);
+        }
+        let _ = fs::remove_file(&dump);
+    }
+}
+
+/// Waits for the tool like `Child::wait`, also telling the CPU time it used.
+fn wait_with_cpu(running: Running) -> (Option<i32>, Duration) {
+    let pid = running.0.id() as libc::pid_t;
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::wait4(pid, &mut status, 0, &mut usage) }, pid);
+    // reaped already, not to be killed by its pid
+    std::mem::forget(running);
+    let time = |t: libc::timeval| Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64);
+    let code = if libc::WIFEXITED(status) { Some(libc::WEXITSTATUS(status)) } else { None };
+    (code, time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+#[test]
+fn mini_term_handles_the_end_of_piped_input() {
+    // (extra arguments, whether the session ends with the input)
+    let cases: [(&[&str], bool); 2] = [(&["--exit-on-eof"], true), (&["--exit-after", "2"], false)];
+    for &(args, ends) in cases.iter() {
+        let mut pty = Pty::open();
+        let started = Instant::now();
+        let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+            .args([pty.path(), "--force", "--color", "never"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap());
+        // written and closed at once
+        term.0.stdin.take().unwrap().write_all(b"uname\n").unwrap();
+        assert_eq!(pty.expect(6, Duration::from_secs(5)), b"uname\r");
+        let mut pipe = term.0.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut stdout = String::new();
+            let _ = pipe.read_to_string(&mut stdout);
+            stdout
+        });
+        if !ends {
+            thread::sleep(Duration::from_millis(500));
+            pty.send(b"Linux\r\n");
+        }
+
+        let (code, cpu) = wait_with_cpu(term);
+        let elapsed = started.elapsed();
+        let stdout = reader.join().unwrap();
+        assert_eq!(code, Some(0), "{:?}: {}", args, stdout);
+        if ends {
+            assert!(elapsed < Duration::from_secs(2), "{:?} took {:?}", args, elapsed);
+        } else {
+            // still showing the output after the input ended, without spinning meanwhile
+            assert!(elapsed >= Duration::from_secs(2), "{:?} took {:?}", args, elapsed);
+            assert!(stdout.contains("Linux"), "{}", stdout);
+            assert!(cpu < Duration::from_millis(500), "{:?} used {:?} of CPU in {:?}", args, cpu, elapsed);
+        }
+    }
+}
+
+#[test]
+fn mini_push_sends_the_time_once_the_target_is_ready() {
+    let mut pty = Pty::open();
+    let args = ["--sync-time", "unix64", "--sync-time-ready", "str:# ", "--sync-time-timeout", "2"];
+    let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let (status, stdout) = push_over(&mut pty, "time", b"kernel", &args, b"/ # ");
+    assert!(status.success(), "{}", stdout);
+    let mut sent = [0; 8];
+    sent.copy_from_slice(&pty.expect(8, Duration::from_secs(1)));
+    let sent = u64::from_le_bytes(sent);
+    assert!((before..=before + 5).contains(&sent), "{} is not about {}", sent, before);
+
+    // a target that never gets ready is no failed push
+    let mut pty = Pty::open();
+    let args = ["--sync-time", "unix64", "--sync-time-ready", "str:# ", "--sync-time-timeout", "1"];
+    let (status, stdout) = push_over(&mut pty, "no-time", b"kernel", &args, b"Kernel panic\r\n");
+    assert!(status.success(), "{}", stdout);
+    assert!(stdout.contains("time not sent, \"# \" didn't show up within 1s"), "{}", stdout);
+}
+
+#[test]
+fn mini_term_keeps_up_with_a_flood() {
+    let mut pty = Pty::open();
+    let log = std::env::temp_dir().join(format!("pty-flood-{}.log", std::process::id()));
+    let _ = fs::remove_file(&log);
+    let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--high-throughput", "--log", log.to_str().unwrap(),
+               "--idle-timeout", "1", "--on-idle", "exit:0"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap());
+    thread::sleep(Duration::from_millis(300));
+    // 4 MiB of numbered lines as fast as the pty takes them
+    let flood: Vec<u8> = (0..65536).flat_map(|i| format!("{:08} the quick brown fox jumps over the lazy dog 012345678\r\n", i).into_bytes()).collect();
+    assert_eq!(flood.len(), 4 << 20);
+    pty.send(&flood);
+
+    assert!(term.0.wait().unwrap().success());
+    let logged = fs::read(&log).unwrap();
+    let _ = fs::remove_file(&log);
+    assert_eq!(logged.len(), flood.len(), "lost {} bytes", flood.len() as i64 - logged.len() as i64);
+    assert!(logged == flood, "the log differs from what was sent");
+}
+
+#[test]
+fn mini_term_monitors_two_ports() {
+    let (mut a, mut b) = (Pty::open(), Pty::open());
+    let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([a.path(), "--force", "--color", "never", "--second", b.path(), "--second-baud", "115200", "--exit-after", "2"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap());
+    thread::sleep(Duration::from_millis(500));
+    a.send(b"U-Boot 2024.01\r\n");
+    b.send(b"rst:0x1 (POWERON)\r\n");
+    thread::sleep(Duration::from_millis(200));
+    // a prompt without its line ending, interrupted by the other port
+    a.send(b"=> ");
+    b.send(b"I (312) wifi: connected\r\n");
+
+    let mut stdout = String::new();
+    term.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+    let status = term.0.wait().unwrap();
+    assert!(status.success(), "{}", stdout);
+    let tagged: Vec<&str> = stdout.lines().filter(|line| line.starts_with("[A]") || line.starts_with("[B]")).collect();
+    assert_eq!(tagged.len(), 4, "{}", stdout);
+    for line in ["[A] U-Boot 2024.01", "[B] rst:0x1 (POWERON)", "[A] => …", "[B] I (312) wifi: connected"] {
+        assert!(tagged.contains(&line), "{:?} not in {}", line, stdout);
+    }
+}
+
+#[test]
+fn mini_term_shows_both_directions_on_the_wire() {
+    let mut pty = Pty::open();
+    let log = std::env::temp_dir().join(format!("pty-wire-{}.jsonl", std::process::id()));
+    let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--debug-wire", "--wire-log", log.to_str().unwrap(), "--exit-after", "1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap());
+    thread::sleep(Duration::from_millis(500));
+    term.0.stdin.take().unwrap().write_all(b"ping\n").unwrap();
+    assert_eq!(&pty.expect(4, Duration::from_secs(5)), b"ping");
+    pty.send(b"pong\r\n");
+
+    let mut stdout = String::new();
+    term.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+    assert!(term.0.wait().unwrap().success(), "{}", stdout);
+    // paced like a paste, a byte a row
+    let sent = stdout.find(">> 67 ").expect(&stdout);
+    let received = stdout.find("<< 70 6f 6e 67 0d 0a").expect(&stdout);
+    assert!(sent < received, "{}", stdout);
+    // the target's output is shown as rows only
+    assert!(!stdout.lines().any(|line| line == "pong"), "{}", stdout);
+
+    let entries: Vec<serde_json::Value> = fs::read_to_string(&log).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(entries.first().map(|entry| &entry["dir"]), Some(&serde_json::json!("tx")));
+    assert!(entries.iter().any(|entry| entry["dir"] == "rx" && entry["hex"] == "706f6e670d0a"), "{:?}", entries);
+    let _ = fs::remove_file(&log);
+}
+
+#[test]
+fn mini_term_takes_commands_on_its_control_socket() {
+    use std::{io::{BufRead, BufReader}, os::unix::net::UnixStream};
+
+    let mut pty = Pty::open();
+    let socket = std::env::temp_dir().join(format!("pty-control-{}.sock", std::process::id()));
+    let log = std::env::temp_dir().join(format!("pty-control-{}.log", std::process::id()));
+    let _ = fs::remove_file(&log);
+    let mut term = Running(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--control", socket.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let client = loop {
+        if let Ok(client) = UnixStream::connect(&socket) { break client; }
+        assert!(Instant::now() < deadline, "no control socket");
+        thread::sleep(Duration::from_millis(20));
+    };
+    let mut replies = BufReader::new(client.try_clone().unwrap()).lines();
+    let mut ask = |request: String| {
+        (&client).write_all(format!("{}\n", request).as_bytes()).unwrap();
+        replies.next().unwrap().unwrap()
+    };
+
+    assert_eq!(ask(r#"{"cmd":"send","data":"reboot\n"}"#.to_string()), r#"{"ok":true,"output":["→ 7 B typed in over the control socket"]}"#);
+    assert_eq!(pty.expect(7, Duration::from_secs(2)), b"reboot\n");
+    let started = ask(format!(r#"{{"cmd":"log","action":"start","path":"{}"}}"#, log.display()));
+    assert!(started.starts_with(r#"{"ok":true"#), "{}", started);
+    pty.send(b"Booting Linux\r\n");
+    thread::sleep(Duration::from_millis(300));
+    let stats = ask(r#"{"cmd":"stats"}"#.to_string());
+    assert!(stats.contains(r#""received":15,"sent":7,"#), "{}", stats);
+    // another client at the same time
+    let mut other = BufReader::new(UnixStream::connect(&socket).unwrap());
+    other.get_mut().write_all(b"{\"cmd\":\"nope\"}\n").unwrap();
+    let mut unknown = String::new();
+    other.read_line(&mut unknown).unwrap();
+    assert!(unknown.starts_with(r#"{"ok":false,"error":"unknown command \"nope\""#), "{}", unknown);
+
+    assert_eq!(ask(r#"{"cmd":"quit"}"#.to_string()), r#"{"ok":true}"#);
+    let status = term.0.wait().unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read(&log).unwrap(), b"Booting Linux\r\n");
+    assert!(!socket.exists(), "the socket is left behind");
+    let _ = fs::remove_file(&log);
+}
+
+#[test]
+fn mini_term_pulls_a_dump() {
+    use std::{io::{BufRead, BufReader}, os::unix::net::UnixStream};
+
+    let dir = std::env::temp_dir().join(format!("pty-pull-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 13) as u8).collect();
+    let frame = rust_serial_tool::pull::encode("ram.bin", &data);
+
+    // on its own, written where --output says
+    let mut pty = Pty::open();
+    let output = dir.join("crash.bin");
+    let (mut pulling, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--pull", "--output", output.to_str().unwrap()])
+        .stdin(Stdio::null()));
+    thread::sleep(Duration::from_millis(300));
+    pty.send(b"panic!\r\n");
+    pty.send(&frame);
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    assert!(pulling.0.wait().unwrap().success(), "{}", stdout);
+    assert!(stdout.contains("panic!\n"), "{}", stdout);
+    assert_eq!(fs::read(&output).unwrap(), data);
+
+    // in the terminal, once the pull command armed it
+    let socket = dir.join("control.sock");
+    let (mut term, mut pipe) = spawn_merged(Command::new(env!("CARGO_BIN_EXE_mini_term"))
+        .args([pty.path(), "--force", "--color", "never", "--control", socket.to_str().unwrap()])
+        .stdin(Stdio::null()));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let client = loop {
+        if let Ok(client) = UnixStream::connect(&socket) { break client; }
+        assert!(Instant::now() < deadline, "no control socket");
+        thread::sleep(Duration::from_millis(20));
+    };
+    let mut replies = BufReader::new(client.try_clone().unwrap()).lines();
+    (&client).write_all(format!("{{\"cmd\":\"pull\",\"args\":\"{}\"}}\n", dir.display()).as_bytes()).unwrap();
+    let armed = replies.next().unwrap().unwrap();
+    assert!(armed.contains("Waiting for the target to send a dump into"), "{}", armed);
+    pty.send(&[&frame[..], b"> "].concat());
+    let pulled = dir.join("ram.bin");
+    while fs::read(&pulled).map_or(true, |got| got != data) {
+        assert!(Instant::now() < deadline, "nothing pulled");
+        thread::sleep(Duration::from_millis(20));
+    }
+    (&client).write_all(b"{\"cmd\":\"quit\"}\n").unwrap();
+    let mut stdout = String::new();
+    pipe.read_to_string(&mut stdout).unwrap();
+    assert!(term.0.wait().unwrap().success(), "{}", stdout);
+    assert!(stdout.contains(&format!("Pulled {}, 19.5 KiB", pulled.display())), "{}", stdout);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::{fs, io::{Cursor, Read}, path::{Path, PathBuf}, time::Duration};
+
+use common::scratch;
+use rust_serial_tool::{delta::crc32, output::{Output, Verbosity}, pull::{self, DumpHeader, MagicScanner, MAGIC}};
+
+#[test]
+fn finds_the_dump_in_the_console_output() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+    let frame = pull::encode("ram.bin", &data);
+    assert_eq!(frame[..4], MAGIC);
+    assert_eq!(frame.len(), 4 + 4 + 1 + 7 + data.len() + 4);
+    let stream = [&b"panic at 0x8000\r\n\x7fD"[..], &frame, b"> "].concat();
+
+    // read three bytes at a time, the magic split across reads
+    let mut scanner = MagicScanner::default();
+    let (mut shown, mut rest) = (Vec::new(), None);
+    let mut pieces = stream.chunks(3);
+    for piece in pieces.by_ref() {
+        let (output, found) = scanner.feed(piece);
+        shown.extend(output);
+        if let Some(found) = found {
+            rest = Some(found);
+            break;
+        }
+    }
+    assert_eq!(shown, b"panic at 0x8000\r\n\x7fD");
+    let remaining: Vec<u8> = pieces.flatten().copied().collect();
+    let mut port = Cursor::new(rest.unwrap()).chain(Cursor::new(remaining));
+
+    let header = pull::read_header(&mut port, Duration::from_millis(10)).unwrap();
+    assert_eq!(header, DumpHeader { length: 10_000, name: "ram.bin".to_string() });
+    let mut progress = Vec::new();
+    assert_eq!(pull::read_data(&mut port, &header, Duration::from_millis(10), |bytes| progress.push(bytes)).unwrap(), data);
+    assert_eq!(progress, [4096, 8192, 10_000]);
+    let mut after = Vec::new();
+    port.read_to_end(&mut after).unwrap();
+    assert_eq!(after, b"> ");
+}
+
+#[test]
+fn writes_what_it_caught() {
+    let dir = scratch("catch");
+    let frame = pull::encode("../../ram.bin", b"\xde\xad\xbe\xef");
+    let rest = [&frame[4..], b"reset\r\n"].concat();
+    let pulled = pull::catch(&mut Cursor::new(Vec::new()), &rest, Some(&dir), &Output::new("T", Verbosity::Quiet)).unwrap();
+    assert_eq!((pulled.path.clone(), pulled.bytes, pulled.after), (dir.join("ram.bin"), 4, b"reset\r\n".to_vec()));
+    assert_eq!(fs::read(&pulled.path).unwrap(), b"\xde\xad\xbe\xef");
+
+    // (name in the header, --output, where it goes)
+    let file = dir.join("crash.bin");
+    let cases: [(&str, Option<&Path>, PathBuf); 3] = [
+        ("ram.bin", None, PathBuf::from("ram.bin")),
+        ("ram.bin", Some(&file), file.clone()),
+        ("/", Some(&dir), dir.join("dump-")),
+    ];
+    for (name, output, expected) in cases {
+        let path = pull::dump_path(&DumpHeader { length: 0, name: name.to_string() }, output);
+        assert!(path.to_string_lossy().starts_with(&*expected.to_string_lossy()), "{}: {}", name, path.display());
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn tells_a_broken_dump() {
+    let data = vec![0x5A; 1000];
+    let frame = pull::encode("", &data);
+    let mut corrupted = frame.clone();
+    corrupted[500] ^= 0x01;
+    let crc = crc32(&data);
+    let cases = [
+        (frame[4..105].to_vec(), "the dump stopped after 96 of 1000 bytes".to_string()),
+        (frame[4..frame.len() - 2].to_vec(), "the dump stopped after 1000 of 1000 bytes".to_string()),
+        (corrupted[4..].to_vec(), format!("the dump's CRC-32 is {:08x}, the target sent {:08x}", crc32(&corrupted[9..1009]), crc)),
+    ];
+    for (rest, expected) in cases {
+        let mut port = Cursor::new(rest);
+        let header = pull::read_header(&mut port, Duration::from_millis(10)).unwrap();
+        let e = pull::read_data(&mut port, &header, Duration::from_millis(10), |_| {}).unwrap_err();
+        assert_eq!(e.to_string(), expected);
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use common::Shared;
+use rust_serial_tool::{ErrorKind, record::*, terminal::RxTap};
+
+fn frames(data: &[u8]) -> Result<Vec<Frame>, ErrorKind> {
+    let mut input = Cursor::new(data);
+    read_magic(&mut input)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = read_frame(&mut input)? { frames.push(frame); }
+    Ok(frames)
+}
+
+#[test]
+fn frame_layout() {
+    let frame = Frame { delta: Duration::from_micros(0x0102), direction: Direction::Tx, data: b"hi".to_vec() };
+    let mut out = Vec::new();
+    write_frame(&mut out, &frame).unwrap();
+    assert_eq!(out, [2, 0, 0, 0, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 1, b'h', b'i']);
+    assert_eq!(read_frame(&mut Cursor::new(&out)).unwrap(), Some(frame));
+}
+
+#[test]
+fn recorder_round_trips() {
+    let shared = Shared::default();
+    let recorder = Arc::new(Recorder::new(Box::new(shared.clone())).unwrap());
+    recorder.clone().rx(b"login: ");
+    recorder.record(Direction::Tx, b"root\r").unwrap();
+    recorder.record(Direction::Rx, b"").unwrap();
+    recorder.clone().rx(b"root\r\n# ");
+
+    let frames = frames(&shared.contents()).unwrap();
+    let data: Vec<(Direction, &[u8])> = frames.iter().map(|f| (f.direction, &f.data[..])).collect();
+    assert_eq!(data, [(Direction::Rx, &b"login: "[..]), (Direction::Tx, b"root\r"), (Direction::Rx, b"root\r\n# ")]);
+}
+
+#[test]
+fn rejects_damaged_recordings() {
+    let mut good = MAGIC.to_vec();
+    write_frame(&mut good, &Frame { delta: Duration::ZERO, direction: Direction::Rx, data: b"boot".to_vec() }).unwrap();
+
+    let mut bad_direction = good.clone();
+    bad_direction[MAGIC.len() + 12] = 7;
+    let mut oversized = MAGIC.to_vec();
+    oversized.extend_from_slice(&(MAX_FRAME + 1).to_le_bytes());
+
+    let cases: [(&[u8], &str); 5] = [
+        (b"", "not a session recording"),
+        (b"\x7fELF\x02\x01\x01\x00", "not a session recording"),
+        (&good[..good.len() - 1], "truncated frame"),
+        (&bad_direction, "unknown direction 7"),
+        (&oversized, "frame of 1048577 bytes"),
+    ];
+    for (data, reason) in cases.iter() {
+        match frames(data) {
+            Err(ErrorKind::RecordingError(e)) => assert_eq!(e, *reason),
+            other => panic!("expected {:?}, got {:?}", reason, other),
+        }
+    }
+    assert_eq!(frames(&good).unwrap().len(), 1);
+}
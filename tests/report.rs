@@ -0,0 +1,59 @@
+use std::fs;
+
+use rust_serial_tool::{identity::TargetIdentity, observer::PushReport, protocol::{Capabilities, LoaderInfo}, report::{self, TransferReport}};
+use serde_json::{json, Value};
+
+fn push() -> PushReport {
+    PushReport {
+        bytes: 4096,
+        total: 6144,
+        seconds: 2.0,
+        retransmitted: 1,
+        sha256: [0xab; 32],
+        loader: Some(LoaderInfo { version: 2, capabilities: Capabilities::RESUME | Capabilities::CMDLINE }),
+        target: Some(TargetIdentity::parse("serial=00000000a1b2c3d4 loader=4f1c2e9")),
+    }
+}
+
+#[test]
+fn serializes_the_push() {
+    let report = TransferReport::new("/dev/ttyUSB0", "kernel8.img", Some(push()), None);
+    let value = serde_json::to_value(&report).unwrap();
+    assert_eq!(value["status"], "ok");
+    assert_eq!(value["error"], Value::Null);
+    assert_eq!(value["device"], "/dev/ttyUSB0");
+    assert_eq!(value["rate"], 2048.0);
+    assert_eq!(value["push"], json!({
+        "bytes": 4096,
+        "total": 6144,
+        "seconds": 2.0,
+        "retransmitted": 1,
+        "sha256": "ab".repeat(32),
+        "loader": { "version": 2, "capabilities": ["resume", "cmdline"] },
+        "target": {
+            "text": "serial=00000000a1b2c3d4 loader=4f1c2e9",
+            "fields": { "serial": "00000000a1b2c3d4", "loader": "4f1c2e9" },
+        },
+    }));
+    assert!(value["timestamp"].as_str().unwrap().ends_with('Z'));
+
+    let failed = serde_json::to_value(TransferReport::new("COM3", "-", None, Some("TimeoutError during handshake on COM3".to_string()))).unwrap();
+    assert_eq!((&failed["status"], &failed["push"], &failed["rate"]), (&json!("failed"), &Value::Null, &Value::Null));
+}
+
+#[test]
+fn writes_the_whole_report_or_none() {
+    let dir = std::env::temp_dir().join(format!("report-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("unit-42.json");
+    fs::write(&path, b"the report before").unwrap();
+
+    let reports = vec![TransferReport::new("/dev/ttyUSB0", "kernel8.img", Some(push()), None)];
+    report::write(&reports, &path).unwrap();
+    let written: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(written[0]["push"]["total"], 6144);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "no temporary file is left");
+
+    assert!(report::write(&reports, &dir.join("missing").join("unit.json")).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
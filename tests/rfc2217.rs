@@ -0,0 +1,119 @@
+use std::{io::{Read, Write}, net::TcpListener, thread, time::{Duration, Instant}};
+
+use rust_serial_tool::{ReadSerial, settings::SerialSettings, transport::{rfc2217::*, Target}};
+
+fn decode_chunks(chunks: &[&[u8]]) -> (Vec<u8>, Vec<TelnetEvent>) {
+    let mut decoder = TelnetDecoder::default();
+    let (mut data, mut events) = (Vec::new(), Vec::new());
+    for chunk in chunks { decoder.decode(chunk, &mut data, &mut events); }
+    (data, events)
+}
+
+#[test]
+fn escape_doubles_iac() {
+    let cases: &[(&[u8], &[u8])] = &[
+        (b"", b""),
+        (b"abc", b"abc"),
+        (&[0xFF], &[0xFF, 0xFF]),
+        (&[0x00, 0xFF, 0xFF, 0x01], &[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+        (&[0xFE, 0xFD, 0xFA, 0xF0], &[0xFE, 0xFD, 0xFA, 0xF0]),
+    ];
+    for (input, expected) in cases {
+        let mut out = Vec::new();
+        escape(input, &mut out);
+        assert_eq!(out, *expected);
+    }
+}
+
+#[test]
+fn every_byte_round_trips() {
+    let image: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+    let mut wire = Vec::new();
+    escape(&image, &mut wire);
+
+    // split the wire at every offset across the first few escaped pairs
+    for split in 0..wire.len().min(600) {
+        let (data, events) = decode_chunks(&[&wire[..split], &wire[split..]]);
+        assert_eq!(data, image, "split at {}", split);
+        assert!(events.is_empty());
+    }
+}
+
+type Case<'a> = (&'a [&'a [u8]], &'a [u8], Vec<TelnetEvent>);
+
+#[test]
+fn decoder_separates_commands() {
+    let cases: &[Case] = &[
+        (&[&[b'a', IAC, IAC, b'b']], &[b'a', 0xFF, b'b'], vec![]),
+        (&[&[b'a', IAC], &[IAC, b'b']], &[b'a', 0xFF, b'b'], vec![]),
+        (&[&[IAC, DO, OPT_BINARY, b'x']], b"x", vec![TelnetEvent::Negotiation(DO, OPT_BINARY)]),
+        (&[&[IAC], &[WILL], &[OPT_COM_PORT]], b"", vec![TelnetEvent::Negotiation(WILL, OPT_COM_PORT)]),
+        (&[&[IAC, 241, b'y']], b"y", vec![TelnetEvent::Command(241)]),
+        (
+            &[&[IAC, SB, OPT_COM_PORT, 101, 0, 0, IAC], &[IAC, 0, IAC, SE, b'z']],
+            b"z",
+            vec![TelnetEvent::Subnegotiation(vec![OPT_COM_PORT, 101, 0, 0, 0xFF, 0])],
+        ),
+    ];
+    for (chunks, data, events) in cases {
+        assert_eq!(decode_chunks(chunks), (data.to_vec(), events.clone()));
+    }
+}
+
+#[test]
+fn subnegotiation_escapes_value() {
+    assert_eq!(subnegotiation(SET_BAUDRATE, &115_200u32.to_be_bytes()),
+               vec![IAC, SB, OPT_COM_PORT, SET_BAUDRATE, 0x00, 0x01, 0xC2, 0x00, IAC, SE]);
+    assert_eq!(subnegotiation(SET_BAUDRATE, &0x0000_FFFFu32.to_be_bytes()),
+               vec![IAC, SB, OPT_COM_PORT, SET_BAUDRATE, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, IAC, SE]);
+}
+
+#[test]
+fn target_parsing() {
+    assert_eq!(Target::parse("/dev/ttyUSB0"), Target::Native("/dev/ttyUSB0"));
+    assert_eq!(Target::parse("COM3"), Target::Native("COM3"));
+    assert_eq!(Target::parse("rfc2217://labhost:4000"), Target::Rfc2217("labhost:4000"));
+    assert_eq!(Target::parse("rfc2217://labhost:4000/"), Target::Rfc2217("labhost:4000"));
+}
+
+#[test]
+fn port_against_fake_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        stream.write_all(&[IAC, DO, 24, b'h', b'i', IAC, IAC]).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = Vec::new();
+        let mut buf = [0; 256];
+        while !received.ends_with(&[b'!', 0xFF, 0xFF]) && Instant::now() < deadline {
+            if let Ok(n) = stream.read(&mut buf) { received.extend_from_slice(&buf[..n]); }
+        }
+        received
+    });
+
+    let settings = SerialSettings { baud_rate: 115_200, ..SerialSettings::default() };
+    let mut port: rust_serial_tool::SerialPort = Box::new(Rfc2217Port::open(&addr, &settings, Duration::from_millis(10)).unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut data = Vec::new();
+    let mut buf = [0; 64];
+    while data.len() < 3 && Instant::now() < deadline {
+        let n = port.read_serial(&mut buf).unwrap();
+        data.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(data, [b'h', b'i', 0xFF]);
+
+    port.write_all(&[b'!', 0xFF]).unwrap();
+    let received = server.join().unwrap();
+
+    let baud = subnegotiation(SET_BAUDRATE, &115_200u32.to_be_bytes());
+    assert!(received.starts_with(&[IAC, WILL, OPT_BINARY]));
+    assert!(received.windows(baud.len()).any(|w| w == &baud[..]));
+    // the unknown option the server asked for is refused
+    assert!(received.windows(3).any(|w| w == [IAC, WONT, 24]));
+    assert!(received.ends_with(&[b'!', 0xFF, 0xFF]));
+}
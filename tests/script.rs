@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use rust_serial_tool::{ErrorKind, pattern::{Pattern, StreamBuffer}, script::{Script, Step, unescape}};
+#[cfg(feature = "mock")]
+use rust_serial_tool::mock::MockSerial;
+
+fn script_error(result: rust_serial_tool::Result<Script>) -> String {
+    match result {
+        Err(ErrorKind::ScriptError(reason)) => reason,
+        other => panic!("expected a script error, got {:?}", other.map(|s| s.steps().count())),
+    }
+}
+
+#[test]
+fn parses_steps() {
+    let script = Script::parse(r##"
+        # wait for the shell
+        expect "login:" timeout 30
+        send "root\n"
+        expect regex "#\s*$"
+        sleep 0.5
+    "##).unwrap();
+
+    let steps: Vec<_> = script.steps().collect();
+    assert_eq!(steps.len(), 4);
+    match steps[0] {
+        Step::Expect { pattern: Pattern::Literal(needle), timeout } => {
+            assert_eq!(needle, b"login:");
+            assert_eq!(*timeout, Duration::from_secs(30));
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(matches!(steps[1], Step::Send(data) if data == b"root\n"));
+    assert!(matches!(steps[2], Step::Expect { pattern: Pattern::Regex(_), timeout } if *timeout == Duration::from_secs(10)));
+    assert!(matches!(steps[3], Step::Sleep(d) if *d == Duration::from_millis(500)));
+}
+
+#[test]
+fn parse_errors_report_line() {
+    let cases = [
+        ("send", "line 1: expected a quoted string"),
+        ("\nexpect \"a\" timeout", "line 2: expected a number of seconds"),
+        ("reboot", "line 1: unknown command"),
+        ("send \"open", "line 1: unterminated string"),
+        ("sleep -1", "line 1: invalid number of seconds"),
+        ("expect regex \"(\"", "line 1: regex parse error"),
+        ("send \"a\" \"b\"", "line 1: trailing"),
+    ];
+    for (text, expected) in cases.iter() {
+        let reason = script_error(Script::parse(text));
+        assert!(reason.starts_with(expected), "{:?}: {}", text, reason);
+    }
+}
+
+#[test]
+fn unescapes_strings() {
+    assert_eq!(unescape(r#"a\r\n\t\\\"\x7f\0"#).unwrap(), b"a\r\n\t\\\"\x7f\0");
+    assert!(unescape(r"\x4").is_err());
+    assert!(unescape(r"\q").is_err());
+}
+
+#[test]
+fn stream_buffer_matches_across_chunks() {
+    let mut buffer = StreamBuffer::default();
+    let pattern = Pattern::literal("login:");
+    buffer.push(b"Welcome\r\nlog");
+    assert_eq!(buffer.take_match(&pattern), None);
+    buffer.push(b"in: ");
+    assert_eq!(buffer.take_match(&pattern), Some(b"login:".to_vec()));
+    assert_eq!(buffer.pending(), b" ");
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn runs_against_split_output() {
+    let script = Script::parse(r#"
+        expect "login:" timeout 1
+        send "root\n"
+        expect regex "Linux \d+\.\d+" timeout 1
+    "#).unwrap();
+    let mock = MockSerial::new().reply(b"U-Boot\r\nlo").reply(b"gin").reply(b": ").expect(b"root\n").reply(b"Lin").reply(b"ux 6.1 #1");
+
+    let mut echoed = Vec::new();
+    let outcomes = script.run(&mut mock.clone(), |data| echoed.extend_from_slice(data)).unwrap();
+
+    mock.assert_done();
+    assert_eq!(echoed, b"U-Boot\r\nlogin: Linux 6.1 #1");
+    let matched: Vec<_> = outcomes.iter().map(|o| o.matched.clone()).collect();
+    assert_eq!(matched, [Some(b"login:".to_vec()), None, Some(b"Linux 6.1".to_vec())]);
+    assert_eq!(outcomes.iter().map(|o| o.line).collect::<Vec<_>>(), [2, 3, 4]);
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn expect_times_out() {
+    let script = Script::parse("send \"x\"\nexpect \"never\" timeout 0.05").unwrap();
+    let mock = MockSerial::new().expect(b"x").reply(b"something else");
+
+    match script.run(&mut mock.clone(), |_| {}) {
+        Err(ErrorKind::ScriptError(reason)) => assert!(reason.starts_with("line 2: expect \"never\" timed out"), "{}", reason),
+        other => panic!("expected a timeout, got {:?}", other.map(|o| o.len())),
+    }
+    mock.assert_done();
+}
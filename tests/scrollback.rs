@@ -0,0 +1,40 @@
+use std::fs;
+
+use rust_serial_tool::scrollback::Scrollback;
+
+#[test]
+fn keeps_the_last_of_the_output() {
+    // (reads, what is kept of 8 bytes)
+    let cases: [(&[&[u8]], &[u8]); 5] = [
+        (&[], b""),
+        (&[b"abc", b"de"], b"abcde"),
+        (&[b"abcdef", b"ghij"], b"cdefghij"),
+        (&[b"abcdefgh", b"ij", b"klmnopq"], b"jklmnopq"),
+        (&[b"ab", b"0123456789xyz"], b"56789xyz"),
+    ];
+    for &(reads, kept) in cases.iter() {
+        let scrollback = Scrollback::new(8);
+        reads.iter().for_each(|read| scrollback.push(read));
+        assert_eq!(scrollback.contents(), kept, "{:?}", reads);
+        assert_eq!(scrollback.len(), kept.len());
+    }
+}
+
+#[test]
+fn takes_the_last_lines() {
+    let scrollback = Scrollback::new(1024);
+    scrollback.push(b"one\r\ntwo\r\nthree\r\n");
+    // (lines, what they are)
+    let cases: [(usize, &[u8]); 4] = [(0, b""), (1, b"three\r\n"), (2, b"two\r\nthree\r\n"), (9, b"one\r\ntwo\r\nthree\r\n")];
+    for (lines, expected) in cases {
+        assert_eq!(scrollback.last_lines(lines), expected, "{}", lines);
+    }
+    // a partial line is the last one
+    scrollback.push(b"=> ");
+    assert_eq!(scrollback.last_lines(2), b"three\r\n=> ");
+
+    let path = std::env::temp_dir().join(format!("scrollback-{}.log", std::process::id()));
+    assert_eq!(scrollback.dump(&path, Some(1)).unwrap(), 3);
+    assert_eq!(fs::read(&path).unwrap(), b"=> ");
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::Jumper;
+use rust_serial_tool::{ErrorKind, selftest::*, settings::{DataBits, Parity, SerialSettings, StopBits}};
+
+fn config(bytes: u64) -> SelftestConfig {
+    SelftestConfig { bytes: Some(bytes), ..SelftestConfig::default() }
+}
+
+#[test]
+fn clean_loopback_passes() {
+    let mut port = Jumper::default();
+    let report = run(&mut port, &config(20_000), |_| {}).unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!((report.sent, report.received), (20_000, 20_000));
+}
+
+#[test]
+fn reports_corruption_and_drops() {
+    let mut port = Jumper::faulty(&[0, 5000], &[9000]);
+    let report = run(&mut port, &config(20_000), |_| {}).unwrap();
+    assert!(!report.passed());
+    assert_eq!(report.error_offsets[..2], [0, 5000]);
+    assert_eq!(report.dropped, 1);
+    assert_eq!(report.received, 19_999);
+}
+
+#[test]
+fn open_loop_fails_fast() {
+    let mut port = Jumper::open();
+    match run(&mut port, &config(20_000), |_| {}) {
+        Err(ErrorKind::SelftestError(reason)) => assert!(reason.contains("nothing looped back"), "{}", reason),
+        other => panic!("expected a self-test error, got {:?}", other),
+    }
+}
+
+#[test]
+fn seven_bit_framing_masks_the_sequence() {
+    let settings = SerialSettings { data_bits: DataBits::Seven, parity: Parity::Even, ..SerialSettings::default() };
+    let mut port = Jumper::masked(0x7F);
+    let report = run(&mut port, &SelftestConfig { settings, bytes: Some(1000), ..SelftestConfig::default() }, |_| {}).unwrap();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(settings.bits_per_char(), 10);
+    let two_stop = SerialSettings { stop_bits: StopBits::Two, ..SerialSettings::default() };
+    assert_eq!(two_stop.bits_per_char(), 11);
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "mock")]
+
+use std::{io, time::Duration};
+
+use rust_serial_tool::{ErrorKind, mock::FlakyWriter, WriteSerial};
+
+#[test]
+fn maps_write_errors() {
+    let cases: Vec<(io::Result<usize>, Option<usize>)> = vec![
+        (Ok(3), Some(3)),
+        (Err(io::ErrorKind::TimedOut.into()), Some(0)),
+        (Err(io::ErrorKind::WouldBlock.into()), Some(0)),
+        (Err(io::Error::from_raw_os_error(22)), None),
+        (Err(io::Error::from_raw_os_error(5)), None),
+        (Err(io::Error::from_raw_os_error(6)), None),
+        (Err(io::ErrorKind::NotConnected.into()), None),
+        (Err(io::ErrorKind::BrokenPipe.into()), None),
+        (Ok(0), None),
+    ];
+    for (outcome, expected) in cases {
+        let label = format!("{:?}", outcome);
+        let result = FlakyWriter::new(vec![outcome]).write_serial(b"abcdef");
+        match (result, expected) {
+            (Ok(n), Some(expected)) => assert_eq!(n, expected, "{}", label),
+            (Err(ErrorKind::ConnectionError), None) => {}
+            (other, _) => panic!("{}: unexpected {:?}", label, other),
+        }
+    }
+}
+
+#[test]
+fn write_all_retries_short_and_stalled_writes() {
+    let mut port = FlakyWriter::new(vec![Ok(2), Err(io::ErrorKind::TimedOut.into()), Ok(1), Err(io::ErrorKind::Interrupted.into()), Ok(10)]);
+    port.write_serial_all(b"hello world", Duration::from_secs(1)).unwrap();
+    assert_eq!(port.written(), b"hello world");
+}
+
+#[test]
+fn write_all_gives_up_after_the_deadline() {
+    let mut port = FlakyWriter::new(vec![Ok(4)]);
+    match port.write_serial_all(b"hello world", Duration::from_millis(50)) {
+        Err(ErrorKind::WriteTimeout { written, expected }) => assert_eq!((written, expected), (4, 11)),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut unplugged = FlakyWriter::new(vec![Ok(4), Err(io::Error::from_raw_os_error(22))]);
+    assert!(matches!(unplugged.write_serial_all(b"hello world", Duration::from_secs(1)), Err(ErrorKind::ConnectionError)));
+}
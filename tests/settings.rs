@@ -0,0 +1,65 @@
+#![cfg(feature = "mock")]
+
+use std::time::{Duration, Instant};
+
+use rust_serial_tool::{mock::MockSerial, ReadSerial, settings::{SYNC_SETTLE, SyncAction}, WRITE_TIMEOUT, WriteSerial};
+
+#[test]
+fn parses_sync_actions() {
+    let ms = Duration::from_millis;
+    let cases = [
+        ("break", Ok(SyncAction::Break(ms(250)))),
+        ("break:40", Ok(SyncAction::Break(ms(40)))),
+        ("autobaud", Ok(SyncAction::Autobaud(16))),
+        ("autobaud:64", Ok(SyncAction::Autobaud(64))),
+        ("quiet", Ok(SyncAction::Quiet(ms(100)))),
+        ("quiet:500", Ok(SyncAction::Quiet(ms(500)))),
+        ("break:0", Err("expected a number greater than zero")),
+        ("autobaud:lots", Err("expected a number greater than zero")),
+        ("flush", Err("expected break[:MS], autobaud[:COUNT] or quiet[:MS]")),
+    ];
+    for (s, expected) in cases {
+        match (s.parse::<SyncAction>(), expected) {
+            (Ok(action), Ok(expected)) => assert_eq!(action, expected, "{}", s),
+            (Err(e), Err(start)) => assert!(e.starts_with(start), "{}: {}", s, e),
+            (result, _) => panic!("{}: {:?}", s, result),
+        }
+    }
+    assert_eq!(SyncAction::Autobaud(16).to_string(), "16 × 0x55");
+    assert!(!SyncAction::Quiet(ms(1)).writes());
+}
+
+/// How long `action` took on a port whose target answers it with garbage, checking that none
+/// of that is left to read.
+fn sync(action: SyncAction, mock: MockSerial) -> Duration {
+    let started = Instant::now();
+    action.apply(&mut mock.clone()).unwrap();
+    let elapsed = started.elapsed();
+    mock.assert_done();
+    elapsed
+}
+
+#[test]
+fn sync_actions_drop_what_the_target_answers() {
+    let ms = Duration::from_millis;
+    let elapsed = sync(SyncAction::Break(ms(30)), MockSerial::new().reply(b"\xff\x00noise before").expect_break().reply(b"\x00\xfe"));
+    assert!(elapsed >= ms(30) + SYNC_SETTLE, "{:?}", elapsed);
+
+    let elapsed = sync(SyncAction::Autobaud(4), MockSerial::new().expect(&[0x55; 4]).reply(b"\x55?autobaud ok\r\n"));
+    assert!(elapsed >= SYNC_SETTLE, "{:?}", elapsed);
+
+    let elapsed = sync(SyncAction::Quiet(ms(20)), MockSerial::new().reply(b"\x1b[?\xe0garbage"));
+    assert!(elapsed >= ms(20) && elapsed < ms(20) + SYNC_SETTLE, "{:?}", elapsed);
+}
+
+#[test]
+fn what_follows_the_sync_is_read() {
+    let mock = MockSerial::new().expect_break().reply(b"\xf8").expect(b"\r").reply(b"login: ");
+    let mut port = mock.clone();
+    SyncAction::Break(Duration::from_millis(1)).apply(&mut port).unwrap();
+    port.write_serial_all(b"\r", WRITE_TIMEOUT).unwrap();
+    let mut buf = [0; 16];
+    let n = port.read_serial(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"login: ");
+    mock.assert_done();
+}
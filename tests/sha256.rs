@@ -0,0 +1,19 @@
+use rust_serial_tool::sha256::{self, Sha256};
+
+#[test]
+fn hashes_like_sha256() {
+    let cases: [(&[u8], &str); 3] = [
+        (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+         "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+    ];
+    for (data, digest) in cases {
+        // fed in pieces, as a download arrives
+        let mut hasher = Sha256::default();
+        data.chunks(7).for_each(|piece| hasher.update(piece));
+        assert_eq!(sha256::hex(&hasher.finish()), digest);
+        assert_eq!(sha256::parse(&digest.to_uppercase()).unwrap().to_vec(), sha256::parse(digest).unwrap());
+    }
+    assert!(sha256::parse("abc").is_err());
+}
@@ -0,0 +1,34 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use rust_serial_tool::{stats::*, terminal::RxTap};
+
+#[test]
+fn counts_from_several_threads() {
+    let stats = Arc::new(SessionStats::default());
+    let readers: Vec<_> = (0..4).map(|_| {
+        let mut tap = stats.clone();
+        thread::spawn(move || (0..1000).for_each(|_| tap.rx(b"0123456789abcdef")))
+    }).collect();
+    (0..1000).for_each(|_| stats.add_sent(1));
+    readers.into_iter().for_each(|reader| reader.join().unwrap());
+
+    let summary = stats.summary();
+    assert_eq!((summary.received, summary.sent, summary.reconnects, summary.pushed), (64_000, 1000, 0, 0));
+}
+
+#[test]
+fn summaries() {
+    let summary = SessionSummary {
+        received: 2048,
+        sent: 12,
+        duration: Duration::from_millis(61_500),
+        reconnects: 1,
+        pushed: 0,
+        push_time: Duration::ZERO,
+    };
+    assert_eq!(summary.to_string(), "2.0 KiB received, 12 B sent in 61.5s, 1 reconnect");
+
+    let pushed = SessionSummary { sent: 4 << 20, reconnects: 2, pushed: 4 << 20, push_time: Duration::from_secs(2), ..summary };
+    assert_eq!(pushed.push_bytes_per_sec(), (2 << 20) as f64);
+    assert_eq!(pushed.to_string(), "2.0 KiB received, 4.0 MiB sent in 61.5s, 2 reconnects; pushed 4.0 MiB in 2.0s (2.0 MiB/s)");
+}
@@ -0,0 +1,121 @@
+//! Targets behind a terminal server's raw TCP port, played by a local listener.
+
+use std::{io::{self, Read, Write}, net::{TcpListener, TcpStream}, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+use std::{fs, sync::{Arc, Mutex}};
+
+use rust_serial_tool::{ErrorKind, ReadSerial, settings::SerialSettings, transport::{check_name_on, Presence, Target}, WRITE_TIMEOUT, WriteSerial};
+
+/// The next connection that stays open; the tools probe a network target by connecting and
+/// hanging up before they open it.
+fn accept_session(listener: &TcpListener) -> TcpStream {
+    loop {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        match stream.read(&mut [0; 1]) {
+            Ok(0) => continue,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return stream,
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+fn read_exactly(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut data = vec![0; n];
+    stream.read_exact(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn parses_tcp_targets() {
+    assert_eq!(Target::parse("tcp://terminal-server:4001"), Target::Tcp("terminal-server:4001"));
+    assert_eq!(Target::parse("tcp://10.0.0.5:4001/"), Target::Tcp("10.0.0.5:4001"));
+    assert!(Target::parse("tcp://10.0.0.5:4001").fixed_settings());
+    assert!(!Target::parse("rfc2217://10.0.0.5:4001").fixed_settings());
+    assert_eq!(check_name_on("tcp://terminal-server:4001", false), Ok(()));
+    assert_eq!(check_name_on("tcp://terminal-server", true).unwrap_err(), "tcp://terminal-server has no port, e.g. tcp://terminal-server:4001");
+}
+
+#[test]
+fn tcp_ports_read_and_write_like_serial_ones() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let name = format!("tcp://{}", listener.local_addr().unwrap());
+    let target = Target::parse(&name);
+    assert!(matches!(target.presence().unwrap(), Presence::Present(_)));
+
+    // the baud rate is kept, not sent anywhere
+    let settings = SerialSettings { baud_rate: 115_200, ..SerialSettings::default() };
+    let mut port = target.open(&settings, Duration::from_millis(10)).unwrap();
+    let mut server = accept_session(&listener);
+    assert_eq!(port.baud_rate().unwrap(), 115_200);
+    port.set_baud_rate(921_600).unwrap();
+    assert!(port.write_data_terminal_ready(true).is_err());
+
+    // a read that times out is an empty read
+    let started = Instant::now();
+    assert_eq!(port.read_serial(&mut [0; 16]).unwrap(), 0);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    port.write_serial_all(&[0xFF, 0x00, b'!'], WRITE_TIMEOUT).unwrap();
+    assert_eq!(read_exactly(&mut server, 3), [0xFF, 0x00, b'!']);
+    server.write_all(b"pong").unwrap();
+    let mut buf = [0; 4];
+    port.read_serial_exact_timeout(&mut buf, Duration::from_secs(2)).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    // the server hanging up is a lost connection, and the target is gone once it stops listening
+    drop(server);
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        match port.read_serial(&mut buf) {
+            Ok(0) if Instant::now() < deadline => {}
+            Err(ErrorKind::ConnectionError) => break,
+            other => panic!("{:?}", other),
+        }
+    }
+    drop(listener);
+    assert_eq!(target.presence().unwrap(), Presence::Missing);
+}
+
+#[test]
+fn mini_push_pushes_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let name = format!("tcp://{}", listener.local_addr().unwrap());
+    let image_path = std::env::temp_dir().join(format!("tcp-push-{}.img", std::process::id()));
+    let image: Vec<u8> = (0..3000u32).map(|i| (i * 13) as u8).collect();
+    fs::write(&image_path, &image).unwrap();
+
+    let mut push = Command::new(env!("CARGO_BIN_EXE_mini_push"))
+        .args([name.as_str(), image_path.to_str().unwrap(), "--no-terminal", "--color", "never", "--progress", "never", "--baud", "9600"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = Arc::new(Mutex::new(String::new()));
+    let mut pipe = push.stdout.take().unwrap();
+    let collected = stdout.clone();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+            collected.lock().unwrap().push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    });
+
+    let mut loader = accept_session(&listener);
+    loader.write_all(b"booting\r\n\x03\x03\x03").unwrap();
+    assert_eq!(read_exactly(&mut loader, 4), (image.len() as u32).to_le_bytes());
+    loader.write_all(b"OK").unwrap();
+    assert_eq!(read_exactly(&mut loader, image.len()), image);
+
+    let mut stderr = String::new();
+    push.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+    let status = push.wait().unwrap();
+    reader.join().unwrap();
+    let _ = fs::remove_file(&image_path);
+    let stdout = stdout.lock().unwrap();
+    assert!(status.success(), "{}", stderr);
+    assert!(stderr.contains("raw TCP"), "{}", stderr);
+    // the target's output apart from what the tool says
+    assert_eq!(*stdout, "booting\n", "{}", stderr);
+}
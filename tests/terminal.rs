@@ -0,0 +1,277 @@
+use std::{borrow::Cow, io, sync::atomic::{AtomicUsize, Ordering}, time::{Duration, Instant}};
+
+use rust_serial_tool::terminal::{console_closed, HexDump, LINE_IDLE, Newline, RawMode, read_size_for, READ_BATCH, TerminalOptions, translate_rx, utf8_complete};
+
+#[test]
+fn newlines() {
+    for (name, newline, bytes) in [
+        ("cr", Newline::Cr, &b"\r"[..]),
+        ("LF", Newline::Lf, b"\n"),
+        ("crlf", Newline::CrLf, b"\r\n"),
+    ] {
+        assert_eq!(name.parse::<Newline>(), Ok(newline));
+        assert_eq!(newline.bytes(), bytes);
+    }
+    assert!("nl".parse::<Newline>().is_err());
+    assert_eq!(TerminalOptions::default().newline, Newline::Cr);
+    assert!(!TerminalOptions::default().echo);
+}
+
+#[test]
+fn read_size_scales_with_the_baud_rate() {
+    let cases = [(9600, 256), (115_200, 256), (460_800, 1024), (921_600, 2048), (3_000_000, 8192), (12_000_000, 32768), (u32::MAX, 65536)];
+    for (baud, size) in cases {
+        assert_eq!(read_size_for(baud), size, "{}", baud);
+    }
+    let options = TerminalOptions::default();
+    assert_eq!((options.read_size(3_000_000), options.batch_size(3_000_000)), (8192, 8192 * READ_BATCH));
+    let fast = TerminalOptions { high_throughput: true, ..TerminalOptions::default() };
+    assert_eq!((fast.read_size(3_000_000), fast.batch_size(3_000_000)), (32768, 32768 * 16));
+    // --read-buffer wins, still batched
+    let given = TerminalOptions { read_size: Some(1000), high_throughput: true, ..TerminalOptions::default() };
+    assert_eq!((given.read_size(115_200), given.batch_size(115_200)), (1000, 16_000));
+}
+
+#[test]
+fn normalizes_piped_line_endings() {
+    // the input is fed in two pieces, split at the given offset
+    let cases: [(Newline, &[u8], usize, &[u8]); 4] = [
+        (Newline::Cr, b"version\nboot\n", 0, b"version\rboot\r"),
+        (Newline::CrLf, b"a\r\nb\rc\n", 3, b"a\r\nb\r\nc\r\n"),
+        (Newline::Lf, b"a\r\nb", 2, b"a\nb"),
+        (Newline::Cr, b"\n\n", 1, b"\r\r"),
+    ];
+    for (newline, data, split, normalized) in cases.iter() {
+        let mut after_cr = false;
+        let mut out = newline.normalize(&data[..*split], &mut after_cr);
+        out.extend(newline.normalize(&data[*split..], &mut after_cr));
+        assert_eq!(out, *normalized, "{:?}", data);
+    }
+}
+
+#[test]
+fn translates_line_endings_for_where_output_goes() {
+    // (received, on a terminal, into a pipe)
+    let cases = [
+        ("", "", ""),
+        ("no newline", "no newline", "no newline"),
+        ("a\nb\n", "a\r\nb\r\n", "a\nb\n"),
+        ("a\r\nb", "a\r\r\nb", "a\nb"),
+        ("\r\r\n\n", "\r\r\r\n\r\n", "\n\n"),
+        ("progress 10%\rprogress 20%\r", "progress 10%\rprogress 20%\r", "progress 10%progress 20%"),
+        ("größe ✓\n日本\r\n", "größe ✓\r\n日本\r\r\n", "größe ✓\n日本\n"),
+    ];
+    for (received, terminal, pipe) in cases {
+        assert_eq!(translate_rx(received, true), terminal, "{:?}", received);
+        assert_eq!(translate_rx(received, false), pipe, "{:?}", received);
+    }
+    // nothing to change, nothing copied
+    assert!(matches!(translate_rx("a\rb", true), Cow::Borrowed(_)));
+    assert!(matches!(translate_rx("a\nb", false), Cow::Borrowed(_)));
+}
+
+#[test]
+fn holds_back_cut_off_characters() {
+    let cases: [(&[u8], usize); 7] = [
+        (b"", 0),
+        (b"boot\r\n", 6),
+        ("ok \u{2713}".as_bytes(), 6),
+        (b"ok \xe2\x9c", 3),
+        (b"\xf0\x9f\x98", 0),
+        // invalid bytes are not held back, they are replaced when shown
+        (b"\xff\xfe", 2),
+        (b"\xffok\xc3", 3),
+    ];
+    for (data, complete) in cases.iter() {
+        assert_eq!(utf8_complete(data), *complete, "{:?}", data);
+    }
+}
+
+#[test]
+fn hex_rows() {
+    let now = Instant::now();
+    let mut hex = HexDump::new(LINE_IDLE);
+    assert!(hex.push(b"Hello, ", now).is_empty());
+    assert_eq!(hex.push(b"world!\r\n\x00\xff\x1bmore", now), [
+        "00000000  48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0d 0a 00  |Hello, world!...|\n",
+    ]);
+    assert_eq!(hex.flush().unwrap(), format!("00000010  ff 1b 6d 6f 72 65{}  |..more|\n", " ".repeat(30)));
+    assert_eq!(hex.flush(), None);
+}
+
+#[test]
+fn partial_hex_rows_come_out_once() {
+    let now = Instant::now();
+    let mut hex = HexDump::new(Duration::from_millis(100));
+    hex.push(b"AB", now);
+    assert_eq!(hex.flush_idle(now + Duration::from_millis(50)), None);
+    assert!(hex.flush_idle(now + Duration::from_millis(100)).unwrap().starts_with("00000000  41 42 "));
+    assert_eq!(hex.flush_idle(now + Duration::from_secs(1)), None);
+    // the offset carries on where the flushed row stopped
+    assert!(hex.push(b"CDEFGHIJKLMNOPQ", now).is_empty());
+    assert!(hex.flush().unwrap().starts_with("00000002  43 44 "));
+}
+
+// each test gets its own mode and counters, tests run in parallel
+macro_rules! fake_mode {
+    ($mode:ident, $entered:ident, $left:ident) => {
+        static $entered: AtomicUsize = AtomicUsize::new(0);
+        static $left: AtomicUsize = AtomicUsize::new(0);
+        static $mode: RawMode = RawMode::new(
+            || { $entered.fetch_add(1, Ordering::SeqCst); Ok(()) },
+            || { $left.fetch_add(1, Ordering::SeqCst); Ok(()) },
+        );
+    };
+}
+
+fn counts(entered: &AtomicUsize, left: &AtomicUsize) -> (usize, usize) {
+    (entered.load(Ordering::SeqCst), left.load(Ordering::SeqCst))
+}
+
+#[test]
+fn nested_guards_enter_and_leave_once() {
+    fake_mode!(MODE, ENTERED, LEFT);
+    let outer = MODE.guard().unwrap();
+    let inner = MODE.guard().unwrap();
+    assert!(MODE.is_raw());
+    drop(inner);
+    assert_eq!(counts(&ENTERED, &LEFT), (1, 0));
+    drop(outer);
+    assert_eq!(counts(&ENTERED, &LEFT), (1, 1));
+    assert!(!MODE.is_raw());
+}
+
+#[test]
+fn restore_is_idempotent() {
+    fake_mode!(MODE, ENTERED, LEFT);
+    MODE.restore();
+    assert_eq!(counts(&ENTERED, &LEFT), (0, 0));
+
+    let guard = MODE.guard().unwrap();
+    MODE.restore();
+    MODE.restore();
+    drop(guard);
+    assert_eq!(counts(&ENTERED, &LEFT), (1, 1));
+}
+
+#[test]
+fn early_return_restores() {
+    fake_mode!(MODE, ENTERED, LEFT);
+    fn session() -> io::Result<()> {
+        let _raw = MODE.guard()?;
+        Err(io::Error::other("port gone"))?;
+        unreachable!()
+    }
+    assert!(session().is_err());
+    assert_eq!(counts(&ENTERED, &LEFT), (1, 1));
+    assert!(!MODE.is_raw());
+}
+
+#[test]
+fn failed_enter_leaves_nothing_to_undo() {
+    static LEFT: AtomicUsize = AtomicUsize::new(0);
+    static MODE: RawMode = RawMode::new(|| Err(io::Error::other("not a tty")),
+                                        || { LEFT.fetch_add(1, Ordering::SeqCst); Ok(()) });
+    assert!(MODE.guard().is_err());
+    assert!(!MODE.is_raw());
+    MODE.restore();
+    assert_eq!(LEFT.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn only_a_gone_console_ends_the_session_quietly() {
+    assert!(console_closed(&io::Error::from(io::ErrorKind::UnexpectedEof)));
+    assert!(console_closed(&io::Error::from(io::ErrorKind::BrokenPipe)));
+    assert!(console_closed(&io::Error::from_raw_os_error(5)));
+    assert!(!console_closed(&io::Error::from(io::ErrorKind::PermissionDenied)));
+    assert!(!console_closed(&io::Error::other("bad event")));
+}
+
+/// Piped input that only arrives after `wait`, e.g. once the port is back.
+#[cfg(feature = "mock")]
+struct Later {
+    wait: Option<Duration>,
+    data: io::Cursor<&'static [u8]>,
+}
+
+#[cfg(feature = "mock")]
+impl io::Read for Later {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(wait) = self.wait.take() { std::thread::sleep(wait); }
+        self.data.read(buf)
+    }
+}
+
+/// A port that comes back as `next` once it went away.
+#[cfg(feature = "mock")]
+struct Replugged {
+    port: rust_serial_tool::SerialPort,
+    next: Option<rust_serial_tool::SerialPort>,
+}
+
+#[cfg(feature = "mock")]
+impl rust_serial_tool::terminal::SerialConnection for Replugged {
+    fn port(&mut self) -> &mut rust_serial_tool::SerialPort {
+        &mut self.port
+    }
+
+    fn reopen(&mut self) -> Option<rust_serial_tool::SerialPort> {
+        self.next.take()
+    }
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn runs_against_a_mock() {
+    use rust_serial_tool::{mock::MockSerial, output::{Output, Verbosity}, terminal::{ExitReason, Terminal}};
+
+    let mock = MockSerial::new().expect(b"root\r");
+    let options = TerminalOptions { exit_on_eof: true, ..TerminalOptions::default() };
+    let reason = Terminal::new(Output::new("T", Verbosity::Quiet))
+        .options(options)
+        .input(Box::new(Later { wait: None, data: io::Cursor::new(b"root\n") }))
+        .run(&mut (Box::new(mock.clone()) as rust_serial_tool::SerialPort))
+        .unwrap();
+    assert_eq!(reason, ExitReason::StdinClosed);
+    mock.assert_done();
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn writes_reach_the_port_that_came_back() {
+    use std::sync::Arc;
+
+    use rust_serial_tool::{mock::MockSerial, output::{Output, Verbosity}, stats::SessionStats, terminal::{ExitReason, Terminal}};
+
+    let (unplugged, replugged) = (MockSerial::new().disconnect(), MockSerial::new().expect(b"hi\r"));
+    let mut connection = Replugged { port: Box::new(unplugged.clone()), next: Some(Box::new(replugged.clone())) };
+    let stats = Arc::new(SessionStats::default());
+    let options = TerminalOptions { reconnect: 3, exit_on_eof: true, ..TerminalOptions::default() };
+    let reason = Terminal::new(Output::new("T", Verbosity::Quiet))
+        .options(options)
+        .stats(stats.clone())
+        .input(Box::new(Later { wait: Some(Duration::from_millis(300)), data: io::Cursor::new(b"hi\n") }))
+        .run(&mut connection)
+        .unwrap();
+    assert_eq!(reason, ExitReason::StdinClosed);
+    assert!(unplugged.written().is_empty());
+    replugged.assert_done();
+    assert_eq!(stats.summary().reconnects, 1);
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn gives_up_on_a_port_that_stays_away() {
+    use rust_serial_tool::{ErrorKind, mock::MockSerial, output::{Output, Verbosity}, terminal::Terminal};
+
+    let mut connection = Replugged { port: Box::new(MockSerial::new().disconnect()), next: None };
+    let options = TerminalOptions { reconnect: 2, ..TerminalOptions::default() };
+    let started = Instant::now();
+    let result = Terminal::new(Output::new("T", Verbosity::Quiet))
+        .options(options)
+        .input(Box::new(io::empty()))
+        .run(&mut connection);
+    assert!(matches!(result, Err(ErrorKind::ConnectionError)), "{:?}", result);
+    // two attempts, a second apart
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use rust_serial_tool::timesync::{Iso8601, TimeFormat};
+
+#[test]
+fn parses_formats() {
+    let cases = [
+        ("unix64", Some(TimeFormat::Unix64)),
+        ("text:date -s '{iso8601}'\\n", Some(TimeFormat::Text("date -s '{iso8601}'\\n".to_string()))),
+        ("text:", None),
+        ("text:bad \\q", None),
+        ("unix32", None),
+    ];
+    for (s, expected) in cases {
+        assert_eq!(s.parse::<TimeFormat>().ok(), expected, "{}", s);
+    }
+}
+
+#[test]
+fn dates_are_utc_and_gregorian() {
+    let cases = [
+        (0, "1970-01-01T00:00:00Z"),
+        (951_782_400, "2000-02-29T00:00:00Z"),
+        (951_868_799, "2000-02-29T23:59:59Z"),
+        (1_709_211_909, "2024-02-29T13:05:09Z"),
+        (1_735_689_599, "2024-12-31T23:59:59Z"),
+        (2_147_483_648, "2038-01-19T03:14:08Z"),
+        (4_107_542_400, "2100-03-01T00:00:00Z"),
+    ];
+    for (unix, expected) in cases {
+        assert_eq!(Iso8601(unix).to_string(), expected, "{}", unix);
+    }
+}
+
+#[test]
+fn encodes_the_time() {
+    let time = UNIX_EPOCH + Duration::from_millis(1_709_211_909_750);
+    let cases = [
+        (TimeFormat::Unix64, 1_709_211_909u64.to_le_bytes().to_vec()),
+        ("text:date -s '{iso8601}'\\n".parse().unwrap(), b"date -s '2024-02-29T13:05:09Z'\n".to_vec()),
+        ("text:settime {unix}\\r".parse().unwrap(), b"settime 1709211909\r".to_vec()),
+        ("text:{unix} {unix}".parse().unwrap(), b"1709211909 1709211909".to_vec()),
+        ("text:no time".parse().unwrap(), b"no time".to_vec()),
+    ];
+    for (format, expected) in cases {
+        assert_eq!(format.encode(time), expected, "{:?}", format);
+    }
+    assert_eq!(TimeFormat::Unix64.encode(UNIX_EPOCH - Duration::from_secs(1)), [0; 8]);
+}
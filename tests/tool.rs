@@ -0,0 +1,105 @@
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+use rust_serial_tool::{ErrorKind, lock, output::{Output, Verbosity}, Result, SerialTool};
+
+/// A tool that fails with `error` the first time and succeeds after that, counting its runs.
+/// With a `port`, every run opens it first.
+struct Counting {
+    port: Option<String>,
+    output: Output,
+    execs: Arc<AtomicUsize>,
+    error: Option<ErrorKind>,
+}
+
+impl SerialTool for Counting {
+    fn target_serial_name(&self) -> &str {
+        self.port.as_deref().unwrap_or("none")
+    }
+
+    fn name_short(&self) -> &str {
+        "CT"
+    }
+
+    fn output(&self) -> &Output {
+        &self.output
+    }
+
+    fn exec(&mut self) -> Result<()> {
+        self.execs.fetch_add(1, Ordering::Relaxed);
+        if self.port.is_some() { self.open_serial()?; }
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+fn tool(error: Option<ErrorKind>, execs: &Arc<AtomicUsize>) -> Box<dyn SerialTool> {
+    Box::new(Counting { port: None, output: Output::new("CT", Verbosity::Quiet), execs: execs.clone(), error })
+}
+
+#[test]
+fn runs_through_a_box() {
+    let execs = Arc::new(AtomicUsize::new(0));
+    // picked at runtime, like a launcher choosing between a push and a terminal
+    let mut tools: Vec<Box<dyn SerialTool>> = vec![tool(None, &execs), tool(Some(ErrorKind::ConnectionError), &execs)];
+    for tool in tools.iter_mut() {
+        assert!(tool.run().is_ok());
+    }
+    // the connection error was reconnected from, with a second exec
+    assert_eq!(execs.load(Ordering::Relaxed), 3);
+
+    let mut failing = tool(Some(ErrorKind::ScriptError("no such step".to_string())), &execs);
+    let error = failing.run().unwrap_err();
+    assert!(matches!(error.kind(), ErrorKind::ScriptError(reason) if reason == "no such step"));
+    assert_eq!(error.to_string(), r#"ScriptError("no such step") during session on none"#);
+}
+
+#[test]
+fn errors_say_where_they_happened() {
+    let cases: [(ErrorKind, &str); 3] = [
+        (ErrorKind::ConnectionError.context("handshake", "/dev/ttyUSB0"), "ConnectionError during handshake on /dev/ttyUSB0"),
+        (ErrorKind::ReadTimeout { received: 1, expected: 2 }.context("size", "COM3"),
+         "ReadTimeout { received: 1, expected: 2 } during size on COM3"),
+        // the innermost context wins
+        (ErrorKind::ProtocolError.context("push", "COM3").context("terminal", "COM4"), "ProtocolError during push on COM3"),
+    ];
+    for (error, rendered) in cases {
+        assert_eq!(error.to_string(), rendered);
+        assert!(!matches!(error.kind(), ErrorKind::WithContext { .. }));
+    }
+    assert_eq!(ErrorKind::TimeoutError.context("push", "COM3").name(), "timeout");
+    assert_eq!(ErrorKind::Interrupted.to_string(), "Interrupted");
+}
+
+#[cfg(unix)]
+#[test]
+fn open_failures_are_returned() {
+    // a file that isn't a tty: present, but opening it as a port fails
+    let dir = std::env::temp_dir().join(format!("tool-open-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let device = dir.join("ttyFAKE0");
+    std::fs::write(&device, b"").unwrap();
+    let execs = Arc::new(AtomicUsize::new(0));
+    let opening = |execs: &Arc<AtomicUsize>| Counting {
+        port: Some(device.display().to_string()),
+        output: Output::new("CT", Verbosity::Quiet),
+        execs: execs.clone(),
+        error: None,
+    };
+
+    let error = opening(&execs).run().unwrap_err();
+    assert!(matches!(error.kind(), ErrorKind::SerialError(_)), "{}", error);
+    assert_eq!(error.phase(), Some("open"));
+    assert_eq!(error.exit_code(), 1);
+    assert_eq!(execs.load(Ordering::Relaxed), 1);
+
+    // held by a live process, ours
+    let lock = lock::lock_dirs().last().unwrap().join(lock::lock_name(&device).unwrap());
+    std::fs::write(&lock, format!("{:>10}\n", std::process::id())).unwrap();
+    let error = opening(&execs).run().unwrap_err();
+    let _ = std::fs::remove_file(&lock);
+    assert!(matches!(error.kind(), ErrorKind::PortLocked { stale: false, .. }), "{}", error);
+    assert_eq!(error.exit_code(), lock::LOCKED_EXIT_CODE);
+    let _ = std::fs::remove_dir_all(&dir);
+}
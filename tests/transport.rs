@@ -0,0 +1,197 @@
+use rust_serial_tool::transport::{check_name_on, classify_access, device_name, explain_open_error, explain_open_error_on, group_name};
+use rust_serial_tool::transport::{find_listing, match_description, PortListing, Presence, resolve_native, Target, UsbIdentity};
+use rust_serial_tool::ErrorKind;
+#[cfg(unix)]
+use serialport::SerialPort as _;
+
+const PORTS: &[&str] = &[
+    "/dev/ttyUSB0",
+    "/dev/tty.usbserial-A50285BI",
+    "/dev/cu.usbserial-A50285BI",
+    "/dev/cu.usbmodem14201",
+    "/dev/cu.usbmodem14101",
+    "COM3",
+];
+
+#[test]
+fn resolves_against_port_list() {
+    let cases = [
+        ("/dev/ttyUSB0", Some("/dev/ttyUSB0")),
+        ("/dev/ttyUSB1", None),
+        ("com3", Some("COM3")),
+        ("COM4", None),
+        ("/dev/cu.usbserial-*", Some("/dev/cu.usbserial-A50285BI")),
+        // the lowest name wins so the pick is stable
+        ("/dev/cu.usbmodem*", Some("/dev/cu.usbmodem14101")),
+        ("/dev/cu.usbmodem*01", Some("/dev/cu.usbmodem14101")),
+        ("/dev/ttyACM*", None),
+        ("*", Some("/dev/cu.usbmodem14101")),
+        (r"\\.\COM3", Some("COM3")),
+        (r"\\.\com3", Some("COM3")),
+    ];
+    for (name, expected) in cases.iter() {
+        assert_eq!(resolve_native(name, PORTS).as_deref(), *expected, "{}", name);
+    }
+}
+
+#[test]
+fn macos_twins_stand_in_for_each_other() {
+    let cu_only = ["/dev/cu.usbserial-1410"];
+    assert_eq!(resolve_native("/dev/tty.usbserial-1410", &cu_only).as_deref(), Some("/dev/cu.usbserial-1410"));
+    let tty_only = ["/dev/tty.usbserial-1410"];
+    assert_eq!(resolve_native("/dev/cu.usbserial-1410", &tty_only).as_deref(), Some("/dev/tty.usbserial-1410"));
+    // an exact match is preferred over the twin
+    assert_eq!(resolve_native("/dev/tty.usbserial-A50285BI", PORTS).as_deref(), Some("/dev/tty.usbserial-A50285BI"));
+}
+
+#[test]
+fn empty_port_list() {
+    let none: [&str; 0] = [];
+    assert_eq!(resolve_native("/dev/ttyUSB0", &none), None);
+    assert_eq!(resolve_native("/dev/cu.*", &none), None);
+}
+
+#[test]
+fn device_namespace_is_stripped() {
+    assert_eq!(device_name(r"\\.\COM12"), "COM12");
+    assert_eq!(device_name("//./COM12"), "COM12");
+    assert_eq!(device_name("COM12"), "COM12");
+    assert_eq!(device_name("/dev/ttyUSB0"), "/dev/ttyUSB0");
+}
+
+fn listing(name: &str, description: &str) -> PortListing {
+    PortListing { name: name.to_string(), description: description.to_string(), serial_number: None, usb_id: None }
+}
+
+#[test]
+fn matches_descriptions() {
+    let ports = [
+        listing("COM3", "Silicon Labs CP210x USB to UART Bridge"),
+        listing("COM5", "FTDI FT232R USB UART"),
+        listing("COM7", "FTDI FT232R USB UART"),
+        listing("COM1", ""),
+    ];
+    let names = |query: &str| match_description(query, &ports).iter().map(|port| port.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names("Silicon Labs CP210x"), ["COM3"]);
+    assert_eq!(names("cp210x"), ["COM3"]);
+    assert_eq!(names("FT232R"), ["COM5", "COM7"]);
+    assert!(names("CH340").is_empty());
+    assert!(names("").is_empty());
+}
+
+fn adapter(name: &str, usb_id: (u16, u16), serial_number: Option<&str>) -> PortListing {
+    PortListing { usb_id: Some(usb_id), serial_number: serial_number.map(str::to_string), ..listing(name, "") }
+}
+
+#[test]
+fn tells_adapters_apart_under_the_same_name() {
+    let before = [adapter("/dev/ttyUSB0", (0x10c4, 0xea60), Some("0001")), listing("/dev/ttyS0", "")];
+    // the hub reset, and the other board's adapter came up first
+    let after = [adapter("/dev/ttyUSB0", (0x0403, 0x6001), Some("A50285BI")), adapter("/dev/ttyUSB1", (0x10c4, 0xea60), Some("0001"))];
+    let identity = |ports: &[PortListing], path: &str| find_listing(ports, path, None).and_then(UsbIdentity::of);
+
+    let was = identity(&before, "/dev/ttyUSB0").unwrap();
+    assert_eq!(was, UsbIdentity { usb_id: (0x10c4, 0xea60), serial_number: Some("0001".to_string()) });
+    assert_ne!(identity(&after, "/dev/ttyUSB0"), Some(was.clone()));
+    assert_eq!(identity(&after, "/dev/ttyUSB1"), Some(was));
+    assert_eq!(identity(&before, "/dev/ttyS0"), None);
+    assert_eq!(identity(&before, "/dev/ttyACM0"), None);
+
+    // the same make without serial numbers can't be told apart
+    let bare = [adapter("/dev/ttyUSB0", (0x1a86, 0x7523), None)];
+    let twin = [adapter("/dev/ttyUSB0", (0x1a86, 0x7523), None)];
+    assert_eq!(identity(&bare, "/dev/ttyUSB0"), identity(&twin, "/dev/ttyUSB0"));
+}
+
+#[test]
+fn finds_the_listing_a_link_points_at() {
+    let ports = [adapter("/dev/ttyUSB3", (0x0403, 0x6001), Some("A50285BI"))];
+    let link = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0";
+    assert_eq!(find_listing(&ports, link, None), None);
+    assert_eq!(find_listing(&ports, link, Some("/dev/ttyUSB3".as_ref())), Some(&ports[0]));
+}
+
+#[test]
+fn says_how_the_device_changed() {
+    let was = UsbIdentity { usb_id: (0x10c4, 0xea60), serial_number: Some("0001".to_string()) };
+    let now = UsbIdentity { usb_id: (0x1a86, 0x7523), serial_number: None };
+    let error = ErrorKind::DeviceChanged { device: "/dev/ttyUSB0".to_string(), was: was.to_string(), now: now.to_string() };
+    assert_eq!(error.to_string(), "device at /dev/ttyUSB0 changed identity: it was 10c4:ea60 serial 0001, now it is \
+                                   1a86:7523 without a serial number; --allow-device-change uses it anyway");
+    assert_eq!(error.name(), "device_changed");
+}
+
+#[test]
+fn explains_open_errors() {
+    let error = |kind, description: &str| serialport::Error::new(kind, description);
+    let cases = [
+        (error(serialport::ErrorKind::NoDevice, "Access is denied."), "in use by another program"),
+        (error(serialport::ErrorKind::Io(std::io::ErrorKind::Other), "Device or resource busy"), "in use by another program"),
+        (error(serialport::ErrorKind::Io(std::io::ErrorKind::Other), "The device is not connected."), "went away"),
+        (error(serialport::ErrorKind::Io(std::io::ErrorKind::Other), "The parameter is incorrect."), "went away"),
+        (error(serialport::ErrorKind::NoDevice, "The system cannot find the file specified."), "went away"),
+        // the permission hint already says what to do
+        (error(serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied), "permission denied on /dev/ttyUSB0: add yourself"), "add yourself"),
+        (error(serialport::ErrorKind::Unknown, "something else"), "COM7: something else"),
+    ];
+    for (error, expected) in cases.iter() {
+        let message = explain_open_error("COM7", error);
+        assert!(message.contains(expected), "{:?} -> {}", error, message);
+    }
+
+    // what unix means by it is the permission hint, what Windows means is someone else having it open
+    let denied = error(serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied), "Access is denied.");
+    assert!(explain_open_error_on("COM7", &denied, true).contains("in use by another program"));
+    assert_eq!(explain_open_error_on("COM7", &denied, false), "COM7: Access is denied.");
+}
+
+#[test]
+fn tells_missing_from_denied() {
+    use std::io::ErrorKind::{Interrupted, NotFound, PermissionDenied};
+    let path = "/dev/ttyUSB0";
+    let present = || Presence::Present(path.to_string());
+    let cases = [
+        (Ok(()), Some("dialout"), present()),
+        (Err(NotFound), None, Presence::Missing),
+        (Err(PermissionDenied), Some("dialout"), Presence::PermissionDenied("permission denied on /dev/ttyUSB0: add yourself to the \
+            dialout group (sudo usermod -aG dialout $USER) and log in again".to_string())),
+        (Err(PermissionDenied), None, Presence::PermissionDenied("permission denied on /dev/ttyUSB0: check its owner and mode".to_string())),
+        (Err(Interrupted), None, present()),
+    ];
+    for (checked, group, expected) in cases {
+        assert_eq!(classify_access(path, checked, group), expected, "{:?} {:?}", checked, group);
+    }
+
+    let groups = "root:x:0:\nbroken\ndialout:x:20:pi\nuucp:x:14:";
+    assert_eq!(group_name(20, groups), Some("dialout"));
+    assert_eq!(group_name(14, groups), Some("uucp"));
+    assert_eq!(group_name(1000, groups), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn unlisted_paths_are_found_on_disk() {
+    let (_master, slave) = serialport::TTYPort::pair().unwrap();
+    let name = slave.name().unwrap();
+    assert_eq!(Target::parse(&name).presence().unwrap(), Presence::Present(name.clone()));
+    assert_eq!(Target::parse("/dev/definitely-not-a-tty").presence().unwrap(), Presence::Missing);
+}
+
+#[test]
+fn catches_name_typos() {
+    let unix_ok = ["/dev/ttyUSB0", "/dev/serial/by-id/usb-FTDI-if00", "CP210x", "ttyUSB0", "rfc2217://host:2217"];
+    for name in unix_ok {
+        assert_eq!(check_name_on(name, false), Ok(()), "{}", name);
+    }
+    for name in ["/dve/ttyUSB0", "/dev/nonexistent-dir/ttyUSB0", "", "rfc2217://host"] {
+        assert!(check_name_on(name, false).is_err(), "{}", name);
+    }
+
+    let windows_ok = ["COM3", "com12", r"\\.\COM10", "USB Serial", "Communications Port"];
+    for name in windows_ok {
+        assert_eq!(check_name_on(name, true), Ok(()), "{}", name);
+    }
+    for name in ["COM", "COM3:", "COM3a", r"\\.\ttyUSB0", r"\\.\COM"] {
+        assert!(check_name_on(name, true).is_err(), "{}", name);
+    }
+}
@@ -0,0 +1,61 @@
+use rust_serial_tool::trigger::*;
+#[cfg(feature = "mock")]
+use {std::time::Instant, rust_serial_tool::{mock::MockSerial, ReadSerial, terminal::{LINE_IDLE, LineBuffer}}};
+
+fn triggers(rules: &[&str]) -> Triggers {
+    Triggers::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+}
+
+#[test]
+fn parses_rules() {
+    let cases = [
+        ("Kernel panic:exit", "Kernel panic", Action::Exit(1)),
+        ("Kernel panic:exit:3", "Kernel panic", Action::Exit(3)),
+        ("login::bell", "login:", Action::Bell),
+        ("(?:ERR|WARN):exec:notify-send \"$SERIAL_TOOL_LINE\"", "(?:ERR|WARN)", Action::Exec("notify-send \"$SERIAL_TOOL_LINE\"".to_string())),
+        ("marker:exec:a:b", "marker", Action::Exec("a:b".to_string())),
+    ];
+    for (arg, regex, action) in cases.iter() {
+        let rule: Trigger = arg.parse().unwrap();
+        assert_eq!((rule.regex.as_str(), &rule.action), (*regex, action), "{}", arg);
+    }
+    for arg in ["panic", "panic:exit:x", ":bell", "(:exit", "x:exec:"].iter() {
+        assert!(arg.parse::<Trigger>().is_err(), "{}", arg);
+    }
+    assert!(triggers(&["login:bell", "panic:exit:2"]).exits());
+    assert!(!triggers(&["login:bell"]).exits());
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn matches_lines_split_across_reads() {
+    let rules = triggers(&["Kernel panic:exit:2", "login:bell", "panic:exec:true"]);
+    let mut port = MockSerial::new().reply(b"boot ok\r\nKernel pa").reply(b"nic - not syncing\r\n").reply(b"login: ");
+    let mut lines = LineBuffer::new(LINE_IDLE);
+    let mut fired = Vec::new();
+
+    let mut buf = [0; 64];
+    loop {
+        let n = port.read_serial(&mut buf).unwrap();
+        // the rest of the stream arrives after the target has gone quiet
+        let now = if n == 0 { Instant::now() + LINE_IDLE } else { Instant::now() };
+        for line in lines.push(&buf[..n], now).into_iter().chain(lines.flush_idle(now)) {
+            let line = String::from_utf8_lossy(&line);
+            fired.extend(rules.matching(&line).map(|action| (line.trim_end().to_string(), action.clone())));
+        }
+        if n == 0 { break; }
+    }
+
+    assert_eq!(fired, [
+        ("Kernel panic - not syncing".to_string(), Action::Exit(2)),
+        ("Kernel panic - not syncing".to_string(), Action::Exec("true".to_string())),
+        ("login:".to_string(), Action::Bell),
+    ]);
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_passes_the_line() {
+    let out = exec("test \"$SERIAL_TOOL_LINE\" = 'Kernel panic'", "Kernel panic").unwrap().wait().unwrap();
+    assert!(out.success());
+}
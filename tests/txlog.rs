@@ -0,0 +1,71 @@
+mod common;
+
+use std::{sync::mpsc, time::{Duration, Instant}};
+
+use common::Shared;
+use rust_serial_tool::txlog::{QUEUE_MAX, TxLog, TxLogFormat};
+#[cfg(feature = "mock")]
+use {std::{io::Cursor, sync::Arc}, rust_serial_tool::{mock::MockSerial, protocol::{self, SizeHeader}, record::{self, Direction}, txlog, WRITE_TIMEOUT, WriteSerial}};
+
+#[cfg(feature = "mock")]
+#[test]
+fn mirrors_what_is_written_to_the_port() {
+    for format in [TxLogFormat::Raw, TxLogFormat::Record] {
+        let file = Shared::default();
+        let log = Arc::new(TxLog::new(Box::new(file.clone()), format, "tx.log").unwrap());
+        let mock = MockSerial::new().expect(&6u32.to_le_bytes()).reply(b"OK").expect(b"kernel").expect(b"reboot\n");
+        let mut port = txlog::mirror(Box::new(mock.clone()), Some(&log));
+        protocol::send_size(&mut port, 6, SizeHeader::Legacy).unwrap();
+        port.write_serial_all(b"kernel", WRITE_TIMEOUT).unwrap();
+        // a clone, as the terminal's reader has, writes to the same log
+        port.try_clone().unwrap().write_serial_all(b"reboot\n", WRITE_TIMEOUT).unwrap();
+        mock.assert_done();
+
+        let summary = log.finish();
+        assert_eq!((summary.logged, summary.dropped, summary.error), (17, 0, None));
+        let data = file.contents();
+        let sent = [&6u32.to_le_bytes()[..], b"kernel", b"reboot\n"].concat();
+        match format {
+            TxLogFormat::Raw => assert_eq!(data, sent),
+            TxLogFormat::Record => {
+                let mut recording = Cursor::new(data);
+                record::read_magic(&mut recording).unwrap();
+                let mut frames = Vec::new();
+                while let Some(frame) = record::read_frame(&mut recording).unwrap() { frames.push(frame); }
+                assert!(frames.iter().all(|frame| frame.direction == Direction::Tx));
+                assert_eq!(frames.iter().flat_map(|frame| frame.data.clone()).collect::<Vec<u8>>(), sent);
+            }
+        }
+        // closed: what goes out later is only counted
+        log.note(b"late");
+        assert_eq!(log.dropped(), 4);
+    }
+}
+
+#[test]
+fn drops_rather_than_holding_up_the_port() {
+    let (release, gate) = mpsc::channel();
+    let file = Shared::gated(gate);
+    let log = TxLog::new(Box::new(file.clone()), TxLogFormat::Raw, "tx.log").unwrap();
+    let chunk = vec![0x5a; 64 * 1024];
+    let chunks = QUEUE_MAX / chunk.len() + 16;
+    let started = Instant::now();
+    for _ in 0..chunks { log.note(&chunk); }
+    // the file takes nothing meanwhile, and the writes went on regardless
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(log.dropped() >= 15 * chunk.len() as u64, "{}", log.dropped());
+
+    for _ in 0..chunks { let _ = release.send(()); }
+    drop(release);
+    let summary = log.finish();
+    assert_eq!(summary.logged + summary.dropped, (chunks * chunk.len()) as u64);
+    assert_eq!(file.contents().len() as u64, summary.logged);
+}
+
+#[test]
+fn parses_the_format() {
+    for (text, format) in [("raw", TxLogFormat::Raw), ("record", TxLogFormat::Record), ("RECORD", TxLogFormat::Record)] {
+        assert_eq!(text.parse::<TxLogFormat>().unwrap(), format);
+    }
+    assert!("hex".parse::<TxLogFormat>().is_err());
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::{fs, path::PathBuf, sync::atomic::Ordering, thread, time::{Duration, Instant}};
+
+use common::scratch;
+use rust_serial_tool::watch::{self, Build, ImageStamp, Snapshot, Watch};
+
+#[test]
+fn snapshots_tell_what_changed() {
+    let dir = scratch("snapshot");
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.join("src/old.rs"), "").unwrap();
+    let paths = [dir.clone()];
+    let before = Snapshot::take(&paths);
+
+    fs::write(dir.join("src/main.rs"), "fn main() { boot(); }").unwrap();
+    fs::remove_file(dir.join("src/old.rs")).unwrap();
+    fs::write(dir.join("src/new.rs"), "").unwrap();
+    // where git and cargo write, and a path that isn't there at all
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::write(dir.join(".git/index"), "x").unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+    fs::write(dir.join("target/kernel.o"), "x").unwrap();
+    let after = Snapshot::take(&[dir.clone(), dir.join("missing")]);
+
+    let expected: Vec<PathBuf> = ["src/main.rs", "src/new.rs", "src/old.rs"].iter().map(|name| dir.join(name)).collect();
+    assert_eq!(after.changes(&before), expected);
+    assert!(after.changes(&after).is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn watch_sets_its_flag_once_changes_settle() {
+    let dir = scratch("flag");
+    fs::create_dir(dir.join("src")).unwrap();
+    let watch = Watch::spawn(vec![dir.join("src")], Duration::from_millis(20));
+    let changed = watch.changed();
+    thread::sleep(Duration::from_millis(60));
+    assert!(!changed.load(Ordering::Relaxed));
+
+    fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+    let started = Instant::now();
+    while !changed.load(Ordering::Relaxed) {
+        assert!(started.elapsed() < Duration::from_secs(5), "no change seen");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(watch.changes(), [dir.join("src/main.rs")]);
+
+    watch.rebase();
+    assert!(!changed.load(Ordering::Relaxed));
+    assert!(watch.changes().is_empty());
+    thread::sleep(Duration::from_millis(100));
+    assert!(!changed.load(Ordering::Relaxed));
+    drop(watch);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn image_stamps_see_contents_under_the_same_time() {
+    let dir = scratch("stamp");
+    fs::create_dir(dir.join("src")).unwrap();
+    let path = dir.join("kernel8.img");
+    fs::write(&path, b"build 1").unwrap();
+    let first = ImageStamp::of(&path).unwrap();
+    assert_eq!(ImageStamp::of(&path).unwrap(), first);
+
+    // a second build within the same tick of a coarse clock
+    fs::write(&path, b"build 2").unwrap();
+    fs::File::options().write(true).open(&path).unwrap().set_modified(first.modified.unwrap()).unwrap();
+    let second = ImageStamp::of(&path).unwrap();
+    assert_eq!(second.modified, first.modified);
+    assert_ne!(second, first);
+    assert!(ImageStamp::of(dir.join("missing.img")).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn builds_succeed_or_fail_by_exit_status() {
+    assert_eq!(watch::build("true").unwrap(), Build::Succeeded);
+    match watch::build("echo building; exit 3").unwrap() {
+        Build::Failed(status) => assert_eq!(status.code(), Some(3)),
+        build => panic!("{:?}", build),
+    }
+}
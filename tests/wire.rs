@@ -0,0 +1,44 @@
+mod common;
+
+use std::{sync::Arc, thread, time::Duration};
+
+use common::Shared;
+use rust_serial_tool::{output::{Output, Verbosity}, record::Direction, wire::{self, Entry, WireLog}};
+
+#[test]
+fn renders_rows_of_each_direction() {
+    let at = Duration::from_millis(512);
+    let cases: [(Direction, &[u8], &[&str]); 3] = [
+        (Direction::Tx, b"reboot\r", &["   0.512 >> 72 65 62 6f 6f 74 0d                             |reboot.|\n"]),
+        (Direction::Rx, b"\x03\x03\x03", &["   0.512 << 03 03 03                                         |...|\n"]),
+        (Direction::Rx, b"0123456789abcdefXY", &[
+            "   0.512 << 30 31 32 33 34 35 36 37 38 39 61 62 63 64 65 66  |0123456789abcdef|\n",
+            "            58 59                                            |XY|\n",
+        ]),
+    ];
+    for (direction, data, expected) in cases {
+        assert_eq!(wire::rows(at, direction, data), expected, "{:?}", data);
+    }
+    assert_eq!(serde_json::to_string(&Entry::new(Duration::from_micros(1_500_250), Direction::Tx, b"OK")).unwrap(),
+               r#"{"t":1.50025,"dir":"tx","hex":"4f4b"}"#);
+}
+
+#[test]
+fn logs_both_sides_whole_and_in_order() {
+    let file = Shared::default();
+    let wire = Arc::new(WireLog::new(Output::new("T", Verbosity::Quiet), Some(Box::new(file.clone()))));
+    let sides: Vec<_> = [Direction::Tx, Direction::Rx].iter().map(|&direction| {
+        let wire = wire.clone();
+        thread::spawn(move || for _ in 0..50 { wire.note(direction, &[0x55; 40]); })
+    }).collect();
+    sides.into_iter().for_each(|side| side.join().unwrap());
+    wire.note(Direction::Rx, b"");
+
+    let log = String::from_utf8(file.contents()).unwrap();
+    let entries: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(entries.len(), 100);
+    assert!(entries.iter().all(|entry| entry["hex"] == "55".repeat(40)));
+    assert_eq!(entries.iter().filter(|entry| entry["dir"] == "tx").count(), 50);
+    let times: Vec<f64> = entries.iter().map(|entry| entry["t"].as_f64().unwrap()).collect();
+    assert!(times.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", times);
+}
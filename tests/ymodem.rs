@@ -0,0 +1,201 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use std::fs;
+
+use common::scratch;
+use rust_serial_tool::{ErrorKind, mock::MockSerial, ymodem::*};
+
+#[test]
+fn crc16_is_the_xmodem_one() {
+    assert_eq!(crc16(b""), 0);
+    assert_eq!(crc16(b"123456789"), 0x31C3);
+    assert_eq!(crc16(&[0; 128]), 0);
+}
+
+#[test]
+fn blocks_decode_as_they_were_encoded() {
+    let cases: [(u8, &[u8], u8, usize); 4] = [
+        (0, b"kernel8.img\x0012345", SOH, 128),
+        (1, &[0x55; 128], SOH, 128),
+        (2, &[0xAA; 129], STX, 1024),
+        (255, &[7; 1024], STX, 1024),
+    ];
+    for (seq, data, start, len) in cases {
+        let block = encode_block(seq, data);
+        assert_eq!((block[0], block.len()), (start, len + 5));
+        let (decoded_seq, decoded) = decode_block(&block).unwrap();
+        assert_eq!(decoded_seq, seq);
+        assert_eq!(&decoded[..data.len()], data);
+        assert!(decoded[data.len()..].iter().all(|&b| b == SUB));
+    }
+}
+
+#[test]
+fn corrupted_blocks_are_rejected() {
+    let good = encode_block(3, b"results");
+    let corrupt = |at: usize| {
+        let mut block = good.clone();
+        block[at] ^= 0x40;
+        block
+    };
+    let cases: [(&str, Vec<u8>); 7] = [
+        ("data", corrupt(10)),
+        ("crc", corrupt(good.len() - 1)),
+        ("sequence", corrupt(1)),
+        ("complement", corrupt(2)),
+        ("short", good[..good.len() - 1].to_vec()),
+        ("start byte", corrupt(0)),
+        ("empty", Vec::new()),
+    ];
+    for (what, block) in cases {
+        assert!(matches!(decode_block(&block), Err(ErrorKind::ProtocolError)), "{}", what);
+    }
+}
+
+#[test]
+fn headers_give_the_name_and_maybe_the_size() {
+    let header = |name: &str, size| Some(FileHeader { name: name.to_string(), size });
+    let cases: [(&[u8], Option<FileHeader>); 5] = [
+        (b"results.txt\x001500 14717234261 100644\x00", header("results.txt", Some(1500))),
+        (b"results.txt\x001500", header("results.txt", Some(1500))),
+        (b"dump.bin\x00\x00", header("dump.bin", None)),
+        (b"dump.bin", header("dump.bin", None)),
+        (&[0; 128], None),
+    ];
+    for (data, expected) in cases {
+        assert_eq!(parse_header(data).unwrap(), expected, "{}", data.escape_ascii());
+    }
+    assert!(matches!(parse_header(b"\xff\xfe\x00"), Err(ErrorKind::TransferError(_))));
+}
+
+#[test]
+fn names_cannot_leave_the_directory() {
+    let cases = [
+        ("results.txt", Some("results.txt")),
+        ("logs/boot.log", Some("boot.log")),
+        ("../../.bashrc", Some(".bashrc")),
+        ("/etc/passwd", Some("passwd")),
+        ("..\\..\\boot.ini", Some("boot.ini")),
+        ("C:autoexec.bat", Some("Cautoexec.bat")),
+        ("bad\x1b[2Jname", Some("bad[2Jname")),
+        ("..", None),
+        ("logs/..", None),
+        ("/", None),
+        ("", None),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(sanitize_name(name).as_deref(), expected, "{:?}", name);
+    }
+}
+
+#[test]
+fn receives_a_file_through_retries_and_duplicates() {
+    let dir = scratch("retries");
+    let contents: Vec<u8> = (0..1500u32).map(|i| (i % 251) as u8).collect();
+    let first = encode_block(1, &contents[..1024]);
+    let mut corrupted = first.clone();
+    corrupted[100] ^= 1;
+    let mock = MockSerial::new()
+        .expect(&[CRC_REQUEST]).reply(b"booting\r\n").reply(&encode_block(0, b"../results.bin\x001500 0 100644"))
+        .expect(&[ACK, CRC_REQUEST]).reply(&corrupted)
+        .expect(&[NAK]).reply(&first)
+        .expect(&[ACK]).reply(&first)
+        // the ACK got lost: acknowledged again, stored once
+        .expect(&[ACK]).reply(&encode_block(2, &contents[1024..]))
+        .expect(&[ACK]).reply(&[EOT])
+        .expect(&[NAK]).reply(&[EOT])
+        // the end of the batch, padded with NULs as block 0 is
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(0, &[0; 128]))
+        .expect(&[ACK]);
+
+    let (mut output, mut progress, mut started) = (Vec::new(), Vec::new(), Vec::new());
+    let files = receive(&mut mock.clone(), &dir, |transfer| match transfer {
+        Transfer::Output(bytes) => output.extend_from_slice(bytes),
+        Transfer::Started(header) => started.push(header.clone()),
+        Transfer::Progress(bytes) => progress.push(bytes),
+        Transfer::Finished(_) => {}
+    }).unwrap();
+
+    mock.assert_done();
+    assert_eq!(output, b"booting\r\n");
+    assert_eq!(started, [FileHeader { name: "../results.bin".to_string(), size: Some(1500) }]);
+    assert_eq!(progress, [1024, 1500]);
+    assert_eq!(files, [ReceivedFile { path: dir.join("results.bin"), bytes: 1500 }]);
+    assert_eq!(fs::read(dir.join("results.bin")).unwrap(), contents);
+    assert!(!dir.join("results.bin.part").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn receives_a_batch_without_sizes() {
+    let dir = scratch("batch");
+    let mock = MockSerial::new()
+        .expect(&[CRC_REQUEST]).reply(&encode_block(0, b"a.txt\x00"))
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(1, b"first\n"))
+        .expect(&[ACK]).reply(&[EOT])
+        .expect(&[NAK]).reply(&[EOT])
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(0, b"b.txt\x00"))
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(1, b"second\n"))
+        .expect(&[ACK]).reply(&[EOT])
+        .expect(&[NAK]).reply(&[EOT])
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(0, &[0; 128]))
+        .expect(&[ACK]);
+
+    let files = receive(&mut mock.clone(), &dir, |_| {}).unwrap();
+    mock.assert_done();
+    assert_eq!(files.iter().map(|file| file.bytes).collect::<Vec<u64>>(), [6, 7]);
+    // without a size the padding is cut off
+    assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"first\n");
+    assert_eq!(fs::read(dir.join("b.txt")).unwrap(), b"second\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn never_replaces_a_file() {
+    let dir = scratch("unused");
+    fs::write(dir.join("a.txt"), b"mine").unwrap();
+    fs::write(dir.join("a-1.txt.part"), b"left over").unwrap();
+    assert_eq!(unused_path(&dir, "a.txt"), dir.join("a-2.txt"));
+    assert_eq!(unused_path(&dir, ".profile"), dir.join(".profile"));
+    fs::write(dir.join("README"), b"").unwrap();
+    assert_eq!(unused_path(&dir, "README"), dir.join("README-1"));
+
+    let mock = MockSerial::new()
+        .expect(&[CRC_REQUEST]).reply(&encode_block(0, b"a.txt\x00"))
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(1, b"theirs\n"))
+        .expect(&[ACK]).reply(&[EOT])
+        .expect(&[NAK]).reply(&[EOT])
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(0, &[0; 128]))
+        .expect(&[ACK]);
+    let files = receive(&mut mock.clone(), &dir, |_| {}).unwrap();
+    mock.assert_done();
+    assert_eq!(files, [ReceivedFile { path: dir.join("a-2.txt"), bytes: 7 }]);
+    assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"mine");
+    assert_eq!(fs::read(dir.join("a-2.txt")).unwrap(), b"theirs\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn out_of_order_blocks_cancel_the_transfer() {
+    let dir = scratch("order");
+    let mock = MockSerial::new()
+        .expect(&[CRC_REQUEST]).reply(&encode_block(0, b"results.bin\x00256"))
+        .expect(&[ACK, CRC_REQUEST]).reply(&encode_block(2, &[1; 128]))
+        .expect(&[CAN; 5]);
+
+    match receive(&mut mock.clone(), &dir, |_| {}) {
+        Err(ErrorKind::TransferError(reason)) => assert_eq!(reason, "block 2 out of order, expected 1"),
+        result => panic!("{:?}", result),
+    }
+    mock.assert_done();
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    let mock = MockSerial::new()
+        .expect(&[CRC_REQUEST]).reply(&encode_block(0, b"..\x00256"))
+        .expect(&[ACK, CRC_REQUEST]).expect(&[CAN; 5]);
+    assert!(matches!(receive(&mut mock.clone(), &dir, |_| {}), Err(ErrorKind::TransferError(_))));
+    mock.assert_done();
+    let _ = fs::remove_dir_all(&dir);
+}